@@ -83,6 +83,30 @@ enum ErrorCode {
    * Entry has been changed and no longer matches the expected value
    */
   EntryChanged = 16,
+  /**
+   * The operation would exceed the storage quota of the repository
+   */
+  QuotaExceeded = 17,
+  /**
+   * The operation would make the file larger than the maximum file size of the repository
+   */
+  FileTooLarge = 18,
+  /**
+   * The repository database is corrupted
+   */
+  Corrupted = 19,
+  /**
+   * Entry name is not valid
+   */
+  InvalidName = 20,
+  /**
+   * Entry name is too long
+   */
+  NameTooLong = 21,
+  /**
+   * The maximum number of repositories registered in the network has been reached
+   */
+  RegistrationLimitExceeded = 22,
   VfsInvalidMountPoint = 2048,
   VfsDriverInstall = (2048 + 1),
   VfsBackend = (2048 + 2),
@@ -62,6 +62,12 @@ enum ErrorCode {
   connectionLost,
   invalidHandle,
   entryChanged,
+  quotaExceeded,
+  fileTooLarge,
+  corrupted,
+  invalidName,
+  nameTooLong,
+  registrationLimitExceeded,
   vfsInvalidMountPoint,
   vfsDriverInstall,
   vfsBackend,
@@ -86,6 +92,12 @@ enum ErrorCode {
       case 14: return ErrorCode.connectionLost;
       case 15: return ErrorCode.invalidHandle;
       case 16: return ErrorCode.entryChanged;
+      case 17: return ErrorCode.quotaExceeded;
+      case 18: return ErrorCode.fileTooLarge;
+      case 19: return ErrorCode.corrupted;
+      case 20: return ErrorCode.invalidName;
+      case 21: return ErrorCode.nameTooLong;
+      case 22: return ErrorCode.registrationLimitExceeded;
       case 2048: return ErrorCode.vfsInvalidMountPoint;
       case 2049: return ErrorCode.vfsDriverInstall;
       case 2050: return ErrorCode.vfsBackend;
@@ -112,6 +124,12 @@ enum ErrorCode {
       case ErrorCode.connectionLost: return 14;
       case ErrorCode.invalidHandle: return 15;
       case ErrorCode.entryChanged: return 16;
+      case ErrorCode.quotaExceeded: return 17;
+      case ErrorCode.fileTooLarge: return 18;
+      case ErrorCode.corrupted: return 19;
+      case ErrorCode.invalidName: return 20;
+      case ErrorCode.nameTooLong: return 21;
+      case ErrorCode.registrationLimitExceeded: return 22;
       case ErrorCode.vfsInvalidMountPoint: return 2048;
       case ErrorCode.vfsDriverInstall: return 2049;
       case ErrorCode.vfsBackend: return 2050;
@@ -180,6 +198,7 @@ enum PeerSource {
   localDiscovery,
   dht,
   peerExchange,
+  knownPeer,
   ;
 
   static PeerSource decode(int n) {
@@ -189,6 +208,7 @@ enum PeerSource {
       case 2: return PeerSource.localDiscovery;
       case 3: return PeerSource.dht;
       case 4: return PeerSource.peerExchange;
+      case 5: return PeerSource.knownPeer;
       default: throw ArgumentError('invalid value: $n');
     }
   }
@@ -200,6 +220,7 @@ enum PeerSource {
       case PeerSource.localDiscovery: return 2;
       case PeerSource.dht: return 3;
       case PeerSource.peerExchange: return 4;
+      case PeerSource.knownPeer: return 5;
     }
   }
 
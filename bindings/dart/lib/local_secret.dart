@@ -112,6 +112,40 @@ class PasswordSalt {
   String toString() => "PasswordSalt(${base64.encode(_bytes)})";
 }
 
+/// Parameters of the password hashing function (Argon2id) used to derive [LocalSecretKey] from
+/// [LocalPassword].
+class KdfParams {
+  /// Memory cost in KiB.
+  final int memoryCost;
+  final int iterations;
+  final int parallelism;
+
+  KdfParams({
+    required this.memoryCost,
+    required this.iterations,
+    required this.parallelism,
+  });
+
+  static KdfParams decode(Object? raw) {
+    final list = raw as List<Object?>;
+    return KdfParams(
+      memoryCost: list[0] as int,
+      iterations: list[1] as int,
+      parallelism: list[2] as int,
+    );
+  }
+
+  Object encode() => {
+        'memory_cost': memoryCost,
+        'iterations': iterations,
+        'parallelism': parallelism,
+      };
+
+  @override
+  String toString() =>
+      "KdfParams(memoryCost: $memoryCost, iterations: $iterations, parallelism: $parallelism)";
+}
+
 Uint8List _randomBytes(int size) {
   final random = Random.secure();
   Uint8List bytes = Uint8List(size);
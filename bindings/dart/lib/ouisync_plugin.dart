@@ -194,11 +194,16 @@ class Session {
       .invoke<Uint8List>('generate_salt_for_secret_key')
       .then((bytes) => PasswordSalt(bytes));
 
+  /// Derives [LocalSecretKey] from the password. If [kdfParams] are not given, the default ones
+  /// are used. To derive a key for an existing repository, pass the params obtained from
+  /// [Repository.getKdfParams].
   Future<LocalSecretKey> deriveLocalSecretKey(
-          LocalPassword pwd, PasswordSalt salt) =>
+          LocalPassword pwd, PasswordSalt salt,
+          [KdfParams? kdfParams]) =>
       _client.invoke<Uint8List>('derive_secret_key', {
         'password': pwd.string,
-        'salt': salt._bytes
+        'salt': salt._bytes,
+        'kdf_params': kdfParams?.encode(),
       }).then((bytes) => LocalSecretKey(bytes));
 
   /// Try to gracefully close connections to peers then close the session.
@@ -331,6 +336,7 @@ class Repository {
     required SetLocalSecret? readSecret,
     required SetLocalSecret? writeSecret,
     ShareToken? shareToken,
+    KdfParams? kdfParams,
   }) async {
     if (debugTrace) {
       print("Repository.create $store");
@@ -342,7 +348,8 @@ class Repository {
         'path': store,
         'read_secret': readSecret?.encode(),
         'write_secret': writeSecret?.encode(),
-        'share_token': shareToken?.toString()
+        'share_token': shareToken?.toString(),
+        'kdf_params': kdfParams?.encode(),
       },
     );
 
@@ -492,10 +499,34 @@ class Repository {
         'enabled': enabled,
       });
 
-  /// Create a share token providing access to this repository with the given mode. Can optionally
-  /// specify repository name which will be included in the token and suggested to the recipient.
+  /// Access mode of the share tokens created without specifying one.
+  Future<AccessMode> get defaultShareAccess => _client
+      .invoke<int>('repository_default_share_access', _handle)
+      .then((n) => AccessMode.decode(n));
+
+  Future<void> setDefaultShareAccess(AccessMode accessMode) =>
+      _client.invoke<void>('repository_set_default_share_access', {
+        'repository': _handle,
+        'access_mode': accessMode.encode(),
+      });
+
+  /// Highest access mode of the created share tokens, regardless of the requested one.
+  Future<AccessMode> get maxShareAccess => _client
+      .invoke<int>('repository_max_share_access', _handle)
+      .then((n) => AccessMode.decode(n));
+
+  Future<void> setMaxShareAccess(AccessMode accessMode) =>
+      _client.invoke<void>('repository_set_max_share_access', {
+        'repository': _handle,
+        'access_mode': accessMode.encode(),
+      });
+
+  /// Create a share token providing access to this repository with the given mode. If the mode is
+  /// omitted, the default share access mode of the repository is used. The mode is always limited
+  /// by the max share access mode of the repository. Can optionally specify repository name which
+  /// will be included in the token and suggested to the recipient.
   Future<ShareToken> createShareToken({
-    required AccessMode accessMode,
+    AccessMode? accessMode,
     LocalSecret? secret,
     String? name,
   }) {
@@ -506,7 +537,7 @@ class Repository {
     return _client.invoke<String>('repository_create_share_token', {
       'repository': _handle,
       'secret': secret?.encode(),
-      'access_mode': accessMode.encode(),
+      'access_mode': accessMode?.encode(),
       'name': name,
     }).then((token) => ShareToken._(_client, token));
   }
@@ -562,6 +593,10 @@ class Repository {
       .invoke<Uint8List>("get_write_password_salt", _handle)
       .then((bytes) => PasswordSalt(bytes));
 
+  Future<KdfParams> getKdfParams() => _client
+      .invoke<Object?>("get_kdf_params", _handle)
+      .then(KdfParams.decode);
+
   Future<String?> getMetadata(String key) =>
       _client.invoke<String?>('repository_get_metadata', {
         'repository': _handle,
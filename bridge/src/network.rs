@@ -1,11 +1,16 @@
 use crate::config::{ConfigKey, ConfigStore};
 use ouisync_lib::network::{peer_addr::PeerAddr, Network};
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 const BIND_KEY: ConfigKey<Vec<PeerAddr>> =
     ConfigKey::new("bind", "Addresses to bind the network listeners to");
 
+const BIND_ALL_KEY: ConfigKey<bool> = ConfigKey::new(
+    "bind_all",
+    "Listen on all the bind addresses instead of only the first one of each protocol and family",
+);
+
 const PORT_FORWARDING_ENABLED_KEY: ConfigKey<bool> =
     ConfigKey::new("port_forwarding_enabled", "Enable port forwarding / UPnP");
 
@@ -18,6 +23,17 @@ const PEERS_KEY: ConfigKey<Vec<PeerAddr>> = ConfigKey::new(
      (e.g. DHT)",
 );
 
+const LAST_USED_PORTS_KEY: ConfigKey<Vec<PeerAddr>> = ConfigKey::new(
+    "last_used_ports",
+    "The values stored in this file are the last used ports for listening on incoming connections,\n\
+     one for each protocol and bind address. They are used to avoid binding to a random port every\n\
+     time the application starts. This, in turn, is mainly useful for users who can't or don't want\n\
+     to use UPnP and have to default to manually setting up port forwarding on their routers.",
+);
+
+// The following keys are no longer written, they are only read to migrate the ports stored by older
+// versions to `LAST_USED_PORTS_KEY`.
+
 const LAST_USED_TCP_V4_PORT_KEY: ConfigKey<u16> =
     ConfigKey::new("last_used_tcp_v4_port", LAST_USED_TCP_PORT_COMMENT);
 
@@ -52,7 +68,8 @@ pub struct NetworkDefaults {
 /// Initialize the network according to the config.
 pub async fn init(network: &Network, config: &ConfigStore, defaults: NetworkDefaults) {
     let bind_addrs = config.entry(BIND_KEY).get().await.unwrap_or_default();
-    bind_with_reuse_ports(network, config, &bind_addrs).await;
+    let bind_all = config.entry(BIND_ALL_KEY).get().await.unwrap_or(false);
+    bind_with_reuse_ports(network, config, &bind_addrs, bind_all).await;
 
     let enabled = config
         .entry(PORT_FORWARDING_ENABLED_KEY)
@@ -80,17 +97,35 @@ pub async fn init(network: &Network, config: &ConfigStore, defaults: NetworkDefa
 /// not bound. If all are missing the network is disabled.
 pub async fn bind(network: &Network, config: &ConfigStore, addrs: &[PeerAddr]) {
     config.entry(BIND_KEY).set(addrs).await.ok();
-    bind_with_reuse_ports(network, config, addrs).await;
+    config.entry(BIND_ALL_KEY).set(&false).await.ok();
+    bind_with_reuse_ports(network, config, addrs, false).await;
+}
+
+/// Like `bind` but listens on all the addresses that can be bound instead of only the first one of
+/// each protocol and family. See `Network::bind_all` for details.
+pub async fn bind_all(network: &Network, config: &ConfigStore, addrs: &[PeerAddr]) {
+    config.entry(BIND_KEY).set(addrs).await.ok();
+    config.entry(BIND_ALL_KEY).set(&true).await.ok();
+    bind_with_reuse_ports(network, config, addrs, true).await;
 }
 
-async fn bind_with_reuse_ports(network: &Network, config: &ConfigStore, addrs: &[PeerAddr]) {
+async fn bind_with_reuse_ports(
+    network: &Network,
+    config: &ConfigStore,
+    addrs: &[PeerAddr],
+    all: bool,
+) {
     let mut last_used_ports = LastUsedPorts::load(config).await;
     let addrs: Vec<_> = addrs
         .iter()
         .map(|addr| last_used_ports.apply(*addr))
         .collect();
 
-    network.bind(&addrs).await;
+    if all {
+        network.bind_all(&addrs).await;
+    } else {
+        network.bind(&addrs).await;
+    }
 
     // Write the actually used ports to the config
     last_used_ports.extract(&network.listener_local_addrs());
@@ -162,118 +197,97 @@ pub async fn user_provided_peers(config: &ConfigStore) -> Vec<PeerAddr> {
     config.entry(PEERS_KEY).get().await.unwrap_or_default()
 }
 
-/// Utility to help reuse bind ports across network restarts.
+/// Utility to help reuse bind ports across network restarts. Remembers the port last bound for
+/// each protocol and IP address, so several addresses with port 0 (e.g., the fallbacks used with
+/// `bind_all`) each keep their own port.
 struct LastUsedPorts {
-    quic_v4: u16,
-    quic_v6: u16,
-    tcp_v4: u16,
-    tcp_v6: u16,
+    addrs: Vec<PeerAddr>,
 }
 
 impl LastUsedPorts {
     async fn load(config: &ConfigStore) -> Self {
-        Self {
-            quic_v4: config
-                .entry(LAST_USED_UDP_V4_PORT_KEY)
-                .get()
-                .await
-                .unwrap_or(0),
-            quic_v6: config
-                .entry(LAST_USED_UDP_V6_PORT_KEY)
-                .get()
-                .await
-                .unwrap_or(0),
-            tcp_v4: config
-                .entry(LAST_USED_TCP_V4_PORT_KEY)
-                .get()
-                .await
-                .unwrap_or(0),
-            tcp_v6: config
-                .entry(LAST_USED_TCP_V6_PORT_KEY)
-                .get()
-                .await
-                .unwrap_or(0),
+        if let Ok(addrs) = config.entry(LAST_USED_PORTS_KEY).get().await {
+            return Self { addrs };
         }
-    }
 
-    async fn save(&self, config: &ConfigStore) {
-        for (key, value) in [
-            (LAST_USED_UDP_V4_PORT_KEY, self.quic_v4),
-            (LAST_USED_UDP_V6_PORT_KEY, self.quic_v6),
-            (LAST_USED_TCP_V4_PORT_KEY, self.tcp_v4),
-            (LAST_USED_TCP_V6_PORT_KEY, self.tcp_v6),
+        // Migrate the legacy per protocol and family ports. They were used for any bind address so
+        // assign them to the unspecified ones, which is what most configs bind to.
+        let mut addrs = Vec::new();
+
+        for (key, addr) in [
+            (
+                LAST_USED_UDP_V4_PORT_KEY,
+                PeerAddr::Quic((Ipv4Addr::UNSPECIFIED, 0).into()),
+            ),
+            (
+                LAST_USED_UDP_V6_PORT_KEY,
+                PeerAddr::Quic((Ipv6Addr::UNSPECIFIED, 0).into()),
+            ),
+            (
+                LAST_USED_TCP_V4_PORT_KEY,
+                PeerAddr::Tcp((Ipv4Addr::UNSPECIFIED, 0).into()),
+            ),
+            (
+                LAST_USED_TCP_V6_PORT_KEY,
+                PeerAddr::Tcp((Ipv6Addr::UNSPECIFIED, 0).into()),
+            ),
         ] {
-            if value != 0 {
-                config.entry(key).set(&value).await.ok();
+            let port = config.entry(key).get().await.unwrap_or(0);
+
+            if port != 0 {
+                let mut addr = addr;
+                addr.set_port(port);
+                addrs.push(addr);
             }
         }
+
+        Self { addrs }
+    }
+
+    async fn save(&self, config: &ConfigStore) {
+        if !self.addrs.is_empty() {
+            config
+                .entry(LAST_USED_PORTS_KEY)
+                .set(&self.addrs)
+                .await
+                .ok();
+        }
     }
 
-    /// If `addr`'s port is not zero, replace it with the corresponding last used port.
+    /// If `addr`'s port is zero, replace it with the last used port of the same protocol and IP
+    /// address, if any.
     fn apply(&self, mut addr: PeerAddr) -> PeerAddr {
         if addr.port() != 0 {
             return addr;
         }
 
-        let new_port = match addr {
-            PeerAddr::Quic(SocketAddr::V4(_)) => self.quic_v4,
-            PeerAddr::Quic(SocketAddr::V6(_)) => self.quic_v6,
-            PeerAddr::Tcp(SocketAddr::V4(_)) => self.tcp_v4,
-            PeerAddr::Tcp(SocketAddr::V6(_)) => self.tcp_v6,
-        };
+        if let Some(last) = self.addrs.iter().find(|last| same_listener(last, &addr)) {
+            addr.set_port(last.port());
+        }
 
-        addr.set_port(new_port);
         addr
     }
 
     fn extract(&mut self, addrs: &[PeerAddr]) {
-        if let Some(port) = addrs.iter().find_map(|addr| {
-            if let PeerAddr::Quic(SocketAddr::V4(_)) = addr {
-                Some(addr.port())
-            } else {
-                None
-            }
-        }) {
-            self.quic_v4 = port;
-        }
-
-        if let Some(port) = addrs.iter().find_map(|addr| {
-            if let PeerAddr::Quic(SocketAddr::V6(_)) = addr {
-                Some(addr.port())
-            } else {
-                None
-            }
-        }) {
-            self.quic_v6 = port;
-        }
-
-        if let Some(port) = addrs.iter().find_map(|addr| {
-            if let PeerAddr::Tcp(SocketAddr::V4(_)) = addr {
-                Some(addr.port())
+        for addr in addrs {
+            if let Some(last) = self.addrs.iter_mut().find(|last| same_listener(last, addr)) {
+                *last = *addr;
             } else {
-                None
+                self.addrs.push(*addr);
             }
-        }) {
-            self.tcp_v4 = port;
-        }
-
-        if let Some(port) = addrs.iter().find_map(|addr| {
-            if let PeerAddr::Tcp(SocketAddr::V6(_)) = addr {
-                Some(addr.port())
-            } else {
-                None
-            }
-        }) {
-            self.tcp_v6 = port;
         }
     }
 }
 
+fn same_listener(a: &PeerAddr, b: &PeerAddr) -> bool {
+    a.is_quic() == b.is_quic() && a.ip() == b.ip()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use state_monitor::StateMonitor;
-    use std::{net::Ipv4Addr, time::Duration};
+    use std::time::Duration;
     use tempfile::TempDir;
     use tokio::time;
 
@@ -360,6 +374,66 @@ mod tests {
         assert_ne!(local_addr_1, local_addr_0);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn network_disable_enable_bind_all() {
+        let config_dir = TempDir::new().unwrap();
+        let config = ConfigStore::new(config_dir.path());
+        let network = Network::new(StateMonitor::make_root(), None, None);
+
+        let bind_addrs = [
+            PeerAddr::Quic((Ipv4Addr::LOCALHOST, 0).into()),
+            PeerAddr::Quic((Ipv4Addr::UNSPECIFIED, 0).into()),
+        ];
+
+        bind_all(&network, &config, &bind_addrs).await;
+
+        let mut local_addrs_0 = network.listener_local_addrs();
+        local_addrs_0.sort();
+        assert_eq!(local_addrs_0.len(), 2);
+
+        bind(&network, &config, &[]).await;
+        bind_all(&network, &config, &bind_addrs).await;
+
+        let mut local_addrs_1 = network.listener_local_addrs();
+        local_addrs_1.sort();
+
+        // Each address got back its own port.
+        assert_eq!(local_addrs_1, local_addrs_0);
+    }
+
+    #[test]
+    fn last_used_ports_per_address() {
+        let mut ports = LastUsedPorts { addrs: Vec::new() };
+        ports.extract(&[
+            PeerAddr::Quic((Ipv4Addr::UNSPECIFIED, 1000).into()),
+            PeerAddr::Quic((Ipv4Addr::LOCALHOST, 1001).into()),
+            PeerAddr::Tcp((Ipv4Addr::UNSPECIFIED, 1002).into()),
+        ]);
+
+        assert_eq!(
+            ports.apply(PeerAddr::Quic((Ipv4Addr::UNSPECIFIED, 0).into())),
+            PeerAddr::Quic((Ipv4Addr::UNSPECIFIED, 1000).into())
+        );
+        assert_eq!(
+            ports.apply(PeerAddr::Quic((Ipv4Addr::LOCALHOST, 0).into())),
+            PeerAddr::Quic((Ipv4Addr::LOCALHOST, 1001).into())
+        );
+        assert_eq!(
+            ports.apply(PeerAddr::Tcp((Ipv4Addr::UNSPECIFIED, 0).into())),
+            PeerAddr::Tcp((Ipv4Addr::UNSPECIFIED, 1002).into())
+        );
+        // Unknown address keeps the random port.
+        assert_eq!(
+            ports.apply(PeerAddr::Tcp((Ipv4Addr::LOCALHOST, 0).into())),
+            PeerAddr::Tcp((Ipv4Addr::LOCALHOST, 0).into())
+        );
+        // Explicit port is kept.
+        assert_eq!(
+            ports.apply(PeerAddr::Quic((Ipv4Addr::LOCALHOST, 2000).into())),
+            PeerAddr::Quic((Ipv4Addr::LOCALHOST, 2000).into())
+        );
+    }
+
     const TIMEOUT: Duration = Duration::from_secs(10);
 
     async fn expect_knows(network: &Network, peer_addr: PeerAddr) {
@@ -5,8 +5,9 @@ use crate::{
     transport::RemoteClient,
 };
 use ouisync_lib::{
-    crypto::sign::Signature, Access, AccessMode, AccessSecrets, LocalSecret, Repository,
-    RepositoryId, RepositoryParams, SetLocalSecret, ShareToken, StorageSize, WriteSecrets,
+    crypto::{sign::Signature, KdfParams},
+    Access, AccessMode, AccessSecrets, LocalSecret, Repository, RepositoryId, RepositoryParams,
+    SetLocalSecret, ShareToken, StorageSize, WriteSecrets,
 };
 use state_monitor::StateMonitor;
 use std::{io, path::PathBuf, sync::Arc, time::Duration};
@@ -54,11 +55,13 @@ pub async fn create(
     local_read_secret: Option<SetLocalSecret>,
     local_write_secret: Option<SetLocalSecret>,
     share_token: Option<ShareToken>,
+    kdf_params: Option<KdfParams>,
     config: &ConfigStore,
     repos_monitor: &StateMonitor,
 ) -> Result<Repository, OpenError> {
     let params = RepositoryParams::new(store)
         .with_device_id(device_id::get_or_create(config).await?)
+        .with_kdf_params(kdf_params.unwrap_or_default())
         .with_parent_monitor(repos_monitor.clone());
 
     let access_secrets = if let Some(share_token) = share_token {
@@ -98,19 +101,19 @@ pub async fn open(
 
 /// The `key` parameter is optional, if `None` the current access level of the opened
 /// repository is used. If provided, the highest access level that the key can unlock is used.
+///
+/// The `access_mode` parameter is optional, if `None` the default share access mode of the
+/// repository is used. In any case it's limited by the max share access mode of the repository
+/// (see `Repository::share_access`).
 pub async fn create_share_token(
     repository: &Repository,
     local_secret: Option<LocalSecret>,
-    access_mode: AccessMode,
+    access_mode: Option<AccessMode>,
     name: Option<String>,
 ) -> Result<String, ouisync_lib::Error> {
-    let access_secrets = if let Some(local_secret) = local_secret {
-        repository.unlock_secrets(local_secret).await?
-    } else {
-        repository.secrets()
-    };
+    let access_secrets = repository.share_secrets(local_secret, access_mode).await?;
 
-    let share_token = ShareToken::from(access_secrets.with_mode(access_mode));
+    let share_token = ShareToken::from(access_secrets);
     let share_token = if let Some(name) = name {
         share_token.with_name(name)
     } else {
@@ -86,13 +86,14 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
                         .map(Password::from)
                         .map(SetLocalSecret::Password),
                     share_token,
+                    None,
                     &self.state.config,
                     &self.state.repositories_monitor,
                 )
                 .await?;
 
                 let holder =
-                    RepositoryHolder::new(repository, name.clone(), &self.state.network).await;
+                    RepositoryHolder::new(repository, name.clone(), &self.state.network).await?;
                 let holder = Arc::new(holder);
 
                 if !self.state.repositories.try_insert(holder.clone()) {
@@ -135,7 +136,7 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
                 .await?;
 
                 let holder =
-                    RepositoryHolder::new(repository, name.clone(), &self.state.network).await;
+                    RepositoryHolder::new(repository, name.clone(), &self.state.network).await?;
                 let holder = Arc::new(holder);
                 if !self.state.repositories.try_insert(holder.clone()) {
                     Err(ouisync_lib::Error::EntryExists)?;
@@ -244,8 +245,13 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
                     .collect();
                 Ok(names.into())
             }
-            Request::Bind { addrs } => {
-                network::bind(&self.state.network, &self.state.config, &addrs).await;
+            Request::Bind { addrs, all } => {
+                if all {
+                    network::bind_all(&self.state.network, &self.state.config, &addrs).await;
+                } else {
+                    network::bind(&self.state.network, &self.state.config, &addrs).await;
+                }
+
                 Ok(().into())
             }
             Request::ListBinds => Ok(self.state.network.listener_local_addrs().into()),
@@ -310,6 +316,32 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
                     Ok(holder.registration.is_pex_enabled().into())
                 }
             }
+            Request::ShareAccess { name, max, mode } => {
+                let holder = self.state.repositories.find(&name)?;
+
+                match (mode, max) {
+                    (Some(mode), false) => {
+                        holder.repository.set_default_share_access(mode).await?;
+                        Ok(().into())
+                    }
+                    (Some(mode), true) => {
+                        holder.repository.set_max_share_access(mode).await?;
+                        Ok(().into())
+                    }
+                    (None, false) => Ok(holder
+                        .repository
+                        .default_share_access()
+                        .await?
+                        .to_string()
+                        .into()),
+                    (None, true) => Ok(holder
+                        .repository
+                        .max_share_access()
+                        .await?
+                        .to_string()
+                        .into()),
+                }
+            }
             Request::Quota {
                 name,
                 default: _,
@@ -135,13 +135,16 @@ async fn create_repository(
         None,
         None,
         Some(ShareToken::from(secrets)),
+        None,
         &state.config,
         &state.repositories_monitor,
     )
     .await
     .map_err(|error| ServerError::Internal(error.to_string()))?;
 
-    let holder = RepositoryHolder::new(repository, name, &state.network).await;
+    let holder = RepositoryHolder::new(repository, name, &state.network)
+        .await
+        .map_err(|error| ServerError::Internal(error.to_string()))?;
     let holder = Arc::new(holder);
 
     if !state.repositories.try_insert(holder.clone()) {
@@ -86,9 +86,10 @@ pub(crate) enum Request {
         #[arg(short, long)]
         name: String,
 
-        /// Access mode of the token ("blind", "read" or "write")
-        #[arg(short, long, default_value_t = AccessMode::Write, value_name = "MODE")]
-        mode: AccessMode,
+        /// Access mode of the token ("blind", "read" or "write"). If omitted, the default share
+        /// access mode of the repository is used. Always limited by its max share access mode.
+        #[arg(short, long, value_name = "MODE")]
+        mode: Option<AccessMode>,
 
         /// Local password
         #[arg(short = 'P', long)]
@@ -138,6 +139,11 @@ pub(crate) enum Request {
         /// Examples: quic/0.0.0.0:0, quic/[::]:0, tcp/192.168.0.100:55555
         #[arg(value_name = "PROTO/IP:PORT")]
         addrs: Vec<PeerAddr>,
+
+        /// Listen on all the given addresses that can be bound. By default, only the first one of
+        /// each protocol and family is used and the others are fallbacks.
+        #[arg(short, long)]
+        all: bool,
     },
     /// List addresses and ports we are listening on
     ListBinds,
@@ -188,6 +194,20 @@ pub(crate) enum Request {
         #[arg(value_parser = BoolishValueParser::new())]
         enabled: Option<bool>,
     },
+    /// Get or set the access mode of the share tokens created without specifying one, or the
+    /// highest access mode of any created share token
+    ShareAccess {
+        #[arg(short = 'n', long)]
+        name: String,
+
+        /// Get/set the highest access mode instead of the default one
+        #[arg(short, long)]
+        max: bool,
+
+        /// Access mode to set ("blind", "read" or "write"). If omitted, prints the current one.
+        #[arg(value_name = "MODE")]
+        mode: Option<AccessMode>,
+    },
     /// Get or set storage quota
     Quota {
         /// Name of the repository to get/set the quota for
@@ -466,6 +486,7 @@ impl fmt::Display for PeerInfoDisplay<'_> {
                 PeerSource::LocalDiscovery => "local-discovery",
                 PeerSource::Dht => "dht",
                 PeerSource::PeerExchange => "pex",
+                PeerSource::KnownPeer => "known-peer",
             },
             match self.0.state {
                 PeerState::Known => "known",
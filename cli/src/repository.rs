@@ -94,16 +94,20 @@ pub(crate) struct RepositoryHolder {
 }
 
 impl RepositoryHolder {
-    pub async fn new(repository: Repository, name: RepositoryName, network: &Network) -> Self {
+    pub async fn new(
+        repository: Repository,
+        name: RepositoryName,
+        network: &Network,
+    ) -> Result<Self, ouisync_lib::Error> {
         let repository = Arc::new(repository);
-        let registration = network.register(repository.handle()).await;
+        let registration = network.register(repository.handle()).await?;
 
-        Self {
+        Ok(Self {
             repository,
             registration,
             name,
             mount: Mutex::new(None),
-        }
+        })
     }
 
     pub fn name(&self) -> &RepositoryName {
@@ -481,7 +485,13 @@ pub(crate) async fn find_all(
 
         tracing::info!(%name, "Repository opened");
 
-        let holder = RepositoryHolder::new(repository, name, network).await;
+        let holder = match RepositoryHolder::new(repository, name, network).await {
+            Ok(holder) => holder,
+            Err(error) => {
+                tracing::error!(?error, "Failed to register repository");
+                continue;
+            }
+        };
         let holder = Arc::new(holder);
         holder.mount(&dirs.mount_dir).await.ok();
 
@@ -60,6 +60,18 @@ pub enum ErrorCode {
     InvalidHandle = 15,
     /// Entry has been changed and no longer matches the expected value
     EntryChanged = 16,
+    /// The operation would exceed the storage quota of the repository
+    QuotaExceeded = 17,
+    /// The operation would make the file larger than the maximum file size of the repository
+    FileTooLarge = 18,
+    /// The repository database is corrupted
+    Corrupted = 19,
+    /// Entry name is not valid
+    InvalidName = 20,
+    /// Entry name is too long
+    NameTooLong = 21,
+    /// The maximum number of repositories registered in the network has been reached
+    RegistrationLimitExceeded = 22,
 
     VfsInvalidMountPoint = 2048,
     VfsDriverInstall = 2048 + 1,
@@ -107,6 +119,12 @@ impl ToErrorCode for ouisync_lib::Error {
                 ErrorCode::InvalidArgument
             }
             Self::StorageVersionMismatch => ErrorCode::StorageVersionMismatch,
+            Self::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            Self::FileTooLarge => ErrorCode::FileTooLarge,
+            Self::Corrupted(_) => ErrorCode::Corrupted,
+            Self::InvalidName => ErrorCode::InvalidName,
+            Self::NameTooLong => ErrorCode::NameTooLong,
+            Self::RegistrationLimitExceeded => ErrorCode::RegistrationLimitExceeded,
             Self::EntryIsFile | Self::EntryIsDirectory | Self::Writer(_) | Self::Locked => {
                 ErrorCode::Other
             }
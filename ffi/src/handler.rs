@@ -42,12 +42,14 @@ impl ouisync_bridge::transport::Handler for Handler {
                 read_secret,
                 write_secret,
                 share_token,
+                kdf_params,
             } => repository::create(
                 &self.state,
                 path.into_std_path_buf(),
                 read_secret,
                 write_secret,
                 share_token,
+                kdf_params,
             )
             .await?
             .into(),
@@ -196,6 +198,30 @@ impl ouisync_bridge::transport::Handler for Handler {
             Request::RepositoryAccessMode(repository) => {
                 repository::access_mode(&self.state, repository)?.into()
             }
+            Request::RepositoryDefaultShareAccess(repository) => {
+                repository::default_share_access(&self.state, repository)
+                    .await?
+                    .into()
+            }
+            Request::RepositorySetDefaultShareAccess {
+                repository,
+                access_mode,
+            } => {
+                repository::set_default_share_access(&self.state, repository, access_mode).await?;
+                ().into()
+            }
+            Request::RepositoryMaxShareAccess(repository) => {
+                repository::max_share_access(&self.state, repository)
+                    .await?
+                    .into()
+            }
+            Request::RepositorySetMaxShareAccess {
+                repository,
+                access_mode,
+            } => {
+                repository::set_max_share_access(&self.state, repository, access_mode).await?;
+                ().into()
+            }
             Request::RepositorySyncProgress(repository) => {
                 repository::sync_progress(&self.state, repository)
                     .await?
@@ -285,6 +311,11 @@ impl ouisync_bridge::transport::Handler for Handler {
                 .await;
                 ().into()
             }
+            Request::NetworkBindAll(addrs) => {
+                ouisync_bridge::network::bind_all(&self.state.network, &self.state.config, &addrs)
+                    .await;
+                ().into()
+            }
             Request::NetworkTcpListenerLocalAddrV4 => self
                 .state
                 .network
@@ -389,12 +420,20 @@ impl ouisync_bridge::transport::Handler for Handler {
                 ().into()
             }
             Request::GenerateSaltForSecretKey => SecretKey::random_salt().as_ref().to_vec().into(),
-            Request::DeriveSecretKey { password, salt } => {
+            Request::DeriveSecretKey {
+                password,
+                salt,
+                kdf_params,
+            } => {
                 // TODO: This is a slow operation, do we need to send it to the thread pool?
-                SecretKey::derive_from_password(&password, &salt)
-                    .as_array()
-                    .to_vec()
-                    .into()
+                SecretKey::derive_from_password_with_params(
+                    &password,
+                    &salt,
+                    &kdf_params.unwrap_or_default(),
+                )
+                .as_array()
+                .to_vec()
+                .into()
             }
             Request::GetReadPasswordSalt(handle) => self
                 .state
@@ -416,6 +455,14 @@ impl ouisync_bridge::transport::Handler for Handler {
                 .as_array()
                 .to_vec()
                 .into(),
+            Request::GetKdfParams(handle) => self
+                .state
+                .repositories
+                .get(handle)?
+                .repository
+                .kdf_params()
+                .await?
+                .into(),
         };
 
         Ok(response)
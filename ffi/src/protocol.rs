@@ -8,7 +8,7 @@ use crate::{
 use camino::Utf8PathBuf;
 use ouisync_bridge::network::NetworkDefaults;
 use ouisync_lib::{
-    crypto::PasswordSalt,
+    crypto::{KdfParams, PasswordSalt},
     network::{NatBehavior, TrafficStats},
     AccessChange, AccessMode, LocalSecret, PeerAddr, PeerInfo, Progress, SetLocalSecret,
     ShareToken,
@@ -31,6 +31,7 @@ pub(crate) enum Request {
         read_secret: Option<SetLocalSecret>,
         write_secret: Option<SetLocalSecret>,
         share_token: Option<ShareToken>,
+        kdf_params: Option<KdfParams>,
     },
     RepositoryOpen {
         path: Utf8PathBuf,
@@ -85,9 +86,19 @@ pub(crate) enum Request {
     RepositoryCreateShareToken {
         repository: RepositoryHandle,
         secret: Option<LocalSecret>,
-        access_mode: AccessMode,
+        access_mode: Option<AccessMode>,
         name: Option<String>,
     },
+    RepositoryDefaultShareAccess(RepositoryHandle),
+    RepositorySetDefaultShareAccess {
+        repository: RepositoryHandle,
+        access_mode: AccessMode,
+    },
+    RepositoryMaxShareAccess(RepositoryHandle),
+    RepositorySetMaxShareAccess {
+        repository: RepositoryHandle,
+        access_mode: AccessMode,
+    },
     RepositorySyncProgress(RepositoryHandle),
     RepositoryCreateMirror {
         repository: RepositoryHandle,
@@ -174,6 +185,7 @@ pub(crate) enum Request {
         #[serde(with = "as_option_str", default)]
         tcp_v6: Option<SocketAddrV6>,
     },
+    NetworkBindAll(#[serde(with = "as_vec_str")] Vec<PeerAddr>),
     NetworkTcpListenerLocalAddrV4,
     NetworkTcpListenerLocalAddrV6,
     NetworkQuicListenerLocalAddrV4,
@@ -201,9 +213,11 @@ pub(crate) enum Request {
     DeriveSecretKey {
         password: String,
         salt: PasswordSalt,
+        kdf_params: Option<KdfParams>,
     },
     GetReadPasswordSalt(RepositoryHandle),
     GetWritePasswordSalt(RepositoryHandle),
+    GetKdfParams(RepositoryHandle),
 }
 
 #[derive(Eq, PartialEq, Serialize, Deserialize)]
@@ -224,6 +238,7 @@ pub(crate) enum Response {
     PeerInfos(Vec<PeerInfo>),
     PeerAddrs(#[serde(with = "as_vec_str")] Vec<PeerAddr>),
     TrafficStats(TrafficStats),
+    KdfParams(KdfParams),
 }
 
 impl<T> From<Option<T>> for Response
@@ -426,6 +441,12 @@ impl From<TrafficStats> for Response {
     }
 }
 
+impl From<KdfParams> for Response {
+    fn from(value: KdfParams) -> Self {
+        Self::KdfParams(value)
+    }
+}
+
 impl fmt::Debug for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -589,6 +610,7 @@ mod tests {
 
     use super::*;
     use ouisync_lib::{
+        crypto::cipher::SecretKey,
         network::{PeerSource, PeerState},
         AccessSecrets, Credentials, PeerInfo, SecretRuntimeId,
     };
@@ -603,6 +625,19 @@ mod tests {
                 read_secret: None,
                 write_secret: None,
                 share_token: None,
+                kdf_params: None,
+            },
+            Request::RepositoryCreate {
+                path: Utf8PathBuf::from("/tmp/repo.db"),
+                read_secret: None,
+                write_secret: None,
+                share_token: None,
+                kdf_params: Some(KdfParams::new(2048, 2, 1).unwrap()),
+            },
+            Request::DeriveSecretKey {
+                password: "mellon".to_owned(),
+                salt: SecretKey::random_salt(),
+                kdf_params: Some(KdfParams::default()),
             },
             Request::RepositoryClose(Handle::from_id(1)),
             Request::RepositorySetCredentials {
@@ -658,6 +693,7 @@ mod tests {
                 },
             ]),
             Response::PeerAddrs(vec![PeerAddr::Tcp(([192, 168, 1, 234], 45678).into())]),
+            Response::KdfParams(KdfParams::new(2048, 2, 1).unwrap()),
         ];
 
         for orig in origs {
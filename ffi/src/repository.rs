@@ -6,6 +6,7 @@ use crate::{
 use camino::Utf8PathBuf;
 use ouisync_bridge::{protocol::Notification, repository, transport::NotificationSender};
 use ouisync_lib::{
+    crypto::KdfParams,
     network::{self, Registration},
     path, AccessMode, Credentials, Event, LocalSecret, Payload, Progress, Repository,
     SetLocalSecret, ShareToken,
@@ -42,6 +43,7 @@ pub(crate) async fn create(
     local_read_secret: Option<SetLocalSecret>,
     local_write_secret: Option<SetLocalSecret>,
     share_token: Option<ShareToken>,
+    kdf_params: Option<KdfParams>,
 ) -> Result<RepositoryHandle, Error> {
     let entry = ensure_vacant_entry(state, store_path.clone()).await?;
 
@@ -50,6 +52,7 @@ pub(crate) async fn create(
         local_read_secret,
         local_write_secret,
         share_token,
+        kdf_params,
         &state.config,
         &state.repos_monitor,
     )
@@ -184,7 +187,7 @@ pub(crate) async fn set_sync_enabled(
     if enabled {
         let mut registration = holder.registration.write().await;
         if registration.is_none() {
-            *registration = Some(state.network.register(holder.repository.handle()).await);
+            *registration = Some(state.network.register(holder.repository.handle()).await?);
         }
     } else {
         holder.registration.write().await.take();
@@ -226,6 +229,59 @@ pub(crate) fn access_mode(state: &State, handle: RepositoryHandle) -> Result<u8,
         .into())
 }
 
+pub(crate) async fn default_share_access(
+    state: &State,
+    handle: RepositoryHandle,
+) -> Result<u8, Error> {
+    Ok(state
+        .repositories
+        .get(handle)?
+        .repository
+        .default_share_access()
+        .await?
+        .into())
+}
+
+pub(crate) async fn set_default_share_access(
+    state: &State,
+    handle: RepositoryHandle,
+    access_mode: AccessMode,
+) -> Result<(), Error> {
+    state
+        .repositories
+        .get(handle)?
+        .repository
+        .set_default_share_access(access_mode)
+        .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn max_share_access(state: &State, handle: RepositoryHandle) -> Result<u8, Error> {
+    Ok(state
+        .repositories
+        .get(handle)?
+        .repository
+        .max_share_access()
+        .await?
+        .into())
+}
+
+pub(crate) async fn set_max_share_access(
+    state: &State,
+    handle: RepositoryHandle,
+    access_mode: AccessMode,
+) -> Result<(), Error> {
+    state
+        .repositories
+        .get(handle)?
+        .repository
+        .set_max_share_access(access_mode)
+        .await?;
+
+    Ok(())
+}
+
 pub(crate) async fn set_access_mode(
     state: &State,
     handle: RepositoryHandle,
@@ -288,7 +344,7 @@ pub(crate) async fn move_entry(
 
     holder
         .repository
-        .move_entry(src_dir, src_name, dst_dir, dst_name)
+        .move_entry(src_dir, src_name, dst_dir, dst_name, true)
         .await?;
 
     Ok(())
@@ -389,12 +445,13 @@ pub(crate) async fn set_pex_enabled(
 
 /// The `local_secret` parameter is optional, if `None` the current access level of the opened
 /// repository is used. If provided, the highest access level that the local_secret can unlock is
-/// used.
+/// used. The `access_mode` parameter is optional, if `None` the default share access mode of the
+/// repository is used.
 pub(crate) async fn create_share_token(
     state: &State,
     repository: RepositoryHandle,
     local_secret: Option<LocalSecret>,
-    access_mode: AccessMode,
+    access_mode: Option<AccessMode>,
     name: Option<String>,
 ) -> Result<String, Error> {
     let holder = state.repositories.get(repository)?;
@@ -95,7 +95,7 @@ fn main() -> ExitCode {
             )
             .await
             .unwrap();
-            let _reg = network.register(repo.handle()).await;
+            let _reg = network.register(repo.handle()).await.unwrap();
 
             // Create the file by one of the writers
             if watch_tx.is_some() {
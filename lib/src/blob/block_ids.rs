@@ -3,7 +3,7 @@ use crate::{
     blob::BlobId,
     branch::Branch,
     error::{Error, Result},
-    protocol::{BlockId, Locator, RootNode, RootNodeFilter},
+    protocol::{BlockId, LeafNode, Locator, RootNode, RootNodeFilter},
     store,
 };
 
@@ -44,6 +44,11 @@ impl BlockIds {
     }
 
     pub async fn try_next(&mut self) -> Result<Option<BlockId>> {
+        Ok(self.try_next_leaf_node().await?.map(|node| node.block_id))
+    }
+
+    /// Like `try_next` but yields the whole leaf node, which contains also the block presence.
+    pub async fn try_next_leaf_node(&mut self) -> Result<Option<LeafNode>> {
         if let Some(upper_bound) = self.upper_bound {
             if self.locator.number() >= upper_bound {
                 return Ok(None);
@@ -53,10 +58,10 @@ impl BlockIds {
         let encoded = self.locator.encode(self.branch.keys().read());
         let mut tx = self.branch.store().begin_read().await?;
 
-        match tx.find_block_at(&self.root_node, &encoded).await {
-            Ok(block_id) => {
+        match tx.find_leaf_node_at(&self.root_node, &encoded).await {
+            Ok(node) => {
                 self.locator = self.locator.next();
-                Ok(Some(block_id))
+                Ok(Some(node))
             }
             Err(error @ store::Error::LocatorNotFound) => {
                 // There are two reasons why this error can be returned here:
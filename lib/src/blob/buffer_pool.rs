@@ -0,0 +1,180 @@
+//! Limit on the number of decrypted blocks kept in memory by the open blobs.
+
+use deadlock::BlockingMutex;
+use metrics::Gauge;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Default max number of decrypted blocks held by all the blobs of a repository.
+const DEFAULT_CAPACITY: usize = 4096; // 128 MiB
+
+/// Pool of permits to hold a decrypted block in a blob cache. Shared among all branches of a
+/// repository. Each cached block holds one permit which is released when the block is evicted from
+/// the cache or when the blob is dropped. When the pool is exhausted, blobs first recycle their own
+/// clean blocks and otherwise wait until another blob releases some. Waiting is only done outside
+/// of db transactions (otherwise a blob waiting for a permit inside a transaction could deadlock
+/// with the holder of the permit waiting for that transaction). Inside transactions, the pool is
+/// allowed to be temporarily exceeded instead (see [`Self::acquire_now`]).
+///
+/// Note that short-lived buffers used only within a single blob operation (e.g., when updating
+/// the blob length during flush) don't count towards the limit.
+#[derive(Clone)]
+pub(crate) struct BufferPool {
+    shared: Arc<Shared>,
+}
+
+impl BufferPool {
+    pub fn new(gauge: Gauge) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: BlockingMutex::new(State {
+                    capacity: DEFAULT_CAPACITY,
+                    in_use: 0,
+                }),
+                notify: Notify::new(),
+                gauge,
+            }),
+        }
+    }
+
+    /// Sets the max number of permits. If more than that are currently in use, no new ones are
+    /// given until enough of them are released.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.shared.state.lock().unwrap().capacity = capacity.max(1);
+        self.shared.notify.notify_waiters();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.state.lock().unwrap().capacity
+    }
+
+    /// Number of permits currently in use.
+    pub fn in_use(&self) -> usize {
+        self.shared.state.lock().unwrap().in_use
+    }
+
+    /// Acquires a permit, waiting for one to be released if the pool is exhausted.
+    pub async fn acquire(&self) -> BufferPermit {
+        loop {
+            // Create the `Notified` before checking the state so that no release is missed.
+            let notified = self.shared.notify.notified();
+
+            if let Some(permit) = self.try_acquire() {
+                return permit;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Acquires a permit without waiting. If the pool is exhausted, it's temporarily exceeded. The
+    /// excess permits are not given back out until enough permits are released.
+    pub fn acquire_now(&self) -> BufferPermit {
+        let mut state = self.shared.state.lock().unwrap();
+
+        state.in_use += 1;
+        self.shared.gauge.increment(1.0);
+
+        BufferPermit {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Acquires a permit if the pool is not exhausted.
+    pub fn try_acquire(&self) -> Option<BufferPermit> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.in_use >= state.capacity {
+            return None;
+        }
+
+        state.in_use += 1;
+        self.shared.gauge.increment(1.0);
+
+        Some(BufferPermit {
+            shared: self.shared.clone(),
+        })
+    }
+}
+
+/// Permit to hold one decrypted block. Released on drop.
+pub(crate) struct BufferPermit {
+    shared: Arc<Shared>,
+}
+
+impl Drop for BufferPermit {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().in_use -= 1;
+        self.shared.gauge.decrement(1.0);
+        self.shared.notify.notify_waiters();
+    }
+}
+
+struct Shared {
+    state: BlockingMutex<State>,
+    notify: Notify,
+    gauge: Gauge,
+}
+
+struct State {
+    capacity: usize,
+    in_use: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn acquire_waits_for_release() {
+        let pool = BufferPool::new(Gauge::noop());
+        pool.set_capacity(2);
+
+        let permit_0 = pool.try_acquire().unwrap();
+        let _permit_1 = pool.try_acquire().unwrap();
+        assert!(pool.try_acquire().is_none());
+        assert_eq!(pool.in_use(), 2);
+
+        assert!(timeout(Duration::from_millis(50), pool.acquire())
+            .await
+            .is_err());
+
+        let (_permit_2, ()) = tokio::join!(pool.acquire(), async move {
+            tokio::task::yield_now().await;
+            drop(permit_0);
+        });
+
+        assert_eq!(pool.in_use(), 2);
+    }
+
+    #[test]
+    fn acquire_now_exceeds_capacity() {
+        let pool = BufferPool::new(Gauge::noop());
+        pool.set_capacity(1);
+
+        let _permit_0 = pool.acquire_now();
+        let permit_1 = pool.acquire_now();
+        assert_eq!(pool.in_use(), 2);
+
+        // No permits given out until the pool is below the capacity again.
+        drop(permit_1);
+        assert!(pool.try_acquire().is_none());
+    }
+
+    #[tokio::test]
+    async fn increase_capacity() {
+        let pool = BufferPool::new(Gauge::noop());
+        pool.set_capacity(1);
+
+        let _permit_0 = pool.acquire().await;
+
+        let (_permit_1, ()) = tokio::join!(pool.acquire(), async {
+            tokio::task::yield_now().await;
+            pool.set_capacity(2);
+        });
+
+        assert_eq!(pool.in_use(), 2);
+    }
+}
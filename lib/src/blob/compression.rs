@@ -0,0 +1,104 @@
+//! Optional compression of the block plaintext.
+//!
+//! A compressed block is stored (and transferred) as `[COMPRESSION_LZ4, <data len>, <lz4 data>...]`
+//! followed by zero padding up to the next multiple of `BUCKET_SIZE`, encrypted the same way as an
+//! uncompressed one. Blocks are compressed only if that makes them shorter than `BLOCK_SIZE`, so
+//! uncompressed blocks are always exactly `BLOCK_SIZE` long and compressed ones are always shorter.
+//! This means the length of the stored content alone tells the two kinds apart and blocks written
+//! before compression was introduced remain valid. The leading byte identifies the compression
+//! method, to allow adding other ones in the future.
+//!
+//! The padding is there because the length of a block is visible to anyone who stores it, including
+//! replicas without read access, and the exact compressed length would reveal a lot about the
+//! plaintext. With the padding it reveals only roughly how compressible the block is (which of the
+//! few possible lengths it has). That is still more than uncompressed blocks reveal (nothing),
+//! which is why compression is opt-in.
+//!
+//! Note the block id is computed from the stored (that is, possibly compressed and always
+//! encrypted) content as usual. So the same plaintext produces a different block id depending on
+//! whether it was stored compressed or not. This doesn't affect correctness, only deduplication of
+//! blocks between replicas that use different compression settings.
+
+use crate::protocol::{BlockContent, BLOCK_SIZE};
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+const COMPRESSION_LZ4: u8 = 1;
+
+/// Compressed blocks are padded to a multiple of this.
+pub(super) const BUCKET_SIZE: usize = BLOCK_SIZE / 8;
+
+// Compression method and the length of the compressed data.
+const HEADER_SIZE: usize = 1 + mem::size_of::<u32>();
+
+/// Whether newly written blocks should be compressed. Shared among all branches of a repository.
+/// Disabled by default.
+#[derive(Clone, Default)]
+pub(crate) struct CompressionSetting(Arc<AtomicBool>);
+
+impl CompressionSetting {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Compresses the given block plaintext. Returns `None` if the data is not compressible enough
+/// for the padded compressed block to be shorter than `BLOCK_SIZE`, in which case the block should
+/// be stored uncompressed.
+pub(super) fn compress(content: &BlockContent) -> Option<BlockContent> {
+    let mut buffer = BlockContent::new();
+
+    // Leave at least one bucket unused so the padded block is strictly shorter than an
+    // uncompressed one.
+    let len = lz4_flex::block::compress_into(
+        &content[..],
+        &mut buffer[HEADER_SIZE..BLOCK_SIZE - BUCKET_SIZE],
+    )
+    .ok()?;
+
+    buffer[0] = COMPRESSION_LZ4;
+    buffer[1..HEADER_SIZE].copy_from_slice(&(len as u32).to_le_bytes());
+
+    // The buffer is zero initialized so the padding is zeros.
+    let padded_len = (HEADER_SIZE + len).div_ceil(BUCKET_SIZE) * BUCKET_SIZE;
+
+    Some(BlockContent::from_slice(&buffer[..padded_len]))
+}
+
+/// Decompresses the given block plaintext if it's compressed, otherwise returns it unchanged.
+/// Returns `None` if the content is malformed.
+pub(super) fn decompress(content: BlockContent) -> Option<BlockContent> {
+    if content.len() == BLOCK_SIZE {
+        return Some(content);
+    }
+
+    if content.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let len = u32::from_le_bytes(content[1..HEADER_SIZE].try_into().ok()?) as usize;
+    let data = content[HEADER_SIZE..].get(..len)?;
+
+    match content[0] {
+        COMPRESSION_LZ4 => {
+            let mut buffer = BlockContent::new();
+            let len = lz4_flex::block::decompress_into(data, &mut buffer[..]).ok()?;
+
+            (len == BLOCK_SIZE).then_some(buffer)
+        }
+        _ => None,
+    }
+}
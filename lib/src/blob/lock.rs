@@ -7,7 +7,10 @@ use crate::{
     sync::{AwaitDrop, DropAwaitable},
 };
 use deadlock::BlockingMutex;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 
 /// Container for blob locks in all branches.
 #[derive(Default, Clone)]
@@ -48,6 +51,47 @@ impl Locker {
             })
             .collect()
     }
+
+    /// Returns the branch id, blob id, kind and the number of holders of every currently held
+    /// lock.
+    pub fn held(&self) -> Vec<(PublicKey, BlobId, LockKind, usize)> {
+        self.shared
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|(branch_id, states)| {
+                states.iter().map(|(blob_id, state)| {
+                    let (kind, count) = match state.kind {
+                        Kind::Read(count) => (LockKind::Read, count),
+                        Kind::Write(count) => (LockKind::Write, count),
+                        Kind::Unique => (LockKind::Unique, 1),
+                    };
+
+                    (*branch_id, *blob_id, kind, count)
+                })
+            })
+            .collect()
+    }
+
+    /// Forcibly releases the locks of the given blob in all branches, regardless of whether their
+    /// holders still exist. Returns the number of branches the blob was locked in. The holders
+    /// are not notified and can keep using the blob as if they still held the lock, only their
+    /// release (drop) becomes no-op. So this is only safe when the holders are known to be gone
+    /// (e.g. leaked).
+    pub fn force_release(&self, blob_id: &BlobId) -> usize {
+        let mut shared = self.shared.lock().unwrap();
+        let mut count = 0;
+
+        shared.retain(|_, states| {
+            if states.remove(blob_id).is_some() {
+                count += 1;
+            }
+
+            !states.is_empty()
+        });
+
+        count
+    }
 }
 
 /// Container for blob locks in a given branch.
@@ -67,16 +111,14 @@ impl BranchLocker {
             .entry(self.branch_id)
             .or_default()
             .entry(blob_id)
-            .or_insert(State::new(Kind::Read(0)));
+            .or_insert_with(|| State::new(Kind::Read(0)));
 
         match &mut state.kind {
             Kind::Read(count) | Kind::Write(count) => {
                 *count = count.checked_add(1).expect("lock limit reached");
 
                 Ok(ReadLock {
-                    shared: self.shared.clone(),
-                    branch_id: self.branch_id,
-                    blob_id,
+                    key: Key::new(self, blob_id, state),
                 })
             }
             Kind::Unique => Err(state.notify.subscribe()),
@@ -102,12 +144,10 @@ impl BranchLocker {
 
         match shared.entry(self.branch_id).or_default().entry(blob_id) {
             Entry::Vacant(entry) => {
-                entry.insert(State::new(Kind::Unique));
+                let state = entry.insert(State::new(Kind::Unique));
 
                 Ok(UniqueLock {
-                    shared: self.shared.clone(),
-                    branch_id: self.branch_id,
-                    blob_id,
+                    key: Key::new(self, blob_id, state),
                 })
             }
             Entry::Occupied(mut entry) => {
@@ -125,168 +165,166 @@ impl BranchLocker {
     }
 }
 
-/// Lock that signals that the blob is being read. It protects the blob from being removed.
-pub(crate) struct ReadLock {
+/// Identifies the lock state a lock belongs to.
+struct Key {
     shared: Arc<Shared>,
     branch_id: PublicKey,
     blob_id: BlobId,
+    // Distinguishes the state from a state of the same blob created after this one has been
+    // forcibly released (see `Locker::force_release`).
+    generation: u64,
 }
 
-impl ReadLock {
-    pub fn blob_id(&self) -> &BlobId {
-        &self.blob_id
+impl Key {
+    fn new(locker: &BranchLocker, blob_id: BlobId, state: &State) -> Self {
+        Self {
+            shared: locker.shared.clone(),
+            branch_id: locker.branch_id,
+            blob_id,
+            generation: state.generation,
+        }
     }
 
-    pub fn upgrade(&self) -> Option<WriteLock> {
-        let mut shared = self.shared.lock().unwrap();
+    fn duplicate(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            branch_id: self.branch_id,
+            blob_id: self.blob_id,
+            generation: self.generation,
+        }
+    }
 
-        let Some(state) = shared
+    /// Calls `f` with the state of the lock, unless it's been forcibly released.
+    fn with_state<R>(&self, f: impl FnOnce(&mut State) -> R) -> Option<R> {
+        self.shared
+            .lock()
+            .unwrap()
             .get_mut(&self.branch_id)
             .and_then(|states| states.get_mut(&self.blob_id))
-        else {
-            unreachable!();
+            .filter(|state| state.generation == self.generation)
+            .map(f)
+    }
+
+    /// Releases the lock. `f` updates the state and returns whether it should be removed. Does
+    /// nothing if the lock has been forcibly released.
+    fn release(&self, f: impl FnOnce(&mut Kind) -> bool) {
+        let mut shared = self.shared.lock().unwrap();
+
+        let Entry::Occupied(mut states_entry) = shared.entry(self.branch_id) else {
+            return;
         };
 
-        match &mut state.kind {
-            Kind::Read(count) => {
-                state.kind = Kind::Write(count.checked_add(1).expect("lock count limit exceeded"));
+        let Entry::Occupied(mut state_entry) = states_entry.get_mut().entry(self.blob_id) else {
+            return;
+        };
 
-                Some(WriteLock {
-                    shared: self.shared.clone(),
-                    branch_id: self.branch_id,
-                    blob_id: self.blob_id,
-                })
-            }
-            Kind::Write(_) => None,
-            Kind::Unique => unreachable!(),
+        if state_entry.get().generation != self.generation {
+            return;
+        }
+
+        if f(&mut state_entry.get_mut().kind) {
+            state_entry.remove();
+        }
+
+        if states_entry.get().is_empty() {
+            states_entry.remove();
         }
     }
 }
 
-impl Clone for ReadLock {
-    fn clone(&self) -> Self {
-        let mut shared = self.shared.lock().unwrap();
+/// Lock that signals that the blob is being read. It protects the blob from being removed.
+pub(crate) struct ReadLock {
+    key: Key,
+}
 
-        let Some(state) = shared
-            .get_mut(&self.branch_id)
-            .and_then(|states| states.get_mut(&self.blob_id))
-        else {
-            unreachable!();
-        };
+impl ReadLock {
+    pub fn blob_id(&self) -> &BlobId {
+        &self.key.blob_id
+    }
 
-        match &mut state.kind {
+    pub fn upgrade(&self) -> Option<WriteLock> {
+        self.key
+            .with_state(|state| match &mut state.kind {
+                Kind::Read(count) => {
+                    state.kind =
+                        Kind::Write(count.checked_add(1).expect("lock count limit exceeded"));
+
+                    Some(WriteLock {
+                        key: self.key.duplicate(),
+                    })
+                }
+                Kind::Write(_) => None,
+                Kind::Unique => unreachable!(),
+            })
+            // Forcibly released, can't be upgraded anymore.
+            .flatten()
+    }
+}
+
+impl Clone for ReadLock {
+    fn clone(&self) -> Self {
+        self.key.with_state(|state| match &mut state.kind {
             Kind::Read(count) | Kind::Write(count) => {
                 *count = count.checked_add(1).expect("lock count limit exceeded");
-
-                Self {
-                    shared: self.shared.clone(),
-                    branch_id: self.branch_id,
-                    blob_id: self.blob_id,
-                }
             }
             Kind::Unique => unreachable!(),
+        });
+
+        // If the lock has been forcibly released, the clone is released as well.
+        Self {
+            key: self.key.duplicate(),
         }
     }
 }
 
 impl Drop for ReadLock {
     fn drop(&mut self) {
-        let mut shared = self.shared.lock().unwrap();
-
-        let Entry::Occupied(mut states_entry) = shared.entry(self.branch_id) else {
-            unreachable!();
-        };
-
-        let Entry::Occupied(mut state_entry) = states_entry.get_mut().entry(self.blob_id) else {
-            unreachable!();
-        };
-
-        match &mut state_entry.get_mut().kind {
+        self.key.release(|kind| match kind {
             Kind::Read(count) | Kind::Write(count) => {
                 *count = count.checked_sub(1).expect("lock count cannot be zero");
-
-                if *count == 0 {
-                    state_entry.remove();
-                }
+                *count == 0
             }
             Kind::Unique => unreachable!(),
-        }
-
-        if states_entry.get().is_empty() {
-            states_entry.remove();
-        }
+        })
     }
 }
 
 /// Lock that signals that the blob is being written to. It protects the blob from being removed
 /// (same as read lock) and additionally protects it from being written to by anyone else.
 pub(crate) struct WriteLock {
-    shared: Arc<Shared>,
-    branch_id: PublicKey,
-    blob_id: BlobId,
+    key: Key,
 }
 
 impl Drop for WriteLock {
     fn drop(&mut self) {
-        let mut shared = self.shared.lock().unwrap();
-
-        let Entry::Occupied(mut states_entry) = shared.entry(self.branch_id) else {
-            unreachable!();
-        };
-
-        let Entry::Occupied(mut state_entry) = states_entry.get_mut().entry(self.blob_id) else {
-            unreachable!();
-        };
-
-        match &mut state_entry.get_mut().kind {
+        self.key.release(|kind| match kind {
             Kind::Write(count) => {
                 *count = count.checked_sub(1).expect("lock count cannot be zero");
 
                 if *count > 0 {
-                    state_entry.get_mut().kind = Kind::Read(*count);
+                    *kind = Kind::Read(*count);
+                    false
                 } else {
-                    state_entry.remove();
+                    true
                 }
             }
             Kind::Read(_) | Kind::Unique => unreachable!(),
-        }
-
-        if states_entry.get().is_empty() {
-            states_entry.remove();
-        }
+        })
     }
 }
 
 /// Lock that expresses unique (exclusive) access to a blob. No one else except the owner of the
 /// lock can access the blob in any way while this lock is held.
 pub(crate) struct UniqueLock {
-    shared: Arc<Shared>,
-    branch_id: PublicKey,
-    blob_id: BlobId,
+    key: Key,
 }
 
 impl Drop for UniqueLock {
     fn drop(&mut self) {
-        let mut shared = self.shared.lock().unwrap();
-
-        let Entry::Occupied(mut states_entry) = shared.entry(self.branch_id) else {
-            unreachable!();
-        };
-
-        let Entry::Occupied(mut state_entry) = states_entry.get_mut().entry(self.blob_id) else {
-            unreachable!();
-        };
-
-        match &mut state_entry.get_mut().kind {
-            Kind::Unique => {
-                state_entry.remove();
-            }
+        self.key.release(|kind| match kind {
+            Kind::Unique => true,
             Kind::Read(_) | Kind::Write(_) => unreachable!(),
-        }
-
-        if states_entry.get().is_empty() {
-            states_entry.remove();
-        }
+        })
     }
 }
 
@@ -313,6 +351,7 @@ impl UpgradableLock {
 }
 
 /// Type of the lock currently being held for some blob.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub(crate) enum LockKind {
     Read,
     Write,
@@ -322,13 +361,17 @@ pub(crate) enum LockKind {
 struct State {
     kind: Kind,
     notify: DropAwaitable,
+    generation: u64,
 }
 
 impl State {
     fn new(kind: Kind) -> Self {
+        static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
         Self {
             kind,
             notify: DropAwaitable::new(),
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
         }
     }
 }
@@ -385,4 +428,71 @@ mod tests {
         drop(remove1);
         let _read3 = locker.try_read(blob_id).ok().unwrap();
     }
+
+    #[test]
+    fn held() {
+        let branch_id = PublicKey::random();
+        let blob_id_0: BlobId = rand::random();
+        let blob_id_1: BlobId = rand::random();
+
+        let locker = Locker::new();
+        assert!(locker.held().is_empty());
+
+        let branch_locker = locker.branch(branch_id);
+
+        let read0 = branch_locker.try_read(blob_id_0).ok().unwrap();
+        let _read1 = read0.clone();
+        let write = read0.upgrade().unwrap();
+        let unique = branch_locker.try_unique(blob_id_1).ok().unwrap();
+
+        let mut held = locker.held();
+        held.sort_by_key(|(_, blob_id, _, _)| *blob_id);
+
+        let mut expected = vec![
+            (branch_id, blob_id_0, LockKind::Write, 3),
+            (branch_id, blob_id_1, LockKind::Unique, 1),
+        ];
+        expected.sort_by_key(|(_, blob_id, _, _)| *blob_id);
+
+        assert_eq!(held, expected);
+
+        drop(write);
+        drop(unique);
+
+        assert_eq!(locker.held(), [(branch_id, blob_id_0, LockKind::Read, 2)]);
+    }
+
+    #[test]
+    fn force_release() {
+        let branch_id = PublicKey::random();
+        let blob_id: BlobId = rand::random();
+
+        let locker = Locker::new();
+        let branch_locker = locker.branch(branch_id);
+
+        let read0 = branch_locker.try_read(blob_id).ok().unwrap();
+        let write0 = read0.upgrade().unwrap();
+        assert!(branch_locker.try_unique(blob_id).is_err());
+
+        assert_eq!(locker.force_release(&blob_id), 1);
+        assert!(locker.held().is_empty());
+        assert_eq!(locker.force_release(&blob_id), 0);
+
+        // The blob can be locked again.
+        let read1 = branch_locker.try_read(blob_id).ok().unwrap();
+        assert!(read1.upgrade().is_some());
+
+        // The released locks still exist but can't be upgraded and dropping them doesn't affect
+        // the new ones.
+        assert!(read0.upgrade().is_none());
+        let read2 = read0.clone();
+        drop(write0);
+        drop(read0);
+        drop(read2);
+        assert_eq!(locker.held(), [(branch_id, blob_id, LockKind::Read, 1)]);
+
+        drop(read1);
+        assert!(locker.held().is_empty());
+        let _unique = branch_locker.try_unique(blob_id).ok().unwrap();
+    }
 }
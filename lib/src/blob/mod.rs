@@ -1,15 +1,19 @@
 pub(crate) mod lock;
 
 mod block_ids;
+mod buffer_pool;
+mod compression;
 mod id;
 mod position;
 
 #[cfg(test)]
 mod tests;
 
-pub(crate) use self::{block_ids::BlockIds, id::BlobId};
+pub(crate) use self::{
+    block_ids::BlockIds, buffer_pool::BufferPool, compression::CompressionSetting, id::BlobId,
+};
 
-use self::position::Position;
+use self::{buffer_pool::BufferPermit, position::Position};
 use crate::{
     branch::Branch,
     collections::{hash_map::Entry, HashMap},
@@ -25,7 +29,7 @@ use crate::{
     },
     store::{self, Changeset, ReadTransaction},
 };
-use std::{io::SeekFrom, iter, mem};
+use std::{io::SeekFrom, iter, mem, ops::Range};
 use thiserror::Error;
 
 /// Size of the blob header in bytes.
@@ -43,15 +47,28 @@ pub(crate) enum ReadWriteError {
     CacheMiss,
     #[error("cache is full")]
     CacheFull,
+    #[error("no block buffer available")]
+    NoBuffer,
+    #[error("blob would exceed the maximum size")]
+    TooLarge,
 }
 
 pub(crate) struct Blob {
     branch: Branch,
     id: BlobId,
     cache: HashMap<u32, CachedBlock>,
+    // Permit for the next block to be loaded into the cache (see `reserve`).
+    spare: Option<BufferPermit>,
+    // Created but not yet flushed.
+    is_new: bool,
     len_original: u64,
     len_modified: u64,
     position: Position,
+    // Whether the blob is subject to the max file size of the repository (see `limit_size`).
+    size_limited: bool,
+    // Blocks cleared by `grow` which are not flushed yet. Instead of being kept in the cache they
+    // are linked to the zero block on flush.
+    zeroed: Range<u32>,
 }
 
 impl Blob {
@@ -69,11 +86,13 @@ impl Blob {
     ) -> Result<Self> {
         assert_eq!(root_node.proof.writer_id, *branch.id());
 
+        // Not waiting for a buffer here because we are inside a transaction (see `BufferPool`).
+        let permit = branch.buffer_pool().acquire_now();
         let (_, buffer) =
             read_block(tx, root_node, &Locator::head(id), branch.keys().read()).await?;
 
         let len = buffer.read_u64(0);
-        let cached_block = CachedBlock::from(buffer);
+        let cached_block = CachedBlock::new(buffer, permit);
         let cache = iter::once((0, cached_block)).collect();
         let position = Position::ZERO;
 
@@ -81,27 +100,42 @@ impl Blob {
             branch,
             id,
             cache,
+            spare: None,
+            is_new: false,
             len_original: len,
             len_modified: len,
             position,
+            size_limited: false,
+            zeroed: 0..0,
         })
     }
 
     /// Creates a new blob.
     pub fn create(branch: Branch, id: BlobId) -> Self {
-        let cached_block = CachedBlock::new().with_dirty(true);
-        let cache = iter::once((0, cached_block)).collect();
-
+        // The first block is not put into the cache until something is written into it (see
+        // `write_blocks`) so that creating a blob never has to wait for a buffer.
         Self {
             branch,
             id,
-            cache,
+            cache: HashMap::default(),
+            spare: None,
+            is_new: true,
             len_original: 0,
             len_modified: 0,
             position: Position::ZERO,
+            size_limited: false,
+            zeroed: 0..0,
         }
     }
 
+    /// Makes this blob subject to the max file size of the repository: writes and grows that would
+    /// extend it past that size fail with `ReadWriteError::TooLarge`. Rewriting or shrinking a blob
+    /// that is already larger than that is still allowed.
+    pub fn limit_size(mut self) -> Self {
+        self.size_limited = true;
+        self
+    }
+
     pub fn branch(&self) -> &Branch {
         &self.branch
     }
@@ -116,6 +150,11 @@ impl Blob {
         self.len_modified
     }
 
+    /// Length of this blob in bytes as of the last flush.
+    pub fn committed_len(&self) -> u64 {
+        self.len_original
+    }
+
     // Returns the current seek position from the start of the blob.
     pub fn seek_position(&self) -> u64 {
         self.position.get()
@@ -127,7 +166,10 @@ impl Blob {
 
     /// Was this blob modified and not flushed yet?
     pub fn is_dirty(&self) -> bool {
-        self.cache.values().any(|block| block.dirty) || self.len_modified != self.len_original
+        self.is_new
+            || self.cache.values().any(|block| block.dirty)
+            || self.len_modified != self.len_original
+            || !self.zeroed.is_empty()
     }
 
     /// Seek to an offset in the blob.
@@ -172,11 +214,8 @@ impl Blob {
         let block = match self.cache.get(&self.position.block) {
             Some(block) => block,
             None => {
-                if self.check_cache_capacity() {
-                    return Err(ReadWriteError::CacheMiss);
-                } else {
-                    return Err(ReadWriteError::CacheFull);
-                }
+                self.spare = Some(self.reserve()?);
+                return Err(ReadWriteError::CacheMiss);
             }
         };
 
@@ -223,10 +262,12 @@ impl Blob {
                     offset += len;
                 }
                 Err(ReadWriteError::CacheMiss) => self.warmup_at(tx, root_node).await?,
+                Err(ReadWriteError::NoBuffer) => self.reserve_now(),
                 Err(ReadWriteError::CacheFull) => {
                     tracing::error!("cache full");
                     return Err(Error::OperationNotSupported);
                 }
+                Err(ReadWriteError::TooLarge) => unreachable!("reading never extends the blob"),
             }
         }
 
@@ -276,18 +317,32 @@ impl Blob {
             return Ok(0);
         }
 
+        let buffer = match self.max_len() {
+            Some(max) => {
+                let remaining = max.saturating_sub(self.position.get());
+
+                if remaining == 0 {
+                    return Err(ReadWriteError::TooLarge);
+                }
+
+                &buffer[..buffer.len().min(remaining.try_into().unwrap_or(usize::MAX))]
+            }
+            None => buffer,
+        };
+
         let block = match self.cache.get_mut(&self.position.block) {
             Some(block) => block,
             None => {
-                if !self.check_cache_capacity() {
-                    return Err(ReadWriteError::CacheFull);
-                }
+                let permit = self.reserve()?;
 
                 if self.position.get() >= self.len_modified
                     || self.position.offset == 0 && buffer.len() >= BLOCK_SIZE
                 {
-                    self.cache.entry(self.position.block).or_default()
+                    self.cache
+                        .entry(self.position.block)
+                        .or_insert_with(|| CachedBlock::new(BlockContent::new(), permit))
                 } else {
+                    self.spare = Some(permit);
                     return Err(ReadWriteError::CacheMiss);
                 }
             }
@@ -323,9 +378,13 @@ impl Blob {
                 Err(ReadWriteError::CacheMiss) => {
                     self.warmup(tx).await?;
                 }
+                Err(ReadWriteError::NoBuffer) => {
+                    self.reserve_now();
+                }
                 Err(ReadWriteError::CacheFull) => {
                     self.flush(tx, changeset).await?;
                 }
+                Err(ReadWriteError::TooLarge) => return Err(Error::FileTooLarge),
             }
         }
 
@@ -342,6 +401,14 @@ impl Blob {
         Ok(())
     }
 
+    /// Id of the block at the current seek position.
+    pub async fn current_block_id(&self, tx: &mut ReadTransaction) -> Result<BlockId> {
+        let locator = Locator::head(self.id).nth(self.position.block);
+        let encoded_locator = locator.encode(self.branch.keys().read());
+
+        Ok(tx.find_block(self.branch.id(), &encoded_locator).await?)
+    }
+
     /// Load the current block at the given snapshot into the cache.
     pub async fn warmup_at(
         &mut self,
@@ -351,16 +418,43 @@ impl Blob {
         match self.cache.entry(self.position.block) {
             Entry::Occupied(_) => (),
             Entry::Vacant(entry) => {
-                let locator = Locator::head(self.id).nth(self.position.block);
-                let (_, buffer) =
-                    read_block(tx, root_node, &locator, self.branch.keys().read()).await?;
-                entry.insert(CachedBlock::from(buffer));
+                let permit = match self.spare.take() {
+                    Some(permit) => permit,
+                    None => self.branch.buffer_pool().acquire_now(),
+                };
+
+                let buffer = if self.zeroed.contains(&self.position.block) {
+                    BlockContent::new()
+                } else {
+                    let locator = Locator::head(self.id).nth(self.position.block);
+                    read_block(tx, root_node, &locator, self.branch.keys().read())
+                        .await?
+                        .1
+                };
+
+                entry.insert(CachedBlock::new(buffer, permit));
             }
         }
 
         Ok(())
     }
 
+    /// Waits until a block buffer becomes available. To be called when an operation fails with
+    /// `ReadWriteError::NoBuffer`, before retrying it. Must not be called while holding a db
+    /// transaction (use `reserve_now` instead).
+    pub async fn wait_for_buffer(&mut self) {
+        if self.spare.is_none() {
+            self.spare = Some(self.branch.buffer_pool().acquire().await);
+        }
+    }
+
+    /// Like `wait_for_buffer` but doesn't wait, temporarily exceeding the buffer pool instead.
+    fn reserve_now(&mut self) {
+        if self.spare.is_none() {
+            self.spare = Some(self.branch.buffer_pool().acquire_now());
+        }
+    }
+
     /// Truncate the blob to the given length.
     pub fn truncate(&mut self, len: u64) -> Result<()> {
         if len == self.len() {
@@ -381,6 +475,83 @@ impl Blob {
         Ok(())
     }
 
+    /// Extends the blob to the given length, filling the added space with zeros. Leaves the seek
+    /// position at the end of the blob.
+    ///
+    /// Only the partially filled last block is modified in the cache. The blocks past it are
+    /// linked to the zero block when the blob is flushed, so the memory used doesn't depend on how
+    /// much the blob grows and the whole change is saved atomically by a single flush. Clearing
+    /// them (as opposed to just bumping the length) is needed because the blocks past the current
+    /// length might still contain data from before a previous truncation.
+    pub fn grow(&mut self, len: u64) -> Result<(), ReadWriteError> {
+        if len <= self.len_modified {
+            return Ok(());
+        }
+
+        if self.max_len().is_some_and(|max| len > max) {
+            return Err(ReadWriteError::TooLarge);
+        }
+
+        self.position.set(self.len_modified);
+
+        let start = if self.position.offset > 0 {
+            let block = match self.cache.get_mut(&self.position.block) {
+                Some(block) => block,
+                None => {
+                    // Only this one block is needed so it's fine to temporarily exceed the cache
+                    // capacity instead of flushing the dirty blocks.
+                    let permit = match self.reserve() {
+                        Ok(permit) => permit,
+                        Err(ReadWriteError::CacheFull) => self.branch.buffer_pool().acquire_now(),
+                        Err(error) => return Err(error),
+                    };
+
+                    // The block needs to be loaded first to preserve its existing content.
+                    self.spare = Some(permit);
+                    return Err(ReadWriteError::CacheMiss);
+                }
+            };
+
+            block.content[self.position.offset..].fill(0);
+            block.dirty = true;
+
+            self.position.block + 1
+        } else {
+            self.position.block
+        };
+
+        let end = block_count(len);
+
+        if start < end {
+            self.cache
+                .retain(|number, _| !(start..end).contains(number));
+
+            self.zeroed = if self.zeroed.is_empty() {
+                start..end
+            } else {
+                self.zeroed.start.min(start)..self.zeroed.end.max(end)
+            };
+        }
+
+        self.len_modified = len;
+        self.position.set(len);
+
+        Ok(())
+    }
+
+    // Length this blob can be extended to without exceeding the max file size, or `None` if not
+    // limited. Never less than the current length.
+    fn max_len(&self) -> Option<u64> {
+        if !self.size_limited {
+            return None;
+        }
+
+        self.branch
+            .max_file_size()
+            .get()
+            .map(|max| max.max(self.len_modified))
+    }
+
     /// Flushes this blob, ensuring that all intermediately buffered contents gets written to the
     /// store.
     pub(crate) async fn flush(
@@ -390,10 +561,39 @@ impl Blob {
     ) -> Result<()> {
         self.write_len(tx, changeset).await?;
         self.write_blocks(changeset);
+        self.len_original = self.len_modified;
 
         Ok(())
     }
 
+    /// Like `flush` but leaves this blob dirty until `mark_flushed` is called. To be used when the
+    /// changeset is committed only after some further awaits, so that if the commit doesn't happen
+    /// (because it failed or the future was cancelled) the modifications are not lost but written
+    /// again by the next flush. The cost is that the dirty blocks are copied into the changeset
+    /// instead of moved.
+    pub(crate) async fn prepare_flush(
+        &mut self,
+        tx: &mut ReadTransaction,
+        changeset: &mut Changeset,
+    ) -> Result<()> {
+        self.write_len(tx, changeset).await?;
+        self.copy_blocks(changeset);
+
+        Ok(())
+    }
+
+    /// Marks all the modifications written by the previous `prepare_flush` as flushed.
+    pub(crate) fn mark_flushed(&mut self) {
+        self.is_new = false;
+        self.len_original = self.len_modified;
+
+        self.zeroed = 0..0;
+
+        for block in self.cache.values_mut() {
+            block.dirty = false;
+        }
+    }
+
     /// Remove this blob from the store.
     pub(crate) fn remove(self, changeset: &mut Changeset) {
         let locators = Locator::head(self.id)
@@ -406,9 +606,19 @@ impl Blob {
         }
     }
 
-    fn check_cache_capacity(&mut self) -> bool {
+    /// Obtains a permit to load a new block into the cache. If the cache or the buffer pool is
+    /// full, a clean block is evicted and its permit reused. Fails with `CacheFull` if all the
+    /// cached blocks are dirty (flushing them releases their permits) and with `NoBuffer` if
+    /// there are none, in which case the caller should `wait_for_buffer`.
+    fn reserve(&mut self) -> Result<BufferPermit, ReadWriteError> {
+        if let Some(permit) = self.spare.take() {
+            return Ok(permit);
+        }
+
         if self.cache.len() < CACHE_CAPACITY {
-            return true;
+            if let Some(permit) = self.branch.buffer_pool().try_acquire() {
+                return Ok(permit);
+            }
         }
 
         let number = self
@@ -417,11 +627,12 @@ impl Blob {
             .find(|(_, block)| !block.dirty)
             .map(|(number, _)| *number);
 
-        if let Some(number) = number {
-            self.cache.remove(&number);
-            true
+        if let Some(block) = number.and_then(|number| self.cache.remove(&number)) {
+            Ok(block.permit)
+        } else if self.cache.is_empty() {
+            Err(ReadWriteError::NoBuffer)
         } else {
-            false
+            Err(ReadWriteError::CacheFull)
         }
     }
 
@@ -446,15 +657,34 @@ impl Blob {
             let (_, mut content) =
                 read_block(tx, &root_node, &locator, self.branch.keys().read()).await?;
             content.write_u64(0, self.len_modified);
-            write_block(changeset, &locator, content, self.branch.keys().read());
+            write_block(
+                changeset,
+                &locator,
+                content,
+                self.branch.keys().read(),
+                self.branch.compression().is_enabled(),
+            );
         }
 
-        self.len_original = self.len_modified;
-
         Ok(())
     }
 
     fn write_blocks(&mut self, changeset: &mut Changeset) {
+        // Nothing has been written into the blob since it was created so its first block is not in
+        // the cache. It still needs to be written so the (empty) blob exists in the store.
+        if mem::take(&mut self.is_new) && !self.cache.contains_key(&0) {
+            write_block(
+                changeset,
+                &Locator::head(self.id),
+                BlockContent::new(),
+                self.branch.keys().read(),
+                self.branch.compression().is_enabled(),
+            );
+        }
+
+        self.write_zeroed(changeset);
+        self.zeroed = 0..0;
+
         // Poor man's `drain_filter`.
         let cache = mem::take(&mut self.cache);
         let (dirty, clean): (HashMap<_, _>, _) =
@@ -468,9 +698,68 @@ impl Blob {
                 &locator,
                 block.content,
                 self.branch.keys().read(),
+                self.branch.compression().is_enabled(),
             );
         }
     }
+
+    // Like `write_blocks` but keeps the dirty blocks in the cache.
+    fn copy_blocks(&self, changeset: &mut Changeset) {
+        if self.is_new && !self.cache.contains_key(&0) {
+            write_block(
+                changeset,
+                &Locator::head(self.id),
+                BlockContent::new(),
+                self.branch.keys().read(),
+                self.branch.compression().is_enabled(),
+            );
+        }
+
+        self.write_zeroed(changeset);
+
+        for (number, block) in self.cache.iter().filter(|(_, block)| block.dirty) {
+            let locator = Locator::head(self.id).nth(*number);
+            write_block(
+                changeset,
+                &locator,
+                block.content.clone(),
+                self.branch.keys().read(),
+                self.branch.compression().is_enabled(),
+            );
+        }
+    }
+
+    // Links the blocks cleared by `grow` to the zero block, except those that have been written
+    // into since (they are written together with the other dirty blocks).
+    fn write_zeroed(&self, changeset: &mut Changeset) {
+        let read_key = self.branch.keys().read();
+        let mut zero_block_id = None;
+
+        for number in self.zeroed.clone() {
+            if self.cache.get(&number).is_some_and(|block| block.dirty) {
+                continue;
+            }
+
+            let locator = Locator::head(self.id).nth(number);
+
+            if let Some(block_id) = zero_block_id {
+                changeset.link_block(
+                    locator.encode(read_key),
+                    block_id,
+                    SingleBlockPresence::Present,
+                );
+            } else {
+                // The zero block is the same for every locator so it needs to be written only once.
+                zero_block_id = Some(write_block(
+                    changeset,
+                    &locator,
+                    BlockContent::new(),
+                    read_key,
+                    false,
+                ));
+            }
+        }
+    }
 }
 
 // NOTE: Clone only creates a new instance of the same blob. It doesn't preserve dirtiness.
@@ -480,34 +769,29 @@ impl Clone for Blob {
             branch: self.branch.clone(),
             id: self.id,
             cache: HashMap::default(),
+            spare: None,
+            is_new: false,
             len_original: self.len_original,
             len_modified: self.len_original,
             position: self.position,
+            size_limited: self.size_limited,
+            zeroed: 0..0,
         }
     }
 }
 
-#[derive(Default)]
 struct CachedBlock {
     content: BlockContent,
     dirty: bool,
+    permit: BufferPermit,
 }
 
 impl CachedBlock {
-    fn new() -> Self {
-        Self::default()
-    }
-
-    fn with_dirty(self, dirty: bool) -> Self {
-        Self { dirty, ..self }
-    }
-}
-
-impl From<BlockContent> for CachedBlock {
-    fn from(content: BlockContent) -> Self {
+    fn new(content: BlockContent, permit: BufferPermit) -> Self {
         Self {
             content,
             dirty: false,
+            permit,
         }
     }
 }
@@ -614,6 +898,58 @@ pub(crate) async fn fork(blob_id: BlobId, src_branch: &Branch, dst_branch: &Bran
     Ok(())
 }
 
+/// Creates a shallow copy of the blob `src_id` from `src_branch` under the new id `dst_id`. The
+/// blocks are not copied, the locators of the new blob are only linked to the same blocks as the
+/// locators of the source blob. The changes are added to `changeset` which determines the
+/// destination branch.
+///
+/// Because a block is addressed by the locator (which depends on the blob id) in the index, the
+/// two blobs diverge as soon as any of them is modified: the modified blocks are written under the
+/// locators of the modified blob only.
+pub(crate) async fn link(
+    tx: &mut ReadTransaction,
+    changeset: &mut Changeset,
+    src_branch: &Branch,
+    src_id: BlobId,
+    dst_id: BlobId,
+) -> Result<()> {
+    let read_key = src_branch.keys().read();
+
+    let root_node = tx
+        .load_root_node(src_branch.id(), RootNodeFilter::Any)
+        .await?;
+    let end = load_block_count_hint(tx, &root_node, src_id, read_key).await?;
+
+    let locators = Locator::head(src_id)
+        .sequence()
+        .zip(Locator::head(dst_id).sequence())
+        .take(end as usize);
+
+    for (src_locator, dst_locator) in locators {
+        let block_id = match tx
+            .find_block_at(&root_node, &src_locator.encode(read_key))
+            .await
+        {
+            Ok(id) => id,
+            Err(store::Error::LocatorNotFound) => {
+                // end of the blob
+                break;
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let block_presence = if tx.block_exists(&block_id).await? {
+            SingleBlockPresence::Present
+        } else {
+            SingleBlockPresence::Missing
+        };
+
+        changeset.link_block(dst_locator.encode(read_key), block_id, block_presence);
+    }
+
+    Ok(())
+}
+
 fn block_count(len: u64) -> u32 {
     // https://stackoverflow.com/questions/2745074/fast-ceiling-of-an-integer-division-in-c-c
     (1 + (len + HEADER_SIZE as u64 - 1) / BLOCK_SIZE as u64)
@@ -657,10 +993,23 @@ async fn read_block(
         .await?;
 
     let mut content = BlockContent::new();
-    let nonce = tx.read_block(&id, &mut content).await?;
+    let nonce = match tx.read_block(&id, &mut content).await {
+        Ok(nonce) => nonce,
+        // The content of the zero block is known so there is no need to wait for it to be
+        // downloaded.
+        Err(store::Error::BlockNotFound) if id == zero_block_id(read_key) => {
+            return Ok((id, BlockContent::new()));
+        }
+        Err(error) => return Err(error.into()),
+    };
 
     decrypt_block(read_key, &nonce, &mut content);
 
+    let content = compression::decompress(content).ok_or_else(|| {
+        tracing::error!(?id, "malformed compressed block");
+        Error::MalformedData
+    })?;
+
     Ok((id, content))
 }
 
@@ -669,7 +1018,15 @@ fn write_block(
     locator: &Locator,
     mut content: BlockContent,
     read_key: &cipher::SecretKey,
+    compress: bool,
 ) -> BlockId {
+    // Zero blocks are shared (see `make_block_nonce`) so they are never compressed.
+    if compress && !is_zero(&content) {
+        if let Some(compressed) = compression::compress(&content) {
+            content = compressed;
+        }
+    }
+
     let nonce = make_block_nonce(locator, &content, read_key);
     encrypt_block(read_key, &nonce, &mut content);
 
@@ -705,6 +1062,13 @@ fn encrypt_block(blob_key: &cipher::SecretKey, block_nonce: &BlockNonce, content
 /// they have the same content and are at the same locator which means they are in fact the same
 /// block, just referenced from two different branches.
 ///
+/// The exception are blocks whose content is all zeros (including the unused tail of the last
+/// block of a blob). Those get the same nonce regardless of the locator, which makes them the same
+/// block as well (see `zero_block_id`). This way long runs of zeros in sparse files (disk images,
+/// preallocated databases, ...) are stored and transferred only once. A last block which contains
+/// zeros only up to the blob length but has some leftover data past it (e.g., after truncation)
+/// is not a zero block.
+///
 /// The reason nonces are computed this way instead of randomly is to guarantee two blocks with the
 /// same content at the same locators but in different branches have the same nonce and thus the
 /// same block_id even if they were created independently (as opposed to linking an existing block
@@ -718,7 +1082,29 @@ fn make_block_nonce(
     plaintext_content: &[u8],
     read_key: &cipher::SecretKey,
 ) -> BlockNonce {
+    if is_zero(plaintext_content) {
+        return make_zero_block_nonce(read_key);
+    }
+
     (read_key.as_ref(), locator, plaintext_content)
         .hash()
         .into()
 }
+
+fn make_zero_block_nonce(read_key: &cipher::SecretKey) -> BlockNonce {
+    (read_key.as_ref(), b"ouisync zero block").hash().into()
+}
+
+/// Id of the block whose plaintext content is all zeros. It's the same for every locator in the
+/// repository with the given read key.
+fn zero_block_id(read_key: &cipher::SecretKey) -> BlockId {
+    let mut content = BlockContent::new();
+    let nonce = make_zero_block_nonce(read_key);
+    encrypt_block(read_key, &nonce, &mut content);
+
+    BlockId::new(&content, &nonce)
+}
+
+fn is_zero(content: &[u8]) -> bool {
+    content.iter().all(|byte| *byte == 0)
+}
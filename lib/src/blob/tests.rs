@@ -10,10 +10,13 @@ use crate::{
     store::Store,
     test_utils,
 };
+use assert_matches::assert_matches;
 use proptest::collection::vec;
 use rand::{distributions::Standard, prelude::*};
+use std::time::Duration;
 use tempfile::TempDir;
 use test_strategy::proptest;
+use tokio::time;
 
 #[tokio::test(flavor = "multi_thread")]
 async fn empty_blob() {
@@ -858,6 +861,246 @@ async fn block_ids_of_identical_blocks_in_the_same_blob() {
     assert_ne!(block_ids[1], block_ids[2]);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn zero_blocks_are_shared() {
+    let (mut rng, _base_dir, store, [branch]) = setup(0).await;
+
+    let blob_id: BlobId = rng.gen();
+    let mut blob = Blob::create(branch.clone(), blob_id);
+
+    let mut content = random_bytes(&mut rng, BLOCK_SIZE - HEADER_SIZE);
+    content.extend(iter::repeat(0).take(3 * BLOCK_SIZE));
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+    blob.write_all(&mut tx, &mut changeset, &content)
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let block_ids: Vec<_> = BlockIds::open(branch.clone(), blob_id)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    assert_eq!(block_ids.len(), 4);
+    assert_eq!(block_ids[1], zero_block_id(branch.keys().read()));
+    assert_eq!(block_ids[2], block_ids[1]);
+    assert_eq!(block_ids[3], block_ids[1]);
+    assert_eq!(store.count_blocks().await.unwrap(), 2);
+
+    // The zero block content is synthesized even when the block is not present locally.
+    let mut tx = store.begin_write().await.unwrap();
+    tx.remove_block(&block_ids[1]).await.unwrap();
+    tx.commit().await.unwrap();
+
+    let mut tx = store.begin_read().await.unwrap();
+    let mut blob = Blob::open(&mut tx, branch, blob_id).await.unwrap();
+    assert_eq!(blob.read_to_end(&mut tx).await.unwrap(), content);
+
+    drop(tx);
+    store.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compressed_blocks() {
+    let (mut rng, _base_dir, store, [branch]) = setup(0).await;
+    branch.compression().set_enabled(true);
+
+    let blob_id: BlobId = rng.gen();
+    let mut blob = Blob::create(branch.clone(), blob_id);
+
+    // One compressible block followed by one incompressible one.
+    let mut content: Vec<_> = b"hello world "
+        .iter()
+        .copied()
+        .cycle()
+        .take(BLOCK_SIZE - HEADER_SIZE)
+        .collect();
+    content.extend(random_bytes(&mut rng, BLOCK_SIZE));
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+    blob.write_all(&mut tx, &mut changeset, &content)
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let block_ids: Vec<_> = BlockIds::open(branch.clone(), blob_id)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(block_ids.len(), 2);
+
+    let mut tx = store.begin_read().await.unwrap();
+
+    let mut stored = BlockContent::new();
+    tx.read_block(&block_ids[0], &mut stored).await.unwrap();
+    assert!(stored.len() < BLOCK_SIZE);
+    // The exact compressed length is hidden by padding.
+    assert_eq!(stored.len() % compression::BUCKET_SIZE, 0);
+
+    let mut stored = BlockContent::new();
+    tx.read_block(&block_ids[1], &mut stored).await.unwrap();
+    assert_eq!(stored.len(), BLOCK_SIZE);
+
+    // Blocks written with compression disabled can be read alongside the compressed ones.
+    branch.compression().set_enabled(false);
+
+    let mut blob = Blob::open(&mut tx, branch.clone(), blob_id).await.unwrap();
+    assert_eq!(blob.read_to_end(&mut tx).await.unwrap(), content);
+    drop(tx);
+
+    let extra = random_bytes(&mut rng, 64);
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+    blob.write_all(&mut tx, &mut changeset, &extra)
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    content.extend(extra);
+
+    let mut tx = store.begin_read().await.unwrap();
+    let mut blob = Blob::open(&mut tx, branch, blob_id).await.unwrap();
+    assert_eq!(blob.read_to_end(&mut tx).await.unwrap(), content);
+
+    drop(tx);
+    store.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn limited_buffer_pool() {
+    let (mut rng, _base_dir, store, [branch]) = setup(0).await;
+    branch.buffer_pool().set_capacity(2);
+
+    let id = rng.gen();
+    let content = random_bytes(&mut rng, 4 * BLOCK_SIZE);
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+
+    // Writing more blocks than there are buffers forces intermediate flushes.
+    let mut blob = Blob::create(branch.clone(), id);
+    blob.write_all(&mut tx, &mut changeset, &content)
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    assert!(branch.buffer_pool().in_use() <= 2);
+
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+    drop(blob);
+
+    // Reading recycles the buffers of the already read blocks.
+    let mut tx = store.begin_read().await.unwrap();
+    let mut blob = Blob::open(&mut tx, branch.clone(), id).await.unwrap();
+    assert_eq!(blob.read_to_end(&mut tx).await.unwrap(), content);
+    assert_eq!(branch.buffer_pool().in_use(), 2);
+    drop(tx);
+
+    // Opening another blob inside a transaction doesn't wait for a buffer but temporarily exceeds
+    // the pool.
+    branch.buffer_pool().set_capacity(1);
+    drop(blob);
+
+    let blob_0 = {
+        let mut tx = store.begin_read().await.unwrap();
+        Blob::open(&mut tx, branch.clone(), id).await.unwrap()
+    };
+
+    let mut tx = store.begin_read().await.unwrap();
+    let blob_1 = time::timeout(
+        Duration::from_millis(100),
+        Blob::open(&mut tx, branch.clone(), id),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    drop(tx);
+    assert_eq!(branch.buffer_pool().in_use(), 2);
+
+    // Waiting outside of a transaction does wait until a buffer is released.
+    let mut blob_2 = blob_1;
+    assert!(
+        time::timeout(Duration::from_millis(100), blob_2.wait_for_buffer())
+            .await
+            .is_err()
+    );
+
+    // Releasing `blob_0` only brings the pool back to its capacity.
+    drop(blob_0);
+    branch.buffer_pool().set_capacity(2);
+    blob_2.wait_for_buffer().await;
+    assert_eq!(branch.buffer_pool().in_use(), 2);
+    drop(blob_2);
+    assert_eq!(branch.buffer_pool().in_use(), 0);
+
+    store.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn size_limit() {
+    let (mut rng, _base_dir, store, [branch]) = setup(0).await;
+    branch.max_file_size().set(Some(10));
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+
+    // Blobs are not limited unless asked to (e.g., directories).
+    let mut blob = Blob::create(branch.clone(), rng.gen());
+    blob.write_all(&mut tx, &mut changeset, &[0; 11])
+        .await
+        .unwrap();
+
+    let mut blob = Blob::create(branch.clone(), rng.gen()).limit_size();
+    assert_eq!(blob.write(b"hello world").unwrap(), 10);
+    assert_matches!(blob.write(b"!"), Err(ReadWriteError::TooLarge));
+    assert_matches!(blob.grow(11), Err(ReadWriteError::TooLarge));
+    assert_matches!(
+        blob.write_all(&mut tx, &mut changeset, b"!").await,
+        Err(Error::FileTooLarge)
+    );
+    assert_eq!(blob.len(), 10);
+
+    // A blob that is already larger than the limit can be rewritten and shrunk but not grown.
+    branch.max_file_size().set(Some(5));
+
+    blob.seek(SeekFrom::Start(0));
+    assert_eq!(blob.write(b"HELLO WORLD").unwrap(), 10);
+    assert_matches!(blob.write(b"!"), Err(ReadWriteError::TooLarge));
+
+    blob.truncate(8).unwrap();
+    assert_matches!(blob.grow(9), Err(ReadWriteError::TooLarge));
+    assert_eq!(blob.len(), 8);
+
+    drop(tx);
+    store.close().await.unwrap();
+}
+
 async fn setup<const N: usize>(rng_seed: u64) -> (StdRng, TempDir, Store, [Branch; N]) {
     let mut rng = StdRng::seed_from_u64(rng_seed);
     let keys: AccessKeys = WriteSecrets::generate(&mut rng).into();
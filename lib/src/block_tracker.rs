@@ -3,8 +3,11 @@ use crate::{
     protocol::BlockId,
 };
 use deadlock::BlockingMutex;
-use std::{collections::hash_map::Entry, sync::Arc};
-use tokio::sync::watch;
+use std::{collections::hash_map::Entry, sync::Arc, time::Duration};
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
+};
 
 /// Helper for tracking required missing blocks.
 #[derive(Clone)]
@@ -15,22 +18,27 @@ pub(crate) struct BlockTracker {
 impl BlockTracker {
     pub fn new() -> Self {
         let (notify_tx, _) = watch::channel(());
+        let (required_tx, _) = broadcast::channel(REQUIRED_CHANNEL_CAPACITY);
 
         Self {
             shared: Arc::new(Shared {
                 inner: BlockingMutex::new(Inner {
                     missing_blocks: HashMap::default(),
                     clients: HashMap::default(),
+                    stats: HashMap::default(),
                     next_client_id: 0,
+                    deferred: false,
+                    high_priority_offers: HashMap::default(),
                 }),
                 notify_tx,
+                required_tx,
             }),
         }
     }
 
     /// Mark the block with the given id as required.
     pub fn require(&self, block_id: BlockId) {
-        if self.shared.inner.lock().unwrap().require(block_id) {
+        if self.shared.require(block_id, BlockPriority::Normal) {
             self.shared.notify()
         }
     }
@@ -38,6 +46,7 @@ impl BlockTracker {
     pub fn require_batch(&self) -> RequireBatch<'_> {
         RequireBatch {
             shared: &self.shared,
+            priority: BlockPriority::Normal,
             notify: false,
         }
     }
@@ -65,6 +74,12 @@ impl BlockTracker {
         }
     }
 
+    /// Subscribe to notifications about blocks becoming required. Each block id is sent only when
+    /// it becomes required, not when it's required again while still missing.
+    pub fn subscribe_required(&self) -> broadcast::Receiver<BlockId> {
+        self.shared.required_tx.subscribe()
+    }
+
     pub fn client(&self) -> TrackerClient {
         let client_id = self.shared.inner.lock().unwrap().insert_client();
         let notify_rx = self.shared.notify_tx.subscribe();
@@ -83,14 +98,30 @@ pub(crate) enum OfferState {
     Approved,
 }
 
+/// Order in which the required blocks are requested. Blocks of higher priority are requested
+/// before those of lower priority (when offered by the same peer), otherwise the order is
+/// unspecified.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub(crate) enum BlockPriority {
+    Normal,
+    High,
+}
+
 pub(crate) struct RequireBatch<'a> {
     shared: &'a Shared,
+    priority: BlockPriority,
     notify: bool,
 }
 
 impl RequireBatch<'_> {
+    /// Sets the priority of the blocks subsequently added to this batch. If a block is already
+    /// required with a lower priority, its priority is raised.
+    pub fn with_priority(self, priority: BlockPriority) -> Self {
+        Self { priority, ..self }
+    }
+
     pub fn add(&mut self, block_id: BlockId) {
-        if self.shared.inner.lock().unwrap().require(block_id) {
+        if self.shared.require(block_id, self.priority) {
             self.notify = true;
         }
     }
@@ -146,12 +177,15 @@ impl TrackerClient {
                     required: false,
                     approved: false,
                 },
+                priority: BlockPriority::Normal,
             });
 
         missing_block
             .offers
             .insert(self.client_id, Offer::Available);
 
+        let priority = missing_block.priority;
+
         match &mut missing_block.state {
             State::Idle { approved, .. } => {
                 match state {
@@ -168,8 +202,24 @@ impl TrackerClient {
             State::Accepted(_) => (),
         }
 
+        if priority == BlockPriority::High {
+            inner.index_high_priority(self.client_id, block_id);
+        }
+
         true
     }
+
+    /// Current score of the peer this client belongs to.
+    pub fn score(&self) -> PeerScore {
+        self.shared
+            .inner
+            .lock()
+            .unwrap()
+            .stats
+            .get(&self.client_id)
+            .map(|stats| stats.score)
+            .unwrap_or_default()
+    }
 }
 
 impl Drop for TrackerClient {
@@ -208,12 +258,18 @@ impl BlockOffers {
 
     /// Returns the next offer or `None` if none exists currently.
     pub fn try_next(&self) -> Option<BlockOffer> {
-        let block_id = self
+        let (block_id, notify) = self
             .shared
             .inner
             .lock()
             .unwrap()
-            .propose_offer(self.client_id)?;
+            .propose_offer(self.client_id);
+
+        if notify {
+            self.shared.notify();
+        }
+
+        let block_id = block_id?;
 
         Some(BlockOffer {
             shared: self.shared.clone(),
@@ -233,7 +289,6 @@ pub(crate) struct BlockOffer {
 }
 
 impl BlockOffer {
-    #[cfg(test)]
     pub fn block_id(&self) -> &BlockId {
         &self.block_id
     }
@@ -242,13 +297,18 @@ impl BlockOffer {
     /// peer) but only one returns `Some` here. The returned `BlockPromise` is a commitment to send
     /// the block request through this client.
     pub fn accept(self) -> Option<BlockPromise> {
-        if self
+        let (accepted, notify) = self
             .shared
             .inner
             .lock()
             .unwrap()
-            .accept_offer(&self.block_id, self.client_id)
-        {
+            .accept_offer(&self.block_id, self.client_id);
+
+        if notify {
+            self.shared.notify();
+        }
+
+        if accepted {
             Some(BlockPromise(self))
         } else {
             None
@@ -282,6 +342,38 @@ impl BlockPromise {
         &self.0.block_id
     }
 
+    /// Records how long the request for this block took into the score of the peer it was
+    /// requested from.
+    pub(crate) fn record_latency(&self, latency: Duration) {
+        if self
+            .0
+            .shared
+            .inner
+            .lock()
+            .unwrap()
+            .record_latency(self.0.client_id, latency)
+        {
+            self.0.shared.notify();
+        }
+    }
+
+    /// Records that the request for this block timed out after `latency`. Besides counting into
+    /// the latency score, peers whose requests time out repeatedly (e.g. because they offer
+    /// blocks they don't actually have) are considered unreliable for a while: their offers are
+    /// then only accepted for blocks that no reliable peer offers.
+    pub(crate) fn record_timeout(&self, latency: Duration) {
+        if self
+            .0
+            .shared
+            .inner
+            .lock()
+            .unwrap()
+            .record_timeout(self.0.client_id, latency)
+        {
+            self.0.shared.notify();
+        }
+    }
+
     /// Mark the block request as successfully completed.
     pub fn complete(mut self) {
         self.0.complete = true;
@@ -297,12 +389,24 @@ impl BlockPromise {
 struct Shared {
     inner: BlockingMutex<Inner>,
     notify_tx: watch::Sender<()>,
+    required_tx: broadcast::Sender<BlockId>,
 }
 
 impl Shared {
     fn notify(&self) {
         self.notify_tx.send(()).unwrap_or(())
     }
+
+    // Returns whether the acceptors need to be notified (see `Inner::require`).
+    fn require(&self, block_id: BlockId, priority: BlockPriority) -> bool {
+        let (newly_required, notify) = self.inner.lock().unwrap().require(block_id, priority);
+
+        if newly_required {
+            self.required_tx.send(block_id).unwrap_or(0);
+        }
+
+        notify
+    }
 }
 
 // Invariant: for all `block_id` and `client_id` such that
@@ -313,11 +417,23 @@ impl Shared {
 //
 //     clients[client_id].contains(block_id)
 //
-// and vice-versa.
+// and vice-versa. Also `stats[client_id].claimed` is the number of offers of `client_id` which
+// are either proposed or accepted. Finally, `high_priority_offers[client_id]` contains exactly
+// those `block_id` from `clients[client_id]` for which
+//
+//     missing_blocks[block_id].priority == BlockPriority::High
 struct Inner {
     missing_blocks: HashMap<BlockId, MissingBlock>,
     clients: HashMap<ClientId, HashSet<BlockId>>,
+    stats: HashMap<ClientId, ClientStats>,
     next_client_id: ClientId,
+    // Whether some offer hasn't been proposed to a client because a faster client was expected
+    // to fetch the block sooner. When the situation changes (the faster client takes more blocks
+    // or becomes slower), the clients need to be notified so they can reconsider.
+    deferred: bool,
+    // Subset of `clients` with only the blocks of `BlockPriority::High`, so they can be proposed
+    // first without scanning all the offers of the client.
+    high_priority_offers: HashMap<ClientId, HashSet<BlockId>>,
 }
 
 impl Inner {
@@ -328,12 +444,19 @@ impl Inner {
             .checked_add(1)
             .expect("too many clients");
         self.clients.insert(client_id, HashSet::new());
+        self.stats.insert(client_id, ClientStats::default());
         client_id
     }
 
+    /// Returns whether the clients need to be notified, either because some of the blocks accepted
+    /// by the removed client can now be accepted by others or because some previously deferred
+    /// offer might now be proposable (see `propose_offer`).
     fn remove_client(&mut self, client_id: ClientId) -> bool {
         // unwrap is ok because if `self` exists the `clients` entry must exists as well.
         let block_ids = self.clients.remove(&client_id).unwrap();
+        self.stats.remove(&client_id);
+        self.high_priority_offers.remove(&client_id);
+
         let mut notify = false;
 
         for block_id in block_ids {
@@ -349,12 +472,15 @@ impl Inner {
             // TODO: if the block hasn't other offers and isn't required, remove it
         }
 
-        notify
+        // The removed client might have been the faster one some offers were deferred to.
+        let deferred = self.take_deferred();
+
+        notify || deferred
     }
 
-    /// Mark the block with the given id as required. Returns true if the block wasn't already
-    /// required and if it has at least one offer. Otherwise returns false.
-    fn require(&mut self, block_id: BlockId) -> bool {
+    /// Mark the block with the given id as required. Returns a pair of bools: the first is true if
+    /// the block wasn't already required, the second if additionally it has at least one offer.
+    fn require(&mut self, block_id: BlockId, priority: BlockPriority) -> (bool, bool) {
         let missing_block = self
             .missing_blocks
             .entry(block_id)
@@ -364,13 +490,25 @@ impl Inner {
                     required: false,
                     approved: false,
                 },
+                priority: BlockPriority::Normal,
             });
 
+        if priority > missing_block.priority {
+            missing_block.priority = priority;
+
+            for client_id in missing_block.offers.keys() {
+                self.high_priority_offers
+                    .entry(*client_id)
+                    .or_default()
+                    .insert(block_id);
+            }
+        }
+
         match &mut missing_block.state {
-            State::Idle { required: true, .. } | State::Accepted(_) => false,
+            State::Idle { required: true, .. } | State::Accepted(_) => (false, false),
             State::Idle { required, .. } => {
                 *required = true;
-                !missing_block.offers.is_empty()
+                (true, !missing_block.offers.is_empty())
             }
         }
     }
@@ -380,18 +518,69 @@ impl Inner {
             return;
         };
 
-        for (client_id, _) in missing_block.offers {
+        // The block was delivered so the client that requested it is reliable again.
+        if let State::Accepted(client_id) = missing_block.state {
+            if let Some(stats) = self.stats.get_mut(&client_id) {
+                stats.timeouts = 0;
+                stats.unreliable_until = None;
+            }
+        }
+
+        for (client_id, offer) in missing_block.offers {
             if let Some(block_ids) = self.clients.get_mut(&client_id) {
                 block_ids.remove(block_id);
             }
+
+            self.unindex_high_priority(client_id, *block_id);
+
+            match offer {
+                Offer::Proposed | Offer::Accepted => self.unclaim(client_id),
+                Offer::Available => (),
+            }
         }
     }
 
-    fn propose_offer(&mut self, client_id: ClientId) -> Option<BlockId> {
+    /// Finds a block to propose to the given client, preferring the ones with higher priority.
+    /// Returns the block id (if any) and whether the clients need to be notified because some
+    /// previously deferred offer might now be proposable.
+    fn propose_offer(&mut self, client_id: ClientId) -> (Option<BlockId>, bool) {
+        let now = Instant::now();
+
+        let Some(block_id) = self
+            .find_offer(client_id, BlockPriority::High, now)
+            .or_else(|| self.find_offer(client_id, BlockPriority::Normal, now))
+        else {
+            return (None, false);
+        };
+
+        // unwrap is ok because the block was found above.
+        self.missing_blocks
+            .get_mut(&block_id)
+            .unwrap()
+            .offers
+            .insert(client_id, Offer::Proposed);
+
+        self.claim(client_id);
+        (Some(block_id), self.take_deferred())
+    }
+
+    // Finds an offer of the given client that can be proposed to it, looking only at the blocks of
+    // at least the given priority.
+    fn find_offer(
+        &mut self,
+        client_id: ClientId,
+        min_priority: BlockPriority,
+        now: Instant,
+    ) -> Option<BlockId> {
+        let block_ids = match min_priority {
+            BlockPriority::High => &self.high_priority_offers,
+            BlockPriority::Normal => &self.clients,
+        };
+
         // TODO: OPTIMIZE (but profile first) this linear lookup
-        for block_id in self.clients.get(&client_id).into_iter().flatten() {
+        for block_id in block_ids.get(&client_id).into_iter().flatten() {
             // unwrap is ok because of the invariant in `Inner`
-            let missing_block = self.missing_blocks.get_mut(block_id).unwrap();
+            let missing_block = self.missing_blocks.get(block_id).unwrap();
 
             match missing_block.state {
                 State::Idle {
@@ -402,23 +591,29 @@ impl Inner {
             }
 
             // unwrap is ok because of the invariant.
-            let offer = missing_block.offers.get_mut(&client_id).unwrap();
-            match offer {
-                Offer::Available => {
-                    *offer = Offer::Proposed;
-                }
+            match missing_block.offers.get(&client_id).unwrap() {
+                Offer::Available => (),
                 Offer::Proposed | Offer::Accepted => continue,
             }
 
+            if is_outpaced(&self.stats, client_id, &missing_block.offers)
+                || is_superseded(&self.stats, client_id, &missing_block.offers, now)
+            {
+                self.deferred = true;
+                continue;
+            }
+
             return Some(*block_id);
         }
 
         None
     }
 
-    fn accept_offer(&mut self, block_id: &BlockId, client_id: ClientId) -> bool {
+    /// Returns whether the offer was accepted and whether the clients need to be notified (see
+    /// `propose_offer`).
+    fn accept_offer(&mut self, block_id: &BlockId, client_id: ClientId) -> (bool, bool) {
         let Some(missing_block) = self.missing_blocks.get_mut(block_id) else {
-            return false;
+            return (false, false);
         };
 
         match missing_block.state {
@@ -426,13 +621,89 @@ impl Inner {
                 required: true,
                 approved: true,
             } => (),
-            State::Idle { .. } | State::Accepted(_) => return false,
+            State::Idle { .. } | State::Accepted(_) => return (false, false),
         }
 
         missing_block.state = State::Accepted(client_id);
-        missing_block.offers.insert(client_id, Offer::Accepted);
 
-        true
+        match missing_block.offers.insert(client_id, Offer::Accepted) {
+            Some(Offer::Proposed | Offer::Accepted) => (),
+            Some(Offer::Available) | None => self.claim(client_id),
+        }
+
+        (true, self.take_deferred())
+    }
+
+    /// Updates the score of the given client. Returns whether the clients need to be notified
+    /// (see `propose_offer`).
+    fn record_latency(&mut self, client_id: ClientId, latency: Duration) -> bool {
+        let Some(stats) = self.stats.get_mut(&client_id) else {
+            return false;
+        };
+
+        stats.score.record(latency);
+
+        self.take_deferred()
+    }
+
+    /// Updates the score and the reliability of the given client after its request timed out.
+    /// Returns whether the clients need to be notified (see `propose_offer`).
+    fn record_timeout(&mut self, client_id: ClientId, latency: Duration) -> bool {
+        let Some(stats) = self.stats.get_mut(&client_id) else {
+            return false;
+        };
+
+        stats.score.record(latency);
+        stats.timeouts = stats.timeouts.saturating_add(1);
+
+        if let Some(excess) = stats.timeouts.checked_sub(UNRELIABLE_TIMEOUTS) {
+            // Each further timeout doubles the penalty.
+            let penalty =
+                UNRELIABLE_PENALTY.saturating_mul(1 << excess.min(UNRELIABLE_PENALTY_MAX_SHIFT));
+            stats.unreliable_until = Some(Instant::now() + penalty);
+
+            tracing::debug!(
+                client_id,
+                timeouts = stats.timeouts,
+                ?penalty,
+                "Peer considered unreliable"
+            );
+        }
+
+        self.take_deferred()
+    }
+
+    fn claim(&mut self, client_id: ClientId) {
+        if let Some(stats) = self.stats.get_mut(&client_id) {
+            stats.claimed += 1;
+        }
+    }
+
+    fn unclaim(&mut self, client_id: ClientId) {
+        if let Some(stats) = self.stats.get_mut(&client_id) {
+            stats.claimed = stats.claimed.saturating_sub(1);
+        }
+    }
+
+    fn index_high_priority(&mut self, client_id: ClientId, block_id: BlockId) {
+        self.high_priority_offers
+            .entry(client_id)
+            .or_default()
+            .insert(block_id);
+    }
+
+    fn unindex_high_priority(&mut self, client_id: ClientId, block_id: BlockId) {
+        if let Entry::Occupied(mut entry) = self.high_priority_offers.entry(client_id) {
+            entry.get_mut().remove(&block_id);
+
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
+
+    fn take_deferred(&mut self) -> bool {
+        std::mem::take(&mut self.deferred)
     }
 
     fn cancel_offer(&mut self, block_id: &BlockId, client_id: ClientId) -> bool {
@@ -457,19 +728,121 @@ impl Inner {
                 // `missing_block.offers[&self.client_id]` would not exists and this function would
                 // have exited earlier.
                 self.clients.get_mut(&client_id).unwrap().remove(block_id);
+                self.unindex_high_priority(client_id, *block_id);
             }
             Offer::Available => unreachable!(),
         }
 
-        missing_block.unaccept_by(client_id)
+        let notify = missing_block.unaccept_by(client_id);
+        self.unclaim(client_id);
+
+        notify
+    }
+}
+
+/// Is there another client offering the block (and not yet committed to requesting it) which is
+/// expected to deliver it sooner than the given client? The expected delivery time of a client is
+/// estimated as its recent latency multiplied by the number of blocks it would need to get through
+/// first. Clients without any latency samples yet are never outpaced and never outpace others, so
+/// that every peer gets a chance to be scored.
+fn is_outpaced(
+    stats: &HashMap<ClientId, ClientStats>,
+    client_id: ClientId,
+    offers: &HashMap<ClientId, Offer>,
+) -> bool {
+    let Some(latency) = stats
+        .get(&client_id)
+        .and_then(|stats| stats.score.latency())
+    else {
+        return false;
+    };
+
+    offers
+        .iter()
+        .filter(|(other_id, _)| **other_id != client_id)
+        .filter_map(|(other_id, _)| stats.get(other_id))
+        .any(|other| {
+            let Some(other_latency) = other.score.latency() else {
+                return false;
+            };
+
+            let backlog = u32::try_from(other.claimed + 1).unwrap_or(u32::MAX);
+
+            other_latency.saturating_mul(backlog) < latency
+        })
+}
+
+/// Is the given client currently considered unreliable (see `BlockPromise::record_timeout`) while
+/// another client offering the block isn't?
+fn is_superseded(
+    stats: &HashMap<ClientId, ClientStats>,
+    client_id: ClientId,
+    offers: &HashMap<ClientId, Offer>,
+    now: Instant,
+) -> bool {
+    let is_unreliable = |client_id| {
+        stats
+            .get(client_id)
+            .and_then(|stats| stats.unreliable_until)
+            .is_some_and(|until| until > now)
+    };
+
+    is_unreliable(&client_id)
+        && offers
+            .keys()
+            .any(|other_id| *other_id != client_id && !is_unreliable(other_id))
+}
+
+/// Recent performance of a peer as a source of blocks. Used to prefer faster peers when more than
+/// one of them offers the same block while still falling back to the slower ones when the faster
+/// ones are busy.
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct PeerScore {
+    // Exponential moving average of the block request latencies.
+    latency: Option<Duration>,
+}
+
+impl PeerScore {
+    /// Average recent block request latency or `None` if no block has been requested yet.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    fn record(&mut self, sample: Duration) {
+        self.latency = Some(match self.latency {
+            Some(latency) => (latency * (LATENCY_WEIGHT - 1) + sample) / LATENCY_WEIGHT,
+            None => sample,
+        });
     }
 }
 
+// How much the latency average is smoothed: each new sample contributes `1 / LATENCY_WEIGHT` of it.
+const LATENCY_WEIGHT: u32 = 4;
+
+// Number of consecutive request timeouts after which a peer is considered unreliable.
+const UNRELIABLE_TIMEOUTS: u32 = 3;
+// For how long a peer is considered unreliable after reaching `UNRELIABLE_TIMEOUTS`. Doubled with
+// each further timeout, up to `2^UNRELIABLE_PENALTY_MAX_SHIFT` times.
+const UNRELIABLE_PENALTY: Duration = Duration::from_secs(30);
+const UNRELIABLE_PENALTY_MAX_SHIFT: u32 = 5;
+
+#[derive(Default)]
+struct ClientStats {
+    score: PeerScore,
+    // Number of offers which are currently proposed to or accepted by the client.
+    claimed: usize,
+    // Number of consecutive timed out requests.
+    timeouts: u32,
+    // Until when the offers of this client are ignored in favor of other clients.
+    unreliable_until: Option<Instant>,
+}
+
 #[derive(Debug)]
 struct MissingBlock {
     // Clients that offered this block.
     offers: HashMap<ClientId, Offer>,
     state: State,
+    priority: BlockPriority,
 }
 
 impl MissingBlock {
@@ -502,6 +875,9 @@ enum Offer {
 
 type ClientId = usize;
 
+// Capacity of the channel of newly required blocks. Slow subscribers may miss some.
+const REQUIRED_CHANNEL_CAPACITY: usize = 1024;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -547,6 +923,34 @@ mod tests {
         assert!(client.offers().try_next().is_none());
     }
 
+    #[test]
+    fn subscribe_required() {
+        let tracker = BlockTracker::new();
+        let mut rx = tracker.subscribe_required();
+
+        let client = tracker.client();
+        let block: Block = rand::random();
+
+        tracker.require(block.id);
+        assert_eq!(rx.try_recv().ok(), Some(block.id));
+
+        // Requiring an already required block doesn't notify again.
+        tracker.require(block.id);
+        assert!(rx.try_recv().is_err());
+
+        client.register(block.id, OfferState::Approved);
+        client
+            .offers()
+            .try_next()
+            .and_then(BlockOffer::accept)
+            .unwrap()
+            .complete();
+
+        // After completion the block can become required again.
+        tracker.require(block.id);
+        assert_eq!(rx.try_recv().ok(), Some(block.id));
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn simple_async() {
         let tracker = BlockTracker::new();
@@ -731,6 +1135,154 @@ mod tests {
         );
     }
 
+    #[test]
+    fn prefer_high_priority() {
+        let tracker = BlockTracker::new();
+        let client = tracker.client();
+
+        let blocks: Vec<Block> = (0..8).map(|_| rand::random()).collect();
+        let (high, normal) = blocks.split_at(2);
+
+        for block in normal {
+            tracker.require(block.id);
+        }
+
+        let mut batch = tracker.require_batch().with_priority(BlockPriority::High);
+        for block in high {
+            batch.add(block.id);
+        }
+        drop(batch);
+
+        for block in &blocks {
+            client.register(block.id, OfferState::Approved);
+        }
+
+        let offers = client.offers();
+        let mut promises = Vec::new();
+
+        for _ in 0..high.len() {
+            promises.push(offers.try_next().and_then(BlockOffer::accept).unwrap());
+        }
+
+        let mut actual: Vec<_> = promises.iter().map(|promise| *promise.block_id()).collect();
+        let mut expected: Vec<_> = high.iter().map(|block| block.id).collect();
+        actual.sort();
+        expected.sort();
+        assert_eq!(actual, expected);
+
+        // Raising the priority of an already required block.
+        let mut batch = tracker.require_batch().with_priority(BlockPriority::High);
+        batch.add(normal[3].id);
+        drop(batch);
+
+        assert_eq!(
+            offers
+                .try_next()
+                .and_then(BlockOffer::accept)
+                .as_ref()
+                .map(BlockPromise::block_id),
+            Some(&normal[3].id)
+        );
+    }
+
+    #[test]
+    fn high_priority_scheduling() {
+        let tracker = BlockTracker::new();
+        let client = tracker.client();
+        let offers = client.offers();
+
+        let normal: Vec<Block> = (0..4).map(|_| rand::random()).collect();
+        let high: Vec<Block> = (0..2).map(|_| rand::random()).collect();
+
+        for block in &normal {
+            tracker.require(block.id);
+            client.register(block.id, OfferState::Approved);
+        }
+
+        let mut batch = tracker.require_batch().with_priority(BlockPriority::High);
+        for block in &high {
+            batch.add(block.id);
+        }
+        drop(batch);
+
+        for block in &high {
+            client.register(block.id, OfferState::Approved);
+        }
+
+        // Keep the promises so the accepted blocks are not offered again.
+        let mut promises = Vec::new();
+        let mut accept = || {
+            let promise = offers.try_next().and_then(BlockOffer::accept).unwrap();
+            let block_id = *promise.block_id();
+            promises.push(promise);
+            block_id
+        };
+
+        // All the high priority blocks go first, regardless of the order they were offered in.
+        let mut promised = vec![accept(), accept()];
+        promised.sort();
+        let mut expected: Vec<_> = high.iter().map(|block| block.id).collect();
+        expected.sort();
+        assert_eq!(promised, expected);
+
+        // High priority block offered while the normal ones are still pending also jumps the
+        // queue.
+        let late: Block = rand::random();
+        let mut batch = tracker.require_batch().with_priority(BlockPriority::High);
+        batch.add(late.id);
+        drop(batch);
+        client.register(late.id, OfferState::Approved);
+
+        assert_eq!(accept(), late.id);
+
+        // Then the normal ones.
+        let mut promised: Vec<_> = (0..normal.len()).map(|_| accept()).collect();
+        promised.sort();
+        let mut expected: Vec<_> = normal.iter().map(|block| block.id).collect();
+        expected.sort();
+        assert_eq!(promised, expected);
+
+        assert!(offers.try_next().is_none());
+    }
+
+    #[test]
+    fn high_priority_of_other_client_does_not_hold_back_normal() {
+        let tracker = BlockTracker::new();
+        let client0 = tracker.client();
+        let client1 = tracker.client();
+
+        let high: Block = rand::random();
+        let normal: Block = rand::random();
+
+        let mut batch = tracker.require_batch().with_priority(BlockPriority::High);
+        batch.add(high.id);
+        drop(batch);
+        tracker.require(normal.id);
+
+        client0.register(high.id, OfferState::Approved);
+        client1.register(normal.id, OfferState::Approved);
+
+        let offers0 = client0.offers();
+        let offers1 = client1.offers();
+
+        assert_eq!(
+            offers1
+                .try_next()
+                .and_then(BlockOffer::accept)
+                .as_ref()
+                .map(BlockPromise::block_id),
+            Some(&normal.id)
+        );
+        assert_eq!(
+            offers0
+                .try_next()
+                .and_then(BlockOffer::accept)
+                .as_ref()
+                .map(BlockPromise::block_id),
+            Some(&high.id)
+        );
+    }
+
     #[test]
     fn multiple_offers_from_different_clients() {
         let tracker = BlockTracker::new();
@@ -776,6 +1328,196 @@ mod tests {
         assert!(offer2.is_none());
     }
 
+    #[test]
+    fn prefer_faster_peer() {
+        let tracker = BlockTracker::new();
+        let fast = tracker.client();
+        let slow = tracker.client();
+
+        score(&tracker, &fast, Duration::from_millis(10));
+        score(&tracker, &slow, Duration::from_millis(100));
+
+        let block_ids: Vec<BlockId> = (0..20).map(|_| rand::random()).collect();
+
+        for block_id in &block_ids {
+            fast.register(*block_id, OfferState::Approved);
+            slow.register(*block_id, OfferState::Approved);
+            tracker.require(*block_id);
+        }
+
+        // Simulate both peers serving one request at a time, each at its own speed. The slow peer
+        // always gets the first pick.
+        let clients = [(&slow, 100), (&fast, 10)];
+        let mut inflight: [Option<(BlockPromise, u64)>; 2] = [None, None];
+        let mut counts = [0; 2];
+
+        for now in 0.. {
+            if counts.iter().sum::<usize>() == block_ids.len() {
+                break;
+            }
+
+            for (index, (client, latency)) in clients.iter().enumerate() {
+                if let Some((promise, done_at)) = inflight[index].take() {
+                    if done_at > now {
+                        inflight[index] = Some((promise, done_at));
+                        continue;
+                    }
+
+                    promise.record_latency(Duration::from_millis(*latency));
+                    promise.complete();
+                    counts[index] += 1;
+                }
+
+                inflight[index] = client
+                    .offers()
+                    .try_next()
+                    .and_then(BlockOffer::accept)
+                    .map(|promise| (promise, now + latency));
+            }
+        }
+
+        let [slow_count, fast_count] = counts;
+        assert!(
+            fast_count > slow_count,
+            "fast: {fast_count}, slow: {slow_count}"
+        );
+    }
+
+    #[test]
+    fn fallback_to_slower_peer_when_faster_is_busy() {
+        let tracker = BlockTracker::new();
+        let fast = tracker.client();
+        let slow = tracker.client();
+
+        score(&tracker, &fast, Duration::from_millis(10));
+        score(&tracker, &slow, Duration::from_millis(100));
+        assert_eq!(fast.score().latency(), Some(Duration::from_millis(10)));
+        assert_eq!(slow.score().latency(), Some(Duration::from_millis(100)));
+
+        let block_ids: Vec<BlockId> = (0..20).map(|_| rand::random()).collect();
+
+        for block_id in &block_ids {
+            fast.register(*block_id, OfferState::Approved);
+            slow.register(*block_id, OfferState::Approved);
+            tracker.require(*block_id);
+        }
+
+        // The fast peer is idle so it's expected to deliver any block sooner.
+        assert!(slow.offers().try_next().is_none());
+
+        // Keep the fast peer busy until fetching from it would take longer than from the slow one.
+        let mut offers = Vec::new();
+
+        while slow.offers().try_next().is_none() {
+            offers.push(fast.offers().try_next().unwrap());
+        }
+
+        assert_eq!(offers.len(), 9);
+    }
+
+    #[tokio::test]
+    async fn notify_when_faster_peer_removed() {
+        let tracker = BlockTracker::new();
+        let fast = tracker.client();
+        let slow = tracker.client();
+
+        score(&tracker, &fast, Duration::from_millis(10));
+        score(&tracker, &slow, Duration::from_millis(100));
+
+        let block_id: BlockId = rand::random();
+        fast.register(block_id, OfferState::Approved);
+        slow.register(block_id, OfferState::Approved);
+        tracker.require(block_id);
+
+        // Deferred to the fast peer which is expected to deliver the block sooner.
+        let mut offers = slow.offers();
+        assert!(offers.try_next().is_none());
+
+        // Removing the fast peer must wake up the slow one.
+        drop(fast);
+
+        let offer = time::timeout(Duration::from_secs(5), offers.next())
+            .await
+            .unwrap();
+        assert_eq!(*offer.block_id(), block_id);
+    }
+
+    #[test]
+    fn ignore_unreliable_peer() {
+        let tracker = BlockTracker::new();
+        let liar = tracker.client();
+        let honest = tracker.client();
+
+        // The lying peer offers blocks it doesn't have so all the requests to it time out.
+        for _ in 0..UNRELIABLE_TIMEOUTS {
+            let block_id: BlockId = rand::random();
+            liar.register(block_id, OfferState::Approved);
+            tracker.require(block_id);
+
+            let promise = liar
+                .offers()
+                .try_next()
+                .and_then(BlockOffer::accept)
+                .unwrap();
+            promise.record_timeout(Duration::from_secs(30));
+        }
+
+        // Now its offers are ignored when an honest peer offers the same block...
+        let block_id: BlockId = rand::random();
+        liar.register(block_id, OfferState::Approved);
+        honest.register(block_id, OfferState::Approved);
+        tracker.require(block_id);
+
+        assert!(liar.offers().try_next().is_none());
+
+        let promise = honest
+            .offers()
+            .try_next()
+            .and_then(BlockOffer::accept)
+            .unwrap();
+        assert_eq!(*promise.block_id(), block_id);
+        promise.complete();
+
+        // ...but not when it's the only one offering it.
+        let block_id: BlockId = rand::random();
+        liar.register(block_id, OfferState::Approved);
+        tracker.require(block_id);
+
+        let promise = liar
+            .offers()
+            .try_next()
+            .and_then(BlockOffer::accept)
+            .unwrap();
+        assert_eq!(*promise.block_id(), block_id);
+
+        // Delivering a block makes it reliable again.
+        promise.complete();
+
+        let block_id: BlockId = rand::random();
+        liar.register(block_id, OfferState::Approved);
+        honest.register(block_id, OfferState::Approved);
+        tracker.require(block_id);
+
+        assert_eq!(
+            liar.offers().try_next().map(|offer| *offer.block_id()),
+            Some(block_id)
+        );
+    }
+
+    fn score(tracker: &BlockTracker, client: &TrackerClient, latency: Duration) {
+        let block: Block = rand::random();
+        client.register(block.id, OfferState::Approved);
+        tracker.require(block.id);
+
+        let promise = client
+            .offers()
+            .try_next()
+            .and_then(BlockOffer::accept)
+            .unwrap();
+        promise.record_latency(latency);
+        promise.complete();
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn race() {
         let num_clients = 10;
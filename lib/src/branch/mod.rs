@@ -1,18 +1,27 @@
 use crate::{
     access_control::AccessKeys,
-    blob::lock::{BranchLocker, Locker},
+    blob::{
+        lock::{BranchLocker, Locker},
+        BufferPool, CompressionSetting,
+    },
     crypto::sign::PublicKey,
     debug::DebugPrinter,
-    directory::{Directory, DirectoryFallback, DirectoryLocking, EntryRef},
+    directory::{
+        Directory, DirectoryFallback, DirectoryLocking, DirectoryTree, EntryRef, NamePolicy,
+        PurgedTombstones,
+    },
     error::{Error, Result},
-    event::{EventScope, EventSender, Payload},
-    file::{File, FileProgressCache},
+    event::{Event, EventScope, EventSender, Payload},
+    file::{File, FileProgressCache, MaxFileSizeSetting},
+    joint_directory::TypeConflictSetting,
     path,
     protocol::{BlockId, Locator, Proof, RootNodeFilter},
     store::{self, Store},
     version_vector::VersionVector,
 };
 use camino::{Utf8Component, Utf8Path};
+use metrics::Gauge;
+use tokio::sync::broadcast;
 
 #[derive(Clone)]
 pub struct Branch {
@@ -132,6 +141,37 @@ impl Branch {
         Ok(curr)
     }
 
+    /// Ensures that the directories at all the specified paths exist including all their
+    /// ancestors. Unlike calling `ensure_directory_exists` for each path, all the missing
+    /// directories are created in a single transaction. The same restrictions on the paths apply.
+    pub(crate) async fn ensure_directories_exist<P>(&self, paths: &[P]) -> Result<()>
+    where
+        P: AsRef<Utf8Path>,
+    {
+        let mut tree = DirectoryTree::default();
+
+        for path in paths {
+            let mut names = Vec::new();
+
+            for component in path.as_ref().components() {
+                match component {
+                    Utf8Component::RootDir | Utf8Component::CurDir => (),
+                    Utf8Component::Normal(name) => names.push(name),
+                    Utf8Component::Prefix(_) | Utf8Component::ParentDir => {
+                        return Err(Error::OperationNotSupported)
+                    }
+                }
+            }
+
+            tree.insert(names);
+        }
+
+        self.open_or_create_root()
+            .await?
+            .create_directories(&tree)
+            .await
+    }
+
     pub(crate) async fn ensure_file_exists(&self, path: &Utf8Path) -> Result<File> {
         let (parent, name) = path::decompose(path).ok_or(Error::EntryIsDirectory)?;
         self.ensure_directory_exists(parent)
@@ -157,6 +197,30 @@ impl Branch {
         &self.shared.file_progress_cache
     }
 
+    pub(crate) fn compression(&self) -> &CompressionSetting {
+        &self.shared.compression
+    }
+
+    pub(crate) fn buffer_pool(&self) -> &BufferPool {
+        &self.shared.buffer_pool
+    }
+
+    pub(crate) fn max_file_size(&self) -> &MaxFileSizeSetting {
+        &self.shared.max_file_size
+    }
+
+    pub(crate) fn name_policy(&self) -> &NamePolicy {
+        &self.shared.name_policy
+    }
+
+    pub(crate) fn type_conflict(&self) -> &TypeConflictSetting {
+        &self.shared.type_conflict
+    }
+
+    pub(crate) fn purged_tombstones(&self) -> &PurgedTombstones {
+        &self.shared.purged_tombstones
+    }
+
     pub(crate) fn notify(&self) -> BranchEventSender {
         BranchEventSender {
             event_tx: self.event_tx.clone(),
@@ -164,6 +228,20 @@ impl Branch {
         }
     }
 
+    /// Returns a read-only handle to another branch of the same repository.
+    pub(crate) fn sibling(&self, id: PublicKey) -> Self {
+        Self {
+            id,
+            keys: self.keys.clone().read_only(),
+            ..self.clone()
+        }
+    }
+
+    /// Subscribes to the events of the repository this branch belongs to.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.event_tx.subscribe()
+    }
+
     pub async fn debug_print(&self, print: DebugPrinter) {
         match self
             .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
@@ -213,6 +291,12 @@ impl Branch {
 pub(crate) struct BranchShared {
     pub locker: Locker,
     pub file_progress_cache: FileProgressCache,
+    pub compression: CompressionSetting,
+    pub max_file_size: MaxFileSizeSetting,
+    pub name_policy: NamePolicy,
+    pub type_conflict: TypeConflictSetting,
+    pub purged_tombstones: PurgedTombstones,
+    pub buffer_pool: BufferPool,
 }
 
 impl BranchShared {
@@ -220,6 +304,20 @@ impl BranchShared {
         Self {
             locker: Locker::new(),
             file_progress_cache: FileProgressCache::new(),
+            compression: CompressionSetting::new(),
+            max_file_size: MaxFileSizeSetting::new(),
+            name_policy: NamePolicy::new(),
+            type_conflict: TypeConflictSetting::new(),
+            purged_tombstones: PurgedTombstones::new(),
+            buffer_pool: BufferPool::new(Gauge::noop()),
+        }
+    }
+
+    /// Uses the given buffer pool instead of the default one (which doesn't report its usage).
+    pub fn with_buffer_pool(self, buffer_pool: BufferPool) -> Self {
+        Self {
+            buffer_pool,
+            ..self
         }
     }
 }
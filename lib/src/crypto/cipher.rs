@@ -1,7 +1,6 @@
 //! Encryption / Decryption utilities.
 
-use super::{hash::Digest, password::PasswordSalt};
-use argon2::Argon2;
+use super::{hash::Digest, kdf::KdfParams, password::PasswordSalt};
 use chacha20::{
     cipher::{KeyIvInit, StreamCipher},
     ChaCha20,
@@ -82,13 +81,23 @@ impl SecretKey {
         OsRng.gen()
     }
 
-    /// Derive a secret key from user's password and salt.
+    /// Derive a secret key from user's password and salt using the default key derivation params.
     pub fn derive_from_password(user_password: &str, salt: &PasswordSalt) -> Self {
+        Self::derive_from_password_with_params(user_password, salt, &KdfParams::default())
+    }
+
+    /// Derive a secret key from user's password and salt using the given key derivation params.
+    pub fn derive_from_password_with_params(
+        user_password: &str,
+        salt: &PasswordSalt,
+        params: &KdfParams,
+    ) -> Self {
         let mut result = Self::zero();
         // Note: we control the output and salt size. And the only other check that this function
         // does is whether the password isn't too long, but that would have to be more than
         // 0xffffffff so the `.expect` shouldn't be an issue.
-        Argon2::default()
+        params
+            .hasher()
             .hash_password_into(user_password.as_ref(), salt.as_ref(), result.as_mut())
             .expect("failed to hash password");
         result
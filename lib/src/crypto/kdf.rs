@@ -0,0 +1,271 @@
+//! Parameters of the password-based key derivation function.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Cost parameters of the Argon2 key derivation function used to derive secret keys from
+/// passwords. Higher costs make brute-forcing the password harder but also make unlocking the
+/// repository slower.
+///
+/// The parameters are stored in the repository when it's created so the same ones are used when it
+/// is unlocked, possibly on a different device.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawKdfParams")]
+pub struct KdfParams {
+    memory_cost: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+// Unvalidated `KdfParams`, used to validate them on deserialization.
+#[derive(Deserialize)]
+struct RawKdfParams {
+    memory_cost: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl TryFrom<RawKdfParams> for KdfParams {
+    type Error = InvalidKdfParams;
+
+    fn try_from(raw: RawKdfParams) -> Result<Self, Self::Error> {
+        Self::new(raw.memory_cost, raw.iterations, raw.parallelism)
+    }
+}
+
+impl KdfParams {
+    /// Size of the serialized params in bytes.
+    pub const SIZE: usize = 12;
+
+    /// Maximum allowed memory cost in KiB (4 GiB).
+    pub const MAX_MEMORY_COST: u32 = 4 * 1024 * 1024;
+    /// Maximum allowed number of iterations.
+    pub const MAX_ITERATIONS: u32 = 64;
+    /// Maximum allowed degree of parallelism.
+    pub const MAX_PARALLELISM: u32 = 16;
+
+    /// Creates the params with the given memory cost (in KiB), number of iterations and degree of
+    /// parallelism. Fails if any of them is outside of what argon2 supports or above the maximum
+    /// allowed (`MAX_*`), to prevent a repository with absurd stored params from making unlocking
+    /// it take forever or exhaust the memory.
+    pub fn new(
+        memory_cost: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> Result<Self, InvalidKdfParams> {
+        if memory_cost > Self::MAX_MEMORY_COST
+            || iterations > Self::MAX_ITERATIONS
+            || parallelism > Self::MAX_PARALLELISM
+        {
+            return Err(InvalidKdfParams);
+        }
+
+        // Use argon2 to validate the rest.
+        Params::new(memory_cost, iterations, parallelism, None).map_err(|_| InvalidKdfParams)?;
+
+        Ok(Self {
+            memory_cost,
+            iterations,
+            parallelism,
+        })
+    }
+
+    /// Picks the params such that deriving a key with them takes approximately (but no more than)
+    /// `target` on the current device. If even the cheapest params take longer, those are returned.
+    ///
+    /// Note this performs several key derivations so it blocks for a multiple of `target`. Consider
+    /// calling it using `spawn_blocking`.
+    pub fn calibrate(target: Duration) -> Self {
+        let mut params = Self::MIN;
+
+        while let Some(next) = params.next_stronger() {
+            if next.measure() > target {
+                break;
+            }
+
+            params = next;
+        }
+
+        params
+    }
+
+    /// Memory cost in KiB.
+    pub fn memory_cost(&self) -> u32 {
+        self.memory_cost
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    pub fn parallelism(&self) -> u32 {
+        self.parallelism
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.memory_cost.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.iterations.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.parallelism.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn hasher(&self) -> Argon2<'static> {
+        // The params have been validated on construction.
+        let params = Params::new(self.memory_cost, self.iterations, self.parallelism, None)
+            .expect("invalid kdf params");
+
+        Argon2::new(Algorithm::default(), Version::default(), params)
+    }
+
+    // The cheapest params `calibrate` considers.
+    const MIN: Self = Self {
+        memory_cost: 1024,
+        iterations: 1,
+        parallelism: Params::DEFAULT_P_COST,
+    };
+
+    // The most expensive params `calibrate` considers.
+    const CALIBRATE_MAX_MEMORY_COST: u32 = 1024 * 1024;
+    const CALIBRATE_MAX_ITERATIONS: u32 = 16;
+
+    // Doubles the memory cost until its maximum is reached, then increases the number of
+    // iterations.
+    fn next_stronger(&self) -> Option<Self> {
+        if self.memory_cost < Self::CALIBRATE_MAX_MEMORY_COST {
+            Some(Self {
+                memory_cost: self.memory_cost * 2,
+                ..*self
+            })
+        } else if self.iterations < Self::CALIBRATE_MAX_ITERATIONS {
+            Some(Self {
+                iterations: self.iterations + 1,
+                ..*self
+            })
+        } else {
+            None
+        }
+    }
+
+    fn measure(&self) -> Duration {
+        let mut output = [0; 32];
+        let start = Instant::now();
+
+        self.hasher()
+            .hash_password_into(b"calibration", &[0; 16], &mut output)
+            .expect("failed to hash password");
+
+        start.elapsed()
+    }
+}
+
+/// The params used by repositories that don't have any stored (that is, all repositories created
+/// before the params became configurable).
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_cost: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for KdfParams {
+    type Error = InvalidKdfParams;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() != Self::SIZE {
+            return Err(InvalidKdfParams);
+        }
+
+        let read = |offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap_or_default())
+        };
+
+        Self::new(read(0), read(4), read(8))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid key derivation params")]
+pub struct InvalidKdfParams;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_matches_argon2_default() {
+        let salt = [1; 16];
+        let mut expected = [0; 32];
+        let mut actual = [0; 32];
+
+        Argon2::default()
+            .hash_password_into(b"password", &salt, &mut expected)
+            .unwrap();
+        KdfParams::default()
+            .hasher()
+            .hash_password_into(b"password", &salt, &mut actual)
+            .unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let orig = KdfParams::new(2048, 2, 1).unwrap();
+        let bytes = orig.to_bytes();
+        assert_eq!(KdfParams::try_from(&bytes[..]).unwrap(), orig);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let orig = KdfParams::new(2048, 2, 1).unwrap();
+        let json = serde_json::to_string(&orig).unwrap();
+        assert_eq!(
+            json,
+            r#"{"memory_cost":2048,"iterations":2,"parallelism":1}"#
+        );
+        assert_eq!(serde_json::from_str::<KdfParams>(&json).unwrap(), orig);
+
+        // Invalid params are rejected.
+        assert!(serde_json::from_str::<KdfParams>(
+            r#"{"memory_cost":2048,"iterations":0,"parallelism":1}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(KdfParams::new(1024, 0, 1).is_err());
+        assert!(KdfParams::new(1024, 1, 0).is_err());
+        assert!(KdfParams::try_from(&[0; 3][..]).is_err());
+    }
+
+    #[test]
+    fn too_expensive() {
+        assert!(KdfParams::new(KdfParams::MAX_MEMORY_COST, 1, 1).is_ok());
+        assert!(KdfParams::new(KdfParams::MAX_MEMORY_COST + 1, 1, 1).is_err());
+        assert!(KdfParams::new(u32::MAX, 1, 1).is_err());
+
+        assert!(KdfParams::new(1024, KdfParams::MAX_ITERATIONS, 1).is_ok());
+        assert!(KdfParams::new(1024, KdfParams::MAX_ITERATIONS + 1, 1).is_err());
+        assert!(KdfParams::new(1024, u32::MAX, 1).is_err());
+
+        assert!(KdfParams::new(1024, 1, KdfParams::MAX_PARALLELISM).is_ok());
+        assert!(KdfParams::new(1024, 1, KdfParams::MAX_PARALLELISM + 1).is_err());
+
+        let mut bytes = KdfParams::default().to_bytes();
+        bytes[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(KdfParams::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn calibrate() {
+        let params = KdfParams::calibrate(Duration::ZERO);
+        assert_eq!(params, KdfParams::MIN);
+    }
+}
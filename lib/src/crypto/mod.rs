@@ -1,10 +1,12 @@
 pub mod cipher;
 mod hash;
+mod kdf;
 mod password;
 pub mod sign;
 
 pub(crate) use self::hash::CacheHash;
 pub use self::{
     hash::{Digest, Hash, Hashable},
+    kdf::{InvalidKdfParams, KdfParams},
     password::{Password, PasswordSalt},
 };
@@ -4,6 +4,7 @@ mod macros;
 mod connection;
 mod id;
 mod migrations;
+mod write_priority;
 
 pub use id::DatabaseId;
 pub use migrations::SCHEMA_VERSION;
@@ -11,13 +12,14 @@ pub use migrations::SCHEMA_VERSION;
 use tracing::Span;
 
 use deadlock::ExpectShortLifetime;
+use futures_util::TryStreamExt;
 use ref_cast::RefCast;
 use sqlx::{
     sqlite::{
         Sqlite, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous,
         SqliteTransactionManager,
     },
-    Row, SqlitePool, TransactionManager,
+    Connection as _, Row, SqliteConnection, SqlitePool, TransactionManager,
 };
 use std::{
     fmt,
@@ -26,6 +28,10 @@ use std::{
     ops::{Deref, DerefMut},
     panic::Location,
     path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 #[cfg(test)]
@@ -36,7 +42,9 @@ use tokio::{fs, task};
 const WARN_AFTER_TRANSACTION_LIFETIME: Duration = Duration::from_secs(3);
 const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
-pub(crate) use self::connection::Connection;
+pub(crate) use self::{connection::Connection, write_priority::low_priority};
+
+use self::write_priority::WritePriority;
 
 /// Database connection pool.
 #[derive(Clone)]
@@ -45,6 +53,9 @@ pub(crate) struct Pool {
     reads: SqlitePool,
     // Pool with a single writable connection.
     write: SqlitePool,
+    // Number of tasks currently holding or waiting for the write transaction.
+    write_queue: Arc<AtomicUsize>,
+    write_priority: Arc<WritePriority>,
 }
 
 impl Pool {
@@ -71,7 +82,12 @@ impl Pool {
             .connect_with(conn_options.read_only(true))
             .await?;
 
-        Ok(Self { reads, write })
+        Ok(Self {
+            reads,
+            write,
+            write_queue: Arc::new(AtomicUsize::new(0)),
+            write_priority: Arc::new(WritePriority::new()),
+        })
     }
 
     /// Acquire a read-only database connection.
@@ -87,17 +103,72 @@ impl Pool {
     }
 
     /// Begin a write transaction. See [`WriteTransaction`] for more details.
+    ///
+    /// The transaction is high-priority, which is what anything the user might be waiting for
+    /// should use, unless the current task runs inside [`low_priority`], in which case this is the
+    /// same as [`Self::begin_write_low_priority`].
     #[track_caller]
     pub fn begin_write(&self) -> impl Future<Output = Result<WriteTransaction, sqlx::Error>> + '_ {
         let location = Location::caller();
 
         async move {
-            Ok(WriteTransaction {
-                inner: ReadTransaction::begin(&self.write, location).await?,
-            })
+            if write_priority::is_low_priority() {
+                return self.begin_write_low_priority_at(location).await;
+            }
+
+            let _pending = self.write_priority.high();
+            self.begin_write_at(location).await
         }
     }
 
+    /// Begin a low-priority write transaction. Lets all the high-priority writers (those using
+    /// [`Self::begin_write`] outside of [`low_priority`]) waiting for the write transaction go
+    /// first, even if this one started waiting earlier. Meant for bulk operations running in the
+    /// background (e.g., garbage collection or import) which should split their work into batches,
+    /// each in its own transaction, so they yield to the high-priority writers between the
+    /// batches.
+    #[track_caller]
+    pub fn begin_write_low_priority(
+        &self,
+    ) -> impl Future<Output = Result<WriteTransaction, sqlx::Error>> + '_ {
+        self.begin_write_low_priority_at(Location::caller())
+    }
+
+    async fn begin_write_low_priority_at(
+        &self,
+        location: &'static Location<'static>,
+    ) -> Result<WriteTransaction, sqlx::Error> {
+        loop {
+            self.write_priority.wait_low().await;
+
+            let tx = self.begin_write_at(location).await?;
+
+            // A high-priority writer might have started waiting while we were waiting too. Let it
+            // go first by releasing the transaction (nothing has been done in it yet).
+            if !self.write_priority.is_high_pending() {
+                return Ok(tx);
+            }
+        }
+    }
+
+    async fn begin_write_at(
+        &self,
+        location: &'static Location<'static>,
+    ) -> Result<WriteTransaction, sqlx::Error> {
+        let queue_entry = WriteQueueEntry::new(self.write_queue.clone());
+
+        Ok(WriteTransaction {
+            inner: ReadTransaction::begin(&self.write, location).await?,
+            _queue_entry: queue_entry,
+        })
+    }
+
+    /// Number of tasks currently holding or waiting for the write transaction. Useful to detect
+    /// when the database is saturated with writes.
+    pub fn write_queue_depth(&self) -> usize {
+        self.write_queue.load(Ordering::Relaxed)
+    }
+
     pub(crate) async fn close(&self) -> Result<(), sqlx::Error> {
         // Make sure to first close `reads` and only then `write`. That way when closing the write
         // connection it is the last remaining connection and so it performs a WAL checkpoint and
@@ -223,6 +294,7 @@ struct Committed(#[allow(dead_code)] ReadTransaction);
 /// transaction until that transaction is committed however.
 pub(crate) struct WriteTransaction {
     inner: ReadTransaction,
+    _queue_entry: WriteQueueEntry,
 }
 
 impl WriteTransaction {
@@ -316,6 +388,23 @@ impl std::fmt::Debug for WriteTransaction {
 
 impl_executor_by_deref!(WriteTransaction);
 
+// Counts the task in the write queue for as long as it's waiting for or holding the write
+// transaction.
+struct WriteQueueEntry(Arc<AtomicUsize>);
+
+impl WriteQueueEntry {
+    fn new(queue: Arc<AtomicUsize>) -> Self {
+        queue.fetch_add(1, Ordering::Relaxed);
+        Self(queue)
+    }
+}
+
+impl Drop for WriteQueueEntry {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 /// Creates a new database and opens a connection to it.
 pub(crate) async fn create(path: impl AsRef<Path>) -> Result<Pool, Error> {
     let path = path.as_ref();
@@ -346,16 +435,248 @@ pub(crate) async fn create_temp() -> Result<(TempDir, Pool), Error> {
     Ok((temp_dir, pool))
 }
 
-/// Opens a connection to the specified database. Fails if the db doesn't exist.
+/// Opens a connection to the specified database. Fails if the db doesn't exist. Fails with
+/// `Error::Corrupted` if the db file is corrupted.
 pub(crate) async fn open(path: impl AsRef<Path>) -> Result<Pool, Error> {
     let connect_options = SqliteConnectOptions::new().filename(path);
-    let pool = Pool::create(connect_options).await.map_err(Error::Open)?;
+    let pool = Pool::create(connect_options)
+        .await
+        .map_err(|error| Error::Open(error).detect_corruption())?;
 
-    migrations::run(&pool).await?;
+    migrations::run(&pool)
+        .await
+        .map_err(Error::detect_corruption)?;
 
     Ok(pool)
 }
 
+/// Copies everything that can still be read from the (possibly corrupted) database at `src` into
+/// a newly created database at `dst`. Returns the number of copied rows and whether everything
+/// could be copied.
+///
+/// The source database must have the latest schema version.
+pub(crate) async fn recover(src: &Path, dst: &Path) -> Result<(u64, bool), Error> {
+    // Attaching a non-existing database would create it.
+    fs::metadata(src)
+        .await
+        .map_err(|error| Error::Open(error.into()))?;
+
+    create(dst).await?.close().await?;
+
+    let mut conn = SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(dst))
+        .await
+        .map_err(Error::Open)?;
+
+    sqlx::query("ATTACH DATABASE ? AS src")
+        .bind(src.as_os_str().to_string_lossy())
+        .execute(&mut conn)
+        .await
+        .map_err(|error| Error::Open(error).detect_corruption())?;
+
+    let version: u32 = sqlx::query("PRAGMA src.user_version")
+        .fetch_one(&mut conn)
+        .await
+        .map_err(|error| Error::Query(error).detect_corruption())?
+        .get(0);
+
+    if version != *SCHEMA_VERSION {
+        return Err(Error::SchemaVersionMismatch);
+    }
+
+    let tables: Vec<(String, bool)> = sqlx::query(
+        "SELECT name, sql LIKE '%WITHOUT ROWID%'
+         FROM main.sqlite_master
+         WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+    )
+    .fetch_all(&mut conn)
+    .await?
+    .into_iter()
+    .map(|row| (row.get(0), row.get(1)))
+    .collect();
+
+    let mut recovered = 0;
+    let mut complete = true;
+
+    for (table, without_rowid) in tables {
+        // Try to copy the whole table at once first, falling back to copying it row by row which
+        // is slower but allows skipping the unreadable rows.
+        match sqlx::query(&format!(
+            "INSERT OR IGNORE INTO main.\"{table}\" SELECT * FROM src.\"{table}\""
+        ))
+        .execute(&mut conn)
+        .await
+        {
+            Ok(result) => {
+                recovered += result.rows_affected();
+                continue;
+            }
+            Err(error) => {
+                tracing::warn!(%table, ?error, "Failed to recover table, retrying row by row");
+            }
+        }
+
+        let key = if without_rowid {
+            match primary_key(&mut conn, &table).await? {
+                Some(key) => key,
+                None => {
+                    complete = false;
+                    continue;
+                }
+            }
+        } else {
+            "rowid".to_owned()
+        };
+
+        // `quote` turns the keys into SQL literals so they can be embedded into the queries below.
+        let mut keys = Vec::new();
+        let mut rows =
+            sqlx::query(&format!("SELECT quote(\"{key}\") FROM src.\"{table}\"")).fetch(&mut conn);
+
+        loop {
+            match rows.try_next().await {
+                Ok(Some(row)) => keys.push(row.get::<String, _>(0)),
+                Ok(None) => break,
+                Err(error) => {
+                    tracing::warn!(%table, ?error, "Failed to read some rows");
+                    complete = false;
+                    break;
+                }
+            }
+        }
+
+        drop(rows);
+
+        for value in keys {
+            match sqlx::query(&format!(
+                "INSERT OR IGNORE INTO main.\"{table}\"
+                 SELECT * FROM src.\"{table}\" WHERE \"{key}\" = {value}"
+            ))
+            .execute(&mut conn)
+            .await
+            {
+                Ok(result) => recovered += result.rows_affected(),
+                Err(_) => complete = false,
+            }
+        }
+    }
+
+    sqlx::query("DETACH DATABASE src")
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+
+    Ok((recovered, complete))
+}
+
+/// Creates a new database at `dst` and attaches the database of `src` to it, to copy selected data
+/// from it (see [`Export`]). Fails if `dst` already exists.
+pub(crate) async fn export(src: &Pool, dst: &Path) -> Result<Export, Error> {
+    let src_path: String = {
+        let mut conn = src.acquire().await?;
+        sqlx::query("SELECT file FROM pragma_database_list WHERE name = 'main'")
+            .fetch_one(&mut *conn)
+            .await?
+            .get(0)
+    };
+
+    create(dst).await?.close().await?;
+
+    let mut conn = SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(dst))
+        .await
+        .map_err(Error::Open)?;
+
+    sqlx::query("ATTACH DATABASE ? AS src")
+        .bind(src_path)
+        .execute(&mut conn)
+        .await
+        .map_err(Error::Open)?;
+
+    SqliteTransactionManager::begin(&mut conn).await?;
+
+    Ok(Export { conn })
+}
+
+/// Connection to a newly created database with another database attached to it as the `src`
+/// schema. Data is copied from it with `INSERT INTO main.<table> SELECT ... FROM src.<table>`.
+/// Everything happens in a single transaction so the copied data is a consistent snapshot of the
+/// source database even if it's being modified concurrently. Nothing is written unless `commit`
+/// is called.
+pub(crate) struct Export {
+    conn: SqliteConnection,
+}
+
+impl Export {
+    pub async fn commit(mut self) -> Result<(), Error> {
+        SqliteTransactionManager::commit(&mut self.conn).await?;
+        sqlx::query("DETACH DATABASE src")
+            .execute(&mut self.conn)
+            .await?;
+        self.conn.close().await?;
+
+        Ok(())
+    }
+}
+
+impl Deref for Export {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        Connection::ref_cast(&self.conn)
+    }
+}
+
+impl DerefMut for Export {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        Connection::ref_cast_mut(&mut self.conn)
+    }
+}
+
+/// Size of the database files in bytes: the main file and the write-ahead log (which holds the
+/// changes not yet checkpointed into the main file).
+pub(crate) async fn file_size(pool: &Pool) -> Result<u64, Error> {
+    let mut conn = pool.acquire().await?;
+
+    let row =
+        sqlx::query("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+            .fetch_one(&mut *conn)
+            .await?;
+    let main_size = decode_u64(row.get(0));
+
+    let path: String = sqlx::query("SELECT file FROM pragma_database_list WHERE name = 'main'")
+        .fetch_one(&mut *conn)
+        .await?
+        .get(0);
+
+    // The WAL doesn't exist when there is nothing to checkpoint (or for in-memory databases).
+    let wal_size = if path.is_empty() {
+        0
+    } else {
+        fs::metadata(format!("{path}-wal"))
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0)
+    };
+
+    Ok(main_size + wal_size)
+}
+
+// Returns the name of the primary key column of the given table, if it has exactly one.
+async fn primary_key(conn: &mut SqliteConnection, table: &str) -> Result<Option<String>, Error> {
+    let columns: Vec<String> = sqlx::query(&format!(
+        "SELECT name FROM pragma_table_info('{table}') WHERE pk > 0"
+    ))
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .map(|row| row.get(0))
+    .collect();
+
+    match <[String; 1]>::try_from(columns) {
+        Ok([column]) => Ok(Some(column)),
+        Err(_) => Ok(None),
+    }
+}
+
 async fn create_directory(path: &Path) -> Result<(), Error> {
     if let Some(dir) = path.parent() {
         fs::create_dir_all(dir)
@@ -388,6 +709,32 @@ pub enum Error {
     Open(#[source] sqlx::Error),
     #[error("failed to execute database query")]
     Query(#[from] sqlx::Error),
+    #[error("database is corrupted")]
+    Corrupted(#[source] sqlx::Error),
+    #[error("database schema version mismatch")]
+    SchemaVersionMismatch,
+}
+
+impl Error {
+    // Turns `Open` and `Query` errors caused by a corrupted (or not a) database file into
+    // `Corrupted`.
+    fn detect_corruption(self) -> Self {
+        match self {
+            Self::Open(sqlx::Error::Database(error))
+            | Self::Query(sqlx::Error::Database(error))
+                if matches!(
+                    error.code().as_deref(),
+                    // SQLITE_CORRUPT, SQLITE_NOTADB, SQLITE_CORRUPT_VTAB,
+                    // SQLITE_CORRUPT_SEQUENCE, SQLITE_CORRUPT_INDEX
+                    Some("11" | "26" | "267" | "523" | "779")
+                ) =>
+            {
+                tracing::error!(?error, "Database is corrupted");
+                Self::Corrupted(sqlx::Error::Database(error))
+            }
+            error => error,
+        }
+    }
 }
 
 async fn get_pragma(conn: &mut Connection, name: &str) -> Result<u32, Error> {
@@ -409,6 +756,101 @@ async fn set_pragma(conn: &mut Connection, name: &str, value: u32) -> Result<(),
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn write_queue_depth() {
+        let (_base_dir, pool) = create_temp().await.unwrap();
+        assert_eq!(pool.write_queue_depth(), 0);
+
+        let tx = pool.begin_write().await.unwrap();
+        assert_eq!(pool.write_queue_depth(), 1);
+
+        let waiter = task::spawn({
+            let pool = pool.clone();
+            async move { pool.begin_write().await.unwrap().commit().await.unwrap() }
+        });
+
+        while pool.write_queue_depth() < 2 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        tx.commit().await.unwrap();
+        waiter.await.unwrap();
+
+        assert_eq!(pool.write_queue_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn file_size_includes_wal() {
+        let (base_dir, pool) = create_temp().await.unwrap();
+
+        let mut tx = pool.begin_write().await.unwrap();
+        sqlx::query("CREATE TABLE data (content BLOB NOT NULL)")
+            .execute(&mut **tx)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO data (content) VALUES (zeroblob(65536))")
+            .execute(&mut **tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        // Not checkpointed yet so the WAL holds the inserted data.
+        let wal_size = fs::metadata(base_dir.path().join("temp.db-wal"))
+            .await
+            .unwrap()
+            .len();
+        assert!(wal_size > 65536);
+
+        assert!(file_size(&pool).await.unwrap() >= wal_size);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn low_priority_scope() {
+        let (_base_dir, pool) = create_temp().await.unwrap();
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // Hold the write transaction so the other writers have to wait for it.
+        let tx = pool.begin_write().await.unwrap();
+
+        // Plain `begin_write` run in the low-priority scope starts waiting first...
+        let low = task::spawn({
+            let pool = pool.clone();
+            let order_tx = order_tx.clone();
+
+            low_priority(async move {
+                let _tx = pool.begin_write().await.unwrap();
+                order_tx.send("low").unwrap();
+            })
+        });
+
+        while pool.write_queue_depth() < 2 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // ...but the one outside of it goes first.
+        let high = task::spawn({
+            let pool = pool.clone();
+
+            async move {
+                let _tx = pool.begin_write().await.unwrap();
+                order_tx.send("high").unwrap();
+            }
+        });
+
+        while pool.write_queue_depth() < 3 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(tx);
+
+        low.await.unwrap();
+        high.await.unwrap();
+
+        assert_eq!(order_rx.recv().await, Some("high"));
+        assert_eq!(order_rx.recv().await, Some("low"));
+    }
 
     // Check the casts are lossless
 
@@ -0,0 +1,68 @@
+//! Priority lanes for the write transactions, so that bulk operations running in the background
+//! (garbage collection, repair, import, ...) don't starve the interactive writes. There is only one
+//! write transaction at a time. A high-priority writer registers itself as pending while waiting
+//! for it and low-priority writers let all the pending ones go first.
+
+// Probably false positive triggered by `task_local`
+#![allow(clippy::declare_interior_mutable_const)]
+
+use std::future::Future;
+use tokio::sync::watch;
+
+tokio::task_local! {
+    static LOW_PRIORITY: ();
+}
+
+pub(super) struct WritePriority {
+    // Number of high-priority writers waiting for the write transaction.
+    pending_tx: watch::Sender<usize>,
+}
+
+impl WritePriority {
+    pub fn new() -> Self {
+        Self {
+            pending_tx: watch::channel(0).0,
+        }
+    }
+
+    /// Registers a pending high-priority writer. It stays pending until the returned guard is
+    /// dropped.
+    pub fn high(&self) -> PendingGuard<'_> {
+        self.pending_tx.send_modify(|count| *count += 1);
+        PendingGuard(&self.pending_tx)
+    }
+
+    /// Is any high-priority writer currently pending?
+    pub fn is_high_pending(&self) -> bool {
+        *self.pending_tx.borrow() > 0
+    }
+
+    /// Waits until there are no pending high-priority writers.
+    pub async fn wait_low(&self) {
+        // The sender lives as long as `self` so this can't fail.
+        self.pending_tx
+            .subscribe()
+            .wait_for(|count| *count == 0)
+            .await
+            .ok();
+    }
+}
+
+pub(super) struct PendingGuard<'a>(&'a watch::Sender<usize>);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.send_modify(|count| *count -= 1);
+    }
+}
+
+/// Runs `f` with all the write transactions it begins being low-priority. Applies only to the
+/// current task, not to the tasks spawned from `f`.
+pub(crate) async fn low_priority<F: Future>(f: F) -> F::Output {
+    LOW_PRIORITY.scope((), f).await
+}
+
+/// Is the current task running inside `low_priority`?
+pub(super) fn is_low_priority() -> bool {
+    LOW_PRIORITY.try_with(|_| ()).is_ok()
+}
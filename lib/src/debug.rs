@@ -1,3 +1,12 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Returns a new id to identify a tracing span with, so the events of concurrent operations of the
+/// same kind can be told apart.
+pub(crate) fn next_span_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
 #[derive(Clone)]
 pub struct DebugPrinter {
     // Used for indentation
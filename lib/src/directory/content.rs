@@ -14,6 +14,7 @@ use std::{
         btree_map::{self, Entry},
         BTreeMap,
     },
+    ops::Bound,
 };
 
 /// Version of the Directory serialization format.
@@ -55,6 +56,17 @@ impl Content {
         self.entries.iter()
     }
 
+    /// Iterates the entries whose names are ordered strictly after `name` (or all of them if
+    /// `name` is `None`).
+    pub fn range_after(&self, name: Option<&str>) -> btree_map::Range<String, EntryData> {
+        let start = match name {
+            Some(name) => Bound::Excluded(name),
+            None => Bound::Unbounded,
+        };
+
+        self.entries.range::<str, _>((start, Bound::Unbounded))
+    }
+
     pub fn get_key_value(&self, name: &str) -> Option<(&String, &EntryData)> {
         self.entries.get_key_value(name)
     }
@@ -87,6 +99,11 @@ impl Content {
         }
     }
 
+    /// Removes the entry at `name` completely (without leaving a tombstone).
+    pub fn remove(&mut self, name: &str) -> Option<EntryData> {
+        self.entries.remove(name)
+    }
+
     /// Checks whether an entry can be inserted into this directory without actually inserting it.
     /// If so, returns the blob_id of the existing entry (if any).
     pub fn check_insert(
@@ -10,7 +10,7 @@ use crate::{
     crypto::sign::PublicKey,
     error::{Error, Result},
     file::File,
-    protocol::Locator,
+    protocol::{Locator, RootNode},
     store::ReadTransaction,
     version_vector::VersionVector,
     versioned::{BranchItem, Versioned},
@@ -220,6 +220,22 @@ impl<'a> DirectoryRef<'a> {
         .await
     }
 
+    /// Opens the directory at the given snapshot of its branch. The directory is not locked.
+    pub(crate) async fn open_at(
+        &self,
+        tx: &mut ReadTransaction,
+        root_node: &RootNode,
+    ) -> Result<Directory> {
+        Directory::open_at(
+            tx,
+            root_node,
+            self.branch().clone(),
+            *self.blob_id(),
+            Some(self.inner.parent_context()),
+        )
+        .await
+    }
+
     pub(super) async fn open_snapshot(
         &self,
         tx: &mut ReadTransaction,
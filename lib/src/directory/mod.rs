@@ -2,7 +2,9 @@ mod content;
 mod entry;
 mod entry_data;
 mod entry_type;
+mod name_policy;
 mod parent_context;
+mod purged_tombstones;
 #[cfg(test)]
 mod tests;
 
@@ -13,12 +15,18 @@ pub use self::{
 };
 pub(crate) use self::{
     entry_data::{EntryData, EntryTombstoneData, TombstoneCause},
+    name_policy::NamePolicy,
     parent_context::ParentContext,
+    purged_tombstones::PurgedTombstones,
 };
 
-use self::content::Content;
+use self::content::{Content, EntryExists};
 use crate::{
-    blob::{lock::ReadLock, Blob, BlobId},
+    blob::{
+        self,
+        lock::{LockKind, ReadLock},
+        Blob, BlobId,
+    },
     branch::Branch,
     crypto::sign::PublicKey,
     debug::DebugPrinter,
@@ -29,7 +37,11 @@ use crate::{
     version_vector::VersionVector,
 };
 use async_recursion::async_recursion;
-use std::{cmp::Ordering, fmt, mem};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{btree_map, BTreeMap},
+    fmt, mem,
+};
 use tracing::instrument;
 
 #[derive(Clone)]
@@ -51,6 +63,15 @@ impl Directory {
         Self::open(branch, BlobId::ROOT, None, locking, fallback).await
     }
 
+    /// Opens the root directory at the given snapshot of the branch. The directory is not locked.
+    pub(crate) async fn open_root_at(
+        tx: &mut ReadTransaction,
+        root_node: &RootNode,
+        branch: Branch,
+    ) -> Result<Self> {
+        Self::open_at(tx, root_node, branch, BlobId::ROOT, None).await
+    }
+
     /// Opens the root directory or creates it if it doesn't exists.
     ///
     /// See [`Self::create_directory`] for info about the `merge` parameter.
@@ -111,10 +132,19 @@ impl Directory {
 
     /// Lookup an entry of this directory by name.
     pub fn lookup(&self, name: &'_ str) -> Result<EntryRef> {
-        self.content
-            .get_key_value(name)
-            .map(|(name, data)| EntryRef::new(self, name, data))
-            .ok_or(Error::EntryNotFound)
+        let (name, data) = match self.content.get_key_value(name) {
+            Some(entry) => entry,
+            // With name normalization enabled the entry might have been created under the
+            // normalized form of the name.
+            None => self
+                .branch()
+                .name_policy()
+                .normalize(name)
+                .and_then(|name| self.content.get_key_value(&name))
+                .ok_or(Error::EntryNotFound)?,
+        };
+
+        Ok(EntryRef::new(self, name, data))
     }
 
     /// Returns iterator over the entries of this directory.
@@ -124,8 +154,25 @@ impl Directory {
             .map(move |(name, data)| EntryRef::new(self, name, data))
     }
 
-    /// Creates a new file inside this directory.
+    /// Returns iterator over the entries of this directory whose names are ordered strictly after
+    /// `after` (or over all the entries if it's `None`). Passing the name of the last entry
+    /// returned by the previous call resumes the listing where it left off, even when entries
+    /// were added or removed in the meantime.
+    pub fn entries_after<'a>(
+        &'a self,
+        after: Option<&str>,
+    ) -> impl DoubleEndedIterator<Item = EntryRef<'a>> + Clone {
+        self.content
+            .range_after(after)
+            .map(move |(name, data)| EntryRef::new(self, name, data))
+    }
+
+    /// Creates a new file inside this directory. The name is checked and normalized according to
+    /// the name policy of the repository and the function fails with `InvalidName` or
+    /// `NameTooLong` if it doesn't conform to it.
     pub async fn create_file(&mut self, name: String) -> Result<File> {
+        let name = self.branch().name_policy().apply(name)?;
+
         let mut tx = self.branch().store().begin_write().await?;
         let mut changeset = Changeset::new();
 
@@ -153,6 +200,200 @@ impl Directory {
         Ok(file)
     }
 
+    /// Atomically replaces the content of the file at `name` (or creates it if it doesn't exist)
+    /// with `content`. The content is written into a new blob and the entry is switched to it in
+    /// the same transaction, so readers observe either the old or the new content but never a mix
+    /// of the two. The blocks of the old blob are then collected by the garbage collector.
+    ///
+    /// The version vector of the entry is the existing one (if any) merged with `merge` and with
+    /// the local version incremented. Pass the version vectors of the versions of the file in
+    /// other branches as `merge` to make the new content supersede them.
+    pub(crate) async fn replace_file(
+        &mut self,
+        name: String,
+        merge: &VersionVector,
+        content: &[u8],
+    ) -> Result<()> {
+        let name = self.branch().name_policy().apply(name)?;
+
+        if let Some(max) = self.branch().max_file_size().get() {
+            if content.len() as u64 > max {
+                return Err(Error::FileTooLarge);
+            }
+        }
+
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+
+        let mut version_vector = match self.lookup(&name) {
+            Ok(EntryRef::File(entry)) => entry.version_vector().clone(),
+            Ok(EntryRef::Tombstone(entry)) => entry.version_vector().clone(),
+            Ok(EntryRef::Directory(_)) => return Err(Error::EntryIsDirectory),
+            Err(Error::EntryNotFound) => VersionVector::new(),
+            Err(error) => return Err(error),
+        };
+        version_vector.merge(merge);
+        version_vector.increment(*self.branch().id());
+
+        let blob_id = rand::random();
+        let mut blob = Blob::create(self.branch().clone(), blob_id);
+        blob.write_all(&mut tx, &mut changeset, content).await?;
+        blob.flush(&mut tx, &mut changeset).await?;
+
+        let new_content = self
+            .begin_insert_entry(
+                &mut tx,
+                &mut changeset,
+                name,
+                EntryData::file(blob_id, version_vector),
+            )
+            .await?;
+
+        self.commit(tx, changeset).await?;
+        self.finalize(new_content);
+
+        Ok(())
+    }
+
+    /// Creates a new file entry at `name` whose content is the content of the file `src_blob_id`
+    /// in `src_branch`. The blocks are shared with the source file, only the index is copied (see
+    /// [`blob::link`] for details). Fails with `EntryExists` if a file or directory already exists
+    /// at `name`.
+    pub(crate) async fn link_file(
+        &mut self,
+        name: String,
+        src_branch: &Branch,
+        src_blob_id: BlobId,
+    ) -> Result<()> {
+        let name = self.branch().name_policy().apply(name)?;
+
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+
+        match self.lookup(&name) {
+            Ok(EntryRef::File(_) | EntryRef::Directory(_)) => return Err(Error::EntryExists),
+            Ok(EntryRef::Tombstone(_)) | Err(Error::EntryNotFound) => (),
+            Err(error) => return Err(error),
+        }
+
+        let blob_id = rand::random();
+        let version_vector = self
+            .content
+            .initial_version_vector(&name)
+            .incremented(*self.branch().id());
+
+        blob::link(&mut tx, &mut changeset, src_branch, src_blob_id, blob_id).await?;
+
+        let new_content = self
+            .begin_insert_entry(
+                &mut tx,
+                &mut changeset,
+                name,
+                EntryData::file(blob_id, version_vector),
+            )
+            .await?;
+
+        self.commit(tx, changeset).await?;
+        self.finalize(new_content);
+
+        Ok(())
+    }
+
+    /// Makes the file at `name` a copy of the file `src_blob_id` in `src_branch`, replacing the
+    /// existing file or tombstone at `name` (if any). The blocks are shared with the source file
+    /// (see [`Self::link_file`]). The version vector of the entry is the existing one merged with
+    /// `merge` and with the local version incremented, so the copy supersedes both the existing
+    /// entry and the source one (when its version vector is passed as `merge`). Does nothing if
+    /// the existing entry is already the same file and at least as up to date as `merge`.
+    pub(crate) async fn pull_file(
+        &mut self,
+        name: String,
+        src_branch: &Branch,
+        src_blob_id: BlobId,
+        merge: &VersionVector,
+    ) -> Result<()> {
+        let name = self.branch().name_policy().apply(name)?;
+
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+
+        let mut version_vector = match self.lookup(&name) {
+            Ok(EntryRef::File(entry)) => {
+                if *entry.blob_id() == src_blob_id && entry.version_vector() >= merge {
+                    return Ok(());
+                }
+
+                entry.version_vector().clone()
+            }
+            Ok(EntryRef::Tombstone(entry)) => entry.version_vector().clone(),
+            Ok(EntryRef::Directory(_)) => return Err(Error::EntryIsDirectory),
+            Err(Error::EntryNotFound) => VersionVector::new(),
+            Err(error) => return Err(error),
+        };
+        version_vector.merge(merge);
+        version_vector.increment(*self.branch().id());
+
+        let blob_id = rand::random();
+        blob::link(&mut tx, &mut changeset, src_branch, src_blob_id, blob_id).await?;
+
+        let new_content = self
+            .begin_insert_entry(
+                &mut tx,
+                &mut changeset,
+                name,
+                EntryData::file(blob_id, version_vector),
+            )
+            .await?;
+
+        self.commit(tx, changeset).await?;
+        self.finalize(new_content);
+
+        Ok(())
+    }
+
+    /// Merges `merge` into the version vector of the file at `name`, provided it's still the blob
+    /// `blob_id`. This makes the file supersede the version `merge` comes from without changing
+    /// its content - useful when the two versions are known to have the same content. Fails with
+    /// `EntryExists` if the entry is no longer the given blob.
+    pub(crate) async fn merge_file_version_vector(
+        &mut self,
+        name: &str,
+        blob_id: &BlobId,
+        merge: VersionVector,
+    ) -> Result<()> {
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+
+        match self.lookup(name)? {
+            EntryRef::File(entry) if entry.blob_id() == blob_id => (),
+            EntryRef::File(_) | EntryRef::Directory(_) | EntryRef::Tombstone(_) => {
+                return Err(Error::EntryExists)
+            }
+        }
+
+        let mut content = self.content.clone();
+        let diff = content.bump(name, Bump::Merge(merge))?;
+
+        if diff.is_empty() {
+            return Ok(());
+        }
+
+        self.save(&mut tx, &mut changeset, &content).await?;
+        self.bump(&mut tx, &mut changeset, Bump::Add(diff)).await?;
+        self.commit(tx, changeset).await?;
+        self.finalize(content);
+
+        Ok(())
+    }
+
     /// Creates a new subdirectory of this directory.
     ///
     /// `blob_id` is the blob id of the directory to be created. It must be unique. The easiest way
@@ -166,6 +407,8 @@ impl Directory {
     ///
     /// where `old_vv` is the version vector of the existing entry or `VersionVector::new()` if not
     /// such exists yet.
+    ///
+    /// The name is checked and normalized the same way as in `create_file`.
     #[instrument(level = "trace", skip(self))]
     pub(crate) async fn create_directory(
         &mut self,
@@ -173,6 +416,8 @@ impl Directory {
         blob_id: BlobId,
         merge: &VersionVector,
     ) -> Result<Self> {
+        let name = self.branch().name_policy().apply(name)?;
+
         let lock = self
             .branch()
             .locker()
@@ -226,6 +471,109 @@ impl Directory {
         Ok((dir, content))
     }
 
+    /// Creates all the directories in `tree` (relative to this directory) that don't exist yet.
+    ///
+    /// Unlike calling `create_directory` for each of them, all the directories are created in a
+    /// single transaction which bumps the root version vector (and so creates a new snapshot)
+    /// only once.
+    #[instrument(level = "trace", skip_all)]
+    pub(crate) async fn create_directories(&mut self, tree: &DirectoryTree) -> Result<()> {
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+
+        let (content, diff) = self
+            .create_directories_in(&mut tx, &mut changeset, tree)
+            .await?;
+
+        if diff.is_empty() {
+            // All the directories already exist.
+            return Ok(());
+        }
+
+        self.save(&mut tx, &mut changeset, &content).await?;
+        self.bump(&mut tx, &mut changeset, Bump::Add(diff)).await?;
+        self.commit(tx, changeset).await?;
+        self.finalize(content);
+
+        Ok(())
+    }
+
+    // Creates the missing directories in `tree` bottom-up, saving each modified descendant of
+    // `self` exactly once. Returns the new content of `self` (not saved) and the difference
+    // between the new and the old version vector of `self`.
+    #[async_recursion]
+    async fn create_directories_in(
+        &mut self,
+        tx: &mut WriteTransaction,
+        changeset: &mut Changeset,
+        tree: &DirectoryTree,
+    ) -> Result<(Content, VersionVector)> {
+        let mut content = self.content.clone();
+        let mut diff = VersionVector::new();
+
+        for (name, subtree) in &tree.0 {
+            let name = &self.branch().name_policy().apply(name.clone())?;
+
+            let blob_id = match self.lookup(name) {
+                Ok(EntryRef::Directory(entry)) => Some(*entry.blob_id()),
+                Ok(EntryRef::File(_)) => return Err(Error::EntryIsFile),
+                Ok(EntryRef::Tombstone(_)) | Err(Error::EntryNotFound) => None,
+                Err(error) => return Err(error),
+            };
+
+            let parent = self.create_parent_context(name.clone());
+
+            if let Some(blob_id) = blob_id {
+                let lock = self
+                    .branch()
+                    .locker()
+                    .try_read(blob_id)
+                    .map_err(|_| Error::Locked)?;
+                let mut dir = Self::open_in(
+                    Some(lock),
+                    tx,
+                    self.branch().clone(),
+                    blob_id,
+                    Some(parent),
+                    DirectoryFallback::Disabled,
+                )
+                .await?;
+
+                let (dir_content, dir_diff) =
+                    dir.create_directories_in(tx, changeset, subtree).await?;
+
+                if !dir_diff.is_empty() {
+                    dir.save(tx, changeset, &dir_content).await?;
+                    diff += &content.bump(name, Bump::Add(dir_diff))?;
+                }
+            } else {
+                let blob_id = rand::random();
+                let lock = self
+                    .branch()
+                    .locker()
+                    .try_read(blob_id)
+                    .map_err(|_| Error::EntryExists)?;
+                let mut dir = Self::create(lock, self.branch().clone(), blob_id, Some(parent));
+
+                let (dir_content, dir_diff) =
+                    dir.create_directories_in(tx, changeset, subtree).await?;
+                dir.save(tx, changeset, &dir_content).await?;
+
+                let mut version_vector = content
+                    .initial_version_vector(name)
+                    .incremented(*self.branch().id());
+                version_vector += &dir_diff;
+
+                let data = EntryData::directory(blob_id, version_vector);
+                diff += &content.insert(name.clone(), data)?;
+            }
+        }
+
+        Ok((content, diff))
+    }
+
     fn create_parent_context(&self, entry_name: String) -> ParentContext {
         ParentContext::new(
             *self.blob_id(),
@@ -277,7 +625,8 @@ impl Directory {
     }
 
     /// Creates a tombstone for entry with the given name. If the entry exists, this effectively
-    /// removes it. If it doesn't exist, it still creates the tombstone. This method is meant to be
+    /// removes it. If it doesn't exist, it still creates the tombstone, unless the same or a newer
+    /// tombstone has been purged from this directory before. This method is meant to be
     /// used for merging removed entries from other branches. For removing entries locally, use
     /// [`Self::remove_entry`] instead.
     #[instrument()]
@@ -286,11 +635,8 @@ impl Directory {
         name: &str,
         tombstone: EntryTombstoneData,
     ) -> Result<()> {
-        let mut tx = self.branch().store().begin_write().await?;
-        let mut changeset = Changeset::new();
-
         match self.lookup(name) {
-            Ok(EntryRef::File(_) | EntryRef::Directory(_)) | Err(Error::EntryNotFound) => (),
+            Ok(EntryRef::File(_) | EntryRef::Directory(_)) => (),
             Ok(EntryRef::Tombstone(old_entry)) => {
                 // Attempt to replace a tombstone with another tombstone whose version vector is
                 // the same or lower is a no-op.
@@ -298,9 +644,23 @@ impl Directory {
                     return Ok(());
                 }
             }
+            Err(Error::EntryNotFound) => {
+                // The tombstone has been merged before and purged since (see `purge_tombstones`).
+                // Don't bring it back.
+                if self.branch().purged_tombstones().contains(
+                    self.blob_id(),
+                    name,
+                    &tombstone.version_vector,
+                ) {
+                    return Ok(());
+                }
+            }
             Err(e) => return Err(e),
         }
 
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
         let content = self
             .begin_insert_entry(
                 &mut tx,
@@ -316,6 +676,55 @@ impl Directory {
         Ok(())
     }
 
+    /// Removes the given tombstones from this directory completely. Each tombstone is removed only
+    /// if it's still there with the given version vector (that is, it hasn't been replaced in the
+    /// meantime). Returns the number of removed tombstones.
+    ///
+    /// Removing a tombstone makes the removed entry forgotten, so if a replica that still has the
+    /// entry and hasn't seen the tombstone yet syncs with us afterwards, the entry gets
+    /// resurrected. Only remove tombstones which are known to be no longer needed.
+    ///
+    /// To prevent the same tombstones still present in other branches from being merged back
+    /// afterwards, record them in the branch's `PurgedTombstones` first (see `create_tombstone`).
+    pub(crate) async fn purge_tombstones(
+        &mut self,
+        tombstones: &[(String, VersionVector)],
+    ) -> Result<usize> {
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+
+        let mut content = self.content.clone();
+        let mut count = 0;
+
+        for (name, version_vector) in tombstones {
+            match content.get_key_value(name) {
+                Some((_, EntryData::Tombstone(data))) if &data.version_vector == version_vector => {
+                    content.remove(name);
+                    count += 1;
+                }
+                Some(_) | None => (),
+            }
+        }
+
+        if count == 0 {
+            return Ok(0);
+        }
+
+        self.save(&mut tx, &mut changeset, &content).await?;
+        self.bump(
+            &mut tx,
+            &mut changeset,
+            Bump::increment(*self.branch().id()),
+        )
+        .await?;
+        self.commit(tx, changeset).await?;
+        self.finalize(content);
+
+        Ok(count)
+    }
+
     /// Moves an entry at `src_name` from this directory to the `dst_dir` directory at `dst_name`.
     ///
     /// It adds a tombstone to where the entry is being moved from and creates a new entry at the
@@ -332,6 +741,10 @@ impl Directory {
     ///
     /// To move an entry within the same directory, clone `self` and pass it as `dst_dir`.
     ///
+    /// If `overwrite` is false and `dst_dir` contains a (non-tombstone) entry at `dst_name`,
+    /// `Error::EntryExists` is returned. This is checked inside the transaction so it catches also
+    /// entries created concurrently after the caller looked up `dst_dir`.
+    ///
     /// # Cancel safety
     ///
     /// This function is atomic and thus cancel safe. Either the entry is both removed from the src
@@ -344,12 +757,23 @@ impl Directory {
         dst_dir: &mut Directory,
         dst_name: &str,
         dst_vv: VersionVector,
+        overwrite: bool,
     ) -> Result<()> {
         let mut dst_data = src_data;
         let src_vv = mem::replace(dst_data.version_vector_mut(), dst_vv);
 
         let mut tx = self.branch().store().begin_write().await?;
 
+        if !overwrite {
+            dst_dir.refresh_in(&mut tx).await?;
+
+            match dst_dir.lookup(dst_name) {
+                Ok(EntryRef::File(_) | EntryRef::Directory(_)) => return Err(Error::EntryExists),
+                Ok(EntryRef::Tombstone(_)) | Err(Error::EntryNotFound) => (),
+                Err(error) => return Err(error),
+            }
+        }
+
         let mut changeset = Changeset::new();
         let dst_content = dst_dir
             .begin_insert_entry(&mut tx, &mut changeset, dst_name.to_owned(), dst_data)
@@ -657,6 +1081,24 @@ impl Directory {
         })
     }
 
+    /// Opens the directory at the given snapshot of its branch. The directory is not locked.
+    async fn open_at(
+        tx: &mut ReadTransaction,
+        root_node: &RootNode,
+        branch: Branch,
+        blob_id: BlobId,
+        parent: Option<ParentContext>,
+    ) -> Result<Self> {
+        let (blob, content) = load_at(tx, root_node, branch, blob_id).await?;
+
+        Ok(Self {
+            blob,
+            parent,
+            content,
+            lock: None,
+        })
+    }
+
     async fn open_snapshot(
         tx: &mut ReadTransaction,
         branch: Branch,
@@ -777,6 +1219,11 @@ impl Directory {
         self.blob.id()
     }
 
+    /// Number of ancestors of this directory (0 for the root).
+    fn depth(&self) -> usize {
+        self.parent.as_ref().map(ParentContext::depth).unwrap_or(0)
+    }
+
     /// Length of this directory in bytes. Does not include the content, only the size of directory
     /// itself.
     pub fn len(&self) -> u64 {
@@ -956,6 +1403,141 @@ pub(crate) enum DirectoryLocking {
     Disabled,
 }
 
+/// Tree of directory names to be created with [`Directory::create_directories`].
+#[derive(Default, Debug)]
+pub(crate) struct DirectoryTree(BTreeMap<String, DirectoryTree>);
+
+impl DirectoryTree {
+    /// Inserts the path given as a sequence of names. All its ancestors are inserted as well.
+    pub fn insert<'a, I>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut node = self;
+
+        for name in names {
+            node = node.0.entry(name.to_owned()).or_default();
+        }
+    }
+}
+
+/// Forks the given files into the local branch. `groups` contains pairs of a directory (which
+/// must be already forked into the local branch) and the files to fork into it. Each file is given
+/// by its name, the branch it's currently in and its entry in that branch. Like
+/// `ParentContext::fork` but all the files are forked in a single transaction which bumps the
+/// root version vector (and so creates a new snapshot) only once. The files are forked shallowly:
+/// only the index is copied, the blocks are shared.
+#[instrument(level = "trace", skip_all)]
+pub(crate) async fn fork_files(
+    branch: &Branch,
+    mut groups: Vec<(Directory, Vec<(String, Branch, EntryData)>)>,
+) -> Result<()> {
+    // Acquire unique locks for the destination blobs (see `ParentContext::fork` for details). The
+    // locks are acquired in a fixed order and if any of them is currently held by someone else,
+    // we release all of them and try again so that concurrent forks can't deadlock each other.
+    let _locks = loop {
+        let mut lock_ids = Vec::new();
+
+        for (dir, files) in &mut groups {
+            dir.refresh().await?;
+
+            for (name, _, data) in files.iter() {
+                let new_blob_id = *data.blob_id().ok_or(Error::EntryNotFound)?;
+
+                match dir.content.check_insert(name, data) {
+                    Ok(old_blob_id) => lock_ids.push(old_blob_id.unwrap_or(new_blob_id)),
+                    Err(EntryExists::Same) => continue,
+                    Err(EntryExists::Different) => return Err(Error::EntryExists),
+                }
+            }
+        }
+
+        lock_ids.sort();
+        lock_ids.dedup();
+
+        let mut locks = Vec::with_capacity(lock_ids.len());
+        let mut busy = None;
+
+        for blob_id in lock_ids {
+            match branch.locker().try_unique(blob_id) {
+                Ok(lock) => locks.push(lock),
+                Err((notify, LockKind::Unique)) => {
+                    busy = Some(notify);
+                    break;
+                }
+                Err((_, LockKind::Read | LockKind::Write)) => return Err(Error::Locked),
+            }
+        }
+
+        if let Some(notify) = busy {
+            drop(locks);
+            notify.await;
+        } else {
+            break locks;
+        }
+    };
+
+    let mut tx = branch.store().begin_write().await?;
+    let mut changeset = Changeset::new();
+
+    // Modified directories keyed by their depth (deepest first) and blob id, together with their
+    // new content and the diff to propagate to their parents.
+    let mut pending = BTreeMap::new();
+
+    for (mut dir, files) in groups {
+        dir.refresh_in(&mut tx).await?;
+
+        let mut content = dir.content.clone();
+        let mut diff = VersionVector::new();
+
+        for (name, src_branch, data) in files {
+            match content.check_insert(&name, &data) {
+                Ok(_) => (),
+                Err(EntryExists::Same) => continue,
+                Err(EntryExists::Different) => return Err(Error::EntryExists),
+            }
+
+            let blob_id = *data.blob_id().ok_or(Error::EntryNotFound)?;
+            blob::link(&mut tx, &mut changeset, &src_branch, blob_id, blob_id).await?;
+
+            diff += &content.insert(name, data)?;
+        }
+
+        pending.insert((Reverse(dir.depth()), *dir.blob_id()), (dir, content, diff));
+    }
+
+    // Save the modified directories and bump the version vectors of their ancestors. Processing
+    // the deepest directories first makes sure each directory is saved only once even if several
+    // of its descendants were modified.
+    while let Some(((Reverse(depth), _), (mut dir, content, diff))) = pending.pop_first() {
+        if diff.is_empty() {
+            // All the files in this directory already forked.
+            continue;
+        }
+
+        dir.save(&mut tx, &mut changeset, &content).await?;
+
+        let Some(parent) = &dir.parent else {
+            changeset.bump(Bump::Add(diff));
+            continue;
+        };
+
+        let (_, parent_content, parent_diff) =
+            match pending.entry((Reverse(depth - 1), *parent.directory_id())) {
+                btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                btree_map::Entry::Vacant(entry) => {
+                    let parent_dir = parent.open_in(&mut tx, branch.clone()).await?;
+                    let parent_content = parent_dir.content.clone();
+                    entry.insert((parent_dir, parent_content, VersionVector::new()))
+                }
+            };
+
+        *parent_diff += &parent_content.bump(parent.entry_name(), Bump::Add(diff))?;
+    }
+
+    commit(tx, changeset, branch).await
+}
+
 /// Update the root version vector of the given branch by merging it with `merge`.
 /// If `merge` is less that or equal to the current root version vector, this is s no-op.
 #[instrument(skip(branch), fields(writer_id = ?branch.id()))]
@@ -0,0 +1,129 @@
+use crate::error::{Error, Result};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+const UNLIMITED: usize = 0;
+
+/// Rules for the names of newly created entries. Shared among all branches of a repository.
+///
+/// Names that are empty, `.`, `..` or contain `/` or the NUL character are always rejected. In
+/// addition, the names can be optionally normalized to the Unicode Normalization Form C (NFC) and
+/// their length (in bytes, after the normalization) limited. Both are disabled by default.
+///
+/// The normalization makes the names created on different platforms consistent (e.g., macOS
+/// tends to produce decomposed names (NFD) while most other systems produce composed ones), so
+/// "the same" name can't end up in the same directory twice.
+#[derive(Clone, Default)]
+pub(crate) struct NamePolicy(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    normalize: AtomicBool,
+    max_len: AtomicUsize,
+}
+
+impl NamePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_normalization_enabled(&self, enabled: bool) {
+        self.0.normalize.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_normalization_enabled(&self) -> bool {
+        self.0.normalize.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_len(&self, max: Option<usize>) {
+        // Zero is reserved for "unlimited" and a zero-length name is invalid anyway.
+        self.0.max_len.store(
+            max.map(|max| max.max(1)).unwrap_or(UNLIMITED),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn max_len(&self) -> Option<usize> {
+        match self.0.max_len.load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            max => Some(max),
+        }
+    }
+
+    /// Checks that the name of a new entry is valid and returns it normalized (if enabled).
+    pub fn apply(&self, name: String) -> Result<String> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+            return Err(Error::InvalidName);
+        }
+
+        let name = self.normalize(&name).unwrap_or(name);
+
+        if self.max_len().map(|max| name.len() > max).unwrap_or(false) {
+            return Err(Error::NameTooLong);
+        }
+
+        Ok(name)
+    }
+
+    /// Returns the normalized form of the name if normalization is enabled and the name is not
+    /// normalized already, otherwise `None`. Used both when creating entries (see `apply`) and
+    /// when looking them up.
+    pub fn normalize(&self, name: &str) -> Option<String> {
+        if self.is_normalization_enabled() && !is_nfc(name) {
+            Some(name.nfc().collect())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn invalid() {
+        let policy = NamePolicy::new();
+
+        for name in ["", ".", "..", "a/b", "a\0b"] {
+            assert_matches!(policy.apply(name.to_owned()), Err(Error::InvalidName));
+        }
+
+        assert_eq!(policy.apply("a.b".to_owned()).unwrap(), "a.b");
+    }
+
+    #[test]
+    fn normalize() {
+        let policy = NamePolicy::new();
+        let nfd = "e\u{301}.txt";
+        let nfc = "\u{e9}.txt";
+
+        assert_eq!(policy.apply(nfd.to_owned()).unwrap(), nfd);
+
+        policy.set_normalization_enabled(true);
+        assert_eq!(policy.apply(nfd.to_owned()).unwrap(), nfc);
+        assert_eq!(policy.apply(nfc.to_owned()).unwrap(), nfc);
+    }
+
+    #[test]
+    fn max_len() {
+        let policy = NamePolicy::new();
+        assert_eq!(policy.max_len(), None);
+
+        policy.set_max_len(Some(5));
+        assert_eq!(policy.max_len(), Some(5));
+
+        assert_eq!(policy.apply("abcde".to_owned()).unwrap(), "abcde");
+        assert_matches!(policy.apply("abcdef".to_owned()), Err(Error::NameTooLong));
+        // Length is in bytes
+        assert_matches!(policy.apply("ééé".to_owned()), Err(Error::NameTooLong));
+
+        policy.set_max_len(None);
+        assert_eq!(policy.max_len(), None);
+        assert_eq!(policy.apply("abcdef".to_owned()).unwrap(), "abcdef");
+    }
+}
@@ -8,6 +8,7 @@ use crate::{
     branch::Branch,
     directory::{content::EntryExists, Directory},
     error::Result,
+    file::File,
     protocol::Bump,
     store::{Changeset, ReadTransaction},
     version_vector::VersionVector,
@@ -181,6 +182,16 @@ impl ParentContext {
         &self.entry_name
     }
 
+    /// BlobId of the parent directory of the entry.
+    pub(super) fn directory_id(&self) -> &BlobId {
+        &self.directory_id
+    }
+
+    /// Number of ancestors of the entry.
+    pub(super) fn depth(&self) -> usize {
+        1 + self.parent.as_deref().map(Self::depth).unwrap_or(0)
+    }
+
     /// Returns the version vector of this entry.
     pub async fn entry_version_vector(&self, branch: Branch) -> Result<VersionVector> {
         Ok(self
@@ -191,6 +202,13 @@ impl ParentContext {
             .clone())
     }
 
+    /// Opens the entry as a file.
+    pub async fn open_file(&self, branch: Branch) -> Result<File> {
+        let directory = self.open(branch).await?;
+        let entry = directory.lookup(&self.entry_name)?.file()?;
+        entry.open().await
+    }
+
     /// Opens the parent directory of this entry.
     pub async fn open(&self, branch: Branch) -> Result<Directory> {
         let mut tx = branch.store().begin_read().await?;
@@ -0,0 +1,88 @@
+use crate::{blob::BlobId, collections::HashMap, version_vector::VersionVector};
+use deadlock::BlockingMutex;
+use std::sync::Arc;
+
+// Maximum number of purged tombstones to remember. When exceeded, the oldest ones are forgotten
+// first.
+const MAX_ENTRIES: usize = 4096;
+
+/// Tombstones purged from the local branch (see `Directory::purge_tombstones`), keyed by the id of
+/// the local directory blob they were in and their name. Used to prevent the same tombstones still
+/// present in other branches from being merged back. Shared among all branches.
+#[derive(Clone, Default)]
+pub(crate) struct PurgedTombstones(Arc<BlockingMutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<(BlobId, String), (VersionVector, u64)>,
+    next_seq: u64,
+}
+
+impl PurgedTombstones {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers that the tombstone with the given name and version vector has been purged from
+    /// the directory with the given blob id.
+    pub fn insert(&self, blob_id: BlobId, name: String, version_vector: &VersionVector) {
+        let mut inner = self.0.lock().unwrap();
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+
+        let (vv, entry_seq) = inner
+            .entries
+            .entry((blob_id, name))
+            .or_insert_with(|| (VersionVector::new(), seq));
+        *vv += version_vector;
+        *entry_seq = seq;
+
+        if inner.entries.len() > MAX_ENTRIES {
+            // unwrap is ok because `entries` is non-empty.
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, seq))| *seq)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            inner.entries.remove(&oldest);
+        }
+    }
+
+    /// Checks whether a tombstone with the given name and version vector from the directory with
+    /// the given blob id is superseded by one that's been purged.
+    pub fn contains(&self, blob_id: &BlobId, name: &str, version_vector: &VersionVector) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .entries
+            .get(&(*blob_id, name.to_owned()))
+            .map(|(vv, _)| version_vector <= vv)
+            .unwrap_or(false)
+    }
+
+    /// Returns all the remembered tombstones, oldest first.
+    pub fn to_vec(&self) -> Vec<(BlobId, String, VersionVector)> {
+        let inner = self.0.lock().unwrap();
+        let mut entries: Vec<_> = inner.entries.iter().collect();
+        entries.sort_by_key(|(_, (_, seq))| *seq);
+        entries
+            .into_iter()
+            .map(|((blob_id, name), (vv, _))| (*blob_id, name.clone(), vv.clone()))
+            .collect()
+    }
+
+    /// Replaces the remembered tombstones with the given ones (in the order returned by
+    /// `to_vec`).
+    pub fn set(&self, entries: Vec<(BlobId, String, VersionVector)>) {
+        let mut inner = self.0.lock().unwrap();
+        inner.entries.clear();
+        inner.next_seq = 0;
+
+        for (blob_id, name, vv) in entries {
+            let seq = inner.next_seq;
+            inner.next_seq += 1;
+            inner.entries.insert((blob_id, name), (vv, seq));
+        }
+    }
+}
@@ -156,6 +156,7 @@ async fn rename_file() {
             &mut parent_dir_dst,
             dst_name,
             VersionVector::first(*branch.id()),
+            true,
         )
         .await
         .unwrap();
@@ -219,6 +220,7 @@ async fn move_file_within_branch() {
             &mut aux_dir,
             file_name,
             VersionVector::first(*branch.id()),
+            true,
         )
         .await
         .unwrap();
@@ -262,6 +264,7 @@ async fn move_file_within_branch() {
             &mut root_dir,
             file_name,
             tombstone_vv.incremented(*branch.id()),
+            true,
         )
         .await
         .unwrap();
@@ -323,6 +326,7 @@ async fn move_non_empty_directory() {
             &mut dst_dir,
             dir_name,
             VersionVector::first(*branch.id()),
+            true,
         )
         .await
         .unwrap();
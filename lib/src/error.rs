@@ -1,4 +1,4 @@
-use crate::{db, store};
+use crate::{db, storage_size::StorageSize, store};
 use std::{array::TryFromSliceError, fmt, io};
 use thiserror::Error;
 
@@ -9,9 +9,9 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub enum Error {
     // TODO: remove / merge with `Store`
     #[error("database error")]
-    Db(#[from] db::Error),
+    Db(#[source] db::Error),
     #[error("store error")]
-    Store(#[from] store::Error),
+    Store(#[source] store::Error),
     #[error("permission denied")]
     PermissionDenied,
     // TODO: remove
@@ -45,6 +45,25 @@ pub enum Error {
     StorageVersionMismatch,
     #[error("file or directory is locked")]
     Locked,
+    #[error("storage quota exceeded (limit: {limit}, actual: {actual})")]
+    QuotaExceeded {
+        limit: StorageSize,
+        actual: StorageSize,
+    },
+    #[error("file size limit exceeded")]
+    FileTooLarge,
+    #[error("invalid entry name")]
+    InvalidName,
+    #[error("entry name too long")]
+    NameTooLong,
+    /// The repository database is corrupted. Use `Repository::recover` to salvage what's left of
+    /// it.
+    #[error("repository database is corrupted")]
+    Corrupted(#[source] db::Error),
+    /// The maximum number of repositories registered in the network has been reached. See
+    /// `Network::set_max_registrations`.
+    #[error("too many registered repositories")]
+    RegistrationLimitExceeded,
 }
 
 impl Error {
@@ -55,6 +74,24 @@ impl Error {
     }
 }
 
+impl From<db::Error> for Error {
+    fn from(src: db::Error) -> Self {
+        match src {
+            src @ db::Error::Corrupted(_) => Self::Corrupted(src),
+            src => Self::Db(src),
+        }
+    }
+}
+
+impl From<store::Error> for Error {
+    fn from(src: store::Error) -> Self {
+        match src {
+            store::Error::QuotaExceeded { limit, actual } => Self::QuotaExceeded { limit, actual },
+            src => Self::Store(src),
+        }
+    }
+}
+
 impl From<TryFromSliceError> for Error {
     fn from(_: TryFromSliceError) -> Self {
         Self::MalformedData
@@ -1,7 +1,11 @@
 // Probably false positive triggered by `task_local`
 #![allow(clippy::declare_interior_mutable_const)]
 
-use crate::{crypto::sign::PublicKey, protocol::BlockId};
+use crate::{
+    crypto::{sign::PublicKey, Hash},
+    protocol::BlockId,
+    storage_size::StorageSize,
+};
 use core::fmt;
 use futures_util::{stream, Stream};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -14,6 +18,17 @@ pub enum Payload {
     BranchChanged(PublicKey),
     /// A block with the specified id was received from a remote replica.
     BlockReceived(BlockId),
+    /// A snapshot received from a remote replica was rejected because approving it would make the
+    /// repository exceed its storage quota. `actual` is the size the repository would have had.
+    QuotaExceeded {
+        limit: StorageSize,
+        actual: StorageSize,
+    },
+    /// Merging the specified snapshot (identified by its hash) of the specified remote branch into
+    /// the local branch failed repeatedly. The local branch won't absorb any changes from that
+    /// branch until it advances to a different snapshot or the snapshot is skipped with
+    /// `Repository::skip_merge`.
+    MergeStalled { branch_id: PublicKey, hash: Hash },
     /// The `maintain` worker job successfully completed. It won't perform any more work until
     /// triggered again by any of the above events.
     /// This event is useful mostly for diagnostics or testing and can be safely ignored in other
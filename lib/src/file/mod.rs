@@ -1,16 +1,26 @@
 mod progress_cache;
+mod range;
+mod size_limit;
+mod tail;
 
 pub(crate) use progress_cache::FileProgressCache;
+pub(crate) use range::BlockWaiter;
+pub use range::{FileRange, MissingBlockPolicy};
+pub(crate) use size_limit::MaxFileSizeSetting;
+
+use self::tail::Tail;
 
 use crate::{
-    blob::{lock::UpgradableLock, Blob, ReadWriteError},
+    blob::{lock::UpgradableLock, Blob, BlockIds, ReadWriteError, HEADER_SIZE},
     branch::Branch,
+    crypto::Hash,
     directory::{Directory, ParentContext},
     error::{Error, Result},
-    protocol::{Bump, Locator, BLOCK_SIZE},
+    protocol::{BlockId, Bump, Locator, SingleBlockPresence, BLOCK_SIZE},
     store::{Changeset, ReadTransaction},
     version_vector::VersionVector,
 };
+use futures_util::{stream, Stream};
 use std::{fmt, future::Future, io::SeekFrom};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
@@ -33,7 +43,9 @@ impl File {
         let mut tx = branch.store().begin_read().await?;
 
         Ok(Self {
-            blob: Blob::open(&mut tx, branch, *locator.blob_id()).await?,
+            blob: Blob::open(&mut tx, branch, *locator.blob_id())
+                .await?
+                .limit_size(),
             parent,
             lock,
         })
@@ -53,7 +65,7 @@ impl File {
         let lock = UpgradableLock::Read(lock);
 
         Self {
-            blob: Blob::create(branch, *locator.blob_id()),
+            blob: Blob::create(branch, *locator.blob_id()).limit_size(),
             parent,
             lock,
         }
@@ -107,7 +119,68 @@ impl File {
         }
     }
 
+    /// Stream of the blocks backing this file, in order. Each item is the offset in the file (in
+    /// bytes) of the start of the block's data, the block id and whether the block is available
+    /// locally. The first block also contains the blob header so the offset of the block `n > 0`
+    /// is `n * BLOCK_SIZE - HEADER_SIZE`.
+    ///
+    /// The blocks are read from the latest snapshot at the time the stream is first polled, so
+    /// any modifications of this file not yet flushed are not reflected.
+    /// NOTE: Like with `progress`, the returned stream doesn't borrow from `self`.
+    pub fn block_ids(&self) -> impl Stream<Item = Result<(u64, BlockId, SingleBlockPresence)>> {
+        let branch = self.branch().clone();
+        let blob_id = *self.blob.id();
+
+        stream::try_unfold(
+            (None, 0),
+            move |(block_ids, number): (Option<BlockIds>, u64)| {
+                let branch = branch.clone();
+
+                async move {
+                    let mut block_ids = match block_ids {
+                        Some(block_ids) => block_ids,
+                        None => BlockIds::open(branch, blob_id).await?,
+                    };
+
+                    let Some(node) = block_ids.try_next_leaf_node().await? else {
+                        return Ok(None);
+                    };
+
+                    let offset = (number * BLOCK_SIZE as u64).saturating_sub(HEADER_SIZE as u64);
+
+                    Ok::<_, Error>(Some((
+                        (offset, node.block_id, node.block_presence),
+                        (Some(block_ids), number + 1),
+                    )))
+                }
+            },
+        )
+    }
+
+    /// Stream of the data appended to this file, starting from its length as of the last flush.
+    /// Useful for following a log file that is being appended to, possibly by other replicas.
+    ///
+    /// The stream follows the version of this file in its branch: the new data is yielded once it's
+    /// been flushed to that branch (or, if it's a remote branch, once its snapshot and the blocks
+    /// of the new data have been received). If the file gets forked to another branch and then
+    /// appended to there (e.g., a remote file written to locally), the stream switches to
+    /// following the fork. Concurrent versions that don't descend from the followed one are
+    /// ignored.
+    ///
+    /// The stream ends when the file is removed, replaced with a different file or truncated to
+    /// less than what has already been yielded. Modifications to the already yielded part of the
+    /// file that don't shrink it are not detected.
+    /// NOTE: Like with `progress`, the returned stream doesn't borrow from `self`.
+    pub fn tail(&self) -> impl Stream<Item = Result<Vec<u8>>> {
+        stream::try_unfold(Tail::new(self), |mut tail| async move {
+            Ok::<_, Error>(tail.next().await?.map(|chunk| (chunk, tail)))
+        })
+    }
+
     /// Reads data from this file. Returns the number of bytes actually read.
+    ///
+    /// Cancel safe: if the returned future is dropped before completing, no data has been read and
+    /// the seek position is unchanged.
     pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
         loop {
             match self.blob.read(buffer) {
@@ -116,13 +189,23 @@ impl File {
                     let mut tx = self.branch().store().begin_read().await?;
                     self.blob.warmup(&mut tx).await?;
                 }
+                Err(ReadWriteError::NoBuffer) => {
+                    self.blob.wait_for_buffer().await;
+                }
                 Err(ReadWriteError::CacheFull) => {
                     self.flush().await?;
                 }
+                Err(ReadWriteError::TooLarge) => unreachable!("reading never extends the file"),
             }
         }
     }
 
+    /// Id of the block at the current seek position.
+    pub(crate) async fn current_block_id(&self) -> Result<BlockId> {
+        let mut tx = self.branch().store().begin_read().await?;
+        self.blob.current_block_id(&mut tx).await
+    }
+
     pub async fn read_all(&mut self, buffer: &mut [u8]) -> Result<usize> {
         let mut offset = 0;
 
@@ -149,7 +232,38 @@ impl File {
         Ok(buffer)
     }
 
+    /// Computes the digest of the whole content of this file (regardless of the current seek
+    /// position). Files with the same content have the same digest even if they are in different
+    /// branches or repositories. The content is read in chunks so this works for files of any
+    /// size. Leaves the seek position at the end of the file.
+    pub async fn content_digest(&mut self) -> Result<Hash> {
+        self.seek(SeekFrom::Start(0));
+
+        let mut hasher = blake3::Hasher::new();
+        let mut buffer = vec![0; BLOCK_SIZE];
+
+        loop {
+            let len = self.read(&mut buffer).await?;
+
+            if len == 0 {
+                break;
+            }
+
+            hasher.update(&buffer[..len]);
+        }
+
+        Ok(Hash::from(*hasher.finalize().as_bytes()))
+    }
+
     /// Writes `buffer` into this file. Returns the number of bytes actually written.
+    ///
+    /// Fails with `FileTooLarge` if writing anything would grow the file past the maximum file size
+    /// of the repository. Writes only up to that size (or up to the current length of a file that
+    /// is already larger) otherwise.
+    ///
+    /// Cancel safe: if the returned future is dropped before completing, no data has been written
+    /// and the seek position is unchanged. If the write had to flush the file first to make room
+    /// for the new data, that flush is cancel safe as well (see `flush`).
     pub async fn write(&mut self, buffer: &[u8]) -> Result<usize> {
         self.acquire_write_lock()?;
 
@@ -160,14 +274,29 @@ impl File {
                     let mut tx = self.branch().store().begin_read().await?;
                     self.blob.warmup(&mut tx).await?;
                 }
+                Err(ReadWriteError::NoBuffer) => {
+                    self.blob.wait_for_buffer().await;
+                }
                 Err(ReadWriteError::CacheFull) => {
                     self.flush().await?;
                 }
+                Err(ReadWriteError::TooLarge) => return Err(Error::FileTooLarge),
             }
         }
     }
 
+    /// Writes the whole `buffer` into this file. Fails with `FileTooLarge` without writing anything
+    /// if it would grow the file past the maximum file size of the repository.
+    ///
+    /// Not cancel safe: if the returned future is dropped before completing, only a part of the
+    /// buffer might have been written. Use `write` to track the progress.
     pub async fn write_all(&mut self, buffer: &[u8]) -> Result<()> {
+        self.check_len(
+            self.blob
+                .seek_position()
+                .saturating_add(buffer.len() as u64),
+        )?;
+
         let mut offset = 0;
 
         loop {
@@ -191,8 +320,41 @@ impl File {
         self.blob.truncate(len)
     }
 
+    /// Sets the length of the file. If `len` is less than the current length, the file is
+    /// truncated. If it's greater, the file is extended with zeros. The seek position is preserved
+    /// unless it would be past the new end of the file.
+    ///
+    /// Extending the file doesn't allocate buffer proportional to the added size and nothing is
+    /// saved until the next `flush`, which then saves the whole change atomically. So growing even
+    /// by several gigabytes uses only a bounded amount of memory and never leaves the file
+    /// partially extended.
+    pub async fn set_len(&mut self, len: u64) -> Result<()> {
+        self.acquire_write_lock()?;
+
+        if len <= self.len() {
+            return self.blob.truncate(len);
+        }
+
+        self.check_len(len)?;
+
+        let position = self.blob.seek_position();
+        let result = self.grow(len).await;
+        self.blob.seek(SeekFrom::Start(position));
+
+        result
+    }
+
     /// Atomically saves any pending modifications and updates the version vectors of this file and
     /// all its ancestors.
+    ///
+    /// Cancel safe: if the returned future is dropped before completing, the file stays dirty so
+    /// the pending modifications are saved by the next flush. If it's dropped while the transaction
+    /// is already being committed, the commit still completes in the background, in which case the
+    /// next flush saves the same content again (with another version vector increment).
+    ///
+    /// Note that dropping any of the futures of this file doesn't release the lock on the file.
+    /// That happens only when the `File` itself is dropped (which discards any unflushed
+    /// modifications).
     pub async fn flush(&mut self) -> Result<()> {
         if !self.blob.is_dirty() {
             return Ok(());
@@ -201,7 +363,7 @@ impl File {
         let mut tx = self.branch().store().begin_write().await?;
         let mut changeset = Changeset::new();
 
-        self.blob.flush(&mut tx, &mut changeset).await?;
+        self.blob.prepare_flush(&mut tx, &mut changeset).await?;
         self.parent
             .bump(
                 &mut tx,
@@ -225,6 +387,8 @@ impl File {
         let event_tx = self.branch().notify();
         tx.commit_and_then(move || event_tx.send()).await?;
 
+        self.blob.mark_flushed();
+
         Ok(())
     }
 
@@ -273,7 +437,9 @@ impl File {
 
         let blob = {
             let mut tx = dst_branch.store().begin_read().await?;
-            Blob::open(&mut tx, dst_branch, *self.blob.id()).await?
+            Blob::open(&mut tx, dst_branch, *self.blob.id())
+                .await?
+                .limit_size()
         };
 
         *self = Self { blob, parent, lock };
@@ -293,9 +459,36 @@ impl File {
         self.blob.id()
     }
 
+    async fn grow(&mut self, len: u64) -> Result<()> {
+        loop {
+            match self.blob.grow(len) {
+                Ok(()) => return Ok(()),
+                Err(ReadWriteError::CacheMiss) => {
+                    let mut tx = self.branch().store().begin_read().await?;
+                    self.blob.warmup(&mut tx).await?;
+                }
+                Err(ReadWriteError::NoBuffer) => {
+                    self.blob.wait_for_buffer().await;
+                }
+                // `grow` exceeds the cache capacity rather than failing with this.
+                Err(ReadWriteError::CacheFull) => unreachable!(),
+                Err(ReadWriteError::TooLarge) => return Err(Error::FileTooLarge),
+            }
+        }
+    }
+
     fn acquire_write_lock(&mut self) -> Result<()> {
         self.lock.upgrade().then_some(()).ok_or(Error::Locked)
     }
+
+    // Checks that the file can be made `len` bytes long without exceeding the max file size. Files
+    // already larger than that can still be rewritten or shrunk, just not grown any further.
+    fn check_len(&self, len: u64) -> Result<()> {
+        match self.branch().max_file_size().get() {
+            Some(max) if len > max && len > self.len() => Err(Error::FileTooLarge),
+            Some(_) | None => Ok(()),
+        }
+    }
 }
 
 impl fmt::Debug for File {
@@ -437,6 +630,304 @@ mod tests {
         assert_matches!(file1.truncate(0), Err(Error::Locked));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn set_len() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let content = vec![0xaa; 2 * BLOCK_SIZE];
+
+        let mut file = branch.ensure_file_exists("cat.img".into()).await.unwrap();
+        file.write_all(&content).await.unwrap();
+        file.flush().await.unwrap();
+
+        // Shrink
+        file.set_len(BLOCK_SIZE as u64 / 2).await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(file.len(), BLOCK_SIZE as u64 / 2);
+
+        // Grow past the original length. The previously truncated data must not reappear.
+        let new_len = 3 * BLOCK_SIZE + 7;
+
+        file.seek(SeekFrom::Start(1));
+        file.set_len(new_len as u64).await.unwrap();
+        assert_eq!(file.len(), new_len as u64);
+        assert_eq!(file.seek(SeekFrom::Current(0)), 1);
+
+        file.flush().await.unwrap();
+
+        file.seek(SeekFrom::Start(0));
+        let actual = file.read_to_end().await.unwrap();
+        assert_eq!(actual.len(), new_len);
+        assert_eq!(actual[..BLOCK_SIZE / 2], content[..BLOCK_SIZE / 2]);
+        assert!(actual[BLOCK_SIZE / 2..].iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn set_len_large() {
+        let (_base_dir, [branch]) = setup().await;
+        branch.buffer_pool().set_capacity(2);
+
+        let mut file = branch.ensure_file_exists("disk.img".into()).await.unwrap();
+        file.write_all(&[0xaa; 10]).await.unwrap();
+        file.flush().await.unwrap();
+
+        let vv = branch.version_vector().await.unwrap();
+
+        // Growing by much more than the buffer pool capacity neither uses more buffers nor saves
+        // anything until flushed.
+        let new_len = 64 * BLOCK_SIZE as u64 + 3;
+        file.set_len(new_len).await.unwrap();
+        assert!(branch.buffer_pool().in_use() <= 2);
+        assert_eq!(branch.version_vector().await.unwrap(), vv);
+
+        file.flush().await.unwrap();
+        assert!(branch.version_vector().await.unwrap() > vv);
+        drop(file);
+
+        let mut file = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("disk.img")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+        assert_eq!(file.len(), new_len);
+
+        let content = file.read_to_end().await.unwrap();
+        assert_eq!(content[..10], [0xaa; 10]);
+        assert!(content[10..].iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn block_ids() {
+        use futures_util::TryStreamExt;
+
+        let (_base_dir, [branch]) = setup().await;
+
+        let content: Vec<u8> = (0..2 * BLOCK_SIZE + 10).map(|_| rand::random()).collect();
+
+        let mut file = branch.ensure_file_exists("cat.jpg".into()).await.unwrap();
+        file.write_all(&content).await.unwrap();
+        file.flush().await.unwrap();
+
+        let blocks: Vec<_> = file.block_ids().try_collect().await.unwrap();
+        assert_eq!(blocks.len(), file.blob.block_count() as usize);
+
+        let offsets: Vec<_> = blocks.iter().map(|(offset, _, _)| *offset).collect();
+        assert_eq!(
+            offsets,
+            [
+                0,
+                (BLOCK_SIZE - HEADER_SIZE) as u64,
+                (2 * BLOCK_SIZE - HEADER_SIZE) as u64
+            ]
+        );
+
+        for (index, (offset, block_id, presence)) in blocks.iter().enumerate() {
+            assert_eq!(*presence, SingleBlockPresence::Present);
+
+            file.seek(SeekFrom::Start(*offset));
+            assert_eq!(file.current_block_id().await.unwrap(), *block_id, "{index}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancel_flush() {
+        use futures_util::poll;
+        use std::pin::pin;
+        use tokio::task;
+
+        let (_base_dir, [branch]) = setup().await;
+
+        let mut file = branch.ensure_file_exists("log.txt".into()).await.unwrap();
+        let mut expected = Vec::new();
+
+        for polls in 0..32 {
+            let chunk = format!("line {polls}\n");
+            file.write_all(chunk.as_bytes()).await.unwrap();
+            expected.extend_from_slice(chunk.as_bytes());
+
+            // Cancel the flush at various points of its progress.
+            let mut flush = pin!(file.flush());
+
+            for _ in 0..polls {
+                if poll!(flush.as_mut()).is_ready() {
+                    break;
+                }
+
+                task::yield_now().await;
+            }
+        }
+
+        file.flush().await.unwrap();
+        drop(file);
+
+        let mut file = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("log.txt")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+
+        assert_eq!(file.read_to_end().await.unwrap(), expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancel_read() {
+        use futures_util::poll;
+        use std::{pin::pin, task::Poll};
+
+        let (_base_dir, [branch]) = setup().await;
+
+        let content: Vec<u8> = (0..4 * BLOCK_SIZE).map(|_| rand::random()).collect();
+
+        let mut file = branch.ensure_file_exists("cat.jpg".into()).await.unwrap();
+        file.write_all(&content).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+
+        let mut file = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("cat.jpg")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+
+        let mut actual = Vec::new();
+        let mut buffer = vec![0; BLOCK_SIZE / 3];
+
+        loop {
+            let position = file.blob.seek_position();
+
+            // Start a read and cancel it before it completes (unless it completes right away).
+            let len = {
+                let mut read = pin!(file.read(&mut buffer));
+
+                match poll!(read.as_mut()) {
+                    Poll::Ready(result) => Some(result.unwrap()),
+                    Poll::Pending => None,
+                }
+            };
+
+            let len = match len {
+                Some(len) => len,
+                None => {
+                    assert_eq!(file.blob.seek_position(), position);
+                    file.read(&mut buffer).await.unwrap()
+                }
+            };
+
+            if len == 0 {
+                break;
+            }
+
+            actual.extend_from_slice(&buffer[..len]);
+        }
+
+        assert_eq!(actual, content);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tail() {
+        use futures_util::TryStreamExt;
+        use std::pin::pin;
+
+        let (_base_dir, [branch]) = setup().await;
+
+        let mut file = branch.ensure_file_exists("log.txt".into()).await.unwrap();
+        file.write_all(b"one\n").await.unwrap();
+        file.flush().await.unwrap();
+
+        let mut tail = pin!(file.tail());
+
+        file.write_all(b"two\n").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), b"two\n");
+
+        file.write_all(b"three\n").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), b"three\n");
+
+        // Truncation ends the stream.
+        file.set_len(4).await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tail_starts_at_committed_len() {
+        use futures_util::TryStreamExt;
+        use std::pin::pin;
+
+        let (_base_dir, [branch]) = setup().await;
+
+        let mut file = branch.ensure_file_exists("log.txt".into()).await.unwrap();
+        file.write_all(b"one\n").await.unwrap();
+        file.flush().await.unwrap();
+
+        // Not flushed yet so it's not part of the initial length.
+        file.write_all(b"two\n").await.unwrap();
+
+        let mut tail = pin!(file.tail());
+
+        file.flush().await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), b"two\n");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tail_follows_fork() {
+        use futures_util::TryStreamExt;
+        use std::pin::pin;
+
+        let (_base_dir, [branch0, branch1]) = setup().await;
+
+        let mut file0 = branch0.ensure_file_exists("log.txt".into()).await.unwrap();
+        file0.write_all(b"one\n").await.unwrap();
+        file0.flush().await.unwrap();
+
+        let mut tail = pin!(file0.tail());
+        drop(file0);
+
+        // Fork the file into branch 1 and append to it there.
+        let mut file1 = branch0
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("log.txt")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+
+        file1.fork(branch1.clone()).await.unwrap();
+        file1.seek(SeekFrom::End(0));
+        file1.write_all(b"two\n").await.unwrap();
+        file1.flush().await.unwrap();
+
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), b"two\n");
+
+        file1.write_all(b"three\n").await.unwrap();
+        file1.flush().await.unwrap();
+
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), b"three\n");
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn copy_to_writer() {
         use tokio::{fs, io::AsyncReadExt};
@@ -0,0 +1,198 @@
+use super::File;
+use crate::{
+    block_tracker::BlockTracker,
+    error::{Error, Result},
+    event::{Event, Payload},
+    protocol::BLOCK_SIZE,
+    store,
+};
+use futures_util::{future::BoxFuture, FutureExt};
+use std::{
+    fmt,
+    io::{self, SeekFrom},
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    sync::broadcast::{self, error::RecvError},
+};
+
+/// What to do when reading a block that is not yet available locally.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MissingBlockPolicy {
+    /// Fail the read immediately. Useful when offline.
+    Fail,
+    /// Request the block from the peers and wait until it's received. Note this might wait
+    /// forever if no peer has the block, so the caller should consider applying a timeout.
+    Wait,
+}
+
+/// `AsyncRead` over a byte range of a file. Created with `Repository::open_range`.
+///
+/// Yields exactly the bytes of the range (or fewer if the range extends past the end of the file)
+/// and then reaches EOF. Only the blocks of the file that overlap the range are read.
+pub struct FileRange {
+    state: State,
+    // Data read from the file but not yet consumed by the caller.
+    chunk: Vec<u8>,
+    chunk_offset: usize,
+}
+
+impl FileRange {
+    pub(crate) fn new(
+        mut file: File,
+        start: u64,
+        end: u64,
+        waiter: Option<BlockWaiter>,
+    ) -> Result<Self> {
+        if start > end {
+            return Err(Error::InvalidArgument);
+        }
+
+        let start = file.seek(SeekFrom::Start(start));
+        let remaining = end.min(file.len()).saturating_sub(start);
+
+        Ok(Self {
+            state: State::Idle(Box::new(Reader {
+                file,
+                remaining,
+                waiter,
+            })),
+            chunk: Vec::new(),
+            chunk_offset: 0,
+        })
+    }
+}
+
+impl AsyncRead for FileRange {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.chunk_offset < this.chunk.len() {
+                let len = buf.remaining().min(this.chunk.len() - this.chunk_offset);
+                buf.put_slice(&this.chunk[this.chunk_offset..this.chunk_offset + len]);
+                this.chunk_offset += len;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            match mem::replace(&mut this.state, State::Done) {
+                State::Idle(mut reader) => {
+                    if reader.remaining == 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+
+                    this.state = State::Busy(
+                        async move {
+                            let result = reader.read_chunk().await;
+                            (reader, result)
+                        }
+                        .boxed(),
+                    );
+                }
+                State::Busy(mut future) => {
+                    let Poll::Ready((reader, result)) = future.poll_unpin(cx) else {
+                        this.state = State::Busy(future);
+                        return Poll::Pending;
+                    };
+
+                    this.state = State::Idle(reader);
+                    this.chunk =
+                        result.map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+                    this.chunk_offset = 0;
+
+                    if this.chunk.is_empty() {
+                        // The file is shorter than expected (truncated by a concurrent write).
+                        this.state = State::Done;
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                State::Done => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FileRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileRange").finish_non_exhaustive()
+    }
+}
+
+enum State {
+    Idle(Box<Reader>),
+    Busy(BoxFuture<'static, (Box<Reader>, Result<Vec<u8>>)>),
+    Done,
+}
+
+struct Reader {
+    file: File,
+    remaining: u64,
+    waiter: Option<BlockWaiter>,
+}
+
+impl Reader {
+    // Reads the next chunk of at most `BLOCK_SIZE` bytes. Returns an empty chunk on EOF.
+    async fn read_chunk(&mut self) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; self.remaining.min(BLOCK_SIZE as u64) as usize];
+        let mut offset = 0;
+
+        while offset < buffer.len() {
+            match self.file.read(&mut buffer[offset..]).await {
+                Ok(0) => break,
+                Ok(len) => offset += len,
+                // Return what we have already read, the next call will hit the error again.
+                Err(Error::Store(store::Error::BlockNotFound)) if offset > 0 => break,
+                Err(error @ Error::Store(store::Error::BlockNotFound)) => {
+                    let Some(waiter) = &mut self.waiter else {
+                        return Err(error);
+                    };
+
+                    waiter.tracker.require(self.file.current_block_id().await?);
+                    waiter.wait().await.map_err(|_| error)?;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        buffer.truncate(offset);
+        self.remaining -= offset as u64;
+
+        Ok(buffer)
+    }
+}
+
+/// Waits for missing blocks to be received.
+pub(crate) struct BlockWaiter {
+    tracker: BlockTracker,
+    // Subscribed before the first read so no received block can be missed.
+    event_rx: broadcast::Receiver<Event>,
+}
+
+impl BlockWaiter {
+    pub fn new(tracker: BlockTracker, event_rx: broadcast::Receiver<Event>) -> Self {
+        Self { tracker, event_rx }
+    }
+
+    // Waits until any block is received. Fails if the repository has been closed.
+    async fn wait(&mut self) -> Result<(), RecvError> {
+        loop {
+            match self.event_rx.recv().await {
+                Ok(Event {
+                    payload: Payload::BlockReceived(_),
+                    ..
+                })
+                | Err(RecvError::Lagged(_)) => return Ok(()),
+                Ok(_) => continue,
+                Err(error @ RecvError::Closed) => return Err(error),
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+const UNLIMITED: u64 = u64::MAX;
+
+/// Maximum size of files in bytes. Shared among all branches of a repository. Unlimited by
+/// default.
+#[derive(Clone)]
+pub(crate) struct MaxFileSizeSetting(Arc<AtomicU64>);
+
+impl MaxFileSizeSetting {
+    pub fn new() -> Self {
+        Self(Arc::new(AtomicU64::new(UNLIMITED)))
+    }
+
+    pub fn set(&self, max: Option<u64>) {
+        self.0.store(max.unwrap_or(UNLIMITED), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<u64> {
+        match self.0.load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            max => Some(max),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use super::File;
+use crate::{
+    blob::BlobId,
+    branch::Branch,
+    crypto::sign::PublicKey,
+    directory::ParentContext,
+    error::{Error, Result},
+    event::{Event, Payload},
+    store,
+};
+use futures_util::TryStreamExt;
+use std::io::SeekFrom;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// State of `File::tail`.
+pub(super) struct Tail {
+    branch: Branch,
+    parent: ParentContext,
+    blob_id: BlobId,
+    offset: u64,
+    rx: broadcast::Receiver<Event>,
+    // Whether the last read failed because some of the appended blocks haven't been received yet.
+    blocks_pending: bool,
+}
+
+impl Tail {
+    pub fn new(file: &File) -> Self {
+        Self {
+            branch: file.branch().clone(),
+            parent: file.parent.clone(),
+            blob_id: *file.blob.id(),
+            // Not-yet-flushed writes of `file` are yielded too, once they are flushed.
+            offset: file.blob.committed_len(),
+            rx: file.branch().subscribe(),
+            blocks_pending: false,
+        }
+    }
+
+    /// Waits until the file grows and returns the newly appended data. Returns `None` once the
+    /// file has been removed, replaced with a different file or truncated below the data already
+    /// returned.
+    pub async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            match self.wait().await {
+                Some(Changed::Branch(branch_id)) if branch_id != *self.branch.id() => {
+                    if !self.follow_fork(branch_id).await? {
+                        continue;
+                    }
+                }
+                Some(Changed::Branch(_)) => (),
+                Some(Changed::Unknown) => self.follow_any_fork().await?,
+                None => return Ok(None),
+            }
+
+            match self.read().await {
+                Ok(Some(chunk)) if chunk.is_empty() => continue,
+                Ok(chunk) => {
+                    self.blocks_pending = false;
+                    return Ok(chunk);
+                }
+                Err(Error::Store(store::Error::BlockNotFound)) => {
+                    // The snapshot has been received but some of its blocks not yet. Try again
+                    // when they arrive.
+                    self.blocks_pending = true;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    // Waits for an event that might indicate the file has changed. Returns `None` if no more events
+    // will be received.
+    async fn wait(&mut self) -> Option<Changed> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => match event.payload {
+                    Payload::BranchChanged(branch_id) => return Some(Changed::Branch(branch_id)),
+                    // The missing blocks might belong to the followed version or to a fork of it.
+                    Payload::BlockReceived(_) if self.blocks_pending => {
+                        return Some(Changed::Unknown)
+                    }
+                    _ => continue,
+                },
+                // Some events were missed, one of them might have been relevant.
+                Err(RecvError::Lagged(_)) => return Some(Changed::Unknown),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    async fn follow_any_fork(&mut self) -> Result<()> {
+        let branch_ids: Vec<_> = self
+            .branch
+            .store()
+            .acquire_read()
+            .await?
+            .load_writer_ids()
+            .try_collect()
+            .await?;
+
+        for branch_id in branch_ids {
+            self.follow_fork(branch_id).await?;
+        }
+
+        Ok(())
+    }
+
+    // Starts following the version of the file in the given branch if it's a fork of the currently
+    // followed version, that is, if it's the same file and its version happened after the current
+    // one. This happens when the file is appended to through a different branch (e.g., a remote
+    // file being written to locally). Returns whether the followed branch changed.
+    async fn follow_fork(&mut self, branch_id: PublicKey) -> Result<bool> {
+        if branch_id == *self.branch.id() {
+            return Ok(false);
+        }
+
+        let branch = self.branch.sibling(branch_id);
+
+        let fork = match self.parent.open_file(branch.clone()).await {
+            Ok(file) if *file.blob.id() == self.blob_id => file,
+            Ok(_) => return Ok(false),
+            Err(
+                Error::EntryNotFound
+                | Error::EntryIsDirectory
+                | Error::Store(store::Error::BranchNotFound | store::Error::LocatorNotFound),
+            ) => return Ok(false),
+            Err(Error::Store(store::Error::BlockNotFound)) => {
+                // Retry once the blocks arrive.
+                self.blocks_pending = true;
+                return Ok(false);
+            }
+            Err(error) => return Err(error),
+        };
+
+        let fork_vv = fork.version_vector().await?;
+
+        let is_newer = match self.parent.entry_version_vector(self.branch.clone()).await {
+            Ok(vv) => fork_vv > vv,
+            // The followed version is gone (e.g., its branch has been pruned after it was merged
+            // into the fork).
+            Err(
+                Error::EntryNotFound
+                | Error::Store(store::Error::BranchNotFound | store::Error::LocatorNotFound),
+            ) => true,
+            Err(error) => return Err(error),
+        };
+
+        if is_newer {
+            self.branch = branch;
+        }
+
+        Ok(is_newer)
+    }
+
+    async fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut file = match self.parent.open_file(self.branch.clone()).await {
+            Ok(file) => file,
+            Err(
+                Error::EntryNotFound
+                | Error::EntryIsDirectory
+                | Error::Store(store::Error::BranchNotFound),
+            ) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        // `file` is freshly opened so its length is the committed one.
+        if *file.blob.id() != self.blob_id || file.len() < self.offset {
+            return Ok(None);
+        }
+
+        file.seek(SeekFrom::Start(self.offset));
+        let chunk = file.read_to_end().await?;
+        self.offset += chunk.len() as u64;
+
+        Ok(Some(chunk))
+    }
+}
+
+enum Changed {
+    // The given branch changed.
+    Branch(PublicKey),
+    // Something changed, possibly in any branch.
+    Unknown,
+}
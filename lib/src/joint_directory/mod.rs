@@ -1,7 +1,13 @@
+mod type_conflict;
+
 #[cfg(test)]
 mod tests;
 
+pub use self::type_conflict::TypeConflictPolicy;
+pub(crate) use self::type_conflict::TypeConflictSetting;
+
 use crate::{
+    blob::BlobId,
     branch::Branch,
     conflict,
     crypto::sign::PublicKey,
@@ -64,6 +70,11 @@ impl JointDirectory {
             .and_then(|branch| self.versions.get_mut(branch.id()))
     }
 
+    /// Returns all versions of this directory, including the local one (if any).
+    pub(crate) fn versions(&self) -> impl Iterator<Item = &Directory> {
+        self.versions.values()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.entries().next().is_none()
     }
@@ -71,13 +82,48 @@ impl JointDirectory {
     /// Returns iterator over the entries of this directory. Multiple concurrent versions of the
     /// same file are returned as separate `JointEntryRef::File` entries. Multiple concurrent
     /// versions of the same directory are returned as a single `JointEntryRef::Directory` entry.
+    /// Files and directories with the same name are returned according to the
+    /// `TypeConflictPolicy` of the repository.
     pub fn entries(&self) -> impl Iterator<Item = JointEntryRef> {
+        let policy = self.type_conflict_policy();
+
         self.merge_entries()
-            .flat_map(|(_, merge)| merge.ignore_tombstones())
+            .flat_map(move |(_, merge)| merge.ignore_tombstones().resolve(policy))
+    }
+
+    /// Returns the entries of at most `limit` distinct names ordered strictly after `after` (or
+    /// from the beginning if it's `None`), in the same order as [`Self::entries`]. Concurrent
+    /// versions of the same file count as a single name so they are never split between pages.
+    ///
+    /// To list the next page, pass the name (not the unique name) of the last returned entry as
+    /// `after`. Only the entries after it are merged, so listing a huge directory page by page
+    /// merges every entry only once. The listing resumes correctly even if the directory changes
+    /// between the calls.
+    pub fn entries_after<'a>(
+        &'a self,
+        after: Option<&'a str>,
+        limit: usize,
+    ) -> impl Iterator<Item = JointEntryRef<'a>> + 'a {
+        let policy = self.type_conflict_policy();
+
+        self.merge_entries_after(after)
+            .filter(|(_, merge)| matches!(merge, Merge::Existing(_)))
+            .take(limit)
+            .flat_map(move |(_, merge)| merge.ignore_tombstones().resolve(policy))
     }
 
     fn merge_entries(&self) -> impl Iterator<Item = (&str, Merge)> {
-        let entries = self.versions.values().map(|directory| directory.entries());
+        self.merge_entries_after(None)
+    }
+
+    fn merge_entries_after<'a>(
+        &'a self,
+        after: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a str, Merge<'a>)> + 'a {
+        let entries = self
+            .versions
+            .values()
+            .map(move |directory| directory.entries_after(after));
         let entries = SortedUnion::new(entries, |entry| entry.name());
         let entries = Accumulate::new(entries, |entry| entry.name());
         entries.map(|(name, entries)| {
@@ -98,6 +144,7 @@ impl JointDirectory {
             self.local_branch.as_ref(),
         )
         .ignore_tombstones()
+        .resolve(self.type_conflict_policy())
     }
 
     /// Looks up single entry with the specified name if it is unique.
@@ -107,13 +154,18 @@ impl JointDirectory {
     ///   returned. To lookup a single version, include a disambiguator in the `name`.
     /// - If there are multiple versiond and all of them are directories, they are merged into a
     ///   single `JointEntryRef::Directory` and returned.
-    /// - Finally, if there are both files and directories, only the directories are retured (merged
-    ///   into a `JointEntryRef::Directory`) and the files are discarded. This is so it's possible
-    ///   to unambiguously lookup a directory even in the presence of conflicting files.
+    /// - Finally, if there are both files and directories, it depends on the `TypeConflictPolicy`
+    ///   of the repository: with `PreferDirectory` the (merged) directory is returned, with
+    ///   `PreferFile` it's the same as if there were only the files and with `KeepBoth` (the
+    ///   default) an `AmbiguousEntry` error is returned and the entries need to be looked up by
+    ///   their unique names.
     pub fn lookup_unique<'a>(&'a self, name: &'a str) -> Result<JointEntryRef<'a>> {
+        let policy = self.type_conflict_policy();
+
         // First try exact match as it is more common.
-        let mut entries =
-            Merge::new(self.entry_versions(name), self.local_branch.as_ref()).ignore_tombstones();
+        let mut entries = Merge::new(self.entry_versions(name), self.local_branch.as_ref())
+            .ignore_tombstones()
+            .resolve(policy);
         if let Some(entry) = entries.next() {
             if entries.next().is_none() {
                 return Ok(entry);
@@ -129,6 +181,7 @@ impl JointDirectory {
 
         let mut entries = Merge::new(self.entry_versions(name), self.local_branch.as_ref())
             .ignore_tombstones()
+            .resolve(policy)
             .filter(|entry| entry.first_branch().id().starts_with(&branch_id_prefix));
 
         let first = entries.next().ok_or(Error::EntryNotFound)?;
@@ -275,6 +328,7 @@ impl JointDirectory {
 
         let mut conflict = false;
         let mut check_for_removal = Vec::new();
+        let mut check_for_identical = Vec::new();
 
         for (name, merge) in self.merge_entries() {
             match merge {
@@ -286,10 +340,15 @@ impl JointDirectory {
                                     Ok(()) => {}
                                     Err(Error::EntryExists) => {
                                         // This error indicates the local and the remote files are in conflict and
-                                        // so can't be automatically merged. We still proceed with merging the
-                                        // remaining entries but we won't mark this directory as merged (by bumping its
-                                        // vv) to prevent the conflicting remote file from being collected.
-                                        conflict = true;
+                                        // so can't be automatically merged, unless they have the same content (see
+                                        // below). We still proceed with merging the remaining entries but we won't
+                                        // mark this directory as merged (by bumping its vv) to prevent the
+                                        // conflicting remote file from being collected.
+                                        check_for_identical.push((
+                                            name.to_owned(),
+                                            *entry.inner().blob_id(),
+                                            entry.version_vector().clone(),
+                                        ));
                                     }
                                     Err(error) => return Err(error),
                                 }
@@ -331,6 +390,35 @@ impl JointDirectory {
             local_version.create_tombstone(&name, tombstone).await?;
         }
 
+        // Concurrent versions with identical content (e.g., two replicas saving the same bytes
+        // into the same file, or creating identical files independently) are not a real conflict.
+        // Collapse them by merging the version vector of the remote version into the local one.
+        // To make all the replicas converge to the same entry only the version with the lower blob
+        // id is kept this way. The other one is then superseded by it and replaced by a regular
+        // merge.
+        for (name, remote_blob_id, version_vector) in check_for_identical {
+            let Some(local_blob_id) = self.find_identical(&name, &remote_blob_id).await? else {
+                conflict = true;
+                continue;
+            };
+
+            // unwrap is ok because the local version exists (see above).
+            match self
+                .local_version_mut()
+                .unwrap()
+                .merge_file_version_vector(&name, &local_blob_id, version_vector)
+                .await
+            {
+                Ok(()) => tracing::trace!(name, "Identical concurrent versions collapsed"),
+                // Modified locally in the meantime.
+                Err(Error::EntryExists) => conflict = true,
+                Err(error) => return Err(error),
+            }
+        }
+
+        // unwrap is ok because the local version exists (see above).
+        let local_version = self.local_version().unwrap();
+
         // Need to bump the root version vector to reflect any non-filesystem changes (e.g.,
         // removal of nodes during garbage collection).
         if !conflict && local_version.is_root() {
@@ -386,11 +474,72 @@ impl JointDirectory {
             .ok_or(Error::EntryNotFound)
     }
 
+    fn type_conflict_policy(&self) -> TypeConflictPolicy {
+        self.local_branch
+            .as_ref()
+            .or_else(|| self.versions.values().next().map(Directory::branch))
+            .map(|branch| branch.type_conflict().get())
+            .unwrap_or_default()
+    }
+
     fn entry_versions<'a>(&'a self, name: &'a str) -> impl Iterator<Item = EntryRef<'a>> {
         self.versions
             .values()
             .filter_map(move |v| v.lookup(name).ok())
     }
+
+    // Returns the blob id of the local version of the file `name` if it should absorb the
+    // concurrent remote version with the blob id `remote_blob_id` because they have the same
+    // content. Returns `None` if they differ, if the local version is the one to be absorbed (see
+    // `merge`) or if it can't be determined yet because some of the blocks are missing.
+    async fn find_identical(&self, name: &str, remote_blob_id: &BlobId) -> Result<Option<BlobId>> {
+        let Some(local_branch) = &self.local_branch else {
+            return Ok(None);
+        };
+
+        let mut local_entry = None;
+        let mut remote_entry = None;
+
+        for entry in self
+            .entry_versions(name)
+            .filter_map(|entry| entry.file().ok())
+        {
+            if entry.branch().id() == local_branch.id() {
+                local_entry = Some(entry);
+            } else if entry.blob_id() == remote_blob_id {
+                remote_entry = Some(entry);
+            }
+        }
+
+        let (Some(local_entry), Some(remote_entry)) = (local_entry, remote_entry) else {
+            return Ok(None);
+        };
+
+        if local_entry.blob_id() > remote_blob_id {
+            return Ok(None);
+        }
+
+        match has_same_content(&local_entry, &remote_entry).await {
+            Ok(true) => Ok(Some(*local_entry.blob_id())),
+            Ok(false) | Err(Error::Store(store::Error::BlockNotFound)) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Checks whether the two files have the same content by comparing their lengths first and then
+/// the digests of their content. Unlike comparing block ids this works also for files with
+/// different blob ids (the block nonces are derived from the locators, see
+/// `blob::make_block_nonce`).
+async fn has_same_content(a: &FileRef<'_>, b: &FileRef<'_>) -> Result<bool> {
+    let mut a = a.open().await?;
+    let mut b = b.open().await?;
+
+    if a.len() != b.len() {
+        return Ok(false);
+    }
+
+    Ok(a.content_digest().await? == b.content_digest().await?)
 }
 
 impl fmt::Debug for JointDirectory {
@@ -657,6 +806,29 @@ impl<'a> Iterator for Existing<'a> {
     }
 }
 
+impl Existing<'_> {
+    // Hides the files or the directories if there are both, according to `policy`.
+    fn resolve(mut self, policy: TypeConflictPolicy) -> Self {
+        if self.files.is_empty() || self.directories.is_empty() {
+            return self;
+        }
+
+        match policy {
+            TypeConflictPolicy::KeepBoth => (),
+            TypeConflictPolicy::PreferDirectory => {
+                self.files.clear();
+                self.needs_disambiguation = false;
+            }
+            TypeConflictPolicy::PreferFile => {
+                self.directories.clear();
+                self.needs_disambiguation = self.files.len() > 1;
+            }
+        }
+
+        self
+    }
+}
+
 impl<'a> Merge<'a> {
     // All these entries are expected to have the same name. They can be either files, directories
     // or a mix of the two.
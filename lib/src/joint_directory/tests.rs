@@ -287,6 +287,89 @@ async fn conflict_file_and_multi_version_directory() {
     );
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn type_conflict_policy_file_then_directory() {
+    type_conflict_policy_case(true).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn type_conflict_policy_directory_then_file() {
+    type_conflict_policy_case(false).await
+}
+
+async fn type_conflict_policy_case(file_first: bool) {
+    let (_base_dir, [branch0, branch1]) = setup().await;
+
+    let mut root0 = branch0.open_or_create_root().await.unwrap();
+    let mut root1 = branch1.open_or_create_root().await.unwrap();
+
+    let (file_root, dir_root) = if file_first {
+        (&mut root0, &mut root1)
+    } else {
+        (&mut root1, &mut root0)
+    };
+
+    create_file(file_root, "foo", b"file").await;
+    dir_root
+        .create_directory("foo".to_owned(), rand::random(), &VersionVector::new())
+        .await
+        .unwrap();
+
+    let file_branch_id = *file_root.branch().id();
+    let dir_branch_id = *dir_root.branch().id();
+
+    let root = JointDirectory::new(Some(branch0.clone()), [root0, root1]);
+
+    let list = |root: &JointDirectory| -> Vec<_> {
+        root.entries()
+            .map(|entry| (entry.entry_type(), entry.unique_name().into_owned()))
+            .collect()
+    };
+
+    // Default
+    assert_eq!(branch0.type_conflict().get(), TypeConflictPolicy::KeepBoth);
+    assert_eq!(
+        list(&root),
+        [
+            (
+                EntryType::Directory,
+                conflict::create_unique_name("foo", &dir_branch_id)
+            ),
+            (
+                EntryType::File,
+                conflict::create_unique_name("foo", &file_branch_id)
+            ),
+        ]
+    );
+    assert_matches!(root.lookup_unique("foo"), Err(Error::AmbiguousEntry));
+
+    // The setting is shared by all the branches.
+    branch1
+        .type_conflict()
+        .set(TypeConflictPolicy::PreferDirectory);
+    assert_eq!(list(&root), [(EntryType::Directory, "foo".to_owned())]);
+    assert_eq!(
+        root.lookup_unique("foo").unwrap().entry_type(),
+        EntryType::Directory
+    );
+    assert_eq!(root.lookup("foo").count(), 1);
+    root.cd("foo").await.unwrap();
+
+    branch0.type_conflict().set(TypeConflictPolicy::PreferFile);
+    assert_eq!(list(&root), [(EntryType::File, "foo".to_owned())]);
+    assert_eq!(
+        root.lookup_unique("foo")
+            .unwrap()
+            .file()
+            .unwrap()
+            .branch()
+            .id(),
+        &file_branch_id
+    );
+    assert_eq!(root.lookup("foo").count(), 1);
+    assert_matches!(root.cd("foo").await, Err(Error::EntryNotFound));
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn conflict_identical_versions() {
     let (_base_dir, [branch0, branch1]) = setup().await;
@@ -1012,6 +1095,115 @@ async fn merge_concurrent_directories() {
     dir.lookup("cat.jpg").unwrap().file().unwrap();
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_identical_concurrent_edits() {
+    let (_base_dir, [branch0, branch1]) = setup().await;
+
+    let mut root0 = branch0.open_or_create_root().await.unwrap();
+    create_file(&mut root0, "file.txt", b"one").await;
+
+    // Both branches modify the file concurrently, writing the same content.
+    update_file(&root0, "file.txt", b"two", &branch1).await;
+    update_file(&root0, "file.txt", b"two", &branch0).await;
+
+    root0.refresh().await.unwrap();
+    let mut root1 = branch1
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+
+    let vv0 = read_version_vector(&root0, "file.txt").await;
+    let vv1 = read_version_vector(&root1, "file.txt").await;
+    assert_eq!(vv0.partial_cmp(&vv1), None);
+
+    // Merging in either direction collapses the versions instead of reporting a conflict.
+    merge(&[&branch1, &branch0]).await.unwrap();
+    merge(&[&branch0, &branch1]).await.unwrap();
+
+    root0.refresh().await.unwrap();
+    root1.refresh().await.unwrap();
+
+    for root in [&root0, &root1] {
+        assert_eq!(
+            read_version_vector(root, "file.txt").await,
+            vv0.merged(&vv1)
+        );
+
+        let mut file = open_file(root, "file.txt").await;
+        assert_eq!(file.read_to_end().await.unwrap(), b"two");
+    }
+
+    let root = JointDirectory::new(Some(branch0.clone()), [root0, root1]);
+    assert_eq!(root.entries().count(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_identical_independent_files() {
+    let (_base_dir, [branch0, branch1]) = setup().await;
+
+    // Both branches create the same file independently, so the versions have different blob ids.
+    let mut root0 = branch0.open_or_create_root().await.unwrap();
+    let mut root1 = branch1.open_or_create_root().await.unwrap();
+    create_file(&mut root0, "file.txt", b"same").await;
+    create_file(&mut root1, "file.txt", b"same").await;
+
+    // Only the replica with the lower blob id collapses the versions immediately, the other one
+    // reports a conflict until it receives the collapsed version.
+    let result0 = merge(&[&branch0, &branch1]).await;
+    let result1 = merge(&[&branch1, &branch0]).await;
+    assert!(result0.is_ok() || result1.is_ok());
+
+    merge(&[&branch0, &branch1]).await.unwrap();
+    merge(&[&branch1, &branch0]).await.unwrap();
+
+    root0.refresh().await.unwrap();
+    root1.refresh().await.unwrap();
+
+    assert_eq!(
+        read_version_vector(&root0, "file.txt").await,
+        read_version_vector(&root1, "file.txt").await
+    );
+
+    let mut file0 = open_file(&root0, "file.txt").await;
+    let mut file1 = open_file(&root1, "file.txt").await;
+    assert_eq!(file0.blob_id(), file1.blob_id());
+    assert_eq!(file0.read_to_end().await.unwrap(), b"same");
+    assert_eq!(file1.read_to_end().await.unwrap(), b"same");
+
+    let root = JointDirectory::new(Some(branch0.clone()), [root0, root1]);
+    assert_eq!(root.entries().count(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_different_independent_files() {
+    let (_base_dir, [branch0, branch1]) = setup().await;
+
+    let mut root0 = branch0.open_or_create_root().await.unwrap();
+    let mut root1 = branch1.open_or_create_root().await.unwrap();
+    create_file(&mut root0, "file.txt", b"one").await;
+    create_file(&mut root1, "file.txt", b"two").await;
+
+    for branches in [[&branch0, &branch1], [&branch1, &branch0]] {
+        assert_matches!(merge(&branches).await, Err(Error::AmbiguousEntry));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_different_concurrent_edits() {
+    let (_base_dir, [branch0, branch1]) = setup().await;
+
+    let mut root0 = branch0.open_or_create_root().await.unwrap();
+    create_file(&mut root0, "file.txt", b"one").await;
+
+    update_file(&root0, "file.txt", b"two", &branch1).await;
+    update_file(&root0, "file.txt", b"three", &branch0).await;
+
+    assert_matches!(
+        merge(&[&branch1, &branch0]).await,
+        Err(Error::AmbiguousEntry)
+    );
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn merge_file_and_tombstone() {
     // Create two branches.
@@ -1049,6 +1241,63 @@ async fn merge_file_and_tombstone() {
     assert!(entry.is_tombstone());
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_tombstone_of_never_seen_file() {
+    let (_base_dir, [branch_l, branch_a, branch_b, branch_c]) = setup().await;
+
+    // Create a file in branch A and fork it into branch B.
+    let mut root_a = branch_a.open_or_create_root().await.unwrap();
+    let mut file = create_file(&mut root_a, "dog.jpg", &[]).await;
+    let file_vv = file.version_vector().await.unwrap();
+    file.fork(branch_b.clone()).await.unwrap();
+    drop(file);
+
+    // Remove the file in branch A.
+    root_a
+        .remove_entry("dog.jpg", branch_a.id(), file_vv)
+        .await
+        .unwrap();
+    let tombstone_vv = root_a.lookup("dog.jpg").unwrap().version_vector().clone();
+
+    // Branch C merges the tombstone and then purges it, so its root now incorporates the
+    // tombstone without containing it.
+    merge(&[&branch_c, &branch_a]).await.unwrap();
+
+    let mut root_c = branch_c
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+    assert_eq!(
+        root_c
+            .purge_tombstones(&[("dog.jpg".to_owned(), tombstone_vv)])
+            .await
+            .unwrap(),
+        1
+    );
+
+    // The local branch merges branch C first, so it never sees the file.
+    merge(&[&branch_l, &branch_c]).await.unwrap();
+
+    // Then it merges branch A (which still has the tombstone) and branch B (which still has the
+    // file). The tombstone must be merged even though the local root already incorporates it.
+    merge(&[&branch_l, &branch_a, &branch_b]).await.unwrap();
+
+    let root_l = branch_l
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+    assert!(root_l.lookup("dog.jpg").unwrap().is_tombstone());
+
+    // So the file is not resurrected when merging only branch B.
+    merge(&[&branch_l, &branch_b]).await.unwrap();
+
+    let root_l = branch_l
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+    assert!(root_l.lookup("dog.jpg").unwrap().is_tombstone());
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn merge_moved_file() {
     // Create two branches.
@@ -1086,6 +1335,7 @@ async fn merge_moved_file() {
             &mut dir,
             file_name,
             VersionVector::first(*branch1.id()),
+            true,
         )
         .await
         .unwrap();
@@ -0,0 +1,59 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+/// How `JointDirectory` presents a file and a directory with the same name that exist in
+/// different versions of the directory (e.g., because two writers created them concurrently).
+///
+/// This only affects how the entries are listed and looked up. All of them are still kept and
+/// merged, so changing the policy later makes the hidden ones visible again.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum TypeConflictPolicy {
+    /// Present both the directory and the file(s), each under a unique name disambiguated by the
+    /// id of its branch (see `JointEntryRef::unique_name`). This is the default.
+    #[default]
+    KeepBoth,
+    /// Present only the directory, under the plain name. The files are hidden.
+    PreferDirectory,
+    /// Present only the file(s). The directory is hidden. If there is only one file, it's
+    /// presented under the plain name.
+    PreferFile,
+}
+
+impl TypeConflictPolicy {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Self::KeepBoth => 0,
+            Self::PreferDirectory => 1,
+            Self::PreferFile => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::KeepBoth),
+            1 => Some(Self::PreferDirectory),
+            2 => Some(Self::PreferFile),
+            _ => None,
+        }
+    }
+}
+
+/// The `TypeConflictPolicy` of a repository. Shared among all its branches.
+#[derive(Clone, Default)]
+pub(crate) struct TypeConflictSetting(Arc<AtomicU8>);
+
+impl TypeConflictSetting {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, policy: TypeConflictPolicy) {
+        self.0.store(policy.to_u8(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> TypeConflictPolicy {
+        TypeConflictPolicy::from_u8(self.0.load(Ordering::Relaxed)).unwrap_or_default()
+    }
+}
@@ -55,17 +55,24 @@ pub use self::{
     directory::{Directory, EntryRef, EntryType, DIRECTORY_VERSION},
     error::{Error, Result},
     event::{Event, Payload},
-    file::File,
-    joint_directory::{JointDirectory, JointEntryRef},
+    file::{File, FileRange, MissingBlockPolicy},
+    joint_directory::{JointDirectory, JointEntryRef, TypeConflictPolicy},
     joint_entry::JointEntry,
     network::{peer_addr::PeerAddr, PeerInfo, PeerInfoCollector, PublicRuntimeId, SecretRuntimeId},
     progress::Progress,
-    protocol::BLOCK_SIZE,
+    protocol::{BlockId, SingleBlockPresence, BLOCK_SIZE},
     repository::{
-        delete as delete_repository, Credentials, Metadata, Repository, RepositoryHandle,
-        RepositoryId, RepositoryParams,
+        delete as delete_repository, low_priority_writes, ChangeKind, Credentials, DedupStats,
+        Difference, DifferenceKind, EntryMetadata, LockInfo, Metadata, OpenHandleInfo,
+        OpenHandleMode, PathEvent, PresenceSnapshot, ReadSnapshot, RecoveryReport, RepairProgress,
+        Repository, RepositoryHandle, RepositoryId, RepositoryMeta, RepositoryParams,
+        RepositorySizes, RepositoryStatus, SnapshotDirectory, SnapshotEntry, SnapshotFile,
+        WatchedDirectory,
     },
     storage_size::StorageSize,
-    store::{Error as StoreError, DATA_VERSION},
+    store::{BlockAccess, BlockAccessKind, Error as StoreError, ExpirationPolicy, DATA_VERSION},
     version_vector::VersionVector,
 };
+
+#[cfg(feature = "unstable")]
+pub use self::protocol::{BlockContent, BlockNonce};
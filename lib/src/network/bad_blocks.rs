@@ -0,0 +1,111 @@
+use crate::sync::{AwaitDrop, DropAwaitable};
+use deadlock::BlockingMutex;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Counts the blocks received from a single peer that failed validation and signals when their
+/// number exceeds the limit.
+///
+/// Note the id of a received block is always computed from its content so a block can't be
+/// received under a wrong id. Instead, a block is considered bad if it doesn't match any block
+/// requested from the peer and is also not referenced by the index. That is, the peer sent us
+/// either corrupted or made-up content.
+#[derive(Clone)]
+pub(super) struct BadBlockCounter {
+    count: Arc<AtomicU64>,
+    limit: BadBlockLimit,
+    // Dropped (and set to `None`) when the limit is exceeded.
+    on_limit_exceeded: Arc<BlockingMutex<Option<DropAwaitable>>>,
+}
+
+impl BadBlockCounter {
+    pub fn new(limit: BadBlockLimit) -> Self {
+        Self {
+            count: Arc::new(AtomicU64::new(0)),
+            limit,
+            on_limit_exceeded: Arc::new(BlockingMutex::new(Some(DropAwaitable::new()))),
+        }
+    }
+
+    /// Records a bad block.
+    pub fn increment(&self) {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if self.limit.get().map(|limit| count > limit).unwrap_or(false) {
+            self.on_limit_exceeded.lock().unwrap().take();
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns a `AwaitDrop` that gets notified when the number of bad blocks exceeds the limit.
+    pub fn limit_exceeded(&self) -> AwaitDrop {
+        self.on_limit_exceeded
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|tx| tx.subscribe())
+            .unwrap_or_else(|| DropAwaitable::new().subscribe())
+    }
+}
+
+/// Maximum number of bad blocks a peer can send before it gets disconnected. Shared by all the
+/// peers.
+#[derive(Clone, Default)]
+pub(super) struct BadBlockLimit(Arc<AtomicU64>);
+
+impl BadBlockLimit {
+    // Zero means no limit.
+    pub fn set(&self, limit: Option<u64>) {
+        self.0.store(
+            limit.map(|limit| limit.saturating_add(1)).unwrap_or(0),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn get(&self) -> Option<u64> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit - 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn no_limit() {
+        let counter = BadBlockCounter::new(BadBlockLimit::default());
+
+        for _ in 0..10 {
+            counter.increment();
+        }
+
+        assert_eq!(counter.get(), 10);
+        assert!(counter.limit_exceeded().now_or_never().is_none());
+    }
+
+    #[test]
+    fn limit() {
+        let limit = BadBlockLimit::default();
+        limit.set(Some(2));
+
+        let counter = BadBlockCounter::new(limit);
+        let exceeded = counter.limit_exceeded();
+
+        counter.increment();
+        counter.increment();
+        assert!(counter.limit_exceeded().now_or_never().is_none());
+
+        counter.increment();
+        assert!(exceeded.now_or_never().is_some());
+        assert!(counter.limit_exceeded().now_or_never().is_some());
+    }
+}
@@ -0,0 +1,222 @@
+//! Chunked block transfer. Instead of sending a block in a single message, the server splits it
+//! into chunks and the client reassembles them. The partially received blocks are kept across
+//! reconnects so an interrupted transfer can be resumed from where it stopped instead of starting
+//! over.
+
+use crate::protocol::{Block, BlockContent, BlockId, BlockNonce, BLOCK_SIZE};
+use deadlock::BlockingMutex;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::{fmt, num::NonZeroUsize, sync::Arc};
+use zeroize::Zeroize;
+
+/// Size of a single chunk (except possibly the last one of a block) in bytes.
+pub(super) const BLOCK_CHUNK_SIZE: usize = 8 * 1024;
+
+// Maximum number of partially received blocks to keep. When exceeded, the least recently updated
+// one is discarded.
+const MAX_PARTIAL_BLOCKS: usize = 64;
+
+/// Part of a block content starting at `offset`.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct BlockChunk {
+    pub id: BlockId,
+    pub nonce: BlockNonce,
+    /// Position of this chunk within the block content.
+    pub offset: u32,
+    /// Length of the whole block content.
+    pub len: u32,
+    pub data: Vec<u8>,
+}
+
+impl BlockChunk {
+    /// Splits the block content (starting at `offset`) into chunks.
+    pub fn split(
+        id: BlockId,
+        content: &BlockContent,
+        nonce: BlockNonce,
+        offset: u32,
+    ) -> impl Iterator<Item = Self> + '_ {
+        let offset = (offset as usize).min(content.len());
+
+        content[offset..]
+            .chunks(BLOCK_CHUNK_SIZE)
+            .enumerate()
+            .map(move |(index, data)| Self {
+                id,
+                nonce,
+                offset: (offset + index * BLOCK_CHUNK_SIZE) as u32,
+                len: content.len() as u32,
+                data: data.to_vec(),
+            })
+    }
+}
+
+impl Drop for BlockChunk {
+    fn drop(&mut self) {
+        self.data.zeroize()
+    }
+}
+
+impl fmt::Debug for BlockChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockChunk")
+            .field("id", &self.id)
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .field("data.len", &self.data.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Partially received blocks of a single repository, shared by all its links.
+#[derive(Clone)]
+pub(crate) struct PartialBlocks(Arc<BlockingMutex<LruCache<BlockId, PartialBlock>>>);
+
+impl PartialBlocks {
+    pub fn new() -> Self {
+        Self(Arc::new(BlockingMutex::new(LruCache::new(
+            NonZeroUsize::new(MAX_PARTIAL_BLOCKS).expect("capacity must be non-zero"),
+        ))))
+    }
+
+    /// Offset from which to resume receiving the given block (zero if nothing has been received
+    /// yet).
+    pub fn offset(&self, id: &BlockId) -> u32 {
+        self.0
+            .lock()
+            .unwrap()
+            .peek(id)
+            .map(|partial| partial.data.len() as u32)
+            .unwrap_or(0)
+    }
+
+    /// Appends the chunk to the corresponding partial block. Returns the whole block once its last
+    /// chunk is received. Chunks that don't continue right where the partial block ends are
+    /// ignored (they can only come from an outdated request which will eventually be retried).
+    pub fn insert(&self, chunk: BlockChunk) -> Option<Block> {
+        let len = chunk.len as usize;
+
+        if len > BLOCK_SIZE || chunk.offset as usize + chunk.data.len() > len {
+            return None;
+        }
+
+        let mut map = self.0.lock().unwrap();
+
+        if chunk.offset == 0 {
+            map.put(
+                chunk.id,
+                PartialBlock {
+                    nonce: chunk.nonce,
+                    len,
+                    data: Vec::with_capacity(len),
+                },
+            );
+        }
+
+        let partial = map.get_mut(&chunk.id)?;
+
+        if partial.nonce != chunk.nonce
+            || partial.len != len
+            || partial.data.len() != chunk.offset as usize
+        {
+            return None;
+        }
+
+        partial.data.extend_from_slice(&chunk.data);
+
+        if partial.data.len() < len {
+            return None;
+        }
+
+        let partial = map.pop(&chunk.id)?;
+
+        Some(Block::new(
+            BlockContent::from_slice(&partial.data),
+            partial.nonce,
+        ))
+    }
+}
+
+struct PartialBlock {
+    nonce: BlockNonce,
+    len: usize,
+    data: Vec<u8>,
+}
+
+impl Drop for PartialBlock {
+    fn drop(&mut self) {
+        self.data.zeroize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn split_and_reassemble() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let block: Block = rng.gen();
+        let partials = PartialBlocks::new();
+
+        let chunks: Vec<_> = BlockChunk::split(block.id, &block.content, block.nonce, 0).collect();
+        assert_eq!(chunks.len(), BLOCK_SIZE / BLOCK_CHUNK_SIZE);
+
+        let (last, init) = chunks.split_last().unwrap();
+
+        for chunk in init {
+            assert!(partials.insert(chunk.clone()).is_none());
+        }
+
+        assert_eq!(
+            partials.offset(&block.id) as usize,
+            init.len() * BLOCK_CHUNK_SIZE
+        );
+
+        let received = partials.insert(last.clone()).unwrap();
+        assert_eq!(received.id, block.id);
+        assert_eq!(partials.offset(&block.id), 0);
+    }
+
+    #[test]
+    fn resume() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let block: Block = rng.gen();
+        let partials = PartialBlocks::new();
+
+        let first = BlockChunk::split(block.id, &block.content, block.nonce, 0)
+            .next()
+            .unwrap();
+        assert!(partials.insert(first).is_none());
+
+        let offset = partials.offset(&block.id);
+        assert_eq!(offset as usize, BLOCK_CHUNK_SIZE);
+
+        let received = BlockChunk::split(block.id, &block.content, block.nonce, offset)
+            .find_map(|chunk| partials.insert(chunk))
+            .unwrap();
+        assert_eq!(received.id, block.id);
+    }
+
+    #[test]
+    fn out_of_order_chunks_are_ignored() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let block: Block = rng.gen();
+        let partials = PartialBlocks::new();
+
+        let mut chunks = BlockChunk::split(block.id, &block.content, block.nonce, 0);
+        let first = chunks.next().unwrap();
+        let _second = chunks.next().unwrap();
+        let third = chunks.next().unwrap();
+
+        // Nothing received yet so a non-first chunk can't be used.
+        assert!(partials.insert(third.clone()).is_none());
+        assert_eq!(partials.offset(&block.id), 0);
+
+        assert!(partials.insert(first).is_none());
+        assert!(partials.insert(third).is_none());
+        assert_eq!(partials.offset(&block.id) as usize, BLOCK_CHUNK_SIZE);
+    }
+}
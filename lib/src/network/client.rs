@@ -1,5 +1,5 @@
 use super::{
-    constants::MAX_PENDING_REQUESTS_PER_CLIENT,
+    bad_blocks::BadBlockCounter,
     debug_payload::{DebugResponse, PendingDebugRequest},
     message::{Content, Request, Response, ResponseDisambiguator},
     pending::{PendingRequest, PendingRequests, PendingResponse, ProcessedResponse},
@@ -14,12 +14,13 @@ use crate::{
     repository::{BlockRequestMode, Vault},
     store,
 };
-use std::{future, sync::Arc, time::Instant};
+use std::{future, sync::Arc};
 use tokio::{
     select,
     sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    time::Instant,
 };
-use tracing::{instrument, Level};
+use tracing::{instrument, Instrument, Level};
 
 pub(super) struct Client {
     inner: Inner,
@@ -33,6 +34,9 @@ impl Client {
         content_tx: mpsc::Sender<Content>,
         response_rx: mpsc::Receiver<Response>,
         peer_request_limiter: Arc<Semaphore>,
+        max_pending_requests: usize,
+        bad_blocks: BadBlockCounter,
+        chunked_blocks: bool,
     ) -> Self {
         let pending_requests = PendingRequests::new(vault.monitor.clone());
         let block_tracker = vault.block_tracker.client();
@@ -45,10 +49,12 @@ impl Client {
             vault,
             pending_requests,
             peer_request_limiter,
-            link_request_limiter: Arc::new(Semaphore::new(MAX_PENDING_REQUESTS_PER_CLIENT)),
+            link_request_limiter: Arc::new(Semaphore::new(max_pending_requests)),
             block_tracker,
             content_tx,
             send_queue_tx,
+            bad_blocks,
+            chunked_blocks,
         };
 
         Self {
@@ -79,6 +85,9 @@ struct Inner {
     block_tracker: TrackerClient,
     content_tx: mpsc::Sender<Content>,
     send_queue_tx: mpsc::UnboundedSender<(PendingRequest, Instant)>,
+    bad_blocks: BadBlockCounter,
+    // Whether to request blocks in chunks (if the peer supports it).
+    chunked_blocks: bool,
 }
 
 impl Inner {
@@ -108,23 +117,38 @@ impl Inner {
                 break;
             };
 
-            let permits = self.acquire_send_permits().await;
+            let span = request.span();
 
-            self.vault
-                .monitor
-                .request_queue_time
-                .record(timestamp.elapsed());
-
-            if let Some(request) = self
-                .pending_requests
-                .insert(request, permits.link, permits.peer)
-            {
-                self.send_request(request).await;
+            async {
+                let permits = self.acquire_send_permits().await;
+
+                self.vault
+                    .monitor
+                    .request_queue_time
+                    .record(timestamp.elapsed());
+
+                if let Some(request) =
+                    self.pending_requests
+                        .insert(request, permits.link, permits.peer)
+                {
+                    self.send_request(request).await;
+                }
             }
+            .instrument(span)
+            .await;
         }
     }
 
     async fn send_request(&self, request: Request) {
+        // Resume the transfer of partially received blocks.
+        let request = match request {
+            Request::Block(block_id, debug) if self.chunked_blocks => {
+                let offset = self.vault.partial_blocks.offset(&block_id);
+                Request::BlockChunks(block_id, offset, debug)
+            }
+            request => request,
+        };
+
         self.content_tx
             .send(Content::Request(request))
             .await
@@ -158,6 +182,16 @@ impl Inner {
         while let Some(response) = rx.recv().await {
             self.vault.monitor.responses_received.increment(1);
 
+            let response = match response {
+                Response::BlockChunk(chunk, debug) => {
+                    match self.vault.partial_blocks.insert(chunk) {
+                        Some(block) => Response::Block(block.content, block.nonce, debug),
+                        None => continue,
+                    }
+                }
+                response => response,
+            };
+
             let response = self.pending_requests.remove(response);
 
             let start = Instant::now();
@@ -172,6 +206,8 @@ impl Inner {
     }
 
     async fn handle_response(&self, response: PendingResponse) -> Result<()> {
+        let solicited = response.is_solicited();
+
         match response.response {
             ProcessedResponse::RootNode(proof, block_presence, debug) => {
                 self.handle_root_node(proof, block_presence, debug).await
@@ -186,7 +222,7 @@ impl Inner {
                 self.handle_block_offer(block_id, debug).await
             }
             ProcessedResponse::Block(block, debug) => {
-                self.handle_block(block, response.block_promise, debug)
+                self.handle_block(block, response.block_promise, solicited, debug)
                     .await
             }
             ProcessedResponse::BlockError(block_id, debug) => {
@@ -367,14 +403,24 @@ impl Inner {
         &self,
         block: Block,
         block_promise: Option<BlockPromise>,
+        solicited: bool,
         debug_payload: DebugResponse,
     ) -> Result<()> {
         tracing::trace!("Received block");
 
         match self.vault.receive_block(&block, block_promise).await {
-            // Ignore `BlockNotReferenced` errors as they only mean that the block is no longer
-            // needed.
-            Ok(()) | Err(Error::Store(store::Error::BlockNotReferenced)) => Ok(()),
+            Ok(()) => Ok(()),
+            // Ignore `BlockNotReferenced` errors for requested blocks as they only mean that the
+            // block is no longer needed. An unreferenced block we didn't request means the content
+            // is bogus because its id (computed from the content) doesn't match anything we know.
+            Err(Error::Store(store::Error::BlockNotReferenced)) => {
+                if !solicited {
+                    tracing::warn!("Received invalid block");
+                    self.bad_blocks.increment();
+                }
+
+                Ok(())
+            }
             Err(error) => Err(error),
         }
     }
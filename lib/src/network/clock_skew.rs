@@ -0,0 +1,144 @@
+//! Measuring the difference between our wall clock and the wall clock of a peer.
+//!
+//! Syncing itself doesn't depend on wall clocks (it uses version vectors) so this is purely
+//! advisory. It's useful to diagnose issues with features that do assume roughly synchronized
+//! clocks (e.g., file timestamps).
+
+use std::{
+    io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Clock skews larger than this are reported.
+pub(super) const CLOCK_SKEW_WARNING_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// Difference between the wall clock of a peer and our wall clock, measured during the handshake.
+/// The measurement is only approximate as it's affected by the network latency.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct ClockSkew(i64); // in milliseconds
+
+impl ClockSkew {
+    /// Skew in milliseconds. Positive means the peer's clock is ahead of ours, negative that it's
+    /// behind.
+    pub fn as_millis(&self) -> i64 {
+        self.0
+    }
+
+    /// Absolute value of the skew.
+    pub fn magnitude(&self) -> Duration {
+        Duration::from_millis(self.0.unsigned_abs())
+    }
+
+    /// Is the peer's clock ahead of ours?
+    pub fn is_ahead(&self) -> bool {
+        self.0 > 0
+    }
+}
+
+/// Exchanges the current time with the peer and returns the estimated clock skew. Each side sends
+/// its time only if it has the detection `enabled`, so the skew is known only if both sides have
+/// it enabled.
+pub(super) async fn exchange<IO>(io: &mut IO, enabled: bool) -> io::Result<Option<ClockSkew>>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let sent_at = now();
+
+    if enabled {
+        io.write_all(&[1]).await?;
+        io.write_all(&sent_at.to_le_bytes()).await?;
+    } else {
+        io.write_all(&[0]).await?;
+    }
+
+    let mut flag = [0; 1];
+    io.read_exact(&mut flag).await?;
+
+    let their_time = match flag[0] {
+        0 => None,
+        1 => Some(read_time(io).await?),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid clock skew flag",
+            ))
+        }
+    };
+
+    let received_at = now();
+
+    Ok(their_time
+        .filter(|_| enabled)
+        .map(|their_time| estimate(sent_at, received_at, their_time)))
+}
+
+async fn read_time<IO>(io: &mut IO) -> io::Result<i64>
+where
+    IO: AsyncRead + Unpin,
+{
+    let mut buffer = [0; 8];
+    io.read_exact(&mut buffer).await?;
+    Ok(i64::from_le_bytes(buffer))
+}
+
+// Both peers send their time at about the same moment, so assume their timestamp corresponds to
+// the midpoint between us sending ours and receiving theirs.
+fn estimate(sent_at: i64, received_at: i64, their_time: i64) -> ClockSkew {
+    let midpoint = sent_at.saturating_add(received_at.saturating_sub(sent_at) / 2);
+    ClockSkew(their_time.saturating_sub(midpoint))
+}
+
+// Current time as milliseconds since the unix epoch.
+fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => i64::try_from(duration.as_millis()).unwrap_or(i64::MAX),
+        Err(error) => -i64::try_from(error.duration().as_millis()).unwrap_or(i64::MAX),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_skew() {
+        assert_eq!(estimate(1000, 1000, 1000).as_millis(), 0);
+        assert_eq!(estimate(1000, 1200, 1100).as_millis(), 0);
+        assert_eq!(estimate(1000, 1200, 61_100).as_millis(), 60_000);
+        assert_eq!(estimate(1000, 1200, 900).as_millis(), -200);
+
+        let skew = estimate(1000, 1000, 500);
+        assert!(!skew.is_ahead());
+        assert_eq!(skew.magnitude(), Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn exchange_with_self() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+
+        let (skew_a, skew_b) =
+            tokio::try_join!(exchange(&mut a, true), exchange(&mut b, true)).unwrap();
+
+        assert!(skew_a.unwrap().magnitude() < Duration::from_secs(1));
+        assert!(skew_b.unwrap().magnitude() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn exchange_with_one_side_disabled() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+
+        let (skew_a, skew_b) =
+            tokio::try_join!(exchange(&mut a, true), exchange(&mut b, false)).unwrap();
+
+        assert_eq!(skew_a, None);
+        assert_eq!(skew_b, None);
+
+        // The stream is still usable afterwards.
+        let (skew_a, skew_b) =
+            tokio::try_join!(exchange(&mut a, true), exchange(&mut b, true)).unwrap();
+
+        assert!(skew_a.is_some());
+        assert!(skew_b.is_some());
+    }
+}
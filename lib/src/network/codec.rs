@@ -0,0 +1,385 @@
+//! Versioned (de)serialization of the message content exchanged between peers.
+
+use super::{
+    message::{Content, Response},
+    protocol::{Version, FIRST_COMPACT_VERSION, FIRST_COMPRESSED_VERSION},
+};
+use bincode::{ErrorKind, Options};
+
+/// Encodes and decodes message content using the layout selected by the protocol version
+/// negotiated during the handshake.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub(super) enum MessageCodec {
+    /// Default bincode layout: fixed-width integers, four byte enum tags and eight byte lengths.
+    /// Used by protocol versions before `FIRST_COMPACT_VERSION`.
+    Legacy,
+    /// Bincode layout with variable-length integers. Enum tags and small lengths take only a single
+    /// byte which noticeably shrinks the most frequent messages: `Block` (content length),
+    /// `ChildNodes` requests and `InnerNodes` / `LeafNodes` responses (the per node presence and
+    /// state tags and the node counts). Trailing bytes are rejected.
+    Compact,
+    /// `Compact` layout prefixed with a single byte telling whether the rest is compressed with
+    /// LZ4. Used since `FIRST_COMPRESSED_VERSION`. Whether outgoing messages are compressed is up
+    /// to the sender (`compress`), incoming messages are decoded either way. Messages carrying
+    /// block content are never compressed because the content is encrypted and so incompressible.
+    /// Other messages are sent compressed only if it makes them shorter.
+    Compressible { compress: bool },
+}
+
+impl MessageCodec {
+    pub fn new(version: Version) -> Self {
+        if version >= FIRST_COMPRESSED_VERSION {
+            Self::Compressible { compress: false }
+        } else if version >= FIRST_COMPACT_VERSION {
+            Self::Compact
+        } else {
+            Self::Legacy
+        }
+    }
+
+    /// Enables or disables compression of the outgoing messages. Has no effect if the peer doesn't
+    /// support it.
+    pub fn with_compression(self, enabled: bool) -> Self {
+        match self {
+            Self::Legacy | Self::Compact => self,
+            Self::Compressible { .. } => Self::Compressible { compress: enabled },
+        }
+    }
+
+    pub fn encode(&self, content: &Content) -> Vec<u8> {
+        // unwrap is OK because serialization into a vec should never fail unless we have a bug
+        // somewhere.
+        match self {
+            Self::Legacy => bincode::serialize(content).unwrap(),
+            Self::Compact => compact().serialize(content).unwrap(),
+            Self::Compressible { compress } => {
+                let plain = compact().serialize(content).unwrap();
+
+                if *compress && is_compressible(content) {
+                    compress_content(&plain)
+                } else {
+                    let mut output = Vec::with_capacity(plain.len() + 1);
+                    output.push(FLAG_PLAIN);
+                    output.extend_from_slice(&plain);
+                    output
+                }
+            }
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Content, bincode::Error> {
+        match self {
+            Self::Legacy => bincode::deserialize(bytes),
+            Self::Compact => compact().deserialize(bytes),
+            Self::Compressible { .. } => match bytes.split_first() {
+                Some((&FLAG_PLAIN, rest)) => compact().deserialize(rest),
+                Some((&FLAG_LZ4, rest)) => compact().deserialize(&decompress_content(rest)?),
+                Some(_) | None => Err(malformed()),
+            },
+        }
+    }
+}
+
+const FLAG_PLAIN: u8 = 0;
+const FLAG_LZ4: u8 = 1;
+
+// Max length of a decompressed message. No bigger message can be sent uncompressed anyway, so
+// anything beyond it is malformed (and possibly an attempt to make us allocate too much memory).
+const MAX_DECOMPRESSED_LEN: usize = u16::MAX as usize;
+
+fn is_compressible(content: &Content) -> bool {
+    !matches!(
+        content,
+        Content::Response(Response::Block(..) | Response::BlockChunk(..))
+    )
+}
+
+// Compressed message layout: [ FLAG_LZ4 ][ decompressed len: u32 LE ][ lz4 block ]. Falls back to
+// the plain layout if compression doesn't make the message shorter.
+fn compress_content(plain: &[u8]) -> Vec<u8> {
+    let compressed = lz4_flex::block::compress(plain);
+
+    let mut output = Vec::with_capacity(plain.len().min(compressed.len() + 4) + 1);
+
+    if compressed.len() + 4 < plain.len() {
+        output.push(FLAG_LZ4);
+        output.extend_from_slice(&(plain.len() as u32).to_le_bytes());
+        output.extend_from_slice(&compressed);
+    } else {
+        output.push(FLAG_PLAIN);
+        output.extend_from_slice(plain);
+    }
+
+    output
+}
+
+fn decompress_content(bytes: &[u8]) -> Result<Vec<u8>, bincode::Error> {
+    if bytes.len() < 4 {
+        return Err(malformed());
+    }
+
+    let (len, compressed) = bytes.split_at(4);
+    // unwrap is OK because `len` is exactly 4 bytes long.
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+
+    if len > MAX_DECOMPRESSED_LEN {
+        return Err(malformed());
+    }
+
+    lz4_flex::block::decompress(compressed, len).map_err(|_| malformed())
+}
+
+fn malformed() -> bincode::Error {
+    Box::new(ErrorKind::Custom("malformed message".to_owned()))
+}
+
+fn compact() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_varint_encoding()
+        .with_little_endian()
+        .reject_trailing_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::sign::Keypair,
+        network::{
+            block_chunks::BlockChunk,
+            debug_payload::{DebugRequest, DebugResponse, PendingDebugRequest},
+            message::{Request, Response, ResponseDisambiguator},
+            protocol::{MIN_VERSION, VERSIONS},
+        },
+        protocol::{
+            Block, BlockContent, InnerNode, InnerNodes, LeafNodes, MultiBlockPresence, Proof,
+            SingleBlockPresence, Summary,
+        },
+        version_vector::VersionVector,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for version in VERSIONS.iter() {
+            let codec = MessageCodec::new(version);
+
+            for content in sample_contents(&mut rng) {
+                let encoded = codec.encode(&content);
+                let decoded = codec.decode(&encoded).unwrap();
+
+                assert_eq!(
+                    format!("{decoded:?}"),
+                    format!("{content:?}"),
+                    "{version:?}"
+                );
+                assert_eq!(codec.encode(&decoded), encoded, "{version:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn compact_is_smaller() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let legacy = MessageCodec::new(MIN_VERSION);
+        let compact = MessageCodec::new(FIRST_COMPACT_VERSION);
+
+        assert_eq!(legacy, MessageCodec::Legacy);
+        assert_eq!(compact, MessageCodec::Compact);
+
+        for content in sample_contents(&mut rng) {
+            assert!(
+                compact.encode(&content).len() < legacy.encode(&content).len(),
+                "{content:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let plain = MessageCodec::new(FIRST_COMPRESSED_VERSION);
+        let compressed = plain.with_compression(true);
+
+        assert_eq!(plain, MessageCodec::Compressible { compress: false });
+        assert_eq!(compressed, MessageCodec::Compressible { compress: true });
+
+        // Compression is not available in older versions.
+        assert_eq!(
+            MessageCodec::new(FIRST_COMPACT_VERSION).with_compression(true),
+            MessageCodec::Compact
+        );
+
+        for content in sample_contents(&mut rng) {
+            let encoded = compressed.encode(&content);
+
+            // Compression never makes the message longer than the plain one.
+            assert!(encoded.len() <= plain.encode(&content).len(), "{content:?}");
+
+            // Both sides decode the message regardless of their own compression setting.
+            for codec in [plain, compressed] {
+                let decoded = codec.decode(&encoded).unwrap();
+                assert_eq!(format!("{decoded:?}"), format!("{content:?}"));
+            }
+        }
+    }
+
+    #[test]
+    fn compress_index() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let plain = MessageCodec::new(FIRST_COMPRESSED_VERSION);
+        let compressed = plain.with_compression(true);
+
+        // Inner nodes of a subtree whose leaves are all the same (e.g., a large file consisting of
+        // zero blocks only) have all the same hash.
+        let hash = rng.gen();
+        let inner_nodes: InnerNodes = (0..=u8::MAX)
+            .map(|bucket| {
+                (
+                    bucket,
+                    InnerNode::new(
+                        hash,
+                        Summary {
+                            block_presence: MultiBlockPresence::Full,
+                            ..Summary::INCOMPLETE
+                        },
+                    ),
+                )
+            })
+            .collect();
+        let content = Content::Response(Response::InnerNodes(
+            inner_nodes,
+            ResponseDisambiguator::new(MultiBlockPresence::Full),
+            debug_response(),
+        ));
+
+        let plain_len = plain.encode(&content).len();
+        let compressed_len = compressed.encode(&content).len();
+
+        assert!(
+            compressed_len * 4 < plain_len,
+            "plain: {plain_len}, compressed: {compressed_len}"
+        );
+    }
+
+    #[test]
+    fn dont_compress_blocks() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let plain = MessageCodec::new(FIRST_COMPRESSED_VERSION);
+        let compressed = plain.with_compression(true);
+
+        // Zero content would be very compressible but blocks are sent as they are.
+        let block = Block::new(BlockContent::new(), rng.gen());
+        let content = Content::Response(Response::Block(
+            block.content,
+            block.nonce,
+            debug_response(),
+        ));
+
+        assert_eq!(compressed.encode(&content), plain.encode(&content));
+    }
+
+    #[test]
+    fn reject_malformed_compressed() {
+        let codec = MessageCodec::new(FIRST_COMPRESSED_VERSION);
+
+        // Unknown flag
+        assert!(codec.decode(&[2, 0, 0]).is_err());
+        // Missing length
+        assert!(codec.decode(&[FLAG_LZ4, 0]).is_err());
+        // Too long
+        let mut bytes = vec![FLAG_LZ4];
+        bytes.extend_from_slice(&(MAX_DECOMPRESSED_LEN as u32 + 1).to_le_bytes());
+        bytes.extend_from_slice(&lz4_flex::block::compress(&[0; 16]));
+        assert!(codec.decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn reject_trailing_bytes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let content = Content::Request(Request::Block(
+            rng.gen(),
+            PendingDebugRequest::start().send(),
+        ));
+
+        let mut encoded = MessageCodec::Compact.encode(&content);
+        encoded.push(0);
+
+        assert!(MessageCodec::Compact.decode(&encoded).is_err());
+    }
+
+    // One instance of every `Request` and `Response` variant.
+    fn sample_contents(rng: &mut StdRng) -> Vec<Content> {
+        let write_keys = Keypair::generate(rng);
+        let writer_id = write_keys.public_key();
+        let proof = Proof::new(
+            writer_id,
+            VersionVector::first(writer_id),
+            rng.gen(),
+            &write_keys,
+        );
+        let presence = MultiBlockPresence::Some(rng.gen());
+        let disambiguator = ResponseDisambiguator::new(presence);
+
+        let inner_nodes: InnerNodes = (0..16)
+            .map(|bucket| {
+                (
+                    bucket,
+                    InnerNode::new(
+                        rng.gen(),
+                        Summary {
+                            block_presence: MultiBlockPresence::Full,
+                            ..Summary::INCOMPLETE
+                        },
+                    ),
+                )
+            })
+            .collect();
+
+        let mut leaf_nodes = LeafNodes::default();
+        for _ in 0..16 {
+            leaf_nodes.insert(rng.gen(), rng.gen(), SingleBlockPresence::Present);
+        }
+
+        let block: Block = rng.gen();
+
+        let requests = [
+            Request::RootNode(writer_id, debug_request()),
+            Request::ChildNodes(rng.gen(), disambiguator, debug_request()),
+            Request::Block(block.id, debug_request()),
+            Request::BlockChunks(block.id, 1024, debug_request()),
+        ];
+
+        let chunk = BlockChunk::split(block.id, &block.content, block.nonce, 0)
+            .next()
+            .unwrap();
+
+        let responses = [
+            Response::RootNode(proof.into(), presence, debug_response()),
+            Response::RootNodeError(writer_id, debug_response()),
+            Response::InnerNodes(inner_nodes, disambiguator, debug_response()),
+            Response::LeafNodes(leaf_nodes, disambiguator, debug_response()),
+            Response::ChildNodesError(rng.gen(), disambiguator, debug_response()),
+            Response::BlockOffer(block.id, debug_response()),
+            Response::Block(block.content, block.nonce, debug_response()),
+            Response::BlockError(block.id, debug_response()),
+            Response::BlockChunk(chunk, debug_response()),
+        ];
+
+        requests
+            .into_iter()
+            .map(Content::Request)
+            .chain(responses.into_iter().map(Content::Response))
+            .collect()
+    }
+
+    fn debug_request() -> DebugRequest {
+        PendingDebugRequest::start().send()
+    }
+
+    fn debug_response() -> DebugResponse {
+        DebugResponse::unsolicited()
+    }
+}
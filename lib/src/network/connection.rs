@@ -1,8 +1,12 @@
 use super::{
-    peer_addr::PeerAddr, peer_info::PeerInfo, peer_source::PeerSource, peer_state::PeerState,
-    runtime_id::PublicRuntimeId, traffic_tracker::TrafficTracker,
+    clock_skew::ClockSkew, peer_addr::PeerAddr, peer_info::PeerInfo, peer_source::PeerSource,
+    peer_state::PeerState, protocol::Version, runtime_id::PublicRuntimeId,
+    traffic_tracker::TrafficTracker,
+};
+use crate::{
+    collections::{hash_map::Entry, HashMap},
+    repository::RepositoryId,
 };
-use crate::collections::{hash_map::Entry, HashMap};
 use deadlock::BlockingMutex;
 use serde::Serialize;
 use std::{
@@ -31,7 +35,7 @@ use crate::sync::{uninitialized_watch, AwaitDrop, DropAwaitable};
 /// Prevents establishing duplicate connections.
 pub(super) struct ConnectionDeduplicator {
     next_id: AtomicU64,
-    connections: Arc<BlockingMutex<HashMap<ConnectionInfo, Peer>>>,
+    connections: Arc<BlockingMutex<HashMap<ConnectionKey, Peer>>>,
     on_change_tx: uninitialized_watch::Sender<()>,
 }
 
@@ -51,7 +55,7 @@ impl ConnectionDeduplicator {
     /// lives. Otherwise it returns `None`. To release a connection the permit needs to be dropped.
     /// Also returns a notification object that can be used to wait until the permit gets released.
     pub fn reserve(&self, addr: PeerAddr, source: PeerSource) -> ReserveResult {
-        let info = ConnectionInfo {
+        let info = ConnectionKey {
             addr,
             dir: ConnectionDirection::from_source(source),
         };
@@ -66,6 +70,8 @@ impl ConnectionDeduplicator {
                     state: PeerState::Known,
                     source,
                     tracker: TrafficTracker::new(),
+                    clock_skew: None,
+                    protocol_version: None,
                     on_release: on_release_tx,
                 });
                 self.on_change_tx.send(()).unwrap_or(());
@@ -95,11 +101,11 @@ impl ConnectionDeduplicator {
     pub fn get_peer_info(&self, addr: PeerAddr) -> Option<PeerInfo> {
         let connections = self.connections.lock().unwrap();
 
-        let incoming = ConnectionInfo {
+        let incoming = ConnectionKey {
             addr,
             dir: ConnectionDirection::Incoming,
         };
-        let outgoing = ConnectionInfo {
+        let outgoing = ConnectionKey {
             addr,
             dir: ConnectionDirection::Outgoing,
         };
@@ -115,6 +121,30 @@ impl ConnectionDeduplicator {
             })
     }
 
+    /// Returns info about the connections that completed the handshake. The `bad_blocks` and
+    /// `repositories` fields are left empty, it's up to the caller to fill them in.
+    pub fn active_connections(&self) -> Vec<ConnectionInfo> {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(key, peer)| match peer.state {
+                PeerState::Active { id, since } => Some(ConnectionInfo {
+                    addr: key.addr,
+                    direction: key.dir,
+                    source: peer.source,
+                    runtime_id: id,
+                    since,
+                    clock_skew: peer.clock_skew,
+                    protocol_version: peer.protocol_version.map(Into::into),
+                    bad_blocks: 0,
+                    repositories: Vec::new(),
+                }),
+                PeerState::Known | PeerState::Connecting | PeerState::Handshaking => None,
+            })
+            .collect()
+    }
+
     pub fn on_change(&self) -> uninitialized_watch::Receiver<()> {
         self.on_change_tx.subscribe()
     }
@@ -127,7 +157,7 @@ pub(super) enum ReserveResult {
 }
 
 #[derive(Clone)]
-pub struct PeerInfoCollector(Arc<BlockingMutex<HashMap<ConnectionInfo, Peer>>>);
+pub struct PeerInfoCollector(Arc<BlockingMutex<HashMap<ConnectionKey, Peer>>>);
 
 impl PeerInfoCollector {
     pub fn collect(&self) -> Vec<PeerInfo> {
@@ -150,11 +180,33 @@ pub(super) struct Peer {
     state: PeerState,
     source: PeerSource,
     tracker: TrafficTracker,
+    clock_skew: Option<ClockSkew>,
+    protocol_version: Option<Version>,
     on_release: DropAwaitable,
 }
 
+/// Information about an active connection to a peer.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ConnectionInfo {
+    pub addr: PeerAddr,
+    pub direction: ConnectionDirection,
+    pub source: PeerSource,
+    /// Runtime id of the peer on the other end of the connection.
+    pub runtime_id: PublicRuntimeId,
+    /// When the connection became active.
+    pub since: SystemTime,
+    /// Difference between the peer's clock and ours, if known.
+    pub clock_skew: Option<ClockSkew>,
+    /// Protocol version negotiated with the peer during the handshake.
+    pub protocol_version: Option<u32>,
+    /// Number of invalid blocks received from the peer.
+    pub bad_blocks: u64,
+    /// Ids of the local repositories currently linked with the peer.
+    pub repositories: Vec<RepositoryId>,
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize)]
-pub(super) enum ConnectionDirection {
+pub enum ConnectionDirection {
     Incoming,
     Outgoing,
 }
@@ -166,7 +218,8 @@ impl ConnectionDirection {
             PeerSource::UserProvided
             | PeerSource::LocalDiscovery
             | PeerSource::Dht
-            | PeerSource::PeerExchange => Self::Outgoing,
+            | PeerSource::PeerExchange
+            | PeerSource::KnownPeer => Self::Outgoing,
         }
     }
 }
@@ -174,8 +227,8 @@ impl ConnectionDirection {
 /// Connection permit that prevents another connection to the same peer (socket address) to be
 /// established as long as it remains in scope.
 pub(super) struct ConnectionPermit {
-    connections: Arc<BlockingMutex<HashMap<ConnectionInfo, Peer>>>,
-    info: ConnectionInfo,
+    connections: Arc<BlockingMutex<HashMap<ConnectionKey, Peer>>>,
+    info: ConnectionKey,
     id: PermitId,
     on_deduplicator_change: uninitialized_watch::Sender<()>,
 }
@@ -213,6 +266,26 @@ impl ConnectionPermit {
         });
     }
 
+    pub fn set_clock_skew(&self, clock_skew: ClockSkew) {
+        // unwrap is ok because if `self` exists then the entry should exists as well.
+        self.connections
+            .lock()
+            .unwrap()
+            .get_mut(&self.info)
+            .unwrap()
+            .clock_skew = Some(clock_skew);
+    }
+
+    pub fn set_protocol_version(&self, version: Version) {
+        // unwrap is ok because if `self` exists then the entry should exists as well.
+        self.connections
+            .lock()
+            .unwrap()
+            .get_mut(&self.info)
+            .unwrap()
+            .protocol_version = Some(version);
+    }
+
     fn set_state(&self, new_state: PeerState) {
         let mut lock = self.connections.lock().unwrap();
 
@@ -241,6 +314,12 @@ impl ConnectionPermit {
         self.id
     }
 
+    /// Traffic tracker of the connection.
+    pub fn tracker(&self) -> TrafficTracker {
+        self.with_peer(|peer| peer.tracker.clone())
+            .unwrap_or_default()
+    }
+
     pub fn source(&self) -> PeerSource {
         // unwrap is ok because if `self` exists then the entry should exists as well.
         self.with_peer(|peer| peer.source).unwrap()
@@ -251,7 +330,7 @@ impl ConnectionPermit {
     pub fn dummy() -> Self {
         use std::net::Ipv4Addr;
 
-        let info = ConnectionInfo {
+        let info = ConnectionKey {
             addr: PeerAddr::Tcp((Ipv4Addr::UNSPECIFIED, 0).into()),
             dir: ConnectionDirection::Incoming,
         };
@@ -261,6 +340,8 @@ impl ConnectionPermit {
             state: PeerState::Known,
             source: PeerSource::UserProvided,
             tracker: TrafficTracker::new(),
+            clock_skew: None,
+            protocol_version: None,
             on_release: DropAwaitable::new(),
         };
 
@@ -315,9 +396,7 @@ impl ConnectionPermitHalf {
     }
 
     pub fn tracker(&self) -> TrafficTracker {
-        self.0
-            .with_peer(|peer| peer.tracker.clone())
-            .unwrap_or_default()
+        self.0.tracker()
     }
 
     pub fn released(&self) -> AwaitDrop {
@@ -326,7 +405,7 @@ impl ConnectionPermitHalf {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub(super) struct ConnectionInfo {
+pub(super) struct ConnectionKey {
     pub addr: PeerAddr,
     pub dir: ConnectionDirection,
 }
@@ -4,19 +4,30 @@ use std::time::Duration;
 /// triggered.
 pub(super) const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Response to a request that has already timed out is still considered solicited if it arrives
+/// within this time after the timeout. This prevents counting late responses from slow peers as
+/// unsolicited (e.g., as invalid blocks).
+pub(super) const REQUEST_TIMEOUT_GRACE_PERIOD: Duration = REQUEST_TIMEOUT;
+
 /// Maximum number of requests that have been sent to a given peer but for which we haven't received
 /// a response yet. Higher values give better performance but too high risks congesting the
 /// network. There is also a point of diminishing returns. 32 seems to be the sweet spot based on a
 /// simple experiment.
 /// NOTE: This limit is protecting the peer against being overhelmed by too many requests from us.
+/// This is only the default, it can be changed with `Network::set_max_in_flight_requests_per_peer`.
 // TODO: run more precise benchmarks to find the actual optimum.
 pub(super) const MAX_IN_FLIGHT_REQUESTS_PER_PEER: usize = 32;
 
 /// Maximum number of requests that have been sent on a given `Client` but for which the response
 /// hasn't yet been processed (although it may have been received).
 /// NOTE: This limit is protecting us against being overhelmed by too many responses from the peer.
+/// This is only the default, it can be changed with `Network::set_max_pending_requests_per_link`.
 pub(super) const MAX_PENDING_REQUESTS_PER_CLIENT: usize = 2 * MAX_IN_FLIGHT_REQUESTS_PER_PEER;
 
+/// How often to check whether a connection has become idle (see
+/// `Network::set_connection_idle_timeout`).
+pub(super) const CONNECTION_IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Maximum number of unchoked peers at the same time.
 pub(super) const MAX_UNCHOKED_COUNT: usize = 3;
 /// Maximum duration that a peer remains unchoked.
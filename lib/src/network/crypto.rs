@@ -6,6 +6,12 @@
 //! Using the salted hash of the secret repository id as the pre-shared key. This way only the
 //! replicas that posses the secret repository id are able to communicate and no authentication
 //! based on the identity of the replicas is needed.
+//!
+//! The repository id is contained in every share token (including blind ones), so any replica the
+//! repository has been shared with can establish the channel while anyone else fails the
+//! handshake. The id of the message channel is itself only a salted hash of the repository id and
+//! neither the repository id nor the repository name is ever sent over the wire, so eavesdroppers
+//! can't learn which repositories are being synced.
 
 use super::{
     message_dispatcher::{ChannelClosed, ContentSink, ContentStream, ContentStreamError},
@@ -268,3 +274,99 @@ async fn handshake_recv(
     let content = stream.recv().await?;
     Ok(state.read_message_vec(&content)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{
+        connection::ConnectionPermit, message::MessageChannelId,
+        message_dispatcher::MessageDispatcher, raw,
+    };
+    use assert_matches::assert_matches;
+    use net::tcp::{TcpListener, TcpStream};
+    use std::{net::Ipv4Addr, time::Duration};
+    use tokio::time;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn establish_with_same_repository_id() {
+        let repo_id = RepositoryId::random();
+        let (_dispatchers, [(mut a_stream, mut a_sink), (mut b_stream, mut b_sink)]) =
+            setup().await;
+
+        let (a, b) = tokio::join!(
+            establish_channel(
+                Role::Initiator,
+                &repo_id,
+                &mut a_stream,
+                &mut a_sink,
+                TrafficTracker::new()
+            ),
+            establish_channel(
+                Role::Responder,
+                &repo_id,
+                &mut b_stream,
+                &mut b_sink,
+                TrafficTracker::new()
+            ),
+        );
+
+        let (_a_stream, mut a_sink) = a.unwrap();
+        let (mut b_stream, _b_sink) = b.unwrap();
+
+        a_sink.send(b"hello world".to_vec()).await.unwrap();
+        assert_eq!(b_stream.recv().await.unwrap(), b"hello world");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn establish_with_different_repository_id() {
+        let (_dispatchers, [(mut a_stream, mut a_sink), (mut b_stream, mut b_sink)]) =
+            setup().await;
+
+        let (_, b) = tokio::join!(
+            // The initiator never gets a response so it would wait forever.
+            time::timeout(
+                Duration::from_secs(1),
+                establish_channel(
+                    Role::Initiator,
+                    &RepositoryId::random(),
+                    &mut a_stream,
+                    &mut a_sink,
+                    TrafficTracker::new()
+                )
+            ),
+            establish_channel(
+                Role::Responder,
+                &RepositoryId::random(),
+                &mut b_stream,
+                &mut b_sink,
+                TrafficTracker::new()
+            ),
+        );
+
+        assert_matches!(b, Err(EstablishError::Crypto));
+    }
+
+    async fn setup() -> ([MessageDispatcher; 2], [(ContentStream, ContentSink); 2]) {
+        let channel = MessageChannelId::random();
+
+        let a = MessageDispatcher::new();
+        let b = MessageDispatcher::new();
+
+        // Open the channels before binding the sockets so no message is missed.
+        let a_channel = (a.open_recv(channel), a.open_send(channel));
+        let b_channel = (b.open_recv(channel), b.open_send(channel));
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0u16))
+            .await
+            .unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        a.bind(raw::Stream::Tcp(client), ConnectionPermit::dummy());
+        b.bind(raw::Stream::Tcp(server), ConnectionPermit::dummy());
+
+        ([a, b], [a_channel, b_channel])
+    }
+}
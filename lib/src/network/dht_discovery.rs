@@ -1,4 +1,5 @@
 use super::{
+    event::{NetworkEvent, NetworkEventSender},
     peer_addr::PeerAddr,
     seen_peers::{SeenPeer, SeenPeers},
 };
@@ -25,7 +26,7 @@ use std::{
 use tokio::{
     select,
     sync::{mpsc, watch},
-    time::{self, timeout, Duration},
+    time::{self, timeout, Duration, Instant},
 };
 use tracing::{instrument::Instrument, Span};
 
@@ -45,6 +46,17 @@ pub const DHT_ROUTERS: &[&str] = &[
 pub const MIN_DHT_ANNOUNCE_DELAY: Duration = Duration::from_secs(3 * 60);
 pub const MAX_DHT_ANNOUNCE_DELAY: Duration = Duration::from_secs(6 * 60);
 
+// If the repository hasn't changed for this long, it's considered idle and the re-announce delay
+// is picked from the (longer) idle interval below instead. The first change of an idle repository
+// triggers an immediate re-announce.
+pub const DHT_IDLE_THRESHOLD: Duration = Duration::from_secs(30 * 60);
+pub const MIN_IDLE_DHT_ANNOUNCE_DELAY: Duration = Duration::from_secs(9 * 60);
+pub const MAX_IDLE_DHT_ANNOUNCE_DELAY: Duration = Duration::from_secs(12 * 60);
+
+// Minimum interval between two searches requested explicitly (see `LookupRequest::search_now`),
+// to not flood the DHT when they are requested repeatedly (e.g., by the user spamming a button).
+pub const MIN_EXPLICIT_DHT_SEARCH_INTERVAL: Duration = Duration::from_secs(30);
+
 #[async_trait]
 pub trait DhtContactsStoreTrait: Sync + Send + 'static {
     async fn load_v4(&self) -> io::Result<HashSet<SocketAddrV4>>;
@@ -68,10 +80,19 @@ impl DhtDiscovery {
         socket_maker_v4: Option<quic::SideChannelMaker>,
         socket_maker_v6: Option<quic::SideChannelMaker>,
         contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+        events_tx: NetworkEventSender,
         monitor: StateMonitor,
     ) -> Self {
-        let v4 = BlockingMutex::new(RestartableDht::new(socket_maker_v4, contacts_store.clone()));
-        let v6 = BlockingMutex::new(RestartableDht::new(socket_maker_v6, contacts_store));
+        let v4 = BlockingMutex::new(RestartableDht::new(
+            socket_maker_v4,
+            contacts_store.clone(),
+            events_tx.clone(),
+        ));
+        let v6 = BlockingMutex::new(RestartableDht::new(
+            socket_maker_v6,
+            contacts_store,
+            events_tx,
+        ));
 
         let lookups = Arc::new(BlockingMutex::new(HashMap::default()));
 
@@ -122,11 +143,28 @@ impl DhtDiscovery {
         }
     }
 
+    /// Makes all the ongoing lookups search for peers (and announce) again right away instead of
+    /// waiting for their next scheduled round.
+    pub fn wake_up_lookups(&self) {
+        for lookup in self.lookups.lock().unwrap().values() {
+            lookup.wake_up_tx.send(()).unwrap_or(());
+        }
+    }
+
+    /// Starts looking up peers for the given info hash. If `announce` is true, the lookup also
+    /// announces that we have it. Otherwise it only searches passively without revealing us to the
+    /// other peers (unless another request for the same info hash announces).
     pub fn start_lookup(
         &self,
         info_hash: InfoHash,
+        announce: bool,
         found_peers_tx: mpsc::UnboundedSender<SeenPeer>,
     ) -> LookupRequest {
+        let requester = Requester {
+            found_peers_tx,
+            announce,
+        };
+
         let id = self.next_id.fetch_add(1, Ordering::Relaxed);
 
         let request = LookupRequest {
@@ -138,7 +176,7 @@ impl DhtDiscovery {
         let mut lookups = self.lookups.lock().unwrap();
 
         match lookups.entry(info_hash) {
-            hash_map::Entry::Occupied(mut entry) => entry.get_mut().add_request(id, found_peers_tx),
+            hash_map::Entry::Occupied(mut entry) => entry.get_mut().add_request(id, requester),
             hash_map::Entry::Vacant(entry) => {
                 let dht_v4 = self
                     .v4
@@ -160,7 +198,7 @@ impl DhtDiscovery {
                         &self.lookups_monitor,
                         &self.span,
                     ))
-                    .add_request(id, found_peers_tx);
+                    .add_request(id, requester);
             }
         }
 
@@ -173,17 +211,20 @@ struct RestartableDht {
     socket_maker: Option<quic::SideChannelMaker>,
     dht: Weak<Option<TaskOrResult<MonitoredDht>>>,
     contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+    events_tx: NetworkEventSender,
 }
 
 impl RestartableDht {
     fn new(
         socket_maker: Option<quic::SideChannelMaker>,
         contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+        events_tx: NetworkEventSender,
     ) -> Self {
         Self {
             socket_maker,
             dht: Weak::new(),
             contacts_store,
+            events_tx,
         }
     }
 
@@ -198,7 +239,13 @@ impl RestartableDht {
             dht
         } else if let Some(maker) = &self.socket_maker {
             let socket = maker.make();
-            let dht = MonitoredDht::start(socket, monitor, span, self.contacts_store.clone());
+            let dht = MonitoredDht::start(
+                socket,
+                monitor,
+                span,
+                self.contacts_store.clone(),
+                self.events_tx.clone(),
+            );
 
             let dht = Arc::new(Some(dht));
 
@@ -229,6 +276,7 @@ impl MonitoredDht {
         parent_monitor: &StateMonitor,
         span: &Span,
         contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+        events_tx: NetworkEventSender,
     ) -> TaskOrResult<Self> {
         // TODO: Unwrap
         let local_addr = socket.local_addr().unwrap();
@@ -246,6 +294,7 @@ impl MonitoredDht {
             monitor,
             span,
             contacts_store,
+            events_tx,
         )))
     }
 
@@ -255,8 +304,16 @@ impl MonitoredDht {
         monitor: StateMonitor,
         span: Span,
         contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+        events_tx: NetworkEventSender,
     ) -> Self {
+        // TODO: Unwrap
+        let local_addr = socket.local_addr().unwrap();
+
         // TODO: load the DHT state from a previous save if it exists.
+        // Note: "read-only" refers to the participation of this node in the DHT itself (whether it
+        // responds to queries from other nodes), not to announcing. This node is shared by all
+        // the repositories and announcing is controlled per lookup (see `start_lookup`), so it
+        // always participates fully.
         let mut builder = MainlineDht::builder()
             .add_routers(DHT_ROUTERS.iter().copied())
             .set_read_only(false);
@@ -293,9 +350,11 @@ impl MonitoredDht {
                 if dht.bootstrapped().await {
                     *first_bootstrap.get() = "done";
                     tracing::info!("bootstrap complete");
+                    events_tx.send(NetworkEvent::DhtBootstrapped(local_addr));
                 } else {
                     *first_bootstrap.get() = "failed";
                     tracing::error!("bootstrap failed");
+                    events_tx.send(NetworkEvent::DhtBootstrapFailed(local_addr));
 
                     // Don't `return`, instead halt here so that the `first_bootstrap` monitored value
                     // is preserved for the user to see.
@@ -426,12 +485,62 @@ type Lookups = HashMap<InfoHash, Lookup>;
 
 type RequestId = u64;
 
+struct Requester {
+    found_peers_tx: mpsc::UnboundedSender<SeenPeer>,
+    announce: bool,
+}
+
 pub struct LookupRequest {
     id: RequestId,
     info_hash: InfoHash,
     lookups: Weak<BlockingMutex<Lookups>>,
 }
 
+impl LookupRequest {
+    /// Notifies the lookup that the repository changed. This resets the idle period and if the
+    /// repository was idle, triggers an immediate re-announce.
+    pub fn mark_changed(&self) {
+        let Some(lookups) = self.lookups.upgrade() else {
+            return;
+        };
+
+        if let Some(lookup) = lookups.lock().unwrap().get(&self.info_hash) {
+            lookup.changed_tx.send(()).unwrap_or(());
+        }
+    }
+
+    /// Searches for peers (and announces, if requested) right away instead of waiting for the next
+    /// scheduled round. The following round is then scheduled relative to this search. Rate
+    /// limited to one per `MIN_EXPLICIT_DHT_SEARCH_INTERVAL` per info hash. Returns whether the
+    /// search has been triggered.
+    pub fn search_now(&self) -> bool {
+        let Some(lookups) = self.lookups.upgrade() else {
+            return false;
+        };
+
+        let mut lookups = lookups.lock().unwrap();
+
+        let Some(lookup) = lookups.get_mut(&self.info_hash) else {
+            return false;
+        };
+
+        // DHT is not running.
+        if lookup.task.is_none() {
+            return false;
+        }
+
+        if lookup
+            .last_explicit_search
+            .is_some_and(|last| last.elapsed() < MIN_EXPLICIT_DHT_SEARCH_INTERVAL)
+        {
+            return false;
+        }
+
+        lookup.last_explicit_search = Some(Instant::now());
+        lookup.wake_up_tx.send(()).is_ok()
+    }
+}
+
 impl Drop for LookupRequest {
     fn drop(&mut self) {
         if let Some(lookups) = self.lookups.upgrade() {
@@ -454,9 +563,11 @@ impl Drop for LookupRequest {
 
 struct Lookup {
     seen_peers: Arc<SeenPeers>,
-    requests: Arc<BlockingMutex<HashMap<RequestId, mpsc::UnboundedSender<SeenPeer>>>>,
+    requests: Arc<BlockingMutex<HashMap<RequestId, Requester>>>,
     wake_up_tx: watch::Sender<()>,
+    changed_tx: watch::Sender<()>,
     task: Option<ScopedJoinHandle<()>>,
+    last_explicit_search: Option<Instant>,
 }
 
 impl Lookup {
@@ -472,6 +583,8 @@ impl Lookup {
         // but only when we create the first request.
         wake_up_rx.borrow_and_update();
 
+        let (changed_tx, changed_rx) = watch::channel(());
+
         let seen_peers = Arc::new(SeenPeers::new());
         let requests = Arc::new(BlockingMutex::new(HashMap::default()));
 
@@ -483,6 +596,7 @@ impl Lookup {
                 seen_peers.clone(),
                 requests.clone(),
                 wake_up_rx,
+                changed_rx,
                 monitor,
                 span,
             ))
@@ -494,7 +608,9 @@ impl Lookup {
             seen_peers,
             requests,
             wake_up_tx,
+            changed_tx,
             task,
+            last_explicit_search: None,
         }
     }
 
@@ -519,6 +635,7 @@ impl Lookup {
             self.seen_peers.clone(),
             self.requests.clone(),
             self.wake_up_tx.subscribe(),
+            self.changed_tx.subscribe(),
             monitor,
             span,
         );
@@ -527,12 +644,12 @@ impl Lookup {
         self.wake_up_tx.send(()).ok();
     }
 
-    fn add_request(&mut self, id: RequestId, tx: mpsc::UnboundedSender<SeenPeer>) {
+    fn add_request(&mut self, id: RequestId, requester: Requester) {
         for peer in self.seen_peers.collect() {
-            tx.send(peer.clone()).unwrap_or(());
+            requester.found_peers_tx.send(peer.clone()).unwrap_or(());
         }
 
-        self.requests.lock().unwrap().insert(id, tx);
+        self.requests.lock().unwrap().insert(id, requester);
         // `unwrap_or` because if the network is down, there should be no tasks that listen to this
         // wake up request.
         self.wake_up_tx.send(()).unwrap_or(());
@@ -544,8 +661,9 @@ impl Lookup {
         dht_v6: Arc<Option<TaskOrResult<MonitoredDht>>>,
         info_hash: InfoHash,
         seen_peers: Arc<SeenPeers>,
-        requests: Arc<BlockingMutex<HashMap<RequestId, mpsc::UnboundedSender<SeenPeer>>>>,
+        requests: Arc<BlockingMutex<HashMap<RequestId, Requester>>>,
         mut wake_up: watch::Receiver<()>,
+        mut changed: watch::Receiver<()>,
         lookups_monitor: &StateMonitor,
         span: &Span,
     ) -> ScopedJoinHandle<()> {
@@ -567,13 +685,22 @@ impl Lookup {
             // Wait for the first request to be created
             wake_up.changed().await.unwrap_or(());
 
+            let mut activity = Activity::new();
+
             loop {
                 seen_peers.start_new_round();
 
-                tracing::debug!(?info_hash, "starting search");
+                // Announce only if at least one of the requests wants it.
+                let announce = requests
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .any(|requester| requester.announce);
+
+                tracing::debug!(?info_hash, announce, "starting search");
                 *state.get() = "making request";
 
-                // find peers for the repo and also announce that we have it.
+                // find peers for the repo and, if enabled, also announce that we have it.
                 let dhts = dht_v4.iter().chain(dht_v6.iter());
 
                 let mut peers = Box::pin(stream::iter(dhts).flat_map(|dht| {
@@ -581,7 +708,7 @@ impl Lookup {
                         timeout(Duration::from_secs(10), dht.dht.bootstrapped())
                             .await
                             .unwrap_or(false);
-                        dht.dht.search(info_hash, true)
+                        dht.dht.search(info_hash, announce)
                     })
                     .flatten()
                 }));
@@ -590,21 +717,28 @@ impl Lookup {
 
                 while let Some(addr) = peers.next().await {
                     if let Some(peer) = seen_peers.insert(PeerAddr::Quic(addr)) {
-                        for tx in requests.lock().unwrap().values() {
-                            tx.send(peer.clone()).unwrap_or(());
+                        for requester in requests.lock().unwrap().values() {
+                            requester.found_peers_tx.send(peer.clone()).unwrap_or(());
                         }
                     }
                 }
 
+                if changed.has_changed().unwrap_or(false) {
+                    changed.borrow_and_update();
+                    activity.mark_changed();
+                }
+
+                let idle = activity.is_idle();
+
                 // sleep a random duration before the next search, but wake up if there is a new
-                // request.
-                let duration =
-                    rand::thread_rng().gen_range(MIN_DHT_ANNOUNCE_DELAY..MAX_DHT_ANNOUNCE_DELAY);
+                // request or, if the repository is idle, when it changes.
+                let duration = activity.next_delay(&mut rand::thread_rng());
 
                 {
                     let time: DateTime<Local> = (SystemTime::now() + duration).into();
                     tracing::debug!(
                         ?info_hash,
+                        idle,
                         "search ended. next one scheduled at {} (in {:?})",
                         time.format("%T"),
                         duration
@@ -617,6 +751,10 @@ impl Lookup {
                 select! {
                     _ = time::sleep(duration) => (),
                     _ = wake_up.changed() => (),
+                    Ok(()) = changed.changed(), if idle => {
+                        tracing::debug!(?info_hash, "idle repository changed");
+                        activity.mark_changed();
+                    }
                 }
             }
         };
@@ -644,6 +782,36 @@ impl btdht::SocketTrait for Socket {
     }
 }
 
+// Tracks when the repository last changed to tell whether it's idle.
+struct Activity {
+    last_changed: Instant,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            last_changed: Instant::now(),
+        }
+    }
+
+    fn mark_changed(&mut self) {
+        self.last_changed = Instant::now();
+    }
+
+    fn is_idle(&self) -> bool {
+        self.last_changed.elapsed() >= DHT_IDLE_THRESHOLD
+    }
+
+    // Picks a random delay until the next announce, longer if the repository is idle.
+    fn next_delay<R: Rng>(&self, rng: &mut R) -> Duration {
+        if self.is_idle() {
+            rng.gen_range(MIN_IDLE_DHT_ANNOUNCE_DELAY..MAX_IDLE_DHT_ANNOUNCE_DELAY)
+        } else {
+            rng.gen_range(MIN_DHT_ANNOUNCE_DELAY..MAX_DHT_ANNOUNCE_DELAY)
+        }
+    }
+}
+
 struct TaskOrResult<T> {
     task: AsyncMutex<Option<ScopedJoinHandle<T>>>,
     result: once_cell::sync::OnceCell<T>,
@@ -675,3 +843,31 @@ impl<T> TaskOrResult<T> {
         self.result.get().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn activity_becomes_idle() {
+        let mut rng = rand::thread_rng();
+        let mut activity = Activity::new();
+
+        assert!(!activity.is_idle());
+        let delay = activity.next_delay(&mut rng);
+        assert!((MIN_DHT_ANNOUNCE_DELAY..MAX_DHT_ANNOUNCE_DELAY).contains(&delay));
+
+        time::advance(DHT_IDLE_THRESHOLD).await;
+
+        assert!(activity.is_idle());
+        let delay = activity.next_delay(&mut rng);
+        assert!((MIN_IDLE_DHT_ANNOUNCE_DELAY..MAX_IDLE_DHT_ANNOUNCE_DELAY).contains(&delay));
+
+        // A change makes it active again.
+        activity.mark_changed();
+
+        assert!(!activity.is_idle());
+        let delay = activity.next_delay(&mut rng);
+        assert!((MIN_DHT_ANNOUNCE_DELAY..MAX_DHT_ANNOUNCE_DELAY).contains(&delay));
+    }
+}
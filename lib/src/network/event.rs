@@ -0,0 +1,46 @@
+use super::{peer_addr::PeerAddr, peer_source::PeerSource, runtime_id::PublicRuntimeId};
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+
+const CAPACITY: usize = 256;
+
+/// Network lifecycle events. See [`Network::subscribe`](super::Network::subscribe).
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum NetworkEvent {
+    /// Connection to a peer has been established (the handshake succeeded).
+    PeerConnected {
+        addr: PeerAddr,
+        runtime_id: PublicRuntimeId,
+        source: PeerSource,
+    },
+    /// Connection to a previously connected peer has been lost.
+    PeerDisconnected {
+        addr: PeerAddr,
+        runtime_id: PublicRuntimeId,
+        source: PeerSource,
+    },
+    /// The DHT bound to the given local address finished bootstrapping.
+    DhtBootstrapped(SocketAddr),
+    /// The DHT bound to the given local address failed to bootstrap.
+    DhtBootstrapFailed(SocketAddr),
+    /// Listener has been bound to the given local address.
+    ListenerBound(PeerAddr),
+}
+
+#[derive(Clone)]
+pub(super) struct NetworkEventSender(broadcast::Sender<NetworkEvent>);
+
+impl NetworkEventSender {
+    pub fn new() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+
+    pub fn send(&self, event: NetworkEvent) {
+        // Error means there are no subscribers which is fine.
+        self.0.send(event).unwrap_or(0);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.0.subscribe()
+    }
+}
@@ -40,28 +40,7 @@ impl Gateway {
     }
 
     pub fn listener_local_addrs(&self) -> Vec<PeerAddr> {
-        let stacks = self.stacks.read();
-        [
-            stacks
-                .quic_listener_local_addr_v4()
-                .copied()
-                .map(PeerAddr::Quic),
-            stacks
-                .quic_listener_local_addr_v6()
-                .copied()
-                .map(PeerAddr::Quic),
-            stacks
-                .tcp_listener_local_addr_v4()
-                .copied()
-                .map(PeerAddr::Tcp),
-            stacks
-                .tcp_listener_local_addr_v6()
-                .copied()
-                .map(PeerAddr::Tcp),
-        ]
-        .into_iter()
-        .flatten()
-        .collect()
+        self.stacks.read().listener_local_addrs()
     }
 
     /// Binds the gateway to the specified addresses. Rebinds if already bound.
@@ -78,19 +57,19 @@ impl Gateway {
         let prev = self.stacks.swap(next);
         let next = self.stacks.read();
 
-        if prev.quic_v4.is_some() && next.quic_v4.is_none() {
+        if !prev.quic_v4.is_empty() && next.quic_v4.is_empty() {
             tracing::info!("Terminated IPv4 QUIC stack");
         }
 
-        if prev.quic_v6.is_some() && next.quic_v6.is_none() {
+        if !prev.quic_v6.is_empty() && next.quic_v6.is_empty() {
             tracing::info!("Terminated IPv6 QUIC stack");
         }
 
-        if prev.tcp_v4.is_some() && next.tcp_v4.is_none() {
+        if !prev.tcp_v4.is_empty() && next.tcp_v4.is_empty() {
             tracing::info!("Terminated IPv4 TCP stack");
         }
 
-        if prev.tcp_v6.is_some() && next.tcp_v6.is_none() {
+        if !prev.tcp_v6.is_empty() && next.tcp_v6.is_empty() {
             tracing::info!("Terminated IPv6 TCP stack");
         }
 
@@ -99,6 +78,12 @@ impl Gateway {
         (side_channel_maker_v4, side_channel_maker_v6)
     }
 
+    /// Closes all the stacks, leaving the gateway unbound until the next `bind`.
+    pub fn unbind(&self) {
+        let prev = self.stacks.swap(Stacks::unbound());
+        prev.close();
+    }
+
     pub async fn connect_with_retries(
         &self,
         peer: &SeenPeer,
@@ -183,20 +168,23 @@ impl ConnectError {
     }
 }
 
+/// Bound stacks of each protocol and family. The first stack of each is the primary one, used also
+/// for outgoing connections and as the side channel for DHT and STUN. The rest (only when bound
+/// with `BindMode::All`) only accept incoming connections.
 struct Stacks {
-    quic_v4: Option<QuicStack>,
-    quic_v6: Option<QuicStack>,
-    tcp_v4: Option<TcpStack>,
-    tcp_v6: Option<TcpStack>,
+    quic_v4: Vec<QuicStack>,
+    quic_v6: Vec<QuicStack>,
+    tcp_v4: Vec<TcpStack>,
+    tcp_v6: Vec<TcpStack>,
 }
 
 impl Stacks {
     fn unbound() -> Self {
         Self {
-            quic_v4: None,
-            quic_v6: None,
-            tcp_v4: None,
-            tcp_v6: None,
+            quic_v4: Vec::new(),
+            quic_v6: Vec::new(),
+            tcp_v4: Vec::new(),
+            tcp_v6: Vec::new(),
         }
     }
 
@@ -208,35 +196,12 @@ impl Stacks {
         Option<quic::SideChannelMaker>,
         Option<quic::SideChannelMaker>,
     ) {
-        let (quic_v4, side_channel_maker_v4) = if let Some(addr) = bind.quic_v4 {
-            QuicStack::new(addr, incoming_tx.clone())
-                .await
-                .map(|(stack, side_channel)| (Some(stack), Some(side_channel)))
-                .unwrap_or((None, None))
-        } else {
-            (None, None)
-        };
-
-        let (quic_v6, side_channel_maker_v6) = if let Some(addr) = bind.quic_v6 {
-            QuicStack::new(addr, incoming_tx.clone())
-                .await
-                .map(|(stack, side_channel)| (Some(stack), Some(side_channel)))
-                .unwrap_or((None, None))
-        } else {
-            (None, None)
-        };
-
-        let tcp_v4 = if let Some(addr) = bind.tcp_v4 {
-            TcpStack::new(addr, incoming_tx.clone()).await
-        } else {
-            None
-        };
-
-        let tcp_v6 = if let Some(addr) = bind.tcp_v6 {
-            TcpStack::new(addr, incoming_tx).await
-        } else {
-            None
-        };
+        let (quic_v4, side_channel_maker_v4) =
+            bind_quic_stacks(&bind.quic_v4, bind.mode, &incoming_tx).await;
+        let (quic_v6, side_channel_maker_v6) =
+            bind_quic_stacks(&bind.quic_v6, bind.mode, &incoming_tx).await;
+        let tcp_v4 = bind_tcp_stacks(&bind.tcp_v4, bind.mode, &incoming_tx).await;
+        let tcp_v6 = bind_tcp_stacks(&bind.tcp_v6, bind.mode, &incoming_tx).await;
 
         let this = Self {
             quic_v4,
@@ -250,31 +215,43 @@ impl Stacks {
 
     fn addresses(&self) -> StackAddresses {
         StackAddresses {
-            quic_v4: self.quic_v4.as_ref().map(|stack| stack.listener_local_addr),
-            quic_v6: self.quic_v6.as_ref().map(|stack| stack.listener_local_addr),
-            tcp_v4: self.tcp_v6.as_ref().map(|stack| stack.listener_local_addr),
-            tcp_v6: self.tcp_v6.as_ref().map(|stack| stack.listener_local_addr),
+            quic_v4: self
+                .quic_v4
+                .iter()
+                .map(|stack| stack.listener_local_addr)
+                .collect(),
+            quic_v6: self
+                .quic_v6
+                .iter()
+                .map(|stack| stack.listener_local_addr)
+                .collect(),
+            tcp_v4: self
+                .tcp_v4
+                .iter()
+                .map(|stack| stack.listener_local_addr)
+                .collect(),
+            tcp_v6: self
+                .tcp_v6
+                .iter()
+                .map(|stack| stack.listener_local_addr)
+                .collect(),
+            mode: BindMode::All,
         }
     }
 
-    fn quic_listener_local_addr_v4(&self) -> Option<&SocketAddr> {
-        self.quic_v4
-            .as_ref()
-            .map(|stack| &stack.listener_local_addr)
-    }
-
-    fn quic_listener_local_addr_v6(&self) -> Option<&SocketAddr> {
-        self.quic_v6
-            .as_ref()
-            .map(|stack| &stack.listener_local_addr)
-    }
-
-    fn tcp_listener_local_addr_v4(&self) -> Option<&SocketAddr> {
-        self.tcp_v4.as_ref().map(|stack| &stack.listener_local_addr)
-    }
-
-    fn tcp_listener_local_addr_v6(&self) -> Option<&SocketAddr> {
-        self.tcp_v6.as_ref().map(|stack| &stack.listener_local_addr)
+    fn listener_local_addrs(&self) -> Vec<PeerAddr> {
+        let quic = self
+            .quic_v4
+            .iter()
+            .chain(&self.quic_v6)
+            .map(|stack| PeerAddr::Quic(stack.listener_local_addr));
+        let tcp = self
+            .tcp_v4
+            .iter()
+            .chain(&self.tcp_v6)
+            .map(|stack| PeerAddr::Tcp(stack.listener_local_addr));
+
+        quic.chain(tcp).collect()
     }
 
     async fn connect(&self, addr: PeerAddr) -> Result<raw::Stream, ConnectError> {
@@ -353,20 +330,104 @@ impl Stacks {
 
     fn quic_stack_for(&self, ip: &IpAddr) -> Option<&QuicStack> {
         match ip {
-            IpAddr::V4(_) => self.quic_v4.as_ref(),
-            IpAddr::V6(_) => self.quic_v6.as_ref(),
+            IpAddr::V4(_) => self.quic_v4.first(),
+            IpAddr::V6(_) => self.quic_v6.first(),
         }
     }
 
     fn close(&self) {
-        if let Some(stack) = &self.quic_v4 {
+        for stack in self.quic_v4.iter().chain(&self.quic_v6) {
             stack.close();
         }
+    }
+}
 
-        if let Some(stack) = &self.quic_v6 {
+/// Binds QUIC stacks to `addrs`, trying them in order. With `BindMode::First` stops after the first
+/// one that succeeds. Returns the side channel maker of the first bound stack.
+async fn bind_quic_stacks(
+    addrs: &[SocketAddr],
+    mode: BindMode,
+    incoming_tx: &mpsc::Sender<(raw::Stream, PeerAddr)>,
+) -> (Vec<QuicStack>, Option<quic::SideChannelMaker>) {
+    let mut stacks = Vec::new();
+    let mut side_channel_maker = None;
+
+    for (index, addr) in addrs.iter().enumerate() {
+        let Some((stack, maker)) = QuicStack::new(*addr, incoming_tx.clone()).await else {
+            continue;
+        };
+
+        let last_resort = stacks.is_empty() && index + 1 == addrs.len();
+
+        if !check_bound_port(
+            PeerAddr::Quic(*addr),
+            PeerAddr::Quic(stack.listener_local_addr),
+            last_resort,
+        ) {
             stack.close();
+            continue;
+        }
+
+        stacks.push(stack);
+        side_channel_maker.get_or_insert(maker);
+
+        if mode == BindMode::First {
+            break;
+        }
+    }
+
+    (stacks, side_channel_maker)
+}
+
+/// Binds TCP stacks to `addrs`, trying them in order. With `BindMode::First` stops after the first
+/// one that succeeds.
+async fn bind_tcp_stacks(
+    addrs: &[SocketAddr],
+    mode: BindMode,
+    incoming_tx: &mpsc::Sender<(raw::Stream, PeerAddr)>,
+) -> Vec<TcpStack> {
+    let mut stacks = Vec::new();
+
+    for (index, addr) in addrs.iter().enumerate() {
+        let Some(stack) = TcpStack::new(*addr, incoming_tx.clone()).await else {
+            continue;
+        };
+
+        let last_resort = stacks.is_empty() && index + 1 == addrs.len();
+
+        if !check_bound_port(
+            PeerAddr::Tcp(*addr),
+            PeerAddr::Tcp(stack.listener_local_addr),
+            last_resort,
+        ) {
+            continue;
+        }
+
+        stacks.push(stack);
+
+        if mode == BindMode::First {
+            break;
         }
     }
+
+    stacks
+}
+
+// When the requested port is taken, the socket gets bound to a random port instead. Accept that
+// only as the last resort, that is, when there is no other address left to try and nothing else
+// has been bound.
+fn check_bound_port(requested: PeerAddr, bound: PeerAddr, last_resort: bool) -> bool {
+    if requested.port() == 0 || requested.port() == bound.port() || last_resort {
+        return true;
+    }
+
+    tracing::info!(
+        %requested,
+        %bound,
+        "Requested port not available, discarding listener"
+    );
+
+    false
 }
 
 struct QuicStack {
@@ -603,90 +664,109 @@ fn ok_to_connect(addr: &SocketAddr, source: PeerSource) -> bool {
     true
 }
 
+/// How to treat multiple addresses of the same protocol and family.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub(super) enum BindMode {
+    /// Try the addresses in order and use the first one that can be bound.
+    First,
+    /// Bind all the addresses that can be bound.
+    All,
+}
+
 #[derive(Debug)]
 pub(super) struct StackAddresses {
-    quic_v4: Option<SocketAddr>,
-    quic_v6: Option<SocketAddr>,
-    tcp_v4: Option<SocketAddr>,
-    tcp_v6: Option<SocketAddr>,
+    quic_v4: Vec<SocketAddr>,
+    quic_v6: Vec<SocketAddr>,
+    tcp_v4: Vec<SocketAddr>,
+    tcp_v6: Vec<SocketAddr>,
+    mode: BindMode,
 }
 
 impl StackAddresses {
+    pub(super) fn new(addrs: &[PeerAddr], mode: BindMode) -> Self {
+        let mut this = Self {
+            quic_v4: Vec::new(),
+            quic_v6: Vec::new(),
+            tcp_v4: Vec::new(),
+            tcp_v6: Vec::new(),
+            mode,
+        };
+
+        for addr in addrs {
+            match addr {
+                PeerAddr::Quic(addr @ SocketAddr::V4(_)) => this.quic_v4.push(*addr),
+                PeerAddr::Quic(addr @ SocketAddr::V6(_)) => this.quic_v6.push(*addr),
+                PeerAddr::Tcp(addr @ SocketAddr::V4(_)) => this.tcp_v4.push(*addr),
+                PeerAddr::Tcp(addr @ SocketAddr::V6(_)) => this.tcp_v6.push(*addr),
+            }
+        }
+
+        this
+    }
+
     pub(super) fn any_stack_needs_rebind(&self, new_stack_addresses: &StackAddresses) -> bool {
-        needs_rebind(&self.quic_v4, &new_stack_addresses.quic_v4)
-            || needs_rebind(&self.quic_v6, &new_stack_addresses.quic_v6)
-            || needs_rebind(&self.tcp_v4, &new_stack_addresses.tcp_v4)
-            || needs_rebind(&self.tcp_v6, &new_stack_addresses.tcp_v6)
+        let mode = new_stack_addresses.mode;
+
+        stack_needs_rebind(&self.quic_v4, &new_stack_addresses.quic_v4, mode)
+            || stack_needs_rebind(&self.quic_v6, &new_stack_addresses.quic_v6, mode)
+            || stack_needs_rebind(&self.tcp_v4, &new_stack_addresses.tcp_v4, mode)
+            || stack_needs_rebind(&self.tcp_v6, &new_stack_addresses.tcp_v6, mode)
     }
 }
 
-fn needs_rebind(old_addr: &Option<SocketAddr>, new_addr: &Option<SocketAddr>) -> bool {
-    match (old_addr, new_addr) {
-        (Some(old_addr), Some(new_addr)) => {
-            let old_ip = old_addr.ip();
-            let old_port = old_addr.port();
-            let new_ip = new_addr.ip();
-            let new_port = new_addr.port();
-
-            // Just for readability as "true" and "false" have different lengths.
-            const T: bool = true;
-            const F: bool = false;
-
-            // `old_port` is not expected to be 0, but doesn't hurt to cover that case as well.
-            match (
-                old_ip.is_unspecified(),
-                old_port == 0,
-                new_ip.is_unspecified(),
-                new_port == 0,
-            ) {
-                (T, T, T, T) => false,
-                (F, T, T, T) => true,
-                (T, F, T, T) => false,
-                (F, F, T, T) => true,
-                (T, T, F, T) => true,
-                (F, T, F, T) => old_ip != new_ip,
-                (T, F, F, T) => true,
-                (F, F, F, T) => old_ip != new_ip,
-                (T, T, T, F) => true,
-                (F, T, T, F) => true,
-                (T, F, T, F) => old_port != new_port,
-                (F, F, T, F) => true,
-                (T, T, F, F) => true,
-                (F, T, F, F) => true,
-                (T, F, F, F) => true,
-                (F, F, F, F) => old_ip != new_ip || old_port != new_port,
-            }
+fn stack_needs_rebind(old_addrs: &[SocketAddr], new_addrs: &[SocketAddr], mode: BindMode) -> bool {
+    match mode {
+        // The bound address is fine if it satisfies any of the new ones. Note this means that if we
+        // previously fell back to a less preferred address, the more preferred one is not retried.
+        BindMode::First => match old_addrs {
+            [] => !new_addrs.is_empty(),
+            [old_addr] => !new_addrs
+                .iter()
+                .any(|new_addr| !needs_rebind(old_addr, new_addr)),
+            _ => true,
+        },
+        BindMode::All => {
+            old_addrs.len() != new_addrs.len()
+                || old_addrs
+                    .iter()
+                    .zip(new_addrs)
+                    .any(|(old_addr, new_addr)| needs_rebind(old_addr, new_addr))
         }
-        (Some(_), None) => true,
-        (None, Some(_)) => true,
-        (None, None) => false,
     }
 }
 
-impl From<&[PeerAddr]> for StackAddresses {
-    fn from(addrs: &[PeerAddr]) -> Self {
-        let quic_v4 = addrs.iter().find_map(|addr| match addr {
-            PeerAddr::Quic(addr @ SocketAddr::V4(_)) => Some(*addr),
-            _ => None,
-        });
-        let quic_v6 = addrs.iter().find_map(|addr| match addr {
-            PeerAddr::Quic(addr @ SocketAddr::V6(_)) => Some(*addr),
-            _ => None,
-        });
-        let tcp_v4 = addrs.iter().find_map(|addr| match addr {
-            PeerAddr::Tcp(addr @ SocketAddr::V4(_)) => Some(*addr),
-            _ => None,
-        });
-        let tcp_v6 = addrs.iter().find_map(|addr| match addr {
-            PeerAddr::Tcp(addr @ SocketAddr::V6(_)) => Some(*addr),
-            _ => None,
-        });
-
-        StackAddresses {
-            quic_v4,
-            quic_v6,
-            tcp_v4,
-            tcp_v6,
-        }
+fn needs_rebind(old_addr: &SocketAddr, new_addr: &SocketAddr) -> bool {
+    let old_ip = old_addr.ip();
+    let old_port = old_addr.port();
+    let new_ip = new_addr.ip();
+    let new_port = new_addr.port();
+
+    // Just for readability as "true" and "false" have different lengths.
+    const T: bool = true;
+    const F: bool = false;
+
+    // `old_port` is not expected to be 0, but doesn't hurt to cover that case as well.
+    match (
+        old_ip.is_unspecified(),
+        old_port == 0,
+        new_ip.is_unspecified(),
+        new_port == 0,
+    ) {
+        (T, T, T, T) => false,
+        (F, T, T, T) => true,
+        (T, F, T, T) => false,
+        (F, F, T, T) => true,
+        (T, T, F, T) => true,
+        (F, T, F, T) => old_ip != new_ip,
+        (T, F, F, T) => true,
+        (F, F, F, T) => old_ip != new_ip,
+        (T, T, T, F) => true,
+        (F, T, T, F) => true,
+        (T, F, T, F) => old_port != new_port,
+        (F, F, T, F) => true,
+        (T, T, F, F) => true,
+        (F, T, F, F) => true,
+        (T, F, F, F) => true,
+        (F, F, F, F) => old_ip != new_ip || old_port != new_port,
     }
 }
@@ -59,7 +59,7 @@ pub(crate) fn watch_ipv4_multicast_interfaces() -> mpsc::Receiver<InterfaceChang
     rx
 }
 
-async fn find_ipv4_multicast_interfaces() -> HashSet<Ipv4Addr> {
+pub(crate) async fn find_ipv4_multicast_interfaces() -> HashSet<Ipv4Addr> {
     match tokio::task::spawn_blocking(find_ipv4_multicast_interfaces_sync).await {
         Ok(interfaces) => interfaces,
         Err(_) => HashSet::default(),
@@ -0,0 +1,160 @@
+//! Per-repository list of the peers we recently successfully synced the repository with. The list
+//! is persisted in the repository metadata and used to reconnect to those peers on the next
+//! registration, without having to wait for them to be discovered again.
+//!
+//! The addresses reveal who the repository is shared with so they are stored encrypted with a key
+//! derived from the repository secrets. In blind mode there is no such key and the list is kept
+//! only in memory.
+
+use super::peer_addr::PeerAddr;
+use crate::{crypto::cipher, repository::Metadata};
+use std::time::Duration;
+
+/// Default maximum number of known peers retained per repository.
+pub(super) const DEFAULT_LIMIT: usize = 16;
+
+/// For how long to keep trying to reconnect to the known peers after the registration. After
+/// that they are only connected to if discovered by other means.
+pub(super) const RECONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+const KNOWN_PEERS: &str = "known_peers";
+const KNOWN_PEERS_LIMIT: &str = "known_peers_limit";
+
+#[derive(Clone)]
+pub(super) struct KnownPeers {
+    // Most recent first.
+    peers: Vec<PeerAddr>,
+    limit: usize,
+    key: Option<cipher::SecretKey>,
+}
+
+impl KnownPeers {
+    pub async fn load(metadata: &Metadata, key: Option<cipher::SecretKey>) -> Self {
+        let limit = metadata
+            .get::<u64>(KNOWN_PEERS_LIMIT)
+            .await
+            .unwrap_or(None)
+            .map(|limit| limit.try_into().unwrap_or(usize::MAX))
+            .unwrap_or(DEFAULT_LIMIT);
+
+        let mut peers: Vec<PeerAddr> = if let Some(key) = &key {
+            metadata
+                .get_encrypted(KNOWN_PEERS, key)
+                .await
+                .unwrap_or(None)
+                .and_then(|peers| String::from_utf8(peers).ok())
+                .map(|peers| peers.lines().filter_map(|peer| peer.parse().ok()).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        peers.truncate(limit);
+
+        Self { peers, limit, key }
+    }
+
+    pub fn peers(&self) -> &[PeerAddr] {
+        &self.peers
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Moves the peer to the front of the list, evicting the least recent one if the limit is
+    /// exceeded. Returns whether the list changed.
+    pub fn insert(&mut self, peer: PeerAddr) -> bool {
+        if self.limit == 0 || self.peers.first() == Some(&peer) {
+            return false;
+        }
+
+        self.peers.retain(|existing| *existing != peer);
+        self.peers.insert(0, peer);
+        self.peers.truncate(self.limit);
+
+        true
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.peers.truncate(limit);
+    }
+
+    pub async fn save(&self, metadata: &Metadata) {
+        let mut writer = match metadata.write().await {
+            Ok(writer) => writer,
+            Err(error) => {
+                tracing::error!(?error, "Failed to save known peers");
+                return;
+            }
+        };
+
+        let result = async move {
+            if let Some(key) = &self.key {
+                if self.peers.is_empty() {
+                    writer.remove_encrypted(KNOWN_PEERS).await?;
+                } else {
+                    let peers: Vec<_> = self.peers.iter().map(|peer| peer.to_string()).collect();
+                    writer
+                        .set_encrypted(KNOWN_PEERS, peers.join("\n").as_bytes(), key)
+                        .await?;
+                }
+            }
+
+            if self.limit == DEFAULT_LIMIT {
+                writer.remove(KNOWN_PEERS_LIMIT).await?;
+            } else {
+                writer.set(KNOWN_PEERS_LIMIT, self.limit as u64).await?;
+            }
+
+            writer.commit().await
+        }
+        .await;
+
+        if let Err(error) = result {
+            tracing::error!(?error, "Failed to save known peers");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn insert() {
+        let a = PeerAddr::Quic((Ipv4Addr::LOCALHOST, 1000).into());
+        let b = PeerAddr::Quic((Ipv4Addr::LOCALHOST, 1001).into());
+        let c = PeerAddr::Tcp((Ipv4Addr::LOCALHOST, 1002).into());
+
+        let mut known = KnownPeers {
+            peers: Vec::new(),
+            limit: 2,
+            key: None,
+        };
+
+        assert!(known.insert(a));
+        assert!(known.insert(b));
+        assert_eq!(known.peers(), [b, a]);
+
+        // Already the most recent one.
+        assert!(!known.insert(b));
+
+        // Moved to the front.
+        assert!(known.insert(a));
+        assert_eq!(known.peers(), [a, b]);
+
+        // The least recent one is evicted.
+        assert!(known.insert(c));
+        assert_eq!(known.peers(), [c, a]);
+
+        known.set_limit(1);
+        assert_eq!(known.peers(), [c]);
+
+        known.set_limit(0);
+        assert!(known.peers().is_empty());
+        assert!(!known.insert(a));
+    }
+}
@@ -1,4 +1,5 @@
 use super::{
+    block_chunks::BlockChunk,
     crypto::Role,
     debug_payload::{DebugRequest, DebugResponse},
     peer_exchange::PexPayload,
@@ -23,6 +24,9 @@ pub(crate) enum Request {
     ChildNodes(Hash, ResponseDisambiguator, DebugRequest),
     /// Request block with the given id.
     Block(BlockId, DebugRequest),
+    /// Request block with the given id to be sent in chunks, starting at the given offset (to
+    /// resume an interrupted transfer). Supported since `FIRST_CHUNKED_VERSION`.
+    BlockChunks(BlockId, u32, DebugRequest),
 }
 
 /// ResponseDisambiguator is used to uniquelly assign a response to a request.
@@ -59,6 +63,9 @@ pub(crate) enum Response {
     Block(BlockContent, BlockNonce, DebugResponse),
     /// Send that a Block request failed
     BlockError(BlockId, DebugResponse),
+    /// Send a chunk of a requested block (response to `Request::BlockChunks`). Failure is
+    /// reported with `BlockError`.
+    BlockChunk(BlockChunk, DebugResponse),
 }
 
 const LEGACY_TAG: u8 = 2;
@@ -1,13 +1,18 @@
 use super::{
+    bad_blocks::BadBlockCounter,
     barrier::{Barrier, BarrierError},
     client::Client,
-    connection::ConnectionPermit,
-    constants::MAX_IN_FLIGHT_REQUESTS_PER_PEER,
+    codec::MessageCodec,
+    connection::{ConnectionPermit, PermitId},
     crypto::{self, DecryptingStream, EncryptingSink, EstablishError, RecvError, Role, SendError},
     message::{Content, MessageChannelId, Request, Response},
     message_dispatcher::{ContentSink, ContentStream, MessageDispatcher},
+    peer_addr::PeerAddr,
     peer_exchange::{PexPeer, PexReceiver, PexRepository, PexSender},
+    peer_source::PeerSource,
+    protocol::{Version, FIRST_CHUNKED_VERSION},
     raw,
+    request_limits::RequestLimits,
     runtime_id::PublicRuntimeId,
     server::Server,
     traffic_tracker::TrafficTracker,
@@ -15,10 +20,11 @@ use super::{
 use crate::{
     collections::{hash_map::Entry, HashMap},
     repository::{LocalId, Vault},
+    sync::AwaitDrop,
 };
 use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
 use state_monitor::StateMonitor;
-use std::{future, sync::Arc};
+use std::{future, mem, sync::Arc};
 use tokio::{
     select,
     sync::{mpsc, oneshot, Semaphore},
@@ -40,11 +46,16 @@ pub(super) struct MessageBroker {
     this_runtime_id: PublicRuntimeId,
     that_runtime_id: PublicRuntimeId,
     dispatcher: MessageDispatcher,
+    connections: Vec<ConnectionEntry>,
     links: HashMap<LocalId, oneshot::Sender<()>>,
+    codec: MessageCodec,
+    chunked_blocks: bool,
     request_limiter: Arc<Semaphore>,
+    request_limits: RequestLimits,
     pex_peer: PexPeer,
     monitor: StateMonitor,
     tracker: TrafficTracker,
+    bad_blocks: BadBlockCounter,
     span: SpanGuard,
 }
 
@@ -52,9 +63,13 @@ impl MessageBroker {
     pub fn new(
         this_runtime_id: PublicRuntimeId,
         that_runtime_id: PublicRuntimeId,
+        protocol_version: Version,
         pex_peer: PexPeer,
         monitor: StateMonitor,
         tracker: TrafficTracker,
+        bad_blocks: BadBlockCounter,
+        request_limits: RequestLimits,
+        message_compression: bool,
     ) -> Self {
         let span = SpanGuard::new(&that_runtime_id);
 
@@ -62,19 +77,82 @@ impl MessageBroker {
             this_runtime_id,
             that_runtime_id,
             dispatcher: MessageDispatcher::new(),
+            connections: Vec::new(),
             links: HashMap::default(),
-            request_limiter: Arc::new(Semaphore::new(MAX_IN_FLIGHT_REQUESTS_PER_PEER)),
+            codec: MessageCodec::new(protocol_version).with_compression(message_compression),
+            chunked_blocks: protocol_version >= FIRST_CHUNKED_VERSION,
+            request_limiter: Arc::new(Semaphore::new(request_limits.per_peer())),
+            request_limits,
             pex_peer,
             monitor,
             tracker,
+            bad_blocks,
             span,
         }
     }
 
-    pub fn add_connection(&self, stream: raw::Stream, permit: ConnectionPermit) {
+    /// Adds a new connection to this broker. The returned receiver gets notified if the connection
+    /// is closed in favor of a newer one (see [`Self::close_replaced_connections`]).
+    pub fn add_connection(
+        &mut self,
+        stream: raw::Stream,
+        permit: ConnectionPermit,
+    ) -> oneshot::Receiver<()> {
+        let (replaced_tx, replaced_rx) = oneshot::channel();
+
+        // Forget the connections that have already been closed.
+        self.connections
+            .retain(|entry| !entry.replaced_tx.is_closed());
+        self.connections.push(ConnectionEntry {
+            id: permit.id(),
+            addr: permit.addr(),
+            source: permit.source(),
+            replaced_tx,
+        });
+
         self.pex_peer
             .handle_connection(permit.addr(), permit.source(), permit.released());
-        self.dispatcher.bind(stream, permit)
+        self.dispatcher.bind(stream, permit);
+
+        replaced_rx
+    }
+
+    /// Closes the incoming connections that are replaced by the given newer incoming connection,
+    /// that is, those coming from the same IP address over the same protocol. The peer keeps at
+    /// most one connection to each of our listeners, so such connections are almost certainly
+    /// half-open ones that the peer has already abandoned and reconnected.
+    pub fn close_replaced_connections(&mut self, newest: PermitId) {
+        let Some(newest) = self.connections.iter().find(|entry| entry.id == newest) else {
+            return;
+        };
+
+        if newest.source != PeerSource::Listener {
+            return;
+        }
+
+        let (id, addr) = (newest.id, newest.addr);
+
+        let (replaced, kept): (Vec<_>, Vec<_>) = mem::take(&mut self.connections)
+            .into_iter()
+            .partition(|entry| {
+                entry.id != id
+                    && entry.source == PeerSource::Listener
+                    && entry.addr.is_quic() == addr.is_quic()
+                    && entry.addr.ip() == addr.ip()
+            });
+
+        self.connections = kept;
+
+        for entry in replaced {
+            entry.replaced_tx.send(()).ok();
+            self.dispatcher.unbind(entry.id);
+        }
+    }
+
+    /// Closes the given connection.
+    pub fn close_connection(&mut self, id: PermitId) {
+        self.connections.retain(|entry| entry.id != id);
+        self.dispatcher.unbind(id);
     }
 
     /// Has this broker at least one live connection?
@@ -85,11 +163,14 @@ impl MessageBroker {
     /// Try to establish a link between a local repository and a remote repository. The remote
     /// counterpart needs to call this too with matching repository id for the link to actually be
     /// created.
+    ///
+    /// Once the link gets established, the id of the remote peer is sent to `established_tx`.
     pub fn create_link(
         &mut self,
         vault: Vault,
         pex_repo: &PexRepository,
         response_limiter: Arc<Semaphore>,
+        established_tx: mpsc::UnboundedSender<PublicRuntimeId>,
     ) {
         let monitor = self.monitor.make_child(vault.monitor.name());
         let span = tracing::info_span!(
@@ -136,12 +217,18 @@ impl MessageBroker {
             stream: self.dispatcher.open_recv(channel_id),
             sink: self.dispatcher.open_send(channel_id),
             vault,
+            codec: self.codec,
+            chunked_blocks: self.chunked_blocks,
             request_limiter: self.request_limiter.clone(),
+            request_limits: self.request_limits.clone(),
             response_limiter,
             pex_tx,
             pex_rx,
             monitor,
             tracker: self.tracker.clone(),
+            bad_blocks: self.bad_blocks.clone(),
+            that_runtime_id: self.that_runtime_id,
+            established_tx,
         };
 
         drop(span_enter);
@@ -163,11 +250,42 @@ impl MessageBroker {
         self.links.remove(&id);
     }
 
+    /// Is the local repository with the given id currently linked with this peer?
+    pub fn is_linked(&self, id: LocalId) -> bool {
+        self.links
+            .get(&id)
+            .map(|abort_tx| !abort_tx.is_closed())
+            .unwrap_or(false)
+    }
+
+    /// Is any local repository currently linked with this peer?
+    pub fn has_links(&self) -> bool {
+        self.links.values().any(|abort_tx| !abort_tx.is_closed())
+    }
+
+    /// Number of invalid blocks received from this peer.
+    pub fn bad_blocks(&self) -> u64 {
+        self.bad_blocks.get()
+    }
+
+    /// Returns a `AwaitDrop` that gets notified when this peer sends more invalid blocks than
+    /// allowed.
+    pub fn bad_block_limit_exceeded(&self) -> AwaitDrop {
+        self.bad_blocks.limit_exceeded()
+    }
+
     pub async fn shutdown(self) {
         self.dispatcher.shutdown().await;
     }
 }
 
+struct ConnectionEntry {
+    id: PermitId,
+    addr: PeerAddr,
+    source: PeerSource,
+    replaced_tx: oneshot::Sender<()>,
+}
+
 struct SpanGuard(Span);
 
 impl SpanGuard {
@@ -194,12 +312,18 @@ struct Link {
     stream: ContentStream,
     sink: ContentSink,
     vault: Vault,
+    codec: MessageCodec,
+    chunked_blocks: bool,
     request_limiter: Arc<Semaphore>,
+    request_limits: RequestLimits,
     response_limiter: Arc<Semaphore>,
     pex_tx: PexSender,
     pex_rx: PexReceiver,
     monitor: StateMonitor,
     tracker: TrafficTracker,
+    bad_blocks: BadBlockCounter,
+    that_runtime_id: PublicRuntimeId,
+    established_tx: mpsc::UnboundedSender<PublicRuntimeId>,
 }
 
 impl Link {
@@ -260,15 +384,20 @@ impl Link {
             };
 
             *state.get() = State::Running;
+            self.established_tx.send(self.that_runtime_id).ok();
 
             match run_link(
                 crypto_stream,
                 crypto_sink,
                 &self.vault,
+                self.codec,
+                self.chunked_blocks,
                 self.request_limiter.clone(),
+                self.request_limits.per_client(),
                 self.response_limiter.clone(),
                 &mut self.pex_tx,
                 &mut self.pex_rx,
+                self.bad_blocks.clone(),
             )
             .await
             {
@@ -303,10 +432,14 @@ async fn run_link(
     stream: DecryptingStream<'_>,
     sink: EncryptingSink<'_>,
     repo: &Vault,
+    codec: MessageCodec,
+    chunked_blocks: bool,
     request_limiter: Arc<Semaphore>,
+    max_pending_requests: usize,
     response_limiter: Arc<Semaphore>,
     pex_tx: &mut PexSender,
     pex_rx: &mut PexReceiver,
+    bad_blocks: BadBlockCounter,
 ) -> ControlFlow {
     let (request_tx, request_rx) = mpsc::channel(1);
     let (response_tx, response_rx) = mpsc::channel(1);
@@ -316,10 +449,18 @@ async fn run_link(
 
     // Run everything in parallel:
     let flow = select! {
-        flow = run_client(repo.clone(), content_tx.clone(), response_rx, request_limiter) => flow,
+        flow = run_client(
+            repo.clone(),
+            content_tx.clone(),
+            response_rx,
+            request_limiter,
+            max_pending_requests,
+            bad_blocks,
+            chunked_blocks,
+        ) => flow,
         flow = run_server(repo.clone(), content_tx.clone(), request_rx, response_limiter) => flow,
-        flow = recv_messages(stream, request_tx, response_tx, pex_rx) => flow,
-        flow = send_messages(content_rx, sink) => flow,
+        flow = recv_messages(stream, codec, request_tx, response_tx, pex_rx) => flow,
+        flow = send_messages(content_rx, sink, codec) => flow,
         _ = pex_tx.run(content_tx) => ControlFlow::Continue,
     };
 
@@ -331,6 +472,7 @@ async fn run_link(
 // Handle incoming messages
 async fn recv_messages(
     mut stream: DecryptingStream<'_>,
+    codec: MessageCodec,
     request_tx: mpsc::Sender<Request>,
     response_tx: mpsc::Sender<Response>,
     pex_rx: &PexReceiver,
@@ -356,7 +498,7 @@ async fn recv_messages(
             }
         };
 
-        let content: Content = match bincode::deserialize(&content) {
+        let content = match codec.decode(&content) {
             Ok(content) => content,
             Err(error) => {
                 tracing::warn!(?error, "Failed to deserialize incoming message");
@@ -376,6 +518,7 @@ async fn recv_messages(
 async fn send_messages(
     mut content_rx: mpsc::Receiver<Content>,
     mut sink: EncryptingSink<'_>,
+    codec: MessageCodec,
 ) -> ControlFlow {
     loop {
         let content = if let Some(content) = content_rx.recv().await {
@@ -384,9 +527,7 @@ async fn send_messages(
             forever().await
         };
 
-        // unwrap is OK because serialization into a vec should never fail unless we have a bug
-        // somewhere.
-        let content = bincode::serialize(&content).unwrap();
+        let content = codec.encode(&content);
 
         match sink.send(content).await {
             Ok(()) => (),
@@ -408,8 +549,19 @@ async fn run_client(
     content_tx: mpsc::Sender<Content>,
     response_rx: mpsc::Receiver<Response>,
     request_limiter: Arc<Semaphore>,
+    max_pending_requests: usize,
+    bad_blocks: BadBlockCounter,
+    chunked_blocks: bool,
 ) -> ControlFlow {
-    let mut client = Client::new(repo, content_tx, response_rx, request_limiter);
+    let mut client = Client::new(
+        repo,
+        content_tx,
+        response_rx,
+        request_limiter,
+        max_pending_requests,
+        bad_blocks,
+        chunked_blocks,
+    );
     let result = client.run().await;
 
     tracing::debug!("Client stopped running with result {:?}", result);
@@ -60,6 +60,11 @@ impl MessageDispatcher {
         self.command_tx.send(Command::Bind { socket, permit }).ok();
     }
 
+    /// Closes the connection bound with the given permit (if it's still bound).
+    pub fn unbind(&self, id: PermitId) {
+        self.command_tx.send(Command::Unbind { id }).ok();
+    }
+
     /// Is this dispatcher bound to at least one connection?
     pub fn is_bound(&self) -> bool {
         self.connection_count.load(Ordering::Acquire) > 0
@@ -275,7 +280,7 @@ impl Drop for ConnectionStream {
 // released on drop. Automatically closes when the corresponding `ConnectionStream` is closed.
 struct ConnectionSink {
     writer: MessageSink<TrackingWrapper<raw::OwnedWriteHalf>>,
-    _permit: ConnectionPermitHalf,
+    permit: ConnectionPermitHalf,
     permit_released: AwaitDrop,
 }
 
@@ -285,10 +290,14 @@ impl ConnectionSink {
 
         Self {
             writer: MessageSink::new(TrackingWrapper::new(writer, permit.tracker())),
-            _permit: permit,
+            permit,
             permit_released,
         }
     }
+
+    fn permit_id(&self) -> PermitId {
+        self.permit.id()
+    }
 }
 
 impl Sink<Message> for ConnectionSink {
@@ -390,6 +399,11 @@ impl Worker {
                     self.connection_count.clone(),
                 ));
             }
+            Command::Unbind { id } => {
+                // Dropping the sink releases the permit which closes the corresponding stream as
+                // well.
+                self.send.sinks.retain(|sink| sink.permit_id() != id);
+            }
             Command::Shutdown { tx } => {
                 self.shutdown().await;
                 tx.send(()).ok();
@@ -425,6 +439,9 @@ enum Command {
         socket: raw::Stream,
         permit: ConnectionPermit,
     },
+    Unbind {
+        id: PermitId,
+    },
     Shutdown {
         tx: oneshot::Sender<()>,
     },
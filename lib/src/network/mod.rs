@@ -1,67 +1,91 @@
 pub mod dht_discovery;
 pub mod peer_addr;
 
+mod bad_blocks;
 mod barrier;
+mod block_chunks;
 mod client;
+mod clock_skew;
+mod codec;
 mod connection;
 mod connection_monitor;
 mod constants;
 mod crypto;
 mod debug_payload;
+mod event;
 mod gateway;
 mod interface;
 mod ip;
+mod known_peers;
 mod local_discovery;
 mod message;
 mod message_broker;
 mod message_dispatcher;
 mod message_io;
 mod peer_exchange; // TODO: replace with v2
+mod peer_filter;
 mod peer_info;
 mod peer_source;
 mod peer_state;
 mod pending;
 mod protocol;
 mod raw;
+mod request_limits;
 mod runtime_id;
 mod seen_peers;
 mod server;
 mod stun;
 mod stun_server_list;
+mod task_counter;
 #[cfg(test)]
 mod tests;
 mod traffic_tracker;
 mod upnp;
 
+pub(crate) use self::block_chunks::PartialBlocks;
 pub use self::{
-    connection::PeerInfoCollector,
+    clock_skew::ClockSkew,
+    connection::{ConnectionDirection, ConnectionInfo, PeerInfoCollector},
+    event::NetworkEvent,
+    ip::Protocol as IpProtocol,
+    peer_filter::PeerFilterFn,
     peer_info::PeerInfo,
     peer_source::PeerSource,
     peer_state::PeerState,
     runtime_id::{PublicRuntimeId, SecretRuntimeId},
     traffic_tracker::TrafficStats,
+    upnp::{UpnpDeviceStatus, UpnpMappingStatus, UpnpStatus},
 };
-use futures_util::future;
+use futures_util::{future, Stream, StreamExt};
 pub use net::stun::NatBehavior;
 
 use self::{
+    bad_blocks::{BadBlockCounter, BadBlockLimit},
+    clock_skew::CLOCK_SKEW_WARNING_THRESHOLD,
     connection::{ConnectionDeduplicator, ConnectionPermit, ReserveResult},
     connection_monitor::ConnectionMonitor,
-    constants::MAX_UNCHOKED_COUNT,
+    constants::{CONNECTION_IDLE_CHECK_INTERVAL, MAX_UNCHOKED_COUNT},
     dht_discovery::{DhtContactsStoreTrait, DhtDiscovery},
-    gateway::{Gateway, StackAddresses},
+    event::NetworkEventSender,
+    gateway::{BindMode, Gateway, StackAddresses},
+    interface::InterfaceChange,
+    known_peers::KnownPeers,
     local_discovery::LocalDiscovery,
     message_broker::MessageBroker,
     peer_addr::{PeerAddr, PeerPort},
     peer_exchange::{PexDiscovery, PexRepository},
-    protocol::{Version, MAGIC, VERSION},
+    peer_filter::PeerFilter,
+    protocol::{Version, VersionMismatch, VersionRange, MAGIC, VERSION, VERSIONS},
+    request_limits::RequestLimits,
     seen_peers::{SeenPeer, SeenPeers},
     stun::StunClients,
+    task_counter::{TaskCounter, TaskGuard},
     traffic_tracker::TrafficTracker,
 };
 use crate::{
     collections::{hash_map::Entry, HashMap, HashSet},
-    repository::{RepositoryHandle, RepositoryId, Vault},
+    event::{Event, Payload},
+    repository::{LocalId, RepositoryHandle, RepositoryId, SyncFilter, Vault},
     sync::uninitialized_watch,
 };
 use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
@@ -74,19 +98,29 @@ use std::{
     future::Future,
     io, mem,
     net::{SocketAddr, SocketAddrV4, SocketAddrV6},
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
 };
 use thiserror::Error;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    sync::{mpsc, Semaphore},
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, watch, Semaphore,
+    },
     task::{AbortHandle, JoinSet},
-    time::Duration,
+    time::{self, Duration, Instant, MissedTickBehavior},
 };
+use tokio_stream::wrappers::BroadcastStream;
 use tracing::{Instrument, Span};
 
 const DHT_ENABLED: &str = "dht_enabled";
+const DHT_ANNOUNCE: &str = "dht_announce";
 const PEX_ENABLED: &str = "pex_enabled";
+const SYNC_FILTER: &str = "sync_filter";
 
 pub struct Network {
     inner: Arc<Inner>,
@@ -110,7 +144,15 @@ impl Network {
         // TODO: There are ways to address this: e.g. we could try both, or we could include
         // the protocol information in the info-hash generation. There are pros and cons to
         // these approaches.
-        let dht_discovery = DhtDiscovery::new(None, None, dht_contacts, monitor.make_child("DHT"));
+        let events_tx = NetworkEventSender::new();
+
+        let dht_discovery = DhtDiscovery::new(
+            None,
+            None,
+            dht_contacts,
+            events_tx.clone(),
+            monitor.make_child("DHT"),
+        );
         // TODO: do we need unbounded channel here?
         let (dht_discovery_tx, dht_discovery_rx) = mpsc::unbounded_channel();
 
@@ -120,6 +162,7 @@ impl Network {
         let pex_discovery = PexDiscovery::new(pex_discovery_tx);
 
         let (on_protocol_mismatch_tx, _) = uninitialized_watch::channel();
+        let (on_clock_skew_tx, _) = uninitialized_watch::channel();
 
         let user_provided_peers = SeenPeers::new();
 
@@ -130,6 +173,7 @@ impl Network {
         let peers_monitor = monitor.make_child("Peers");
 
         let tasks = Arc::new(BlockingMutex::new(JoinSet::new()));
+        let task_counter = TaskCounter::new(&monitor);
 
         let inner = Arc::new(Inner {
             main_monitor: monitor,
@@ -142,6 +186,7 @@ impl Network {
             state: BlockingMutex::new(State {
                 message_brokers: Some(HashMap::default()),
                 registry: Slab::new(),
+                max_registrations: None,
             }),
             port_forwarder,
             port_forwarder_state: BlockingMutex::new(ComponentState::disabled(
@@ -150,15 +195,30 @@ impl Network {
             local_discovery_state: BlockingMutex::new(ComponentState::disabled(
                 DisableReason::Explicit,
             )),
+            network_change_watch_state: BlockingMutex::new(ComponentState::disabled(
+                DisableReason::Explicit,
+            )),
+            bind_request: BlockingMutex::new((Vec::new(), BindMode::First)),
             dht_discovery,
             dht_discovery_tx,
             pex_discovery,
             stun_clients: StunClients::new(),
             connection_deduplicator: ConnectionDeduplicator::new(),
             on_protocol_mismatch_tx,
+            on_clock_skew_tx,
+            events_tx,
             user_provided_peers,
+            peer_filter: PeerFilter::new(),
             tasks: Arc::downgrade(&tasks),
+            task_counter,
             highest_seen_protocol_version: BlockingMutex::new(VERSION),
+            bad_block_limit: BadBlockLimit::default(),
+            request_limits: RequestLimits::new(),
+            prefer_newest_connection: AtomicBool::new(false),
+            message_compression: AtomicBool::new(false),
+            clock_skew_detection: AtomicBool::new(true),
+            connection_idle_timeout: BlockingMutex::new(None),
+            on_registration_tx: watch::channel(()).0,
             our_addresses: BlockingMutex::new(HashSet::default()),
         });
 
@@ -177,10 +237,19 @@ impl Network {
     /// Binds the network to the specified addresses.
     /// Rebinds if already bound. Unbinds and disables the network if `addrs` is empty.
     ///
-    /// NOTE: currently at most one address per protocol (QUIC/TCP) and family (IPv4/IPv6) is used
-    /// and the rest are ignored, but this might change in the future.
+    /// If there are several addresses of the same protocol (QUIC/TCP) and family (IPv4/IPv6), they
+    /// are tried in order and the first one that can be bound is used, so the preferred port can be
+    /// followed by fallback ones. If none of them is available, a random port is used. Failures are
+    /// only logged. Use `listener_local_addrs` to find out which addresses were actually bound.
     pub async fn bind(&self, addrs: &[PeerAddr]) {
-        self.inner.bind(addrs).await
+        self.inner.bind(addrs, BindMode::First).await
+    }
+
+    /// Like `bind` but listens on all the addresses that can be bound, not just the first one per
+    /// protocol and family. Outgoing connections use the first bound address of each protocol and
+    /// family.
+    pub async fn bind_all(&self, addrs: &[PeerAddr]) {
+        self.inner.bind(addrs, BindMode::All).await
     }
 
     pub fn listener_local_addrs(&self) -> Vec<PeerAddr> {
@@ -208,6 +277,20 @@ impl Network {
         self.inner.port_forwarder_state.lock().unwrap().is_enabled()
     }
 
+    /// Status of the UPnP port forwarding: the gateway devices found, whether the port mappings on
+    /// them are currently active and the external IP addresses they report. Useful e.g. to warn the
+    /// user when port forwarding failed. The mappings are leased and renewed periodically so the
+    /// status is kept up to date even if the device drops them (e.g., when rebooted).
+    pub fn upnp_status(&self) -> UpnpStatus {
+        self.inner.port_forwarder.status()
+    }
+
+    /// Re-requests the UPnP port mappings right away: the gateway devices are rediscovered and the
+    /// mappings recreated on them. Does nothing if port forwarding is disabled.
+    pub fn refresh_port_mappings(&self) {
+        self.inner.refresh_port_mappings()
+    }
+
     pub fn set_local_discovery_enabled(&self, enabled: bool) {
         let mut state = self.inner.local_discovery_state.lock().unwrap();
 
@@ -234,6 +317,50 @@ impl Network {
             .is_enabled()
     }
 
+    /// Notifies the network that the local network interfaces or addresses changed (e.g., when
+    /// switching from WiFi to Ethernet) so it can recover quickly instead of waiting for the next
+    /// periodic rediscovery: the UPnP port mappings are recreated, local discovery is restarted and
+    /// all the DHT lookups immediately search for peers and re-announce.
+    ///
+    /// Listeners bound to unspecified addresses (e.g. `0.0.0.0`) accept connections on the new
+    /// interfaces as well so they are kept as they are, together with their connections. If any
+    /// of the addresses passed to the last [`Self::bind`] (or [`Self::bind_all`]) is a specific one,
+    /// all the listeners are rebound to those addresses in the background, keeping the current
+    /// ports if possible. A specific address that is no longer available falls back to the same
+    /// port (or a random one) on the unspecified address, as with `bind`.
+    ///
+    /// This is called automatically when the network change watch is enabled (see
+    /// [`Self::set_network_change_watch_enabled`]). Call it manually on platforms which provide
+    /// their own notifications about network changes.
+    pub fn handle_network_change(&self) {
+        self.inner.handle_network_change()
+    }
+
+    /// Enables or disables periodically checking the local network interfaces and calling
+    /// [`Self::handle_network_change`] whenever they change. Currently only changes of the IPv4
+    /// interfaces are detected. Default is disabled.
+    pub fn set_network_change_watch_enabled(&self, enabled: bool) {
+        let mut state = self.inner.network_change_watch_state.lock().unwrap();
+
+        if enabled {
+            if state.is_enabled() {
+                return;
+            }
+
+            state.enable(self.inner.spawn_network_change_watch().into());
+        } else {
+            state.disable(DisableReason::Explicit);
+        }
+    }
+
+    pub fn is_network_change_watch_enabled(&self) -> bool {
+        self.inner
+            .network_change_watch_state
+            .lock()
+            .unwrap()
+            .is_enabled()
+    }
+
     /// Find out external address using the STUN protocol.
     /// Currently QUIC only.
     pub async fn external_addr_v4(&self) -> Option<SocketAddrV4> {
@@ -265,6 +392,15 @@ impl Network {
         self.inner.user_provided_peers.remove(peer)
     }
 
+    /// Sets a filter which is consulted whenever a new peer is found by local discovery, DHT or
+    /// peer exchange, before connecting to it. If the filter returns `false`, the peer is ignored
+    /// and the filter is not consulted for it again for a while, even if it's discovered again.
+    /// User provided peers and incoming connections are not subject to the filter. Passing `None`
+    /// removes the filter.
+    pub fn set_peer_filter(&self, filter: Option<Box<PeerFilterFn>>) {
+        self.inner.peer_filter.set(filter);
+    }
+
     pub fn this_runtime_id(&self) -> PublicRuntimeId {
         self.inner.this_runtime_id.public()
     }
@@ -277,6 +413,155 @@ impl Network {
         self.inner.connection_deduplicator.get_peer_info(addr)
     }
 
+    /// Returns all currently active connections, that is, the ones that completed the handshake.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.inner.connections()
+    }
+
+    /// Forcibly closes all connections to the peer with the given runtime id. Returns whether
+    /// such peer was connected.
+    ///
+    /// Note this doesn't prevent the peer from being connected to again later (e.g., when it gets
+    /// rediscovered or when it reconnects to us).
+    pub async fn disconnect(&self, runtime_id: &PublicRuntimeId) -> bool {
+        self.inner.disconnect(runtime_id).await
+    }
+
+    /// Sets the maximum number of invalid blocks a peer can send us before it's automatically
+    /// disconnected. A block is invalid if it's neither requested from the peer nor referenced by
+    /// any branch. `None` (the default) means no limit. Use [`Self::connections`] to find out how
+    /// many invalid blocks each peer has sent so far.
+    pub fn set_bad_block_limit(&self, limit: Option<u64>) {
+        self.inner.bad_block_limit.set(limit);
+    }
+
+    pub fn bad_block_limit(&self) -> Option<u64> {
+        self.inner.bad_block_limit.get()
+    }
+
+    /// Enables/disables closing the stale duplicate connections. When enabled, a new incoming
+    /// connection from a peer we are already connected to closes the older incoming connections
+    /// from the same peer coming from the same IP address over the same protocol. This happens
+    /// when the peer reconnects (e.g., after its network went down for a while) before we notice
+    /// the previous connection is dead (half-open), which would otherwise linger until it times
+    /// out. All the connections are kept when disabled (the default).
+    pub fn set_prefer_newest_connection_enabled(&self, enabled: bool) {
+        self.inner
+            .prefer_newest_connection
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_prefer_newest_connection_enabled(&self) -> bool {
+        self.inner.prefer_newest_connection.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables compression of the messages sent to peers. This reduces the bandwidth
+    /// used by the index sync (the exchange of the metadata describing what data each replica has)
+    /// at the cost of some CPU time. Block content is always sent uncompressed because it's
+    /// encrypted and thus incompressible. Only applied to peers which support it. Disabled by
+    /// default. Affects only peers connected after this call.
+    pub fn set_message_compression_enabled(&self, enabled: bool) {
+        self.inner
+            .message_compression
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_message_compression_enabled(&self) -> bool {
+        self.inner.message_compression.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables the detection of the clock skew between us and the peers (see
+    /// [`ConnectionInfo::clock_skew`] and [`Self::on_clock_skew`]). When disabled we don't send our
+    /// current time to the peers during the handshake. The skew is detected only if both sides
+    /// have it enabled. Enabled by default. Affects only peers connected after this call.
+    pub fn set_clock_skew_detection_enabled(&self, enabled: bool) {
+        self.inner
+            .clock_skew_detection
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_clock_skew_detection_enabled(&self) -> bool {
+        self.inner.clock_skew_detection.load(Ordering::Relaxed)
+    }
+
+    /// Sets the idle timeout of the connections. A connection to a peer that no registered
+    /// repository is linked with (e.g., because they've all been deregistered) and over which
+    /// nothing has been sent or received for this long gets closed to free the resources it holds.
+    /// The peer is reconnected only after a repository gets registered again. `None` (the default)
+    /// keeps such connections open indefinitely. Applies also to the existing connections.
+    pub fn set_connection_idle_timeout(&self, timeout: Option<Duration>) {
+        *self.inner.connection_idle_timeout.lock().unwrap() = timeout;
+    }
+
+    pub fn connection_idle_timeout(&self) -> Option<Duration> {
+        *self.inner.connection_idle_timeout.lock().unwrap()
+    }
+
+    /// Sets the maximum number of requests sent to a single peer (across all repositories) that
+    /// haven't been responded to yet. This is the size of the request pipeline - increasing it can
+    /// improve the throughput on links with high bandwidth-delay product (fast but high latency).
+    /// The trade-off is memory: every request in flight can be answered with up to one block
+    /// (32 KiB), so the response buffers can grow up to that many blocks per peer. Also setting it
+    /// too high can congest the network or overwhelm the peer. The default is 32. Affects only
+    /// peers connected after this call.
+    pub fn set_max_in_flight_requests_per_peer(&self, value: usize) {
+        self.inner.request_limits.set_per_peer(value);
+    }
+
+    pub fn max_in_flight_requests_per_peer(&self) -> usize {
+        self.inner.request_limits.per_peer()
+    }
+
+    /// Sets the maximum number of requests sent for a single repository to a single peer whose
+    /// responses haven't been processed yet. This bounds the memory used by received but not yet
+    /// processed responses. Should be at least as large as the per-peer limit (see
+    /// [`Self::set_max_in_flight_requests_per_peer`]) otherwise that one would be ineffective. The
+    /// default is 64. Affects only links established after this call.
+    pub fn set_max_pending_requests_per_link(&self, value: usize) {
+        self.inner.request_limits.set_per_client(value);
+    }
+
+    pub fn max_pending_requests_per_link(&self) -> usize {
+        self.inner.request_limits.per_client()
+    }
+
+    /// Number of currently alive background tasks spawned by the network. This includes the
+    /// long-running ones (accepting connections, discovery, ...) and a task per every connected or
+    /// being connected peer. A number that keeps growing without the number of peers growing as
+    /// well indicates a leak. Also reported to the state monitor as `tasks`.
+    pub fn task_count(&self) -> usize {
+        self.inner.task_counter.count()
+    }
+
+    /// Sets the maximum number of background tasks. When reached, newly discovered peers and
+    /// incoming connections are ignored until some of the existing tasks finish. The long-running
+    /// tasks and connections to user provided peers are not subject to the limit (but count
+    /// towards it). `None` (the default) means no limit.
+    pub fn set_max_tasks(&self, max: Option<usize>) {
+        self.inner.task_counter.set_max(max);
+    }
+
+    pub fn max_tasks(&self) -> Option<usize> {
+        self.inner.task_counter.max()
+    }
+
+    /// Sets the maximum number of repositories registered at the same time. When reached,
+    /// [`Self::register`] fails with [`crate::Error::RegistrationLimitExceeded`]. Already registered
+    /// repositories are not affected even if there are more of them than the new limit. `None`
+    /// (the default) means no limit.
+    pub fn set_max_registrations(&self, max: Option<usize>) {
+        self.inner.state.lock().unwrap().max_registrations = max;
+    }
+
+    pub fn max_registrations(&self) -> Option<usize> {
+        self.inner.state.lock().unwrap().max_registrations
+    }
+
+    /// Number of currently registered repositories.
+    pub fn registered_count(&self) -> usize {
+        self.inner.state.lock().unwrap().registry.len()
+    }
+
     pub fn current_protocol_version(&self) -> u32 {
         VERSION.into()
     }
@@ -290,20 +575,58 @@ impl Network {
         self.inner.on_protocol_mismatch_tx.subscribe()
     }
 
+    /// Subscribe to events triggered when a peer connects whose clock differs significantly from
+    /// ours. Use [`Self::connections`] to find out which peers are affected and by how much.
+    pub fn on_clock_skew(&self) -> uninitialized_watch::Receiver<()> {
+        self.inner.on_clock_skew_tx.subscribe()
+    }
+
     /// Subscribe change in connected peers events.
     pub fn on_peer_set_change(&self) -> uninitialized_watch::Receiver<()> {
         self.inner.connection_deduplicator.on_change()
     }
 
+    /// Returns a stream of network lifecycle events (peers connecting and disconnecting, DHT
+    /// bootstrap results, listeners being bound). Only events emitted after this call are
+    /// reported. If the stream is not consumed fast enough, some events might be skipped.
+    pub fn subscribe(&self) -> impl Stream<Item = NetworkEvent> {
+        BroadcastStream::new(self.inner.events_tx.subscribe())
+            .filter_map(|result| future::ready(result.ok()))
+    }
+
     /// Register a local repository into the network. This links the repository with all matching
     /// repositories of currently connected remote replicas as well as any replicas connected in
     /// the future. The repository is automatically deregistered when the returned handle is
     /// dropped.
     ///
-    /// Note: A repository should have at most one registration - creating more than one has
-    /// undesired effects. This is currently not enforced and so it's a responsibility of the
-    /// caller.
-    pub async fn register(&self, handle: RepositoryHandle) -> Registration {
+    /// Fails with [`crate::Error::RegistrationLimitExceeded`] if the maximum number of registered
+    /// repositories has been reached (see [`Self::set_max_registrations`]).
+    ///
+    /// The same repository can be registered more than once (e.g., by independent components each
+    /// managing its own registration). The registrations then share the links with the peers
+    /// (including their PEX) and the repository stays linked until the last of them is dropped.
+    /// The other per-registration settings (DHT, known peers limit, ...) are not shared but note
+    /// that some of them are persisted in the repository and so they still affect the other
+    /// registrations once they are registered again.
+    ///
+    /// Note that all the registrations of a repository use the same repository id. Registering a
+    /// repository under several ids (e.g., to sync the same data with groups that know it under
+    /// different ids) is not supported, because the id is derived from the write key which signs
+    /// all the snapshots, so the replicas of a different id would reject them.
+    pub async fn register(&self, handle: RepositoryHandle) -> crate::Result<Registration> {
+        // Check the limit before doing anything with the repository so a rejected registration
+        // has no side effects. It's checked again below, under the same lock the registration is
+        // inserted under, in case some other registration completed in the meantime.
+        if self
+            .inner
+            .state
+            .lock()
+            .unwrap()
+            .is_registration_limit_reached()
+        {
+            return Err(crate::Error::RegistrationLimitExceeded);
+        }
+
         *handle.vault.monitor.info_hash.get() =
             Some(repository_info_hash(handle.vault.repository_id()));
 
@@ -313,42 +636,175 @@ impl Network {
             .await
             .unwrap_or(Some(false))
             .unwrap_or(false);
+        let dht_announce = metadata
+            .get(DHT_ANNOUNCE)
+            .await
+            .unwrap_or(Some(true))
+            .unwrap_or(true);
         let pex_enabled = metadata
             .get(PEX_ENABLED)
             .await
             .unwrap_or(Some(false))
             .unwrap_or(false);
+        let sync_filter: Option<String> = metadata.get(SYNC_FILTER).await.unwrap_or(None);
+        let known_peers = KnownPeers::load(&metadata, handle.metadata_key).await;
+
+        if let Some(sync_filter) = sync_filter {
+            handle
+                .vault
+                .sync_filter
+                .send_replace(SyncFilter::new(sync_filter.lines()));
+        }
 
         let dht = if dht_enabled {
-            Some(
-                self.inner
-                    .start_dht_lookup(repository_info_hash(handle.vault.repository_id())),
-            )
+            Some(self.inner.start_dht_lookup(
+                repository_info_hash(handle.vault.repository_id()),
+                dht_announce,
+            ))
         } else {
             None
         };
 
-        let pex = self.inner.pex_discovery.new_repository();
-        pex.set_enabled(pex_enabled);
+        let event_rx = handle.vault.event_tx.subscribe();
 
-        // TODO: This should be global, not per repo
-        let response_limiter = Arc::new(Semaphore::new(MAX_UNCHOKED_COUNT));
+        let reconnect_peers = SeenPeers::new();
+        let reconnect_peers_found: Vec<_> = known_peers
+            .peers()
+            .iter()
+            .filter_map(|peer| reconnect_peers.insert(*peer))
+            .collect();
 
         let mut network_state = self.inner.state.lock().unwrap();
 
-        network_state.create_link(handle.vault.clone(), &pex, response_limiter.clone());
+        if network_state.is_registration_limit_reached() {
+            return Err(crate::Error::RegistrationLimitExceeded);
+        }
+
+        // If the repository is already registered, it's already linked too. Share the resources
+        // of the links with the existing registrations so the links can outlive any of them.
+        let linked = network_state
+            .registry
+            .iter()
+            .find(|(_, holder)| holder.vault.local_id == handle.vault.local_id)
+            .map(|(_, holder)| {
+                (
+                    holder.pex.clone(),
+                    holder.response_limiter.clone(),
+                    holder.link_established_tx.clone(),
+                    holder.known_peers.clone(),
+                )
+            });
+
+        let (pex, response_limiter, link_established_tx, link_established_rx, known_peers) =
+            if let Some((pex, response_limiter, link_established_tx, known_peers)) = linked {
+                (
+                    pex,
+                    response_limiter,
+                    link_established_tx,
+                    None,
+                    known_peers,
+                )
+            } else {
+                let pex = Arc::new(self.inner.pex_discovery.new_repository());
+                pex.set_enabled(pex_enabled);
+
+                // TODO: This should be global, not per repo
+                let response_limiter = Arc::new(Semaphore::new(MAX_UNCHOKED_COUNT));
+
+                let (link_established_tx, link_established_rx) = mpsc::unbounded_channel();
+
+                network_state.create_link(
+                    handle.vault.clone(),
+                    &pex,
+                    response_limiter.clone(),
+                    link_established_tx.clone(),
+                );
+
+                (
+                    pex,
+                    response_limiter,
+                    link_established_tx,
+                    Some(link_established_rx),
+                    known_peers,
+                )
+            };
+
+        let entry = network_state.registry.vacant_entry();
+        let key = entry.key();
+
+        let dht_activity_task = self
+            .inner
+            .spawn(
+                self.inner
+                    .clone()
+                    .forward_dht_activity(key, event_rx)
+                    .instrument(self.inner.span.clone()),
+            )
+            .into();
+
+        // Only the registration that created the links records the peers they are established with.
+        let known_peers_task: Option<ScopedAbortHandle> = link_established_rx.map(|rx| {
+            self.inner
+                .spawn(
+                    self.inner
+                        .clone()
+                        .record_known_peers(handle.vault.local_id, rx)
+                        .instrument(self.inner.span.clone()),
+                )
+                .into()
+        });
+
+        let reconnect_task = self
+            .inner
+            .spawn({
+                let reconnect_peers = reconnect_peers.clone();
+
+                async move {
+                    time::sleep(known_peers::RECONNECT_TIMEOUT).await;
+                    reconnect_peers.clear();
+                }
+            })
+            .into();
 
-        let key = network_state.registry.insert(RegistrationHolder {
+        entry.insert(RegistrationHolder {
             vault: handle.vault,
             dht,
+            dht_announce,
             pex,
             response_limiter,
+            known_peers,
+            link_established_tx,
+            reconnect_peers,
+            _dht_activity_task: dht_activity_task,
+            known_peers_task,
+            _reconnect_task: reconnect_task,
         });
 
-        Registration {
+        drop(network_state);
+
+        self.inner.on_registration_tx.send_replace(());
+
+        // Try to reconnect to the peers we synced with last time, without waiting for them to be
+        // discovered again.
+        for peer in reconnect_peers_found {
+            if !self
+                .inner
+                .is_discovered_peer_allowed(&peer, PeerSource::KnownPeer)
+            {
+                continue;
+            }
+
+            self.inner.spawn(
+                self.inner
+                    .clone()
+                    .handle_peer_found(peer, PeerSource::KnownPeer),
+            );
+        }
+
+        Ok(Registration {
             inner: self.inner.clone(),
             key,
-        }
+        })
     }
 
     /// Gracefully disconnect from peers. Failing to call this function on app termination will
@@ -381,15 +837,46 @@ impl Registration {
         let holder = &mut state.registry[self.key];
 
         if enabled {
+            holder.dht = Some(self.inner.start_dht_lookup(
+                repository_info_hash(holder.vault.repository_id()),
+                holder.dht_announce,
+            ));
+        } else {
+            holder.dht = None;
+        }
+    }
+
+    /// Sets whether to announce this repository on the DHT (when DHT is enabled) or to only use
+    /// the DHT to find peers for it without revealing our presence (passive participation). Note
+    /// that passive participation still allows the found peers to learn our address once we
+    /// connect to them. Default is to announce.
+    pub async fn set_dht_announce_enabled(&self, enabled: bool) {
+        set_metadata_bool(&self.inner, self.key, DHT_ANNOUNCE, enabled).await;
+
+        let mut state = self.inner.state.lock().unwrap();
+        let holder = &mut state.registry[self.key];
+
+        if holder.dht_announce == enabled {
+            return;
+        }
+
+        holder.dht_announce = enabled;
+
+        // Restart the lookup so the change takes effect. The new request is started before the old
+        // one is dropped so the lookup keeps the peers it found so far.
+        if holder.dht.is_some() {
             holder.dht = Some(
                 self.inner
-                    .start_dht_lookup(repository_info_hash(holder.vault.repository_id())),
+                    .start_dht_lookup(repository_info_hash(holder.vault.repository_id()), enabled),
             );
-        } else {
-            holder.dht = None;
         }
     }
 
+    pub fn is_dht_announce_enabled(&self) -> bool {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key].dht_announce
+    }
+
     /// This function provides the information to the user whether DHT is enabled for this
     /// repository, not necessarily whether the DHT tasks are currently running. The subtle
     /// difference is in that this function should return true even in case e.g. the whole network
@@ -399,6 +886,23 @@ impl Registration {
         state.registry[self.key].dht.is_some()
     }
 
+    /// Searches the DHT for peers of this repository (and announces it, unless disabled with
+    /// [`Self::set_dht_announce_enabled`]) right away instead of waiting for the next periodic
+    /// search which can take several minutes. Useful e.g. when the user explicitly asks to find
+    /// peers. The next periodic search is then scheduled relative to this one. To not flood the
+    /// DHT, this is rate limited to one search per 30 seconds. Returns `false` (without doing
+    /// anything) if the rate limit has been hit or if DHT is disabled for this repository or not
+    /// running.
+    pub fn find_peers_now(&self) -> bool {
+        let state = self.inner.state.lock().unwrap();
+
+        state.registry[self.key]
+            .dht
+            .as_ref()
+            .map(|dht| dht.search_now())
+            .unwrap_or(false)
+    }
+
     pub async fn set_pex_enabled(&self, enabled: bool) {
         set_metadata_bool(&self.inner, self.key, PEX_ENABLED, enabled).await;
 
@@ -410,13 +914,135 @@ impl Registration {
         let state = self.inner.state.lock().unwrap();
         state.registry[self.key].pex.is_enabled()
     }
+
+    /// Number of connected peers this repository is currently linked with.
+    pub(crate) fn linked_peer_count(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        let local_id = state.registry[self.key].vault.local_id;
+
+        state
+            .message_brokers
+            .iter()
+            .flat_map(|brokers| brokers.values())
+            .filter(|broker| broker.is_linked(local_id))
+            .count()
+    }
+
+    /// Returns the currently active connections to the peers this repository is linked with, that
+    /// is, the peers it's currently exchanging data with. Unlike [`Network::connections`], which
+    /// returns all the connections regardless of the repositories.
+    pub fn linked_peers(&self) -> Vec<ConnectionInfo> {
+        let repository_id = *self.inner.state.lock().unwrap().registry[self.key]
+            .vault
+            .repository_id();
+
+        self.inner
+            .connections()
+            .into_iter()
+            .filter(|connection| connection.repositories.contains(&repository_id))
+            .collect()
+    }
+
+    /// Sets the selective sync filter: only the content of files whose path matches the given
+    /// glob-style patterns gets downloaded. Patterns prefixed with `!` exclude matching paths.
+    /// Directory listings are still synced in full. Empty `patterns` means sync everything (the
+    /// default). The filter is persisted in the repository.
+    ///
+    /// Files that start matching later (because the filter changed or because they were moved)
+    /// get downloaded then. Files that stop matching keep their already downloaded content.
+    ///
+    /// Note this has no effect on blind replicas which always sync everything.
+    ///
+    /// Returns an error if the filter couldn't be persisted, in which case it is not applied.
+    pub async fn set_sync_filter(&self, patterns: Vec<String>) -> crate::Result<()> {
+        let filter = SyncFilter::new(patterns);
+        let vault = self.inner.state.lock().unwrap().registry[self.key]
+            .vault
+            .clone();
+        let metadata = vault.metadata();
+
+        if filter.is_empty() {
+            metadata.remove(SYNC_FILTER).await?;
+        } else {
+            metadata
+                .set(SYNC_FILTER, filter.patterns().join("\n"))
+                .await?;
+        }
+
+        vault.sync_filter.send_if_modified(|current| {
+            if *current != filter {
+                *current = filter;
+                true
+            } else {
+                false
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Returns the patterns of the current selective sync filter (see [`Self::set_sync_filter`]).
+    pub fn sync_filter(&self) -> Vec<String> {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key]
+            .vault
+            .sync_filter
+            .borrow()
+            .patterns()
+    }
+
+    /// Returns the addresses of the peers this repository was recently linked with, most recent
+    /// first. The list is persisted in the repository (encrypted, and only if the repository was
+    /// registered with at least read access) and on the next registration these peers are
+    /// reconnected to right away, without waiting for them to be discovered.
+    pub fn known_good_peers(&self) -> Vec<PeerAddr> {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key].known_peers.peers().to_vec()
+    }
+
+    /// Sets the maximum number of peers retained in [`Self::known_good_peers`]. Zero disables
+    /// remembering the peers. The limit is persisted in the repository.
+    pub async fn set_known_good_peers_limit(&self, limit: usize) {
+        let (metadata, known_peers) = {
+            let mut state = self.inner.state.lock().unwrap();
+            let holder = &mut state.registry[self.key];
+            holder.known_peers.set_limit(limit);
+
+            (holder.vault.metadata(), holder.known_peers.clone())
+        };
+
+        known_peers.save(&metadata).await;
+    }
+
+    pub fn known_good_peers_limit(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key].known_peers.limit()
+    }
 }
 
 impl Drop for Registration {
     fn drop(&mut self) {
         let mut state = self.inner.state.lock().unwrap();
+        let state = &mut *state;
+
+        if let Some(mut holder) = state.registry.try_remove(self.key) {
+            holder.reconnect_peers.clear();
+
+            // If the repository has other registrations, keep it linked (the links use only the
+            // resources shared with them) and hand the recording of the known peers over to one of
+            // them.
+            if let Some((_, other)) = state
+                .registry
+                .iter_mut()
+                .find(|(_, other)| other.vault.local_id == holder.vault.local_id)
+            {
+                if other.known_peers_task.is_none() {
+                    other.known_peers_task = holder.known_peers_task.take();
+                }
+
+                return;
+            }
 
-        if let Some(holder) = state.registry.try_remove(self.key) {
             if let Some(brokers) = &mut state.message_brokers {
                 for broker in brokers.values_mut() {
                     broker.destroy_link(holder.vault.local_id);
@@ -434,8 +1060,16 @@ async fn set_metadata_bool(inner: &Inner, key: usize, name: &str, value: bool) {
 struct RegistrationHolder {
     vault: Vault,
     dht: Option<dht_discovery::LookupRequest>,
-    pex: PexRepository,
+    dht_announce: bool,
+    // Shared by all the registrations of the same repository (see `Network::register`).
+    pex: Arc<PexRepository>,
     response_limiter: Arc<Semaphore>,
+    known_peers: KnownPeers,
+    link_established_tx: mpsc::UnboundedSender<PublicRuntimeId>,
+    reconnect_peers: SeenPeers,
+    _dht_activity_task: ScopedAbortHandle,
+    known_peers_task: Option<ScopedAbortHandle>,
+    _reconnect_task: ScopedAbortHandle,
 }
 
 struct Inner {
@@ -450,17 +1084,32 @@ struct Inner {
     port_forwarder: upnp::PortForwarder,
     port_forwarder_state: BlockingMutex<ComponentState<PortMappings>>,
     local_discovery_state: BlockingMutex<ComponentState<ScopedAbortHandle>>,
+    network_change_watch_state: BlockingMutex<ComponentState<ScopedAbortHandle>>,
+    // Addresses and mode of the last `bind` call.
+    bind_request: BlockingMutex<(Vec<PeerAddr>, BindMode)>,
     dht_discovery: DhtDiscovery,
     dht_discovery_tx: mpsc::UnboundedSender<SeenPeer>,
     pex_discovery: PexDiscovery,
     stun_clients: StunClients,
     connection_deduplicator: ConnectionDeduplicator,
     on_protocol_mismatch_tx: uninitialized_watch::Sender<()>,
+    on_clock_skew_tx: uninitialized_watch::Sender<()>,
+    events_tx: NetworkEventSender,
     user_provided_peers: SeenPeers,
+    peer_filter: PeerFilter,
     // Note that unwrapping the upgraded weak pointer should be fine because if the underlying Arc
     // was Dropped, we would not be asking for the upgrade in the first place.
     tasks: Weak<BlockingMutex<JoinSet<()>>>,
+    task_counter: TaskCounter,
     highest_seen_protocol_version: BlockingMutex<Version>,
+    bad_block_limit: BadBlockLimit,
+    request_limits: RequestLimits,
+    prefer_newest_connection: AtomicBool,
+    message_compression: AtomicBool,
+    clock_skew_detection: AtomicBool,
+    connection_idle_timeout: BlockingMutex<Option<Duration>>,
+    // Notified whenever a repository gets registered.
+    on_registration_tx: watch::Sender<()>,
     // Used to prevent repeatedly connecting to self.
     our_addresses: BlockingMutex<HashSet<PeerAddr>>,
 }
@@ -469,13 +1118,30 @@ struct State {
     // This is None once the network calls shutdown.
     message_brokers: Option<HashMap<PublicRuntimeId, MessageBroker>>,
     registry: Slab<RegistrationHolder>,
+    max_registrations: Option<usize>,
 }
 
 impl State {
-    fn create_link(&mut self, repo: Vault, pex: &PexRepository, response_limiter: Arc<Semaphore>) {
+    fn is_registration_limit_reached(&self) -> bool {
+        self.max_registrations
+            .is_some_and(|max| self.registry.len() >= max)
+    }
+
+    fn create_link(
+        &mut self,
+        repo: Vault,
+        pex: &PexRepository,
+        response_limiter: Arc<Semaphore>,
+        link_established_tx: mpsc::UnboundedSender<PublicRuntimeId>,
+    ) {
         if let Some(brokers) = &mut self.message_brokers {
             for broker in brokers.values_mut() {
-                broker.create_link(repo.clone(), pex, response_limiter.clone())
+                broker.create_link(
+                    repo.clone(),
+                    pex,
+                    response_limiter.clone(),
+                    link_established_tx.clone(),
+                )
             }
         }
     }
@@ -486,10 +1152,15 @@ impl Inner {
         self.state.lock().unwrap().message_brokers.is_none()
     }
 
-    async fn bind(self: &Arc<Self>, bind: &[PeerAddr]) {
+    async fn bind(self: &Arc<Self>, bind: &[PeerAddr], mode: BindMode) {
+        *self.bind_request.lock().unwrap() = (bind.to_vec(), mode);
+        self.bind_stacks(bind, mode).await
+    }
+
+    async fn bind_stacks(self: &Arc<Self>, bind: &[PeerAddr], mode: BindMode) {
         let conn = Connectivity::infer(bind);
 
-        let bind = StackAddresses::from(bind);
+        let bind = StackAddresses::new(bind, mode);
 
         // TODO: Would be preferable to only rebind those stacks that actually need rebinding.
         if !self.gateway.addresses().any_stack_needs_rebind(&bind) {
@@ -499,6 +1170,10 @@ impl Inner {
         // Gateway
         let side_channel_makers = self.gateway.bind(&bind).instrument(self.span.clone()).await;
 
+        for addr in self.gateway.listener_local_addrs() {
+            self.events_tx.send(NetworkEvent::ListenerBound(addr));
+        }
+
         let (side_channel_maker_v4, side_channel_maker_v6) = match conn {
             Connectivity::Full => side_channel_makers,
             Connectivity::LocalOnly | Connectivity::Disabled => (None, None),
@@ -610,16 +1285,123 @@ impl Inner {
                 break;
             }
 
-            self.spawn(
+            if !self.is_discovered_peer_allowed(&peer, PeerSource::LocalDiscovery) {
+                continue;
+            }
+
+            self.try_spawn(
                 self.clone()
                     .handle_peer_found(peer, PeerSource::LocalDiscovery),
             );
         }
     }
 
-    fn start_dht_lookup(&self, info_hash: InfoHash) -> dht_discovery::LookupRequest {
+    fn handle_network_change(self: &Arc<Self>) {
+        tracing::info!("Network change");
+
+        // The port mappings are tied to our address in the local network which might have changed.
+        self.refresh_port_mappings();
+
+        // Restarting local discovery makes it immediately multicast on the current interfaces.
+        {
+            let mut state = self.local_discovery_state.lock().unwrap();
+            if state.is_enabled() {
+                if let Some(handle) = self.spawn_local_discovery() {
+                    state.enable(handle.into());
+                } else {
+                    state.disable(DisableReason::Implicit);
+                }
+            }
+        }
+
+        self.dht_discovery.wake_up_lookups();
+
+        let (addrs, mode) = self.bind_request.lock().unwrap().clone();
+
+        // Listeners bound to specific addresses might no longer receive anything because the
+        // address went away or moved to a different interface.
+        if addrs.iter().any(|addr| !addr.ip().is_unspecified()) {
+            self.spawn(
+                self.clone()
+                    .rebind_after_network_change(addrs, mode)
+                    .instrument(self.span.clone()),
+            );
+        }
+    }
+
+    async fn rebind_after_network_change(
+        self: Arc<Self>,
+        mut addrs: Vec<PeerAddr>,
+        mode: BindMode,
+    ) {
+        // Keep the randomly assigned ports so the peers that know them can still reach us.
+        let bound_addrs = self.gateway.listener_local_addrs();
+
+        for addr in &mut addrs {
+            if addr.port() != 0 {
+                continue;
+            }
+
+            if let Some(bound_addr) = bound_addrs.iter().find(|bound_addr| {
+                bound_addr.is_quic() == addr.is_quic() && bound_addr.ip() == addr.ip()
+            }) {
+                addr.set_port(bound_addr.port());
+            }
+        }
+
+        // Release the current addresses first so they can be bound again.
+        self.gateway.unbind();
+        self.bind_stacks(&addrs, mode).await;
+    }
+
+    fn refresh_port_mappings(&self) {
+        let mut state = self.port_forwarder_state.lock().unwrap();
+
+        // Drop the current mappings first so the forwarder restarts and rediscovers the gateway
+        // devices.
+        if state.disable_if_enabled(DisableReason::Implicit).is_some() {
+            state.enable(PortMappings::new(&self.port_forwarder, &self.gateway));
+        }
+    }
+
+    fn spawn_network_change_watch(self: &Arc<Self>) -> AbortHandle {
+        self.spawn(
+            self.clone()
+                .run_network_change_watch()
+                .instrument(self.span.clone()),
+        )
+    }
+
+    async fn run_network_change_watch(self: Arc<Self>) {
+        let initial = interface::find_ipv4_multicast_interfaces().await;
+        let mut changes = interface::watch_ipv4_multicast_interfaces();
+        let mut first = true;
+
+        while let Some(change) = changes.recv().await {
+            // The watch first reports all the currently existing interfaces as added.
+            if mem::take(&mut first) {
+                if let InterfaceChange::Added(added) = &change {
+                    if *added == initial {
+                        continue;
+                    }
+                }
+            }
+
+            if self.is_shutdown() {
+                break;
+            }
+
+            self.handle_network_change();
+        }
+    }
+
+    fn start_dht_lookup(
+        &self,
+        info_hash: InfoHash,
+        announce: bool,
+    ) -> dht_discovery::LookupRequest {
         self.dht_discovery
-            .start_lookup(info_hash, self.dht_discovery_tx.clone())
+            .start_lookup(info_hash, announce, self.dht_discovery_tx.clone())
     }
 
     async fn run_dht(self: Arc<Self>, mut discovery_rx: mpsc::UnboundedReceiver<SeenPeer>) {
@@ -628,7 +1410,90 @@ impl Inner {
                 break;
             }
 
-            self.spawn(self.clone().handle_peer_found(seen_peer, PeerSource::Dht));
+            if !self.is_discovered_peer_allowed(&seen_peer, PeerSource::Dht) {
+                continue;
+            }
+
+            self.try_spawn(self.clone().handle_peer_found(seen_peer, PeerSource::Dht));
+        }
+    }
+
+    // Notifies the DHT lookup of the given registration about changes in the repository so it can
+    // adjust how often it re-announces the repository.
+    async fn forward_dht_activity(self: Arc<Self>, key: usize, mut rx: broadcast::Receiver<Event>) {
+        loop {
+            match rx.recv().await {
+                Ok(Event {
+                    payload: Payload::BranchChanged(_),
+                    ..
+                })
+                | Err(RecvError::Lagged(_)) => (),
+                Ok(_) => continue,
+                Err(RecvError::Closed) => break,
+            }
+
+            let state = self.state.lock().unwrap();
+
+            if let Some(dht) = state
+                .registry
+                .get(key)
+                .and_then(|holder| holder.dht.as_ref())
+            {
+                dht.mark_changed();
+            }
+        }
+    }
+
+    // Remembers the addresses of the peers the repository got linked with.
+    async fn record_known_peers(
+        self: Arc<Self>,
+        local_id: LocalId,
+        mut rx: mpsc::UnboundedReceiver<PublicRuntimeId>,
+    ) {
+        while let Some(runtime_id) = rx.recv().await {
+            // Only the addresses the peer can be connected to. The address of an incoming TCP
+            // connection has the port assigned by the peer's OS, not the one it listens on.
+            let addrs: Vec<_> = self
+                .connection_deduplicator
+                .active_connections()
+                .into_iter()
+                .filter(|connection| connection.runtime_id == runtime_id)
+                .filter(|connection| {
+                    connection.direction == ConnectionDirection::Outgoing
+                        || connection.addr.is_quic()
+                })
+                .map(|connection| connection.addr)
+                .collect();
+
+            let (metadata, known_peers) = {
+                let mut state = self.state.lock().unwrap();
+                let mut saved = None;
+
+                // Keep the known peers of all the registrations of the repository up to date.
+                for (_, holder) in &mut state.registry {
+                    if holder.vault.local_id != local_id {
+                        continue;
+                    }
+
+                    let mut changed = false;
+
+                    for addr in &addrs {
+                        changed |= holder.known_peers.insert(*addr);
+                    }
+
+                    if changed && saved.is_none() {
+                        saved = Some((holder.vault.metadata(), holder.known_peers.clone()));
+                    }
+                }
+
+                let Some(saved) = saved else {
+                    continue;
+                };
+
+                saved
+            };
+
+            known_peers.save(&metadata).await;
         }
     }
 
@@ -638,13 +1503,21 @@ impl Inner {
                 break;
             }
 
-            self.spawn(
+            if !self.is_discovered_peer_allowed(&peer, PeerSource::PeerExchange) {
+                continue;
+            }
+
+            self.try_spawn(
                 self.clone()
                     .handle_peer_found(peer, PeerSource::PeerExchange),
             );
         }
     }
 
+    fn is_discovered_peer_allowed(&self, peer: &SeenPeer, source: PeerSource) -> bool {
+        self.peer_filter.check(*peer.initial_addr(), source)
+    }
+
     fn establish_user_provided_connection(self: Arc<Self>, peer: &PeerAddr) {
         let peer = match self.user_provided_peers.insert(*peer) {
             Some(peer) => peer,
@@ -683,7 +1556,7 @@ impl Inner {
                     });
                     monitor.mark_as_connecting(permit.id());
 
-                    self.spawn(async move {
+                    self.try_spawn(async move {
                         this.handle_connection(stream, permit, &monitor).await;
                     });
                 }
@@ -768,38 +1641,78 @@ impl Inner {
                 None => break,
             };
 
-            if !self.handle_connection(socket, permit, &monitor).await {
-                break;
+            match self.handle_connection(socket, permit, &monitor).await {
+                Reconnect::Yes => (),
+                Reconnect::No => break,
+                Reconnect::OnRegistration(mut rx) => {
+                    tracing::debug!(
+                        parent: monitor.span(),
+                        "Reconnecting once a repository gets registered"
+                    );
+
+                    if rx.changed().await.is_err() {
+                        break;
+                    }
+
+                    backoff.reset();
+                    next_sleep = None;
+                }
             }
         }
     }
 
-    /// Return true iff the peer is suitable for reconnection.
+    /// Returns whether and when the peer is suitable for reconnection.
     async fn handle_connection(
         &self,
         mut stream: raw::Stream,
         permit: ConnectionPermit,
         monitor: &ConnectionMonitor,
-    ) -> bool {
+    ) -> Reconnect {
         tracing::debug!(parent: monitor.span(), "Handshaking");
 
         permit.mark_as_handshaking();
         monitor.mark_as_handshaking();
 
-        let handshake_result = perform_handshake(&mut stream, VERSION, &self.this_runtime_id).await;
-
-        if let Err(error) = &handshake_result {
-            tracing::debug!(parent: monitor.span(), ?error, "Handshake failed");
-        }
-
-        let that_runtime_id = match handshake_result {
-            Ok(writer_id) => writer_id,
-            Err(HandshakeError::ProtocolVersionMismatch(their_version)) => {
+        let handshake_result = perform_handshake(
+            &mut stream,
+            VERSIONS,
+            &self.this_runtime_id,
+            self.clock_skew_detection.load(Ordering::Relaxed),
+        )
+        .await;
+
+        let (that_runtime_id, protocol_version, clock_skew) = match handshake_result {
+            Ok(result) => result,
+            Err(HandshakeError::ProtocolVersionMismatch(VersionMismatch::TheirsNewer(
+                their_version,
+            ))) => {
+                tracing::info!(
+                    parent: monitor.span(),
+                    ?their_version,
+                    our_version = ?VERSIONS.max,
+                    "Refusing connection: peer requires a newer protocol version"
+                );
                 self.on_protocol_mismatch(their_version);
-                return false;
+                return Reconnect::No;
             }
-            Err(HandshakeError::Timeout | HandshakeError::BadMagic | HandshakeError::Fatal(_)) => {
-                return false
+            Err(HandshakeError::ProtocolVersionMismatch(VersionMismatch::TheirsOlder(
+                their_version,
+            ))) => {
+                tracing::info!(
+                    parent: monitor.span(),
+                    ?their_version,
+                    our_min_version = ?VERSIONS.min,
+                    "Refusing connection: peer uses an outdated protocol version"
+                );
+                return Reconnect::No;
+            }
+            Err(
+                error @ (HandshakeError::Timeout
+                | HandshakeError::BadMagic
+                | HandshakeError::Fatal(_)),
+            ) => {
+                tracing::debug!(parent: monitor.span(), ?error, "Handshake failed");
+                return Reconnect::No;
             }
         };
 
@@ -807,34 +1720,60 @@ impl Inner {
         if that_runtime_id == self.this_runtime_id.public() {
             tracing::debug!(parent: monitor.span(), "Connection from self, discarding");
             self.our_addresses.lock().unwrap().insert(permit.addr());
-            return false;
+            return Reconnect::No;
+        }
+
+        if let Some(clock_skew) = clock_skew {
+            permit.set_clock_skew(clock_skew);
         }
 
+        permit.set_protocol_version(protocol_version);
         permit.mark_as_active(that_runtime_id);
         monitor.mark_as_active(that_runtime_id);
-        tracing::info!(parent: monitor.span(), "Connected");
+        tracing::info!(parent: monitor.span(), ?protocol_version, "Connected");
+
+        if let Some(clock_skew) =
+            clock_skew.filter(|skew| skew.magnitude() > CLOCK_SKEW_WARNING_THRESHOLD)
+        {
+            tracing::warn!(
+                parent: monitor.span(),
+                skew_ms = clock_skew.as_millis(),
+                "Peer's clock differs significantly from ours"
+            );
+            self.on_clock_skew_tx.send(()).unwrap_or(());
+        }
 
         let released = permit.released();
+        let tracker = permit.tracker();
+        let addr = permit.addr();
+        let source = permit.source();
+        let permit_id = permit.id();
 
-        {
+        let (bad_block_limit_exceeded, mut replaced) = {
             let mut state = self.state.lock().unwrap();
             let state = &mut *state;
 
             let brokers = match &mut state.message_brokers {
                 Some(brokers) => brokers,
                 // Network has been shut down.
-                None => return false,
+                None => return Reconnect::No,
             };
 
+            let duplicate = brokers.contains_key(&that_runtime_id);
+
             let broker = brokers.entry(that_runtime_id).or_insert_with(|| {
                 let mut broker = self.span.in_scope(|| {
                     MessageBroker::new(
                         self.this_runtime_id.public(),
                         that_runtime_id,
+                        protocol_version,
                         self.pex_discovery.new_peer(),
                         self.peers_monitor
                             .make_child(format!("{:?}", that_runtime_id.as_public_key())),
                         self.traffic_tracker.clone(),
+                        BadBlockCounter::new(self.bad_block_limit.clone()),
+                        self.request_limits.clone(),
+                        self.message_compression.load(Ordering::Relaxed),
                     )
                 });
 
@@ -842,18 +1781,30 @@ impl Inner {
                 // lookup but make sure we correctly handle edge cases, for example, when we have
                 // more than one repository shared with the peer.
                 for (_, holder) in &state.registry {
+                    // Link each repository only once even if it's registered more than once.
+                    if broker.is_linked(holder.vault.local_id) {
+                        continue;
+                    }
+
                     broker.create_link(
                         holder.vault.clone(),
                         &holder.pex,
                         holder.response_limiter.clone(),
+                        holder.link_established_tx.clone(),
                     );
                 }
 
                 broker
             });
 
-            broker.add_connection(stream, permit);
-        }
+            let replaced = broker.add_connection(stream, permit);
+
+            if duplicate && self.prefer_newest_connection.load(Ordering::Relaxed) {
+                broker.close_replaced_connections(permit_id);
+            }
+
+            (broker.bad_block_limit_exceeded(), replaced)
+        };
 
         let _remover = MessageBrokerEntryGuard {
             state: &self.state,
@@ -861,9 +1812,153 @@ impl Inner {
             monitor,
         };
 
-        released.await;
+        self.events_tx.send(NetworkEvent::PeerConnected {
+            addr,
+            runtime_id: that_runtime_id,
+            source,
+        });
+
+        let reconnect = select! {
+            _ = released => {
+                if replaced.try_recv().is_ok() {
+                    // Replaced by a newer connection from the same peer. Reconnecting would only
+                    // end up replacing that one.
+                    tracing::debug!(parent: monitor.span(), "Connection replaced by a newer one");
+                    Reconnect::No
+                } else {
+                    Reconnect::Yes
+                }
+            }
+            _ = bad_block_limit_exceeded => {
+                tracing::warn!(
+                    parent: monitor.span(),
+                    "Disconnecting: peer sent too many invalid blocks"
+                );
+                self.disconnect(&that_runtime_id).await;
+                Reconnect::No
+            }
+            on_registration = self.wait_for_idle(&that_runtime_id, &tracker) => {
+                tracing::info!(parent: monitor.span(), "Disconnecting: connection idle");
+
+                if let Some(broker) = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .message_brokers
+                    .as_mut()
+                    .and_then(|brokers| brokers.get_mut(&that_runtime_id))
+                {
+                    broker.close_connection(permit_id);
+                }
+
+                // Reconnecting right away would only create another idle connection.
+                Reconnect::OnRegistration(on_registration)
+            }
+        };
+
+        self.events_tx.send(NetworkEvent::PeerDisconnected {
+            addr,
+            runtime_id: that_runtime_id,
+            source,
+        });
+
+        reconnect
+    }
+
+    /// Completes when the connection with the given traffic tracker to the given peer has been
+    /// idle (no repository linked with the peer and no traffic) for longer than the idle timeout.
+    /// Never completes if the timeout is disabled. Returns a receiver notified when a repository
+    /// gets registered after the connection was found idle.
+    async fn wait_for_idle(
+        &self,
+        that_runtime_id: &PublicRuntimeId,
+        tracker: &TrafficTracker,
+    ) -> watch::Receiver<()> {
+        let mut interval = time::interval(CONNECTION_IDLE_CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let mut last_stats = tracker.get();
+        let mut idle_since = Instant::now();
+
+        loop {
+            interval.tick().await;
+
+            let stats = tracker.get();
+            let (linked, on_registration) = {
+                let state = self.state.lock().unwrap();
+                let linked = state
+                    .message_brokers
+                    .as_ref()
+                    .and_then(|brokers| brokers.get(that_runtime_id))
+                    .map(|broker| broker.has_links())
+                    .unwrap_or(false);
+
+                // Subscribe while holding the lock so that any repository registered after this
+                // check is notified about (the registry is modified under the same lock).
+                (linked, self.on_registration_tx.subscribe())
+            };
+
+            if linked || stats.send != last_stats.send || stats.recv != last_stats.recv {
+                last_stats = stats;
+                idle_since = Instant::now();
+                continue;
+            }
+
+            let Some(timeout) = *self.connection_idle_timeout.lock().unwrap() else {
+                continue;
+            };
+
+            if idle_since.elapsed() >= timeout {
+                break on_registration;
+            }
+        }
+    }
+
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections = self.connection_deduplicator.active_connections();
+
+        let state = self.state.lock().unwrap();
+        let Some(brokers) = &state.message_brokers else {
+            return connections;
+        };
+
+        for connection in &mut connections {
+            let Some(broker) = brokers.get(&connection.runtime_id) else {
+                continue;
+            };
+
+            connection.bad_blocks = broker.bad_blocks();
+            connection.repositories.clear();
 
-        true
+            for (_, holder) in &state.registry {
+                let id = *holder.vault.repository_id();
+
+                // The same repository can be registered more than once.
+                if broker.is_linked(holder.vault.local_id) && !connection.repositories.contains(&id)
+                {
+                    connection.repositories.push(id);
+                }
+            }
+        }
+
+        connections
+    }
+
+    async fn disconnect(&self, runtime_id: &PublicRuntimeId) -> bool {
+        let broker = self
+            .state
+            .lock()
+            .unwrap()
+            .message_brokers
+            .as_mut()
+            .and_then(|brokers| brokers.remove(runtime_id));
+
+        if let Some(broker) = broker {
+            broker.shutdown().await;
+            true
+        } else {
+            false
+        }
     }
 
     fn on_protocol_mismatch(&self, their_version: Version) {
@@ -880,6 +1975,30 @@ impl Inner {
     }
 
     fn spawn<Fut>(&self, f: Fut) -> AbortHandle
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let guard = self.task_counter.acquire();
+        self.spawn_counted(f, guard)
+    }
+
+    // Spawns a task whose number grows with the number of peers, unless the task limit has been
+    // reached.
+    fn try_spawn<Fut>(&self, f: Fut)
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if let Some(guard) = self.task_counter.try_acquire() {
+            self.spawn_counted(f, guard);
+        } else {
+            tracing::warn!(
+                max = ?self.task_counter.max(),
+                "Task limit reached, not spawning a new one"
+            );
+        }
+    }
+
+    fn spawn_counted<Fut>(&self, f: Fut, guard: TaskGuard) -> AbortHandle
     where
         Fut: Future<Output = ()> + Send + 'static,
     {
@@ -890,22 +2009,28 @@ impl Inner {
             .unwrap()
             .lock()
             .unwrap()
-            .spawn(f)
+            .spawn(async move {
+                let _guard = guard;
+                f.await
+            })
     }
 }
 
 //------------------------------------------------------------------------------
 
-// Exchange runtime ids with the peer. Returns their (verified) runtime id.
+// Negotiate the protocol version and exchange runtime ids and current time with the peer. Returns
+// their (verified) runtime id, the negotiated protocol version and the estimated skew between their
+// clock and ours (if both sides have the clock skew detection enabled).
 async fn perform_handshake(
     stream: &mut raw::Stream,
-    this_version: Version,
+    this_versions: VersionRange,
     this_runtime_id: &SecretRuntimeId,
-) -> Result<PublicRuntimeId, HandshakeError> {
+    clock_skew_detection: bool,
+) -> Result<(PublicRuntimeId, Version, Option<ClockSkew>), HandshakeError> {
     let result = tokio::time::timeout(std::time::Duration::from_secs(5), async move {
         stream.write_all(MAGIC).await?;
 
-        this_version.write_into(stream).await?;
+        this_versions.write_into(stream).await?;
 
         let mut that_magic = [0; MAGIC.len()];
         stream.read_exact(&mut that_magic).await?;
@@ -914,14 +2039,15 @@ async fn perform_handshake(
             return Err(HandshakeError::BadMagic);
         }
 
-        let that_version = Version::read_from(stream).await?;
-        if that_version > this_version {
-            return Err(HandshakeError::ProtocolVersionMismatch(that_version));
-        }
+        let that_versions = VersionRange::read_from(stream).await?;
+        let version = this_versions
+            .negotiate(&that_versions)
+            .map_err(HandshakeError::ProtocolVersionMismatch)?;
 
         let that_runtime_id = runtime_id::exchange(this_runtime_id, stream).await?;
+        let clock_skew = clock_skew::exchange(stream, clock_skew_detection).await?;
 
-        Ok(that_runtime_id)
+        Ok((that_runtime_id, version, clock_skew))
     })
     .await;
 
@@ -931,10 +2057,20 @@ async fn perform_handshake(
     }
 }
 
+// What to do after a connection to a peer has been closed.
+enum Reconnect {
+    // Reconnect (with backoff).
+    Yes,
+    // Don't reconnect.
+    No,
+    // Reconnect once a repository gets registered.
+    OnRegistration(watch::Receiver<()>),
+}
+
 #[derive(Debug, Error)]
 enum HandshakeError {
     #[error("protocol version mismatch")]
-    ProtocolVersionMismatch(Version),
+    ProtocolVersionMismatch(VersionMismatch),
     #[error("bad magic")]
     BadMagic,
     #[error("timeout")]
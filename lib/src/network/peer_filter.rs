@@ -0,0 +1,104 @@
+use super::{PeerAddr, PeerSource};
+use crate::collections::HashMap;
+use deadlock::BlockingMutex;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Callback which decides whether to connect to a peer found by one of the discovery mechanisms
+/// (local discovery, DHT, PEX, known peers). Returns `true` to connect to the peer, `false` to ignore it.
+pub type PeerFilterFn = dyn Fn(PeerAddr, PeerSource) -> bool + Send + Sync;
+
+// How long to remember a rejected peer for. Discovery mechanisms keep finding the same peers
+// repeatedly, so without this the filter would be consulted (possibly prompting the user) every
+// time.
+const REJECTION_DURATION: Duration = Duration::from_secs(5 * 60);
+
+pub(super) struct PeerFilter {
+    filter: BlockingMutex<Option<Arc<PeerFilterFn>>>,
+    rejected: BlockingMutex<HashMap<PeerAddr, Instant>>,
+}
+
+impl PeerFilter {
+    pub fn new() -> Self {
+        Self {
+            filter: BlockingMutex::new(None),
+            rejected: BlockingMutex::new(HashMap::default()),
+        }
+    }
+
+    /// Sets or clears the filter. This also forgets all previously rejected peers.
+    pub fn set(&self, filter: Option<Box<PeerFilterFn>>) {
+        *self.filter.lock().unwrap() = filter.map(Arc::from);
+        self.rejected.lock().unwrap().clear();
+    }
+
+    /// Returns whether we should connect to the discovered peer.
+    pub fn check(&self, addr: PeerAddr, source: PeerSource) -> bool {
+        // Clone the filter so it's not called with the lock held.
+        let Some(filter) = self.filter.lock().unwrap().clone() else {
+            return true;
+        };
+
+        let now = Instant::now();
+
+        {
+            let mut rejected = self.rejected.lock().unwrap();
+            rejected.retain(|_, timestamp| now.duration_since(*timestamp) < REJECTION_DURATION);
+
+            if rejected.contains_key(&addr) {
+                return false;
+            }
+        }
+
+        if filter(addr, source) {
+            true
+        } else {
+            tracing::debug!(?addr, ?source, "Discovered peer rejected by filter");
+            self.rejected.lock().unwrap().insert(addr, now);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::Ipv4Addr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn rejected_peers_are_remembered() {
+        let filter = PeerFilter::new();
+
+        let addr_a = PeerAddr::Quic((Ipv4Addr::LOCALHOST, 1000).into());
+        let addr_b = PeerAddr::Quic((Ipv4Addr::LOCALHOST, 1001).into());
+
+        // No filter - everything is allowed.
+        assert!(filter.check(addr_a, PeerSource::Dht));
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        filter.set(Some(Box::new({
+            let calls = calls.clone();
+            move |addr, _| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                addr != addr_a
+            }
+        })));
+
+        assert!(!filter.check(addr_a, PeerSource::Dht));
+        assert!(!filter.check(addr_a, PeerSource::LocalDiscovery));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        assert!(filter.check(addr_b, PeerSource::Dht));
+        assert!(filter.check(addr_b, PeerSource::Dht));
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+        // Clearing the filter forgets the rejections.
+        filter.set(None);
+        assert!(filter.check(addr_a, PeerSource::Dht));
+    }
+}
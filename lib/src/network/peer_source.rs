@@ -29,6 +29,9 @@ pub enum PeerSource {
     Dht,
     /// Discovered on the Peer Exchange.
     PeerExchange,
+    /// Peer the repository was recently synced with, reconnected to on the repository
+    /// registration.
+    KnownPeer,
 }
 
 impl fmt::Display for PeerSource {
@@ -39,6 +42,7 @@ impl fmt::Display for PeerSource {
             PeerSource::LocalDiscovery => write!(f, "outgoing (locally discovered)"),
             PeerSource::Dht => write!(f, "outgoing (found on DHT)"),
             PeerSource::PeerExchange => write!(f, "outgoing (found on peer exchange)"),
+            PeerSource::KnownPeer => write!(f, "outgoing (known peer)"),
         }
     }
 }
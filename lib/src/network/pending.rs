@@ -1,19 +1,22 @@
 use super::{
-    constants::REQUEST_TIMEOUT,
+    constants::{REQUEST_TIMEOUT, REQUEST_TIMEOUT_GRACE_PERIOD},
     debug_payload::{DebugResponse, PendingDebugRequest},
     message::{Request, Response, ResponseDisambiguator},
 };
 use crate::{
     block_tracker::{BlockOffer, BlockPromise},
+    collections::HashMap,
     crypto::{sign::PublicKey, CacheHash, Hash, Hashable},
+    debug::next_span_id,
     protocol::{Block, BlockId, InnerNodes, LeafNodes, MultiBlockPresence, UntrustedProof},
     repository::RepositoryMonitor,
     sync::delay_map::DelayMap,
 };
 use deadlock::BlockingMutex;
+use std::task::Poll;
 use std::{future, sync::Arc, task::ready};
-use std::{task::Poll, time::Instant};
-use tokio::{sync::OwnedSemaphorePermit, task};
+use tokio::{sync::OwnedSemaphorePermit, task, time::Instant};
+use tracing::Span;
 
 pub(crate) enum PendingRequest {
     RootNode(PublicKey, PendingDebugRequest),
@@ -21,12 +24,51 @@ pub(crate) enum PendingRequest {
     Block(BlockOffer, PendingDebugRequest),
 }
 
+impl PendingRequest {
+    /// Span to handle this request in.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::RootNode(writer_id, debug) => tracing::info_span!(
+                "request",
+                id = next_span_id(),
+                kind = "root node",
+                ?writer_id,
+                ?debug
+            ),
+            Self::ChildNodes(hash, _, debug) => tracing::info_span!(
+                "request",
+                id = next_span_id(),
+                kind = "child nodes",
+                ?hash,
+                ?debug
+            ),
+            Self::Block(offer, debug) => tracing::info_span!(
+                "request",
+                id = next_span_id(),
+                kind = "block",
+                block_id = ?offer.block_id(),
+                ?debug
+            ),
+        }
+    }
+}
+
 pub(super) struct PendingResponse {
     pub response: ProcessedResponse,
     // These will be `None` if the request timeouted but we still received the response
     // afterwards.
     pub _client_permit: Option<ClientPermit>,
     pub block_promise: Option<BlockPromise>,
+    // Whether this is a response to a request that was still pending when the response arrived or
+    // that timed out no longer than `REQUEST_TIMEOUT_GRACE_PERIOD` ago.
+    solicited: bool,
+}
+
+impl PendingResponse {
+    /// Is this a response to a request we sent (even if it arrived slightly after the timeout)?
+    pub fn is_solicited(&self) -> bool {
+        self.solicited
+    }
 }
 
 pub(super) enum ProcessedResponse {
@@ -80,6 +122,9 @@ impl From<Response> for ProcessedResponse {
                 Self::ChildNodesError(hash, disambiguator, debug)
             }
             Response::BlockError(block_id, debug) => Self::BlockError(block_id, debug),
+            Response::BlockChunk(..) => {
+                unreachable!("block chunks must be reassembled before being processed")
+            }
         }
     }
 }
@@ -95,6 +140,8 @@ pub(crate) enum Key {
 pub(super) struct PendingRequests {
     monitor: Arc<RepositoryMonitor>,
     map: Arc<BlockingMutex<DelayMap<Key, RequestData>>>,
+    // Recently timed out requests, with the time they timed out at.
+    expired: Arc<BlockingMutex<HashMap<Key, Instant>>>,
 }
 
 impl PendingRequests {
@@ -102,6 +149,7 @@ impl PendingRequests {
         Self {
             monitor,
             map: Arc::new(BlockingMutex::new(DelayMap::default())),
+            expired: Arc::new(BlockingMutex::new(HashMap::default())),
         }
     }
 
@@ -154,6 +202,7 @@ impl PendingRequests {
             task::spawn(run_expiration_tracker(
                 self.monitor.clone(),
                 self.map.clone(),
+                self.expired.clone(),
             ));
         }
 
@@ -173,9 +222,9 @@ impl PendingRequests {
 
             request_removed(&self.monitor, &key);
 
-            self.monitor
-                .request_latency
-                .record(request_data.timestamp.elapsed());
+            let latency = request_data.timestamp.elapsed();
+
+            self.monitor.request_latency.record(latency);
 
             // We `drop` the `peer_permit` here but the `Client` will need the `client_permit` and
             // only `drop` it once the request is processed.
@@ -185,16 +234,29 @@ impl PendingRequests {
             });
             let block_promise = request_data.block_promise;
 
+            if let Some(block_promise) = &block_promise {
+                block_promise.record_latency(latency);
+            }
+
             PendingResponse {
                 response,
                 _client_permit: client_permit,
                 block_promise,
+                solicited: true,
             }
         } else {
+            let solicited = self
+                .expired
+                .lock()
+                .unwrap()
+                .remove(&key)
+                .is_some_and(|expired_at| expired_at.elapsed() <= REQUEST_TIMEOUT_GRACE_PERIOD);
+
             PendingResponse {
                 response,
                 _client_permit: None,
                 block_promise: None,
+                solicited,
             }
         }
     }
@@ -227,10 +289,26 @@ fn request_removed(monitor: &RepositoryMonitor, key: &Key) {
 async fn run_expiration_tracker(
     monitor: Arc<RepositoryMonitor>,
     request_map: Arc<BlockingMutex<DelayMap<Key, RequestData>>>,
+    expired_map: Arc<BlockingMutex<HashMap<Key, Instant>>>,
 ) {
-    while let Some((key, _)) = expired(&request_map).await {
+    while let Some((key, request_data)) = expired(&request_map).await {
         monitor.request_timeouts.increment(1);
         request_removed(&monitor, &key);
+
+        // Remember the request for a while so a late response to it is not mistaken for an
+        // unsolicited one.
+        {
+            let now = Instant::now();
+            let mut expired_map = expired_map.lock().unwrap();
+            expired_map.retain(|_, expired_at| now - *expired_at <= REQUEST_TIMEOUT_GRACE_PERIOD);
+            expired_map.insert(key, now);
+        }
+
+        // Penalize the peer for the timeout so the block is more likely to be requested from a
+        // different one next time.
+        if let Some(block_promise) = &request_data.block_promise {
+            block_promise.record_timeout(request_data.timestamp.elapsed());
+        }
     }
 }
 
@@ -265,3 +343,147 @@ impl Drop for ClientPermit {
         self.monitor.requests_pending.decrement(1.0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block_tracker::{BlockTracker, OfferState},
+        network::debug_payload::DebugResponse,
+    };
+    use metrics::NoopRecorder;
+    use state_monitor::StateMonitor;
+    use tokio::{sync::Semaphore, time};
+
+    #[tokio::test(start_paused = true)]
+    async fn request_timeout() {
+        let requests = PendingRequests::new(Arc::new(RepositoryMonitor::new(
+            StateMonitor::make_root(),
+            &NoopRecorder,
+        )));
+
+        let link_permits = Arc::new(Semaphore::new(1));
+        let peer_permits = Arc::new(Semaphore::new(1));
+        let writer_id = PublicKey::random();
+
+        requests
+            .insert(
+                PendingRequest::RootNode(writer_id, PendingDebugRequest::start()),
+                link_permits.clone().acquire_owned().await.unwrap(),
+                peer_permits.clone().acquire_owned().await.unwrap(),
+            )
+            .unwrap();
+
+        time::sleep(REQUEST_TIMEOUT / 2).await;
+        assert_eq!(link_permits.available_permits(), 0);
+        assert_eq!(peer_permits.available_permits(), 0);
+
+        time::sleep(REQUEST_TIMEOUT).await;
+        assert_eq!(link_permits.available_permits(), 1);
+        assert_eq!(peer_permits.available_permits(), 1);
+
+        // Response received after the timeout is still passed through, but without the permit.
+        let response = requests.remove(Response::RootNodeError(
+            writer_id,
+            DebugResponse::unsolicited(),
+        ));
+        assert!(response._client_permit.is_none());
+        assert!(response.is_solicited());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_response_grace_period() {
+        let requests = PendingRequests::new(Arc::new(RepositoryMonitor::new(
+            StateMonitor::make_root(),
+            &NoopRecorder,
+        )));
+
+        let link_permits = Arc::new(Semaphore::new(2));
+        let peer_permits = Arc::new(Semaphore::new(2));
+        let writer_ids = [PublicKey::random(), PublicKey::random()];
+
+        for writer_id in writer_ids {
+            requests
+                .insert(
+                    PendingRequest::RootNode(writer_id, PendingDebugRequest::start()),
+                    link_permits.clone().acquire_owned().await.unwrap(),
+                    peer_permits.clone().acquire_owned().await.unwrap(),
+                )
+                .unwrap();
+        }
+
+        // Response within the grace period after the timeout is still solicited...
+        time::sleep(REQUEST_TIMEOUT + REQUEST_TIMEOUT_GRACE_PERIOD / 2).await;
+        let response = requests.remove(Response::RootNodeError(
+            writer_ids[0],
+            DebugResponse::unsolicited(),
+        ));
+        assert!(response.is_solicited());
+
+        // ...but only once.
+        let response = requests.remove(Response::RootNodeError(
+            writer_ids[0],
+            DebugResponse::unsolicited(),
+        ));
+        assert!(!response.is_solicited());
+
+        // Response after the grace period is unsolicited.
+        time::sleep(REQUEST_TIMEOUT_GRACE_PERIOD).await;
+        let response = requests.remove(Response::RootNodeError(
+            writer_ids[1],
+            DebugResponse::unsolicited(),
+        ));
+        assert!(!response.is_solicited());
+
+        // Responses to requests never sent are unsolicited.
+        let response = requests.remove(Response::RootNodeError(
+            PublicKey::random(),
+            DebugResponse::unsolicited(),
+        ));
+        assert!(!response.is_solicited());
+    }
+
+    // Dropping the pending requests (which happens when the client is dropped mid-flight, e.g.
+    // because the connection was lost) must release the accepted blocks so they can be requested
+    // from another peer.
+    #[tokio::test]
+    async fn drop_releases_accepted_blocks() {
+        let tracker = BlockTracker::new();
+        let client_a = tracker.client();
+        let client_b = tracker.client();
+
+        let block_id: BlockId = rand::random();
+        client_a.register(block_id, OfferState::Approved);
+        client_b.register(block_id, OfferState::Approved);
+        tracker.require(block_id);
+
+        let requests = PendingRequests::new(Arc::new(RepositoryMonitor::new(
+            StateMonitor::make_root(),
+            &NoopRecorder,
+        )));
+
+        let link_permits = Arc::new(Semaphore::new(1));
+        let peer_permits = Arc::new(Semaphore::new(1));
+
+        let offer = client_a.offers().try_next().unwrap();
+        requests
+            .insert(
+                PendingRequest::Block(offer, PendingDebugRequest::start()),
+                link_permits.clone().acquire_owned().await.unwrap(),
+                peer_permits.clone().acquire_owned().await.unwrap(),
+            )
+            .unwrap();
+
+        // The block is being requested through `client_a` so it's not offered to `client_b`.
+        assert!(client_b.offers().try_next().is_none());
+
+        drop(requests);
+
+        assert_eq!(link_permits.available_permits(), 1);
+        assert_eq!(peer_permits.available_permits(), 1);
+
+        let offer = client_b.offers().try_next().unwrap();
+        assert_eq!(offer.block_id(), &block_id);
+        assert!(offer.accept().is_some());
+    }
+}
@@ -4,7 +4,30 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 // First string in a handshake, helps with weeding out connections with completely different
 // protocols on the other end.
 pub(super) const MAGIC: &[u8; 7] = b"OUISYNC";
-pub(super) const VERSION: Version = Version(12);
+pub(super) const VERSION: Version = Version(18);
+
+// Oldest protocol version we can still communicate with. Bump this when dropping support for an
+// older wire format.
+pub(super) const MIN_VERSION: Version = Version(15);
+
+// Protocol versions we support.
+pub(super) const VERSIONS: VersionRange = VersionRange {
+    min: MIN_VERSION,
+    max: VERSION,
+};
+
+// First version whose handshake contains the whole supported version range. Peers with older
+// versions send only a single version.
+const FIRST_RANGED_VERSION: Version = Version(15);
+
+// First version whose messages use the compact encoding (see `MessageCodec`).
+pub(super) const FIRST_COMPACT_VERSION: Version = Version(16);
+
+// First version supporting chunked block transfer (`Request::BlockChunks`).
+pub(super) const FIRST_CHUNKED_VERSION: Version = Version(17);
+
+// First version supporting compressed messages (see `MessageCodec::Compressible`).
+pub(super) const FIRST_COMPRESSED_VERSION: Version = Version(18);
 
 /// Protocol version
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
@@ -40,3 +63,119 @@ impl std::convert::From<Version> for u32 {
         v.0 as u32
     }
 }
+
+/// Range of protocol versions supported by a peer (inclusive on both ends).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub(super) struct VersionRange {
+    pub min: Version,
+    pub max: Version,
+}
+
+impl VersionRange {
+    // The max version is sent first so that peers predating the version negotiation (which read
+    // only a single version) see it and correctly detect that they are outdated.
+    pub async fn read_from<R>(io: &mut R) -> io::Result<Self>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let max = Version::read_from(io).await?;
+        let min = if max >= FIRST_RANGED_VERSION {
+            Version::read_from(io).await?
+        } else {
+            max
+        };
+
+        if min > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid protocol version range",
+            ));
+        }
+
+        Ok(Self { min, max })
+    }
+
+    pub async fn write_into<W>(&self, io: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        self.max.write_into(io).await?;
+        self.min.write_into(io).await
+    }
+
+    /// Returns the highest version supported by both `self` and `that`.
+    pub fn negotiate(&self, that: &Self) -> Result<Version, VersionMismatch> {
+        if that.min > self.max {
+            Err(VersionMismatch::TheirsNewer(that.max))
+        } else if that.max < self.min {
+            Err(VersionMismatch::TheirsOlder(that.max))
+        } else {
+            Ok(self.max.min(that.max))
+        }
+    }
+
+    /// Iterates all the versions in this range.
+    #[cfg(test)]
+    pub fn iter(&self) -> impl Iterator<Item = Version> {
+        (self.min.0..=self.max.0).map(Version)
+    }
+}
+
+/// Reason why two version ranges are incompatible. Contains the max version of the other peer.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub(super) enum VersionMismatch {
+    /// The other peer supports only versions newer than ours.
+    TheirsNewer(Version),
+    /// The other peer supports only versions older than ours.
+    TheirsOlder(Version),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(min: u64, max: u64) -> VersionRange {
+        VersionRange {
+            min: Version(min),
+            max: Version(max),
+        }
+    }
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(range(15, 17).negotiate(&range(15, 17)), Ok(Version(17)));
+        assert_eq!(range(15, 17).negotiate(&range(16, 20)), Ok(Version(17)));
+        assert_eq!(range(16, 20).negotiate(&range(15, 17)), Ok(Version(17)));
+        assert_eq!(range(15, 17).negotiate(&range(17, 17)), Ok(Version(17)));
+        assert_eq!(
+            range(15, 17).negotiate(&range(18, 19)),
+            Err(VersionMismatch::TheirsNewer(Version(19)))
+        );
+        assert_eq!(
+            range(15, 17).negotiate(&range(13, 14)),
+            Err(VersionMismatch::TheirsOlder(Version(14)))
+        );
+    }
+
+    #[tokio::test]
+    async fn range_roundtrip() {
+        let mut buffer = Vec::new();
+        range(15, 17).write_into(&mut buffer).await.unwrap();
+
+        assert_eq!(
+            VersionRange::read_from(&mut &buffer[..]).await.unwrap(),
+            range(15, 17)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_legacy_version() {
+        let mut buffer = Vec::new();
+        Version(14).write_into(&mut buffer).await.unwrap();
+
+        assert_eq!(
+            VersionRange::read_from(&mut &buffer[..]).await.unwrap(),
+            range(14, 14)
+        );
+    }
+}
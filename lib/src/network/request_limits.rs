@@ -0,0 +1,44 @@
+use super::constants::{MAX_IN_FLIGHT_REQUESTS_PER_PEER, MAX_PENDING_REQUESTS_PER_CLIENT};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Configurable limits on the number of concurrent requests (the request window). Shared by all
+/// peers. Changes don't affect the already existing peers and links, only the ones created
+/// afterwards.
+#[derive(Clone)]
+pub(super) struct RequestLimits(Arc<Inner>);
+
+struct Inner {
+    per_peer: AtomicUsize,
+    per_client: AtomicUsize,
+}
+
+impl RequestLimits {
+    pub fn new() -> Self {
+        Self(Arc::new(Inner {
+            per_peer: AtomicUsize::new(MAX_IN_FLIGHT_REQUESTS_PER_PEER),
+            per_client: AtomicUsize::new(MAX_PENDING_REQUESTS_PER_CLIENT),
+        }))
+    }
+
+    /// See `MAX_IN_FLIGHT_REQUESTS_PER_PEER`.
+    pub fn per_peer(&self) -> usize {
+        self.0.per_peer.load(Ordering::Relaxed)
+    }
+
+    pub fn set_per_peer(&self, value: usize) {
+        // Zero would block all requests.
+        self.0.per_peer.store(value.max(1), Ordering::Relaxed);
+    }
+
+    /// See `MAX_PENDING_REQUESTS_PER_CLIENT`.
+    pub fn per_client(&self) -> usize {
+        self.0.per_client.load(Ordering::Relaxed)
+    }
+
+    pub fn set_per_client(&self, value: usize) {
+        self.0.per_client.store(value.max(1), Ordering::Relaxed);
+    }
+}
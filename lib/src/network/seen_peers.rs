@@ -40,6 +40,13 @@ impl SeenPeers {
         self.inner.write().unwrap().remove(peer)
     }
 
+    /// Removes all the peers, making all the existing `SeenPeer`s unseen.
+    pub(crate) fn clear(&self) {
+        let mut inner = self.inner.write().unwrap();
+        inner.rounds.clear();
+        inner.peers.clear();
+    }
+
     pub(crate) fn collect(&self) -> Vec<SeenPeer> {
         self.inner.write().unwrap().collect(&self.inner)
     }
@@ -1,4 +1,5 @@
 use super::{
+    block_chunks::BlockChunk,
     constants::{INTEREST_TIMEOUT, MAX_UNCHOKED_DURATION},
     debug_payload::{DebugRequest, DebugResponse},
     message::{Content, Request, Response, ResponseDisambiguator},
@@ -99,7 +100,10 @@ impl Inner {
             Request::ChildNodes(hash, disambiguator, debug) => {
                 self.handle_child_nodes(hash, disambiguator, debug).await
             }
-            Request::Block(block_id, debug) => self.handle_block(block_id, debug).await,
+            Request::Block(block_id, debug) => self.handle_block(block_id, None, debug).await,
+            Request::BlockChunks(block_id, offset, debug) => {
+                self.handle_block(block_id, Some(offset), debug).await
+            }
         }
     }
 
@@ -188,8 +192,15 @@ impl Inner {
         Ok(())
     }
 
+    // Sends the block either whole (if `chunks_offset` is `None`) or in chunks starting at the
+    // given offset.
     #[instrument(skip(self, debug), err(Debug))]
-    async fn handle_block(&self, block_id: BlockId, debug: DebugRequest) -> Result<()> {
+    async fn handle_block(
+        &self,
+        block_id: BlockId,
+        chunks_offset: Option<u32>,
+        debug: DebugRequest,
+    ) -> Result<()> {
         let debug = debug.begin_reply();
         let mut content = BlockContent::new();
         let result = self
@@ -203,8 +214,19 @@ impl Inner {
         match result {
             Ok(nonce) => {
                 tracing::trace!("block found");
-                self.enqueue_response(Response::Block(content, nonce, debug.send()))
-                    .await;
+
+                if let Some(offset) = chunks_offset {
+                    let debug = debug.send();
+
+                    for chunk in BlockChunk::split(block_id, &content, nonce, offset) {
+                        self.enqueue_response(Response::BlockChunk(chunk, debug.clone()))
+                            .await;
+                    }
+                } else {
+                    self.enqueue_response(Response::Block(content, nonce, debug.send()))
+                        .await;
+                }
+
                 Ok(())
             }
             Err(store::Error::BlockNotFound) => {
@@ -235,7 +257,9 @@ impl Inner {
                     Payload::BlockReceived(block_id) => {
                         self.handle_block_received_event(block_id).await?;
                     }
-                    Payload::MaintenanceCompleted => continue,
+                    Payload::MaintenanceCompleted
+                    | Payload::QuotaExceeded { .. }
+                    | Payload::MergeStalled { .. } => continue,
                 },
                 Err(RecvError::Lagged(_)) => self.handle_unknown_event().await?,
                 Err(RecvError::Closed) => return Ok(()),
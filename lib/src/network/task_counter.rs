@@ -0,0 +1,115 @@
+use state_monitor::{MonitoredValue, StateMonitor};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+const UNLIMITED: usize = 0;
+
+/// Counts the background tasks spawned by the network that are still alive and optionally limits
+/// the number of the non-essential ones (those whose number grows with the number of peers). The
+/// current count and the limit are also reported to the state monitor.
+#[derive(Clone)]
+pub(super) struct TaskCounter(Arc<Shared>);
+
+struct Shared {
+    count: AtomicUsize,
+    max: AtomicUsize,
+    count_monitor: MonitoredValue<usize>,
+    max_monitor: MonitoredValue<Option<usize>>,
+}
+
+impl TaskCounter {
+    pub fn new(monitor: &StateMonitor) -> Self {
+        Self(Arc::new(Shared {
+            count: AtomicUsize::new(0),
+            max: AtomicUsize::new(UNLIMITED),
+            count_monitor: monitor.make_value("tasks", 0),
+            max_monitor: monitor.make_value("max_tasks", None),
+        }))
+    }
+
+    /// Registers a new task regardless of the limit. The task is considered alive until the
+    /// returned guard is dropped.
+    pub fn acquire(&self) -> TaskGuard {
+        let count = self.0.count.fetch_add(1, Ordering::Relaxed) + 1;
+        *self.0.count_monitor.get() = count;
+
+        TaskGuard(self.0.clone())
+    }
+
+    /// Registers a new task unless the limit has been reached.
+    pub fn try_acquire(&self) -> Option<TaskGuard> {
+        let max = self.0.max.load(Ordering::Relaxed);
+
+        self.0
+            .count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (max == UNLIMITED || count < max).then_some(count + 1)
+            })
+            .ok()
+            .map(|count| {
+                *self.0.count_monitor.get() = count + 1;
+                TaskGuard(self.0.clone())
+            })
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.count.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max(&self, max: Option<usize>) {
+        // Zero is reserved for "unlimited" and a zero limit wouldn't allow any connection anyway.
+        self.0.max.store(
+            max.map(|max| max.max(1)).unwrap_or(UNLIMITED),
+            Ordering::Relaxed,
+        );
+        *self.0.max_monitor.get() = self.max();
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        match self.0.max.load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            max => Some(max),
+        }
+    }
+}
+
+/// Keeps the task counted while alive.
+pub(super) struct TaskGuard(Arc<Shared>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        let count = self.0.count.fetch_sub(1, Ordering::Relaxed) - 1;
+        *self.0.count_monitor.get() = count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let counter = TaskCounter::new(&StateMonitor::make_root());
+        counter.set_max(Some(2));
+
+        let a = counter.try_acquire().unwrap();
+        let _b = counter.try_acquire().unwrap();
+        assert!(counter.try_acquire().is_none());
+        assert_eq!(counter.count(), 2);
+
+        // Essential tasks ignore the limit.
+        let c = counter.acquire();
+        assert_eq!(counter.count(), 3);
+
+        drop(a);
+        drop(c);
+        assert_eq!(counter.count(), 1);
+        assert!(counter.try_acquire().is_some());
+
+        counter.set_max(None);
+        let _guards: Vec<_> = (0..10).map(|_| counter.try_acquire().unwrap()).collect();
+        assert_eq!(counter.count(), 11);
+    }
+}
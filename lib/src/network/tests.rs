@@ -1,7 +1,13 @@
 use super::{
+    bad_blocks::{BadBlockCounter, BadBlockLimit},
+    block_chunks::BLOCK_CHUNK_SIZE,
     client::Client,
-    constants::{MAX_IN_FLIGHT_REQUESTS_PER_PEER, MAX_UNCHOKED_COUNT},
+    constants::{
+        MAX_IN_FLIGHT_REQUESTS_PER_PEER, MAX_PENDING_REQUESTS_PER_CLIENT, MAX_UNCHOKED_COUNT,
+    },
+    debug_payload::DebugResponse,
     message::{Content, Request, Response},
+    request_limits::RequestLimits,
     server::Server,
 };
 use crate::{
@@ -18,11 +24,19 @@ use crate::{
     test_utils,
     version_vector::VersionVector,
 };
+use assert_matches::assert_matches;
 use futures_util::{future, TryStreamExt};
 use metrics::NoopRecorder;
 use rand::prelude::*;
 use state_monitor::StateMonitor;
-use std::{fmt, future::Future, sync::Arc};
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 use tempfile::TempDir;
 use test_strategy::proptest;
 use tokio::{
@@ -125,6 +139,41 @@ fn transfer_blocks_between_two_replicas(
 // }
 
 async fn transfer_blocks_between_two_replicas_case(block_count: usize, rng_seed: u64) {
+    transfer_blocks_between_two_replicas_with(block_count, rng_seed, create_client).await
+}
+
+// The transfer must not stall even if every request has to wait for the previous one to be
+// responded to.
+#[tokio::test]
+async fn transfer_blocks_with_narrow_request_window() {
+    transfer_blocks_between_two_replicas_with(8, 0, create_narrow_window_client).await
+}
+
+#[test]
+fn request_limits() {
+    let limits = RequestLimits::new();
+    assert_eq!(limits.per_peer(), MAX_IN_FLIGHT_REQUESTS_PER_PEER);
+    assert_eq!(limits.per_client(), MAX_PENDING_REQUESTS_PER_CLIENT);
+
+    // The limits are shared among the clones.
+    let other = limits.clone();
+    other.set_per_peer(128);
+    other.set_per_client(256);
+    assert_eq!(limits.per_peer(), 128);
+    assert_eq!(limits.per_client(), 256);
+
+    // Zero would block all requests so it's clamped to one.
+    limits.set_per_peer(0);
+    limits.set_per_client(0);
+    assert_eq!(limits.per_peer(), 1);
+    assert_eq!(limits.per_client(), 1);
+}
+
+async fn transfer_blocks_between_two_replicas_with(
+    block_count: usize,
+    rng_seed: u64,
+    create_client: fn(Vault) -> ClientData,
+) {
     let mut rng = StdRng::seed_from_u64(rng_seed);
 
     let write_keys = Keypair::generate(&mut rng);
@@ -391,6 +440,146 @@ async fn failed_block_other_peer() {
     }
 }
 
+// Two peers sharing an empty repository don't exchange any messages until one of them makes a
+// change.
+#[tokio::test]
+async fn empty_repositories_are_quiescent() {
+    test_utils::init_log();
+
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let write_keys = Keypair::generate(&mut rng);
+    let (_a_base_dir, a_vault, a_choker, a_id) = create_repository(&mut rng, &write_keys).await;
+    let (_b_base_dir, b_vault, _, _) = create_repository(&mut rng, &write_keys).await;
+
+    let mut server = create_server(a_vault.clone(), a_choker);
+    let mut client = create_client(b_vault.clone());
+
+    // Counts the messages sent from the server to the client. The client only ever sends requests
+    // in reaction to those so no messages from the server means no messages at all.
+    static SENT: AtomicUsize = AtomicUsize::new(0);
+
+    fn count(_: &Content) -> bool {
+        SENT.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    run_until(
+        simulate_connection_with_filter(&mut server, &mut client, count),
+        time::sleep(Duration::from_millis(500)),
+    )
+    .await;
+
+    assert_eq!(SENT.load(Ordering::Relaxed), 0);
+    assert_matches!(client.1.try_recv(), Err(_));
+
+    // Making a change wakes the peers up.
+    let snapshot = Snapshot::generate(&mut rng, 1);
+    save_snapshot(&a_vault, a_id, &write_keys, &snapshot).await;
+
+    run_until(
+        simulate_connection_with_filter(&mut server, &mut client, count),
+        wait_until_snapshots_in_sync(&a_vault, a_id, &b_vault),
+    )
+    .await;
+
+    assert!(SENT.load(Ordering::Relaxed) > 0);
+}
+
+// Receive blocks that were not requested and are not referenced by the index and check they are
+// counted as bad and trip the limit.
+#[tokio::test]
+async fn receive_invalid_blocks() {
+    test_utils::init_log();
+
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let write_keys = Keypair::generate(&mut rng);
+    let (_base_dir, vault, _, _) = create_repository(&mut rng, &write_keys).await;
+
+    let limit = BadBlockLimit::default();
+    limit.set(Some(1));
+    let bad_blocks = BadBlockCounter::new(limit);
+
+    let (mut client, _send_rx, recv_tx) = create_client_with_bad_blocks(vault, bad_blocks.clone());
+
+    for _ in 0..2 {
+        let block: Block = rng.gen();
+        recv_tx
+            .send(Response::Block(
+                block.content,
+                block.nonce,
+                DebugResponse::unsolicited(),
+            ))
+            .await
+            .unwrap();
+    }
+
+    run_until(client.run(), bad_blocks.limit_exceeded()).await;
+
+    assert_eq!(bad_blocks.get(), 2);
+}
+
+// A chunked block transfer interrupted by a disconnect is resumed from where it stopped after
+// reconnecting.
+#[tokio::test]
+async fn resume_interrupted_block_transfer() {
+    test_utils::init_log();
+
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let write_keys = Keypair::generate(&mut rng);
+    let (_a_base_dir, a_vault, a_choker, a_id) = create_repository(&mut rng, &write_keys).await;
+    let (_b_base_dir, b_vault, _, _) = create_repository(&mut rng, &write_keys).await;
+
+    let snapshot = Snapshot::generate(&mut rng, 1);
+    save_snapshot(&a_vault, a_id, &write_keys, &snapshot).await;
+    receive_blocks(&a_vault, &snapshot).await;
+
+    let block_id = *snapshot.blocks().keys().next().unwrap();
+
+    // Lose all but the first chunk of the block, then disconnect.
+    let mut server = create_server(a_vault.clone(), a_choker.clone());
+    let mut client = create_chunked_client(b_vault.clone());
+
+    run_until(
+        simulate_connection_with_filter(&mut server, &mut client, |content| {
+            !matches!(
+                content,
+                Content::Response(Response::BlockChunk(chunk, _)) if chunk.offset > 0
+            )
+        }),
+        async {
+            while b_vault.partial_blocks.offset(&block_id) as usize != BLOCK_CHUNK_SIZE {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        },
+    )
+    .await;
+
+    drop(server);
+    drop(client);
+
+    // Reconnect. The transfer must continue from the second chunk.
+    let mut server = create_server(a_vault.clone(), a_choker);
+    let mut client = create_chunked_client(b_vault.clone());
+
+    run_until(
+        simulate_connection_with_filter(&mut server, &mut client, |content| {
+            if let Content::Response(Response::BlockChunk(chunk, _)) = content {
+                assert_ne!(
+                    chunk.offset, 0,
+                    "block transfer restarted from the beginning"
+                );
+            }
+
+            true
+        }),
+        wait_until_block_exists(&b_vault, &block_id),
+    )
+    .await;
+}
+
 async fn create_repository<R: Rng + CryptoRng>(
     rng: &mut R,
     write_keys: &Keypair,
@@ -548,17 +737,29 @@ where
 
 // Simulate connection forever.
 async fn simulate_connection(server: &mut ServerData, client: &mut ClientData) {
+    simulate_connection_with_filter(server, client, |_| true).await
+}
+
+// Simulate connection forever, passing from the server to the client only the messages for which
+// `filter` returns true.
+async fn simulate_connection_with_filter(
+    server: &mut ServerData,
+    client: &mut ClientData,
+    filter: fn(&Content) -> bool,
+) {
     let (server, server_send_rx, server_recv_tx) = server;
     let (client, client_send_rx, client_recv_tx) = client;
 
     let mut server_conn = Connection {
         send_rx: server_send_rx,
         recv_tx: client_recv_tx,
+        filter,
     };
 
     let mut client_conn = Connection {
         send_rx: client_send_rx,
         recv_tx: server_recv_tx,
+        filter: |_| true,
     };
 
     let server_run = server.run().instrument(tracing::info_span!("server"));
@@ -601,13 +802,52 @@ fn create_server(repo: Vault, response_limiter: Arc<Semaphore>) -> ServerData {
 }
 
 fn create_client(repo: Vault) -> ClientData {
+    create_client_with_bad_blocks(repo, BadBlockCounter::new(BadBlockLimit::default()))
+}
+
+fn create_client_with_bad_blocks(repo: Vault, bad_blocks: BadBlockCounter) -> ClientData {
+    create_client_with(repo, bad_blocks, false, RequestLimits::new())
+}
+
+fn create_chunked_client(repo: Vault) -> ClientData {
+    create_client_with(
+        repo,
+        BadBlockCounter::new(BadBlockLimit::default()),
+        true,
+        RequestLimits::new(),
+    )
+}
+
+// Client with the smallest possible request window.
+fn create_narrow_window_client(repo: Vault) -> ClientData {
+    let request_limits = RequestLimits::new();
+    request_limits.set_per_peer(1);
+    request_limits.set_per_client(1);
+
+    create_client_with(
+        repo,
+        BadBlockCounter::new(BadBlockLimit::default()),
+        false,
+        request_limits,
+    )
+}
+
+fn create_client_with(
+    repo: Vault,
+    bad_blocks: BadBlockCounter,
+    chunked_blocks: bool,
+    request_limits: RequestLimits,
+) -> ClientData {
     let (send_tx, send_rx) = mpsc::channel(1);
     let (recv_tx, recv_rx) = mpsc::channel(CAPACITY);
     let client = Client::new(
         repo,
         send_tx,
         recv_rx,
-        Arc::new(Semaphore::new(MAX_IN_FLIGHT_REQUESTS_PER_PEER)),
+        Arc::new(Semaphore::new(request_limits.per_peer())),
+        request_limits.per_client(),
+        bad_blocks,
+        chunked_blocks,
     );
 
     (client, send_rx, recv_tx)
@@ -617,6 +857,7 @@ fn create_client(repo: Vault) -> ClientData {
 struct Connection<'a, T> {
     send_rx: &'a mut mpsc::Receiver<Content>,
     recv_tx: &'a mut mpsc::Sender<T>,
+    filter: fn(&Content) -> bool,
 }
 
 impl<T> Connection<'_, T>
@@ -625,6 +866,10 @@ where
 {
     async fn run(&mut self) {
         while let Some(content) = self.send_rx.recv().await {
+            if !(self.filter)(&content) {
+                continue;
+            }
+
             self.recv_tx.send(content.into()).await.unwrap();
         }
     }
@@ -35,6 +35,7 @@ struct TrackedDevice {
 
 pub(crate) struct PortForwarder {
     mappings: Arc<BlockingMutex<Mappings>>,
+    status: StatusTracker,
     on_change_tx: watch::Sender<()>,
     task: BlockingMutex<Weak<ScopedJoinHandle<()>>>,
     monitor: StateMonitor,
@@ -47,6 +48,7 @@ impl PortForwarder {
 
         Self {
             mappings,
+            status: StatusTracker::default(),
             on_change_tx: watch::Sender::new(()),
             task: BlockingMutex::new(Weak::new()),
             monitor,
@@ -54,6 +56,11 @@ impl PortForwarder {
         }
     }
 
+    /// Current status of the port forwarding on all the gateway devices found so far.
+    pub fn status(&self) -> UpnpStatus {
+        self.status.get()
+    }
+
     pub fn add_mapping(&self, internal: u16, external: u16, protocol: ip::Protocol) -> Mapping {
         let data = MappingData {
             internal,
@@ -87,11 +94,12 @@ impl PortForwarder {
             task
         } else {
             let mappings = self.mappings.clone();
+            let status = self.status.clone();
             let on_change_rx = self.on_change_tx.subscribe();
             let monitor = self.monitor.clone();
 
             let task = async move {
-                let result = Self::run(mappings, on_change_rx, monitor).await;
+                let result = Self::run(mappings, status, on_change_rx, monitor).await;
                 // Warning, because we don't actually expect this to happen.
                 tracing::warn!("UPnP port forwarding ended ({:?})", result)
             };
@@ -120,6 +128,7 @@ impl PortForwarder {
 
     async fn run(
         mappings: Arc<BlockingMutex<Mappings>>,
+        status: StatusTracker,
         on_change_rx: watch::Receiver<()>,
         monitor: StateMonitor,
     ) -> Result<(), rupnp::Error> {
@@ -186,6 +195,7 @@ impl PortForwarder {
 
                 let on_change_rx = on_change_rx.clone();
                 let mappings = mappings.clone();
+                let status = status.clone();
                 let devices_monitor = devices_monitor.clone();
 
                 Self::spawn_if_not_running(device_url.clone(), &job_handles, move || {
@@ -204,6 +214,7 @@ impl PortForwarder {
                                 on_change_rx,
                                 mappings,
                                 active_mappings: Default::default(),
+                                status,
                                 monitor: devices_monitor.make_child(device.friendly_name()),
                             };
 
@@ -336,11 +347,13 @@ struct PerIGDPortForwarder {
     on_change_rx: watch::Receiver<()>,
     mappings: Arc<BlockingMutex<Mappings>>,
     active_mappings: BlockingMutex<HashMap<MappingData, ScopedJoinHandle<()>>>,
+    status: StatusTracker,
     monitor: StateMonitor,
 }
 
 impl PerIGDPortForwarder {
     async fn run(mut self) {
+        let _status_guard = self.status.track_device(self.device_url.clone());
         let _ext_ip_task = self.start_ext_ip_discovery();
 
         let _url_monitor = self.monitor.make_value("url", self.device_url.clone());
@@ -385,13 +398,14 @@ impl PerIGDPortForwarder {
     ) -> ScopedJoinHandle<()> {
         let service = self.service.clone();
         let device_uri = self.device_url.clone();
+        let status = self.status.clone();
         let mapping_monitor = mappings_monitor.make_child(format!(
             "{} EXT:{} -> INT:{}",
             data.protocol, data.external, data.internal,
         ));
 
         scoped_task::spawn(async move {
-            Self::run_mapping(data, local_ip, service, device_uri, mapping_monitor)
+            Self::run_mapping(data, local_ip, service, device_uri, status, mapping_monitor)
                 .instrument(Span::current())
                 .await;
             unreachable!();
@@ -401,11 +415,15 @@ impl PerIGDPortForwarder {
     fn start_ext_ip_discovery(&self) -> ScopedJoinHandle<()> {
         let service = self.service.clone();
         let device_url = self.device_url.clone();
+        let status = self.status.clone();
         let external_ip = self.monitor.make_value("external ip", None);
 
         scoped_task::spawn(async move {
             loop {
-                *external_ip.get() = get_external_ip_address(&service, &device_url).await.ok();
+                let ip = get_external_ip_address(&service, &device_url).await.ok();
+                *external_ip.get() = ip;
+                status.set_external_ip(&device_url, ip);
+
                 sleep(Duration::from_secs(4 * 60)).await;
             }
         })
@@ -416,8 +434,11 @@ impl PerIGDPortForwarder {
         local_ip: net::IpAddr,
         service: Service,
         device_url: Uri,
+        status: StatusTracker,
         monitor: StateMonitor,
     ) {
+        let _status_guard = status.track_mapping(device_url.clone(), mapping);
+
         let lease_duration = Duration::from_secs(5 * 60);
         let sleep_delta = Duration::from_secs(5);
         let sleep_duration = lease_duration.saturating_sub(sleep_delta);
@@ -437,10 +458,13 @@ impl PerIGDPortForwarder {
                 add_port_mappings(&service, &device_url, &local_ip, lease_duration, &mapping).await
             {
                 *state.get() = State::StageOneFailure(err);
+                status.set_mapping_active(&device_url, mapping, false);
                 sleep(error_sleep_duration).await;
                 continue;
             }
 
+            status.set_mapping_active(&device_url, mapping, true);
+
             if !ext_port_reported {
                 ext_port_reported = true;
 
@@ -477,6 +501,7 @@ impl PerIGDPortForwarder {
                 add_port_mappings(&service, &device_url, &local_ip, lease_duration, &mapping).await
             {
                 *state.get() = State::StageTwoFailure(err);
+                status.set_mapping_active(&device_url, mapping, false);
                 sleep(error_sleep_duration).await;
                 continue;
             }
@@ -487,6 +512,136 @@ impl PerIGDPortForwarder {
     }
 }
 
+/// Status of the UPnP port forwarding. Obtained with `Network::upnp_status`.
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct UpnpStatus {
+    /// Internet gateway devices supporting port forwarding found in the local network.
+    pub devices: Vec<UpnpDeviceStatus>,
+}
+
+impl UpnpStatus {
+    /// Is there at least one device on which all the mappings are currently active?
+    pub fn is_active(&self) -> bool {
+        self.devices.iter().any(|device| {
+            !device.mappings.is_empty() && device.mappings.iter().all(|mapping| mapping.active)
+        })
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UpnpDeviceStatus {
+    pub url: String,
+    /// External IP address as reported by the device, if known.
+    pub external_ip: Option<net::IpAddr>,
+    pub mappings: Vec<UpnpMappingStatus>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UpnpMappingStatus {
+    pub protocol: ip::Protocol,
+    pub internal_port: u16,
+    pub external_port: u16,
+    /// Did the last attempt to create or renew the mapping succeed?
+    pub active: bool,
+}
+
+// Collects the state of the port forwarding for `UpnpStatus`.
+#[derive(Clone, Default)]
+struct StatusTracker(Arc<BlockingMutex<HashMap<Uri, DeviceState>>>);
+
+#[derive(Default)]
+struct DeviceState {
+    external_ip: Option<net::IpAddr>,
+    mappings: HashMap<MappingData, bool>,
+}
+
+impl StatusTracker {
+    fn get(&self) -> UpnpStatus {
+        let devices = self.0.lock().unwrap();
+
+        let mut devices: Vec<_> = devices
+            .iter()
+            .map(|(url, state)| {
+                let mut mappings: Vec<_> = state
+                    .mappings
+                    .iter()
+                    .map(|(data, active)| UpnpMappingStatus {
+                        protocol: data.protocol,
+                        internal_port: data.internal,
+                        external_port: data.external,
+                        active: *active,
+                    })
+                    .collect();
+                mappings.sort_by_key(|mapping| (mapping.external_port, mapping.protocol));
+
+                UpnpDeviceStatus {
+                    url: url.to_string(),
+                    external_ip: state.external_ip,
+                    mappings,
+                }
+            })
+            .collect();
+        devices.sort_by(|a, b| a.url.cmp(&b.url));
+
+        UpnpStatus { devices }
+    }
+
+    // Tracks the device until the returned guard is dropped.
+    fn track_device(&self, url: Uri) -> StatusGuard {
+        self.0.lock().unwrap().entry(url.clone()).or_default();
+
+        StatusGuard {
+            tracker: self.clone(),
+            url,
+            mapping: None,
+        }
+    }
+
+    // Tracks the mapping on the device until the returned guard is dropped. The mapping is
+    // initially inactive.
+    fn track_mapping(&self, url: Uri, mapping: MappingData) -> StatusGuard {
+        self.set_mapping_active(&url, mapping, false);
+
+        StatusGuard {
+            tracker: self.clone(),
+            url,
+            mapping: Some(mapping),
+        }
+    }
+
+    fn set_external_ip(&self, url: &Uri, ip: Option<net::IpAddr>) {
+        if let Some(device) = self.0.lock().unwrap().get_mut(url) {
+            device.external_ip = ip;
+        }
+    }
+
+    fn set_mapping_active(&self, url: &Uri, mapping: MappingData, active: bool) {
+        if let Some(device) = self.0.lock().unwrap().get_mut(url) {
+            device.mappings.insert(mapping, active);
+        }
+    }
+}
+
+struct StatusGuard {
+    tracker: StatusTracker,
+    url: Uri,
+    mapping: Option<MappingData>,
+}
+
+impl Drop for StatusGuard {
+    fn drop(&mut self) {
+        let mut devices = self.tracker.0.lock().unwrap();
+
+        if let Some(mapping) = &self.mapping {
+            if let Some(device) = devices.get_mut(&self.url) {
+                device.mappings.remove(mapping);
+            }
+        } else {
+            devices.remove(&self.url);
+        }
+    }
+}
+
 // For IGDv1 see Section 2.4.16 in
 // https://openconnectivity.org/wp-content/uploads/2015/11/UPnP_IGD_WANIPConnection-1.0.pdf
 //
@@ -702,3 +857,56 @@ pub async fn discover_device_urls(
         .map_err(rupnp::Error::SSDPError)
         .map(|res| Ok(res?.location().parse()?)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_tracking() {
+        let tracker = StatusTracker::default();
+        let url: Uri = "http://192.168.1.1:5000/rootDesc.xml".parse().unwrap();
+        let mapping = MappingData {
+            internal: 20209,
+            external: 20209,
+            protocol: ip::Protocol::Udp,
+        };
+
+        assert_eq!(tracker.get(), UpnpStatus::default());
+        assert!(!tracker.get().is_active());
+
+        let device_guard = tracker.track_device(url.clone());
+        tracker.set_external_ip(&url, Some(net::Ipv4Addr::new(1, 2, 3, 4).into()));
+
+        let mapping_guard = tracker.track_mapping(url.clone(), mapping);
+        assert!(!tracker.get().is_active());
+
+        tracker.set_mapping_active(&url, mapping, true);
+
+        let status = tracker.get();
+        assert!(status.is_active());
+        assert_eq!(
+            status.devices,
+            [UpnpDeviceStatus {
+                url: url.to_string(),
+                external_ip: Some(net::Ipv4Addr::new(1, 2, 3, 4).into()),
+                mappings: vec![UpnpMappingStatus {
+                    protocol: ip::Protocol::Udp,
+                    internal_port: 20209,
+                    external_port: 20209,
+                    active: true,
+                }],
+            }]
+        );
+
+        // Lease renewal failed
+        tracker.set_mapping_active(&url, mapping, false);
+        assert!(!tracker.get().is_active());
+
+        drop(mapping_guard);
+        assert!(tracker.get().devices[0].mappings.is_empty());
+
+        drop(device_guard);
+        assert_eq!(tracker.get(), UpnpStatus::default());
+    }
+}
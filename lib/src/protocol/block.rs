@@ -19,7 +19,8 @@ pub(crate) const BLOCK_RECORD_SIZE: u64 =
     BLOCK_SIZE as u64 + BlockId::SIZE as u64 + BLOCK_NONCE_SIZE as u64;
 
 pub(crate) const BLOCK_NONCE_SIZE: usize = 32;
-pub(crate) type BlockNonce = [u8; BLOCK_NONCE_SIZE];
+/// Nonce used to encrypt a block.
+pub type BlockNonce = [u8; BLOCK_NONCE_SIZE];
 
 /// Unique id of a block.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
@@ -92,14 +93,26 @@ impl Distribution<Block> for Standard {
     }
 }
 
+/// Content of a block. `BLOCK_SIZE` bytes long unless stored compressed.
 #[derive(Clone, Serialize, Deserialize)]
-pub(crate) struct BlockContent(Box<[u8]>);
+pub struct BlockContent(Box<[u8]>);
 
 impl BlockContent {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Creates content with a copy of the given bytes. Unlike `new`, the length of the content is
+    /// the length of `src`, which is useful for blocks stored compressed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `src` is longer than `BLOCK_SIZE`.
+    pub fn from_slice(src: &[u8]) -> Self {
+        assert!(src.len() <= BLOCK_SIZE, "block content too long");
+        Self(src.into())
+    }
+
     // Read data from `offset` of the buffer into a fixed-length array.
     //
     // # Panics
@@ -13,17 +13,20 @@ mod summary;
 #[cfg(test)]
 pub(crate) mod test_utils;
 
-pub use self::block::BLOCK_SIZE;
+pub use self::{
+    block::{BlockContent, BlockId, BlockNonce, BLOCK_SIZE},
+    summary::SingleBlockPresence,
+};
 
 pub(crate) use self::{
-    block::{Block, BlockContent, BlockId, BlockNonce, BLOCK_RECORD_SIZE},
+    block::{Block, BLOCK_RECORD_SIZE},
     bump::Bump,
     inner_node::{get_bucket, InnerNode, InnerNodes, EMPTY_INNER_HASH, INNER_LAYER_COUNT},
     leaf_node::{LeafNode, LeafNodes, EMPTY_LEAF_HASH},
     locator::Locator,
     proof::{Proof, ProofError, UntrustedProof},
     root_node::{RootNode, RootNodeFilter, RootNodeKind},
-    summary::{MultiBlockPresence, NodeState, SingleBlockPresence, Summary},
+    summary::{MultiBlockPresence, NodeState, Summary},
 };
 
 #[cfg(test)]
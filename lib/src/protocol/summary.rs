@@ -150,9 +150,12 @@ pub(crate) struct InvalidValue(u8);
 
 /// Information about the presence of a single block.
 #[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub(crate) enum SingleBlockPresence {
+pub enum SingleBlockPresence {
+    /// The block is referenced by the index but hasn't been downloaded yet.
     Missing,
+    /// The block is stored locally.
     Present,
+    /// The block was stored locally but has been removed due to expiration.
     Expired,
 }
 
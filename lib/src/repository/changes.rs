@@ -0,0 +1,95 @@
+use crate::{
+    branch::Branch,
+    directory::{Directory, DirectoryFallback, DirectoryLocking, EntryRef, EntryType},
+    error::{Error, Result},
+    store,
+    version_vector::VersionVector,
+};
+use camino::Utf8PathBuf;
+use futures_util::{stream, Stream};
+use std::collections::VecDeque;
+
+/// Kind of change yielded by `Repository::changed_since`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ChangeKind {
+    /// The entry was created or modified.
+    Modified(EntryType),
+    /// The entry was removed.
+    Removed,
+}
+
+pub(super) fn changed_since(
+    branch: Branch,
+    vv: VersionVector,
+) -> impl Stream<Item = Result<(Utf8PathBuf, ChangeKind)>> {
+    stream::try_unfold(
+        State {
+            branch: Some(branch),
+            vv,
+            dirs: VecDeque::new(),
+            changes: VecDeque::new(),
+        },
+        |mut state| async move {
+            let change = state.next().await?;
+            Ok(change.map(|change| (change, state)))
+        },
+    )
+}
+
+struct State {
+    // `Some` until the root directory is opened.
+    branch: Option<Branch>,
+    vv: VersionVector,
+    // Changed directories not yet visited.
+    dirs: VecDeque<(Utf8PathBuf, Directory)>,
+    // Changes found but not yet yielded.
+    changes: VecDeque<(Utf8PathBuf, ChangeKind)>,
+}
+
+impl State {
+    async fn next(&mut self) -> Result<Option<(Utf8PathBuf, ChangeKind)>> {
+        if let Some(branch) = self.branch.take() {
+            match branch
+                .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+                .await
+            {
+                Ok(root) => self.dirs.push_back((Utf8PathBuf::from("/"), root)),
+                Err(Error::Store(store::Error::BranchNotFound)) => return Ok(None),
+                Err(error) => return Err(error),
+            }
+        }
+
+        loop {
+            if let Some(change) = self.changes.pop_front() {
+                return Ok(Some(change));
+            }
+
+            let Some((path, dir)) = self.dirs.pop_front() else {
+                return Ok(None);
+            };
+
+            for entry in dir.entries() {
+                // The version vector of a directory is the merge of the version vectors of all its
+                // entries, so unchanged directories can be skipped without descending into them.
+                if entry.version_vector() <= &self.vv {
+                    continue;
+                }
+
+                let entry_path = path.join(entry.name());
+
+                let kind = match entry {
+                    EntryRef::File(_) => ChangeKind::Modified(EntryType::File),
+                    EntryRef::Directory(entry) => {
+                        let subdir = entry.open(DirectoryFallback::Disabled).await?;
+                        self.dirs.push_back((entry_path.clone(), subdir));
+
+                        ChangeKind::Modified(EntryType::Directory)
+                    }
+                    EntryRef::Tombstone(_) => ChangeKind::Removed,
+                };
+
+                self.changes.push_back((entry_path, kind));
+            }
+        }
+    }
+}
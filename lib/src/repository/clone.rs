@@ -0,0 +1,58 @@
+use super::Repository;
+use crate::{
+    error::Result,
+    joint_directory::{JointDirectory, JointEntryRef},
+    protocol::BLOCK_SIZE,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::VecDeque;
+
+/// Copies all the files and directories visible in `src` into `dst`, re-encrypting them in the
+/// process. Concurrent versions of a file are copied as separate files, named the same way as
+/// they are listed in the source (see `JointDirectory::entries`).
+pub(super) async fn copy_all(src: &Repository, dst: &Repository) -> Result<()> {
+    let mut dirs = VecDeque::from([(Utf8PathBuf::from("/"), src.root().await?)]);
+
+    while let Some((path, dir)) = dirs.pop_front() {
+        copy_directory(&path, dir, dst, &mut dirs).await?;
+    }
+
+    Ok(())
+}
+
+async fn copy_directory(
+    path: &Utf8Path,
+    dir: JointDirectory,
+    dst: &Repository,
+    dirs: &mut VecDeque<(Utf8PathBuf, JointDirectory)>,
+) -> Result<()> {
+    let mut buffer = vec![0; BLOCK_SIZE];
+
+    for entry in dir.entries() {
+        let entry_path = path.join(entry.unique_name().as_ref());
+
+        match entry {
+            JointEntryRef::File(entry) => {
+                let mut src_file = entry.open().await?;
+                let mut dst_file = dst.create_file(&entry_path).await?;
+
+                loop {
+                    let len = src_file.read(&mut buffer).await?;
+                    if len == 0 {
+                        break;
+                    }
+
+                    dst_file.write_all(&buffer[..len]).await?;
+                }
+
+                dst_file.flush().await?;
+            }
+            JointEntryRef::Directory(entry) => {
+                dst.create_directory(&entry_path).await?;
+                dirs.push_back((entry_path, entry.open().await?));
+            }
+        }
+    }
+
+    Ok(())
+}
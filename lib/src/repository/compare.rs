@@ -0,0 +1,136 @@
+use super::Repository;
+use crate::{
+    directory::EntryType,
+    error::Result,
+    joint_directory::{JointDirectory, JointEntryRef},
+};
+use camino::Utf8PathBuf;
+use futures_util::{stream, Stream};
+use std::collections::{BTreeMap, VecDeque};
+
+/// Difference between two repositories found by `Repository::compare_with`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Difference {
+    pub path: Utf8PathBuf,
+    pub kind: DifferenceKind,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DifferenceKind {
+    /// The entry exists only in this repository. If it's a directory, its content is not reported
+    /// separately.
+    OnlyInThis,
+    /// The entry exists only in the other repository. If it's a directory, its content is not
+    /// reported separately.
+    OnlyInOther,
+    /// The entry is a file in one repository and a directory in the other.
+    TypeMismatch,
+    /// The entry is a file in both repositories but their contents differ.
+    ContentMismatch,
+}
+
+pub(super) fn compare<'a>(
+    this: &'a Repository,
+    other: &'a Repository,
+) -> impl Stream<Item = Result<Difference>> + 'a {
+    stream::try_unfold(
+        State {
+            this,
+            other,
+            dirs: VecDeque::from([Utf8PathBuf::from("/")]),
+            found: VecDeque::new(),
+        },
+        |mut state| async move {
+            let difference = state.next().await?;
+            Ok(difference.map(|difference| (difference, state)))
+        },
+    )
+}
+
+struct State<'a> {
+    this: &'a Repository,
+    other: &'a Repository,
+    // Directories present in both repositories not yet visited.
+    dirs: VecDeque<Utf8PathBuf>,
+    // Differences found but not yet yielded.
+    found: VecDeque<Difference>,
+}
+
+impl State<'_> {
+    async fn next(&mut self) -> Result<Option<Difference>> {
+        loop {
+            if let Some(difference) = self.found.pop_front() {
+                return Ok(Some(difference));
+            }
+
+            let Some(path) = self.dirs.pop_front() else {
+                return Ok(None);
+            };
+
+            let this_dir = self.this.cd(&path).await?;
+            let other_dir = self.other.cd(&path).await?;
+
+            let mut this_entries = entry_types(&this_dir);
+            let other_entries = entry_types(&other_dir);
+
+            for (name, other_type) in other_entries {
+                let entry_path = path.join(&name);
+
+                let kind = match this_entries.remove(&name) {
+                    None => Some(DifferenceKind::OnlyInOther),
+                    Some(this_type) if this_type != other_type => {
+                        Some(DifferenceKind::TypeMismatch)
+                    }
+                    Some(EntryType::Directory) => {
+                        self.dirs.push_back(entry_path);
+                        continue;
+                    }
+                    Some(EntryType::File) => {
+                        (!self.same_content(&this_dir, &other_dir, &name).await?)
+                            .then_some(DifferenceKind::ContentMismatch)
+                    }
+                };
+
+                if let Some(kind) = kind {
+                    self.found.push_back(Difference {
+                        path: entry_path,
+                        kind,
+                    });
+                }
+            }
+
+            // The remaining ones are not in the other repository.
+            for name in this_entries.into_keys() {
+                self.found.push_back(Difference {
+                    path: path.join(name),
+                    kind: DifferenceKind::OnlyInThis,
+                });
+            }
+        }
+    }
+
+    async fn same_content(
+        &self,
+        this_dir: &JointDirectory,
+        other_dir: &JointDirectory,
+        name: &str,
+    ) -> Result<bool> {
+        let mut this_file = this_dir.lookup_unique(name)?.file()?.open().await?;
+        let mut other_file = other_dir.lookup_unique(name)?.file()?.open().await?;
+
+        // Different lengths mean different contents, no need to read them.
+        if this_file.len() != other_file.len() {
+            return Ok(false);
+        }
+
+        Ok(this_file.content_digest().await? == other_file.content_digest().await?)
+    }
+}
+
+// Types of the entries of the directory, keyed by their unique names (so that the versions of a
+// file in conflict are compared one by one).
+fn entry_types(dir: &JointDirectory) -> BTreeMap<String, EntryType> {
+    dir.entries()
+        .map(|entry: JointEntryRef| (entry.unique_name().into_owned(), entry.entry_type()))
+        .collect()
+}
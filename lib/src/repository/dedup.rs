@@ -0,0 +1,29 @@
+/// Statistics about sharing of blocks among the branches of a repository. Obtained with
+/// `Repository::dedup_stats`.
+///
+/// Only the latest snapshot of each branch is considered. The sizes count only the block content as
+/// actually stored (compressed blocks are shorter than `BLOCK_SIZE`), not the ids, nonces or the
+/// index. Blocks that are not stored locally (e.g., not yet downloaded) count as zero.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct DedupStats {
+    /// Size of the data as if every block reference was backed by its own block.
+    pub logical_bytes: u64,
+    /// Size of the distinct referenced blocks.
+    pub physical_bytes: u64,
+    /// Number of distinct blocks referenced more than once.
+    pub shared_blocks: u64,
+    /// Number of distinct blocks referenced exactly once.
+    pub unique_blocks: u64,
+}
+
+impl DedupStats {
+    /// Ratio of the logical size to the physical size (1.0 means nothing is shared). Returns
+    /// `None` if the repository is empty.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.physical_bytes > 0 {
+            Some(self.logical_bytes as f64 / self.physical_bytes as f64)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{
+    blob::{BlobId, BlockIds},
+    branch::Branch,
+    collections::HashSet,
+    directory::{DirectoryFallback, DirectoryLocking, EntryRef},
+    error::{Error, Result},
+    protocol::BlockId,
+    store,
+};
+use std::collections::VecDeque;
+
+/// Collects the ids of the blocks of all the directories (but not files) in the given branches.
+/// Directories whose blocks are not available are skipped, together with their subdirectories.
+pub(super) async fn directory_block_ids(branches: Vec<Branch>) -> Result<HashSet<BlockId>> {
+    let mut block_ids = HashSet::default();
+
+    for branch in branches {
+        let root = match branch
+            .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
+            .await
+        {
+            Ok(root) => root,
+            Err(Error::Store(store::Error::BlockNotFound)) => continue,
+            Err(error) => return Err(error),
+        };
+
+        collect_block_ids(&branch, BlobId::ROOT, &mut block_ids).await?;
+
+        let mut dirs = VecDeque::from([root]);
+
+        while let Some(dir) = dirs.pop_front() {
+            for entry in dir.entries() {
+                let EntryRef::Directory(entry) = entry else {
+                    continue;
+                };
+
+                collect_block_ids(&branch, *entry.blob_id(), &mut block_ids).await?;
+
+                match entry.open(DirectoryFallback::Disabled).await {
+                    Ok(dir) => dirs.push_back(dir),
+                    Err(Error::Store(store::Error::BlockNotFound)) => (),
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+    }
+
+    Ok(block_ids)
+}
+
+async fn collect_block_ids(
+    branch: &Branch,
+    blob_id: BlobId,
+    block_ids: &mut HashSet<BlockId>,
+) -> Result<()> {
+    let mut blob_block_ids = BlockIds::open(branch.clone(), blob_id).await?;
+
+    while let Some(block_id) = blob_block_ids.try_next().await? {
+        block_ids.insert(block_id);
+    }
+
+    Ok(())
+}
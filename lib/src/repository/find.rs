@@ -0,0 +1,128 @@
+use super::{sync_filter, Repository};
+use crate::{
+    error::Result,
+    joint_directory::{JointDirectory, JointEntryRef},
+};
+use camino::Utf8PathBuf;
+use futures_util::{stream, Stream};
+use std::collections::VecDeque;
+
+pub(super) fn find<'a>(
+    repo: &'a Repository,
+    pattern: &str,
+    root: Utf8PathBuf,
+) -> impl Stream<Item = Result<Utf8PathBuf>> + 'a {
+    stream::try_unfold(
+        State {
+            repo,
+            pattern: NamePattern::new(pattern),
+            root: Some(root),
+            dirs: VecDeque::new(),
+            found: VecDeque::new(),
+        },
+        |mut state| async move {
+            let path = state.next().await?;
+            Ok(path.map(|path| (path, state)))
+        },
+    )
+}
+
+struct State<'a> {
+    repo: &'a Repository,
+    pattern: NamePattern,
+    // `Some` until the root directory is opened.
+    root: Option<Utf8PathBuf>,
+    // Directories not yet visited.
+    dirs: VecDeque<(Utf8PathBuf, JointDirectory)>,
+    // Matches found but not yet yielded.
+    found: VecDeque<Utf8PathBuf>,
+}
+
+impl State<'_> {
+    async fn next(&mut self) -> Result<Option<Utf8PathBuf>> {
+        if let Some(root) = self.root.take() {
+            let dir = self.repo.cd(&root).await?;
+            self.dirs.push_back((root, dir));
+        }
+
+        loop {
+            if let Some(path) = self.found.pop_front() {
+                return Ok(Some(path));
+            }
+
+            let Some((path, dir)) = self.dirs.pop_front() else {
+                return Ok(None);
+            };
+
+            for entry in dir.entries() {
+                // Use the unique name in the path so the entry can be opened with it even if it's
+                // in conflict.
+                let entry_path = path.join(entry.unique_name().as_ref());
+
+                if self.pattern.matches(entry.name()) {
+                    self.found.push_back(entry_path.clone());
+                }
+
+                if let JointEntryRef::Directory(entry) = entry {
+                    self.dirs.push_back((entry_path, entry.open().await?));
+                }
+            }
+        }
+    }
+}
+
+/// Case-insensitive pattern to match entry names against. If it contains any of the wildcards `*`
+/// or `?`, it's matched as a glob (see `SyncFilter`) against the whole name, otherwise it matches
+/// any name that contains it.
+enum NamePattern {
+    Glob(Vec<char>),
+    Substring(String),
+}
+
+impl NamePattern {
+    fn new(pattern: &str) -> Self {
+        let pattern = pattern.to_lowercase();
+
+        if pattern.contains(['*', '?']) {
+            Self::Glob(pattern.chars().collect())
+        } else {
+            Self::Substring(pattern)
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+
+        match self {
+            Self::Glob(pattern) => {
+                let name: Vec<_> = name.chars().collect();
+                sync_filter::match_name(pattern, &name)
+            }
+            Self::Substring(pattern) => name.contains(pattern.as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_pattern() {
+        let pattern = NamePattern::new("foo");
+        assert!(pattern.matches("foo"));
+        assert!(pattern.matches("FOO.txt"));
+        assert!(pattern.matches("a-Foo-b"));
+        assert!(!pattern.matches("fo"));
+
+        let pattern = NamePattern::new("*.TXT");
+        assert!(pattern.matches("a.txt"));
+        assert!(pattern.matches("B.Txt"));
+        assert!(!pattern.matches("a.txt.bak"));
+
+        let pattern = NamePattern::new("a?c");
+        assert!(pattern.matches("abc"));
+        assert!(pattern.matches("AXC"));
+        assert!(!pattern.matches("abbc"));
+    }
+}
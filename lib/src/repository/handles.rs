@@ -0,0 +1,112 @@
+use crate::{
+    blob::{lock::LockKind, BlobId},
+    branch::Branch,
+    collections::{HashMap, HashSet},
+    crypto::sign::PublicKey,
+    directory::{DirectoryFallback, DirectoryLocking, EntryRef},
+};
+use camino::Utf8PathBuf;
+use std::collections::VecDeque;
+
+/// File or directory currently held open in the repository. See `Repository::open_handles`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct OpenHandleInfo {
+    /// Branch the blob belongs to.
+    pub branch_id: PublicKey,
+    /// Hex-encoded id of the blob.
+    pub blob_id: String,
+    /// Path of the blob in its branch or `None` if it's not reachable from the root directory
+    /// (e.g., it's been removed or not yet inserted into its parent or its parent couldn't be
+    /// loaded).
+    pub path: Option<Utf8PathBuf>,
+    pub mode: OpenHandleMode,
+    /// Number of handles holding the blob open.
+    pub count: usize,
+}
+
+/// How an open blob is being held.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum OpenHandleMode {
+    /// Held only for reading.
+    Read,
+    /// One of the handles is writing to it.
+    Write,
+    /// Held exclusively, e.g. while being removed.
+    Unique,
+}
+
+impl From<LockKind> for OpenHandleMode {
+    fn from(kind: LockKind) -> Self {
+        match kind {
+            LockKind::Read => Self::Read,
+            LockKind::Write => Self::Write,
+            LockKind::Unique => Self::Unique,
+        }
+    }
+}
+
+/// Lock currently held on a blob in the repository. See `Repository::locks`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LockInfo {
+    /// Branch the blob belongs to.
+    pub branch_id: PublicKey,
+    /// Hex-encoded id of the blob.
+    pub blob_id: String,
+    pub kind: OpenHandleMode,
+    /// Number of holders of the lock.
+    pub count: usize,
+}
+
+/// Finds the paths of the given blobs by walking the directory tree of the branch. Stops as soon
+/// as all of them are found. Directories that can't be opened are skipped.
+pub(super) async fn resolve_paths(
+    branch: &Branch,
+    mut blob_ids: HashSet<BlobId>,
+) -> HashMap<BlobId, Utf8PathBuf> {
+    let mut paths = HashMap::default();
+
+    let Ok(root) = branch
+        .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
+        .await
+    else {
+        return paths;
+    };
+
+    let root_path = Utf8PathBuf::from("/");
+
+    if blob_ids.remove(&BlobId::ROOT) {
+        paths.insert(BlobId::ROOT, root_path.clone());
+    }
+
+    let mut dirs = VecDeque::from([(root_path, root)]);
+
+    while let Some((path, dir)) = dirs.pop_front() {
+        if blob_ids.is_empty() {
+            break;
+        }
+
+        for entry in dir.entries() {
+            let entry_path = path.join(entry.name());
+
+            match entry {
+                EntryRef::File(entry) => {
+                    if blob_ids.remove(entry.blob_id()) {
+                        paths.insert(*entry.blob_id(), entry_path);
+                    }
+                }
+                EntryRef::Directory(entry) => {
+                    if blob_ids.remove(entry.blob_id()) {
+                        paths.insert(*entry.blob_id(), entry_path.clone());
+                    }
+
+                    if let Ok(dir) = entry.open(DirectoryFallback::Disabled).await {
+                        dirs.push_back((entry_path, dir));
+                    }
+                }
+                EntryRef::Tombstone(_) => (),
+            }
+        }
+    }
+
+    paths
+}
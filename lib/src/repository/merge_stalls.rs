@@ -0,0 +1,114 @@
+use crate::{
+    collections::HashMap,
+    crypto::{sign::PublicKey, Hash},
+};
+
+/// Number of consecutive failed attempts to merge the same snapshot of a remote branch after which
+/// the merge is considered stalled.
+pub(super) const THRESHOLD: u32 = 3;
+
+/// Tracks the remote snapshots that repeatedly fail to be merged into the local branch and the
+/// ones that have been excluded from merging with `Repository::skip_merge`. Kept only in memory.
+#[derive(Default)]
+pub(super) struct MergeStalls {
+    // Snapshot that failed to merge and the number of consecutive failures, per branch.
+    failures: HashMap<PublicKey, (Hash, u32)>,
+    // Snapshots excluded from merging, per branch.
+    skipped: HashMap<PublicKey, Hash>,
+}
+
+impl MergeStalls {
+    /// Records a failed attempt to merge the given snapshot. Returns `true` if this attempt
+    /// reached the threshold, which happens at most once per snapshot.
+    pub fn record_failure(&mut self, branch_id: PublicKey, hash: Hash) -> bool {
+        let (failed_hash, count) = self.failures.entry(branch_id).or_insert((hash, 0));
+
+        if *failed_hash != hash {
+            *failed_hash = hash;
+            *count = 0;
+        }
+
+        *count = count.saturating_add(1);
+        *count == THRESHOLD
+    }
+
+    /// Records a successful merge of the given branch.
+    pub fn record_success(&mut self, branch_id: &PublicKey) {
+        self.failures.remove(branch_id);
+    }
+
+    /// Excludes the given snapshot from merging.
+    pub fn skip(&mut self, branch_id: PublicKey, hash: Hash) {
+        self.failures.remove(&branch_id);
+        self.skipped.insert(branch_id, hash);
+    }
+
+    /// Is the given snapshot excluded from merging? Forgets the exclusion once the branch moves to
+    /// a different snapshot.
+    pub fn is_skipped(&mut self, branch_id: &PublicKey, hash: &Hash) -> bool {
+        match self.skipped.get(branch_id) {
+            Some(skipped_hash) if skipped_hash == hash => true,
+            Some(_) => {
+                self.skipped.remove(branch_id);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn threshold() {
+        let mut rng = rand::thread_rng();
+        let branch_id = PublicKey::random();
+        let hash_a: Hash = rng.gen();
+        let hash_b: Hash = rng.gen();
+
+        let mut stalls = MergeStalls::default();
+
+        for _ in 1..THRESHOLD {
+            assert!(!stalls.record_failure(branch_id, hash_a));
+        }
+
+        assert!(stalls.record_failure(branch_id, hash_a));
+
+        // Reported only once.
+        assert!(!stalls.record_failure(branch_id, hash_a));
+
+        // A different snapshot starts counting from scratch.
+        for _ in 1..THRESHOLD {
+            assert!(!stalls.record_failure(branch_id, hash_b));
+        }
+
+        stalls.record_success(&branch_id);
+
+        for _ in 1..THRESHOLD {
+            assert!(!stalls.record_failure(branch_id, hash_b));
+        }
+
+        assert!(stalls.record_failure(branch_id, hash_b));
+    }
+
+    #[test]
+    fn skip() {
+        let mut rng = rand::thread_rng();
+        let branch_id = PublicKey::random();
+        let hash_a: Hash = rng.gen();
+        let hash_b: Hash = rng.gen();
+
+        let mut stalls = MergeStalls::default();
+        assert!(!stalls.is_skipped(&branch_id, &hash_a));
+
+        stalls.skip(branch_id, hash_a);
+        assert!(stalls.is_skipped(&branch_id, &hash_a));
+
+        // The branch advanced, the exclusion no longer applies.
+        assert!(!stalls.is_skipped(&branch_id, &hash_b));
+        assert!(!stalls.is_skipped(&branch_id, &hash_a));
+    }
+}
@@ -0,0 +1,19 @@
+use crate::{crypto::sign::PublicKey, db::DatabaseId};
+use std::time::SystemTime;
+
+/// Local information about a repository replica. Obtained with `Repository::meta`.
+///
+/// Unlike the repository id, these are specific to this replica and are not synced to other
+/// replicas. Repositories created before this information was being recorded have only the
+/// `database_id`.
+#[derive(Clone, Debug)]
+pub struct RepositoryMeta {
+    /// Unique id of the database of this replica.
+    pub database_id: DatabaseId,
+    /// Time when the repository was created.
+    pub created_at: Option<SystemTime>,
+    /// Writer id of the replica at the time the repository was created.
+    pub creator_id: Option<PublicKey>,
+    /// `DATA_VERSION` the repository was created with.
+    pub data_version: Option<u64>,
+}
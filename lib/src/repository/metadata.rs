@@ -4,16 +4,21 @@ use crate::{
     },
     crypto::{
         cipher::{self, Nonce},
-        sign, Hash, Password, PasswordSalt,
+        sign, Hash, KdfParams, Password, PasswordSalt,
     },
     db::{self, DatabaseId},
     device_id::DeviceId,
     repository::RepositoryId,
-    store::Error as StoreError,
+    storage_size::StorageSize,
+    store::{Error as StoreError, ExpirationPolicy},
 };
 use rand::{rngs::OsRng, Rng};
 use sqlx::Row;
-use std::{borrow::Cow, fmt, time::Duration};
+use std::{
+    borrow::Cow,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tracing::instrument;
 use zeroize::Zeroize;
 
@@ -25,6 +30,9 @@ const REPOSITORY_ID: &[u8] = b"repository_id";
 // the same password can still unlock it.
 const READ_PASSWORD_SALT: &[u8] = b"read_password_salt";
 const WRITE_PASSWORD_SALT: &[u8] = b"write_password_salt";
+// Params of the function used to derive the local keys from passwords. Stored for the same reason as
+// the salts. If missing, the default params are used.
+const KDF_PARAMS: &[u8] = b"kdf_params";
 const WRITER_ID: &[u8] = b"writer_id";
 const READ_KEY: &[u8] = b"read_key";
 const WRITE_KEY: &[u8] = b"write_key";
@@ -35,6 +43,26 @@ const READ_KEY_VALIDATOR: &[u8] = b"read_key_validator";
 
 const QUOTA: &[u8] = b"quota";
 const BLOCK_EXPIRATION: &[u8] = b"block_expiration";
+const BLOCK_EXPIRATION_SIZE: &[u8] = b"block_expiration_size";
+const BLOCK_COMPRESSION: &[u8] = b"block_compression";
+const MAX_FALLBACK_SNAPSHOTS: &[u8] = b"max_fallback_snapshots";
+const MAX_VERSIONS_PER_FILE: &[u8] = b"max_versions_per_file";
+const TOMBSTONE_TTL: &[u8] = b"tombstone_ttl";
+const PURGED_TOMBSTONES: &[u8] = b"purged_tombstones";
+const GC_BATCH_SIZE: &[u8] = b"gc_batch_size";
+const AUTO_MERGE: &[u8] = b"auto_merge";
+const SYNC_DIRECTORIES_FIRST: &[u8] = b"sync_directories_first";
+const MAX_FILE_SIZE: &[u8] = b"max_file_size";
+const MAX_NAME_LENGTH: &[u8] = b"max_name_length";
+const NAME_NORMALIZATION: &[u8] = b"name_normalization";
+const TYPE_CONFLICT_POLICY: &[u8] = b"type_conflict_policy";
+const DEFAULT_SHARE_ACCESS: &[u8] = b"default_share_access";
+const MAX_SHARE_ACCESS: &[u8] = b"max_share_access";
+const NAME: &[u8] = b"name";
+const FOLLOWED_BRANCH: &[u8] = b"followed_branch";
+const CREATED_AT: &[u8] = b"created_at";
+const CREATOR_ID: &[u8] = b"creator_id";
+const CREATED_DATA_VERSION: &[u8] = b"created_data_version";
 
 // Support for data migrations.
 const DATA_VERSION: &[u8] = b"data_version";
@@ -96,6 +124,17 @@ impl Metadata {
         Ok(())
     }
 
+    /// Like [`Self::get`] but for values stored encrypted (see [`MetadataWriter::set_encrypted`]).
+    /// Using a different `key` than the one the value was stored with yields garbage.
+    pub(crate) async fn get_encrypted(
+        &self,
+        name: &str,
+        key: &cipher::SecretKey,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        let mut conn = self.db.acquire().await?;
+        get_secret_blob(&mut conn, name.as_bytes(), key).await
+    }
+
     pub async fn write(&self) -> Result<MetadataWriter, StoreError> {
         Ok(MetadataWriter {
             tx: self.db.begin_write().await?,
@@ -126,6 +165,21 @@ impl MetadataWriter {
         remove_public(&mut self.tx, name.as_bytes()).await
     }
 
+    /// Stores the value encrypted with `key` so it can't be read by anyone with access to the
+    /// database file but not to the key.
+    pub(crate) async fn set_encrypted(
+        &mut self,
+        name: &str,
+        value: &[u8],
+        key: &cipher::SecretKey,
+    ) -> Result<(), StoreError> {
+        set_secret_blob(&mut self.tx, name.as_bytes(), value, key).await
+    }
+
+    pub(crate) async fn remove_encrypted(&mut self, name: &str) -> Result<(), StoreError> {
+        remove_secret(&mut self.tx, name.as_bytes()).await
+    }
+
     pub async fn commit(self) -> Result<(), StoreError> {
         self.tx.commit().await?;
         Ok(())
@@ -146,10 +200,12 @@ pub(crate) async fn password_to_key(
     password: &Password,
 ) -> Result<cipher::SecretKey, StoreError> {
     let salt = get_password_salt(tx, key_type).await?;
+    let params = get_kdf_params(tx).await?;
 
-    Ok(cipher::SecretKey::derive_from_password(
+    Ok(cipher::SecretKey::derive_from_password_with_params(
         password.as_ref(),
         &salt,
+        &params,
     ))
 }
 
@@ -166,17 +222,35 @@ async fn secret_to_key<'a>(
     }
 }
 
-pub(crate) fn secret_to_key_and_salt(secret: &'_ SetLocalSecret) -> Cow<'_, KeyAndSalt> {
+pub(crate) fn secret_to_key_and_salt<'a>(
+    secret: &'a SetLocalSecret,
+    params: &KdfParams,
+) -> Cow<'a, KeyAndSalt> {
     match secret {
         SetLocalSecret::Password(password) => {
             let salt = cipher::SecretKey::random_salt();
-            let key = cipher::SecretKey::derive_from_password(password.as_ref(), &salt);
+            let key = cipher::SecretKey::derive_from_password_with_params(
+                password.as_ref(),
+                &salt,
+                params,
+            );
             Cow::Owned(KeyAndSalt { key, salt })
         }
         SetLocalSecret::KeyAndSalt(key_and_salt) => Cow::Borrowed(key_and_salt),
     }
 }
 
+pub(crate) async fn get_kdf_params(conn: &mut db::Connection) -> Result<KdfParams, StoreError> {
+    Ok(get_public_blob(conn, KDF_PARAMS).await?.unwrap_or_default())
+}
+
+pub(crate) async fn set_kdf_params(
+    tx: &mut db::WriteTransaction,
+    params: &KdfParams,
+) -> Result<(), StoreError> {
+    set_public_blob(tx, KDF_PARAMS, params.to_bytes()).await
+}
+
 // -------------------------------------------------------------------
 // Database ID
 // -------------------------------------------------------------------
@@ -257,6 +331,22 @@ pub(crate) async fn set_device_id(
     Ok(())
 }
 
+// Copies only the public metadata needed to open the repository (its id and the data version) from
+// the `src` database into the `main` database of the export connection (see `db::export`). The
+// secrets, the device id, the name and the settings are not copied.
+pub(crate) async fn export(conn: &mut db::Connection) -> Result<(), StoreError> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO main.metadata_public
+         SELECT * FROM src.metadata_public WHERE name IN (?, ?)",
+    )
+    .bind(REPOSITORY_ID)
+    .bind(DATA_VERSION)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
 // -------------------------------------------------------------------
 // Access secrets
 // -------------------------------------------------------------------
@@ -503,7 +593,7 @@ pub(crate) async fn set_access<'a>(
             local_secret,
             read_key,
         } => {
-            let local = secret_to_key_and_salt(local_secret);
+            let local = secret_to_key_and_salt(local_secret, &get_kdf_params(tx).await?);
 
             remove_public_read_key(tx).await?;
             set_secret_read_key(tx, id, read_key, &local).await?;
@@ -531,8 +621,9 @@ pub(crate) async fn set_access<'a>(
             local_write_secret,
             secrets,
         } => {
-            let local_read = secret_to_key_and_salt(local_read_secret);
-            let local_write = secret_to_key_and_salt(local_write_secret);
+            let kdf_params = get_kdf_params(tx).await?;
+            let local_read = secret_to_key_and_salt(local_read_secret, &kdf_params);
+            let local_write = secret_to_key_and_salt(local_write_secret, &kdf_params);
 
             remove_public_read_key(tx).await?;
             set_secret_read_key(tx, &secrets.id, &secrets.read_key, &local_read).await?;
@@ -548,7 +639,8 @@ pub(crate) async fn set_access<'a>(
             local_write_secret,
             secrets,
         } => {
-            let local_write = secret_to_key_and_salt(local_write_secret);
+            let local_write =
+                secret_to_key_and_salt(local_write_secret, &get_kdf_params(tx).await?);
 
             set_public_read_key(tx, &secrets.read_key).await?;
             obfuscate_secret_read_key(tx).await?;
@@ -563,6 +655,55 @@ pub(crate) async fn set_access<'a>(
     }
 }
 
+/// Re-encrypts the access keys (and the writer id) that are currently protected by the `old` local
+/// secret with the `new` one. Keys that are stored unencrypted or protected by a different secret
+/// are left unchanged. Returns whether any key was re-encrypted.
+pub(crate) async fn rekey(
+    tx: &mut db::WriteTransaction,
+    old: &LocalSecret,
+    new: &SetLocalSecret,
+) -> Result<bool, StoreError> {
+    let id = get_repository_id(tx).await?;
+    let kdf_params = get_kdf_params(tx).await?;
+    let mut changed = false;
+
+    if get_public_blob::<sign::Keypair>(tx, WRITE_KEY)
+        .await?
+        .is_none()
+    {
+        let old_key = secret_to_key(tx, KeyType::Write, old).await?;
+
+        if let Some(write_keys) = get_write_key(tx, Some(&*old_key), &id).await? {
+            let writer_id = get_writer_id(tx, Some(&*old_key)).await?;
+            let new = secret_to_key_and_salt(new, &kdf_params);
+
+            set_secret_write_key(tx, &WriteSecrets::from(write_keys), &new).await?;
+
+            if let Some(writer_id) = writer_id {
+                set_writer_id(tx, &writer_id, Some(&new.key)).await?;
+            }
+
+            changed = true;
+        }
+    }
+
+    if get_public_blob::<cipher::SecretKey>(tx, READ_KEY)
+        .await?
+        .is_none()
+    {
+        let old_key = secret_to_key(tx, KeyType::Read, old).await?;
+
+        if let Some(read_key) = get_read_key(tx, Some(&*old_key), &id).await? {
+            let new = secret_to_key_and_salt(new, &kdf_params);
+            set_secret_read_key(tx, &id, &read_key, &new).await?;
+
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
 pub(crate) struct LocalKeys<'a> {
     #[allow(unused)]
     pub read: Option<Cow<'a, cipher::SecretKey>>,
@@ -702,8 +843,113 @@ pub(crate) mod quota {
 pub(crate) mod block_expiration {
     use super::*;
 
+    pub(crate) async fn get(
+        conn: &mut db::Connection,
+    ) -> Result<Option<ExpirationPolicy>, StoreError> {
+        let expiration_time = get_public(conn, BLOCK_EXPIRATION)
+            .await?
+            .map(Duration::from_millis);
+        let size_limit = get_public(conn, BLOCK_EXPIRATION_SIZE)
+            .await?
+            .map(StorageSize::from_bytes);
+
+        Ok(ExpirationPolicy::new(expiration_time, size_limit))
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: Option<ExpirationPolicy>,
+    ) -> Result<(), StoreError> {
+        if let Some(duration) = value.and_then(|policy| policy.expiration_time()) {
+            set_public(
+                tx,
+                BLOCK_EXPIRATION,
+                u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            )
+            .await?;
+        } else {
+            remove_public(tx, BLOCK_EXPIRATION).await?;
+        }
+
+        if let Some(size) = value.and_then(|policy| policy.size_limit()) {
+            set_public(tx, BLOCK_EXPIRATION_SIZE, size.to_bytes()).await
+        } else {
+            remove_public(tx, BLOCK_EXPIRATION_SIZE).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Block compression
+// -------------------------------------------------------------------
+pub(crate) mod block_compression {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<bool, StoreError> {
+        Ok(get_public(conn, BLOCK_COMPRESSION).await?.unwrap_or(false))
+    }
+
+    pub(crate) async fn set(tx: &mut db::WriteTransaction, value: bool) -> Result<(), StoreError> {
+        set_public(tx, BLOCK_COMPRESSION, value).await
+    }
+}
+
+// -------------------------------------------------------------------
+// Max fallback snapshots
+// -------------------------------------------------------------------
+pub(crate) mod max_fallback_snapshots {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<Option<usize>, StoreError> {
+        Ok(get_public::<u64>(conn, MAX_FALLBACK_SNAPSHOTS)
+            .await?
+            .map(|value| usize::try_from(value).unwrap_or(usize::MAX)))
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: Option<usize>,
+    ) -> Result<(), StoreError> {
+        if let Some(value) = value {
+            set_public(tx, MAX_FALLBACK_SNAPSHOTS, value as u64).await
+        } else {
+            remove_public(tx, MAX_FALLBACK_SNAPSHOTS).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Max versions per file
+// -------------------------------------------------------------------
+pub(crate) mod max_versions_per_file {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<Option<usize>, StoreError> {
+        Ok(get_public::<u64>(conn, MAX_VERSIONS_PER_FILE)
+            .await?
+            .map(|value| usize::try_from(value).unwrap_or(usize::MAX)))
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: Option<usize>,
+    ) -> Result<(), StoreError> {
+        if let Some(value) = value {
+            set_public(tx, MAX_VERSIONS_PER_FILE, value as u64).await
+        } else {
+            remove_public(tx, MAX_VERSIONS_PER_FILE).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Tombstone TTL
+// -------------------------------------------------------------------
+pub(crate) mod tombstone_ttl {
+    use super::*;
+
     pub(crate) async fn get(conn: &mut db::Connection) -> Result<Option<Duration>, StoreError> {
-        Ok(get_public(conn, BLOCK_EXPIRATION)
+        Ok(get_public(conn, TOMBSTONE_TTL)
             .await?
             .map(Duration::from_millis))
     }
@@ -715,12 +961,283 @@ pub(crate) mod block_expiration {
         if let Some(duration) = value {
             set_public(
                 tx,
-                BLOCK_EXPIRATION,
+                TOMBSTONE_TTL,
                 u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
             )
             .await
         } else {
-            remove_public(tx, BLOCK_EXPIRATION).await
+            remove_public(tx, TOMBSTONE_TTL).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Purged tombstones
+// -------------------------------------------------------------------
+pub(crate) mod purged_tombstones {
+    use super::*;
+    use crate::{blob::BlobId, version_vector::VersionVector};
+
+    pub(crate) async fn get(
+        conn: &mut db::Connection,
+    ) -> Result<Vec<(BlobId, String, VersionVector)>, StoreError> {
+        let Some(bytes) = get_public_blob::<Vec<u8>>(conn, PURGED_TOMBSTONES).await? else {
+            return Ok(Vec::new());
+        };
+
+        bincode::deserialize(&bytes).map_err(|_| StoreError::MalformedData)
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: &[(BlobId, String, VersionVector)],
+    ) -> Result<(), StoreError> {
+        if value.is_empty() {
+            return remove_public(tx, PURGED_TOMBSTONES).await;
+        }
+
+        // unwrap is ok because serializing these types never fails.
+        set_public_blob(tx, PURGED_TOMBSTONES, bincode::serialize(value).unwrap()).await
+    }
+}
+
+// -------------------------------------------------------------------
+// Garbage collection batch size
+// -------------------------------------------------------------------
+pub(crate) mod gc_batch_size {
+    use super::*;
+
+    pub(crate) const DEFAULT: usize = 32;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<usize, StoreError> {
+        Ok(get_public::<u64>(conn, GC_BATCH_SIZE)
+            .await?
+            .map(|value| usize::try_from(value).unwrap_or(usize::MAX))
+            .unwrap_or(DEFAULT))
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: Option<usize>,
+    ) -> Result<(), StoreError> {
+        if let Some(value) = value {
+            set_public(tx, GC_BATCH_SIZE, value as u64).await
+        } else {
+            remove_public(tx, GC_BATCH_SIZE).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Auto merge
+// -------------------------------------------------------------------
+pub(crate) mod auto_merge {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<bool, StoreError> {
+        Ok(get_public(conn, AUTO_MERGE).await?.unwrap_or(true))
+    }
+
+    pub(crate) async fn set(tx: &mut db::WriteTransaction, value: bool) -> Result<(), StoreError> {
+        set_public(tx, AUTO_MERGE, value).await
+    }
+}
+
+// -------------------------------------------------------------------
+// Sync order
+// -------------------------------------------------------------------
+pub(crate) mod sync_directories_first {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<bool, StoreError> {
+        Ok(get_public(conn, SYNC_DIRECTORIES_FIRST)
+            .await?
+            .unwrap_or(false))
+    }
+
+    pub(crate) async fn set(tx: &mut db::WriteTransaction, value: bool) -> Result<(), StoreError> {
+        if value {
+            set_public(tx, SYNC_DIRECTORIES_FIRST, value).await
+        } else {
+            remove_public(tx, SYNC_DIRECTORIES_FIRST).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Max file size
+// -------------------------------------------------------------------
+pub(crate) mod max_file_size {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<Option<u64>, StoreError> {
+        get_public(conn, MAX_FILE_SIZE).await
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: Option<u64>,
+    ) -> Result<(), StoreError> {
+        if let Some(value) = value {
+            set_public(tx, MAX_FILE_SIZE, value).await
+        } else {
+            remove_public(tx, MAX_FILE_SIZE).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Entry name policy
+// -------------------------------------------------------------------
+pub(crate) mod max_name_length {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<Option<usize>, StoreError> {
+        Ok(get_public::<u64>(conn, MAX_NAME_LENGTH)
+            .await?
+            .map(|value| usize::try_from(value).unwrap_or(usize::MAX)))
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: Option<usize>,
+    ) -> Result<(), StoreError> {
+        if let Some(value) = value {
+            set_public(tx, MAX_NAME_LENGTH, value as u64).await
+        } else {
+            remove_public(tx, MAX_NAME_LENGTH).await
+        }
+    }
+}
+
+pub(crate) mod name_normalization {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<bool, StoreError> {
+        Ok(get_public(conn, NAME_NORMALIZATION).await?.unwrap_or(false))
+    }
+
+    pub(crate) async fn set(tx: &mut db::WriteTransaction, value: bool) -> Result<(), StoreError> {
+        set_public(tx, NAME_NORMALIZATION, value).await
+    }
+}
+
+// -------------------------------------------------------------------
+// File vs directory name conflicts
+// -------------------------------------------------------------------
+pub(crate) mod type_conflict_policy {
+    use super::*;
+    use crate::joint_directory::TypeConflictPolicy;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<TypeConflictPolicy, StoreError> {
+        Ok(get_public::<u64>(conn, TYPE_CONFLICT_POLICY)
+            .await?
+            .and_then(|value| u8::try_from(value).ok())
+            .and_then(TypeConflictPolicy::from_u8)
+            .unwrap_or_default())
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: TypeConflictPolicy,
+    ) -> Result<(), StoreError> {
+        if value == TypeConflictPolicy::default() {
+            remove_public(tx, TYPE_CONFLICT_POLICY).await
+        } else {
+            set_public(tx, TYPE_CONFLICT_POLICY, u64::from(value.to_u8())).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Access mode of newly created share tokens
+// -------------------------------------------------------------------
+pub(crate) mod share_access {
+    use super::*;
+    use crate::access_control::AccessMode;
+
+    pub(crate) async fn get_default(conn: &mut db::Connection) -> Result<AccessMode, StoreError> {
+        get(conn, DEFAULT_SHARE_ACCESS).await
+    }
+
+    pub(crate) async fn set_default(
+        tx: &mut db::WriteTransaction,
+        value: AccessMode,
+    ) -> Result<(), StoreError> {
+        set(tx, DEFAULT_SHARE_ACCESS, value).await
+    }
+
+    pub(crate) async fn get_max(conn: &mut db::Connection) -> Result<AccessMode, StoreError> {
+        get(conn, MAX_SHARE_ACCESS).await
+    }
+
+    pub(crate) async fn set_max(
+        tx: &mut db::WriteTransaction,
+        value: AccessMode,
+    ) -> Result<(), StoreError> {
+        set(tx, MAX_SHARE_ACCESS, value).await
+    }
+
+    async fn get(conn: &mut db::Connection, name: &[u8]) -> Result<AccessMode, StoreError> {
+        Ok(get_public::<u64>(conn, name)
+            .await?
+            .and_then(|value| u8::try_from(value).ok())
+            .and_then(|value| AccessMode::try_from(value).ok())
+            .unwrap_or(AccessMode::Write))
+    }
+
+    async fn set(
+        tx: &mut db::WriteTransaction,
+        name: &[u8],
+        value: AccessMode,
+    ) -> Result<(), StoreError> {
+        if value == AccessMode::Write {
+            remove_public(tx, name).await
+        } else {
+            set_public(tx, name, u64::from(u8::from(value))).await
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Display name
+// -------------------------------------------------------------------
+pub(crate) mod name {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<Option<String>, StoreError> {
+        get_public(conn, NAME).await
+    }
+
+    pub(crate) async fn set(tx: &mut db::WriteTransaction, value: &str) -> Result<(), StoreError> {
+        set_public(tx, NAME, value).await
+    }
+
+    pub(crate) async fn remove(tx: &mut db::WriteTransaction) -> Result<(), StoreError> {
+        remove_public(tx, NAME).await
+    }
+}
+
+// -------------------------------------------------------------------
+// Observer mode
+// -------------------------------------------------------------------
+pub(crate) mod followed_branch {
+    use super::*;
+
+    pub(crate) async fn get(
+        conn: &mut db::Connection,
+    ) -> Result<Option<sign::PublicKey>, StoreError> {
+        get_public_blob(conn, FOLLOWED_BRANCH).await
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        value: Option<&sign::PublicKey>,
+    ) -> Result<(), StoreError> {
+        if let Some(value) = value {
+            set_public_blob(tx, FOLLOWED_BRANCH, value).await
+        } else {
+            remove_public(tx, FOLLOWED_BRANCH).await
         }
     }
 }
@@ -740,6 +1257,51 @@ pub(crate) mod data_version {
     }
 }
 
+// -------------------------------------------------------------------
+// Creation info
+// -------------------------------------------------------------------
+pub(crate) mod creation {
+    use super::*;
+
+    pub(crate) async fn get(conn: &mut db::Connection) -> Result<Creation, StoreError> {
+        let created_at = get_public(conn, CREATED_AT)
+            .await?
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let creator_id = get_public_blob(conn, CREATOR_ID).await?;
+        let data_version = get_public(conn, CREATED_DATA_VERSION).await?;
+
+        Ok(Creation {
+            created_at,
+            creator_id,
+            data_version,
+        })
+    }
+
+    pub(crate) async fn set(
+        tx: &mut db::WriteTransaction,
+        creator_id: &sign::PublicKey,
+        data_version: u64,
+    ) -> Result<(), StoreError> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+
+        set_public(tx, CREATED_AT, created_at).await?;
+        set_public_blob(tx, CREATOR_ID, creator_id).await?;
+        set_public(tx, CREATED_DATA_VERSION, data_version).await?;
+
+        Ok(())
+    }
+
+    // Repositories created before this info was being recorded have all the fields `None`.
+    pub(crate) struct Creation {
+        pub created_at: Option<SystemTime>,
+        pub creator_id: Option<sign::PublicKey>,
+        pub data_version: Option<u64>,
+    }
+}
+
 // -------------------------------------------------------------------
 // Public values
 // -------------------------------------------------------------------
@@ -948,6 +1510,14 @@ where
     Ok(())
 }
 
+async fn remove_secret(tx: &mut db::WriteTransaction, id: &[u8]) -> Result<(), StoreError> {
+    sqlx::query("DELETE FROM metadata_secret WHERE name = ?")
+        .bind(id)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
 fn make_nonce() -> Nonce {
     // Random nonces should be OK given that we're not generating too many of them.
     // But maybe consider using the mixed approach from this SO post?
@@ -0,0 +1,161 @@
+use crate::{
+    collections::{HashMap, HashSet},
+    crypto::Hash,
+    error::Result,
+    protocol::{BlockId, MultiBlockPresence, SingleBlockPresence},
+    store::Store,
+};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Compact description of which blocks a replica has. Obtained with
+/// `Repository::presence_snapshot` and passed to `Repository::blocks_missing_by` of another
+/// replica of the same repository to find out which blocks this one is missing.
+///
+/// Contains the block presence summaries of the index nodes, but only down to the subtrees that
+/// are either complete or empty, so its size is proportional to how much the replica differs from
+/// being complete, not to the size of the repository.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct PresenceSnapshot {
+    nodes: HashMap<Hash, MultiBlockPresence>,
+}
+
+impl PresenceSnapshot {
+    pub(super) async fn load(store: &Store) -> Result<Self> {
+        let mut reader = store.acquire_read().await?;
+        let mut nodes = HashMap::default();
+        let mut stack = Vec::new();
+
+        let root_nodes: Vec<_> = reader.load_root_nodes().try_collect().await?;
+
+        for root_node in root_nodes {
+            insert(
+                &mut nodes,
+                &mut stack,
+                root_node.proof.hash,
+                root_node.summary.block_presence,
+            );
+        }
+
+        while let Some(parent_hash) = stack.pop() {
+            for (_, node) in &reader.load_inner_nodes(&parent_hash).await? {
+                if !node.is_empty() {
+                    insert(
+                        &mut nodes,
+                        &mut stack,
+                        node.hash,
+                        node.summary.block_presence,
+                    );
+                }
+            }
+        }
+
+        Ok(Self { nodes })
+    }
+}
+
+fn insert(
+    nodes: &mut HashMap<Hash, MultiBlockPresence>,
+    stack: &mut Vec<Hash>,
+    hash: Hash,
+    presence: MultiBlockPresence,
+) {
+    // Only partially present subtrees need to be described further.
+    if nodes.insert(hash, presence).is_none() && matches!(presence, MultiBlockPresence::Some(_)) {
+        stack.push(hash);
+    }
+}
+
+/// State of `Repository::blocks_missing_by`.
+pub(super) struct MissingBlocks {
+    snapshot: PresenceSnapshot,
+    // Nodes still to visit together with whether the peer is known to have none of their blocks.
+    stack: Vec<(Hash, bool)>,
+    ready: VecDeque<BlockId>,
+    seen: HashSet<BlockId>,
+    started: bool,
+}
+
+impl MissingBlocks {
+    pub fn new(snapshot: PresenceSnapshot) -> Self {
+        Self {
+            snapshot,
+            stack: Vec::new(),
+            ready: VecDeque::new(),
+            seen: HashSet::default(),
+            started: false,
+        }
+    }
+
+    /// Returns the next block which is present locally but which the peer might be missing.
+    pub async fn next(&mut self, store: &Store) -> Result<Option<BlockId>> {
+        loop {
+            if let Some(block_id) = self.ready.pop_front() {
+                return Ok(Some(block_id));
+            }
+
+            let mut reader = store.acquire_read().await?;
+
+            if !self.started {
+                self.started = true;
+
+                let root_nodes: Vec<_> = reader.load_root_nodes().try_collect().await?;
+
+                for root_node in root_nodes {
+                    self.push(
+                        root_node.proof.hash,
+                        root_node.summary.block_presence,
+                        false,
+                    );
+                }
+            }
+
+            let Some((parent_hash, peer_has_none)) = self.stack.pop() else {
+                return Ok(None);
+            };
+
+            let inner_nodes = reader.load_inner_nodes(&parent_hash).await?;
+
+            if !inner_nodes.is_empty() {
+                for (_, node) in &inner_nodes {
+                    if !node.is_empty() {
+                        self.push(node.hash, node.summary.block_presence, peer_has_none);
+                    }
+                }
+
+                continue;
+            }
+
+            // The snapshot doesn't describe individual blocks so all the present ones are
+            // reported.
+            for node in &reader.load_leaf_nodes(&parent_hash).await? {
+                if node.block_presence == SingleBlockPresence::Present
+                    && self.seen.insert(node.block_id)
+                {
+                    self.ready.push_back(node.block_id);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, hash: Hash, ours: MultiBlockPresence, peer_has_none: bool) {
+        if ours == MultiBlockPresence::None {
+            // We have nothing to offer here.
+            return;
+        }
+
+        if peer_has_none {
+            self.stack.push((hash, true));
+            return;
+        }
+
+        match self.snapshot.nodes.get(&hash) {
+            Some(MultiBlockPresence::Full) => (),
+            Some(theirs) if *theirs == ours => (),
+            Some(MultiBlockPresence::None) => self.stack.push((hash, true)),
+            // Partially present or unknown (the peer doesn't have this version of the subtree).
+            Some(MultiBlockPresence::Some(_)) | None => self.stack.push((hash, false)),
+        }
+    }
+}
@@ -1,9 +1,31 @@
+mod changes;
+mod clone;
+mod compare;
 mod credentials;
+mod dedup;
+mod export;
+mod find;
+mod handles;
 mod id;
+mod merge_stalls;
+mod meta;
 mod metadata;
+mod missing_blocks;
 mod monitor;
 mod params;
+mod path_events;
+mod path_filter;
+mod prefetch;
+mod pull;
+mod recovery;
+mod repair;
+mod sizes;
+mod snapshot;
+mod stat;
+mod status;
+mod sync_filter;
 mod vault;
+mod watched;
 mod worker;
 
 #[cfg(test)]
@@ -12,51 +34,89 @@ mod tests;
 mod vault_tests;
 
 pub use self::{
-    credentials::Credentials, id::RepositoryId, metadata::Metadata, params::RepositoryParams,
+    changes::ChangeKind,
+    compare::{Difference, DifferenceKind},
+    credentials::Credentials,
+    dedup::DedupStats,
+    handles::{LockInfo, OpenHandleInfo, OpenHandleMode},
+    id::RepositoryId,
+    meta::RepositoryMeta,
+    metadata::Metadata,
+    missing_blocks::PresenceSnapshot,
+    params::RepositoryParams,
+    path_events::PathEvent,
+    recovery::RecoveryReport,
+    repair::RepairProgress,
+    sizes::RepositorySizes,
+    snapshot::{ReadSnapshot, SnapshotDirectory, SnapshotEntry, SnapshotFile},
+    stat::EntryMetadata,
+    status::RepositoryStatus,
+    watched::WatchedDirectory,
 };
 
 pub(crate) use self::{
     id::LocalId,
-    metadata::{data_version, quota},
+    metadata::{
+        auto_merge, data_version, gc_batch_size, max_fallback_snapshots, max_versions_per_file,
+        purged_tombstones, quota, sync_directories_first, tombstone_ttl,
+    },
     monitor::RepositoryMonitor,
+    sync_filter::SyncFilter,
     vault::{BlockRequestMode, Vault},
 };
 
+use self::{
+    merge_stalls::MergeStalls, missing_blocks::MissingBlocks, prefetch::Prefetch, repair::Repair,
+};
+
+#[cfg(feature = "unstable")]
+use crate::protocol::{BlockContent, BlockNonce};
 use crate::{
-    access_control::{Access, AccessChange, AccessKeys, AccessMode, AccessSecrets, LocalSecret},
+    access_control::{
+        Access, AccessChange, AccessKeys, AccessMode, AccessSecrets, LocalSecret, SetLocalSecret,
+        ShareToken,
+    },
+    blob::{Blob, BlobId, BufferPool, HEADER_SIZE},
     branch::{Branch, BranchShared},
-    crypto::{sign::PublicKey, PasswordSalt},
+    collections::{HashMap, HashSet},
+    crypto::{cipher, sign::PublicKey, KdfParams, PasswordSalt},
     db::{self, DatabaseId},
-    debug::DebugPrinter,
-    directory::{Directory, DirectoryFallback, DirectoryLocking, EntryRef, EntryType},
+    debug::{next_span_id, DebugPrinter},
+    directory::{
+        self, Directory, DirectoryFallback, DirectoryLocking, EntryData, EntryRef, EntryType,
+    },
     error::{Error, Result},
-    event::{Event, EventSender},
-    file::File,
-    joint_directory::{JointDirectory, JointEntryRef, MissingVersionStrategy},
+    event::{Event, EventSender, Payload},
+    file::{BlockWaiter, File, FileRange, MissingBlockPolicy},
+    joint_directory::{JointDirectory, JointEntryRef, MissingVersionStrategy, TypeConflictPolicy},
+    network::Registration,
     path,
     progress::Progress,
-    protocol::{RootNodeFilter, BLOCK_SIZE},
+    protocol::{BlockId, Locator, MultiBlockPresence, RootNode, RootNodeFilter, BLOCK_SIZE},
     storage_size::StorageSize,
-    store,
+    store::{self, BlockAccess, ExpirationPolicy},
     sync::stream::Throttle,
     version_vector::VersionVector,
 };
-use camino::Utf8Path;
-use deadlock::{BlockingMutex, BlockingRwLock};
+use camino::{Utf8Path, Utf8PathBuf};
+use deadlock::{AsyncMutex, BlockingMutex, BlockingRwLock};
 use futures_util::{future, TryStreamExt};
-use futures_util::{stream, StreamExt};
+use futures_util::{stream, Stream, StreamExt};
 use metrics::Recorder;
 use scoped_task::ScopedJoinHandle;
 use state_monitor::StateMonitor;
-use std::{borrow::Cow, io, path::Path, pin::pin, sync::Arc};
+use std::{borrow::Cow, collections::BTreeMap, fmt, io, path::Path, pin::pin, sync::Arc};
 use tokio::{
     fs,
     sync::broadcast::{self, error::RecvError},
     time::Duration,
 };
-use tracing::instrument::Instrument;
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::{instrument, instrument::Instrument, Span};
 
 const EVENT_CHANNEL_CAPACITY: usize = 256;
+// Number of tasks waiting for a write transaction at which the write pressure is reported as full.
+const WRITE_QUEUE_SATURATION: usize = 16;
 
 pub struct Repository {
     shared: Arc<Shared>,
@@ -87,6 +147,15 @@ pub async fn delete(store: impl AsRef<Path>) -> io::Result<()> {
     .unwrap_or(Ok(()))
 }
 
+/// Runs `f` with all the repository writes it performs being low-priority, that is, yielding to
+/// the other (interactive) writes to the same repository waiting at the same time. Meant for bulk
+/// operations like importing many files, so they don't make the app unresponsive. Writing files
+/// in chunks (flushing between them) gives the other writes more chances to go in between.
+/// Applies only to the current task, not to the tasks spawned from `f`.
+pub async fn low_priority_writes<F: std::future::Future>(f: F) -> F::Output {
+    db::low_priority(f).await
+}
+
 impl Repository {
     /// Creates a new repository.
     pub async fn create(params: &RepositoryParams<impl Recorder>, access: Access) -> Result<Self> {
@@ -96,13 +165,17 @@ impl Repository {
 
         let mut tx = pool.begin_write().await?;
 
+        metadata::set_kdf_params(&mut tx, params.kdf_params()).await?;
         let local_keys = metadata::initialize_access_secrets(&mut tx, &access).await?;
         let writer_id =
             metadata::get_or_generate_writer_id(&mut tx, local_keys.write.as_deref()).await?;
         metadata::set_device_id(&mut tx, &device_id).await?;
+        metadata::creation::set(&mut tx, &writer_id, store::DATA_VERSION).await?;
 
         tx.commit().await?;
 
+        metadata::get_or_generate_database_id(&pool).await?;
+
         let credentials = Credentials {
             secrets: access.secrets(),
             writer_id,
@@ -111,7 +184,70 @@ impl Repository {
         Self::new(pool, credentials, monitor).await
     }
 
+    /// Creates a new repository with the given params and access secrets and copies the current
+    /// content of this repository into it. This is a deep copy, not a share: the new repository
+    /// has its own identity (the secrets in `access` must be freshly generated, with write access)
+    /// and so it doesn't sync with this one or any of its replicas. All files are re-encrypted
+    /// with the new keys and the history (version vectors) is not preserved. Concurrent versions
+    /// of a file are copied as separate files, under the names they are listed with in this
+    /// repository. All the content must be available locally (fully downloaded), otherwise this
+    /// fails with `BlockNotFound`, in which case the partially copied repository is left at the
+    /// destination and should be deleted.
+    pub async fn clone_to(
+        &self,
+        params: &RepositoryParams<impl Recorder>,
+        access: Access,
+    ) -> Result<Repository> {
+        if access.id() == self.credentials().secrets.id() {
+            return Err(Error::OperationNotSupported);
+        }
+
+        if !matches!(
+            access,
+            Access::WriteUnlocked { .. } | Access::WriteLocked { .. }
+        ) {
+            return Err(Error::PermissionDenied);
+        }
+
+        let dst = Self::create(params, access).await?;
+        clone::copy_all(self, &dst).await?;
+
+        Ok(dst)
+    }
+
+    /// Copies everything that can still be read from the repository database at `src` (e.g., one
+    /// that failed to open with `Error::Corrupted`) into a new repository database at `dst`, which
+    /// must not exist yet. The recovered repository can then be opened with `open` as usual.
+    /// The original database is not modified.
+    ///
+    /// The blocks that couldn't be recovered are marked as missing so they can be downloaded
+    /// again from other replicas.
+    pub async fn recover(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<RecoveryReport> {
+        let (recovered, complete) = db::recover(src.as_ref(), dst.as_ref()).await?;
+
+        // Some of the blocks or index nodes might have been lost or damaged. Remove the damaged
+        // blocks, mark all the unrecovered ones as missing and update the index summaries
+        // accordingly, otherwise they would be considered present and never requested again.
+        let store = store::Store::new(db::open(dst.as_ref()).await?);
+        let repair = store.repair_blocks().await?;
+        store.recompute_summaries().await?;
+        store.close().await?;
+
+        let lost_blocks = (repair.corrupt.len() + repair.lost.len()) as u64;
+
+        Ok(RecoveryReport {
+            recovered,
+            lost_blocks,
+            complete: complete && lost_blocks == 0,
+        })
+    }
+
     /// Opens an existing repository.
+    ///
+    /// The repository is opened with the access unlocked by `local_secret` but at most with
+    /// `access_mode`. This can be used to operate a repository read-only (or blind) even if write
+    /// access is available, in which case any attempt to modify it fails with
+    /// `Error::PermissionDenied`. Use [`Self::set_access_mode`] to change the mode later.
     pub async fn open(
         params: &RepositoryParams<impl Recorder>,
         local_secret: Option<LocalSecret>,
@@ -190,11 +326,41 @@ impl Repository {
 
         {
             let mut conn = vault.store().db().acquire().await?;
-            if let Some(block_expiration) = metadata::block_expiration::get(&mut conn).await? {
-                vault.set_block_expiration(Some(block_expiration)).await?;
+            if let Some(policy) = metadata::block_expiration::get(&mut conn).await? {
+                vault.set_block_expiration(Some(policy)).await?;
             }
         }
 
+        let branch_shared = BranchShared::new()
+            .with_buffer_pool(BufferPool::new(vault.monitor.block_buffers_in_use.clone()));
+
+        {
+            let mut conn = vault.store().db().acquire().await?;
+            branch_shared
+                .compression
+                .set_enabled(metadata::block_compression::get(&mut conn).await?);
+            branch_shared
+                .max_file_size
+                .set(metadata::max_file_size::get(&mut conn).await?);
+            branch_shared
+                .name_policy
+                .set_max_len(metadata::max_name_length::get(&mut conn).await?);
+            branch_shared
+                .name_policy
+                .set_normalization_enabled(metadata::name_normalization::get(&mut conn).await?);
+            branch_shared
+                .type_conflict
+                .set(metadata::type_conflict_policy::get(&mut conn).await?);
+            branch_shared
+                .purged_tombstones
+                .set(metadata::purged_tombstones::get(&mut conn).await?);
+        }
+
+        let followed_branch = {
+            let mut conn = vault.store().db().acquire().await?;
+            metadata::followed_branch::get(&mut conn).await?
+        };
+
         tracing::debug!(
             parent: vault.monitor.span(),
             access = ?credentials.secrets.access_mode(),
@@ -205,7 +371,10 @@ impl Repository {
         let shared = Arc::new(Shared {
             vault,
             credentials: BlockingRwLock::new(credentials),
-            branch_shared: BranchShared::new(),
+            branch_shared,
+            followed_branch: BlockingMutex::new(followed_branch),
+            merge_stalls: BlockingMutex::new(MergeStalls::default()),
+            merge_lock: AsyncMutex::new(()),
         });
 
         let worker_handle = spawn_worker(shared.clone());
@@ -228,6 +397,22 @@ impl Repository {
         Ok(metadata::get_or_generate_database_id(self.db()).await?)
     }
 
+    /// Returns the local information about this repository replica (database id, creation time,
+    /// ...). Not to be confused with `metadata` which provides access to user-defined entries.
+    pub async fn meta(&self) -> Result<RepositoryMeta> {
+        let database_id = metadata::get_or_generate_database_id(self.db()).await?;
+
+        let mut conn = self.db().acquire().await?;
+        let creation = metadata::creation::get(&mut conn).await?;
+
+        Ok(RepositoryMeta {
+            database_id,
+            created_at: creation.created_at,
+            creator_id: creation.creator_id,
+            data_version: creation.data_version,
+        })
+    }
+
     pub async fn requires_local_secret_for_reading(&self) -> Result<bool> {
         let mut conn = self.db().acquire().await?;
         Ok(metadata::requires_local_secret_for_reading(&mut conn).await?)
@@ -283,9 +468,10 @@ impl Repository {
         change: AccessChange,
     ) -> Result<()> {
         let local = match &change {
-            AccessChange::Enable(Some(local_secret)) => {
-                Some(metadata::secret_to_key_and_salt(local_secret))
-            }
+            AccessChange::Enable(Some(local_secret)) => Some(metadata::secret_to_key_and_salt(
+                local_secret,
+                &metadata::get_kdf_params(tx).await?,
+            )),
             AccessChange::Enable(None) => None,
             AccessChange::Disable => {
                 metadata::remove_read_key(tx).await?;
@@ -315,9 +501,10 @@ impl Repository {
         change: AccessChange,
     ) -> Result<()> {
         let local = match &change {
-            AccessChange::Enable(Some(local_secret)) => {
-                Some(metadata::secret_to_key_and_salt(local_secret))
-            }
+            AccessChange::Enable(Some(local_secret)) => Some(metadata::secret_to_key_and_salt(
+                local_secret,
+                &metadata::get_kdf_params(tx).await?,
+            )),
             AccessChange::Enable(None) => None,
             AccessChange::Disable => {
                 metadata::remove_write_key(tx).await?;
@@ -342,6 +529,28 @@ impl Repository {
         Ok(())
     }
 
+    /// Changes the local secret protecting the access keys stored in this repository from `old` to
+    /// `new` (e.g., to switch from a password to a secret key).
+    ///
+    /// Only the local encryption of the stored keys changes. The keys themselves (and thus the
+    /// repository id, the share tokens and the encryption of the blocks) stay the same, so nothing
+    /// needs to be re-encrypted and syncing with peers is not affected.
+    ///
+    /// If the read and write keys are protected by different secrets, only the ones protected by
+    /// `old` are changed. Keys that are not protected by any secret are left unchanged. Returns
+    /// `PermissionDenied` if `old` doesn't unlock any key.
+    pub async fn rekey(&self, old: LocalSecret, new: SetLocalSecret) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+
+        if !metadata::rekey(&mut tx, &old, &new).await? {
+            return Err(Error::PermissionDenied);
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     /// Gets the current credentials of this repository.
     ///
     /// See also [set_credentials].
@@ -363,6 +572,59 @@ impl Repository {
             .access_mode()
     }
 
+    /// Checks whether the given share token grants access to this repository, without opening it
+    /// with the token. Useful to fail early when the token is for a different repository. The
+    /// token matches if it's for the same repository id and its read key (if it has one) is the
+    /// one this repository is encrypted with. The write keys don't need to be checked because the
+    /// repository id is derived from them. If the read key of this repository is not known (blind
+    /// mode), the token's read key is verified against the locally stored snapshots instead. If
+    /// there are none yet, this can't be decided and the token is assumed to match.
+    ///
+    /// No secrets (neither the token's nor the repository's) are revealed by this.
+    pub async fn token_matches(&self, token: &ShareToken) -> Result<bool> {
+        let (id, own_read_key) = {
+            let credentials = self.shared.credentials.read().unwrap();
+            (
+                *credentials.secrets.id(),
+                credentials.secrets.read_key().cloned(),
+            )
+        };
+
+        if *token.id() != id {
+            return Ok(false);
+        }
+
+        // Blind token consists of the repository id only.
+        let Some(read_key) = token.secrets().read_key() else {
+            return Ok(true);
+        };
+
+        if let Some(own_read_key) = own_read_key {
+            return Ok(*read_key == own_read_key);
+        }
+
+        // The locator of the root directory is encoded with the read key, so it's found only if
+        // the key is correct. This doesn't require the blocks themselves, only the index.
+        let encoded_locator = Locator::head(BlobId::ROOT).encode(read_key);
+
+        let mut tx = self.shared.vault.store().begin_read().await?;
+        let root_nodes: Vec<_> = tx.load_root_nodes().try_collect().await?;
+
+        if root_nodes.is_empty() {
+            return Ok(true);
+        }
+
+        for root_node in &root_nodes {
+            match tx.find_block_at(root_node, &encoded_locator).await {
+                Ok(_) => return Ok(true),
+                Err(store::Error::LocatorNotFound) => (),
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Ok(false)
+    }
+
     /// Switches the repository to the given mode.
     ///
     /// The actual mode the repository gets switched to is the higher of the current access mode
@@ -492,179 +754,920 @@ impl Repository {
         self.shared.vault.quota().await
     }
 
+    /// Get how much more data can be stored in this repository before exceeding the quota or
+    /// `None` if no quota is set. Note the free space of the underlying disk is not considered.
+    pub async fn available_space(&self) -> Result<Option<StorageSize>> {
+        let Some(quota) = self.quota().await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(quota.saturating_sub(self.size().await?)))
+    }
+
+    /// Checks whether a new file of `bytes` bytes would fit into the quota. Only the blocks of the
+    /// file itself are counted, not the blocks of the directories that would need to be updated,
+    /// so the check is not exact when close to the limit. The free space of the underlying disk is
+    /// not considered.
+    pub async fn can_store(&self, bytes: u64) -> Result<bool> {
+        let Some(available) = self.available_space().await? else {
+            return Ok(true);
+        };
+
+        let blocks = bytes
+            .saturating_add(HEADER_SIZE as u64)
+            .div_ceil(BLOCK_SIZE as u64);
+
+        Ok(StorageSize::from_blocks(blocks) <= available)
+    }
+
     /// Set the duration after which blocks start to expire (are deleted) when not used. Use `None`
-    /// to disable expiration. Default is `None`.
+    /// to disable expiration. Default is `None`. This is a shorthand for
+    /// [`Self::set_block_expiration_policy`] with [`ExpirationPolicy::TimeBased`] and so it
+    /// replaces any size limit previously set.
     pub async fn set_block_expiration(&self, block_expiration: Option<Duration>) -> Result<()> {
+        self.set_block_expiration_policy(block_expiration.map(ExpirationPolicy::TimeBased))
+            .await
+    }
+
+    /// Get the block expiration duration. `None` means block expiration is not set or only the
+    /// size limit is (see [`Self::block_expiration_policy`]).
+    pub async fn block_expiration(&self) -> Option<Duration> {
+        self.block_expiration_policy()
+            .await
+            .and_then(|policy| policy.expiration_time())
+    }
+
+    /// Set when blocks expire (are deleted) to free space: after not being used for some time,
+    /// when their total size exceeds a limit, or both (see [`ExpirationPolicy`]). Expired blocks
+    /// are downloaded again from peers when needed. Use `None` to disable expiration. Default is
+    /// `None`.
+    pub async fn set_block_expiration_policy(
+        &self,
+        policy: Option<ExpirationPolicy>,
+    ) -> Result<()> {
         {
             let mut tx = self.db().begin_write().await?;
-            metadata::block_expiration::set(&mut tx, block_expiration).await?;
+            metadata::block_expiration::set(&mut tx, policy).await?;
             tx.commit().await?;
         }
-        self.shared
-            .vault
-            .set_block_expiration(block_expiration)
-            .await
+        self.shared.vault.set_block_expiration(policy).await
     }
 
-    /// Get the block expiration duration. `None` means block expiration is not set.
-    pub async fn block_expiration(&self) -> Option<Duration> {
+    /// Get the block expiration policy. `None` means block expiration is not set.
+    pub async fn block_expiration_policy(&self) -> Option<ExpirationPolicy> {
         self.shared.vault.block_expiration().await
     }
 
-    /// Get the total size of the data stored in this repository.
-    pub async fn size(&self) -> Result<StorageSize> {
-        self.shared.vault.size().await
-    }
+    /// Set the maximum number of older snapshots of each branch to keep around as fallback while
+    /// the latest snapshot is not yet fully downloaded. Any older snapshots beyond this limit are
+    /// pruned even if they could still serve as fallback. Use `None` to keep all of them. Default
+    /// is `None`.
+    pub async fn set_max_fallback_snapshots(&self, max: Option<usize>) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::max_fallback_snapshots::set(&mut tx, max).await?;
+        tx.commit().await?;
 
-    pub fn handle(&self) -> RepositoryHandle {
-        RepositoryHandle {
-            vault: self.shared.vault.clone(),
-        }
+        Ok(())
     }
 
-    pub async fn get_read_password_salt(&self) -> Result<PasswordSalt> {
-        let mut tx = self.db().begin_write().await?;
-        Ok(metadata::get_password_salt(&mut tx, metadata::KeyType::Read).await?)
+    /// Get the maximum number of fallback snapshots per branch or `None` if not limited.
+    pub async fn max_fallback_snapshots(&self) -> Result<Option<usize>> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::max_fallback_snapshots::get(&mut conn).await?)
     }
 
-    pub async fn get_write_password_salt(&self) -> Result<PasswordSalt> {
+    /// Set the maximum number of versions of any single file to keep among the snapshots of each
+    /// branch (the latest one and the older ones retained as fallback, see
+    /// [`Self::set_max_fallback_snapshots`]). During pruning, the older snapshots of a branch are
+    /// examined from the most recent one and once a snapshot would bring the number of distinct
+    /// versions of some file over the limit, it's removed together with all the even older ones,
+    /// even if they could still serve as fallback. This trades the depth of the available history
+    /// for space.
+    ///
+    /// To not compromise syncing, the latest snapshot of a branch and the most recent of the older
+    /// ones are never removed because of this limit, so the files whose latest version hasn't been
+    /// fully downloaded yet remain readable in their previous version. The older snapshots of the
+    /// branches this replica can't read (in blind mode) are not affected. Use `None` to disable
+    /// the limit. Default is `None`.
+    pub async fn set_max_versions_per_file(&self, max: Option<usize>) -> Result<()> {
         let mut tx = self.db().begin_write().await?;
-        Ok(metadata::get_password_salt(&mut tx, metadata::KeyType::Write).await?)
-    }
+        max_versions_per_file::set(&mut tx, max).await?;
+        tx.commit().await?;
 
-    /// Get the state monitor node of this repository.
-    pub fn monitor(&self) -> &StateMonitor {
-        self.shared.vault.monitor.node()
+        Ok(())
     }
 
-    /// Looks up an entry by its path. The path must be relative to the repository root.
-    /// If the entry exists, returns its `JointEntryType`, otherwise returns `EntryNotFound`.
-    pub async fn lookup_type<P: AsRef<Utf8Path>>(&self, path: P) -> Result<EntryType> {
-        match path::decompose(path.as_ref()) {
-            Some((parent, name)) => {
-                let parent = self.open_directory(parent).await?;
-                Ok(parent.lookup_unique(name)?.entry_type())
-            }
-            None => Ok(EntryType::Directory),
-        }
+    /// Get the maximum number of versions kept per file or `None` if not limited.
+    pub async fn max_versions_per_file(&self) -> Result<Option<usize>> {
+        let mut conn = self.db().acquire().await?;
+        Ok(max_versions_per_file::get(&mut conn).await?)
     }
 
-    /// Opens a file at the given path (relative to the repository root)
-    pub async fn open_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
-        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+    /// Set how long to keep the tombstones (markers of removed entries) in the local branch.
+    /// `None` (the default) keeps them forever.
+    ///
+    /// A tombstone is needed until every replica learns about the removal. If it's purged before
+    /// that, a replica which still has the entry makes it reappear on the next sync (the removed
+    /// file or directory is "resurrected"). So a tombstone is purged only if none of the remote
+    /// branches known to this replica still has a version of the entry the tombstone supersedes
+    /// and either all of them (if there are any) have the tombstone too, or it's been around for
+    /// at least `ttl`.
+    /// This replica can't know about replicas it hasn't synced with, so the TTL should be set
+    /// well above the longest time any replica is expected to stay offline. The age of a
+    /// tombstone is measured from when it's first seen after the repository is opened.
+    ///
+    /// Purging happens as part of the regular background maintenance and requires write access.
+    pub async fn set_tombstone_ttl(&self, ttl: Option<Duration>) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::tombstone_ttl::set(&mut tx, ttl).await?;
+        tx.commit().await?;
 
-        self.cd(parent)
-            .await?
-            .lookup_unique(name)?
-            .file()?
-            .open()
-            .await
+        Ok(())
     }
 
-    /// Open a specific version of the file at the given path.
-    pub async fn open_file_version<P: AsRef<Utf8Path>>(
-        &self,
-        path: P,
-        branch_id: &PublicKey,
-    ) -> Result<File> {
-        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+    /// Get the tombstone TTL or `None` if tombstones are kept forever.
+    pub async fn tombstone_ttl(&self) -> Result<Option<Duration>> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::tombstone_ttl::get(&mut conn).await?)
+    }
 
-        self.cd(parent)
-            .await?
-            .lookup_version(name, branch_id)?
-            .open()
-            .await
+    /// Set how many unreachable blocks the garbage collector removes in a single database
+    /// transaction. Smaller batches keep the write transactions short so other operations (e.g.,
+    /// writing files or storing received blocks) don't have to wait for long, at the cost of the
+    /// collection taking longer overall. Use `None` to reset it to the default (32). Zero is
+    /// treated as one.
+    pub async fn set_gc_batch_size(&self, size: Option<usize>) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::gc_batch_size::set(&mut tx, size).await?;
+        tx.commit().await?;
+
+        Ok(())
     }
 
-    /// Opens a directory at the given path (relative to the repository root)
-    pub async fn open_directory<P: AsRef<Utf8Path>>(&self, path: P) -> Result<JointDirectory> {
-        self.cd(path).await
+    /// Get the garbage collection batch size.
+    pub async fn gc_batch_size(&self) -> Result<usize> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::gc_batch_size::get(&mut conn).await?)
     }
 
-    /// Creates a new file at the given path.
-    pub async fn create_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
-        let file = self
-            .local_branch()?
-            .ensure_file_exists(path.as_ref())
-            .await?;
+    /// Enables or disables automatic merging of remote branches into the local one. Default is
+    /// enabled.
+    ///
+    /// When disabled, the remote snapshots are still received, stored and readable but the local
+    /// branch is never updated to absorb them in the background. Use [`Self::merge`] to integrate
+    /// them manually. In the meantime the repository is presented as the union of all the branches:
+    /// files whose remote version is strictly newer show the remote content, while concurrently
+    /// modified files keep showing up as separate entries with disambiguated names, just like
+    /// unresolved conflicts (see [`JointDirectory::entries`]). Modifying a file still forks its
+    /// current version into the local branch.
+    ///
+    /// Re-enabling it merges the remote branches right away.
+    pub async fn set_auto_merge(&self, enabled: bool) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        auto_merge::set(&mut tx, enabled).await?;
+        tx.commit().await?;
 
-        Ok(file)
-    }
+        if enabled && self.credentials().secrets.can_write() {
+            self.merge().await?;
+        }
 
-    /// Creates a new directory at the given path.
-    pub async fn create_directory<P: AsRef<Utf8Path>>(&self, path: P) -> Result<Directory> {
-        let dir = self
-            .local_branch()?
-            .ensure_directory_exists(path.as_ref())
-            .await?;
+        Ok(())
+    }
 
-        Ok(dir)
+    /// Is automatic merging of remote branches enabled?
+    pub async fn is_auto_merge_enabled(&self) -> Result<bool> {
+        let mut conn = self.db().acquire().await?;
+        Ok(auto_merge::get(&mut conn).await?)
     }
 
-    /// Removes the file or directory (must be empty) and flushes its parent directory.
-    pub async fn remove_entry<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
-        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::OperationNotSupported)?;
-        let mut parent = self.cd(parent).await?;
-        parent.remove_entry(name).await?;
+    /// Enables or disables syncing the directories before the file contents. When enabled, the
+    /// blocks of the directories are requested from the peers before the blocks of the files, so
+    /// that the whole directory tree of a newly synced repository becomes browsable quickly while
+    /// the file contents are still being downloaded. When disabled, the blocks are requested in
+    /// unspecified order. Has no effect in blind replicas, which can't tell directories and files
+    /// apart. Default is disabled.
+    pub async fn set_sync_directories_first(&self, enabled: bool) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        sync_directories_first::set(&mut tx, enabled).await?;
+        tx.commit().await?;
 
         Ok(())
     }
 
-    /// Removes the file or directory (including its content) and flushes its parent directory.
-    pub async fn remove_entry_recursively<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
-        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::OperationNotSupported)?;
-        let mut parent = self.cd(parent).await?;
-        parent.remove_entry_recursively(name).await?;
+    /// Are the directories synced before the file contents?
+    pub async fn is_sync_directories_first_enabled(&self) -> Result<bool> {
+        let mut conn = self.db().acquire().await?;
+        Ok(sync_directories_first::get(&mut conn).await?)
+    }
 
-        Ok(())
+    /// Merges the remote branches into the local one. This is done automatically in the background
+    /// unless disabled with [`Self::set_auto_merge`]. Files modified concurrently are kept as
+    /// separate versions (conflicts). If a merge is already in progress (manual or automatic),
+    /// waits for it to finish first. Requires write access.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id()))]
+    pub async fn merge(&self) -> Result<()> {
+        if !self.credentials().secrets.can_write() {
+            return Err(Error::PermissionDenied);
+        }
+
+        worker::merge::run(&self.shared, &self.local_branch()?).await
     }
 
-    /// Moves (renames) an entry from the source path to the destination path.
-    /// If both source and destination refer to the same entry, this is a no-op.
-    pub async fn move_entry<S: AsRef<Utf8Path>, D: AsRef<Utf8Path>>(
-        &self,
-        src_dir_path: S,
-        src_name: &str,
-        dst_dir_path: D,
-        dst_name: &str,
-    ) -> Result<()> {
+    /// Excludes the current snapshot of the given remote branch from merging, so that the local
+    /// branch can keep absorbing the changes from the other branches. Meant for recovering from a
+    /// stalled merge (see [`Payload::MergeStalled`]). The exclusion is lifted as soon as the branch
+    /// advances to a new snapshot. It is kept only in memory and so it's also lifted when the
+    /// repository is reopened. Requires write access.
+    #[instrument(parent = self.span(), skip(self), fields(id = next_span_id()))]
+    pub async fn skip_merge(&self, writer_id: PublicKey) -> Result<()> {
+        if !self.credentials().secrets.can_write() {
+            return Err(Error::PermissionDenied);
+        }
+
         let local_branch = self.local_branch()?;
-        let src_joint_dir = self.cd(src_dir_path).await?;
 
-        // If the src is in a remote branch, need to merge it into the local one first:
-        let (mut src_dir, src_name, src_type) = match src_joint_dir.lookup_unique(src_name)? {
-            JointEntryRef::File(entry) => {
-                let src_name = entry.name().to_string();
+        if writer_id == *local_branch.id() {
+            return Err(Error::InvalidArgument);
+        }
 
-                let mut file = entry.open().await?;
-                file.fork(local_branch.clone()).await?;
+        let root_node = self
+            .shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .load_root_node(&writer_id, RootNodeFilter::Any)
+            .await?;
 
-                (file.parent().await?, Cow::Owned(src_name), EntryType::File)
-            }
-            JointEntryRef::Directory(entry) => {
-                let mut dir_to_move = entry
-                    .open_with(MissingVersionStrategy::Skip, DirectoryFallback::Disabled)
-                    .await?;
-                let dir_to_move = dir_to_move.merge().await?;
+        tracing::warn!(
+            vv = ?root_node.proof.version_vector,
+            hash = ?root_node.proof.hash,
+            "Skipping merge of the branch snapshot",
+        );
 
-                let src_dir = dir_to_move
-                    .parent()
-                    .await?
-                    .ok_or(Error::OperationNotSupported /* can't move root */)?;
+        self.shared
+            .merge_stalls
+            .lock()
+            .unwrap()
+            .skip(writer_id, root_node.proof.hash);
 
-                (src_dir, Cow::Borrowed(src_name), EntryType::Directory)
-            }
-        };
+        if self.is_auto_merge_enabled().await? {
+            worker::merge::run(&self.shared, &local_branch).await?;
+        }
 
-        let src_entry = src_dir.lookup(&src_name)?.clone_data();
+        Ok(())
+    }
 
-        let mut dst_joint_dir = self.cd(&dst_dir_path).await?;
-        let dst_dir = dst_joint_dir
-            .local_version_mut()
-            .ok_or(Error::PermissionDenied)?;
+    /// Enables or disables compression of newly written blocks. Already stored blocks are not
+    /// affected and blocks written either way can always be read. Default is disabled.
+    ///
+    /// Note that the stored size of a compressed block, which is visible also to replicas without
+    /// read access, reveals roughly how compressible its content is. The sizes are padded to a few
+    /// fixed buckets to limit this, but it's still something uncompressed blocks don't reveal.
+    pub async fn set_block_compression_enabled(&self, enabled: bool) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::block_compression::set(&mut tx, enabled).await?;
+        tx.commit().await?;
+
+        self.shared.branch_shared.compression.set_enabled(enabled);
+
+        Ok(())
+    }
+
+    /// Is compression of newly written blocks enabled?
+    pub fn is_block_compression_enabled(&self) -> bool {
+        self.shared.branch_shared.compression.is_enabled()
+    }
+
+    /// Sets the maximum size of files in bytes. Writes that would make a file larger than this fail
+    /// with `Error::FileTooLarge`. Already existing larger files are not affected, except they can't
+    /// grow any further. Use `None` to disable the limit. Default is `None`.
+    pub async fn set_max_file_size(&self, max: Option<u64>) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::max_file_size::set(&mut tx, max).await?;
+        tx.commit().await?;
+
+        self.shared.branch_shared.max_file_size.set(max);
+
+        Ok(())
+    }
+
+    /// Get the maximum size of files in bytes or `None` if not limited.
+    pub fn max_file_size(&self) -> Option<u64> {
+        self.shared.branch_shared.max_file_size.get()
+    }
+
+    /// Sets the maximum number of decrypted blocks kept in memory by all the open files and
+    /// directories of this repository together. When the limit is reached, reading or writing a
+    /// part of a file that is not in memory waits until some other file releases its blocks (by
+    /// being flushed or closed). Not persisted. Default is 4096 (128 MiB).
+    pub fn set_block_buffer_limit(&self, limit: usize) {
+        self.shared.branch_shared.buffer_pool.set_capacity(limit);
+    }
+
+    /// Get the maximum number of decrypted blocks kept in memory.
+    pub fn block_buffer_limit(&self) -> usize {
+        self.shared.branch_shared.buffer_pool.capacity()
+    }
+
+    /// Number of decrypted blocks currently kept in memory. Also reported as the
+    /// "block buffers in use" metric.
+    pub fn block_buffers_in_use(&self) -> usize {
+        self.shared.branch_shared.buffer_pool.in_use()
+    }
+
+    /// Sets the maximum length (in bytes, after the normalization if enabled) of the names of newly
+    /// created files and directories. Creating an entry with a longer name fails with
+    /// `Error::NameTooLong`. Existing entries (including those received from other replicas) are
+    /// not affected. Use `None` to disable the limit. Default is `None`.
+    pub async fn set_max_name_length(&self, max: Option<usize>) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::max_name_length::set(&mut tx, max).await?;
+        tx.commit().await?;
+
+        self.shared.branch_shared.name_policy.set_max_len(max);
+
+        Ok(())
+    }
+
+    /// Get the maximum length of entry names in bytes or `None` if not limited.
+    pub fn max_name_length(&self) -> Option<usize> {
+        self.shared.branch_shared.name_policy.max_len()
+    }
+
+    /// Enables or disables normalizing the names of newly created files and directories to the
+    /// Unicode Normalization Form C (NFC). This makes the names consistent across platforms that
+    /// encode them differently (e.g., macOS uses the decomposed form). When enabled, looking up an
+    /// entry by a non-normalized name also finds the entry with the normalized one. Existing
+    /// entries are not renamed. Disabled by default.
+    pub async fn set_name_normalization_enabled(&self, enabled: bool) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::name_normalization::set(&mut tx, enabled).await?;
+        tx.commit().await?;
+
+        self.shared
+            .branch_shared
+            .name_policy
+            .set_normalization_enabled(enabled);
+
+        Ok(())
+    }
+
+    /// Is normalization of entry names enabled?
+    pub fn is_name_normalization_enabled(&self) -> bool {
+        self.shared
+            .branch_shared
+            .name_policy
+            .is_normalization_enabled()
+    }
+
+    /// Sets how a file and a directory with the same name in different branches (e.g., created
+    /// concurrently by different writers) are presented when listing and looking up entries. See
+    /// [`TypeConflictPolicy`] for details. Default is `TypeConflictPolicy::KeepBoth`.
+    pub async fn set_type_conflict_policy(&self, policy: TypeConflictPolicy) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::type_conflict_policy::set(&mut tx, policy).await?;
+        tx.commit().await?;
+
+        self.shared.branch_shared.type_conflict.set(policy);
+
+        Ok(())
+    }
+
+    pub fn type_conflict_policy(&self) -> TypeConflictPolicy {
+        self.shared.branch_shared.type_conflict.get()
+    }
+
+    /// Sets the access mode of the share tokens created without explicitly specifying one (see
+    /// [`Self::share_access`]). Stored only locally. Default is `AccessMode::Write`.
+    pub async fn set_default_share_access(&self, mode: AccessMode) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::share_access::set_default(&mut tx, mode).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn default_share_access(&self) -> Result<AccessMode> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::share_access::get_default(&mut conn).await?)
+    }
+
+    /// Sets the highest access mode of the created share tokens (see [`Self::share_access`]),
+    /// regardless of the requested one. Stored only locally. Default is `AccessMode::Write`
+    /// (no limit).
+    pub async fn set_max_share_access(&self, mode: AccessMode) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::share_access::set_max(&mut tx, mode).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn max_share_access(&self) -> Result<AccessMode> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::share_access::get_max(&mut conn).await?)
+    }
+
+    /// Returns the access mode a new share token should have when `requested` is asked for:
+    /// `requested` or, if `None`, the default one (see [`Self::set_default_share_access`]),
+    /// clamped to the maximum one (see [`Self::set_max_share_access`]). Note the mode of the token
+    /// is further limited by the secrets it's created from (see `AccessSecrets::with_mode`).
+    pub async fn share_access(&self, requested: Option<AccessMode>) -> Result<AccessMode> {
+        let mut conn = self.db().acquire().await?;
+
+        let mode = match requested {
+            Some(mode) => mode,
+            None => metadata::share_access::get_default(&mut conn).await?,
+        };
+
+        Ok(mode.min(metadata::share_access::get_max(&mut conn).await?))
+    }
+
+    /// Returns the secrets to create a share token from. If `local_secret` is `None`, the current
+    /// secrets of this repository are used, otherwise the highest ones `local_secret` unlocks. They
+    /// are then limited to the mode returned by [`Self::share_access`] for `requested`. Use this
+    /// instead of [`Self::secrets`] whenever the secrets are going to be shared with other peers,
+    /// so the max share access mode is enforced.
+    pub async fn share_secrets(
+        &self,
+        local_secret: Option<LocalSecret>,
+        requested: Option<AccessMode>,
+    ) -> Result<AccessSecrets> {
+        let mode = self.share_access(requested).await?;
+
+        let secrets = if let Some(local_secret) = local_secret {
+            self.unlock_secrets(local_secret).await?
+        } else {
+            self.secrets()
+        };
+
+        Ok(secrets.with_mode(mode))
+    }
+
+    /// Sets the human-friendly display name of this repository. The name is stored only locally
+    /// (it's not shared with other replicas) and is independent of the name the repository is
+    /// linked under in the network. Empty name removes it.
+    pub async fn set_name(&self, name: String) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+
+        if name.is_empty() {
+            metadata::name::remove(&mut tx).await?;
+        } else {
+            metadata::name::set(&mut tx, &name).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Gets the display name of this repository, if one has been set.
+    pub async fn name(&self) -> Result<Option<String>> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::name::get(&mut conn).await?)
+    }
+
+    /// Get the total size of the data stored in this repository.
+    pub async fn size(&self) -> Result<StorageSize> {
+        self.shared.vault.size().await
+    }
+
+    /// Gets the logical, deduplicated and physical sizes of this repository (see
+    /// [`RepositorySizes`]). This walks the whole directory tree so it can take a while on large
+    /// repositories.
+    pub async fn sizes(&self) -> Result<RepositorySizes> {
+        let logical = sizes::logical_size(self).await?;
+        let deduplicated = self
+            .shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .count_block_ids()
+            .await?;
+        let physical_db_file = db::file_size(self.db()).await?;
+
+        Ok(RepositorySizes {
+            logical: StorageSize::from_bytes(logical),
+            deduplicated: StorageSize::from_bytes(deduplicated * BLOCK_SIZE as u64),
+            physical_db_file: StorageSize::from_bytes(physical_db_file),
+        })
+    }
+
+    /// Attaches custom fields to the tracing span of this repository. The public operations of the
+    /// repository (and the tasks belonging to it) run inside this span so the fields can be used
+    /// by tracing subscribers to correlate them. Replaces any previously attached fields.
+    pub fn with_span_fields<I, K, V>(self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: fmt::Display,
+        V: fmt::Display,
+    {
+        let fields = fields
+            .into_iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        self.span().record("fields", fields.as_str());
+        self
+    }
+
+    pub fn handle(&self) -> RepositoryHandle {
+        RepositoryHandle {
+            vault: self.shared.vault.clone(),
+            metadata_key: self.secrets().read_key().map(|key| {
+                cipher::SecretKey::derive_from_key(key.as_array(), b"ouisync network metadata")
+            }),
+        }
+    }
+
+    /// Params of the function used to derive the local keys from the local passwords of this
+    /// repository. Use them when deriving the keys outside of the repository (e.g. with
+    /// `SecretKey::derive_from_password_with_params`).
+    pub async fn kdf_params(&self) -> Result<KdfParams> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::get_kdf_params(&mut conn).await?)
+    }
+
+    pub async fn get_read_password_salt(&self) -> Result<PasswordSalt> {
+        let mut tx = self.db().begin_write().await?;
+        Ok(metadata::get_password_salt(&mut tx, metadata::KeyType::Read).await?)
+    }
+
+    pub async fn get_write_password_salt(&self) -> Result<PasswordSalt> {
+        let mut tx = self.db().begin_write().await?;
+        Ok(metadata::get_password_salt(&mut tx, metadata::KeyType::Write).await?)
+    }
+
+    /// Get the state monitor node of this repository.
+    pub fn monitor(&self) -> &StateMonitor {
+        self.shared.vault.monitor.node()
+    }
+
+    /// Looks up an entry by its path. The path must be relative to the repository root.
+    /// If the entry exists, returns its `JointEntryType`, otherwise returns `EntryNotFound`.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn lookup_type<P: AsRef<Utf8Path>>(&self, path: P) -> Result<EntryType> {
+        match path::decompose(path.as_ref()) {
+            Some((parent, name)) => {
+                let parent = self.open_directory(parent).await?;
+                Ok(parent.lookup_unique(name)?.entry_type())
+            }
+            None => Ok(EntryType::Directory),
+        }
+    }
+
+    /// Returns the metadata of the file or directory at the given path: its type, length, owner,
+    /// version vector and how much of it is available locally. Everything, including the lookup
+    /// of the entry, is read from a single snapshot of the repository (see `read_snapshot`). Fails
+    /// with `EntryNotFound` if there is no such entry or with `Error::Store(BlockNotFound)` if the
+    /// first block of the file hasn't been downloaded yet.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn stat<P: AsRef<Utf8Path>>(&self, path: P) -> Result<EntryMetadata> {
+        stat::stat(self, path.as_ref()).await
+    }
+
+    /// Opens a file at the given path (relative to the repository root)
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn open_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+
+        self.cd(parent)
+            .await?
+            .lookup_unique(name)?
+            .file()?
+            .open()
+            .await
+    }
+
+    /// Reads the whole content of the file at the given path in one go, without opening it as a
+    /// `File`. Fails with `Error::FileTooLarge` if the file is longer than `max_len` bytes. All the
+    /// blocks of the file are read in a single store transaction which makes this considerably
+    /// faster than `open_file` followed by `read_to_end` for small files (e.g., configs) which
+    /// span only one or a few blocks. Not suitable for big files because their whole content is
+    /// held in memory.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref(), max_len))]
+    pub async fn read_small<P: AsRef<Utf8Path>>(&self, path: P, max_len: u64) -> Result<Vec<u8>> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+
+        let dir = self.cd(parent).await?;
+        let entry = dir.lookup_unique(name)?.file()?;
+        let branch = entry.branch().clone();
+        let blob_id = *entry.blob_id();
+
+        let mut tx = self.shared.vault.store().begin_read().await?;
+        let root_node = tx.load_root_node(branch.id(), RootNodeFilter::Any).await?;
+        let mut blob = Blob::open_at(&mut tx, &root_node, branch, blob_id).await?;
+
+        if blob.len() > max_len {
+            return Err(Error::FileTooLarge);
+        }
+
+        blob.read_to_end_at(&mut tx, &root_node).await
+    }
+
+    /// Opens the file at the given path for reading the bytes in the range `[start, end)`. The
+    /// returned reader reaches EOF at `end` (or at the end of the file if it's shorter). Only the
+    /// blocks of the file that overlap the range are read. `missing_blocks` determines what
+    /// happens when some of those blocks haven't been downloaded yet.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref(), start = start, end = end))]
+    pub async fn open_range<P: AsRef<Utf8Path>>(
+        &self,
+        path: P,
+        start: u64,
+        end: u64,
+        missing_blocks: MissingBlockPolicy,
+    ) -> Result<FileRange> {
+        // Subscribe before opening the file so no block received in the meantime is missed.
+        let waiter = match missing_blocks {
+            MissingBlockPolicy::Fail => None,
+            MissingBlockPolicy::Wait => Some(BlockWaiter::new(
+                self.shared.vault.block_tracker.clone(),
+                self.subscribe(),
+            )),
+        };
+
+        let file = self.open_file(path).await?;
+
+        FileRange::new(file, start, end, waiter)
+    }
+
+    /// Open a specific version of the file at the given path.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref(), ?branch_id))]
+    pub async fn open_file_version<P: AsRef<Utf8Path>>(
+        &self,
+        path: P,
+        branch_id: &PublicKey,
+    ) -> Result<File> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+
+        self.cd(parent)
+            .await?
+            .lookup_version(name, branch_id)?
+            .open()
+            .await
+    }
+
+    /// Opens a directory at the given path (relative to the repository root)
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn open_directory<P: AsRef<Utf8Path>>(&self, path: P) -> Result<JointDirectory> {
+        self.cd(path).await
+    }
+
+    /// Opens a directory at the given path and keeps it up to date. Use
+    /// [`WatchedDirectory::changed`] to wait for its content to change (e.g., to refresh a file
+    /// browser) and [`WatchedDirectory::entries`] to get the current entries.
+    pub async fn open_directory_watched<P: AsRef<Utf8Path>>(
+        &self,
+        path: P,
+    ) -> Result<WatchedDirectory<'_>> {
+        WatchedDirectory::open(self, path.as_ref()).await
+    }
+
+    /// Creates a new file at the given path.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn create_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
+        let file = self
+            .local_branch()?
+            .ensure_file_exists(path.as_ref())
+            .await?;
+
+        Ok(file)
+    }
+
+    /// Atomically replaces the content of the file at the given path with `data`, creating the
+    /// file (but not its parent directory) if it doesn't exist.
+    ///
+    /// Unlike writing into an opened file, which might flush several times for large writes, the
+    /// whole content is written into a new blob and the file entry is switched to it in a single
+    /// snapshot. Readers thus see either the old or the new content but never a partial one. The
+    /// new content supersedes all the current versions of the file, including the remote ones.
+    #[instrument(parent = self.span(), skip(self, data), fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn write_atomic<P: AsRef<Utf8Path>>(&self, path: P, data: &[u8]) -> Result<()> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+
+        let mut merge = VersionVector::new();
+
+        for entry in self.cd(parent).await?.lookup(name) {
+            match entry {
+                JointEntryRef::File(entry) => merge.merge(entry.version_vector()),
+                JointEntryRef::Directory(_) => return Err(Error::EntryIsDirectory),
+            }
+        }
+
+        self.local_branch()?
+            .ensure_directory_exists(parent)
+            .await?
+            .replace_file(name.to_owned(), &merge, data)
+            .await
+    }
+
+    /// Writes `data` into the file at the given path and flushes it, replacing its previous content
+    /// if it already exists. Missing parent directories are created. Returns the final length of
+    /// the file.
+    ///
+    /// This is a shorthand for creating (or opening) the file, writing to it and flushing it which
+    /// is convenient for small files. Large content might get flushed several times during the
+    /// write so use [`Self::write_atomic`] if readers must never observe a partially written file.
+    #[instrument(parent = self.span(), skip(self, data), fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn write_file<P: AsRef<Utf8Path>>(&self, path: P, data: &[u8]) -> Result<u64> {
+        let path = path.as_ref();
+
+        let mut file = match self.open_file(path).await {
+            Ok(mut file) => {
+                file.fork(self.ensure_local_branch().await?).await?;
+                file.truncate(0)?;
+                file
+            }
+            Err(Error::EntryNotFound) => self.create_file(path).await?,
+            Err(error) => return Err(error),
+        };
+
+        file.write_all(data).await?;
+        file.flush().await?;
+
+        Ok(file.len())
+    }
+
+    /// Creates a new file at `new_path` with the same content as the existing file at
+    /// `existing_path` without copying the content. Both files then share the same blocks, which
+    /// makes this cheap even for large files and useful for deduplication. Fails with
+    /// `EntryExists` if there already is a file or directory at `new_path`. Missing parent
+    /// directories of `new_path` are created.
+    ///
+    /// Unlike a hard link on a regular filesystem, the two files are not permanently tied
+    /// together. Modifying either of them is copy-on-write: only the modified blocks are written
+    /// anew and only for the modified file, so the two diverge from that point on. Consequently
+    /// the new file is a separate entry with its own version vector (as if it was newly created)
+    /// and modifying one file doesn't bump the version vector of the other. For the same reason
+    /// the number of links of every file (as reported by the VFS) remains 1.
+    #[instrument(
+        parent = self.span(),
+        skip_all,
+        fields(id = next_span_id(), existing_path = %existing_path.as_ref(), new_path = %new_path.as_ref())
+    )]
+    pub async fn link<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(
+        &self,
+        existing_path: P,
+        new_path: Q,
+    ) -> Result<()> {
+        let (src_parent, src_name) =
+            path::decompose(existing_path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+        let (dst_parent, dst_name) =
+            path::decompose(new_path.as_ref()).ok_or(Error::EntryExists)?;
+
+        let src_dir = self.cd(src_parent).await?;
+        let src_entry = src_dir.lookup_unique(src_name)?.file()?;
+        let src_branch = src_entry.branch().clone();
+        let src_blob_id = *src_entry.blob_id();
+
+        self.local_branch()?
+            .ensure_directory_exists(dst_parent)
+            .await?
+            .link_file(dst_name.to_owned(), &src_branch, src_blob_id)
+            .await
+    }
+
+    /// Creates a new directory at the given path.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn create_directory<P: AsRef<Utf8Path>>(&self, path: P) -> Result<Directory> {
+        let dir = self
+            .local_branch()?
+            .ensure_directory_exists(path.as_ref())
+            .await?;
+
+        Ok(dir)
+    }
+
+    /// Creates the directories at all the given paths, including any missing ancestors. Paths that
+    /// already exist are skipped. All the directories are created atomically in a single
+    /// transaction which is much faster than creating them one by one.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), paths = paths.len()))]
+    pub async fn create_dirs(&self, paths: &[Utf8PathBuf]) -> Result<()> {
+        self.local_branch()?.ensure_directories_exist(paths).await
+    }
+
+    /// Sets the length of the file at the given path. See [`Self::resize_file`] for details.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref(), len = len))]
+    pub async fn set_len<P: AsRef<Utf8Path>>(&self, path: P, len: u64) -> Result<()> {
+        let mut file = self.open_file(path).await?;
+        self.resize_file(&mut file, len).await
+    }
+
+    /// Truncates the file at the given path to zero length.
+    pub async fn truncate_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        self.set_len(path, 0).await
+    }
+
+    /// Sets the length of an already opened file. The file is first forked into the local branch
+    /// (if it's not there already), then truncated or extended with zeros to exactly `len` bytes
+    /// and finally flushed. Extending the file doesn't store the added zero blocks more than once.
+    pub async fn resize_file(&self, file: &mut File, len: u64) -> Result<()> {
+        if file.len() == len {
+            return Ok(());
+        }
+
+        file.fork(self.ensure_local_branch().await?).await?;
+        file.set_len(len).await?;
+        file.flush().await
+    }
+
+    /// Removes the file or directory (must be empty) and flushes its parent directory.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn remove_entry<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::OperationNotSupported)?;
+        let mut parent = self.cd(parent).await?;
+        parent.remove_entry(name).await?;
+
+        Ok(())
+    }
+
+    /// Removes the file or directory (including its content) and flushes its parent directory.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), path = %path.as_ref()))]
+    pub async fn remove_entry_recursively<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::OperationNotSupported)?;
+        let mut parent = self.cd(parent).await?;
+        parent.remove_entry_recursively(name).await?;
+
+        Ok(())
+    }
+
+    /// Moves (renames) an entry from the source path to the destination path.
+    /// If both source and destination refer to the same entry, this is a no-op.
+    ///
+    /// If the destination already exists, it's replaced when `overwrite` is true (following the
+    /// semantics of the libc's `rename`: a file can replace only a file and a directory only an
+    /// empty directory) or `Error::EntryExists` is returned when it's false. The move is atomic:
+    /// the entry is removed from the source and added to the destination in a single transaction
+    /// so it's never observed in both or in neither place.
+    #[instrument(
+        parent = self.span(),
+        skip_all,
+        fields(
+            id = next_span_id(),
+            src_dir_path = %src_dir_path.as_ref(),
+            src_name = src_name,
+            dst_dir_path = %dst_dir_path.as_ref(),
+            dst_name = dst_name,
+            overwrite = overwrite,
+        )
+    )]
+    pub async fn move_entry<S: AsRef<Utf8Path>, D: AsRef<Utf8Path>>(
+        &self,
+        src_dir_path: S,
+        src_name: &str,
+        dst_dir_path: D,
+        dst_name: &str,
+        overwrite: bool,
+    ) -> Result<()> {
+        let local_branch = self.local_branch()?;
+        let src_joint_dir = self.cd(src_dir_path).await?;
+
+        // If the src is in a remote branch, need to merge it into the local one first:
+        let (mut src_dir, src_name, src_type) = match src_joint_dir.lookup_unique(src_name)? {
+            JointEntryRef::File(entry) => {
+                let src_name = entry.name().to_string();
+
+                let mut file = entry.open().await?;
+                file.fork(local_branch.clone()).await?;
+
+                (file.parent().await?, Cow::Owned(src_name), EntryType::File)
+            }
+            JointEntryRef::Directory(entry) => {
+                let mut dir_to_move = entry
+                    .open_with(MissingVersionStrategy::Skip, DirectoryFallback::Disabled)
+                    .await?;
+                let dir_to_move = dir_to_move.merge().await?;
+
+                let src_dir = dir_to_move
+                    .parent()
+                    .await?
+                    .ok_or(Error::OperationNotSupported /* can't move root */)?;
+
+                (src_dir, Cow::Borrowed(src_name), EntryType::Directory)
+            }
+        };
+
+        let src_entry = src_dir.lookup(&src_name)?.clone_data();
+
+        let mut dst_joint_dir = self.cd(&dst_dir_path).await?;
+        let dst_dir = dst_joint_dir
+            .local_version_mut()
+            .ok_or(Error::PermissionDenied)?;
 
         let dst_old_entry = dst_dir.lookup(dst_name);
 
+        if !overwrite
+            && matches!(
+                dst_old_entry,
+                Ok(EntryRef::File(_) | EntryRef::Directory(_))
+            )
+        {
+            return Err(Error::EntryExists);
+        }
+
         // Emulating the behaviour of the libc's `rename` function
         // (https://www.man7.org/linux/man-pages/man2/rename.2.html)
         let dst_old_vv = match (src_type, dst_old_entry) {
@@ -697,7 +1700,7 @@ impl Repository {
             .incremented(*local_branch.id());
 
         src_dir
-            .move_entry(&src_name, src_entry, dst_dir, dst_name, dst_vv)
+            .move_entry(&src_name, src_entry, dst_dir, dst_name, dst_vv, overwrite)
             .await?;
 
         Ok(())
@@ -709,6 +1712,44 @@ impl Repository {
         self.shared.local_branch()
     }
 
+    /// Does the local branch exist? The local branch is created lazily on the first write so this
+    /// returns `false` for repos that haven't been written to yet from this device (e.g., ones
+    /// that have only ever been opened in read mode).
+    pub async fn local_branch_exists(&self) -> Result<bool> {
+        let writer_id = self.shared.credentials.read().unwrap().writer_id;
+
+        match self
+            .shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .load_root_node(&writer_id, RootNodeFilter::Any)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(store::Error::BranchNotFound) => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Returns the local branch, creating it first if it doesn't exist yet. Returns
+    /// `Error::PermissionDenied` if this repo doesn't have write access. Use this to find out
+    /// whether writes are possible before attempting them.
+    pub async fn ensure_local_branch(&self) -> Result<Branch> {
+        let branch = self.local_branch()?;
+
+        if branch.keys().write().is_none() {
+            return Err(Error::PermissionDenied);
+        }
+
+        if !self.local_branch_exists().await? {
+            branch.open_or_create_root().await?;
+        }
+
+        Ok(branch)
+    }
+
     /// Returns the branch corresponding to the given id or `Error::PermissionDenied. if this repo
     /// doesn't have at least read access.
     #[cfg(test)]
@@ -716,6 +1757,40 @@ impl Repository {
         self.shared.get_branch(id)
     }
 
+    /// Switches the repository into the "observer" mode where instead of the merged view of all
+    /// the branches, only the given remote branch is visible (or back to the normal mode if
+    /// `None`). This is useful for replicas that only want to track a specific author.
+    ///
+    /// The observer mode is available only in the blind and read modes where the replica never
+    /// creates a local branch, so nothing is ever merged or forked. Directory and file reads then
+    /// return the content of the latest snapshot of the followed branch (or the root directory is
+    /// empty if the branch doesn't exist yet). The local version vector stays empty so to track
+    /// the progress of the followed branch use `get_branch_version_vector` instead. Returns
+    /// `OperationNotSupported` in the write mode. If the repository is switched to the write mode
+    /// later, the observer mode is ignored.
+    ///
+    /// The followed branch is persisted so the observer mode survives reopening the repository.
+    /// It's also never pruned as outdated, so it stays visible even after its content has been
+    /// merged into other branches.
+    pub async fn follow_branch(&self, branch_id: Option<PublicKey>) -> Result<()> {
+        if self.access_mode() == AccessMode::Write {
+            return Err(Error::OperationNotSupported);
+        }
+
+        let mut tx = self.db().begin_write().await?;
+        metadata::followed_branch::set(&mut tx, branch_id.as_ref()).await?;
+        tx.commit().await?;
+
+        *self.shared.followed_branch.lock().unwrap() = branch_id;
+
+        Ok(())
+    }
+
+    /// Returns the branch followed in the observer mode (see [`Self::follow_branch`]), if any.
+    pub fn followed_branch(&self) -> Option<PublicKey> {
+        self.shared.followed_branch()
+    }
+
     /// Returns version vector of the given branch. Work in all access moded.
     pub async fn get_branch_version_vector(&self, writer_id: &PublicKey) -> Result<VersionVector> {
         Ok(self
@@ -730,17 +1805,561 @@ impl Repository {
             .into_version_vector())
     }
 
+    /// Discards the local copy of the given remote branch (all its snapshots) and downloads it
+    /// again from the peers. Useful when the local copy is suspected to be corrupted. The blocks
+    /// no longer referenced by any other branch are then removed by the background worker. If any
+    /// file or directory of the branch is currently open, waits until it's closed. Does nothing if
+    /// the branch doesn't exist. Returns `OperationNotSupported` if `writer_id` is the id of the
+    /// local branch.
+    pub async fn resync_branch(&self, writer_id: PublicKey) -> Result<()> {
+        if writer_id == self.shared.credentials.read().unwrap().writer_id {
+            return Err(Error::OperationNotSupported);
+        }
+
+        // Prevent removing a branch that's still being used (same as the worker does when pruning).
+        let locker = self.shared.branch_shared.locker.branch(writer_id);
+        let _lock = loop {
+            match locker.try_unique(BlobId::ROOT) {
+                Ok(lock) => break lock,
+                Err((notify, _)) => notify.await,
+            }
+        };
+
+        let store = self.shared.vault.store();
+        let mut tx = store.begin_write().await?;
+
+        let node = match tx.load_root_node(&writer_id, RootNodeFilter::Any).await {
+            Ok(node) => node,
+            Err(store::Error::BranchNotFound) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        tx.remove_branch(&node).await?;
+        tx.commit().await?;
+
+        tracing::debug!(branch_id = ?writer_id, "branch removed for resync");
+
+        // Trigger the removal of the unreachable blocks.
+        self.shared
+            .vault
+            .event_tx
+            .send(Payload::BranchChanged(writer_id));
+
+        // Request the branch again from all the peers.
+        store.client_reload_index_tx.insert(&writer_id);
+
+        Ok(())
+    }
+
+    /// Copies the file or directory at `path` as it is in the branch of `writer_id` into the local
+    /// branch, so that its content in that branch wins over the local one and over any other
+    /// branches (cherry-pick style, as opposed to the automatic merge of all the branches).
+    ///
+    /// A directory is pulled recursively: its files replace the local files at the same paths
+    /// (with version vectors that dominate both the local and the pulled versions), its
+    /// subdirectories are created locally if they don't exist and files removed in the source
+    /// branch are removed locally too. Local entries the source branch doesn't have are kept.
+    /// Fails with `EntryIsDirectory` / `EntryIsFile` if a file is to be pulled over a local
+    /// directory or vice versa.
+    ///
+    /// The files are shallow copies so they can be pulled even if their blocks haven't been
+    /// downloaded yet (they are then downloaded from the peers as usual). The directories however
+    /// need to be available: if any of them isn't, this fails with `Store(BlockNotFound)` and can
+    /// be retried later (what has been pulled so far stays in place). Does nothing if `writer_id`
+    /// is the id of the local branch.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), ?writer_id, path = %path.as_ref()))]
+    pub async fn pull_from<P: AsRef<Utf8Path>>(&self, writer_id: PublicKey, path: P) -> Result<()> {
+        let local_branch = self.local_branch()?;
+
+        if writer_id == *local_branch.id() {
+            return Ok(());
+        }
+
+        let src_branch = self.shared.get_branch(writer_id)?;
+
+        pull::pull(&src_branch, &local_branch, path.as_ref()).await
+    }
+
+    /// Forks the files at the given paths into the local branch, as if each of them was about to
+    /// be modified. Unlike forking them one by one (which happens implicitly on the first write to
+    /// each of them), all the files are forked in a single transaction and so create only one new
+    /// snapshot. Useful before modifying a lot of files at once.
+    ///
+    /// The forks are shallow: only the index of each file is copied, the blocks are shared with
+    /// the original so they don't need to be downloaded first. Files already in the local branch
+    /// and duplicate paths are skipped. Fails with `EntryIsDirectory` if any of the paths is a
+    /// directory. The fork is atomic: in case of error none of the files are forked, although
+    /// the parent directories of the files might have already been forked.
+    #[instrument(parent = self.span(), skip_all, fields(id = next_span_id(), paths = paths.len()))]
+    pub async fn fork_all(&self, paths: &[Utf8PathBuf]) -> Result<()> {
+        let local_branch = self.local_branch()?;
+
+        let mut names_by_parent: BTreeMap<&Utf8Path, Vec<&str>> = BTreeMap::new();
+
+        for path in paths {
+            let (parent, name) = path::decompose(path).ok_or(Error::EntryIsDirectory)?;
+            names_by_parent.entry(parent).or_default().push(name);
+        }
+
+        let mut blob_ids = HashSet::default();
+        let mut groups = Vec::with_capacity(names_by_parent.len());
+
+        for (parent, names) in names_by_parent {
+            let dir = self.cd(parent).await?;
+            let mut src_parent = None;
+            let mut files = Vec::with_capacity(names.len());
+
+            for name in names {
+                let entry = dir.lookup_unique(name)?.file()?;
+
+                if entry.branch().id() == local_branch.id() {
+                    continue;
+                }
+
+                if !blob_ids.insert(*entry.blob_id()) {
+                    continue;
+                }
+
+                src_parent.get_or_insert_with(|| entry.parent().clone());
+                files.push((
+                    entry.name().to_owned(),
+                    entry.branch().clone(),
+                    EntryData::File(entry.data().clone()),
+                ));
+            }
+
+            let Some(src_parent) = src_parent else {
+                continue;
+            };
+
+            groups.push((src_parent.fork(&local_branch).await?, files));
+        }
+
+        if groups.is_empty() {
+            return Ok(());
+        }
+
+        directory::fork_files(&local_branch, groups).await
+    }
+
+    /// Enables or disables logging of the block accesses, for debugging performance problems. When
+    /// enabled, the most recent block reads (including reads of blocks that are missing), local
+    /// writes and blocks received from peers are kept in memory and can be retrieved with
+    /// [`Self::recent_block_accesses`]. Disabling it discards the log. Disabled by default and not
+    /// persisted.
+    pub fn set_block_access_log_enabled(&self, enabled: bool) {
+        self.shared
+            .vault
+            .store()
+            .set_block_access_log_enabled(enabled);
+    }
+
+    /// Is the block access log enabled?
+    pub fn is_block_access_log_enabled(&self) -> bool {
+        self.shared.vault.store().is_block_access_log_enabled()
+    }
+
+    /// Returns up to `n` most recent block accesses, oldest first. See
+    /// [`Self::set_block_access_log_enabled`].
+    pub fn recent_block_accesses(&self, n: usize) -> Vec<BlockAccess> {
+        self.shared.vault.store().recent_block_accesses(n)
+    }
+
+    /// Returns the current write pressure on the store as a value between 0 (writes proceed
+    /// immediately) and 1 (saturated). It's based on the number of tasks waiting to write to the
+    /// database, which includes the local writes as well as storing the nodes and blocks received
+    /// from peers. Apps doing heavy local writes (e.g. imports) can use it to throttle themselves.
+    pub fn write_pressure(&self) -> f32 {
+        let waiting = self.db().write_queue_depth().saturating_sub(1);
+        (waiting as f32 / WRITE_QUEUE_SATURATION as f32).min(1.0)
+    }
+
     /// Subscribe to event notifications.
     pub fn subscribe(&self) -> broadcast::Receiver<Event> {
         self.shared.vault.event_tx.subscribe()
     }
 
+    /// Returns a stream of the changes of the entries at or under the directory at `prefix`. Only
+    /// changes made after the stream is first polled are reported. A change is reported when the
+    /// version vector of the entry changes (`ChangeKind::Modified`) or when the entry disappears
+    /// (`ChangeKind::Removed`). Changes in other parts of the repository don't wake up the
+    /// subscriber. If `prefix` doesn't exist (yet), its entries are reported once it's created.
+    pub fn subscribe_path(&self, prefix: Utf8PathBuf) -> impl Stream<Item = PathEvent> + '_ {
+        path_events::subscribe_path(self, prefix)
+    }
+
+    /// Returns a stream of ids of blocks as they become required (that is, they are missing
+    /// locally and are needed to be downloaded). Use together with `Payload::BlockReceived` events
+    /// (see `subscribe`) to learn when they have been received. If the stream is not consumed fast
+    /// enough, some ids might be skipped.
+    pub fn required_blocks(&self) -> impl Stream<Item = BlockId> {
+        BroadcastStream::new(self.shared.vault.block_tracker.subscribe_required())
+            .filter_map(|result| future::ready(result.ok()))
+    }
+
+    /// Returns a stream of the entries of the local branch that changed since the given version
+    /// vector (e.g., the local version vector at the time of the previous backup), together with
+    /// their paths. An entry is considered changed if its version vector is not less than or equal
+    /// to `vv`. Unchanged directories are skipped without being traversed.
+    pub fn changed_since(
+        &self,
+        vv: VersionVector,
+    ) -> Result<impl Stream<Item = Result<(Utf8PathBuf, ChangeKind)>>> {
+        Ok(changes::changed_since(self.local_branch()?, vv))
+    }
+
+    /// Compares the file tree of this repository with the one of `other` and returns a stream of
+    /// the differences: entries that exist in only one of them, entries that are a file in one and
+    /// a directory in the other and files whose contents differ. Contents are compared by their
+    /// digests (see [`File::content_digest`]), so the repositories don't need to share blocks or
+    /// keys, e.g. to verify a migration. Only read access to both is required. The differences are
+    /// yielded as they are found so the comparison can be cancelled by dropping the stream.
+    ///
+    /// The entries are matched by their unique names (see [`JointEntryRef::unique_name`]), so
+    /// files in conflict normally show up as existing in only one of the repositories.
+    pub fn compare_with<'a>(
+        &'a self,
+        other: &'a Repository,
+    ) -> impl Stream<Item = Result<Difference>> + 'a {
+        compare::compare(self, other)
+    }
+
+    /// Recursively searches the directory at `root` and returns a stream of the paths of all the
+    /// entries (files and directories) whose name matches the given pattern. The match is
+    /// case-insensitive. If the pattern contains `*` (any sequence of characters) or `?` (any
+    /// single character) it has to match the whole name, otherwise the name only has to contain
+    /// it. The results are yielded as they are found so the search can be cancelled by simply
+    /// dropping the stream.
+    pub fn find<'a>(
+        &'a self,
+        pattern: &str,
+        root: Utf8PathBuf,
+    ) -> impl Stream<Item = Result<Utf8PathBuf>> + 'a {
+        find::find(self, pattern, root)
+    }
+
+    /// Makes sure the files at the given paths (including the whole content of directories) are
+    /// fully downloaded, e.g., before going offline. The missing blocks are requested from the
+    /// peers and the returned stream yields the download progress first immediately and then each
+    /// time one of them is received. The stream ends when all the blocks are available locally
+    /// (or when the repository is closed).
+    ///
+    /// Note the stream might never end if no connected peer has the missing blocks, so the caller
+    /// should consider applying a timeout. Dropping the stream cancels the waiting, but the blocks
+    /// that have already been requested might still be downloaded.
+    pub fn ensure_local<'a>(
+        &'a self,
+        paths: &'a [Utf8PathBuf],
+    ) -> impl Stream<Item = Result<Progress>> + 'a {
+        stream::try_unfold(None, move |prefetch: Option<Prefetch>| async move {
+            let prefetch = match prefetch {
+                Some(mut prefetch) => {
+                    if prefetch.is_complete() || !prefetch.wait(self).await? {
+                        return Ok(None);
+                    }
+
+                    prefetch
+                }
+                None => Prefetch::start(self, paths).await?,
+            };
+
+            Ok::<_, Error>(Some((prefetch.progress(), Some(prefetch))))
+        })
+    }
+
+    /// Verifies the locally stored blocks and repairs the damaged ones: blocks whose content
+    /// doesn't match their id (e.g., due to disk corruption) and blocks which the index references
+    /// as present but which aren't actually stored. Those are marked as missing and requested
+    /// from the peers again. The returned stream yields the progress first immediately after the
+    /// verification and then each time one of the damaged blocks is received. The stream ends
+    /// when all the damaged blocks are available again, when none of them has been received for
+    /// 30 seconds (since the verification or since the last received one), or when the repository
+    /// is closed.
+    pub fn verify_and_repair(&self) -> impl Stream<Item = Result<RepairProgress>> + '_ {
+        self.verify_and_repair_with_timeout(repair::IDLE_TIMEOUT)
+    }
+
+    fn verify_and_repair_with_timeout(
+        &self,
+        idle_timeout: Duration,
+    ) -> impl Stream<Item = Result<RepairProgress>> + '_ {
+        stream::try_unfold(None, move |repair: Option<Repair>| async move {
+            let repair = match repair {
+                Some(mut repair) => {
+                    if repair.is_complete() || !repair.wait(self).await? {
+                        return Ok(None);
+                    }
+
+                    repair
+                }
+                None => Repair::start(self, idle_timeout).await?,
+            };
+
+            Ok::<_, Error>(Some((repair.progress(), Some(repair))))
+        })
+    }
+
+    /// Returns a compact description of which blocks this replica has, to be passed to
+    /// [`Self::blocks_missing_by`] of another replica of this repository.
+    pub async fn presence_snapshot(&self) -> Result<PresenceSnapshot> {
+        PresenceSnapshot::load(self.shared.vault.store()).await
+    }
+
+    /// Returns the blocks which this replica has but the peer that produced `snapshot` (see
+    /// [`Self::presence_snapshot`]) doesn't, e.g., to push them to it. Only the parts of the index
+    /// where the two replicas differ are walked. The snapshot doesn't describe individual blocks,
+    /// so where the peer has only some of the blocks referenced by a single leaf node group, all
+    /// of ours from that group are returned. Each block is returned at most once.
+    pub fn blocks_missing_by(
+        &self,
+        snapshot: PresenceSnapshot,
+    ) -> impl Stream<Item = Result<BlockId>> + '_ {
+        stream::try_unfold(
+            MissingBlocks::new(snapshot),
+            move |mut missing| async move {
+                Ok(missing
+                    .next(self.shared.vault.store())
+                    .await?
+                    .map(|block_id| (block_id, missing)))
+            },
+        )
+    }
+
     /// Gets the syncing progress of this repository (number of downloaded blocks / number of
     /// all blocks)
     pub async fn sync_progress(&self) -> Result<Progress> {
         Ok(self.shared.vault.store().sync_progress().await?)
     }
 
+    /// Computes how much of the data is shared among the branches of this repository (e.g., due
+    /// to forking). This walks the whole index so it can take a while on large repositories.
+    pub async fn dedup_stats(&self) -> Result<DedupStats> {
+        let mut reader = self.shared.vault.store().acquire_read().await?;
+        let root_nodes: Vec<_> = reader.load_root_nodes().try_collect().await?;
+
+        // Stored length and number of references of each block.
+        let mut counts: HashMap<BlockId, (u64, u64)> = HashMap::default();
+
+        for root_node in &root_nodes {
+            let mut blocks = reader.load_blocks_in_snapshot(root_node);
+
+            while let Some((block_id, len)) = blocks.try_next().await? {
+                let entry = counts.entry(block_id).or_insert((len, 0));
+                entry.1 += 1;
+            }
+        }
+
+        let mut stats = DedupStats::default();
+
+        for (len, count) in counts.into_values() {
+            stats.logical_bytes += count * len;
+            stats.physical_bytes += len;
+
+            if count > 1 {
+                stats.shared_blocks += 1;
+            } else {
+                stats.unique_blocks += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Lists the files and directories currently held open in this repository (by this process),
+    /// e.g. to find out what prevents the repository from being closed. This is meant for
+    /// diagnostics only: it walks the directory trees of the affected branches to resolve the
+    /// paths so it can take a while on large repositories.
+    pub async fn open_handles(&self) -> Vec<OpenHandleInfo> {
+        let mut held: HashMap<PublicKey, Vec<_>> = HashMap::default();
+
+        for (branch_id, blob_id, kind, count) in self.shared.branch_shared.locker.held() {
+            held.entry(branch_id)
+                .or_default()
+                .push((blob_id, kind, count));
+        }
+
+        let mut handles = Vec::new();
+
+        for (branch_id, locks) in held {
+            let paths = match self.shared.get_branch(branch_id) {
+                Ok(branch) => {
+                    let blob_ids: HashSet<_> =
+                        locks.iter().map(|(blob_id, _, _)| *blob_id).collect();
+                    handles::resolve_paths(&branch, blob_ids).await
+                }
+                Err(_) => HashMap::default(),
+            };
+
+            handles.extend(
+                locks
+                    .into_iter()
+                    .map(|(blob_id, kind, count)| OpenHandleInfo {
+                        branch_id,
+                        blob_id: blob_id.to_string(),
+                        path: paths.get(&blob_id).cloned(),
+                        mode: kind.into(),
+                        count,
+                    }),
+            );
+        }
+
+        handles.sort_by(|a, b| {
+            (a.branch_id, &a.path, &a.blob_id).cmp(&(b.branch_id, &b.path, &b.blob_id))
+        });
+        handles
+    }
+
+    /// Lists the blob locks currently held in this repository (by this process). Unlike
+    /// `open_handles` this doesn't resolve the paths of the blobs and so it's cheap.
+    pub fn locks(&self) -> Vec<LockInfo> {
+        let mut locks: Vec<_> = self
+            .shared
+            .branch_shared
+            .locker
+            .held()
+            .into_iter()
+            .map(|(branch_id, blob_id, kind, count)| LockInfo {
+                branch_id,
+                blob_id: blob_id.to_string(),
+                kind: kind.into(),
+                count,
+            })
+            .collect();
+
+        locks.sort_by(|a, b| (a.branch_id, &a.blob_id).cmp(&(b.branch_id, &b.blob_id)));
+        locks
+    }
+
+    /// Forcibly releases all locks held on the blob with the given (hex-encoded) id, in all
+    /// branches. Returns the number of branches the blob was locked in. Meant for recovery from
+    /// locks leaked by a panicked task or a handle that was never dropped, which would otherwise
+    /// block writes with `Error::Locked`.
+    ///
+    /// DANGER: the holders of the locks are not notified. If any of them is still alive, it keeps
+    /// accessing the blob as if it held the lock, which can race with other accesses and corrupt
+    /// the blob. Only use this when the holders are known to be gone.
+    ///
+    /// Unless `force` is `true`, fails with `Error::Locked` when the repository is not quiescent
+    /// (some background job, e.g. merge or garbage collection, is currently running).
+    pub fn force_unlock(&self, blob_id: &str, force: bool) -> Result<usize> {
+        let mut bytes = [0; BlobId::SIZE];
+        hex::decode_to_slice(blob_id, &mut bytes).map_err(|_| Error::InvalidArgument)?;
+        let blob_id = BlobId::from(bytes);
+
+        let monitor = &self.shared.vault.monitor;
+
+        if !force
+            && (monitor.merge_job.is_running()
+                || monitor.prune_job.is_running()
+                || monitor.trash_job.is_running()
+                || monitor.scan_job.is_running())
+        {
+            return Err(Error::Locked);
+        }
+
+        let count = self.shared.branch_shared.locker.force_release(&blob_id);
+
+        if count > 0 {
+            tracing::warn!(%blob_id, count, force, "Locks forcibly released");
+        }
+
+        Ok(count)
+    }
+
+    /// Returns a snapshot of the overall state of this repository, useful e.g. to display a status
+    /// summary. The network related information (linked peers, DHT and PEX) is left at the
+    /// defaults, use [`Self::status_with_network`] to include it.
+    pub async fn status(&self) -> Result<RepositoryStatus> {
+        let credentials = self.credentials();
+        let access_mode = credentials.secrets.access_mode();
+
+        let root_nodes: Vec<_> = self
+            .shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .load_root_nodes()
+            .try_collect()
+            .await?;
+        let branch_count = root_nodes.len();
+
+        let merge_pending = is_merge_pending(&credentials, &root_nodes);
+
+        let local_version_vector = root_nodes
+            .into_iter()
+            .find(|root_node| root_node.proof.writer_id == credentials.writer_id)
+            .map(|root_node| root_node.proof.into_version_vector())
+            .unwrap_or_default();
+
+        Ok(RepositoryStatus {
+            access_mode,
+            branch_count,
+            local_version_vector,
+            sync_progress: self.sync_progress().await?,
+            linked_peer_count: 0,
+            dht_enabled: false,
+            pex_enabled: false,
+            merge_pending,
+        })
+    }
+
+    /// Like [`Self::status`] but includes also the network related information taken from the
+    /// given network registration of this repository.
+    pub async fn status_with_network(
+        &self,
+        registration: &Registration,
+    ) -> Result<RepositoryStatus> {
+        Ok(RepositoryStatus {
+            linked_peer_count: registration.linked_peer_count(),
+            dht_enabled: registration.is_dht_enabled(),
+            pex_enabled: registration.is_pex_enabled(),
+            ..self.status().await?
+        })
+    }
+
+    /// Cheaply checks whether this repository is fully synced, that is, whether all the blocks of
+    /// every branch have been downloaded and there is no pending merge. Only the root node of each
+    /// branch is read (blocks are not counted like in [`Self::sync_progress`]) so this is suitable
+    /// for frequent polling.
+    pub async fn is_fully_synced(&self) -> Result<bool> {
+        let root_nodes: Vec<_> = self
+            .shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .load_root_nodes()
+            .try_collect()
+            .await?;
+
+        let all_blocks_present = root_nodes
+            .iter()
+            .all(|root_node| root_node.summary.block_presence == MultiBlockPresence::Full);
+
+        Ok(all_blocks_present && !is_merge_pending(&self.credentials(), &root_nodes))
+    }
+
+    /// Reads the block with the given id exactly as it's stored, without decrypting it. Returns the
+    /// block content and the nonce it was encrypted with. The content is ciphertext (possibly
+    /// compressed before encryption, in which case it's shorter than `BLOCK_SIZE`) so this never
+    /// exposes any plaintext. Intended for external tooling that wants to independently verify the
+    /// encryption invariants, not for normal use.
+    #[cfg(feature = "unstable")]
+    pub async fn read_raw_block(&self, id: &BlockId) -> Result<(BlockContent, BlockNonce)> {
+        let mut content = BlockContent::new();
+        let nonce = self
+            .shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .read_block(id, &mut content)
+            .await?;
+
+        Ok((content, nonce))
+    }
+
     /// Check integrity of the stored data.
     // TODO: Return more detailed info about any integrity violation.
     pub async fn check_integrity(&self) -> Result<bool> {
@@ -752,6 +2371,16 @@ impl Repository {
         let local_branch = self.local_branch()?;
         let branches = self.shared.load_branches().await?;
 
+        // In the observer mode only the followed branch is visible.
+        let branches = if let Some(followed_id) = self.followed_branch() {
+            branches
+                .into_iter()
+                .filter(|branch| *branch.id() == followed_id)
+                .collect()
+        } else {
+            branches
+        };
+
         // If we are writer and the local branch doesn't exist yet in the db we include it anyway.
         // This fixes a race condition when the local branch doesn't exist yet at the moment we
         // load the branches but is subsequently created by merging a remote branch and the remote
@@ -816,6 +2445,64 @@ impl Repository {
         self.root().await?.cd(path).await
     }
 
+    /// Pins the current snapshots of all the branches and returns a read-only view of the
+    /// repository as of that moment. Files and directories opened through the view are mutually
+    /// consistent even if the repository is being modified or synced at the same time (e.g., a
+    /// manifest file and the files it lists). See [`ReadSnapshot`] for the caveats.
+    pub async fn read_snapshot(&self) -> Result<ReadSnapshot> {
+        let mut tx = self.shared.vault.store().begin_read().await?;
+        let root_nodes: Vec<_> = tx.load_root_nodes().try_collect().await?;
+
+        // In the observer mode only the followed branch is visible.
+        let followed_id = self.followed_branch();
+
+        let branches = root_nodes
+            .into_iter()
+            .filter(|root_node| {
+                followed_id.map_or(true, |followed_id| root_node.proof.writer_id == followed_id)
+            })
+            .map(|root_node| {
+                self.shared
+                    .get_branch(root_node.proof.writer_id)
+                    .map(|branch| (branch, root_node))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ReadSnapshot::new(tx, branches))
+    }
+
+    /// Exports the index of this repository (the structure of all its branches and the content of
+    /// their directories) into a new repository database at `dst`, without the content of the
+    /// files. The export shows the full directory tree but all the file blocks are missing and
+    /// are downloaded from the peers on demand. This is useful to quickly share a catalog of the
+    /// repository without transferring all its data.
+    ///
+    /// Only the index, the directory blocks and the public data needed to open the repository
+    /// (its id) are exported. In particular no secrets, device id, name or settings are copied so
+    /// the export always opens in the blind mode. Use [`Self::set_credentials`] (e.g. with
+    /// [`Credentials::with_random_writer_id`]) to grant access to it. Directories whose blocks are
+    /// missing in this repository are missing in the export as well. Without read access no
+    /// directory can be read so the export contains only the index nodes.
+    pub async fn export_index(&self, dst: impl AsRef<Path>) -> Result<()> {
+        let dst = dst.as_ref();
+
+        let branches = self.shared.load_branches().await?;
+        let retained = export::directory_block_ids(branches).await?;
+
+        let mut export = db::export(self.shared.vault.store().db(), dst).await?;
+        store::export_index(&mut export, &retained).await?;
+        metadata::export(&mut export).await?;
+        export.commit().await?;
+
+        // The summaries of the nodes whose blocks were not exported are now stale.
+        let store = store::Store::new(db::open(dst).await?);
+        let result = store.recompute_summaries().await;
+        store.close().await?;
+        result?;
+
+        Ok(())
+    }
+
     /// Close all db connections held by this repository. After this function returns, any
     /// subsequent operation on this repository that requires to access the db returns an error.
     pub async fn close(&self) -> Result<()> {
@@ -883,6 +2570,11 @@ impl Repository {
         Ok(self.shared.vault.store().count_blocks().await?)
     }
 
+    // Span of this repository. The public operations run in spans that are children of it.
+    fn span(&self) -> &Span {
+        self.shared.vault.monitor.span()
+    }
+
     fn db(&self) -> &db::Pool {
         self.shared.vault.store().db()
     }
@@ -902,15 +2594,32 @@ impl Repository {
 
 pub struct RepositoryHandle {
     pub(crate) vault: Vault,
+    // Key to encrypt the data the network stores in the repository metadata which shouldn't be
+    // readable without access to the repository (e.g., the addresses of the known peers). Derived
+    // from the read key, so `None` in blind mode.
+    pub(crate) metadata_key: Option<cipher::SecretKey>,
 }
 
 struct Shared {
     vault: Vault,
     credentials: BlockingRwLock<Credentials>,
     branch_shared: BranchShared,
+    followed_branch: BlockingMutex<Option<PublicKey>>,
+    merge_stalls: BlockingMutex<MergeStalls>,
+    // Serializes the merges triggered manually with those run by the worker.
+    merge_lock: AsyncMutex<()>,
 }
 
 impl Shared {
+    // Returns the branch followed in the observer mode, which is ignored in the write mode.
+    fn followed_branch(&self) -> Option<PublicKey> {
+        if self.credentials.read().unwrap().secrets.can_write() {
+            return None;
+        }
+
+        *self.followed_branch.lock().unwrap()
+    }
+
     pub fn local_branch(&self) -> Result<Branch> {
         let credentials = self.credentials.read().unwrap();
 
@@ -957,6 +2666,26 @@ impl Shared {
     }
 }
 
+// Is there any remote branch with changes not yet merged into the local branch? Always `false`
+// if not in write mode.
+fn is_merge_pending(credentials: &Credentials, root_nodes: &[RootNode]) -> bool {
+    if credentials.secrets.access_mode() != AccessMode::Write {
+        return false;
+    }
+
+    let empty = VersionVector::new();
+    let local = root_nodes
+        .iter()
+        .find(|root_node| root_node.proof.writer_id == credentials.writer_id)
+        .map(|root_node| &root_node.proof.version_vector)
+        .unwrap_or(&empty);
+
+    root_nodes
+        .iter()
+        .filter(|root_node| root_node.proof.writer_id != credentials.writer_id)
+        .any(|root_node| !(&root_node.proof.version_vector <= local))
+}
+
 fn spawn_worker(shared: Arc<Shared>) -> ScopedJoinHandle<()> {
     let span = shared.vault.monitor.span().clone();
     scoped_task::spawn(worker::run(shared).instrument(span))
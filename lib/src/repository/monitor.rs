@@ -52,6 +52,16 @@ pub(crate) struct RepositoryMonitor {
     pub prune_job: JobMonitor,
     pub trash_job: JobMonitor,
 
+    // Total number of unreachable blocks removed.
+    pub trash_blocks_removed: Counter,
+    // Time spent removing unreachable blocks in a single trash job.
+    pub trash_removal_time: Histogram,
+    // Number of unreachable blocks removed so far by the current (or the last) trash job.
+    pub trash_progress: MonitoredValue<u64>,
+
+    // Current number of decrypted blocks held in the blob caches.
+    pub block_buffers_in_use: Gauge,
+
     span: Span,
     node: StateMonitor,
 }
@@ -61,7 +71,11 @@ impl RepositoryMonitor {
     where
         R: Recorder + ?Sized,
     {
-        let span = tracing::info_span!("repo", message = node.id().name());
+        let span = tracing::info_span!(
+            "repo",
+            message = node.id().name(),
+            fields = tracing::field::Empty
+        );
 
         let info_hash = node.make_value("info-hash", None);
 
@@ -88,6 +102,12 @@ impl RepositoryMonitor {
         let prune_job = JobMonitor::new(&node, recorder, "prune");
         let trash_job = JobMonitor::new(&node, recorder, "trash");
 
+        let trash_blocks_removed = create_counter(recorder, "trash blocks removed", Unit::Count);
+        let trash_removal_time = create_histogram(recorder, "trash removal time", Unit::Seconds);
+        let trash_progress = node.make_value("trash job progress", 0);
+
+        let block_buffers_in_use = create_gauge(recorder, "block buffers in use", Unit::Count);
+
         Self {
             info_hash,
 
@@ -110,6 +130,12 @@ impl RepositoryMonitor {
             prune_job,
             trash_job,
 
+            trash_blocks_removed,
+            trash_removal_time,
+            trash_progress,
+
+            block_buffers_in_use,
+
             span,
             node,
         }
@@ -189,6 +215,11 @@ impl JobMonitor {
         }
     }
 
+    /// Is at least one job monitored by this monitor currently running?
+    pub(crate) fn is_running(&self) -> bool {
+        *self.count_running_tx.borrow() > 0
+    }
+
     /// Runs a monitored job.
     ///
     /// A single `JobMonitor` can monitor multiple concurrent jobs but they are threated as a single
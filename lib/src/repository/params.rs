@@ -1,5 +1,5 @@
 use super::RepositoryMonitor;
-use crate::{db, device_id::DeviceId, error::Result};
+use crate::{crypto::KdfParams, db, device_id::DeviceId, error::Result};
 use metrics::{NoopRecorder, Recorder};
 use state_monitor::{metrics::MetricsRecorder, StateMonitor};
 use std::{
@@ -10,6 +10,7 @@ use std::{
 pub struct RepositoryParams<R> {
     store: Store,
     device_id: DeviceId,
+    kdf_params: KdfParams,
     parent_monitor: Option<StateMonitor>,
     recorder: Option<R>,
 }
@@ -19,6 +20,13 @@ impl<R> RepositoryParams<R> {
         Self { device_id, ..self }
     }
 
+    /// Params of the function used to derive the local keys from passwords when creating the
+    /// repository. They are stored in the repository so the same ones are used when opening it.
+    /// Has no effect on opening an existing repository. See also `KdfParams::calibrate`.
+    pub fn with_kdf_params(self, kdf_params: KdfParams) -> Self {
+        Self { kdf_params, ..self }
+    }
+
     pub fn with_parent_monitor(self, parent_monitor: StateMonitor) -> Self {
         Self {
             parent_monitor: Some(parent_monitor),
@@ -30,6 +38,7 @@ impl<R> RepositoryParams<R> {
         RepositoryParams {
             store: self.store,
             device_id: self.device_id,
+            kdf_params: self.kdf_params,
             parent_monitor: self.parent_monitor,
             recorder: Some(recorder),
         }
@@ -54,6 +63,10 @@ impl<R> RepositoryParams<R> {
     pub(super) fn device_id(&self) -> DeviceId {
         self.device_id
     }
+
+    pub(super) fn kdf_params(&self) -> &KdfParams {
+        &self.kdf_params
+    }
 }
 
 impl<R> RepositoryParams<R>
@@ -94,6 +107,7 @@ impl RepositoryParams<NoopRecorder> {
         Self {
             store,
             device_id: rand::random(),
+            kdf_params: KdfParams::default(),
             parent_monitor: None,
             recorder: None,
         }
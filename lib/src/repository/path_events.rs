@@ -0,0 +1,213 @@
+use super::{
+    path_filter::{self, PathFilter},
+    ChangeKind, Repository,
+};
+use crate::{
+    directory::EntryType,
+    error::Error,
+    event::{Event, Payload},
+    joint_directory::JointEntryRef,
+    version_vector::VersionVector,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_util::{stream, Stream};
+use std::collections::{BTreeMap, VecDeque};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Change of an entry reported by `Repository::subscribe_path`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PathEvent {
+    pub path: Utf8PathBuf,
+    pub kind: ChangeKind,
+}
+
+pub(super) fn subscribe_path(
+    repo: &Repository,
+    prefix: Utf8PathBuf,
+) -> impl Stream<Item = PathEvent> + '_ {
+    stream::unfold(
+        State {
+            repo,
+            // Subscribe before the initial scan so no change can be missed.
+            event_rx: repo.subscribe(),
+            filter: PathFilter::new(prefix.clone()),
+            prefix,
+            snapshot: None,
+            complete: false,
+            events: VecDeque::new(),
+        },
+        |mut state| async move {
+            let event = state.next().await?;
+            Some((event, state))
+        },
+    )
+}
+
+type Snapshot = BTreeMap<Utf8PathBuf, (EntryType, VersionVector)>;
+
+struct State<'a> {
+    repo: &'a Repository,
+    event_rx: broadcast::Receiver<Event>,
+    // Skips the branch changes that don't affect the subtree so it's not rescanned needlessly.
+    filter: PathFilter,
+    prefix: Utf8PathBuf,
+    // Entries in the subtree as of the last scan. `None` until the initial scan.
+    snapshot: Option<Snapshot>,
+    // Whether the last scan visited the whole subtree. If not (e.g. because some directories are
+    // still being downloaded) the subtree is scanned again also when a block is received.
+    complete: bool,
+    // Events found but not yet yielded.
+    events: VecDeque<PathEvent>,
+}
+
+impl State<'_> {
+    async fn next(&mut self) -> Option<PathEvent> {
+        if self.snapshot.is_none() {
+            self.rescan().await;
+        }
+
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+
+            let mut branch_ids = Vec::new();
+
+            let force = match self.event_rx.recv().await {
+                Ok(Event {
+                    payload: Payload::BranchChanged(branch_id),
+                    ..
+                }) => {
+                    branch_ids.push(branch_id);
+                    false
+                }
+                Ok(Event {
+                    payload: Payload::BlockReceived(_),
+                    ..
+                }) if !self.complete => true,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => true,
+                Err(RecvError::Closed) => return None,
+            };
+
+            // Handle a burst of changes with a single rescan.
+            let force =
+                path_filter::drain_changed_branches(&mut self.event_rx, &mut branch_ids) || force;
+
+            if !force && !self.filter.is_affected(self.repo, &branch_ids).await {
+                continue;
+            }
+
+            self.rescan().await;
+        }
+    }
+
+    async fn rescan(&mut self) {
+        let initial = self.snapshot.is_none();
+        let old = self.snapshot.take().unwrap_or_default();
+        let (new, complete) = scan(self.repo, &self.prefix, &old).await;
+
+        // Don't report the initial state.
+        if !initial {
+            self.events.extend(diff(&old, &new));
+        }
+
+        self.snapshot = Some(new);
+        self.complete = complete;
+    }
+}
+
+// Scans the subtree at `prefix`. Directories whose version vector didn't change since `old` are
+// not descended into, their entries are copied from `old` instead. Returns the new snapshot and
+// whether the whole subtree could be scanned.
+async fn scan(repo: &Repository, prefix: &Utf8Path, old: &Snapshot) -> (Snapshot, bool) {
+    let mut new = Snapshot::new();
+    let mut complete = true;
+
+    let root = match repo.cd(prefix).await {
+        Ok(root) => root,
+        Err(Error::EntryNotFound | Error::EntryIsFile) => return (new, true),
+        Err(error) => {
+            tracing::debug!(?error, "Failed to open directory {}", prefix);
+            return (old.clone(), false);
+        }
+    };
+
+    let mut dirs = vec![(prefix.to_owned(), root)];
+
+    while let Some((path, dir)) = dirs.pop() {
+        for entry in dir.entries() {
+            let entry_path = path.join(entry.unique_name().as_ref());
+            let entry_type = entry.entry_type();
+            let vv = entry.version_vector().into_owned();
+
+            let unchanged = old
+                .get(&entry_path)
+                .map(|(old_type, old_vv)| *old_type == entry_type && *old_vv == vv)
+                .unwrap_or(false);
+
+            if let JointEntryRef::Directory(entry) = entry {
+                let subdir = if unchanged {
+                    None
+                } else {
+                    match entry.open().await {
+                        Ok(subdir) => Some(subdir),
+                        Err(error) => {
+                            tracing::debug!(?error, "Failed to open directory {}", entry_path);
+                            complete = false;
+
+                            // Keep the directory as it was so it doesn't appear removed and is
+                            // retried next time.
+                            if let Some(value) = old.get(&entry_path) {
+                                new.insert(entry_path.clone(), value.clone());
+                                copy_descendants(old, &entry_path, &mut new);
+                            }
+
+                            continue;
+                        }
+                    }
+                };
+
+                match subdir {
+                    Some(subdir) => dirs.push((entry_path.clone(), subdir)),
+                    None => copy_descendants(old, &entry_path, &mut new),
+                }
+            }
+
+            new.insert(entry_path, (entry_type, vv));
+        }
+    }
+
+    (new, complete)
+}
+
+fn copy_descendants(src: &Snapshot, path: &Utf8Path, dst: &mut Snapshot) {
+    dst.extend(
+        src.range(path.to_owned()..)
+            .skip_while(|(entry_path, _)| *entry_path == path)
+            .take_while(|(entry_path, _)| entry_path.starts_with(path))
+            .map(|(entry_path, value)| (entry_path.clone(), value.clone())),
+    );
+}
+
+fn diff(old: &Snapshot, new: &Snapshot) -> Vec<PathEvent> {
+    let modified = new
+        .iter()
+        .filter_map(|(path, (entry_type, vv))| match old.get(path) {
+            Some((old_type, old_vv)) if old_type == entry_type && old_vv == vv => None,
+            Some(_) | None => Some(PathEvent {
+                path: path.clone(),
+                kind: ChangeKind::Modified(*entry_type),
+            }),
+        });
+
+    let removed = old
+        .keys()
+        .filter(|path| !new.contains_key(*path))
+        .map(|path| PathEvent {
+            path: path.clone(),
+            kind: ChangeKind::Removed,
+        });
+
+    modified.chain(removed).collect()
+}
@@ -0,0 +1,136 @@
+use super::Repository;
+use crate::{
+    crypto::sign::PublicKey,
+    directory::{DirectoryFallback, DirectoryLocking},
+    error::{Error, Result},
+    event::{Event, Payload},
+    store,
+    version_vector::VersionVector,
+};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use std::collections::HashMap;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// Tells which branch changes can affect the subtree at a given path. A change to any entry in the
+/// subtree changes the version vector of its root directory, so it's enough to remember that
+/// version vector in every branch and compare it with the current one. This is much cheaper than
+/// reopening the subtree in all the branches on every change.
+pub(super) struct PathFilter {
+    path: Utf8PathBuf,
+    // Version vector of the subtree root in each branch, `None` if it doesn't exist there.
+    versions: HashMap<PublicKey, Option<VersionVector>>,
+}
+
+impl PathFilter {
+    pub fn new(path: Utf8PathBuf) -> Self {
+        Self {
+            path,
+            versions: HashMap::new(),
+        }
+    }
+
+    /// Returns whether changes to the given branches might have affected the subtree since the
+    /// last call. Branches not seen before are considered affected.
+    pub async fn is_affected(&mut self, repo: &Repository, branch_ids: &[PublicKey]) -> bool {
+        let mut affected = false;
+
+        for branch_id in branch_ids {
+            match load_version(repo, branch_id, &self.path).await {
+                Ok(version) => {
+                    if self.versions.insert(*branch_id, version.clone()) != Some(version) {
+                        affected = true;
+                    }
+                }
+                Err(error) => {
+                    // Can't tell so assume it was.
+                    tracing::debug!(
+                        ?error,
+                        ?branch_id,
+                        "Failed to load version of {}",
+                        self.path
+                    );
+                    self.versions.remove(branch_id);
+                    affected = true;
+                }
+            }
+        }
+
+        affected
+    }
+}
+
+/// Receives the event notifications that are already queued, without waiting, and returns the ids
+/// of the changed branches among them. Used to coalesce bursts of notifications into a single
+/// refresh. Returns whether some notifications were missed.
+pub(super) fn drain_changed_branches(
+    rx: &mut broadcast::Receiver<Event>,
+    branch_ids: &mut Vec<PublicKey>,
+) -> bool {
+    loop {
+        match rx.try_recv() {
+            Ok(Event {
+                payload: Payload::BranchChanged(branch_id),
+                ..
+            }) => {
+                if !branch_ids.contains(&branch_id) {
+                    branch_ids.push(branch_id);
+                }
+            }
+            Ok(_) => continue,
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return false,
+            Err(TryRecvError::Lagged(_)) => return true,
+        }
+    }
+}
+
+// Loads the version vector of the directory at `path` in the given branch. Returns `None` if
+// there is no such directory in the branch.
+async fn load_version(
+    repo: &Repository,
+    branch_id: &PublicKey,
+    path: &Utf8Path,
+) -> Result<Option<VersionVector>> {
+    let branch = repo.get_branch(*branch_id)?;
+    let mut dir = match branch
+        .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
+        .await
+    {
+        Ok(dir) => dir,
+        Err(Error::Store(store::Error::BranchNotFound)) => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let mut names = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Utf8Component::RootDir | Utf8Component::CurDir => (),
+            Utf8Component::Normal(name) => names.push(name),
+            Utf8Component::ParentDir | Utf8Component::Prefix(_) => {
+                return Err(Error::OperationNotSupported)
+            }
+        }
+    }
+
+    let Some((last, parents)) = names.split_last() else {
+        return Ok(Some(dir.version_vector().await?));
+    };
+
+    for name in parents {
+        let next = match dir.lookup(name) {
+            Ok(entry) if entry.is_directory() => {
+                entry.directory()?.open(DirectoryFallback::Disabled).await?
+            }
+            Ok(_) | Err(Error::EntryNotFound) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        dir = next;
+    }
+
+    match dir.lookup(last) {
+        Ok(entry) if entry.is_directory() => Ok(Some(entry.version_vector().clone())),
+        Ok(_) | Err(Error::EntryNotFound) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
@@ -0,0 +1,152 @@
+use super::Repository;
+use crate::{
+    blob::{BlobId, BlockIds},
+    branch::Branch,
+    collections::HashSet,
+    error::{Error, Result},
+    event::{Event, Payload},
+    joint_directory::{JointDirectory, JointEntryRef},
+    path,
+    progress::Progress,
+    protocol::{BlockId, SingleBlockPresence},
+};
+use camino::Utf8PathBuf;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// State of `Repository::ensure_local`.
+pub(super) struct Prefetch {
+    // Subscribed before the blocks are collected so no received block can be missed.
+    event_rx: broadcast::Receiver<Event>,
+    total: u64,
+    missing: HashSet<BlockId>,
+}
+
+impl Prefetch {
+    /// Collects the blocks of all the files at or under the given paths and marks the missing ones
+    /// as required.
+    pub async fn start(repo: &Repository, paths: &[Utf8PathBuf]) -> Result<Self> {
+        let event_rx = repo.subscribe();
+
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+
+        for path in paths {
+            match path::decompose(path) {
+                Some((parent, name)) => {
+                    let parent = repo.cd(parent).await?;
+                    let mut found = false;
+
+                    for entry in parent.lookup(name) {
+                        visit(entry, &mut dirs, &mut files).await?;
+                        found = true;
+                    }
+
+                    if !found {
+                        return Err(Error::EntryNotFound);
+                    }
+                }
+                None => dirs.push(repo.cd(path).await?),
+            }
+        }
+
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.entries() {
+                visit(entry, &mut dirs, &mut files).await?;
+            }
+        }
+
+        let mut blocks = HashSet::default();
+        let mut missing = HashSet::default();
+
+        for (branch, blob_id) in files {
+            let mut block_ids = BlockIds::open(branch, blob_id).await?;
+
+            while let Some(node) = block_ids.try_next_leaf_node().await? {
+                if !blocks.insert(node.block_id) {
+                    continue;
+                }
+
+                if node.block_presence != SingleBlockPresence::Present {
+                    missing.insert(node.block_id);
+                }
+            }
+        }
+
+        let block_tracker = &repo.shared.vault.block_tracker;
+
+        for block_id in &missing {
+            block_tracker.require(*block_id);
+        }
+
+        Ok(Self {
+            event_rx,
+            total: blocks.len() as u64,
+            missing,
+        })
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            value: self.total - self.missing.len() as u64,
+            total: self.total,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Waits until at least one of the missing blocks is received. Returns `false` if the
+    /// repository has been closed.
+    pub async fn wait(&mut self, repo: &Repository) -> Result<bool> {
+        loop {
+            match self.event_rx.recv().await {
+                Ok(Event {
+                    payload: Payload::BlockReceived(block_id),
+                    ..
+                }) => {
+                    if self.missing.remove(&block_id) {
+                        return Ok(true);
+                    }
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => {
+                    // Some events were missed, check the missing blocks directly.
+                    let count = self.missing.len();
+                    let mut reader = repo.shared.vault.store().acquire_read().await?;
+                    let mut missing = HashSet::default();
+
+                    for block_id in self.missing.drain() {
+                        if !reader.block_exists(&block_id).await? {
+                            missing.insert(block_id);
+                        }
+                    }
+
+                    self.missing = missing;
+
+                    if self.missing.len() < count {
+                        return Ok(true);
+                    }
+                }
+                Err(RecvError::Closed) => return Ok(false),
+            }
+        }
+    }
+}
+
+async fn visit(
+    entry: JointEntryRef<'_>,
+    dirs: &mut Vec<JointDirectory>,
+    files: &mut Vec<(Branch, BlobId)>,
+) -> Result<()> {
+    match entry {
+        JointEntryRef::File(entry) => {
+            files.push((entry.branch().clone(), *entry.inner().blob_id()));
+        }
+        JointEntryRef::Directory(entry) => {
+            dirs.push(entry.open().await?);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use crate::{
+    blob::BlobId,
+    branch::Branch,
+    directory::{Directory, DirectoryFallback, DirectoryLocking, EntryRef},
+    error::{Error, Result},
+    path,
+    version_vector::VersionVector,
+};
+use camino::{Utf8Component, Utf8Path};
+use std::collections::VecDeque;
+
+/// Copies the entry at `path` in `src_branch` (recursively, if it's a directory) into the local
+/// branch. See `Repository::pull_from` for details.
+pub(super) async fn pull(
+    src_branch: &Branch,
+    local_branch: &Branch,
+    path: &Utf8Path,
+) -> Result<()> {
+    let Some((parent, name)) = path::decompose(path) else {
+        let src = src_branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await?;
+        return pull_directory(src, local_branch).await;
+    };
+
+    let src_parent = open_directory(src_branch, parent).await?;
+
+    match src_parent.lookup(name)? {
+        EntryRef::File(entry) => {
+            let mut dst_parent = src_parent.fork(local_branch).await?;
+            dst_parent
+                .pull_file(
+                    name.to_owned(),
+                    src_branch,
+                    *entry.blob_id(),
+                    entry.version_vector(),
+                )
+                .await
+        }
+        EntryRef::Directory(entry) => {
+            let src = entry.open(DirectoryFallback::Disabled).await?;
+            pull_directory(src, local_branch).await
+        }
+        EntryRef::Tombstone(_) => Err(Error::EntryNotFound),
+    }
+}
+
+/// Opens the directory at `path` in the given (possibly remote) branch.
+async fn open_directory(branch: &Branch, path: &Utf8Path) -> Result<Directory> {
+    let mut curr = branch
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await?;
+
+    for component in path.components() {
+        match component {
+            Utf8Component::RootDir | Utf8Component::CurDir => (),
+            Utf8Component::Normal(name) => {
+                curr = match curr.lookup(name)? {
+                    EntryRef::Directory(entry) => entry.open(DirectoryFallback::Disabled).await?,
+                    EntryRef::File(_) => return Err(Error::EntryIsFile),
+                    EntryRef::Tombstone(_) => return Err(Error::EntryNotFound),
+                };
+            }
+            Utf8Component::Prefix(_) | Utf8Component::ParentDir => {
+                return Err(Error::OperationNotSupported)
+            }
+        }
+    }
+
+    Ok(curr)
+}
+
+enum Action {
+    Pull(BlobId, VersionVector),
+    Remove(VersionVector),
+}
+
+async fn pull_directory(src: Directory, local_branch: &Branch) -> Result<()> {
+    let mut queue = VecDeque::from([src]);
+
+    while let Some(src) = queue.pop_front() {
+        let mut dst = src.fork(local_branch).await?;
+
+        let mut actions = Vec::new();
+
+        for entry in src.entries() {
+            match entry {
+                EntryRef::File(entry) => actions.push((
+                    entry.name().to_owned(),
+                    Action::Pull(*entry.blob_id(), entry.version_vector().clone()),
+                )),
+                EntryRef::Directory(entry) => {
+                    queue.push_back(entry.open(DirectoryFallback::Disabled).await?);
+                }
+                EntryRef::Tombstone(entry) => actions.push((
+                    entry.name().to_owned(),
+                    Action::Remove(entry.version_vector().clone()),
+                )),
+            }
+        }
+
+        for (name, action) in actions {
+            match action {
+                Action::Pull(blob_id, version_vector) => {
+                    dst.pull_file(name, src.branch(), blob_id, &version_vector)
+                        .await?;
+                }
+                Action::Remove(version_vector) => {
+                    // Only files removed in the source branch are removed locally. Local
+                    // directories are kept because they might still contain entries unknown to
+                    // the source branch.
+                    let local_vv = match dst.lookup(&name) {
+                        Ok(EntryRef::File(entry)) => entry.version_vector().clone(),
+                        Ok(EntryRef::Directory(_) | EntryRef::Tombstone(_))
+                        | Err(Error::EntryNotFound) => continue,
+                        Err(error) => return Err(error),
+                    };
+
+                    let branch_id = *local_branch.id();
+                    dst.remove_entry(&name, &branch_id, local_vv.merged(&version_vector))
+                        .await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
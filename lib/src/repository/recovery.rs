@@ -0,0 +1,13 @@
+/// Outcome of `Repository::recover`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct RecoveryReport {
+    /// Number of database rows (blocks, index nodes, metadata entries, ...) copied into the
+    /// recovered repository.
+    pub recovered: u64,
+    /// Number of blocks referenced by the index that couldn't be recovered (or were recovered
+    /// damaged). They are marked as missing.
+    pub lost_blocks: u64,
+    /// Whether everything could be recovered. If `false`, some data was unreadable and is missing
+    /// from the recovered repository. Missing blocks can still be downloaded from other replicas.
+    pub complete: bool,
+}
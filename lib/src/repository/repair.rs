@@ -0,0 +1,134 @@
+use super::Repository;
+use crate::{
+    collections::HashSet,
+    error::Result,
+    event::{Event, Payload},
+    protocol::BlockId,
+};
+use std::time::Duration;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{self, Instant},
+};
+
+/// How long to wait for the next damaged block to be received before giving up.
+pub(super) const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Progress of `Repository::verify_and_repair`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct RepairProgress {
+    /// Number of the locally stored blocks that have been verified.
+    pub verified: u64,
+    /// Number of blocks whose content didn't match their id.
+    pub corrupt: u64,
+    /// Number of blocks that were referenced as present but weren't actually stored.
+    pub missing: u64,
+    /// Number of the corrupt or missing blocks that have been downloaded again.
+    pub repaired: u64,
+}
+
+/// State of `Repository::verify_and_repair`.
+pub(super) struct Repair {
+    // Subscribed before the blocks are verified so no received block can be missed.
+    event_rx: broadcast::Receiver<Event>,
+    progress: RepairProgress,
+    // Damaged blocks that are still referenced and so can be downloaded again.
+    pending: HashSet<BlockId>,
+    idle_timeout: Duration,
+    // Moved forward only when one of the pending blocks is received, not by unrelated events.
+    deadline: Instant,
+}
+
+impl Repair {
+    /// Verifies all the blocks, marks the damaged ones as missing and requires them from the
+    /// peers.
+    pub async fn start(repo: &Repository, idle_timeout: Duration) -> Result<Self> {
+        let event_rx = repo.subscribe();
+        let store = repo.shared.vault.store();
+        let repair = store.repair_blocks().await?;
+
+        let mut reader = store.acquire_read().await?;
+        let mut pending = HashSet::default();
+
+        for block_id in repair.corrupt.iter().chain(&repair.lost) {
+            // Unreferenced corrupt blocks are just removed.
+            if reader.is_block_missing(block_id).await? {
+                pending.insert(*block_id);
+            }
+        }
+
+        let block_tracker = &repo.shared.vault.block_tracker;
+
+        for block_id in &pending {
+            block_tracker.require(*block_id);
+        }
+
+        Ok(Self {
+            event_rx,
+            progress: RepairProgress {
+                verified: repair.verified,
+                corrupt: repair.corrupt.len() as u64,
+                missing: repair.lost.len() as u64,
+                repaired: 0,
+            },
+            pending,
+            idle_timeout,
+            deadline: Instant::now() + idle_timeout,
+        })
+    }
+
+    pub fn progress(&self) -> RepairProgress {
+        self.progress
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Waits until at least one of the pending blocks is received. Returns `false` if the
+    /// repository has been closed or if none of the pending blocks has been received within the
+    /// idle timeout since the start or since the last received one.
+    pub async fn wait(&mut self, repo: &Repository) -> Result<bool> {
+        loop {
+            let Ok(result) = time::timeout_at(self.deadline, self.event_rx.recv()).await else {
+                return Ok(false);
+            };
+
+            let repaired = match result {
+                Ok(Event {
+                    payload: Payload::BlockReceived(block_id),
+                    ..
+                }) => {
+                    if self.pending.remove(&block_id) {
+                        1
+                    } else {
+                        0
+                    }
+                }
+                Ok(_) => 0,
+                Err(RecvError::Lagged(_)) => {
+                    // Some events were missed, check the pending blocks directly.
+                    let count = self.pending.len();
+                    let mut reader = repo.shared.vault.store().acquire_read().await?;
+                    let mut pending = HashSet::default();
+
+                    for block_id in self.pending.drain() {
+                        if !reader.block_exists(&block_id).await? {
+                            pending.insert(block_id);
+                        }
+                    }
+
+                    self.pending = pending;
+                    count - self.pending.len()
+                }
+                Err(RecvError::Closed) => return Ok(false),
+            };
+
+            if repaired > 0 {
+                self.progress.repaired += repaired as u64;
+                self.deadline = Instant::now() + self.idle_timeout;
+                return Ok(true);
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use super::Repository;
+use crate::{
+    error::{Error, Result},
+    joint_directory::JointEntryRef,
+    storage_size::StorageSize,
+    store,
+};
+
+/// Storage usage of a repository. Obtained with `Repository::sizes`.
+///
+/// Comparing the sizes explains where the space goes: `logical` larger than `deduplicated` means
+/// some data is shared among files, file versions or branches. `physical_db_file` can't be
+/// directly compared to `deduplicated` because the blocks may be stored compressed (see
+/// `Repository::set_block_compression_enabled`) and because it counts also the write-ahead log and
+/// the index. So it being smaller doesn't mean the repository hasn't been fully downloaded (use
+/// `Repository::sync_progress` for that).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct RepositorySizes {
+    /// Sum of the lengths of all the files in the repository as presented to the user (concurrent
+    /// versions of the same file are each counted). Files whose length isn't known yet because
+    /// their first block hasn't been downloaded are not counted.
+    pub logical: StorageSize,
+    /// Uncompressed size of the distinct blocks referenced from the index, whether downloaded or
+    /// not. Blocks shared by multiple files or branches are counted only once. Includes also the
+    /// blocks of the directories and of the snapshots not yet pruned.
+    pub deduplicated: StorageSize,
+    /// Size of the database files on disk, including the write-ahead log. Includes the index, the
+    /// downloaded (possibly compressed) blocks, the blocks not yet garbage collected and the free
+    /// space not yet reclaimed.
+    pub physical_db_file: StorageSize,
+}
+
+// Sum of the lengths of all the files, in bytes.
+pub(super) async fn logical_size(repo: &Repository) -> Result<u64> {
+    let mut dirs = vec![repo.cd("/").await?];
+    let mut size = 0u64;
+
+    while let Some(dir) = dirs.pop() {
+        for entry in dir.entries() {
+            // Skip the entries not downloaded yet.
+            match entry {
+                JointEntryRef::File(entry) => match entry.open().await {
+                    Ok(file) => size = size.saturating_add(file.len()),
+                    Err(Error::Store(store::Error::BlockNotFound)) => (),
+                    Err(error) => return Err(error),
+                },
+                JointEntryRef::Directory(entry) => match entry.open().await {
+                    Ok(dir) => dirs.push(dir),
+                    Err(Error::Store(store::Error::BlockNotFound)) => (),
+                    Err(error) => return Err(error),
+                },
+            }
+        }
+    }
+
+    Ok(size)
+}
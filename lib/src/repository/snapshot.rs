@@ -0,0 +1,269 @@
+use super::stat;
+use crate::{
+    blob::{Blob, ReadWriteError},
+    branch::Branch,
+    collections::HashMap,
+    crypto::sign::PublicKey,
+    directory::{Directory, EntryType, FileRef},
+    error::{Error, Result},
+    joint_directory::{JointDirectory, JointEntryRef},
+    path,
+    progress::Progress,
+    protocol::RootNode,
+    store::{self, ReadTransaction},
+    version_vector::VersionVector,
+};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use deadlock::AsyncMutex;
+use std::{borrow::Cow, io::SeekFrom, sync::Arc};
+
+/// Read-only view of the repository pinned to the snapshots its branches had at the time it was
+/// created. All reads through it observe the same state even if the branches are modified or
+/// synced in the meantime, so they are consistent with each other. See
+/// `Repository::read_snapshot`.
+///
+/// The view keeps a database read transaction open for as long as it (or any file opened through
+/// it) is alive, which prevents the database from reclaiming the space of the data removed in the
+/// meantime. It should therefore be dropped as soon as the reads are done.
+#[derive(Clone)]
+pub struct ReadSnapshot {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    tx: AsyncMutex<ReadTransaction>,
+    branches: Vec<Branch>,
+    root_nodes: HashMap<PublicKey, RootNode>,
+}
+
+impl ReadSnapshot {
+    pub(super) fn new(tx: ReadTransaction, branches: Vec<(Branch, RootNode)>) -> Self {
+        let (branches, root_nodes) = branches
+            .into_iter()
+            .map(|(branch, root_node)| {
+                let id = *branch.id();
+                (branch, (id, root_node))
+            })
+            .unzip();
+
+        Self {
+            shared: Arc::new(Shared {
+                tx: AsyncMutex::new(tx),
+                branches,
+                root_nodes,
+            }),
+        }
+    }
+
+    /// Opens the file at the given path for reading as of this snapshot.
+    pub async fn open_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<SnapshotFile> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+        let dir = self.cd_joint(parent).await?;
+        let entry = dir.lookup_unique(name)?.file()?;
+
+        let root_node = self.root_node(entry.branch().id())?;
+        let mut tx = self.shared.tx.lock().await;
+        let blob =
+            Blob::open_at(&mut tx, root_node, entry.branch().clone(), *entry.blob_id()).await?;
+
+        Ok(SnapshotFile {
+            snapshot: self.clone(),
+            blob,
+        })
+    }
+
+    /// Opens the directory at the given path as of this snapshot.
+    pub async fn cd<P: AsRef<Utf8Path>>(&self, path: P) -> Result<SnapshotDirectory> {
+        let path = path.as_ref();
+        let dir = self.cd_joint(path).await?;
+
+        Ok(SnapshotDirectory {
+            snapshot: self.clone(),
+            path: path.to_owned(),
+            dir,
+        })
+    }
+
+    /// Like `cd` but returns the underlying `JointDirectory`. Its entries should be opened only
+    /// through this snapshot (opening them directly reads their current state).
+    pub(super) async fn cd_joint(&self, path: &Utf8Path) -> Result<JointDirectory> {
+        let mut tx = self.shared.tx.lock().await;
+        let mut curr = self.root(&mut tx).await?;
+
+        for component in path.components() {
+            match component {
+                Utf8Component::RootDir | Utf8Component::CurDir => (),
+                Utf8Component::Normal(name) => {
+                    let entry = curr
+                        .lookup(name)
+                        .find_map(|entry| entry.directory().ok())
+                        .ok_or(Error::EntryNotFound)?;
+
+                    let mut versions = Vec::new();
+
+                    for version in entry.versions() {
+                        let root_node = self.root_node(version.branch().id())?;
+
+                        match version.open_at(&mut tx, root_node).await {
+                            Ok(dir) => versions.push(dir),
+                            // Some versions might not be fully downloaded yet. Treat them as if
+                            // they didn't exist (same as `Repository::cd`).
+                            Err(Error::Store(store::Error::BlockNotFound)) => continue,
+                            Err(error) => return Err(error),
+                        }
+                    }
+
+                    let next = JointDirectory::new(None, versions);
+                    curr = next;
+                }
+                Utf8Component::ParentDir | Utf8Component::Prefix(_) => {
+                    return Err(Error::OperationNotSupported)
+                }
+            }
+        }
+
+        Ok(curr)
+    }
+
+    async fn root(&self, tx: &mut ReadTransaction) -> Result<JointDirectory> {
+        let mut dirs = Vec::new();
+
+        for branch in &self.shared.branches {
+            let root_node = self.root_node(branch.id())?;
+
+            let dir = match Directory::open_root_at(tx, root_node, branch.clone()).await {
+                Ok(dir) => dir,
+                // Some branch root blocks may not have been downloaded yet.
+                Err(Error::Store(store::Error::BlockNotFound)) => continue,
+                Err(error) => return Err(error),
+            };
+
+            dirs.push(dir);
+        }
+
+        Ok(JointDirectory::new(None, dirs))
+    }
+
+    /// Returns the length of the file and how many of its blocks are present, as of this
+    /// snapshot. See `Repository::stat`.
+    pub(super) async fn file_blocks(&self, entry: &FileRef<'_>) -> Result<(u64, Progress)> {
+        let root_node = self.root_node(entry.branch().id())?;
+        let mut tx = self.shared.tx.lock().await;
+
+        stat::file_blocks(&mut tx, root_node, entry.branch(), *entry.blob_id()).await
+    }
+
+    pub(super) fn root_node(&self, branch_id: &PublicKey) -> Result<&RootNode> {
+        self.shared
+            .root_nodes
+            .get(branch_id)
+            .ok_or(Error::Store(store::Error::BranchNotFound))
+    }
+}
+
+/// Directory opened through a [`ReadSnapshot`]. Its entries, as well as the subdirectories and
+/// files opened through it, are as of that snapshot.
+pub struct SnapshotDirectory {
+    snapshot: ReadSnapshot,
+    path: Utf8PathBuf,
+    dir: JointDirectory,
+}
+
+impl SnapshotDirectory {
+    /// Path of this directory.
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Entries of this directory.
+    pub fn entries(&self) -> impl Iterator<Item = SnapshotEntry<'_>> {
+        self.dir.entries().map(SnapshotEntry)
+    }
+
+    /// Opens the subdirectory with the given name as of the snapshot.
+    pub async fn cd(&self, name: &str) -> Result<SnapshotDirectory> {
+        self.snapshot.cd(self.path.join(name)).await
+    }
+
+    /// Opens the file with the given name for reading as of the snapshot.
+    pub async fn open_file(&self, name: &str) -> Result<SnapshotFile> {
+        self.snapshot.open_file(self.path.join(name)).await
+    }
+}
+
+/// Entry of a [`SnapshotDirectory`].
+pub struct SnapshotEntry<'a>(JointEntryRef<'a>);
+
+impl<'a> SnapshotEntry<'a> {
+    pub fn name(&self) -> &'a str {
+        self.0.name()
+    }
+
+    /// Name of the entry which is unique within its directory, even if there are several
+    /// concurrent versions of it (see `JointEntryRef::unique_name`).
+    pub fn unique_name(&self) -> Cow<'a, str> {
+        self.0.unique_name()
+    }
+
+    pub fn entry_type(&self) -> EntryType {
+        self.0.entry_type()
+    }
+
+    pub fn version_vector(&'a self) -> Cow<'a, VersionVector> {
+        self.0.version_vector()
+    }
+}
+
+/// File opened through a [`ReadSnapshot`]. Reads its content as of that snapshot.
+pub struct SnapshotFile {
+    snapshot: ReadSnapshot,
+    blob: Blob,
+}
+
+impl SnapshotFile {
+    /// Length of this file in bytes.
+    pub fn len(&self) -> u64 {
+        self.blob.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sets the read position. See [`File::seek`](crate::File::seek).
+    pub fn seek(&mut self, pos: SeekFrom) -> u64 {
+        self.blob.seek(pos)
+    }
+
+    /// Reads data from this file into `buffer`, advancing the read position. Returns the number
+    /// of bytes actually read which might be less than `buffer.len()`.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.blob.read(buffer) {
+                Ok(len) => return Ok(len),
+                Err(ReadWriteError::CacheMiss) => {
+                    let root_node = self.snapshot.root_node(self.blob.branch().id())?;
+                    let mut tx = self.snapshot.shared.tx.lock().await;
+                    self.blob.warmup_at(&mut tx, root_node).await?;
+                }
+                Err(ReadWriteError::NoBuffer) => {
+                    self.blob.wait_for_buffer().await;
+                }
+                Err(ReadWriteError::CacheFull) => {
+                    // Can't happen because the blob is never modified and so all its cached
+                    // blocks can be evicted.
+                    tracing::error!("cache full");
+                    return Err(Error::OperationNotSupported);
+                }
+                Err(ReadWriteError::TooLarge) => unreachable!("reading never extends the blob"),
+            }
+        }
+    }
+
+    /// Reads all data from this file from the current read position until the end.
+    pub async fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let root_node = self.snapshot.root_node(self.blob.branch().id())?;
+        let mut tx = self.snapshot.shared.tx.lock().await;
+        self.blob.read_to_end_at(&mut tx, root_node).await
+    }
+}
@@ -0,0 +1,149 @@
+use super::Repository;
+use crate::{
+    blob::{Blob, BlobId},
+    branch::Branch,
+    crypto::sign::PublicKey,
+    directory::EntryType,
+    error::Result,
+    joint_directory::JointEntryRef,
+    path,
+    progress::Progress,
+    protocol::{Locator, RootNode},
+    store::{self, ReadTransaction},
+    version_vector::VersionVector,
+};
+use camino::Utf8Path;
+
+/// Metadata of a file or directory. Obtained with `Repository::stat`.
+#[derive(Clone, Debug)]
+pub struct EntryMetadata {
+    /// Whether the entry is a file or a directory.
+    pub entry_type: EntryType,
+    /// Length of the file in bytes. Zero for directories.
+    pub len: u64,
+    /// Id of the writer whose branch the entry is in. A directory can exist in several branches
+    /// (whose versions are merged when it's read), in which case this is the local branch if it's
+    /// one of them or any of them otherwise.
+    pub writer_id: PublicKey,
+    /// Version vector of the entry. For directories it's merged from all their versions.
+    pub version_vector: VersionVector,
+    /// Number of blocks of the file available locally / total number of its blocks. The file is
+    /// fully local when the two are equal. Zero for directories.
+    pub blocks: Progress,
+}
+
+impl EntryMetadata {
+    /// Are all the blocks of the entry available locally?
+    pub fn is_complete(&self) -> bool {
+        self.blocks.value == self.blocks.total
+    }
+}
+
+pub(super) async fn stat(repo: &Repository, path: &Utf8Path) -> Result<EntryMetadata> {
+    let local_id = repo.credentials().writer_id;
+
+    // Everything is read from a single snapshot so the directories, the entry and its blocks are
+    // consistent with each other even if the repository is being modified or synced meanwhile.
+    let snapshot = repo.read_snapshot().await?;
+
+    let Some((parent, name)) = path::decompose(path) else {
+        let root = snapshot.cd_joint(Utf8Path::new("/")).await?;
+        let mut version_vector = VersionVector::new();
+
+        for dir in root.versions() {
+            let root_node = snapshot.root_node(dir.branch().id())?;
+            version_vector.merge(&root_node.proof.version_vector);
+        }
+
+        return Ok(EntryMetadata {
+            entry_type: EntryType::Directory,
+            len: 0,
+            writer_id: pick_writer_id(root.versions().map(|dir| dir.branch().id()), local_id),
+            version_vector,
+            blocks: Progress::default(),
+        });
+    };
+
+    let parent = snapshot.cd_joint(parent).await?;
+
+    match parent.lookup_unique(name)? {
+        JointEntryRef::File(entry) => {
+            let (len, blocks) = snapshot.file_blocks(entry.inner()).await?;
+
+            Ok(EntryMetadata {
+                entry_type: EntryType::File,
+                len,
+                writer_id: *entry.branch().id(),
+                version_vector: entry.version_vector().clone(),
+                blocks,
+            })
+        }
+        JointEntryRef::Directory(entry) => Ok(EntryMetadata {
+            entry_type: EntryType::Directory,
+            len: 0,
+            writer_id: pick_writer_id(
+                entry.versions().iter().map(|dir| dir.branch().id()),
+                local_id,
+            ),
+            version_vector: entry.version_vector(),
+            blocks: Progress::default(),
+        }),
+    }
+}
+
+/// Returns the length of the blob and how many of its blocks are present at the given snapshot of
+/// its branch. Blocks whose nodes haven't been received yet count as missing.
+pub(super) async fn file_blocks(
+    tx: &mut ReadTransaction,
+    root_node: &RootNode,
+    branch: &Branch,
+    blob_id: BlobId,
+) -> Result<(u64, Progress)> {
+    let blob = Blob::open_at(tx, root_node, branch.clone(), blob_id).await?;
+    let read_key = branch.keys().read();
+
+    // The first block is present, otherwise the blob couldn't have been opened.
+    let mut present = 1;
+
+    for locator in Locator::head(blob_id)
+        .sequence()
+        .take(blob.block_count() as usize)
+        .skip(1)
+    {
+        let block_id = match tx.find_block_at(root_node, &locator.encode(read_key)).await {
+            Ok(block_id) => block_id,
+            Err(store::Error::LocatorNotFound | store::Error::BlockNotFound) => continue,
+            Err(error) => return Err(error.into()),
+        };
+
+        if tx.block_exists(&block_id).await? {
+            present += 1;
+        }
+    }
+
+    Ok((
+        blob.len(),
+        Progress {
+            value: present,
+            total: blob.block_count().into(),
+        },
+    ))
+}
+
+fn pick_writer_id<'a>(
+    writer_ids: impl Iterator<Item = &'a PublicKey>,
+    local_id: PublicKey,
+) -> PublicKey {
+    let mut first = None;
+
+    for writer_id in writer_ids {
+        if *writer_id == local_id {
+            return local_id;
+        }
+
+        first.get_or_insert(*writer_id);
+    }
+
+    // Only the root of a repository with no branches yet has no versions.
+    first.unwrap_or(local_id)
+}
@@ -0,0 +1,23 @@
+use crate::{access_control::AccessMode, progress::Progress, version_vector::VersionVector};
+
+/// Snapshot of the overall state of a repository. Obtained with `Repository::status`.
+#[derive(Clone, Debug)]
+pub struct RepositoryStatus {
+    /// Access mode of the repository.
+    pub access_mode: AccessMode,
+    /// Number of branches (local and remote).
+    pub branch_count: usize,
+    /// Version vector of the local branch (empty if the local branch doesn't exist yet).
+    pub local_version_vector: VersionVector,
+    /// Number of downloaded blocks / number of all blocks.
+    pub sync_progress: Progress,
+    /// Number of connected peers this repository is currently linked with.
+    pub linked_peer_count: usize,
+    /// Is DHT enabled for this repository?
+    pub dht_enabled: bool,
+    /// Is peer exchange enabled for this repository?
+    pub pex_enabled: bool,
+    /// Is there any remote branch with changes not yet merged into the local branch? Always
+    /// `false` if the repository is not in write mode.
+    pub merge_pending: bool,
+}
@@ -0,0 +1,258 @@
+//! Selective sync - downloading the content of only those files that match a set of path patterns.
+//!
+//! The filter affects only which blocks are marked as required (and so downloaded) by the
+//! background scan. The index is still synced fully, so directory listings show all entries
+//! including those whose content is not downloaded. Directories themselves are always synced.
+//!
+//! When the filter changes or when a file starts to match it (e.g., because it was moved into a
+//! matching directory), the scan is run again and the content of the newly matching files is
+//! downloaded. Files that stop matching don't have their already downloaded content removed.
+
+use camino::{Utf8Component, Utf8Path};
+
+/// Set of glob-style path patterns determining which files have their content synced.
+///
+/// Patterns starting with `!` are excludes, all others are includes. A file is synced if it
+/// matches at least one include (or there are no includes) and none of the excludes. A pattern
+/// that matches a directory matches also everything inside it.
+///
+/// Pattern syntax:
+///
+/// - `?` matches any single character except `/`
+/// - `*` matches any sequence of characters except `/`
+/// - `**` as the whole path component matches any number of path components
+///
+/// Patterns are matched against the path relative to the repository root, so leading `/` is
+/// optional.
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub(crate) struct SyncFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl SyncFilter {
+    pub fn new<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut filter = Self::default();
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+
+            if let Some(pattern) = pattern.strip_prefix('!') {
+                filter.exclude.push(Pattern::new(pattern));
+            } else {
+                filter.include.push(Pattern::new(pattern));
+            }
+        }
+
+        filter
+    }
+
+    /// Does this filter allow everything?
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Returns the patterns this filter was created from (includes first, then excludes).
+    pub fn patterns(&self) -> Vec<String> {
+        self.include
+            .iter()
+            .map(|pattern| pattern.source.clone())
+            .chain(
+                self.exclude
+                    .iter()
+                    .map(|pattern| format!("!{}", pattern.source)),
+            )
+            .collect()
+    }
+
+    /// Should the content of the file at the given path be synced?
+    pub fn matches(&self, path: &Utf8Path) -> bool {
+        let path: Vec<_> = path
+            .components()
+            .filter_map(|component| match component {
+                Utf8Component::Normal(name) => Some(name),
+                Utf8Component::Prefix(_)
+                | Utf8Component::RootDir
+                | Utf8Component::CurDir
+                | Utf8Component::ParentDir => None,
+            })
+            .collect();
+
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(&path)))
+            && !self.exclude.iter().any(|pattern| pattern.matches(&path))
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct Pattern {
+    source: String,
+    components: Vec<Vec<char>>,
+}
+
+impl Pattern {
+    fn new(source: &str) -> Self {
+        Self {
+            source: source.to_owned(),
+            components: source
+                .split('/')
+                .filter(|component| !component.is_empty())
+                .map(|component| component.chars().collect())
+                .collect(),
+        }
+    }
+
+    fn matches(&self, path: &[&str]) -> bool {
+        match_components(&self.components, path)
+    }
+}
+
+// Returns whether `pattern` matches `path` or any of its ancestors.
+fn match_components(pattern: &[Vec<char>], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => true,
+        Some((first, rest)) if first[..] == ['*', '*'] => {
+            (0..=path.len()).any(|skip| match_components(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                let name: Vec<_> = name.chars().collect();
+                match_name(first, &name) && match_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+// Returns whether `pattern` (which can contain the `*` and `?` wildcards) matches the whole
+// `name`. Runs in `O(pattern.len() * name.len())` time: on a mismatch it backtracks only to the
+// last `*` seen, making it match one more character.
+pub(super) fn match_name(pattern: &[char], name: &[char]) -> bool {
+    let mut p = 0;
+    let mut n = 0;
+    // Position of the last `*` in `pattern` and of the first character in `name` it hasn't matched
+    // yet.
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let filter = SyncFilter::new::<_, &str>([]);
+        assert!(filter.is_empty());
+        assert!(filter.matches(Utf8Path::new("a.txt")));
+        assert!(filter.matches(Utf8Path::new("a/b/c.txt")));
+    }
+
+    #[test]
+    fn include_directory() {
+        let filter = SyncFilter::new(["/photos"]);
+        assert!(filter.matches(Utf8Path::new("photos/a.jpg")));
+        assert!(filter.matches(Utf8Path::new("photos/2024/a.jpg")));
+        assert!(!filter.matches(Utf8Path::new("docs/a.txt")));
+        assert!(!filter.matches(Utf8Path::new("photos.txt")));
+    }
+
+    #[test]
+    fn wildcards() {
+        let filter = SyncFilter::new(["*.jpg"]);
+        assert!(filter.matches(Utf8Path::new("a.jpg")));
+        assert!(!filter.matches(Utf8Path::new("a.png")));
+        assert!(!filter.matches(Utf8Path::new("photos/a.jpg")));
+
+        let filter = SyncFilter::new(["**/*.jpg"]);
+        assert!(filter.matches(Utf8Path::new("a.jpg")));
+        assert!(filter.matches(Utf8Path::new("photos/a.jpg")));
+        assert!(filter.matches(Utf8Path::new("photos/2024/a.jpg")));
+        assert!(!filter.matches(Utf8Path::new("photos/2024/a.png")));
+
+        let filter = SyncFilter::new(["photos/202?/*"]);
+        assert!(filter.matches(Utf8Path::new("photos/2024/a.jpg")));
+        assert!(!filter.matches(Utf8Path::new("photos/24/a.jpg")));
+
+        let filter = SyncFilter::new(["?.txt"]);
+        assert!(filter.matches(Utf8Path::new("ž.txt")));
+        assert!(!filter.matches(Utf8Path::new("ab.txt")));
+    }
+
+    #[test]
+    fn match_name_wildcards() {
+        let check = |pattern: &str, name: &str| {
+            let pattern: Vec<_> = pattern.chars().collect();
+            let name: Vec<_> = name.chars().collect();
+            match_name(&pattern, &name)
+        };
+
+        assert!(check("", ""));
+        assert!(!check("", "a"));
+        assert!(check("*", ""));
+        assert!(check("**", "abc"));
+        assert!(check("a*c", "abbbc"));
+        assert!(check("a*b*c", "aXbYbZc"));
+        assert!(!check("a*b*c", "aXbYbZ"));
+        assert!(check("*.jpg", ".jpg"));
+        assert!(!check("?", ""));
+        assert!(check("a?c*", "abcdef"));
+        assert!(!check("a?c", "abcd"));
+    }
+
+    #[test]
+    fn match_name_pathological() {
+        // Takes exponential time with the naive recursive matcher.
+        let pattern: Vec<_> = "a*".repeat(32).chars().chain(['b']).collect();
+        let name: Vec<_> = "a".repeat(64).chars().collect();
+        assert!(!match_name(&pattern, &name));
+
+        let name: Vec<_> = "a".repeat(64).chars().chain(['b']).collect();
+        assert!(match_name(&pattern, &name));
+    }
+
+    #[test]
+    fn exclude() {
+        let filter = SyncFilter::new(["photos", "!photos/raw", "!**/*.tmp"]);
+        assert!(filter.matches(Utf8Path::new("photos/a.jpg")));
+        assert!(!filter.matches(Utf8Path::new("photos/raw/a.cr2")));
+        assert!(!filter.matches(Utf8Path::new("photos/a.tmp")));
+        assert!(!filter.matches(Utf8Path::new("docs/a.txt")));
+
+        // Only excludes
+        let filter = SyncFilter::new(["!videos"]);
+        assert!(filter.matches(Utf8Path::new("photos/a.jpg")));
+        assert!(!filter.matches(Utf8Path::new("videos/a.mp4")));
+    }
+
+    #[test]
+    fn patterns_roundtrip() {
+        let filter = SyncFilter::new(["photos", "!photos/raw"]);
+        assert_eq!(SyncFilter::new(filter.patterns()), filter);
+    }
+}
@@ -1,7 +1,11 @@
 use super::*;
 use crate::{
-    blob, db,
-    protocol::{BlockId, BLOCK_NONCE_SIZE, BLOCK_SIZE},
+    blob,
+    crypto::{cipher::SecretKey, KdfParams, Password},
+    db,
+    event::Payload,
+    protocol::{Block, BlockContent, BlockId, BLOCK_NONCE_SIZE, BLOCK_SIZE},
+    store::Changeset,
     test_utils, LocalSecret, SetLocalSecret, WriteSecrets,
 };
 use assert_matches::assert_matches;
@@ -163,6 +167,82 @@ async fn recreate_previously_deleted_directory() {
     assert_matches!(repo.open_directory("test").await, Ok(_));
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn create_dirs() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("a").await.unwrap();
+    repo.create_file("f").await.unwrap().flush().await.unwrap();
+
+    let local_branch = repo.local_branch().unwrap();
+    let vv_0 = local_branch.version_vector().await.unwrap();
+
+    let mut rx = repo.subscribe();
+
+    let paths: Vec<Utf8PathBuf> = ["a/b/c", "a/d", "/e/f/g", "a/b"]
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    repo.create_dirs(&paths).await.unwrap();
+
+    for path in ["a/b", "a/b/c", "a/d", "e", "e/f", "e/f/g"] {
+        assert_matches!(repo.open_directory(path).await, Ok(_), "{path}");
+    }
+
+    // Only one snapshot was created.
+    let mut branch_changed_count = 0;
+
+    while let Ok(event) = rx.try_recv() {
+        if matches!(event.payload, Payload::BranchChanged(_)) {
+            branch_changed_count += 1;
+        }
+    }
+
+    assert_eq!(branch_changed_count, 1);
+
+    let vv_1 = local_branch.version_vector().await.unwrap();
+    assert!(vv_1 > vv_0);
+
+    // Creating already existing directories is a no-op.
+    repo.create_dirs(&paths).await.unwrap();
+    assert_eq!(local_branch.version_vector().await.unwrap(), vv_1);
+
+    // Using a file as a directory fails and leaves the repository unchanged.
+    let paths: Vec<Utf8PathBuf> = vec!["x".into(), "f/y".into()];
+    assert_matches!(repo.create_dirs(&paths).await, Err(Error::EntryIsFile));
+    assert_matches!(repo.open_directory("x").await, Err(Error::EntryNotFound));
+    assert_eq!(local_branch.version_vector().await.unwrap(), vv_1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_len() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.set_len("test.dat", 5).await.unwrap();
+    assert_eq!(read_file(&repo, "test.dat").await, b"hello");
+
+    let new_len = 3 * BLOCK_SIZE + 1;
+    repo.set_len("test.dat", new_len as u64).await.unwrap();
+
+    let content = read_file(&repo, "test.dat").await;
+    assert_eq!(content.len(), new_len);
+    assert_eq!(&content[..5], b"hello");
+    assert!(content[5..].iter().all(|byte| *byte == 0));
+
+    repo.truncate_file("test.dat").await.unwrap();
+    assert_eq!(read_file(&repo, "test.dat").await, b"");
+
+    assert_matches!(
+        repo.set_len("missing.dat", 1).await,
+        Err(Error::EntryNotFound)
+    );
+}
+
 // This one used to deadlock
 #[tokio::test(flavor = "multi_thread")]
 async fn concurrent_read_and_create_dir() {
@@ -277,925 +357,3870 @@ async fn append_to_file() {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn move_file_onto_non_existing_entry() {
-    let (_base_dir, repo) = setup().await;
-
-    repo.create_file("src.txt").await.unwrap();
-    repo.move_entry("/", "src.txt", "/", "dst.txt")
-        .await
-        .unwrap();
-
-    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
-    assert_matches!(repo.open_file("dst.txt").await, Ok(_));
-}
+async fn open_range() {
+    use tokio::io::AsyncReadExt;
 
-#[tokio::test(flavor = "multi_thread")]
-async fn move_file_onto_tombstone() {
     let (_base_dir, repo) = setup().await;
 
-    repo.create_file("src.txt").await.unwrap();
+    let mut content = vec![0; 3 * BLOCK_SIZE];
+    rand::thread_rng().fill(&mut content[..]);
 
-    repo.create_file("dst.txt").await.unwrap();
-    repo.remove_entry("dst.txt").await.unwrap();
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-    repo.move_entry("/", "src.txt", "/", "dst.txt")
-        .await
-        .unwrap();
+    let len = content.len() as u64;
 
-    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
-    assert_matches!(repo.open_file("dst.txt").await, Ok(_));
-}
+    for (start, end) in [
+        (0, len),
+        (10, 20),
+        (BLOCK_SIZE as u64 - 5, 2 * BLOCK_SIZE as u64 + 5),
+        (len - 10, len + 100),
+        (len + 10, len + 20),
+        (100, 100),
+    ] {
+        let mut reader = repo
+            .open_range("test.dat", start, end, MissingBlockPolicy::Fail)
+            .await
+            .unwrap();
+        let mut actual = Vec::new();
+        reader.read_to_end(&mut actual).await.unwrap();
 
-#[tokio::test(flavor = "multi_thread")]
-async fn move_file_onto_existing_file() {
-    let (_base_dir, repo) = setup().await;
+        let start = start.min(len) as usize;
+        let end = end.min(len) as usize;
+        assert_eq!(actual, &content[start..end]);
+    }
 
-    let mut file = repo.create_file("src.txt").await.unwrap();
-    file.write_all(b"src").await.unwrap();
-    file.flush().await.unwrap();
-    drop(file);
+    assert_matches!(
+        repo.open_range("test.dat", 20, 10, MissingBlockPolicy::Fail)
+            .await,
+        Err(Error::InvalidArgument)
+    );
 
-    let mut file = repo.create_file("dst.txt").await.unwrap();
-    file.write_all(b"dst").await.unwrap();
-    file.flush().await.unwrap();
-    drop(file);
+    // Remove the last block
+    let block_id = {
+        let mut file = repo.open_file("test.dat").await.unwrap();
+        file.seek(SeekFrom::Start(2 * BLOCK_SIZE as u64));
+        file.current_block_id().await.unwrap()
+    };
+
+    let mut tx = repo.shared.vault.store().begin_write().await.unwrap();
+    tx.remove_block(&block_id).await.unwrap();
+    tx.commit().await.unwrap();
 
-    repo.move_entry("/", "src.txt", "/", "dst.txt")
+    // Ranges not overlapping the missing block can still be read.
+    let mut reader = repo
+        .open_range("test.dat", 0, BLOCK_SIZE as u64, MissingBlockPolicy::Fail)
         .await
         .unwrap();
+    let mut actual = Vec::new();
+    reader.read_to_end(&mut actual).await.unwrap();
+    assert_eq!(actual, &content[..BLOCK_SIZE]);
 
-    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
+    // Reading the missing block fails...
+    let mut reader = repo
+        .open_range("test.dat", 0, len, MissingBlockPolicy::Fail)
+        .await
+        .unwrap();
+    let mut actual = Vec::new();
+    assert!(reader.read_to_end(&mut actual).await.is_err());
 
-    let mut file = repo.open_file("dst.txt").await.unwrap();
-    assert_eq!(file.read_to_end().await.unwrap(), b"src");
+    // ...or waits for it to be received.
+    let mut reader = repo
+        .open_range("test.dat", 0, len, MissingBlockPolicy::Wait)
+        .await
+        .unwrap();
+    let mut actual = Vec::new();
+    let result = timeout(Duration::from_millis(200), reader.read_to_end(&mut actual)).await;
+    assert!(result.is_err());
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn move_file_onto_existing_directory() {
-    let (_base_dir, repo) = setup().await;
+async fn follow_branch() {
+    test_utils::init_log();
 
-    repo.create_file("src.txt").await.unwrap();
-    repo.create_directory("dst").await.unwrap();
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+    let secrets = WriteSecrets::random();
 
-    assert_matches!(
-        repo.move_entry("/", "src.txt", "/", "dst").await,
-        Err(Error::EntryIsDirectory)
+    let repo = Repository::create(
+        &params,
+        Access::ReadUnlocked {
+            id: secrets.id,
+            read_key: secrets.read_key.clone(),
+        },
     )
-}
+    .await
+    .unwrap();
 
-#[tokio::test(flavor = "multi_thread")]
-async fn move_directory_onto_non_existing_entry() {
-    let (_base_dir, repo) = setup().await;
+    let remote_id_a = PublicKey::random();
+    let remote_id_b = PublicKey::random();
 
-    repo.create_directory("src").await.unwrap();
-    repo.move_entry("/", "src", "/", "dst").await.unwrap();
+    for (remote_id, name) in [(remote_id_a, "a.txt"), (remote_id_b, "b.txt")] {
+        let branch = repo
+            .get_branch(remote_id)
+            .unwrap()
+            .reopen(secrets.clone().into());
+        create_file_in_branch(&branch, name, b"foo").await;
+    }
 
-    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
-    assert_matches!(repo.open_directory("dst").await, Ok(_));
-}
+    let names = |dir: JointDirectory| -> Vec<String> {
+        dir.entries().map(|entry| entry.name().to_owned()).collect()
+    };
 
-#[tokio::test(flavor = "multi_thread")]
-async fn move_directory_onto_file_tombstone() {
-    let (_base_dir, repo) = setup().await;
+    assert_eq!(
+        names(repo.open_directory("/").await.unwrap()),
+        ["a.txt", "b.txt"]
+    );
 
-    repo.create_directory("src").await.unwrap();
-    repo.create_file("dst").await.unwrap();
-    repo.remove_entry("dst").await.unwrap();
+    repo.follow_branch(Some(remote_id_a)).await.unwrap();
+    assert_eq!(repo.followed_branch(), Some(remote_id_a));
+    assert_eq!(names(repo.open_directory("/").await.unwrap()), ["a.txt"]);
+    assert_eq!(read_file(&repo, "a.txt").await, b"foo");
+    assert_matches!(repo.open_file("b.txt").await, Err(Error::EntryNotFound));
 
-    repo.move_entry("/", "src", "/", "dst").await.unwrap();
+    // No local branch is ever created.
+    assert_eq!(
+        repo.local_branch().unwrap().version_vector().await.unwrap(),
+        VersionVector::new()
+    );
 
-    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
-    assert_matches!(repo.open_directory("dst").await, Ok(_));
+    repo.follow_branch(None).await.unwrap();
+    assert_eq!(
+        names(repo.open_directory("/").await.unwrap()),
+        ["a.txt", "b.txt"]
+    );
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn move_directory_onto_directory_tombstone() {
-    let (_base_dir, repo) = setup().await;
+async fn follow_branch_persists() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+    let secrets = WriteSecrets::random();
 
-    repo.create_directory("src").await.unwrap();
-    repo.create_directory("dst").await.unwrap();
-    repo.remove_entry("dst").await.unwrap();
+    let repo = Repository::create(
+        &params,
+        Access::ReadUnlocked {
+            id: secrets.id,
+            read_key: secrets.read_key.clone(),
+        },
+    )
+    .await
+    .unwrap();
 
-    repo.move_entry("/", "src", "/", "dst").await.unwrap();
+    let remote_id = PublicKey::random();
+    repo.follow_branch(Some(remote_id)).await.unwrap();
+    repo.close().await.unwrap();
+    drop(repo);
 
-    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
-    assert_matches!(repo.open_directory("dst").await, Ok(_));
+    let repo = Repository::open(&params, None, AccessMode::Read)
+        .await
+        .unwrap();
+    assert_eq!(repo.followed_branch(), Some(remote_id));
+
+    repo.follow_branch(None).await.unwrap();
+    repo.close().await.unwrap();
+    drop(repo);
+
+    let repo = Repository::open(&params, None, AccessMode::Read)
+        .await
+        .unwrap();
+    assert_eq!(repo.followed_branch(), None);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn move_directory_onto_existing_empty_directory() {
-    let (_base_dir, repo) = setup().await;
+async fn followed_branch_is_not_pruned() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+    let secrets = WriteSecrets::random();
 
-    repo.create_directory("src").await.unwrap();
-    repo.create_directory("dst").await.unwrap();
+    let repo = Repository::create(
+        &params,
+        Access::ReadUnlocked {
+            id: secrets.id,
+            read_key: secrets.read_key.clone(),
+        },
+    )
+    .await
+    .unwrap();
 
-    repo.move_entry("/", "src", "/", "dst").await.unwrap();
+    let branch = |id| repo.get_branch(id).unwrap().reopen(secrets.clone().into());
 
-    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
-    assert_matches!(repo.open_directory("dst").await, Ok(_));
-}
+    let remote_id_a = PublicKey::random();
+    let remote_id_b = PublicKey::random();
+    let remote_id_c = PublicKey::random();
 
-#[tokio::test(flavor = "multi_thread")]
-async fn move_directory_onto_existing_non_empty_directory() {
-    let (_base_dir, repo) = setup().await;
+    repo.follow_branch(Some(remote_id_a)).await.unwrap();
 
-    repo.create_directory("src").await.unwrap();
+    // Both A and C end up outdated by B. Only the followed one (A) must be kept.
+    create_file_in_branch(&branch(remote_id_a), "a.txt", b"a").await;
+    let branch_b = branch(remote_id_a).clone_into(remote_id_b).await.unwrap();
+    let branch_b = branch_b.reopen(secrets.clone().into());
+    create_file_in_branch(&branch_b, "b.txt", b"b").await;
+    branch_b.clone_into(remote_id_c).await.unwrap();
+    create_file_in_branch(&branch_b, "c.txt", b"c").await;
 
-    repo.create_directory("dst").await.unwrap();
-    repo.create_file("dst/file.txt").await.unwrap();
+    wait_for(&repo, || async {
+        matches!(
+            repo.get_branch_version_vector(&remote_id_c).await,
+            Err(Error::Store(store::Error::BranchNotFound))
+        )
+    })
+    .await;
 
-    assert_matches!(
-        repo.move_entry("/", "src", "/", "dst").await,
-        Err(Error::DirectoryNotEmpty)
-    );
+    assert!(repo.get_branch_version_vector(&remote_id_a).await.is_ok());
+    assert_eq!(read_file(&repo, "a.txt").await, b"a");
+    assert_matches!(repo.open_file("b.txt").await, Err(Error::EntryNotFound));
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn move_directory_onto_existing_file() {
+async fn follow_branch_in_write_mode() {
     let (_base_dir, repo) = setup().await;
 
-    repo.create_directory("src").await.unwrap();
-    repo.create_file("dst").await.unwrap();
-
     assert_matches!(
-        repo.move_entry("/", "src", "/", "dst").await,
-        Err(Error::EntryIsFile)
+        repo.follow_branch(Some(PublicKey::random())).await,
+        Err(Error::OperationNotSupported)
     );
+    assert_eq!(repo.followed_branch(), None);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn move_file_into_non_existing_directory() {
+async fn find() {
     let (_base_dir, repo) = setup().await;
 
-    repo.create_file("src.txt").await.unwrap();
+    repo.create_directory("docs/Reports").await.unwrap();
 
-    assert_matches!(
-        repo.move_entry("/", "src.txt", "/missing", "dst.txt").await,
-        Err(Error::EntryNotFound)
+    for path in ["docs/Reports/annual.TXT", "docs/notes.txt", "readme.md"] {
+        let mut file = repo.create_file(path).await.unwrap();
+        file.flush().await.unwrap();
+    }
+
+    let find = |pattern: &'static str, root: &'static str| {
+        let repo = &repo;
+        async move {
+            let mut paths: Vec<_> = repo
+                .find(pattern, Utf8PathBuf::from(root))
+                .try_collect()
+                .await
+                .unwrap();
+            paths.sort();
+            paths
+        }
+    };
+
+    assert_eq!(
+        find("*.txt", "/").await,
+        [
+            Utf8PathBuf::from("/docs/Reports/annual.TXT"),
+            Utf8PathBuf::from("/docs/notes.txt"),
+        ]
+    );
+    assert_eq!(
+        find("report", "/").await,
+        [Utf8PathBuf::from("/docs/Reports")]
     );
+    assert_eq!(
+        find("*.txt", "/docs/Reports").await,
+        [Utf8PathBuf::from("/docs/Reports/annual.TXT")]
+    );
+    assert_eq!(find("missing", "/").await, Vec::<Utf8PathBuf>::new());
+
+    // Cancel early
+    let mut stream = pin!(repo.find("", Utf8PathBuf::from("/")));
+    assert!(stream.try_next().await.unwrap().is_some());
+    drop(stream);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn remove_open_file() {
+async fn subscribe_path() {
     let (_base_dir, repo) = setup().await;
 
-    let _file = repo.create_file("foo.txt").await.unwrap();
+    repo.create_directory("a").await.unwrap();
+    repo.create_directory("b").await.unwrap();
 
-    repo.remove_entry("foo.txt").await.unwrap();
-    assert_matches!(repo.open_file("foo.txt").await, Err(Error::EntryNotFound));
-}
+    let mut events = pin!(repo.subscribe_path(Utf8PathBuf::from("/a")));
 
-#[tokio::test(flavor = "multi_thread")]
-async fn move_from_open_file() {
-    let (_base_dir, repo) = setup().await;
+    // Take the initial snapshot. Nothing changed yet so this times out.
+    assert!(time::timeout(Duration::from_millis(200), events.next())
+        .await
+        .is_err());
 
-    let _file = repo.create_file("src.txt").await.unwrap();
+    let mut file = repo.create_file("b/y.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut file = repo.create_file("a/x.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-    repo.move_entry("/", "src.txt", "/", "dst.txt")
+    let event = time::timeout(Duration::from_secs(5), events.next())
         .await
+        .unwrap()
         .unwrap();
+    assert_eq!(
+        event,
+        PathEvent {
+            path: Utf8PathBuf::from("/a/x.txt"),
+            kind: ChangeKind::Modified(EntryType::File),
+        }
+    );
 
-    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
-    assert_matches!(repo.open_file("dst.txt").await, Ok(_));
+    repo.remove_entry("a/x.txt").await.unwrap();
+
+    loop {
+        let event = time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.path, Utf8PathBuf::from("/a/x.txt"));
+
+        if event.kind == ChangeKind::Removed {
+            break;
+        }
+    }
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn move_onto_open_file() {
+async fn open_directory_watched() {
     let (_base_dir, repo) = setup().await;
 
-    let mut file = repo.create_file("src.txt").await.unwrap();
-    file.write(b"src").await.unwrap();
+    repo.create_directory("a").await.unwrap();
+    repo.create_directory("b").await.unwrap();
+
+    let mut dir = repo.open_directory_watched("a").await.unwrap();
+    assert_eq!(dir.path(), Utf8Path::new("a"));
+    assert_eq!(dir.entries().count(), 0);
+
+    // Changes outside of the directory don't wake it up.
+    let mut file = repo.create_file("b/y.txt").await.unwrap();
     file.flush().await.unwrap();
     drop(file);
 
-    let mut file = repo.create_file("dst.txt").await.unwrap();
-    file.write(b"dst").await.unwrap();
+    assert!(time::timeout(Duration::from_millis(200), dir.changed())
+        .await
+        .is_err());
+
+    let mut file = repo.create_file("a/x.txt").await.unwrap();
     file.flush().await.unwrap();
+    drop(file);
 
-    repo.move_entry("/", "src.txt", "/", "dst.txt")
+    time::timeout(Duration::from_secs(5), dir.changed())
         .await
+        .unwrap()
         .unwrap();
+    assert_eq!(
+        dir.entries().map(|entry| entry.name()).collect::<Vec<_>>(),
+        ["x.txt"]
+    );
 
-    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
+    repo.remove_entry("a/x.txt").await.unwrap();
 
-    let mut file = repo.open_file("dst.txt").await.unwrap();
-    assert_eq!(file.read_to_end().await.unwrap(), b"src");
-}
+    loop {
+        time::timeout(Duration::from_secs(5), dir.changed())
+            .await
+            .unwrap()
+            .unwrap();
 
-// TODO: test reading / writing file that's been removed / moved from / moved onto
+        if dir.entries().count() == 0 {
+            break;
+        }
+    }
 
-#[tokio::test(flavor = "multi_thread")]
-async fn blind_access_non_empty_repo() {
-    test_utils::init_log();
+    // Removing the directory itself is reported as an error.
+    repo.remove_entry("a").await.unwrap();
 
-    let (_base_dir, pool) = db::create_temp().await.unwrap();
-    let params =
-        RepositoryParams::with_pool(pool, "test").with_parent_monitor(StateMonitor::make_root());
-    let local_secret = SetLocalSecret::random();
+    assert_matches!(
+        time::timeout(Duration::from_secs(5), dir.changed())
+            .await
+            .unwrap(),
+        Err(Error::EntryNotFound)
+    );
+}
 
-    // Create the repo and put a file in it.
-    let repo = Repository::create(
-        &params,
-        Access::WriteLocked {
-            local_read_secret: local_secret.clone(),
-            local_write_secret: local_secret,
-            secrets: WriteSecrets::random(),
-        },
-    )
-    .await
-    .unwrap();
+#[tokio::test(flavor = "multi_thread")]
+async fn path_filter() {
+    let (_base_dir, repo) = setup().await;
+    let branch_id = *repo.local_branch().unwrap().id();
 
-    let mut file = repo.create_file("secret.txt").await.unwrap();
-    file.write_all(b"redacted").await.unwrap();
-    file.flush().await.unwrap();
+    repo.create_directory("a").await.unwrap();
+    repo.create_directory("b").await.unwrap();
 
-    drop(file);
-    drop(repo);
+    let mut filter = path_filter::PathFilter::new(Utf8PathBuf::from("/a"));
 
-    // Reopen the repo first explicitly in blind mode and then using incorrect secret. The two ways
-    // should be indistinguishable from each other.
-    for (local_key, access_mode) in [
-        (None, AccessMode::Blind),
-        (Some(LocalSecret::random()), AccessMode::Write),
-    ] {
-        // Reopen the repo in blind mode.
-        let repo = Repository::open(&params, local_key.clone(), access_mode)
-            .await
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Repo should open in blind mode (local_key.is_some:{:?})",
-                    local_key.is_some(),
-                )
-            });
+    // A branch seen for the first time is considered affected.
+    assert!(filter.is_affected(&repo, &[branch_id]).await);
+    assert!(!filter.is_affected(&repo, &[branch_id]).await);
 
-        // Reading files is not allowed.
-        assert_matches!(
-            repo.open_file("secret.txt").await,
-            Err(Error::PermissionDenied)
-        );
+    // Changes outside of the path don't affect it...
+    let mut file = repo.create_file("b/y.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-        // Creating files is not allowed.
-        assert_matches!(
-            repo.create_file("hack.txt").await,
-            Err(Error::PermissionDenied)
-        );
+    assert!(!filter.is_affected(&repo, &[branch_id]).await);
 
-        // Removing files is not allowed.
-        assert_matches!(
-            repo.remove_entry("secret.txt").await,
-            Err(Error::PermissionDenied)
-        );
+    // ...but nested changes do.
+    repo.create_directory("a/c").await.unwrap();
+    let mut file = repo.create_file("a/c/x.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-        // Reading the root directory is not allowed either.
-        assert_matches!(repo.open_directory("/").await, Err(Error::PermissionDenied));
-    }
+    assert!(filter.is_affected(&repo, &[branch_id]).await);
+    assert!(!filter.is_affected(&repo, &[branch_id]).await);
+
+    // So does removing the path itself.
+    repo.remove_entry_recursively("a").await.unwrap();
+    assert!(filter.is_affected(&repo, &[branch_id]).await);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn blind_access_empty_repo() {
-    test_utils::init_log();
+async fn resync_branch() {
+    let (_base_dir, repo) = setup().await;
 
-    let (_base_dir, pool) = db::create_temp().await.unwrap();
-    let params = RepositoryParams::with_pool(pool, "test");
+    let local_id = *repo.local_branch().unwrap().id();
+    let remote_id = PublicKey::random();
 
-    let local_secret = SetLocalSecret::random();
+    let file = create_remote_file(&repo, remote_id, "test.txt", b"hello").await;
+    drop(file);
 
-    // Create an empty repo.
-    Repository::create(
-        &params,
-        Access::WriteLocked {
-            local_read_secret: local_secret.clone(),
-            local_write_secret: local_secret,
-            secrets: WriteSecrets::random(),
-        },
-    )
-    .await
-    .unwrap();
+    repo.resync_branch(remote_id).await.unwrap();
+    assert_matches!(
+        repo.get_branch_version_vector(&remote_id).await,
+        Err(Error::Store(store::Error::BranchNotFound))
+    );
 
-    // Reopen the repo in blind mode.
-    let repo = Repository::open(&params, Some(LocalSecret::random()), AccessMode::Blind)
-        .await
-        .unwrap();
+    // Unknown branch
+    repo.resync_branch(PublicKey::random()).await.unwrap();
 
-    // Reading the root directory is not allowed.
-    assert_matches!(repo.open_directory("/").await, Err(Error::PermissionDenied));
+    // Local branch
+    let mut file = repo.create_file("local.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert_matches!(
+        repo.resync_branch(local_id).await,
+        Err(Error::OperationNotSupported)
+    );
+    assert!(repo.local_branch_exists().await.unwrap());
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn read_access_same_replica() {
+async fn ensure_local_branch() {
+    let (_base_dir, repo) = setup().await;
+
+    assert!(!repo.local_branch_exists().await.unwrap());
+
+    let branch = repo.ensure_local_branch().await.unwrap();
+    assert_eq!(branch.id(), repo.local_branch().unwrap().id());
+    assert!(repo.local_branch_exists().await.unwrap());
+
+    // Idempotent
+    repo.ensure_local_branch().await.unwrap();
+    assert!(repo.local_branch_exists().await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ensure_local_branch_in_read_mode() {
     test_utils::init_log();
 
     let (_base_dir, pool) = db::create_temp().await.unwrap();
     let params = RepositoryParams::with_pool(pool, "test");
+    let secrets = WriteSecrets::random();
 
     let repo = Repository::create(
         &params,
-        Access::WriteUnlocked {
-            secrets: WriteSecrets::random(),
+        Access::ReadUnlocked {
+            id: secrets.id,
+            read_key: secrets.read_key.clone(),
         },
     )
     .await
     .unwrap();
 
-    let mut file = repo.create_file("public.txt").await.unwrap();
-    file.write_all(b"hello world").await.unwrap();
-    file.flush().await.unwrap();
+    assert_matches!(
+        repo.ensure_local_branch().await,
+        Err(Error::PermissionDenied)
+    );
+    assert!(!repo.local_branch_exists().await.unwrap());
+}
 
-    drop(file);
-    drop(repo);
+#[tokio::test(flavor = "multi_thread")]
+async fn ensure_local() {
+    let (_base_dir, repo) = setup().await;
 
-    // Reopen the repo in read-only mode.
-    let repo = Repository::open(&params, None, AccessMode::Read)
-        .await
-        .unwrap();
+    // 3 blocks
+    let content = random_bytes(5 * BLOCK_SIZE / 2);
 
-    // Reading files is allowed.
-    let mut file = repo.open_file("public.txt").await.unwrap();
+    repo.create_directory("dir").await.unwrap();
+    let mut file = repo.create_file("dir/test.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
 
-    let content = file.read_to_end().await.unwrap();
-    assert_eq!(content, b"hello world");
+    // Remove the last block
+    file.seek(SeekFrom::Start(2 * BLOCK_SIZE as u64));
+    let block_id = file.current_block_id().await.unwrap();
+    drop(file);
 
-    // Writing is not allowed.
-    file.seek(SeekFrom::Start(0));
-    // short writes that don't cross block boundaries don't trigger the permission check which is
-    // why the following works...
-    file.write_all(b"hello universe").await.unwrap();
-    // ...but flushing the file is not allowed.
-    assert_matches!(file.flush().await, Err(Error::PermissionDenied));
+    let block = {
+        let mut reader = repo.shared.vault.store().acquire_read().await.unwrap();
+        let mut content = BlockContent::new();
+        let nonce = reader.read_block(&block_id, &mut content).await.unwrap();
+        Block::new(content, nonce)
+    };
 
-    drop(file);
+    let mut tx = repo.shared.vault.store().begin_write().await.unwrap();
+    tx.remove_block(&block_id).await.unwrap();
+    tx.commit().await.unwrap();
 
-    // Creating files is not allowed.
-    assert_matches!(
-        repo.create_file("hack.txt").await,
-        Err(Error::PermissionDenied)
+    let paths = [Utf8PathBuf::from("dir")];
+    let mut progress = pin!(repo.ensure_local(&paths));
+
+    assert_eq!(
+        progress.try_next().await.unwrap(),
+        Some(Progress { value: 2, total: 3 })
     );
 
-    // Removing files is not allowed.
-    assert_matches!(
-        repo.remove_entry("public.txt").await,
-        Err(Error::PermissionDenied)
+    // Simulate receiving the block from a peer.
+    repo.shared.vault.receive_block(&block, None).await.unwrap();
+
+    assert_eq!(
+        progress.try_next().await.unwrap(),
+        Some(Progress { value: 3, total: 3 })
     );
+    assert_eq!(progress.try_next().await.unwrap(), None);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn read_access_different_replica() {
-    test_utils::init_log();
-
-    let (_base_dir, pool) = db::create_temp().await.unwrap();
+async fn verify_and_repair() {
+    let (_base_dir, repo) = setup().await;
 
-    let params_a = RepositoryParams::with_pool(pool.clone(), "test").with_device_id(rand::random());
-    let repo = Repository::create(
-        &params_a,
-        Access::WriteUnlocked {
-            secrets: WriteSecrets::random(),
-        },
-    )
-    .await
-    .unwrap();
+    // 3 blocks
+    let content = random_bytes(5 * BLOCK_SIZE / 2);
 
-    let mut file = repo.create_file("public.txt").await.unwrap();
-    file.write_all(b"hello world").await.unwrap();
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
     file.flush().await.unwrap();
 
+    file.seek(SeekFrom::Start(BLOCK_SIZE as u64));
+    let block_id = file.current_block_id().await.unwrap();
     drop(file);
-    drop(repo);
 
-    let params_b = RepositoryParams::with_pool(pool, "test").with_device_id(rand::random());
-    let repo = Repository::open(&params_b, None, AccessMode::Read)
+    let store = repo.shared.vault.store();
+    let block_count = store.count_blocks().await.unwrap();
+
+    let block = {
+        let mut reader = store.acquire_read().await.unwrap();
+        let mut content = BlockContent::new();
+        let nonce = reader.read_block(&block_id, &mut content).await.unwrap();
+        Block::new(content, nonce)
+    };
+
+    // Corrupt the block content.
+    let mut tx = store.db().begin_write().await.unwrap();
+    sqlx::query("UPDATE blocks SET content = ? WHERE id = ?")
+        .bind(&random_bytes(BLOCK_SIZE)[..])
+        .bind(&block_id)
+        .execute(&mut tx)
         .await
         .unwrap();
+    tx.commit().await.unwrap();
 
-    let mut file = repo.open_file("public.txt").await.unwrap();
-    let content = file.read_to_end().await.unwrap();
-    assert_eq!(content, b"hello world");
-}
+    let mut progress = pin!(repo.verify_and_repair());
 
-#[tokio::test(flavor = "multi_thread")]
-async fn truncate_forked_remote_file() {
-    let (_base_dir, repo) = setup().await;
+    assert_eq!(
+        progress.try_next().await.unwrap(),
+        Some(RepairProgress {
+            verified: block_count,
+            corrupt: 1,
+            missing: 0,
+            repaired: 0,
+        })
+    );
 
-    let remote_id = PublicKey::random();
-    tracing::info!(local_id = ?repo.local_branch().unwrap().id(), ?remote_id);
+    assert!(store
+        .acquire_read()
+        .await
+        .unwrap()
+        .is_block_missing(&block_id)
+        .await
+        .unwrap());
 
-    create_remote_file(&repo, remote_id, "test.txt", b"foo").await;
+    // Simulate receiving the block from a peer.
+    repo.shared.vault.receive_block(&block, None).await.unwrap();
 
-    let local_branch = repo.local_branch().unwrap();
-    let mut file = repo.open_file("test.txt").await.unwrap();
-    file.fork(local_branch).await.unwrap();
-    file.truncate(0).unwrap();
+    assert_eq!(
+        progress.try_next().await.unwrap(),
+        Some(RepairProgress {
+            verified: block_count,
+            corrupt: 1,
+            missing: 0,
+            repaired: 1,
+        })
+    );
+    assert_eq!(progress.try_next().await.unwrap(), None);
+
+    assert_eq!(read_file(&repo, "test.dat").await, content);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_create_file() {
+async fn verify_and_repair_lost_block() {
     let (_base_dir, repo) = setup().await;
-    let local_branch = repo.local_branch().unwrap();
 
-    let root_vv_0 = local_branch.version_vector().await.unwrap();
+    let content = random_bytes(5 * BLOCK_SIZE / 2);
 
-    let mut file = repo.create_file("parent/test.txt").await.unwrap();
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
 
-    let root_vv_1 = file
-        .parent()
-        .await
-        .unwrap()
-        .parent()
-        .await
-        .unwrap()
-        .unwrap()
-        .version_vector()
+    file.seek(SeekFrom::Start(BLOCK_SIZE as u64));
+    let block_id = file.current_block_id().await.unwrap();
+    drop(file);
+
+    let store = repo.shared.vault.store();
+    let block_count = store.count_blocks().await.unwrap();
+
+    let block = {
+        let mut reader = store.acquire_read().await.unwrap();
+        let mut content = BlockContent::new();
+        let nonce = reader.read_block(&block_id, &mut content).await.unwrap();
+        Block::new(content, nonce)
+    };
+
+    // Remove the block while the index still references it as present.
+    let mut tx = store.db().begin_write().await.unwrap();
+    sqlx::query("DELETE FROM blocks WHERE id = ?")
+        .bind(&block_id)
+        .execute(&mut tx)
         .await
         .unwrap();
-    let parent_vv_1 = file.parent().await.unwrap().version_vector().await.unwrap();
-    let file_vv_1 = file.version_vector().await.unwrap();
+    tx.commit().await.unwrap();
 
-    assert!(root_vv_1 > root_vv_0);
+    let mut progress = pin!(repo.verify_and_repair());
 
-    file.write_all(b"blah").await.unwrap();
-    file.flush().await.unwrap();
+    assert_eq!(
+        progress.try_next().await.unwrap(),
+        Some(RepairProgress {
+            verified: block_count - 1,
+            corrupt: 0,
+            missing: 1,
+            repaired: 0,
+        })
+    );
 
-    let root_vv_2 = file
-        .parent()
-        .await
-        .unwrap()
-        .parent()
+    assert!(store
+        .acquire_read()
         .await
         .unwrap()
-        .unwrap()
-        .version_vector()
+        .is_block_missing(&block_id)
         .await
-        .unwrap();
-    let parent_vv_2 = file.parent().await.unwrap().version_vector().await.unwrap();
-    let file_vv_2 = file.version_vector().await.unwrap();
+        .unwrap());
 
-    assert!(root_vv_2 > root_vv_1);
-    assert!(parent_vv_2 > parent_vv_1);
-    assert!(file_vv_2 > file_vv_1);
+    repo.shared.vault.receive_block(&block, None).await.unwrap();
+
+    assert_eq!(
+        progress.try_next().await.unwrap(),
+        Some(RepairProgress {
+            verified: block_count - 1,
+            corrupt: 0,
+            missing: 1,
+            repaired: 1,
+        })
+    );
+    assert_eq!(progress.try_next().await.unwrap(), None);
+
+    assert_eq!(read_file(&repo, "test.dat").await, content);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_deep_hierarchy() {
+async fn verify_and_repair_gives_up_without_peers() {
     let (_base_dir, repo) = setup().await;
-    let local_branch = repo.local_branch().unwrap();
-    let local_id = *local_branch.id();
 
-    let depth = 10;
-    let mut dirs = Vec::new();
-    dirs.push(local_branch.open_or_create_root().await.unwrap());
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(&random_bytes(BLOCK_SIZE)).await.unwrap();
+    file.flush().await.unwrap();
+    let block_id = file.current_block_id().await.unwrap();
+    drop(file);
 
-    for i in 0..depth {
-        let dir = dirs
-            .last_mut()
-            .unwrap()
-            .create_directory(format!("dir-{}", i), rand::random(), &VersionVector::new())
-            .await
-            .unwrap();
-        dirs.push(dir);
-    }
+    let store = repo.shared.vault.store();
+    let mut tx = store.db().begin_write().await.unwrap();
+    sqlx::query("DELETE FROM blocks WHERE id = ?")
+        .bind(&block_id)
+        .execute(&mut tx)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
 
-    // Each directory's local version is one less than its parent.
-    for (index, dir) in dirs.iter().skip(1).enumerate() {
-        assert_eq!(
-            dir.version_vector().await.unwrap(),
-            vv![local_id => (depth - index) as u64]
-        );
-    }
-}
+    let idle_timeout = Duration::from_millis(500);
+    let mut progress = pin!(repo.verify_and_repair_with_timeout(idle_timeout));
 
-#[tokio::test(flavor = "multi_thread")]
-async fn version_vector_recreate_deleted_file() {
-    let (_base_dir, repo) = setup().await;
+    let item = progress.try_next().await.unwrap().unwrap();
+    assert_eq!(item.missing, 1);
+    assert_eq!(item.repaired, 0);
 
-    let local_id = *repo.local_branch().unwrap().id();
+    // Keep generating unrelated events. They must not postpone the timeout.
+    let writer = async {
+        for i in 0.. {
+            let mut file = repo.create_file(format!("other-{i}.dat")).await.unwrap();
+            file.write_all(b"other").await.unwrap();
+            file.flush().await.unwrap();
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    };
 
-    let file = repo.create_file("test.txt").await.unwrap();
-    drop(file);
+    let end = async {
+        time::timeout(10 * idle_timeout, progress.try_next())
+            .await
+            .unwrap()
+            .unwrap()
+    };
 
-    repo.remove_entry("test.txt").await.unwrap();
+    let item = tokio::select! {
+        item = end => item,
+        _ = writer => unreachable!(),
+    };
 
-    let file = repo.create_file("test.txt").await.unwrap();
-    assert_eq!(
-        file.version_vector().await.unwrap(),
-        vv![local_id => 3 /* = 1*create + 1*remove + 1*create again */]
-    );
+    assert_eq!(item, None);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_fork() {
-    use tokio::time;
-
-    // TODO: this test would be more precise without merger. Consider converting it to a
-    // joint_directory test.
-
+async fn verify_and_repair_intact() {
     let (_base_dir, repo) = setup().await;
 
-    let local_branch = repo.local_branch().unwrap();
-    let remote_branch = repo
-        .get_branch(PublicKey::random())
-        .unwrap()
-        .reopen(repo.secrets().keys().unwrap());
-
-    tracing::info!(local_id = ?local_branch.id(), remote_id = ?remote_branch.id());
-
-    time::timeout(Duration::from_secs(5), async move {
-        let mut remote_root = remote_branch.open_or_create_root().await.unwrap();
-        let mut remote_parent = remote_root
-            .create_directory("parent".into(), rand::random(), &VersionVector::new())
-            .await
-            .unwrap();
-        let mut file = create_file_in_directory(&mut remote_parent, "foo.txt", &[]).await;
-
-        remote_parent.refresh().await.unwrap();
-        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
-        let remote_file_vv = file.version_vector().await.unwrap();
-
-        tracing::info!("step 1");
-        file.fork(local_branch.clone()).await.unwrap();
-        tracing::info!("step 2");
-
-        let local_file_vv_0 = file.version_vector().await.unwrap();
-        assert_eq!(local_file_vv_0, remote_file_vv);
-
-        let local_parent_vv_0 = local_branch
-            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
-            .await
-            .unwrap()
-            .lookup("parent")
-            .unwrap()
-            .version_vector()
-            .clone();
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(&random_bytes(BLOCK_SIZE)).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-        assert!(local_parent_vv_0 <= remote_parent_vv);
+    let mut progress = pin!(repo.verify_and_repair());
 
-        drop(file);
+    let item = progress.try_next().await.unwrap().unwrap();
+    assert!(item.verified > 0);
+    assert_eq!(item.corrupt, 0);
+    assert_eq!(item.missing, 0);
+    assert_eq!(progress.try_next().await.unwrap(), None);
+}
 
-        // modify the file and fork again
-        let mut file = remote_parent
-            .lookup("foo.txt")
-            .unwrap()
-            .file()
-            .unwrap()
-            .open()
-            .await
-            .unwrap();
-        file.write_all(b"hello").await.unwrap();
-        file.flush().await.unwrap();
+#[tokio::test(flavor = "multi_thread")]
+async fn blocks_missing_by() {
+    let (_base_dir, repo) = setup().await;
 
-        remote_parent.refresh().await.unwrap();
-        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
-        let remote_file_vv = file.version_vector().await.unwrap();
+    let mut file = repo.create_file("a.dat").await.unwrap();
+    file.write_all(&random_bytes(3 * BLOCK_SIZE)).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-        file.fork(local_branch.clone()).await.unwrap();
+    // A peer which has nothing is missing everything.
+    let missing: Vec<_> = repo
+        .blocks_missing_by(PresenceSnapshot::default())
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(missing.len() as u64, repo.count_blocks().await.unwrap());
 
-        let local_file_vv_1 = file.version_vector().await.unwrap();
-        assert_eq!(local_file_vv_1, remote_file_vv);
-        assert!(local_file_vv_1 > local_file_vv_0);
+    // A peer which has everything we have is missing nothing.
+    let snapshot = repo.presence_snapshot().await.unwrap();
+    let missing: Vec<_> = repo
+        .blocks_missing_by(snapshot.clone())
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(missing.is_empty());
 
-        let local_parent_vv_1 = local_branch
-            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
-            .await
-            .unwrap()
-            .lookup("parent")
-            .unwrap()
-            .version_vector()
-            .clone();
+    // A peer which hasn't seen the new file is missing its blocks but not all the old ones.
+    let mut file = repo.create_file("b.dat").await.unwrap();
+    file.write_all(&random_bytes(3 * BLOCK_SIZE)).await.unwrap();
+    file.flush().await.unwrap();
+    file.seek(SeekFrom::Start(0));
+    let block_id = file.current_block_id().await.unwrap();
+    drop(file);
 
-        assert!(local_parent_vv_1 <= remote_parent_vv);
-        assert!(local_parent_vv_1 >= local_parent_vv_0);
-    })
-    .await
-    .unwrap();
+    let missing: Vec<_> = repo
+        .blocks_missing_by(snapshot)
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(missing.contains(&block_id));
+    assert!((missing.len() as u64) < repo.count_blocks().await.unwrap());
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_empty_directory() {
+async fn move_file_onto_non_existing_entry() {
     let (_base_dir, repo) = setup().await;
 
-    let local_branch = repo.local_branch().unwrap();
-    let local_id = *local_branch.id();
+    repo.create_file("src.txt").await.unwrap();
+    repo.move_entry("/", "src.txt", "/", "dst.txt", true)
+        .await
+        .unwrap();
 
-    let dir = repo.create_directory("stuff").await.unwrap();
-    assert_eq!(dir.version_vector().await.unwrap(), vv![local_id => 1]);
+    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
+    assert_matches!(repo.open_file("dst.txt").await, Ok(_));
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_moved_non_empty_directory() {
+async fn move_file_onto_tombstone() {
     let (_base_dir, repo) = setup().await;
 
-    repo.create_directory("foo").await.unwrap();
-    repo.create_file("foo/stuff.txt").await.unwrap();
-
-    let vv_0 = repo
-        .local_branch()
-        .unwrap()
-        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
-        .await
-        .unwrap()
-        .lookup("foo")
-        .unwrap()
-        .version_vector()
-        .clone();
+    repo.create_file("src.txt").await.unwrap();
 
-    repo.move_entry("/", "foo", "/", "bar").await.unwrap();
+    repo.create_file("dst.txt").await.unwrap();
+    repo.remove_entry("dst.txt").await.unwrap();
 
-    let vv_1 = repo
-        .local_branch()
-        .unwrap()
-        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+    repo.move_entry("/", "src.txt", "/", "dst.txt", true)
         .await
-        .unwrap()
-        .lookup("bar")
-        .unwrap()
-        .version_vector()
-        .clone();
+        .unwrap();
 
-    assert!(vv_1 > vv_0);
+    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
+    assert_matches!(repo.open_file("dst.txt").await, Ok(_));
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_file_moved_over_tombstone() {
+async fn move_file_onto_existing_file_without_overwrite() {
     let (_base_dir, repo) = setup().await;
 
-    let mut file = repo.create_file("old.txt").await.unwrap();
-    file.write_all(b"a").await.unwrap();
+    let mut file = repo.create_file("src.txt").await.unwrap();
+    file.write_all(b"src").await.unwrap();
     file.flush().await.unwrap();
+    drop(file);
 
-    let vv_0 = file.version_vector().await.unwrap();
-
+    let mut file = repo.create_file("dst.txt").await.unwrap();
+    file.write_all(b"dst").await.unwrap();
+    file.flush().await.unwrap();
     drop(file);
-    repo.remove_entry("old.txt").await.unwrap();
 
-    // The tombstone's vv is the original vv incremented by 1.
-    let branch_id = *repo.local_branch().unwrap().id();
-    let vv_1 = vv_0.incremented(branch_id);
+    let vv = repo.local_branch().unwrap().version_vector().await.unwrap();
 
-    repo.create_file("new.txt").await.unwrap();
-    repo.move_entry("/", "new.txt", "/", "old.txt")
-        .await
-        .unwrap();
+    assert_matches!(
+        repo.move_entry("/", "src.txt", "/", "dst.txt", false).await,
+        Err(Error::EntryExists)
+    );
 
-    let vv_2 = repo
-        .local_branch()
-        .unwrap()
-        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
-        .await
-        .unwrap()
-        .lookup("old.txt")
-        .unwrap()
-        .version_vector()
-        .clone();
+    // Nothing changed
+    assert_eq!(read_file(&repo, "src.txt").await, b"src");
+    assert_eq!(read_file(&repo, "dst.txt").await, b"dst");
+    assert_eq!(
+        repo.local_branch().unwrap().version_vector().await.unwrap(),
+        vv
+    );
 
-    assert!(vv_2 > vv_1);
+    // Moving onto a tombstone is still allowed
+    repo.remove_entry("dst.txt").await.unwrap();
+    repo.move_entry("/", "src.txt", "/", "dst.txt", false)
+        .await
+        .unwrap();
+    assert_eq!(read_file(&repo, "dst.txt").await, b"src");
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn file_conflict_modify_local() {
+async fn move_file_onto_existing_file() {
     let (_base_dir, repo) = setup().await;
 
-    let local_branch = repo.local_branch().unwrap();
-    let local_id = *local_branch.id();
+    let mut file = repo.create_file("src.txt").await.unwrap();
+    file.write_all(b"src").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-    let remote_id = PublicKey::random();
-    let remote_branch = repo
-        .get_branch(remote_id)
-        .unwrap()
-        .reopen(repo.secrets().keys().unwrap());
+    let mut file = repo.create_file("dst.txt").await.unwrap();
+    file.write_all(b"dst").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-    // Create two concurrent versions of the same file.
-    let local_file = create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+    repo.move_entry("/", "src.txt", "/", "dst.txt", true)
+        .await
+        .unwrap();
 
-    assert_eq!(
-        local_file.version_vector().await.unwrap(),
-        vv![local_id => 2]
-    );
-    drop(local_file);
+    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
 
-    let remote_file = create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
-    assert_eq!(
-        remote_file.version_vector().await.unwrap(),
-        vv![remote_id => 2]
-    );
-    drop(remote_file);
+    let mut file = repo.open_file("dst.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"src");
+}
 
-    // Modify the local version.
-    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
-    local_file.write_all(b"local v2").await.unwrap();
-    local_file.flush().await.unwrap();
-    drop(local_file);
+#[tokio::test(flavor = "multi_thread")]
+async fn move_file_onto_existing_directory() {
+    let (_base_dir, repo) = setup().await;
 
-    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
-    assert_eq!(local_file.read_to_end().await.unwrap(), b"local v2");
-    assert_eq!(
-        local_file.version_vector().await.unwrap(),
-        vv![local_id => 3]
-    );
+    repo.create_file("src.txt").await.unwrap();
+    repo.create_directory("dst").await.unwrap();
 
-    let mut remote_file = repo
-        .open_file_version("test.txt", &remote_id)
-        .await
-        .unwrap();
-    assert_eq!(remote_file.read_to_end().await.unwrap(), b"remote v1");
-    assert_eq!(
-        remote_file.version_vector().await.unwrap(),
-        vv![remote_id => 2]
-    );
+    assert_matches!(
+        repo.move_entry("/", "src.txt", "/", "dst", true).await,
+        Err(Error::EntryIsDirectory)
+    )
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn file_conflict_attempt_to_fork_and_modify_remote() {
+async fn move_directory_onto_non_existing_entry() {
     let (_base_dir, repo) = setup().await;
 
-    let local_branch = repo.local_branch().unwrap();
+    repo.create_directory("src").await.unwrap();
+    repo.move_entry("/", "src", "/", "dst", true).await.unwrap();
 
-    let remote_id = PublicKey::random();
-    let remote_branch = repo
-        .get_branch(remote_id)
-        .unwrap()
-        .reopen(repo.secrets().keys().unwrap());
+    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
+    assert_matches!(repo.open_directory("dst").await, Ok(_));
+}
 
-    // Create two concurrent versions of the same file.
-    create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
-    create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+#[tokio::test(flavor = "multi_thread")]
+async fn move_directory_onto_file_tombstone() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("src").await.unwrap();
+    repo.create_file("dst").await.unwrap();
+    repo.remove_entry("dst").await.unwrap();
+
+    repo.move_entry("/", "src", "/", "dst", true).await.unwrap();
+
+    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
+    assert_matches!(repo.open_directory("dst").await, Ok(_));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn move_directory_onto_directory_tombstone() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("src").await.unwrap();
+    repo.create_directory("dst").await.unwrap();
+    repo.remove_entry("dst").await.unwrap();
+
+    repo.move_entry("/", "src", "/", "dst", true).await.unwrap();
+
+    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
+    assert_matches!(repo.open_directory("dst").await, Ok(_));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn move_directory_onto_existing_empty_directory() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("src").await.unwrap();
+    repo.create_directory("dst").await.unwrap();
+
+    repo.move_entry("/", "src", "/", "dst", true).await.unwrap();
+
+    assert_matches!(repo.open_directory("src").await, Err(Error::EntryNotFound));
+    assert_matches!(repo.open_directory("dst").await, Ok(_));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn move_directory_onto_existing_non_empty_directory() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("src").await.unwrap();
+
+    repo.create_directory("dst").await.unwrap();
+    repo.create_file("dst/file.txt").await.unwrap();
 
-    // Attempt to fork the remote version (fork is required to modify it)
-    let mut remote_file = repo
-        .open_file_version("test.txt", &remote_id)
-        .await
-        .unwrap();
     assert_matches!(
-        remote_file.fork(local_branch).await,
-        Err(Error::EntryExists)
+        repo.move_entry("/", "src", "/", "dst", true).await,
+        Err(Error::DirectoryNotEmpty)
     );
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn size() {
+async fn move_directory_onto_existing_file() {
     let (_base_dir, repo) = setup().await;
-    assert_eq!(repo.size().await.unwrap().to_bytes(), 0);
 
-    let mut file = repo.create_file("test.txt").await.unwrap();
+    repo.create_directory("src").await.unwrap();
+    repo.create_file("dst").await.unwrap();
 
-    // 2 blocks: 1 for the file and 1 for the root dir
-    assert_eq!(
-        repo.size().await.unwrap().to_bytes(),
-        2 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+    assert_matches!(
+        repo.move_entry("/", "src", "/", "dst", true).await,
+        Err(Error::EntryIsFile)
     );
+}
 
-    // 1 block size + 1 byte == 2 blocks
-    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
-    file.write_all(&content).await.unwrap();
-    file.flush().await.unwrap();
+#[tokio::test(flavor = "multi_thread")]
+async fn move_file_into_non_existing_directory() {
+    let (_base_dir, repo) = setup().await;
 
-    // 3 blocks: 2 for the file and 1 for the root dir
-    assert_eq!(
-        repo.size().await.unwrap().to_bytes(),
-        3 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+    repo.create_file("src.txt").await.unwrap();
+
+    assert_matches!(
+        repo.move_entry("/", "src.txt", "/missing", "dst.txt", true)
+            .await,
+        Err(Error::EntryNotFound)
     );
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn access_mode() {
-    let secret1 = SetLocalSecret::random();
-    let secret2 = SetLocalSecret::random();
+async fn remove_open_file() {
+    let (_base_dir, repo) = setup().await;
 
-    // TODO: given the large number of cases, consider converting this to a proptest.
+    let _file = repo.create_file("foo.txt").await.unwrap();
 
-    #[rustfmt::skip]
-    let cases = [
-        (
+    repo.remove_entry("foo.txt").await.unwrap();
+    assert_matches!(repo.open_file("foo.txt").await, Err(Error::EntryNotFound));
+}
 
-            // read access
-            AccessChange::Enable(None),
-            // write access
-            AccessChange::Enable(None),
-            // test steps
-            vec![
-                // params to `set_access_mode`                    expected mode
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Read,  None,                         AccessMode::Read),
-                (AccessMode::Write, None,                         AccessMode::Write),
-            ],
-        ),
-        (
-            AccessChange::Enable(None),
-            AccessChange::Enable(Some(secret1.clone())),
-            vec![
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Blind, Some(secret1.clone().into()), AccessMode::Blind),
-                (AccessMode::Read,  None,                         AccessMode::Read),
-                (AccessMode::Read,  Some(secret1.clone().into()), AccessMode::Read),
-                (AccessMode::Write, None,                         AccessMode::Read),
-                (AccessMode::Write, Some(secret1.clone().into()), AccessMode::Write),
-            ],
-        ),
-        (
-            AccessChange::Enable(Some(secret1.clone())),
-            AccessChange::Enable(Some(secret1.clone())),
-            vec![
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Blind, Some(secret1.clone().into()), AccessMode::Blind),
-                (AccessMode::Read,  None,                         AccessMode::Blind),
-                (AccessMode::Write, None,                         AccessMode::Blind),
-                (AccessMode::Read,  Some(secret1.clone().into()), AccessMode::Read),
-                (AccessMode::Write, Some(secret1.clone().into()), AccessMode::Write),
-            ],
-        ),
-        (
-            AccessChange::Enable(Some(secret1.clone())),
-            AccessChange::Enable(Some(secret2.clone())),
-            vec![
-                (AccessMode::Blind, None, AccessMode::Blind),
-                (AccessMode::Blind, Some(secret1.clone().into()), AccessMode::Blind),
-                (AccessMode::Blind, Some(secret2.clone().into()), AccessMode::Blind),
-                (AccessMode::Read,  None,                         AccessMode::Blind),
-                (AccessMode::Write, None,                         AccessMode::Blind),
-                (AccessMode::Read,  Some(secret1.clone().into()), AccessMode::Read),
-                (AccessMode::Write, Some(secret2.clone().into()), AccessMode::Write),
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Read,  Some(secret2.clone().into()), AccessMode::Read),
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Write, Some(secret1.clone().into()), AccessMode::Read),
-            ],
-        ),
-        (
-            AccessChange::Enable(None),
-            AccessChange::Disable,
-            vec![
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Read,  None,                         AccessMode::Read),
-                (AccessMode::Write, None,                         AccessMode::Read),
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Write, None,                         AccessMode::Read),
-            ],
-        ),
-        (
-            AccessChange::Disable,
-            AccessChange::Disable,
-            vec![
-                (AccessMode::Blind, None,                         AccessMode::Blind),
-                (AccessMode::Read,  None,                         AccessMode::Blind),
-                (AccessMode::Write, None,                         AccessMode::Blind),
-            ],
-        ),
-    ];
+#[tokio::test(flavor = "multi_thread")]
+async fn move_from_open_file() {
+    let (_base_dir, repo) = setup().await;
 
-    // TODO: test also wrong local secret
-    // TODO: test "nonsensical" cases, e.g., enabling write mode but disabling read mode, etc...
+    let _file = repo.create_file("src.txt").await.unwrap();
 
-    for (read, write, steps) in cases {
-        let (_base_dir, repo) = setup().await;
+    repo.move_entry("/", "src.txt", "/", "dst.txt", true)
+        .await
+        .unwrap();
 
-        let span = tracing::info_span!("set_access", ?read, ?write);
+    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
+    assert_matches!(repo.open_file("dst.txt").await, Ok(_));
+}
 
-        async move {
-            repo.set_access(Some(read), Some(write)).await.unwrap();
+#[tokio::test(flavor = "multi_thread")]
+async fn move_onto_open_file() {
+    let (_base_dir, repo) = setup().await;
 
-            for (set_mode, set_secret, expected_mode) in steps {
-                tracing::info!(?set_mode, ?set_secret, ?expected_mode);
+    let mut file = repo.create_file("src.txt").await.unwrap();
+    file.write(b"src").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-                repo.set_access_mode(set_mode, set_secret).await.unwrap();
-                assert_eq!(repo.access_mode(), expected_mode);
-            }
-        }
-        .instrument(span)
-        .await;
-    }
+    let mut file = repo.create_file("dst.txt").await.unwrap();
+    file.write(b"dst").await.unwrap();
+    file.flush().await.unwrap();
+
+    repo.move_entry("/", "src.txt", "/", "dst.txt", true)
+        .await
+        .unwrap();
+
+    assert_matches!(repo.open_file("src.txt").await, Err(Error::EntryNotFound));
+
+    let mut file = repo.open_file("dst.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"src");
 }
 
+// TODO: test reading / writing file that's been removed / moved from / moved onto
+
 #[tokio::test(flavor = "multi_thread")]
-async fn set_access_mode_is_idempotent() {
+async fn blind_access_non_empty_repo() {
     test_utils::init_log();
 
-    let base_dir = TempDir::new().unwrap();
-    let params = RepositoryParams::new(base_dir.path().join("repo.db"));
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params =
+        RepositoryParams::with_pool(pool, "test").with_parent_monitor(StateMonitor::make_root());
     let local_secret = SetLocalSecret::random();
 
+    // Create the repo and put a file in it.
     let repo = Repository::create(
         &params,
         Access::WriteLocked {
             local_read_secret: local_secret.clone(),
-            local_write_secret: local_secret.clone(),
+            local_write_secret: local_secret,
             secrets: WriteSecrets::random(),
         },
     )
     .await
     .unwrap();
 
-    repo.close().await.unwrap();
-
-    let repo = Repository::open(&params, None, AccessMode::Blind)
-        .await
-        .unwrap();
-
-    repo.set_access_mode(AccessMode::Write, Some(local_secret.into()))
-        .await
-        .unwrap();
-    let writer_id_0 = *repo.local_branch().unwrap().id();
+    let mut file = repo.create_file("secret.txt").await.unwrap();
+    file.write_all(b"redacted").await.unwrap();
+    file.flush().await.unwrap();
 
-    repo.set_access_mode(AccessMode::Write, None).await.unwrap();
-    let writer_id_1 = *repo.local_branch().unwrap().id();
+    drop(file);
+    drop(repo);
 
-    assert_eq!(writer_id_0, writer_id_1);
+    // Reopen the repo first explicitly in blind mode and then using incorrect secret. The two ways
+    // should be indistinguishable from each other.
+    for (local_key, access_mode) in [
+        (None, AccessMode::Blind),
+        (Some(LocalSecret::random()), AccessMode::Write),
+    ] {
+        // Reopen the repo in blind mode.
+        let repo = Repository::open(&params, local_key.clone(), access_mode)
+            .await
+            .unwrap_or_else(|_| {
+                panic!(
+                    "Repo should open in blind mode (local_key.is_some:{:?})",
+                    local_key.is_some(),
+                )
+            });
+
+        // Reading files is not allowed.
+        assert_matches!(
+            repo.open_file("secret.txt").await,
+            Err(Error::PermissionDenied)
+        );
+
+        // Creating files is not allowed.
+        assert_matches!(
+            repo.create_file("hack.txt").await,
+            Err(Error::PermissionDenied)
+        );
+
+        // Removing files is not allowed.
+        assert_matches!(
+            repo.remove_entry("secret.txt").await,
+            Err(Error::PermissionDenied)
+        );
+
+        // Reading the root directory is not allowed either.
+        assert_matches!(repo.open_directory("/").await, Err(Error::PermissionDenied));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blind_access_empty_repo() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+
+    let local_secret = SetLocalSecret::random();
+
+    // Create an empty repo.
+    Repository::create(
+        &params,
+        Access::WriteLocked {
+            local_read_secret: local_secret.clone(),
+            local_write_secret: local_secret,
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    // Reopen the repo in blind mode.
+    let repo = Repository::open(&params, Some(LocalSecret::random()), AccessMode::Blind)
+        .await
+        .unwrap();
+
+    // Reading the root directory is not allowed.
+    assert_matches!(repo.open_directory("/").await, Err(Error::PermissionDenied));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn create_with_kdf_params() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+
+    let kdf_params = KdfParams::new(1024, 1, 1).unwrap();
+    let params = RepositoryParams::with_pool(pool, "test").with_kdf_params(kdf_params);
+
+    let password = Password::from("mellon".to_string());
+    let local_secret = SetLocalSecret::Password(password.clone());
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteLocked {
+            local_read_secret: local_secret.clone(),
+            local_write_secret: local_secret,
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(repo.kdf_params().await.unwrap(), kdf_params);
+
+    let salt = repo.get_write_password_salt().await.unwrap();
+
+    repo.close().await.unwrap();
+    drop(repo);
+
+    // The stored params are used when unlocking with the password.
+    let repo = Repository::open(
+        &params,
+        Some(LocalSecret::Password(password.clone())),
+        AccessMode::Write,
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Write);
+    repo.close().await.unwrap();
+    drop(repo);
+
+    // A key derived using different params doesn't unlock the repo.
+    let key = SecretKey::derive_from_password(password.as_ref(), &salt);
+    let repo = Repository::open(
+        &params,
+        Some(LocalSecret::SecretKey(key)),
+        AccessMode::Write,
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Blind);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rekey() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+
+    let old_secret = SetLocalSecret::random();
+    let new_secret = SetLocalSecret::random();
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteLocked {
+            local_read_secret: old_secret.clone(),
+            local_write_secret: old_secret.clone(),
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let writer_id = repo.credentials().writer_id;
+
+    assert_matches!(
+        repo.rekey(LocalSecret::random(), new_secret.clone()).await,
+        Err(Error::PermissionDenied)
+    );
+
+    repo.rekey(old_secret.clone().into(), new_secret.clone())
+        .await
+        .unwrap();
+    repo.close().await.unwrap();
+    drop(repo);
+
+    // The old secret no longer unlocks the repo.
+    let repo = Repository::open(&params, Some(old_secret.into()), AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Blind);
+    repo.close().await.unwrap();
+    drop(repo);
+
+    // The new one does and the content is still readable.
+    let repo = Repository::open(&params, Some(new_secret.into()), AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Write);
+    assert_eq!(repo.credentials().writer_id, writer_id);
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello world");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_access_same_replica() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut file = repo.create_file("public.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+
+    drop(file);
+    drop(repo);
+
+    // Reopen the repo in read-only mode.
+    let repo = Repository::open(&params, None, AccessMode::Read)
+        .await
+        .unwrap();
+
+    // Reading files is allowed.
+    let mut file = repo.open_file("public.txt").await.unwrap();
+
+    let content = file.read_to_end().await.unwrap();
+    assert_eq!(content, b"hello world");
+
+    // Writing is not allowed.
+    file.seek(SeekFrom::Start(0));
+    // short writes that don't cross block boundaries don't trigger the permission check which is
+    // why the following works...
+    file.write_all(b"hello universe").await.unwrap();
+    // ...but flushing the file is not allowed.
+    assert_matches!(file.flush().await, Err(Error::PermissionDenied));
+
+    drop(file);
+
+    // Creating files is not allowed.
+    assert_matches!(
+        repo.create_file("hack.txt").await,
+        Err(Error::PermissionDenied)
+    );
+
+    // Removing files is not allowed.
+    assert_matches!(
+        repo.remove_entry("public.txt").await,
+        Err(Error::PermissionDenied)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_access_different_replica() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+
+    let params_a = RepositoryParams::with_pool(pool.clone(), "test").with_device_id(rand::random());
+    let repo = Repository::create(
+        &params_a,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut file = repo.create_file("public.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+
+    drop(file);
+    drop(repo);
+
+    let params_b = RepositoryParams::with_pool(pool, "test").with_device_id(rand::random());
+    let repo = Repository::open(&params_b, None, AccessMode::Read)
+        .await
+        .unwrap();
+
+    let mut file = repo.open_file("public.txt").await.unwrap();
+    let content = file.read_to_end().await.unwrap();
+    assert_eq!(content, b"hello world");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn truncate_forked_remote_file() {
+    let (_base_dir, repo) = setup().await;
+
+    let remote_id = PublicKey::random();
+    tracing::info!(local_id = ?repo.local_branch().unwrap().id(), ?remote_id);
+
+    create_remote_file(&repo, remote_id, "test.txt", b"foo").await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    file.fork(local_branch).await.unwrap();
+    file.truncate(0).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changed_since() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("d").await.unwrap();
+    repo.create_directory("f").await.unwrap();
+
+    for path in ["a.txt", "d/b.txt", "e.txt", "f/g.txt"] {
+        let mut file = repo.create_file(path).await.unwrap();
+        file.write_all(b"foo").await.unwrap();
+        file.flush().await.unwrap();
+    }
+
+    let vv = repo.local_branch().unwrap().version_vector().await.unwrap();
+
+    let changes: Vec<_> = repo
+        .changed_since(vv.clone())
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(changes.is_empty());
+
+    let mut file = repo.open_file("a.txt").await.unwrap();
+    file.write_all(b"bar").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut file = repo.create_file("d/c.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.remove_entry("e.txt").await.unwrap();
+
+    let mut changes: Vec<_> = repo.changed_since(vv).unwrap().try_collect().await.unwrap();
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    assert_eq!(
+        changes,
+        [
+            (
+                Utf8PathBuf::from("/a.txt"),
+                ChangeKind::Modified(EntryType::File)
+            ),
+            (
+                Utf8PathBuf::from("/d"),
+                ChangeKind::Modified(EntryType::Directory)
+            ),
+            (
+                Utf8PathBuf::from("/d/c.txt"),
+                ChangeKind::Modified(EntryType::File)
+            ),
+            (Utf8PathBuf::from("/e.txt"), ChangeKind::Removed),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_create_file() {
+    let (_base_dir, repo) = setup().await;
+    let local_branch = repo.local_branch().unwrap();
+
+    let root_vv_0 = local_branch.version_vector().await.unwrap();
+
+    let mut file = repo.create_file("parent/test.txt").await.unwrap();
+
+    let root_vv_1 = file
+        .parent()
+        .await
+        .unwrap()
+        .parent()
+        .await
+        .unwrap()
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+    let parent_vv_1 = file.parent().await.unwrap().version_vector().await.unwrap();
+    let file_vv_1 = file.version_vector().await.unwrap();
+
+    assert!(root_vv_1 > root_vv_0);
+
+    file.write_all(b"blah").await.unwrap();
+    file.flush().await.unwrap();
+
+    let root_vv_2 = file
+        .parent()
+        .await
+        .unwrap()
+        .parent()
+        .await
+        .unwrap()
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+    let parent_vv_2 = file.parent().await.unwrap().version_vector().await.unwrap();
+    let file_vv_2 = file.version_vector().await.unwrap();
+
+    assert!(root_vv_2 > root_vv_1);
+    assert!(parent_vv_2 > parent_vv_1);
+    assert!(file_vv_2 > file_vv_1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_deep_hierarchy() {
+    let (_base_dir, repo) = setup().await;
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let depth = 10;
+    let mut dirs = Vec::new();
+    dirs.push(local_branch.open_or_create_root().await.unwrap());
+
+    for i in 0..depth {
+        let dir = dirs
+            .last_mut()
+            .unwrap()
+            .create_directory(format!("dir-{}", i), rand::random(), &VersionVector::new())
+            .await
+            .unwrap();
+        dirs.push(dir);
+    }
+
+    // Each directory's local version is one less than its parent.
+    for (index, dir) in dirs.iter().skip(1).enumerate() {
+        assert_eq!(
+            dir.version_vector().await.unwrap(),
+            vv![local_id => (depth - index) as u64]
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_recreate_deleted_file() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_id = *repo.local_branch().unwrap().id();
+
+    let file = repo.create_file("test.txt").await.unwrap();
+    drop(file);
+
+    repo.remove_entry("test.txt").await.unwrap();
+
+    let file = repo.create_file("test.txt").await.unwrap();
+    assert_eq!(
+        file.version_vector().await.unwrap(),
+        vv![local_id => 3 /* = 1*create + 1*remove + 1*create again */]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_fork() {
+    use tokio::time;
+
+    // TODO: this test would be more precise without merger. Consider converting it to a
+    // joint_directory test.
+
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_branch = repo
+        .get_branch(PublicKey::random())
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    tracing::info!(local_id = ?local_branch.id(), remote_id = ?remote_branch.id());
+
+    time::timeout(Duration::from_secs(5), async move {
+        let mut remote_root = remote_branch.open_or_create_root().await.unwrap();
+        let mut remote_parent = remote_root
+            .create_directory("parent".into(), rand::random(), &VersionVector::new())
+            .await
+            .unwrap();
+        let mut file = create_file_in_directory(&mut remote_parent, "foo.txt", &[]).await;
+
+        remote_parent.refresh().await.unwrap();
+        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
+        let remote_file_vv = file.version_vector().await.unwrap();
+
+        tracing::info!("step 1");
+        file.fork(local_branch.clone()).await.unwrap();
+        tracing::info!("step 2");
+
+        let local_file_vv_0 = file.version_vector().await.unwrap();
+        assert_eq!(local_file_vv_0, remote_file_vv);
+
+        let local_parent_vv_0 = local_branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("parent")
+            .unwrap()
+            .version_vector()
+            .clone();
+
+        assert!(local_parent_vv_0 <= remote_parent_vv);
+
+        drop(file);
+
+        // modify the file and fork again
+        let mut file = remote_parent
+            .lookup("foo.txt")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+        file.write_all(b"hello").await.unwrap();
+        file.flush().await.unwrap();
+
+        remote_parent.refresh().await.unwrap();
+        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
+        let remote_file_vv = file.version_vector().await.unwrap();
+
+        file.fork(local_branch.clone()).await.unwrap();
+
+        let local_file_vv_1 = file.version_vector().await.unwrap();
+        assert_eq!(local_file_vv_1, remote_file_vv);
+        assert!(local_file_vv_1 > local_file_vv_0);
+
+        let local_parent_vv_1 = local_branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("parent")
+            .unwrap()
+            .version_vector()
+            .clone();
+
+        assert!(local_parent_vv_1 <= remote_parent_vv);
+        assert!(local_parent_vv_1 >= local_parent_vv_0);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_empty_directory() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let dir = repo.create_directory("stuff").await.unwrap();
+    assert_eq!(dir.version_vector().await.unwrap(), vv![local_id => 1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_moved_non_empty_directory() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("foo").await.unwrap();
+    repo.create_file("foo/stuff.txt").await.unwrap();
+
+    let vv_0 = repo
+        .local_branch()
+        .unwrap()
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .lookup("foo")
+        .unwrap()
+        .version_vector()
+        .clone();
+
+    repo.move_entry("/", "foo", "/", "bar", true).await.unwrap();
+
+    let vv_1 = repo
+        .local_branch()
+        .unwrap()
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .lookup("bar")
+        .unwrap()
+        .version_vector()
+        .clone();
+
+    assert!(vv_1 > vv_0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_file_moved_over_tombstone() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("old.txt").await.unwrap();
+    file.write_all(b"a").await.unwrap();
+    file.flush().await.unwrap();
+
+    let vv_0 = file.version_vector().await.unwrap();
+
+    drop(file);
+    repo.remove_entry("old.txt").await.unwrap();
+
+    // The tombstone's vv is the original vv incremented by 1.
+    let branch_id = *repo.local_branch().unwrap().id();
+    let vv_1 = vv_0.incremented(branch_id);
+
+    repo.create_file("new.txt").await.unwrap();
+    repo.move_entry("/", "new.txt", "/", "old.txt", true)
+        .await
+        .unwrap();
+
+    let vv_2 = repo
+        .local_branch()
+        .unwrap()
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .lookup("old.txt")
+        .unwrap()
+        .version_vector()
+        .clone();
+
+    assert!(vv_2 > vv_1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_conflict_modify_local() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    // Create two concurrent versions of the same file.
+    let local_file = create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+
+    assert_eq!(
+        local_file.version_vector().await.unwrap(),
+        vv![local_id => 2]
+    );
+    drop(local_file);
+
+    let remote_file = create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+    assert_eq!(
+        remote_file.version_vector().await.unwrap(),
+        vv![remote_id => 2]
+    );
+    drop(remote_file);
+
+    // Modify the local version.
+    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
+    local_file.write_all(b"local v2").await.unwrap();
+    local_file.flush().await.unwrap();
+    drop(local_file);
+
+    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
+    assert_eq!(local_file.read_to_end().await.unwrap(), b"local v2");
+    assert_eq!(
+        local_file.version_vector().await.unwrap(),
+        vv![local_id => 3]
+    );
+
+    let mut remote_file = repo
+        .open_file_version("test.txt", &remote_id)
+        .await
+        .unwrap();
+    assert_eq!(remote_file.read_to_end().await.unwrap(), b"remote v1");
+    assert_eq!(
+        remote_file.version_vector().await.unwrap(),
+        vv![remote_id => 2]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_conflict_attempt_to_fork_and_modify_remote() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    // Create two concurrent versions of the same file.
+    create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+    create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+
+    // Attempt to fork the remote version (fork is required to modify it)
+    let mut remote_file = repo
+        .open_file_version("test.txt", &remote_id)
+        .await
+        .unwrap();
+    assert_matches!(
+        remote_file.fork(local_branch).await,
+        Err(Error::EntryExists)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn size() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.size().await.unwrap().to_bytes(), 0);
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+
+    // 2 blocks: 1 for the file and 1 for the root dir
+    assert_eq!(
+        repo.size().await.unwrap().to_bytes(),
+        2 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+    );
+
+    // 1 block size + 1 byte == 2 blocks
+    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+
+    // 3 blocks: 2 for the file and 1 for the root dir
+    assert_eq!(
+        repo.size().await.unwrap().to_bytes(),
+        3 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn access_mode() {
+    let secret1 = SetLocalSecret::random();
+    let secret2 = SetLocalSecret::random();
+
+    // TODO: given the large number of cases, consider converting this to a proptest.
+
+    #[rustfmt::skip]
+    let cases = [
+        (
+
+            // read access
+            AccessChange::Enable(None),
+            // write access
+            AccessChange::Enable(None),
+            // test steps
+            vec![
+                // params to `set_access_mode`                    expected mode
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Read,  None,                         AccessMode::Read),
+                (AccessMode::Write, None,                         AccessMode::Write),
+            ],
+        ),
+        (
+            AccessChange::Enable(None),
+            AccessChange::Enable(Some(secret1.clone())),
+            vec![
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Blind, Some(secret1.clone().into()), AccessMode::Blind),
+                (AccessMode::Read,  None,                         AccessMode::Read),
+                (AccessMode::Read,  Some(secret1.clone().into()), AccessMode::Read),
+                (AccessMode::Write, None,                         AccessMode::Read),
+                (AccessMode::Write, Some(secret1.clone().into()), AccessMode::Write),
+            ],
+        ),
+        (
+            AccessChange::Enable(Some(secret1.clone())),
+            AccessChange::Enable(Some(secret1.clone())),
+            vec![
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Blind, Some(secret1.clone().into()), AccessMode::Blind),
+                (AccessMode::Read,  None,                         AccessMode::Blind),
+                (AccessMode::Write, None,                         AccessMode::Blind),
+                (AccessMode::Read,  Some(secret1.clone().into()), AccessMode::Read),
+                (AccessMode::Write, Some(secret1.clone().into()), AccessMode::Write),
+            ],
+        ),
+        (
+            AccessChange::Enable(Some(secret1.clone())),
+            AccessChange::Enable(Some(secret2.clone())),
+            vec![
+                (AccessMode::Blind, None, AccessMode::Blind),
+                (AccessMode::Blind, Some(secret1.clone().into()), AccessMode::Blind),
+                (AccessMode::Blind, Some(secret2.clone().into()), AccessMode::Blind),
+                (AccessMode::Read,  None,                         AccessMode::Blind),
+                (AccessMode::Write, None,                         AccessMode::Blind),
+                (AccessMode::Read,  Some(secret1.clone().into()), AccessMode::Read),
+                (AccessMode::Write, Some(secret2.clone().into()), AccessMode::Write),
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Read,  Some(secret2.clone().into()), AccessMode::Read),
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Write, Some(secret1.clone().into()), AccessMode::Read),
+            ],
+        ),
+        (
+            AccessChange::Enable(None),
+            AccessChange::Disable,
+            vec![
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Read,  None,                         AccessMode::Read),
+                (AccessMode::Write, None,                         AccessMode::Read),
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Write, None,                         AccessMode::Read),
+            ],
+        ),
+        (
+            AccessChange::Disable,
+            AccessChange::Disable,
+            vec![
+                (AccessMode::Blind, None,                         AccessMode::Blind),
+                (AccessMode::Read,  None,                         AccessMode::Blind),
+                (AccessMode::Write, None,                         AccessMode::Blind),
+            ],
+        ),
+    ];
+
+    // TODO: test also wrong local secret
+    // TODO: test "nonsensical" cases, e.g., enabling write mode but disabling read mode, etc...
+
+    for (read, write, steps) in cases {
+        let (_base_dir, repo) = setup().await;
+
+        let span = tracing::info_span!("set_access", ?read, ?write);
+
+        async move {
+            repo.set_access(Some(read), Some(write)).await.unwrap();
+
+            for (set_mode, set_secret, expected_mode) in steps {
+                tracing::info!(?set_mode, ?set_secret, ?expected_mode);
+
+                repo.set_access_mode(set_mode, set_secret).await.unwrap();
+                assert_eq!(repo.access_mode(), expected_mode);
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_access_mode_is_idempotent() {
+    test_utils::init_log();
+
+    let base_dir = TempDir::new().unwrap();
+    let params = RepositoryParams::new(base_dir.path().join("repo.db"));
+    let local_secret = SetLocalSecret::random();
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteLocked {
+            local_read_secret: local_secret.clone(),
+            local_write_secret: local_secret.clone(),
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    repo.close().await.unwrap();
+
+    let repo = Repository::open(&params, None, AccessMode::Blind)
+        .await
+        .unwrap();
+
+    repo.set_access_mode(AccessMode::Write, Some(local_secret.into()))
+        .await
+        .unwrap();
+    let writer_id_0 = *repo.local_branch().unwrap().id();
+
+    repo.set_access_mode(AccessMode::Write, None).await.unwrap();
+    let writer_id_1 = *repo.local_branch().unwrap().id();
+
+    assert_eq!(writer_id_0, writer_id_1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dedup_stats() {
+    let (_base_dir, repo) = setup().await;
+
+    let stats = repo.dedup_stats().await.unwrap();
+    assert_eq!(stats, DedupStats::default());
+    assert_eq!(stats.ratio(), None);
+
+    let local_branch = repo.local_branch().unwrap();
+    let mut content = vec![0; 2 * BLOCK_SIZE];
+    rand::thread_rng().fill(&mut content[..]);
+    create_file_in_branch(&local_branch, "a.dat", &content).await;
+
+    let stats = repo.dedup_stats().await.unwrap();
+    assert!(stats.unique_blocks > 0);
+    assert_eq!(stats.shared_blocks, 0);
+    assert_eq!(stats.logical_bytes, stats.physical_bytes);
+
+    // Fork the local branch. The forked branch shares all its blocks with the original one except
+    // those we modify afterwards.
+    let remote_branch = local_branch.clone_into(PublicKey::random()).await.unwrap();
+    create_file_in_branch(&remote_branch, "b.dat", b"hello").await;
+
+    let stats = repo.dedup_stats().await.unwrap();
+    assert!(stats.shared_blocks > 0);
+    assert!(stats.logical_bytes > stats.physical_bytes);
+    assert!(stats.ratio().unwrap() > 1.0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sizes() {
+    let (_base_dir, repo) = setup().await;
+
+    let sizes = repo.sizes().await.unwrap();
+    assert_eq!(sizes.logical, StorageSize::from_bytes(0));
+    assert!(sizes.physical_db_file > StorageSize::from_bytes(0));
+
+    let content = random_bytes(4 * BLOCK_SIZE);
+    let mut file = repo.create_file("a.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let sizes = repo.sizes().await.unwrap();
+    assert_eq!(sizes.logical, StorageSize::from_bytes(content.len() as u64));
+    assert!(sizes.deduplicated > sizes.logical);
+
+    // The linked file shares all its blocks with the original one.
+    repo.link("a.dat", "b.dat").await.unwrap();
+
+    let sizes = repo.sizes().await.unwrap();
+    assert_eq!(
+        sizes.logical,
+        StorageSize::from_bytes(2 * content.len() as u64)
+    );
+    assert!(sizes.deduplicated < sizes.logical);
+    assert!(sizes.physical_db_file > sizes.deduplicated);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_corrupted() {
+    test_utils::init_log();
+
+    let base_dir = TempDir::new().unwrap();
+    let path = base_dir.path().join(DEFAULT_REPO_NAME);
+
+    let mut content = vec![0; 4096];
+    rand::thread_rng().fill(&mut content[..]);
+    fs::write(&path, &content).await.unwrap();
+
+    assert_matches!(
+        Repository::open(&RepositoryParams::new(&path), None, AccessMode::Write).await,
+        Err(Error::Corrupted(_))
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recover() {
+    let (base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.close().await.unwrap();
+
+    let src = base_dir.path().join(DEFAULT_REPO_NAME);
+    let dst = base_dir.path().join("recovered.ouisyncdb");
+
+    let report = Repository::recover(&src, &dst).await.unwrap();
+    assert!(report.recovered > 0);
+    assert!(report.complete);
+
+    // Recovering into an existing database is not allowed.
+    assert_matches!(
+        Repository::recover(&src, &dst).await,
+        Err(Error::Db(db::Error::Exists))
+    );
+
+    let repo = Repository::open(&RepositoryParams::new(&dst), None, AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello world");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recover_damaged() {
+    use sqlx::Row;
+
+    let (base_dir, repo) = setup().await;
+
+    let content = random_bytes(8 * BLOCK_SIZE);
+    repo.write_file("test.dat", &content).await.unwrap();
+
+    // Pick one of the stored blocks to be damaged.
+    let damaged: Vec<u8> = {
+        let mut reader = repo.shared.vault.store().acquire_read().await.unwrap();
+        sqlx::query("SELECT content FROM blocks LIMIT 1")
+            .fetch_one(reader.db())
+            .await
+            .unwrap()
+            .get(0)
+    };
+
+    let total = repo.sync_progress().await.unwrap().total;
+    repo.close().await.unwrap();
+
+    let src = base_dir.path().join(DEFAULT_REPO_NAME);
+    let dst = base_dir.path().join("recovered.ouisyncdb");
+
+    // Damage the db file by breaking the overflow page chain of the block content.
+    let mut db_content = fs::read(&src).await.unwrap();
+    let page_size = match u16::from_be_bytes([db_content[16], db_content[17]]) {
+        1 => 65536,
+        n => n as usize,
+    };
+    let needle = &damaged[4096..4096 + 32];
+    let offset = db_content
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .unwrap();
+    let page_start = offset / page_size * page_size;
+    db_content[page_start..page_start + 4].fill(0xff);
+    fs::write(&src, &db_content).await.unwrap();
+
+    let report = Repository::recover(&src, &dst).await.unwrap();
+    assert!(report.recovered > 0);
+    assert!(report.lost_blocks > 0);
+    assert!(!report.complete);
+
+    // The lost block is marked as missing so it can be downloaded again.
+    let repo = Repository::open(&RepositoryParams::new(&dst), None, AccessMode::Write)
+        .await
+        .unwrap();
+
+    let progress = repo.sync_progress().await.unwrap();
+    assert_eq!(progress.total, total);
+    assert!(progress.value < total);
+
+    let root_node = repo
+        .shared
+        .vault
+        .store()
+        .acquire_read()
+        .await
+        .unwrap()
+        .load_root_node(repo.local_branch().unwrap().id(), RootNodeFilter::Any)
+        .await
+        .unwrap();
+    assert_matches!(
+        root_node.summary.block_presence,
+        MultiBlockPresence::Some(_)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_small() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.write_atomic("config.json", b"{}").await.unwrap();
+    assert_eq!(repo.read_small("config.json", 16).await.unwrap(), b"{}");
+    assert_eq!(repo.read_small("config.json", 2).await.unwrap(), b"{}");
+    assert_matches!(
+        repo.read_small("config.json", 1).await,
+        Err(Error::FileTooLarge)
+    );
+
+    // Spanning multiple blocks
+    let content = random_bytes(2 * BLOCK_SIZE);
+    repo.write_atomic("data.bin", &content).await.unwrap();
+    assert_eq!(
+        repo.read_small("data.bin", content.len() as u64)
+            .await
+            .unwrap(),
+        content
+    );
+
+    repo.create_directory("dir").await.unwrap();
+    assert_matches!(
+        repo.read_small("dir", 16).await,
+        Err(Error::EntryIsDirectory)
+    );
+    assert_matches!(
+        repo.read_small("missing.txt", 16).await,
+        Err(Error::EntryNotFound)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stat() {
+    let (_base_dir, repo) = setup().await;
+    let local_id = *repo.local_branch().unwrap().id();
+
+    let content = random_bytes(2 * BLOCK_SIZE);
+    repo.create_directory("dir").await.unwrap();
+    repo.write_atomic("dir/data.bin", &content).await.unwrap();
+
+    let meta = repo.stat("dir/data.bin").await.unwrap();
+    assert_eq!(meta.entry_type, EntryType::File);
+    assert_eq!(meta.len, content.len() as u64);
+    assert_eq!(meta.writer_id, local_id);
+    assert_eq!(
+        meta.version_vector,
+        repo.open_file("dir/data.bin")
+            .await
+            .unwrap()
+            .version_vector()
+            .await
+            .unwrap()
+    );
+    assert_eq!(meta.blocks.total, 3);
+    assert!(meta.is_complete());
+
+    let meta = repo.stat("dir").await.unwrap();
+    assert_eq!(meta.entry_type, EntryType::Directory);
+    assert_eq!(meta.len, 0);
+    assert_eq!(meta.writer_id, local_id);
+    assert!(meta.is_complete());
+
+    let meta = repo.stat("/").await.unwrap();
+    assert_eq!(meta.entry_type, EntryType::Directory);
+    assert_eq!(
+        meta.version_vector,
+        repo.local_branch().unwrap().version_vector().await.unwrap()
+    );
+
+    assert_matches!(repo.stat("missing").await, Err(Error::EntryNotFound));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stat_partially_present_file() {
+    let (_base_dir, repo) = setup().await;
+
+    let content = random_bytes(2 * BLOCK_SIZE);
+    repo.write_atomic("data.bin", &content).await.unwrap();
+
+    // Remove the last block
+    let block_id = {
+        let mut file = repo.open_file("data.bin").await.unwrap();
+        file.seek(SeekFrom::Start(2 * BLOCK_SIZE as u64 - 1));
+        file.current_block_id().await.unwrap()
+    };
+
+    let mut tx = repo.shared.vault.store().begin_write().await.unwrap();
+    tx.remove_block(&block_id).await.unwrap();
+    tx.commit().await.unwrap();
+
+    let meta = repo.stat("data.bin").await.unwrap();
+    assert_eq!(meta.len, content.len() as u64);
+    assert_eq!(meta.blocks.value, 2);
+    assert_eq!(meta.blocks.total, 3);
+    assert!(!meta.is_complete());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_file_size() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.max_file_size(), None);
+
+    repo.set_max_file_size(Some(10)).await.unwrap();
+    assert_eq!(repo.max_file_size(), Some(10));
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+
+    // `write_all` doesn't write anything if it would exceed the limit.
+    assert_matches!(
+        file.write_all(b"hello world").await,
+        Err(Error::FileTooLarge)
+    );
+    assert_eq!(file.len(), 0);
+
+    file.write_all(b"hello").await.unwrap();
+
+    // `write` writes only up to the limit...
+    assert_eq!(file.write(b" world").await.unwrap(), 5);
+    assert_eq!(file.len(), 10);
+
+    // ...and fails when the limit is reached.
+    assert_matches!(file.write(b"!").await, Err(Error::FileTooLarge));
+    assert_matches!(file.set_len(11).await, Err(Error::FileTooLarge));
+
+    // Overwriting and truncating is still possible.
+    file.seek(SeekFrom::Start(0));
+    file.write_all(b"HELLO").await.unwrap();
+    file.set_len(5).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert_eq!(read_file(&repo, "test.txt").await, b"HELLO");
+
+    repo.set_max_file_size(None).await.unwrap();
+
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    file.seek(SeekFrom::End(0));
+    file.write_all(b" world, hello again").await.unwrap();
+    file.flush().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_file_size_of_already_larger_file() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.set_max_file_size(Some(5)).await.unwrap();
+
+    let mut file = repo.open_file("test.txt").await.unwrap();
+
+    // Rewriting the existing content doesn't grow the file and so is allowed...
+    file.write_all(b"HELLO WORLD").await.unwrap();
+
+    // ...but growing it is not.
+    assert_matches!(file.write(b"!").await, Err(Error::FileTooLarge));
+    assert_matches!(file.write_all(b"!").await, Err(Error::FileTooLarge));
+
+    // Shrinking is allowed, even if the file remains larger than the limit.
+    file.set_len(8).await.unwrap();
+    assert_matches!(file.set_len(9).await, Err(Error::FileTooLarge));
+
+    // The file can't grow back to its previous length either.
+    assert_matches!(file.write(b"!").await, Err(Error::FileTooLarge));
+
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert_eq!(read_file(&repo, "test.txt").await, b"HELLO WO");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_name_length() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.max_name_length(), None);
+
+    repo.set_max_name_length(Some(8)).await.unwrap();
+    assert_eq!(repo.max_name_length(), Some(8));
+
+    repo.create_file("12345678").await.unwrap();
+    assert_matches!(repo.create_file("123456789").await, Err(Error::NameTooLong));
+    assert_matches!(
+        repo.create_directory("dir/123456789").await,
+        Err(Error::NameTooLong)
+    );
+
+    repo.set_max_name_length(None).await.unwrap();
+    repo.create_file("123456789").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn name_normalization() {
+    let (_base_dir, repo) = setup().await;
+    assert!(!repo.is_name_normalization_enabled());
+
+    repo.set_name_normalization_enabled(true).await.unwrap();
+    assert!(repo.is_name_normalization_enabled());
+
+    let nfd = "cafe\u{301}.txt";
+    let nfc = "caf\u{e9}.txt";
+
+    let mut file = repo.create_file(nfd).await.unwrap();
+    file.write_all(b"hello").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    // Stored under the normalized name.
+    let root = repo.open_directory("/").await.unwrap();
+    let names: Vec<_> = root
+        .entries()
+        .map(|entry| entry.name().to_owned())
+        .collect();
+    assert_eq!(names, [nfc]);
+
+    // Can be looked up using either form.
+    assert_eq!(read_file(&repo, nfc).await, b"hello");
+    assert_eq!(read_file(&repo, nfd).await, b"hello");
+
+    // Creating it again using the other form doesn't create a duplicate.
+    assert_matches!(repo.create_file(nfc).await, Err(Error::EntryExists));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_atomic() {
+    let (_base_dir, repo) = setup().await;
+
+    // Create
+    repo.write_atomic("test.txt", b"hello").await.unwrap();
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello");
+
+    // Replace with a multi-block content
+    let content = random_bytes(3 * BLOCK_SIZE);
+    repo.write_atomic("test.txt", &content).await.unwrap();
+    assert_eq!(read_file(&repo, "test.txt").await, content);
+
+    // Replace with a shorter content
+    repo.write_atomic("test.txt", b"bye").await.unwrap();
+    assert_eq!(read_file(&repo, "test.txt").await, b"bye");
+
+    // The replaced file is a new blob but the identity of the entry is preserved (its version
+    // vector grows).
+    let vv0 = repo
+        .open_file("test.txt")
+        .await
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+    repo.write_atomic("test.txt", b"again").await.unwrap();
+    let vv1 = repo
+        .open_file("test.txt")
+        .await
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+    assert!(vv1 > vv0);
+
+    // Can't replace a directory
+    repo.create_directory("dir").await.unwrap();
+    assert_matches!(
+        repo.write_atomic("dir", b"hello").await,
+        Err(Error::EntryIsDirectory)
+    );
+
+    // Parent directory must exist
+    assert_matches!(
+        repo.write_atomic("missing/test.txt", b"hello").await,
+        Err(Error::EntryNotFound)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_file() {
+    let (_base_dir, repo) = setup().await;
+
+    // Create, including the missing parent directories
+    let len = repo.write_file("a/b/test.txt", b"hello").await.unwrap();
+    assert_eq!(len, 5);
+    assert_eq!(read_file(&repo, "a/b/test.txt").await, b"hello");
+
+    // Replace with a multi-block content
+    let content = random_bytes(3 * BLOCK_SIZE);
+    let len = repo.write_file("a/b/test.txt", &content).await.unwrap();
+    assert_eq!(len, content.len() as u64);
+    assert_eq!(read_file(&repo, "a/b/test.txt").await, content);
+
+    // Replace with a shorter content
+    let len = repo.write_file("a/b/test.txt", b"bye").await.unwrap();
+    assert_eq!(len, 3);
+    assert_eq!(read_file(&repo, "a/b/test.txt").await, b"bye");
+
+    // Can't write into a directory
+    assert_matches!(
+        repo.write_file("a/b", b"hello").await,
+        Err(Error::EntryIsDirectory)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_file_replaces_remote_version() {
+    let (_base_dir, repo) = setup().await;
+    let remote_id = PublicKey::random();
+
+    create_remote_file(&repo, remote_id, "test.txt", b"remote").await;
+
+    repo.write_file("test.txt", b"local").await.unwrap();
+
+    // The remote version has been forked into the local branch and then overwritten.
+    let file = repo.open_file("test.txt").await.unwrap();
+    assert_eq!(file.branch().id(), repo.local_branch().unwrap().id());
+    drop(file);
+
+    assert_eq!(read_file(&repo, "test.txt").await, b"local");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_atomic_supersedes_remote_version() {
+    let (_base_dir, repo) = setup().await;
+    let remote_id = PublicKey::random();
+
+    create_remote_file(&repo, remote_id, "test.txt", b"remote").await;
+
+    repo.write_atomic("test.txt", b"local").await.unwrap();
+
+    // No conflict - the local version is the only one.
+    let file = repo.open_file("test.txt").await.unwrap();
+    assert_eq!(file.branch().id(), repo.local_branch().unwrap().id());
+    drop(file);
+
+    assert_eq!(read_file(&repo, "test.txt").await, b"local");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pull_from() {
+    let (_base_dir, repo) = setup().await;
+    repo.set_auto_merge(false).await.unwrap();
+
+    let local_id = *repo.local_branch().unwrap().id();
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    let mut dir = remote_branch
+        .ensure_directory_exists(Utf8Path::new("dir"))
+        .await
+        .unwrap();
+    create_file_in_directory(&mut dir, "a.txt", b"remote a").await;
+
+    let mut dir = remote_branch
+        .ensure_directory_exists(Utf8Path::new("dir/sub"))
+        .await
+        .unwrap();
+    create_file_in_directory(&mut dir, "b.txt", b"remote b").await;
+
+    create_file_in_branch(&remote_branch, "c.txt", b"remote c").await;
+
+    // Concurrent local version of one of the files plus a local-only file.
+    repo.write_file("dir/a.txt", b"local a").await.unwrap();
+    repo.write_file("dir/local.txt", b"mine").await.unwrap();
+
+    repo.pull_from(remote_id, "dir").await.unwrap();
+
+    // The pulled versions are in the local branch and supersede the remote ones (no conflict).
+    for (path, content) in [
+        ("dir/a.txt", &b"remote a"[..]),
+        ("dir/sub/b.txt", b"remote b"),
+        ("dir/local.txt", b"mine"),
+    ] {
+        let mut file = repo.open_file(path).await.unwrap();
+        assert_eq!(file.branch().id(), &local_id, "{path}");
+        assert_eq!(file.read_to_end().await.unwrap(), content, "{path}");
+    }
+
+    // Entries outside of the pulled path are not affected.
+    let file = repo.open_file("c.txt").await.unwrap();
+    assert_eq!(file.branch().id(), &remote_id);
+    drop(file);
+
+    // Files removed in the source branch are removed locally as well.
+    let mut dir = remote_branch
+        .ensure_directory_exists(Utf8Path::new("dir/sub"))
+        .await
+        .unwrap();
+    let vv = dir.lookup("b.txt").unwrap().version_vector().clone();
+    dir.remove_entry("b.txt", &remote_id, vv).await.unwrap();
+
+    repo.pull_from(remote_id, "dir/sub").await.unwrap();
+    assert_matches!(
+        repo.open_file("dir/sub/b.txt").await,
+        Err(Error::EntryNotFound)
+    );
+
+    assert_matches!(
+        repo.pull_from(remote_id, "missing.txt").await,
+        Err(Error::EntryNotFound)
+    );
+
+    // Pulling from the local branch is a no-op.
+    repo.pull_from(local_id, "dir").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn link() {
+    let (_base_dir, repo) = setup().await;
+
+    // 3 blocks (including the header)
+    let content = random_bytes(2 * BLOCK_SIZE);
+
+    let mut file = repo.create_file("a.txt").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert_eq!(repo.dedup_stats().await.unwrap().shared_blocks, 0);
+
+    repo.link("a.txt", "dir/b.txt").await.unwrap();
+    assert_eq!(read_file(&repo, "dir/b.txt").await, content);
+
+    // The blocks are shared, not copied.
+    assert_eq!(repo.dedup_stats().await.unwrap().shared_blocks, 3);
+
+    // Modifying one file doesn't affect the other.
+    let mut file = repo.open_file("dir/b.txt").await.unwrap();
+    file.write_all(b"xyz").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert_eq!(read_file(&repo, "a.txt").await, content);
+
+    let mut expected = content.clone();
+    expected[..3].copy_from_slice(b"xyz");
+    assert_eq!(read_file(&repo, "dir/b.txt").await, expected);
+
+    assert_eq!(repo.dedup_stats().await.unwrap().shared_blocks, 2);
+
+    // Destination must not exist
+    assert_matches!(
+        repo.link("a.txt", "dir/b.txt").await,
+        Err(Error::EntryExists)
+    );
+
+    // Source must be a file
+    assert_matches!(
+        repo.link("dir", "c.txt").await,
+        Err(Error::EntryIsDirectory)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remove_unreachable_blocks_in_small_batches() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.gc_batch_size().await.unwrap(), 32);
+
+    repo.set_gc_batch_size(Some(1)).await.unwrap();
+    assert_eq!(repo.gc_batch_size().await.unwrap(), 1);
+
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    wait_for(&repo, || async { repo.count_blocks().await.unwrap() == 2 }).await;
+
+    let mut file = repo.open_file("test.dat").await.unwrap();
+    file.write_all(&random_bytes(4 * BLOCK_SIZE)).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert!(repo.count_blocks().await.unwrap() > 2);
+
+    repo.remove_entry("test.dat").await.unwrap();
+
+    // All the blocks of the removed file are collected, one at a time.
+    wait_for(&repo, || async { repo.count_blocks().await.unwrap() == 1 }).await;
+
+    repo.set_gc_batch_size(None).await.unwrap();
+    assert_eq!(repo.gc_batch_size().await.unwrap(), 32);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn block_expiration_policy_persists() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.block_expiration_policy().await, None);
+
+    let policy = ExpirationPolicy::Both(Duration::from_secs(60), StorageSize::from_blocks(16));
+    repo.set_block_expiration_policy(Some(policy))
+        .await
+        .unwrap();
+    assert_eq!(repo.block_expiration_policy().await, Some(policy));
+    assert_eq!(repo.block_expiration().await, Some(Duration::from_secs(60)));
+
+    drop(repo);
+
+    let repo = Repository::open(&params, None, AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(repo.block_expiration_policy().await, Some(policy));
+
+    // Setting only the expiration time drops the size limit.
+    repo.set_block_expiration(Some(Duration::from_secs(30)))
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.block_expiration_policy().await,
+        Some(ExpirationPolicy::TimeBased(Duration::from_secs(30)))
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_versions_per_file() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.max_versions_per_file().await.unwrap(), None);
+
+    repo.set_max_versions_per_file(Some(3)).await.unwrap();
+    assert_eq!(repo.max_versions_per_file().await.unwrap(), Some(3));
+
+    repo.set_max_versions_per_file(None).await.unwrap();
+    assert_eq!(repo.max_versions_per_file().await.unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sync_directories_first() {
+    let (_base_dir, repo) = setup().await;
+    assert!(!repo.is_sync_directories_first_enabled().await.unwrap());
+
+    repo.set_sync_directories_first(true).await.unwrap();
+    assert!(repo.is_sync_directories_first_enabled().await.unwrap());
+
+    repo.set_sync_directories_first(false).await.unwrap();
+    assert!(!repo.is_sync_directories_first_enabled().await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn share_access() {
+    let (_base_dir, repo) = setup().await;
+
+    assert_eq!(repo.share_access(None).await.unwrap(), AccessMode::Write);
+    assert_eq!(
+        repo.share_access(Some(AccessMode::Blind)).await.unwrap(),
+        AccessMode::Blind
+    );
+
+    repo.set_default_share_access(AccessMode::Read)
+        .await
+        .unwrap();
+    assert_eq!(repo.default_share_access().await.unwrap(), AccessMode::Read);
+    assert_eq!(repo.share_access(None).await.unwrap(), AccessMode::Read);
+    assert_eq!(
+        repo.share_access(Some(AccessMode::Write)).await.unwrap(),
+        AccessMode::Write
+    );
+
+    // The max is applied regardless of the requested mode.
+    repo.set_max_share_access(AccessMode::Blind).await.unwrap();
+    assert_eq!(repo.max_share_access().await.unwrap(), AccessMode::Blind);
+    assert_eq!(repo.share_access(None).await.unwrap(), AccessMode::Blind);
+    assert_eq!(
+        repo.share_access(Some(AccessMode::Write)).await.unwrap(),
+        AccessMode::Blind
+    );
+    assert_eq!(
+        repo.share_secrets(None, Some(AccessMode::Write))
+            .await
+            .unwrap()
+            .access_mode(),
+        AccessMode::Blind
+    );
+
+    repo.set_max_share_access(AccessMode::Read).await.unwrap();
+    assert_eq!(
+        repo.share_secrets(None, None).await.unwrap().access_mode(),
+        AccessMode::Read
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn type_conflict_policy_persists() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.type_conflict_policy(), TypeConflictPolicy::KeepBoth);
+
+    repo.set_type_conflict_policy(TypeConflictPolicy::PreferDirectory)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.type_conflict_policy(),
+        TypeConflictPolicy::PreferDirectory
+    );
+
+    drop(repo);
+
+    let repo = Repository::open(&params, None, AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.type_conflict_policy(),
+        TypeConflictPolicy::PreferDirectory
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn purge_tombstones() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.tombstone_ttl().await.unwrap(), None);
+
+    repo.set_tombstone_ttl(Some(Duration::ZERO)).await.unwrap();
+    assert_eq!(repo.tombstone_ttl().await.unwrap(), Some(Duration::ZERO));
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.remove_entry("test.txt").await.unwrap();
+
+    // There are no remote branches so the tombstone is purged once it's older than the TTL
+    // (immediately in this case).
+    let branch = repo.local_branch().unwrap();
+
+    wait_for(&repo, || async {
+        let root = branch
+            .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap();
+
+        matches!(root.lookup("test.txt"), Err(Error::EntryNotFound))
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn purged_tombstones_are_not_merged_back() {
+    let (_base_dir, repo) = setup().await;
+
+    // Long enough to be never reached during the test, so the tombstone is purged only once the
+    // remote branch has it too.
+    repo.set_tombstone_ttl(Some(Duration::from_secs(24 * 60 * 60)))
+        .await
+        .unwrap();
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    // Create and remove a file in the remote branch.
+    create_file_in_branch(&remote_branch, "test.txt", b"hello").await;
+
+    let mut remote_root = remote_branch
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+    let vv = remote_root
+        .lookup("test.txt")
+        .unwrap()
+        .version_vector()
+        .clone();
+    remote_root
+        .remove_entry("test.txt", &remote_id, vv)
+        .await
+        .unwrap();
+
+    // Whether the local branch has any entry (including a tombstone) named "test.txt".
+    let exists_locally = || async {
+        let root = local_branch
+            .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap();
+
+        match root.lookup("test.txt") {
+            Ok(_) => true,
+            Err(Error::EntryNotFound) => false,
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    };
+
+    let is_merged = || async {
+        local_branch.version_vector().await.unwrap()
+            >= remote_branch.version_vector().await.unwrap()
+    };
+
+    // The tombstone is merged into the local branch and then purged because the remote branch has
+    // it too.
+    wait_for(&repo, || async {
+        is_merged().await && !exists_locally().await
+    })
+    .await;
+
+    // Stop purging so the tombstone would stay if it came back.
+    repo.set_tombstone_ttl(None).await.unwrap();
+
+    // Modify the remote branch to trigger another merge.
+    create_file_in_branch(&remote_branch, "other.txt", b"world").await;
+
+    wait_for(&repo, || async {
+        is_merged().await && repo.open_file("other.txt").await.is_ok()
+    })
+    .await;
+
+    assert!(!exists_locally().await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_directory_in_pages() {
+    let (_base_dir, repo) = setup().await;
+
+    // Create them in non-sorted order.
+    let mut names: Vec<_> = (0..10).rev().map(|i| format!("file-{i}.txt")).collect();
+
+    for name in &names {
+        let mut file = repo.create_file(name).await.unwrap();
+        file.flush().await.unwrap();
+    }
+
+    names.sort();
+
+    let root = repo.open_directory("/").await.unwrap();
+    let all: Vec<_> = root
+        .entries()
+        .map(|entry| entry.unique_name().into_owned())
+        .collect();
+    assert_eq!(all, names);
+
+    let page_size = 3;
+    let mut paged = Vec::new();
+    let mut after = None;
+
+    loop {
+        let page: Vec<_> = root
+            .entries_after(after.as_deref(), page_size)
+            .map(|entry| entry.name().to_owned())
+            .collect();
+
+        assert!(page.len() <= page_size);
+
+        let Some(last) = page.last() else {
+            break;
+        };
+
+        after = Some(last.clone());
+        paged.extend(page);
+    }
+
+    assert_eq!(paged, names);
+    assert_eq!(
+        root.entries_after(names.last().map(|name| name.as_str()), page_size)
+            .count(),
+        0
+    );
+
+    // Modify the directory between the pages. The listing resumes after the last seen name
+    // without skipping or repeating any entries and the removed ones (now tombstones) are not
+    // returned.
+    let first_page: Vec<_> = root
+        .entries_after(None, page_size)
+        .map(|entry| entry.name().to_owned())
+        .collect();
+    assert_eq!(first_page, names[..page_size]);
+
+    repo.remove_entry(&names[0]).await.unwrap();
+    repo.remove_entry(&names[page_size + 1]).await.unwrap();
+
+    let mut file = repo.create_file("file-00.txt").await.unwrap();
+    file.flush().await.unwrap();
+
+    let root = repo.open_directory("/").await.unwrap();
+    let rest: Vec<_> = root
+        .entries_after(first_page.last().map(|name| name.as_str()), names.len())
+        .map(|entry| entry.name().to_owned())
+        .collect();
+
+    let expected: Vec<_> = names[page_size..]
+        .iter()
+        .filter(|name| **name != names[page_size + 1])
+        .cloned()
+        .collect();
+    assert_eq!(rest, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn is_fully_synced() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.write_file("local.txt", b"local").await.unwrap();
+    assert!(repo.is_fully_synced().await.unwrap());
+
+    // The remote branch is eventually merged into the local one.
+    create_remote_file(&repo, PublicKey::random(), "remote.txt", b"remote").await;
+    wait_for(&repo, || async { repo.is_fully_synced().await.unwrap() }).await;
+
+    assert_eq!(read_file(&repo, "remote.txt").await, b"remote");
+
+    // Not synced while some blocks are missing.
+    repo.write_atomic("data.bin", &random_bytes(BLOCK_SIZE))
+        .await
+        .unwrap();
+    assert!(repo.is_fully_synced().await.unwrap());
+
+    let block_id = {
+        let mut file = repo.open_file("data.bin").await.unwrap();
+        file.seek(SeekFrom::Start(BLOCK_SIZE as u64 - 1));
+        file.current_block_id().await.unwrap()
+    };
+
+    let mut tx = repo.shared.vault.store().begin_write().await.unwrap();
+    tx.remove_block(&block_id).await.unwrap();
+    tx.commit().await.unwrap();
+
+    assert!(!repo.is_fully_synced().await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn disable_auto_merge() {
+    let (_base_dir, repo) = setup().await;
+
+    assert!(repo.is_auto_merge_enabled().await.unwrap());
+    repo.set_auto_merge(false).await.unwrap();
+    assert!(!repo.is_auto_merge_enabled().await.unwrap());
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+    let mut rx = repo.subscribe();
+
+    create_remote_file(&repo, remote_id, "test.txt", b"hello").await;
+
+    let remote_vv = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+
+    // Wait for the maintenance triggered by the remote branch change.
+    time::timeout(Duration::from_secs(10), async {
+        loop {
+            match rx.recv().await {
+                Ok(Event {
+                    payload: Payload::MaintenanceCompleted,
+                    ..
+                }) => break,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => panic!("notification channel unexpectedly closed"),
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    // Not merged but still readable.
+    assert!(!(local_branch.version_vector().await.unwrap() >= remote_vv));
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello");
+
+    // Manual merge
+    repo.merge().await.unwrap();
+    assert!(local_branch.version_vector().await.unwrap() >= remote_vv);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_manual_and_auto_merge() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+
+    create_remote_file(&repo, remote_id, "test.txt", b"hello").await;
+
+    let remote_vv = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+
+    // Racing with the merge triggered by the remote branch change and with each other.
+    let (a, b) = future::join(repo.merge(), repo.merge()).await;
+    a.unwrap();
+    b.unwrap();
+
+    assert!(local_branch.version_vector().await.unwrap() >= remote_vv);
+
+    let root = repo.open_directory("/").await.unwrap();
+    assert_eq!(root.lookup("test.txt").unwrap().count(), 1);
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compare_with() {
+    use futures_util::TryStreamExt;
+
+    let (_base_dir_a, repo_a) = setup().await;
+    let (_base_dir_b, repo_b) = setup().await;
+
+    let same = random_bytes(3 * BLOCK_SIZE);
+
+    for repo in [&repo_a, &repo_b] {
+        repo.create_directory("dir").await.unwrap();
+        repo.write_file("dir/same.dat", &same).await.unwrap();
+    }
+
+    repo_a.write_file("only_a.txt", b"a").await.unwrap();
+    repo_b.create_directory("only_b").await.unwrap();
+    repo_b.write_file("only_b/file.txt", b"b").await.unwrap();
+
+    repo_a.write_file("type.txt", b"file").await.unwrap();
+    repo_b.create_directory("type.txt").await.unwrap();
+
+    repo_a
+        .write_file("dir/content.txt", b"hello")
+        .await
+        .unwrap();
+    repo_b
+        .write_file("dir/content.txt", b"world")
+        .await
+        .unwrap();
+
+    let mut differences: Vec<_> = repo_a.compare_with(&repo_b).try_collect().await.unwrap();
+    differences.sort_by(|a, b| a.path.cmp(&b.path));
+
+    assert_eq!(
+        differences,
+        [
+            Difference {
+                path: "/dir/content.txt".into(),
+                kind: DifferenceKind::ContentMismatch,
+            },
+            Difference {
+                path: "/only_a.txt".into(),
+                kind: DifferenceKind::OnlyInThis,
+            },
+            Difference {
+                path: "/only_b".into(),
+                kind: DifferenceKind::OnlyInOther,
+            },
+            Difference {
+                path: "/type.txt".into(),
+                kind: DifferenceKind::TypeMismatch,
+            },
+        ]
+    );
+
+    // Identical repositories
+    let differences: Vec<_> = repo_a.compare_with(&repo_a).try_collect().await.unwrap();
+    assert!(differences.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn block_access_log() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.write_file("a.txt", b"a").await.unwrap();
+    assert!(repo.recent_block_accesses(usize::MAX).is_empty());
+
+    repo.set_block_access_log_enabled(true);
+    assert!(repo.is_block_access_log_enabled());
+
+    repo.write_file("b.txt", b"b").await.unwrap();
+    assert_eq!(read_file(&repo, "a.txt").await, b"a");
+
+    let accesses = repo.recent_block_accesses(usize::MAX);
+    assert!(accesses
+        .iter()
+        .any(|access| access.kind == store::BlockAccessKind::Write));
+    assert!(accesses
+        .iter()
+        .any(|access| access.kind == store::BlockAccessKind::Read));
+
+    assert_eq!(repo.recent_block_accesses(1).len(), 1);
+
+    repo.set_block_access_log_enabled(false);
+    assert!(repo.recent_block_accesses(usize::MAX).is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fork_all() {
+    let (_base_dir, repo) = setup().await;
+    repo.set_auto_merge(false).await.unwrap();
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+
+    create_remote_file(&repo, remote_id, "a.txt", b"a").await;
+    create_remote_file(&repo, remote_id, "b.txt", b"b").await;
+    repo.write_file("c.txt", b"c").await.unwrap();
+
+    repo.fork_all(&["a.txt".into(), "b.txt".into(), "c.txt".into()])
+        .await
+        .unwrap();
+
+    let root = local_branch
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+
+    for (name, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+        let mut file = root
+            .lookup(name)
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+        assert_eq!(file.read_to_end().await.unwrap(), content);
+    }
+
+    assert_matches!(
+        repo.fork_all(&["/".into()]).await,
+        Err(Error::EntryIsDirectory)
+    );
+    assert_matches!(
+        repo.fork_all(&["missing.txt".into()]).await,
+        Err(Error::EntryNotFound)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fork_all_linked_files() {
+    let (_base_dir, repo) = setup().await;
+    repo.set_auto_merge(false).await.unwrap();
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+
+    repo.write_file("x.txt", b"x").await.unwrap();
+
+    let file = create_remote_file(&repo, remote_id, "a.txt", b"a").await;
+    let remote_branch = file.branch().clone();
+    let src_blob_id = *file.blob_id();
+    drop(file);
+
+    remote_branch
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .link_file("b.txt".into(), &remote_branch, src_blob_id)
+        .await
+        .unwrap();
+
+    let vv_before = local_branch.version_vector().await.unwrap();
+
+    // Duplicate paths are forked only once.
+    repo.fork_all(&["a.txt".into(), "b.txt".into(), "a.txt".into()])
+        .await
+        .unwrap();
+
+    // All the files forked in a single snapshot.
+    let vv_after = local_branch.version_vector().await.unwrap();
+    assert_eq!(
+        vv_after.get(local_branch.id()),
+        vv_before.get(local_branch.id()) + 1
+    );
+
+    assert_eq!(open_local_file(&local_branch, "a.txt").await, b"a");
+    assert_eq!(open_local_file(&local_branch, "b.txt").await, b"a");
+
+    // The forked files are still independent of each other.
+    repo.write_file("a.txt", b"aa").await.unwrap();
+    assert_eq!(open_local_file(&local_branch, "a.txt").await, b"aa");
+    assert_eq!(open_local_file(&local_branch, "b.txt").await, b"a");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fork_all_concurrent() {
+    let (_base_dir, repo) = setup().await;
+    repo.set_auto_merge(false).await.unwrap();
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+    let mut remote_dir = remote_branch
+        .ensure_directory_exists(Utf8Path::new("dir"))
+        .await
+        .unwrap();
+
+    let mut paths: Vec<Utf8PathBuf> = Vec::new();
+
+    for i in 0..8 {
+        let name = format!("{i}.txt");
+        create_remote_file(&repo, remote_id, &name, name.as_bytes()).await;
+        create_file_in_directory(&mut remote_dir, &name, name.as_bytes()).await;
+
+        paths.push(name.clone().into());
+        paths.push(format!("dir/{name}").into());
+    }
+
+    let mut reversed = paths.clone();
+    reversed.reverse();
+
+    let (a, b) = future::join(repo.fork_all(&paths), repo.fork_all(&reversed)).await;
+    a.unwrap();
+    b.unwrap();
+
+    for i in 0..8 {
+        let name = format!("{i}.txt");
+        assert_eq!(open_local_file(&local_branch, &name).await, name.as_bytes());
+        assert_eq!(
+            open_local_file(&local_branch, &format!("dir/{name}")).await,
+            name.as_bytes()
+        );
+    }
+}
+
+async fn open_local_file(branch: &Branch, path: &str) -> Vec<u8> {
+    let (parent, name) = path::decompose(Utf8Path::new(path)).unwrap();
+
+    let mut dir = branch
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+
+    for component in parent.components() {
+        let next = dir
+            .lookup(component.as_str())
+            .unwrap()
+            .directory()
+            .unwrap()
+            .open(DirectoryFallback::Disabled)
+            .await
+            .unwrap();
+        dir = next;
+    }
+
+    dir.lookup(name)
+        .unwrap()
+        .file()
+        .unwrap()
+        .open()
+        .await
+        .unwrap()
+        .read_to_end()
+        .await
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_stalled() {
+    let (_base_dir, repo) = setup().await;
+    repo.set_auto_merge(false).await.unwrap();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    // Create a remote branch whose root directory is malformed so it never merges.
+    {
+        let mut tx = repo.shared.vault.store().begin_write().await.unwrap();
+        let mut changeset = Changeset::new();
+
+        let mut blob = Blob::create(remote_branch.clone(), BlobId::ROOT);
+        blob.write_all(&mut tx, &mut changeset, &[0xff; 64])
+            .await
+            .unwrap();
+        blob.flush(&mut tx, &mut changeset).await.unwrap();
+
+        changeset
+            .apply(
+                &mut tx,
+                remote_branch.id(),
+                remote_branch.keys().write().unwrap(),
+            )
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    let mut rx = repo.subscribe();
+
+    // The failing branch doesn't fail the merge of the others.
+    for _ in 0..merge_stalls::THRESHOLD {
+        repo.merge().await.unwrap();
+    }
+
+    timeout(Duration::from_secs(5), async {
+        loop {
+            match rx.recv().await.unwrap().payload {
+                Payload::MergeStalled { branch_id, .. } if branch_id == remote_id => break,
+                _ => continue,
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    // Reported only once.
+    repo.merge().await.unwrap();
+
+    repo.skip_merge(remote_id).await.unwrap();
+    repo.merge().await.unwrap();
+
+    while let Ok(event) = rx.try_recv() {
+        assert!(!matches!(event.payload, Payload::MergeStalled { .. }));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn skip_merge() {
+    let (_base_dir, repo) = setup().await;
+    repo.set_auto_merge(false).await.unwrap();
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+
+    assert_matches!(
+        repo.skip_merge(*local_branch.id()).await,
+        Err(Error::InvalidArgument)
+    );
+    assert_matches!(
+        repo.skip_merge(remote_id).await,
+        Err(Error::Store(store::Error::BranchNotFound))
+    );
+
+    create_remote_file(&repo, remote_id, "a.txt", b"a").await;
+    repo.skip_merge(remote_id).await.unwrap();
+
+    let remote_vv = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+
+    // The skipped snapshot is not merged.
+    repo.merge().await.unwrap();
+    assert!(!(local_branch.version_vector().await.unwrap() >= remote_vv));
+
+    // Once the branch advances it's merged again.
+    create_remote_file(&repo, remote_id, "b.txt", b"b").await;
+
+    let remote_vv = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+
+    repo.merge().await.unwrap();
+    assert!(local_branch.version_vector().await.unwrap() >= remote_vv);
+    assert_eq!(read_file(&repo, "a.txt").await, b"a");
+    assert_eq!(read_file(&repo, "b.txt").await, b"b");
+}
+
+#[cfg(feature = "unstable")]
+#[tokio::test(flavor = "multi_thread")]
+async fn read_raw_block() {
+    use futures_util::TryStreamExt;
+
+    let (_base_dir, repo) = setup().await;
+
+    let content = b"attack at dawn".repeat(BLOCK_SIZE / 8);
+    repo.write_file("test.txt", &content).await.unwrap();
+
+    let file = repo.open_file("test.txt").await.unwrap();
+    let block_ids: Vec<_> = file.block_ids().try_collect().await.unwrap();
+    assert!(!block_ids.is_empty());
+
+    let mut nonces = Vec::new();
+
+    for (_, block_id, _) in block_ids {
+        let (raw, nonce) = repo.read_raw_block(&block_id).await.unwrap();
+
+        // Only ciphertext is exposed.
+        assert!(!raw
+            .windows(b"attack".len())
+            .any(|window| window == b"attack"));
+        nonces.push(nonce);
+    }
+
+    // Every block is encrypted with a different nonce.
+    let count = nonces.len();
+    nonces.sort();
+    nonces.dedup();
+    assert_eq!(nonces.len(), count);
+
+    assert_matches!(
+        repo.read_raw_block(&rand::random()).await,
+        Err(Error::Store(store::Error::BlockNotFound))
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_handles() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("dir").await.unwrap();
+    repo.write_file("b.txt", b"bbb").await.unwrap();
+
+    let mut writer = repo.create_file("dir/a.txt").await.unwrap();
+    writer.write_all(b"aaa").await.unwrap();
+
+    let reader0 = repo.open_file("b.txt").await.unwrap();
+    let reader1 = repo.open_file("b.txt").await.unwrap();
+
+    let local_id = *repo.local_branch().unwrap().id();
+    let handles = repo.open_handles().await;
+
+    let handle = handles
+        .iter()
+        .find(|handle| handle.path.as_deref() == Some(Utf8Path::new("/dir/a.txt")))
+        .unwrap();
+    assert_eq!(handle.branch_id, local_id);
+    assert_eq!(handle.mode, OpenHandleMode::Write);
+    assert_eq!(handle.count, 1);
+
+    let handle = handles
+        .iter()
+        .find(|handle| handle.path.as_deref() == Some(Utf8Path::new("/b.txt")))
+        .unwrap();
+    assert_eq!(handle.mode, OpenHandleMode::Read);
+    assert_eq!(handle.count, 2);
+
+    drop(writer);
+    drop(reader0);
+    drop(reader1);
+
+    let handles = repo.open_handles().await;
+    assert!(handles.iter().all(|handle| handle.path.as_deref()
+        != Some(Utf8Path::new("/dir/a.txt"))
+        && handle.path.as_deref() != Some(Utf8Path::new("/b.txt"))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn token_matches() {
+    let (_base_dir, repo) = setup().await;
+    repo.write_file("a.txt", b"aaa").await.unwrap();
+
+    let secrets = repo.secrets();
+    let other_secrets = AccessSecrets::random_write();
+    let forged_secrets = AccessSecrets::Read {
+        id: *secrets.id(),
+        read_key: SecretKey::random(),
+    };
+
+    for mode in [AccessMode::Blind, AccessMode::Read, AccessMode::Write] {
+        let token = ShareToken::from(secrets.with_mode(mode));
+        assert!(repo.token_matches(&token).await.unwrap());
+
+        let token = ShareToken::from(other_secrets.with_mode(mode));
+        assert!(!repo.token_matches(&token).await.unwrap());
+    }
+
+    let token = ShareToken::from(forged_secrets.clone());
+    assert!(!repo.token_matches(&token).await.unwrap());
+
+    // In blind mode the read key is verified against the stored data.
+    repo.set_credentials(Credentials::with_random_writer_id(
+        secrets.with_mode(AccessMode::Blind),
+    ))
+    .await
+    .unwrap();
+
+    let token = ShareToken::from(secrets.with_mode(AccessMode::Read));
+    assert!(repo.token_matches(&token).await.unwrap());
+
+    let token = ShareToken::from(forged_secrets);
+    assert!(!repo.token_matches(&token).await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn force_unlock() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.write_file("a.txt", b"aaa").await.unwrap();
+
+    // Simulate a leaked handle.
+    let mut file0 = repo.open_file("a.txt").await.unwrap();
+    file0.write_all(b"bbb").await.unwrap();
+    std::mem::forget(file0);
+
+    let mut file1 = repo.open_file("a.txt").await.unwrap();
+    assert_matches!(file1.write_all(b"ccc").await, Err(Error::Locked));
+    drop(file1);
+
+    let locks = repo.locks();
+    let lock = locks
+        .iter()
+        .find(|lock| lock.kind == OpenHandleMode::Write)
+        .unwrap();
+    assert_eq!(lock.branch_id, *repo.local_branch().unwrap().id());
+    assert_eq!(lock.count, 1);
+
+    assert_matches!(
+        repo.force_unlock("not a blob id", true),
+        Err(Error::InvalidArgument)
+    );
+    assert_eq!(repo.force_unlock(&lock.blob_id, true).unwrap(), 1);
+    assert!(repo
+        .locks()
+        .iter()
+        .all(|other| other.blob_id != lock.blob_id));
+    assert_eq!(repo.force_unlock(&lock.blob_id, true).unwrap(), 0);
+
+    let mut file2 = repo.open_file("a.txt").await.unwrap();
+    file2.write_all(b"ddd").await.unwrap();
+    file2.flush().await.unwrap();
+    drop(file2);
+
+    assert_eq!(read_file(&repo, "a.txt").await, b"ddd");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_snapshot() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("dir").await.unwrap();
+    repo.write_file("manifest.txt", b"dir/a.txt").await.unwrap();
+    repo.write_file("dir/a.txt", b"version 1").await.unwrap();
+
+    let snapshot = repo.read_snapshot().await.unwrap();
+
+    repo.write_file("manifest.txt", b"dir/a.txt\ndir/b.txt")
+        .await
+        .unwrap();
+    repo.write_file("dir/a.txt", b"version 2").await.unwrap();
+    repo.write_file("dir/b.txt", b"new").await.unwrap();
+
+    // The snapshot still sees the old content...
+    let mut file = snapshot.open_file("manifest.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"dir/a.txt");
+
+    let mut file = snapshot.open_file("dir/a.txt").await.unwrap();
+    assert_eq!(file.len(), 9);
+    let mut buffer = [0; 7];
+    assert_eq!(file.read(&mut buffer).await.unwrap(), 7);
+    assert_eq!(&buffer, b"version");
+    file.seek(SeekFrom::End(-1));
+    assert_eq!(file.read_to_end().await.unwrap(), b"1");
+
+    assert_matches!(
+        snapshot.open_file("dir/b.txt").await,
+        Err(Error::EntryNotFound)
+    );
+
+    let names: Vec<_> = snapshot
+        .cd("dir")
+        .await
+        .unwrap()
+        .entries()
+        .map(|entry| entry.name().to_owned())
+        .collect();
+    assert_eq!(names, ["a.txt"]);
+
+    // Including the entries opened through the directories of the snapshot.
+    let dir = snapshot.cd("/").await.unwrap().cd("dir").await.unwrap();
+    assert_eq!(dir.path(), Utf8Path::new("/dir"));
+    assert_eq!(dir.entries().count(), 1);
+
+    let mut file = dir.open_file("a.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"version 1");
+    assert_matches!(dir.open_file("b.txt").await, Err(Error::EntryNotFound));
+
+    // ...while the repository sees the new one.
+    assert_eq!(read_file(&repo, "dir/a.txt").await, b"version 2");
+    assert_eq!(read_file(&repo, "dir/b.txt").await, b"new");
+
+    // A new snapshot sees the new content too.
+    let snapshot = repo.read_snapshot().await.unwrap();
+    let mut file = snapshot.open_file("dir/a.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"version 2");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clone_to() {
+    let (base_dir, repo) = setup().await;
+
+    let content = random_bytes(3 * BLOCK_SIZE + 7);
+
+    repo.create_directory("dir/sub").await.unwrap();
+    repo.write_atomic("dir/a.dat", &content).await.unwrap();
+    repo.write_atomic("b.txt", b"hello").await.unwrap();
+
+    let params = RepositoryParams::new(base_dir.path().join("clone.db"));
+
+    // Cloning under the same identity would be a share, not a clone.
+    assert_matches!(
+        repo.clone_to(
+            &params,
+            Access::WriteUnlocked {
+                secrets: repo.secrets().into_write_secrets().unwrap(),
+            },
+        )
+        .await,
+        Err(Error::OperationNotSupported)
+    );
+
+    let secrets = WriteSecrets::random();
+    let clone = repo
+        .clone_to(
+            &params,
+            Access::WriteUnlocked {
+                secrets: secrets.clone(),
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(clone.secrets().id(), &secrets.id);
+    assert_ne!(clone.secrets().id(), repo.secrets().id());
+
+    assert_eq!(read_file(&clone, "dir/a.dat").await, content);
+    assert_eq!(read_file(&clone, "b.txt").await, b"hello");
+    assert_eq!(
+        clone
+            .open_directory("dir/sub")
+            .await
+            .unwrap()
+            .entries()
+            .count(),
+        0
+    );
+
+    // The clone is independent of the original.
+    clone.remove_entry("b.txt").await.unwrap();
+    assert_eq!(read_file(&repo, "b.txt").await, b"hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_index() {
+    let (base_dir, repo) = setup().await;
+
+    repo.set_name("My photos".to_owned()).await.unwrap();
+    repo.create_directory("dir").await.unwrap();
+
+    let mut file = repo.create_file("dir/a.txt").await.unwrap();
+    file.write_all(&vec![1; 2 * BLOCK_SIZE]).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut file = repo.create_file("b.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let dst = base_dir.path().join("exported.ouisyncdb");
+    repo.export_index(&dst).await.unwrap();
+
+    // Exporting into an existing database is not allowed.
+    assert_matches!(
+        repo.export_index(&dst).await,
+        Err(Error::Db(db::Error::Exists))
+    );
+
+    // The source repository is not affected.
+    assert_eq!(read_file(&repo, "b.txt").await, b"hello world");
+
+    let exported = Repository::open(&RepositoryParams::new(&dst), None, AccessMode::Write)
+        .await
+        .unwrap();
+
+    // No secrets or private metadata are exported, only the repository id.
+    assert_eq!(exported.access_mode(), AccessMode::Blind);
+    assert_eq!(exported.secrets().id(), repo.secrets().id());
+    assert_eq!(exported.name().await.unwrap(), None);
+
+    exported
+        .set_credentials(Credentials::with_random_writer_id(repo.secrets()))
+        .await
+        .unwrap();
+    assert_eq!(exported.access_mode(), AccessMode::Write);
+
+    // The export gets its own writer id.
+    assert_ne!(
+        exported.local_branch().unwrap().id(),
+        repo.local_branch().unwrap().id()
+    );
+
+    // The directory tree is there...
+    let names: Vec<_> = exported
+        .open_directory("/")
+        .await
+        .unwrap()
+        .entries()
+        .map(|entry| entry.name().to_owned())
+        .collect();
+    assert_eq!(names, ["b.txt", "dir"]);
+
+    let names: Vec<_> = exported
+        .open_directory("dir")
+        .await
+        .unwrap()
+        .entries()
+        .map(|entry| entry.name().to_owned())
+        .collect();
+    assert_eq!(names, ["a.txt"]);
+
+    // ...but the file content is not.
+    assert_matches!(
+        exported.open_file("b.txt").await,
+        Err(Error::Store(store::Error::BlockNotFound))
+    );
+
+    // Only the blocks of the two directories are present, the file blocks are missing
+    assert_eq!(exported.count_blocks().await.unwrap(), 2);
+
+    // (the total might include also blocks from older snapshots that haven't been pruned yet).
+    let progress = exported.sync_progress().await.unwrap();
+    assert_eq!(progress.value, 2);
+    assert!(progress.total >= 6);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dedup_stats_with_compression() {
+    let (_base_dir, repo) = setup().await;
+    repo.set_block_compression_enabled(true).await.unwrap();
+
+    repo.write_file("zeros.dat", &vec![0; 4 * BLOCK_SIZE])
+        .await
+        .unwrap();
+
+    // The sizes are those of the compressed blocks, not `BLOCK_SIZE` per block.
+    let stats = repo.dedup_stats().await.unwrap();
+    let blocks = stats.shared_blocks + stats.unique_blocks;
+    assert!(blocks > 0);
+    assert!(stats.physical_bytes < blocks * BLOCK_SIZE as u64);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_name() {
+    let (base_dir, repo) = setup().await;
+    assert_eq!(repo.name().await.unwrap(), None);
+
+    repo.set_name("My photos".to_owned()).await.unwrap();
+    assert_eq!(repo.name().await.unwrap().as_deref(), Some("My photos"));
+
+    repo.close().await.unwrap();
+
+    let repo = Repository::open(
+        &RepositoryParams::new(base_dir.path().join(DEFAULT_REPO_NAME)),
+        None,
+        AccessMode::Write,
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.name().await.unwrap().as_deref(), Some("My photos"));
+
+    repo.set_name(String::new()).await.unwrap();
+    assert_eq!(repo.name().await.unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn meta() {
+    let before = std::time::SystemTime::now() - Duration::from_secs(1);
+    let (base_dir, repo) = setup().await;
+
+    let meta = repo.meta().await.unwrap();
+    assert_eq!(meta.database_id, repo.database_id().await.unwrap());
+    assert!(meta.created_at.unwrap() >= before);
+    assert_eq!(
+        meta.creator_id.as_ref(),
+        Some(repo.local_branch().unwrap().id())
+    );
+    assert_eq!(meta.data_version, Some(store::DATA_VERSION));
+
+    repo.close().await.unwrap();
+
+    let repo = Repository::open(
+        &RepositoryParams::new(base_dir.path().join(DEFAULT_REPO_NAME)),
+        None,
+        AccessMode::Write,
+    )
+    .await
+    .unwrap();
+
+    let reopened = repo.meta().await.unwrap();
+    assert_eq!(reopened.database_id, meta.database_id);
+    assert_eq!(reopened.created_at, meta.created_at);
+    assert_eq!(reopened.creator_id, meta.creator_id);
+    assert_eq!(reopened.data_version, meta.data_version);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn local_write_exceeding_quota() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("small.txt").await.unwrap();
+    file.write_all(b"hello").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    // Allow a few more blocks, enough for a small file (one block of the file plus the blocks of
+    // the updated root directory) but not for a large one.
+    let limit = StorageSize::from_blocks(repo.count_blocks().await.unwrap() + 4);
+    repo.set_quota(Some(limit)).await.unwrap();
+
+    let mut file = repo.create_file("medium.txt").await.unwrap();
+    file.write_all(b"world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut content = vec![0; 4 * BLOCK_SIZE];
+    rand::thread_rng().fill(&mut content[..]);
+
+    let mut file = repo.create_file("large.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    assert_matches!(
+        file.flush().await,
+        Err(Error::QuotaExceeded { limit: actual_limit, actual }) => {
+            assert_eq!(actual_limit, limit);
+            assert!(actual > limit);
+        }
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn can_store() {
+    let (_base_dir, repo) = setup().await;
+
+    // No quota
+    assert_eq!(repo.available_space().await.unwrap(), None);
+    assert!(repo.can_store(u64::MAX).await.unwrap());
+
+    let limit = StorageSize::from_blocks(repo.count_blocks().await.unwrap() + 4);
+    repo.set_quota(Some(limit)).await.unwrap();
+
+    assert_eq!(
+        repo.available_space().await.unwrap(),
+        Some(StorageSize::from_blocks(4))
+    );
+    assert!(repo.can_store(0).await.unwrap());
+    assert!(repo.can_store(3 * BLOCK_SIZE as u64).await.unwrap());
+    assert!(!repo.can_store(4 * BLOCK_SIZE as u64).await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn status() {
+    let (_base_dir, repo) = setup().await;
+
+    let status = repo.status().await.unwrap();
+    assert_eq!(status.access_mode, AccessMode::Write);
+    assert_eq!(status.branch_count, 0);
+    assert_eq!(status.local_version_vector, VersionVector::new());
+    assert_eq!(status.linked_peer_count, 0);
+    assert!(!status.dht_enabled);
+    assert!(!status.pex_enabled);
+    assert!(!status.merge_pending);
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello").await.unwrap();
+    file.flush().await.unwrap();
+
+    let status = repo.status().await.unwrap();
+    assert_eq!(status.branch_count, 1);
+    assert!(status.local_version_vector > VersionVector::new());
+    assert_eq!(status.sync_progress.value, status.sync_progress.total);
+    assert!(!status.merge_pending);
 }
 
 // FIXME: This sometimes fails because of a bug in sqlx: https://github.com/launchbadge/sqlx/issues/3217
@@ -1,6 +1,6 @@
 //! Repository state and operations that don't require read or write access.
 
-use super::{quota, LocalId, Metadata, RepositoryId, RepositoryMonitor};
+use super::{quota, LocalId, Metadata, RepositoryId, RepositoryMonitor, SyncFilter};
 use crate::{
     block_tracker::{BlockPromise, BlockTracker, OfferState},
     crypto::{sign::PublicKey, CacheHash},
@@ -8,19 +8,21 @@ use crate::{
     debug::DebugPrinter,
     error::Result,
     event::{EventSender, Payload},
+    network::PartialBlocks,
     protocol::{
         Block, BlockId, InnerNodes, LeafNodes, MultiBlockPresence, NodeState, ProofError,
         UntrustedProof,
     },
     storage_size::StorageSize,
     store::{
-        self, InnerNodeReceiveStatus, LeafNodeReceiveStatus, RootNodeReceiveStatus, Store,
-        WriteTransaction,
+        self, ExpirationPolicy, InnerNodeReceiveStatus, LeafNodeReceiveStatus,
+        RootNodeReceiveStatus, Store, WriteTransaction,
     },
 };
 use futures_util::TryStreamExt;
 use sqlx::Row;
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
+use tokio::sync::watch;
 use tracing::Instrument;
 
 #[derive(Clone)]
@@ -29,9 +31,13 @@ pub(crate) struct Vault {
     store: Store,
     pub event_tx: EventSender,
     pub block_tracker: BlockTracker,
+    /// Blocks whose chunked transfer has been interrupted, kept so it can be resumed.
+    pub partial_blocks: PartialBlocks,
     pub block_request_mode: BlockRequestMode,
     pub local_id: LocalId,
     pub monitor: Arc<RepositoryMonitor>,
+    /// Determines which files have their content downloaded (see `SyncFilter`).
+    pub sync_filter: Arc<watch::Sender<SyncFilter>>,
 }
 
 impl Vault {
@@ -49,9 +55,11 @@ impl Vault {
             store,
             event_tx,
             block_tracker: BlockTracker::new(),
+            partial_blocks: PartialBlocks::new(),
             block_request_mode,
             local_id: LocalId::new(),
             monitor: Arc::new(monitor),
+            sync_filter: Arc::new(watch::Sender::new(SyncFilter::default())),
         }
     }
 
@@ -85,7 +93,8 @@ impl Vault {
 
         let mut tx = self.store().begin_write().await?;
         let status = tx.receive_root_node(proof, block_presence).await?;
-        self.finalize_receive(tx, &status.new_approved).await?;
+        self.finalize_receive(tx, &status.new_approved, None)
+            .await?;
 
         Ok(status)
     }
@@ -100,7 +109,8 @@ impl Vault {
     ) -> Result<InnerNodeReceiveStatus> {
         let mut tx = self.store().begin_write().await?;
         let status = tx.receive_inner_nodes(nodes, quota).await?;
-        self.finalize_receive(tx, &status.new_approved).await?;
+        self.finalize_receive(tx, &status.new_approved, quota.zip(status.quota_exceeded))
+            .await?;
 
         Ok(status)
     }
@@ -115,7 +125,8 @@ impl Vault {
     ) -> Result<LeafNodeReceiveStatus> {
         let mut tx = self.store().begin_write().await?;
         let status = tx.receive_leaf_nodes(nodes, quota).await?;
-        self.finalize_receive(tx, &status.new_approved).await?;
+        self.finalize_receive(tx, &status.new_approved, quota.zip(status.quota_exceeded))
+            .await?;
 
         Ok(status)
     }
@@ -210,15 +221,15 @@ impl Vault {
         Ok(quota::get(&mut conn).await?.map(StorageSize::from_bytes))
     }
 
-    pub async fn set_block_expiration(&self, duration: Option<Duration>) -> Result<()> {
+    pub async fn set_block_expiration(&self, policy: Option<ExpirationPolicy>) -> Result<()> {
         Ok(self
             .store
-            .set_block_expiration(duration, self.block_tracker.clone())
+            .set_block_expiration(policy, self.block_tracker.clone())
             .instrument(self.monitor.span().clone())
             .await?)
     }
 
-    pub async fn block_expiration(&self) -> Option<Duration> {
+    pub async fn block_expiration(&self) -> Option<ExpirationPolicy> {
         self.store.block_expiration().await
     }
 
@@ -238,11 +249,12 @@ impl Vault {
     }
 
     // Finalizes receiving nodes from a remote replica, commits the transaction and notifies the
-    // affected branches.
+    // affected branches and about any snapshot rejected due to quota (`(limit, actual)`).
     async fn finalize_receive(
         &self,
         tx: WriteTransaction,
         new_approved: &[PublicKey],
+        quota_exceeded: Option<(StorageSize, StorageSize)>,
     ) -> Result<()> {
         tx.commit_and_then({
             let new_approved = new_approved.to_vec();
@@ -252,6 +264,10 @@ impl Vault {
                 for branch_id in new_approved {
                     event_tx.send(Payload::BranchChanged(branch_id));
                 }
+
+                if let Some((limit, actual)) = quota_exceeded {
+                    event_tx.send(Payload::QuotaExceeded { limit, actual });
+                }
             }
         })
         .await?;
@@ -672,7 +672,7 @@ async fn prune_snapshots_insert_present() {
 
     assert_eq!(count_snapshots(&vault, &remote_id).await, 2);
 
-    prune_snapshots(&vault, &remote_id).await;
+    prune_snapshots(&vault, &remote_id, None).await;
 
     assert_eq!(count_snapshots(&vault, &remote_id).await, 1);
 }
@@ -700,7 +700,7 @@ async fn prune_snapshots_insert_missing() {
 
     assert_eq!(count_snapshots(&index, &remote_id).await, 2);
 
-    prune_snapshots(&index, &remote_id).await;
+    prune_snapshots(&index, &remote_id, None).await;
 
     // snapshot 1 is pruned because even though snapshot 2 has a locator pointing to a missing
     // block, snapshot 1 doesn't have that locator and so can't serve as fallback for snapshot 2.
@@ -730,7 +730,7 @@ async fn prune_snapshots_update_from_present_to_present() {
 
     assert_eq!(count_snapshots(&index, &remote_id).await, 2);
 
-    prune_snapshots(&index, &remote_id).await;
+    prune_snapshots(&index, &remote_id, None).await;
 
     assert_eq!(count_snapshots(&index, &remote_id).await, 1);
 }
@@ -758,7 +758,7 @@ async fn prune_snapshots_update_from_present_to_missing() {
 
     assert_eq!(count_snapshots(&index, &remote_id).await, 2);
 
-    prune_snapshots(&index, &remote_id).await;
+    prune_snapshots(&index, &remote_id, None).await;
 
     // snapshot 1 is not pruned because snapshot 2 has a locator pointing to a missing block while
     // in snapshot 1 the same locator points to a present block and so snapshot 1 can serve as
@@ -766,6 +766,51 @@ async fn prune_snapshots_update_from_present_to_missing() {
     assert_eq!(count_snapshots(&index, &remote_id).await, 2);
 }
 
+#[tokio::test]
+async fn prune_snapshots_beyond_max_fallback_snapshots() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let (_base_dir, index, secrets) = setup_with_rng(&mut rng).await;
+
+    let remote_id = PublicKey::generate(&mut rng);
+
+    // snapshot 1
+    let mut blocks = [rng.gen(), rng.gen()];
+    let snapshot = Snapshot::new(blocks.clone());
+
+    receive_snapshot(&index, remote_id, &snapshot, &secrets.write_keys).await;
+    receive_block(&index, &blocks[0].1).await;
+    receive_block(&index, &blocks[1].1).await;
+
+    // snapshot 2 (update the first block)
+    blocks[0].1 = rng.gen();
+    let snapshot = Snapshot::new(blocks.clone());
+
+    receive_snapshot(&index, remote_id, &snapshot, &secrets.write_keys).await;
+    // don't receive the new block
+
+    // snapshot 3 (update the second block)
+    blocks[1].1 = rng.gen();
+    let snapshot = Snapshot::new(blocks);
+
+    receive_snapshot(&index, remote_id, &snapshot, &secrets.write_keys).await;
+    // don't receive the new block
+
+    assert_eq!(count_snapshots(&index, &remote_id).await, 3);
+
+    // snapshot 2 can serve as fallback for snapshot 3 and snapshot 1 can serve as fallback for
+    // snapshot 2 and so nothing is pruned without a limit.
+    prune_snapshots(&index, &remote_id, None).await;
+    assert_eq!(count_snapshots(&index, &remote_id).await, 3);
+
+    // Only one fallback allowed - the oldest one (snapshot 1) is pruned.
+    prune_snapshots(&index, &remote_id, Some(1)).await;
+    assert_eq!(count_snapshots(&index, &remote_id).await, 2);
+
+    // No fallbacks allowed - only the latest snapshot is kept.
+    prune_snapshots(&index, &remote_id, Some(0)).await;
+    assert_eq!(count_snapshots(&index, &remote_id).await, 1);
+}
+
 #[tokio::test]
 async fn prune_snapshots_update_from_missing_to_missing() {
     let mut rng = StdRng::seed_from_u64(0);
@@ -789,7 +834,7 @@ async fn prune_snapshots_update_from_missing_to_missing() {
 
     assert_eq!(count_snapshots(&index, &remote_id).await, 2);
 
-    prune_snapshots(&index, &remote_id).await;
+    prune_snapshots(&index, &remote_id, None).await;
 
     // snapshot 1 is pruned because even though snapshot 2 has a locator pointing to a missing
     // block, the same locator is also pointing to a missing block in snapshot 1 and so snapshot 1
@@ -820,7 +865,7 @@ async fn prune_snapshots_keep_missing_and_insert_missing() {
 
     assert_eq!(count_snapshots(&index, &remote_id).await, 2);
 
-    prune_snapshots(&index, &remote_id).await;
+    prune_snapshots(&index, &remote_id, None).await;
 
     // snapshot 1 is pruned because even though snapshot 2 has locators pointing to a missing
     // blocks, one of the locators points to the same missing block also in snapshot 1 and the
@@ -1170,7 +1215,11 @@ async fn count_snapshots(vault: &Vault, writer_id: &PublicKey) -> usize {
         .unwrap()
 }
 
-async fn prune_snapshots(vault: &Vault, writer_id: &PublicKey) {
+async fn prune_snapshots(
+    vault: &Vault,
+    writer_id: &PublicKey,
+    max_fallback_snapshots: Option<usize>,
+) {
     let root_node = vault
         .store()
         .acquire_read()
@@ -1181,7 +1230,7 @@ async fn prune_snapshots(vault: &Vault, writer_id: &PublicKey) {
         .unwrap();
     vault
         .store()
-        .remove_outdated_snapshots(&root_node)
+        .remove_outdated_snapshots(&root_node, max_fallback_snapshots)
         .await
         .unwrap();
 }
@@ -0,0 +1,118 @@
+use super::{
+    path_filter::{self, PathFilter},
+    Repository,
+};
+use crate::{
+    directory::EntryType,
+    error::Result,
+    event::{Event, Payload},
+    joint_directory::{JointDirectory, JointEntryRef},
+    version_vector::VersionVector,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::future;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Directory that keeps itself up to date with the changes made to it, locally or by remote
+/// replicas. See `Repository::open_directory_watched`.
+pub struct WatchedDirectory<'a> {
+    repo: &'a Repository,
+    path: Utf8PathBuf,
+    event_rx: broadcast::Receiver<Event>,
+    filter: PathFilter,
+    dir: JointDirectory,
+    fingerprint: Fingerprint,
+}
+
+impl<'a> WatchedDirectory<'a> {
+    pub(super) async fn open(repo: &'a Repository, path: &Utf8Path) -> Result<Self> {
+        // Subscribe before opening the directory so no change can be missed.
+        let event_rx = repo.subscribe();
+        let dir = repo.cd(path).await?;
+        let fingerprint = fingerprint(&dir);
+
+        Ok(Self {
+            repo,
+            path: path.to_owned(),
+            event_rx,
+            filter: PathFilter::new(path.to_owned()),
+            dir,
+            fingerprint,
+        })
+    }
+
+    /// Path of this directory.
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Entries of this directory as of the last refresh (that is, when the directory was opened
+    /// or when [`Self::changed`] last returned).
+    pub fn entries(&self) -> impl Iterator<Item = JointEntryRef> {
+        self.dir.entries()
+    }
+
+    /// The directory as of the last refresh.
+    pub fn directory(&self) -> &JointDirectory {
+        &self.dir
+    }
+
+    /// Waits until the content of this directory changes and refreshes it. Only the new snapshots
+    /// of the branches are considered, so changes to other directories don't affect it but
+    /// changes of its entries (including nested ones, because they change the version vectors of
+    /// the entries) do. The directory is reopened only if the version of it in the changed
+    /// branches changed, and only once for a burst of changes. Returns an error if the directory
+    /// can't be reopened, e.g. because it's been removed.
+    pub async fn changed(&mut self) -> Result<()> {
+        loop {
+            let mut branch_ids = Vec::new();
+
+            let force = match self.event_rx.recv().await {
+                Ok(Event {
+                    payload: Payload::BranchChanged(branch_id),
+                    ..
+                }) => {
+                    branch_ids.push(branch_id);
+                    false
+                }
+                Err(RecvError::Lagged(_)) => true,
+                Ok(_) => continue,
+                // Can't happen while the repository is alive, which is guaranteed by the borrow.
+                Err(RecvError::Closed) => future::pending().await,
+            };
+
+            let force =
+                path_filter::drain_changed_branches(&mut self.event_rx, &mut branch_ids) || force;
+
+            if !force && !self.filter.is_affected(self.repo, &branch_ids).await {
+                continue;
+            }
+
+            let dir = self.repo.cd(&self.path).await?;
+            let fingerprint = fingerprint(&dir);
+
+            if fingerprint == self.fingerprint {
+                continue;
+            }
+
+            self.dir = dir;
+            self.fingerprint = fingerprint;
+
+            return Ok(());
+        }
+    }
+}
+
+type Fingerprint = Vec<(String, EntryType, VersionVector)>;
+
+fn fingerprint(dir: &JointDirectory) -> Fingerprint {
+    dir.entries()
+        .map(|entry| {
+            (
+                entry.unique_name().into_owned(),
+                entry.entry_type(),
+                entry.version_vector().into_owned(),
+            )
+        })
+        .collect()
+}
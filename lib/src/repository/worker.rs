@@ -1,7 +1,11 @@
 use self::utils::{unlock, Command, Counter};
-use super::Shared;
+use super::{
+    auto_merge, gc_batch_size, max_fallback_snapshots, max_versions_per_file, merge_stalls,
+    purged_tombstones, sync_directories_first, tombstone_ttl, Shared, SyncFilter,
+};
 use crate::{
     blob::{BlobId, BlockIds},
+    block_tracker::BlockPriority,
     branch::Branch,
     directory::{DirectoryFallback, DirectoryLocking},
     error::{Error, Result},
@@ -10,6 +14,7 @@ use crate::{
     store, versioned,
 };
 use async_recursion::async_recursion;
+use camino::Utf8PathBuf;
 use futures_util::{stream, StreamExt};
 use std::{future, sync::Arc};
 use tokio::select;
@@ -22,6 +27,7 @@ use tokio::select;
 pub(super) async fn run(shared: Arc<Shared>) {
     let event_scope = EventScope::new();
     let prune_counter = Counter::new();
+    let tombstone_ages = prune::TombstoneAges::default();
 
     let local_branch = shared
         .local_branch()
@@ -52,7 +58,10 @@ pub(super) async fn run(shared: Arc<Shared>) {
                     })
                     | Err(Lagged) => Some(Command::Wait),
                     Ok(Event {
-                        payload: Payload::MaintenanceCompleted,
+                        payload:
+                            Payload::MaintenanceCompleted
+                            | Payload::QuotaExceeded { .. }
+                            | Payload::MergeStalled { .. },
                         ..
                     }) => None,
                 })
@@ -70,7 +79,15 @@ pub(super) async fn run(shared: Arc<Shared>) {
         let commands = stream::select(events, unlocks);
 
         utils::run(
-            || maintain(&shared, local_branch.as_ref(), &unlock_tx, &prune_counter),
+            || {
+                maintain(
+                    &shared,
+                    local_branch.as_ref(),
+                    &unlock_tx,
+                    &prune_counter,
+                    &tombstone_ages,
+                )
+            },
             commands,
         )
         .await;
@@ -85,7 +102,9 @@ pub(super) async fn run(shared: Arc<Shared>) {
         //   change the set of missing and required blocks.
         // - On any other event (including `Lagged`), let the current job run to completion and
         //   then restart it.
-        let commands =
+        // - On sync filter change restart the current job so the newly matching files are
+        //   required as soon as possible.
+        let events =
             event::into_stream(shared.vault.event_tx.subscribe()).filter_map(move |event| {
                 future::ready(match event {
                     Ok(Event {
@@ -98,12 +117,23 @@ pub(super) async fn run(shared: Arc<Shared>) {
                     })
                     | Err(Lagged) => Some(Command::Wait),
                     Ok(Event {
-                        payload: Payload::MaintenanceCompleted,
+                        payload:
+                            Payload::MaintenanceCompleted
+                            | Payload::QuotaExceeded { .. }
+                            | Payload::MergeStalled { .. },
                         ..
                     }) => None,
                 })
             });
 
+        let sync_filter_changes =
+            stream::unfold(shared.vault.sync_filter.subscribe(), |mut rx| async move {
+                rx.changed().await.ok()?;
+                Some((Command::Interrupt, rx))
+            });
+
+        let commands = stream::select(events, sync_filter_changes);
+
         utils::run(|| scan(&shared, &prune_counter), commands).await;
     };
 
@@ -119,6 +149,7 @@ async fn maintain(
     local_branch: Option<&Branch>,
     unlock_tx: &unlock::Sender,
     prune_counter: &Counter,
+    tombstone_ages: &prune::TombstoneAges,
 ) {
     let mut success = true;
 
@@ -128,17 +159,23 @@ async fn maintain(
             .vault
             .monitor
             .merge_job
-            .run(merge::run(shared, local_branch))
+            .run(merge::run_auto(shared, local_branch))
             .await;
         success = success && job_success;
     }
 
-    // Prune outdated branches and snapshots
+    // Prune outdated branches, snapshots and tombstones
     let job_success = shared
         .vault
         .monitor
         .prune_job
-        .run(prune::run(shared, unlock_tx, prune_counter))
+        .run(prune::run(
+            shared,
+            local_branch,
+            unlock_tx,
+            prune_counter,
+            tombstone_ages,
+        ))
         .await;
     success = success && job_success;
 
@@ -193,6 +230,16 @@ mod scan {
     }
 
     async fn run_once(shared: &Shared) -> Result<()> {
+        let sync_filter = shared.vault.sync_filter.borrow().clone();
+        let directory_priority = if sync_directories_first::get(
+            shared.vault.store().acquire_read().await?.db(),
+        )
+        .await?
+        {
+            BlockPriority::High
+        } else {
+            BlockPriority::Normal
+        };
         let branches = shared.load_branches().await?;
         let mut versions = Vec::with_capacity(branches.len());
 
@@ -211,40 +258,66 @@ mod scan {
                     versions.push(dir);
                 }
                 Err(Error::Store(store::Error::BlockNotFound)) => {
-                    require_missing_blocks(shared, &branch, BlobId::ROOT).await?;
+                    require_missing_blocks(shared, &branch, BlobId::ROOT, directory_priority)
+                        .await?;
                 }
                 Err(error) => return Err(error),
             }
         }
 
-        traverse(shared, JointDirectory::new(None, versions)).await
+        traverse(
+            shared,
+            &sync_filter,
+            directory_priority,
+            JointDirectory::new(None, versions),
+            Utf8PathBuf::new(),
+        )
+        .await
     }
 
     #[async_recursion]
-    async fn traverse(shared: &Shared, dir: JointDirectory) -> Result<()> {
+    async fn traverse(
+        shared: &Shared,
+        sync_filter: &SyncFilter,
+        directory_priority: BlockPriority,
+        dir: JointDirectory,
+        path: Utf8PathBuf,
+    ) -> Result<()> {
         let mut subdirs = Vec::new();
 
         for entry in dir.entries() {
             match entry {
                 JointEntryRef::File(entry) => {
+                    // Directories are always synced (so their listings are complete) but only
+                    // the files matching the filter have their content synced.
+                    if !sync_filter.is_empty() && !sync_filter.matches(&path.join(entry.name())) {
+                        continue;
+                    }
+
                     require_missing_blocks(
                         shared,
                         entry.inner().branch(),
                         *entry.inner().blob_id(),
+                        BlockPriority::Normal,
                     )
                     .await?;
                 }
                 JointEntryRef::Directory(entry) => {
                     for version in entry.versions() {
-                        require_missing_blocks(shared, version.branch(), *version.blob_id())
-                            .await?;
+                        require_missing_blocks(
+                            shared,
+                            version.branch(),
+                            *version.blob_id(),
+                            directory_priority,
+                        )
+                        .await?;
                     }
 
                     match entry
                         .open_with(MissingVersionStrategy::Fail, DirectoryFallback::Disabled)
                         .await
                     {
-                        Ok(dir) => subdirs.push(dir),
+                        Ok(dir) => subdirs.push((dir, path.join(entry.name()))),
                         Err(error) => {
                             // Continue processing the remaining entries
                             tracing::trace!(
@@ -258,8 +331,8 @@ mod scan {
             }
         }
 
-        for dir in subdirs {
-            traverse(shared, dir).await?;
+        for (dir, path) in subdirs {
+            traverse(shared, sync_filter, directory_priority, dir, path).await?;
         }
 
         Ok(())
@@ -270,6 +343,7 @@ mod scan {
         shared: &Shared,
         branch: &Branch,
         blob_id: BlobId,
+        priority: BlockPriority,
     ) -> Result<()> {
         let mut blob_block_ids =
             BlockIds::open(branch.clone(), blob_id)
@@ -280,7 +354,11 @@ mod scan {
                 })?;
         let mut block_number = 0;
         let mut file_progress_cache_reset = false;
-        let mut require_batch = shared.vault.block_tracker.require_batch();
+        let mut require_batch = shared
+            .vault
+            .block_tracker
+            .require_batch()
+            .with_priority(priority);
 
         while let Some(block_id) = blob_block_ids.try_next().await.map_err(|error| {
             tracing::trace!(block_number, ?error, "try_next failed");
@@ -310,32 +388,130 @@ mod scan {
 }
 
 /// Merge remote branches into the local one.
-mod merge {
+pub(super) mod merge {
     use super::*;
-    use crate::store;
+    use crate::{directory::Directory, protocol::RootNode, store};
+    use futures_util::TryStreamExt;
 
-    pub(super) async fn run(shared: &Shared, local_branch: &Branch) -> Result<()> {
-        let branches: Vec<_> = shared.load_branches().await?;
-        let mut roots = Vec::with_capacity(branches.len());
+    /// Merges the branches unless automatic merging is disabled.
+    pub(super) async fn run_auto(shared: &Shared, local_branch: &Branch) -> Result<()> {
+        if !auto_merge::get(shared.vault.store().acquire_read().await?.db()).await? {
+            return Ok(());
+        }
+
+        run(shared, local_branch).await
+    }
+
+    pub(in crate::repository) async fn run(shared: &Shared, local_branch: &Branch) -> Result<()> {
+        let _guard = shared.merge_lock.lock().await;
+
+        let root_nodes: Vec<_> = shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .load_root_nodes()
+            .try_collect()
+            .await?;
+
+        let mut remotes = Vec::with_capacity(root_nodes.len());
+        let mut roots = Vec::with_capacity(root_nodes.len());
+
+        for root_node in root_nodes {
+            let branch_id = root_node.proof.writer_id;
 
-        for branch in &branches {
             // Use the `local_branch` instance to use the correct event scope.
-            let branch = if branch.id() == local_branch.id() {
-                local_branch
+            let branch = if branch_id == *local_branch.id() {
+                local_branch.clone()
+            } else if shared
+                .merge_stalls
+                .lock()
+                .unwrap()
+                .is_skipped(&branch_id, &root_node.proof.hash)
+            {
+                continue;
             } else {
-                branch
+                shared.get_branch(branch_id)?
             };
 
-            match branch
-                .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
-                .await
-            {
-                Ok(dir) => roots.push(dir),
-                Err(Error::Store(store::Error::BlockNotFound)) => continue,
-                Err(error) => return Err(error),
+            let dir = if branch_id == *local_branch.id() {
+                open_root(&branch).await?
+            } else {
+                match open_root(&branch).await {
+                    Ok(dir) => dir,
+                    // A remote branch that can't even be opened doesn't hold back the others.
+                    Err(error) => {
+                        record_failure(shared, &root_node, &error);
+                        continue;
+                    }
+                }
+            };
+
+            let Some(dir) = dir else {
+                continue;
+            };
+
+            if branch_id != *local_branch.id() {
+                remotes.push((root_node, dir.clone()));
+            }
+
+            roots.push(dir);
+        }
+
+        match merge(local_branch, roots).await {
+            Ok(()) => {
+                let mut stalls = shared.merge_stalls.lock().unwrap();
+
+                for (root_node, _) in &remotes {
+                    stalls.record_success(&root_node.proof.writer_id);
+                }
+
+                Ok(())
             }
+            // Not all blocks received yet. This is expected to resolve on its own so there is no
+            // point in finding out which branch is responsible.
+            Err(error @ Error::Store(store::Error::BlockNotFound)) => Err(error),
+            Err(error) => {
+                // Find out which of the remote branches can't be merged by merging them one by
+                // one. This also merges the ones that can, so they are not held back by the
+                // failing ones.
+                for (root_node, remote) in remotes {
+                    let local = open_root(local_branch).await?;
+
+                    match merge(local_branch, local.into_iter().chain([remote])).await {
+                        Ok(()) => shared
+                            .merge_stalls
+                            .lock()
+                            .unwrap()
+                            .record_success(&root_node.proof.writer_id),
+                        // Not all blocks received yet. This is expected to resolve on its own.
+                        Err(Error::Store(store::Error::BlockNotFound)) => (),
+                        Err(error) => record_failure(shared, &root_node, &error),
+                    }
+                }
+
+                Err(error)
+            }
+        }
+    }
+
+    async fn open_root(branch: &Branch) -> Result<Option<Directory>> {
+        match branch
+            .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
+            .await
+        {
+            Ok(dir) => Ok(Some(dir)),
+            Err(Error::Store(store::Error::BlockNotFound | store::Error::BranchNotFound)) => {
+                Ok(None)
+            }
+            Err(error) => Err(error),
         }
+    }
 
+    async fn merge(
+        local_branch: &Branch,
+        roots: impl IntoIterator<Item = Directory>,
+    ) -> Result<()> {
         match JointDirectory::new(Some(local_branch.clone()), roots)
             .merge()
             .await
@@ -344,19 +520,62 @@ mod merge {
             Err(error) => Err(error),
         }
     }
+
+    fn record_failure(shared: &Shared, root_node: &RootNode, error: &Error) {
+        let branch_id = root_node.proof.writer_id;
+        let hash = root_node.proof.hash;
+
+        tracing::debug!(?branch_id, ?hash, ?error, "Failed to merge branch");
+
+        if !shared
+            .merge_stalls
+            .lock()
+            .unwrap()
+            .record_failure(branch_id, hash)
+        {
+            return;
+        }
+
+        tracing::error!(
+            ?branch_id,
+            vv = ?root_node.proof.version_vector,
+            ?hash,
+            ?error,
+            attempts = merge_stalls::THRESHOLD,
+            "Merge stalled: the branch repeatedly fails to merge into the local branch. Use \
+             `Repository::skip_merge` to skip the offending snapshot",
+        );
+
+        shared
+            .vault
+            .event_tx
+            .send(Payload::MergeStalled { branch_id, hash });
+    }
 }
 
-/// Remove outdated branches and snapshots.
+/// Remove outdated branches, snapshots and tombstones.
 mod prune {
     use crate::versioned::PreferBranch;
 
     use super::*;
+    use crate::{
+        collections::HashMap,
+        directory::{Directory, EntryRef},
+        protocol::RootNode,
+        version_vector::VersionVector,
+    };
+    use camino::Utf8Path;
+    use deadlock::BlockingMutex;
     use futures_util::TryStreamExt;
+    use std::mem;
+    use tokio::time::{Duration, Instant};
 
     pub(super) async fn run(
         shared: &Shared,
+        local_branch: Option<&Branch>,
         unlock_tx: &unlock::Sender,
         prune_counter: &Counter,
+        tombstone_ages: &TombstoneAges,
     ) -> Result<()> {
         let all: Vec<_> = shared
             .vault
@@ -368,6 +587,7 @@ mod prune {
             .await?;
 
         let writer_id = shared.credentials.read().unwrap().writer_id;
+        let followed_id = shared.followed_branch();
 
         let (uptodate, outdated): (Vec<_>, Vec<_>) =
             versioned::partition(all, PreferBranch(Some(&writer_id)));
@@ -379,6 +599,11 @@ mod prune {
                 continue;
             }
 
+            // Never remove the branch followed in the observer mode, it would become invisible.
+            if Some(node.proof.writer_id) == followed_id {
+                continue;
+            }
+
             // Try to acquire a unique lock on the root directory of the branch. If any file or
             // directory from the branch is locked, the root will be locked as well and so this
             // acquire will fail, preventing us from pruning a branch that's still being used.
@@ -398,7 +623,7 @@ mod prune {
 
             prune_counter.increment();
 
-            let mut tx = shared.vault.store().begin_write().await?;
+            let mut tx = shared.vault.store().begin_write_low_priority().await?;
             tx.remove_branch(&node).await?;
             tx.commit().await?;
 
@@ -411,16 +636,370 @@ mod prune {
         }
 
         // Remove outdated snapshots.
+        let max_fallbacks =
+            max_fallback_snapshots::get(shared.vault.store().acquire_read().await?.db()).await?;
+        let max_versions =
+            max_versions_per_file::get(shared.vault.store().acquire_read().await?.db()).await?;
+
         for node in uptodate {
             shared
                 .vault
                 .store()
-                .remove_outdated_snapshots(&node)
+                .remove_outdated_snapshots(&node, max_fallbacks)
                 .await?;
+
+            if let Some(max_versions) = max_versions {
+                limit_file_versions(shared, &node, max_versions).await?;
+            }
+        }
+
+        // Remove tombstones that are no longer needed.
+        let ttl = tombstone_ttl::get(shared.vault.store().acquire_read().await?.db()).await?;
+
+        if let (Some(ttl), Some(local_branch)) = (ttl, local_branch) {
+            purge_tombstones(shared, local_branch, ttl, tombstone_ages).await?;
         }
 
         Ok(())
     }
+
+    /// Removes the older snapshots of the branch whose latest snapshot is `root_node` that would
+    /// bring the number of distinct versions of some file over `max` (see
+    /// `Repository::set_max_versions_per_file`). The latest snapshot and the most recent older one
+    /// are always kept. Drafts (snapshots with the same version vector as the next one) are kept
+    /// too, as in `Store::remove_outdated_snapshots`.
+    async fn limit_file_versions(shared: &Shared, root_node: &RootNode, max: usize) -> Result<()> {
+        let branch = match shared.get_branch(root_node.proof.writer_id) {
+            Ok(branch) => branch,
+            // Blind replica can't read the directories.
+            Err(Error::PermissionDenied) => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        let mut versions = FileVersions::new(max);
+        collect_file_versions(shared, &branch, root_node, &mut versions).await?;
+
+        let mut new = root_node.clone();
+        let mut kept = 0;
+        let mut remove = Vec::new();
+
+        while let Some(old) = load_prev_root_node(shared, &new).await? {
+            if old.proof.version_vector != new.proof.version_vector {
+                // Once a snapshot is removed, all the older ones are removed too so there is no
+                // need to walk them.
+                if !remove.is_empty() {
+                    remove.push(old.clone());
+                } else if collect_file_versions(shared, &branch, &old, &mut versions).await?
+                    || kept == 0
+                {
+                    kept += 1;
+                } else {
+                    remove.push(old.clone());
+                }
+            }
+
+            new = old;
+        }
+
+        for node in remove {
+            tracing::trace!(
+                branch_id = ?node.proof.writer_id,
+                vv = ?node.proof.version_vector,
+                "too many file versions"
+            );
+
+            shared.vault.store().remove_snapshot(&node).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn load_prev_root_node(shared: &Shared, node: &RootNode) -> Result<Option<RootNode>> {
+        Ok(shared
+            .vault
+            .store()
+            .acquire_read()
+            .await?
+            .load_prev_root_node(node)
+            .await?)
+    }
+
+    // Adds the versions of the files in the given snapshot to `versions`. Returns whether all the
+    // files are still within the limit. Each directory is loaded in its own read transaction so the
+    // walk doesn't hold one open for the whole tree, and the directories whose version has already
+    // been walked (in a more recent snapshot) are skipped because their files are already counted.
+    // The directories not yet downloaded are skipped too, as are those gone in the meantime.
+    async fn collect_file_versions(
+        shared: &Shared,
+        branch: &Branch,
+        root_node: &RootNode,
+        versions: &mut FileVersions,
+    ) -> Result<bool> {
+        let root = {
+            let mut tx = shared.vault.store().begin_read().await?;
+
+            match Directory::open_root_at(&mut tx, root_node, branch.clone()).await {
+                Ok(root) => root,
+                Err(Error::Store(store::Error::BlockNotFound | store::Error::LocatorNotFound)) => {
+                    return Ok(true)
+                }
+                Err(error) => return Err(error),
+            }
+        };
+
+        let mut within_limit = true;
+        let mut dirs = vec![(Utf8PathBuf::new(), root)];
+
+        while let Some((path, dir)) = dirs.pop() {
+            for entry in dir.entries() {
+                let path = path.join(entry.name());
+
+                match entry {
+                    EntryRef::File(entry) => {
+                        within_limit &= versions.insert_file(path, entry.version_vector());
+                    }
+                    EntryRef::Directory(entry) => {
+                        if !versions.insert_dir(&path, entry.version_vector()) {
+                            continue;
+                        }
+
+                        let mut tx = shared.vault.store().begin_read().await?;
+
+                        match entry.open_at(&mut tx, root_node).await {
+                            Ok(dir) => dirs.push((path, dir)),
+                            Err(Error::Store(
+                                store::Error::BlockNotFound | store::Error::LocatorNotFound,
+                            )) => (),
+                            Err(error) => return Err(error),
+                        }
+                    }
+                    EntryRef::Tombstone(_) => (),
+                }
+            }
+        }
+
+        Ok(within_limit)
+    }
+
+    /// Distinct versions of the files and directories seen while walking the snapshots of a
+    /// branch, keyed by their paths.
+    pub(super) struct FileVersions {
+        max: usize,
+        files: HashMap<Utf8PathBuf, Vec<VersionVector>>,
+        dirs: HashMap<Utf8PathBuf, Vec<VersionVector>>,
+    }
+
+    impl FileVersions {
+        pub fn new(max: usize) -> Self {
+            Self {
+                max,
+                files: HashMap::default(),
+                dirs: HashMap::default(),
+            }
+        }
+
+        /// Records a version of the file at `path`. Returns whether the file still has at most
+        /// `max` versions.
+        pub fn insert_file(&mut self, path: Utf8PathBuf, version_vector: &VersionVector) -> bool {
+            let versions = self.files.entry(path).or_default();
+
+            if !versions.contains(version_vector) {
+                versions.push(version_vector.clone());
+            }
+
+            versions.len() <= self.max
+        }
+
+        /// Records a version of the directory at `path`. Returns `false` if it's already been
+        /// recorded, in which case its content doesn't need to be walked again.
+        pub fn insert_dir(&mut self, path: &Utf8Path, version_vector: &VersionVector) -> bool {
+            let versions = self.dirs.entry(path.to_owned()).or_default();
+
+            if versions.contains(version_vector) {
+                false
+            } else {
+                versions.push(version_vector.clone());
+                true
+            }
+        }
+    }
+
+    /// Times when the tombstones in the local branch were first seen, keyed by their paths. Kept
+    /// only in memory, so the ages restart when the repository is reopened. This only delays the
+    /// purging, never speeds it up.
+    #[derive(Default)]
+    pub(super) struct TombstoneAges(BlockingMutex<HashMap<Utf8PathBuf, (VersionVector, Instant)>>);
+
+    /// Removes the tombstones in the local branch which are no longer needed to prevent the removed
+    /// entries from being resurrected. A tombstone is removed when no known remote branch still
+    /// has a version of the entry the tombstone supersedes and either all of the known remote
+    /// branches have the tombstone as well (they acknowledged the removal) or the tombstone is
+    /// older than `ttl`.
+    async fn purge_tombstones(
+        shared: &Shared,
+        local_branch: &Branch,
+        ttl: Duration,
+        ages: &TombstoneAges,
+    ) -> Result<()> {
+        let branches = shared.load_branches().await?;
+        let remote_count = branches
+            .iter()
+            .filter(|branch| branch.id() != local_branch.id())
+            .count();
+        let mut versions = Vec::with_capacity(branches.len());
+
+        for branch in &branches {
+            // Use the `local_branch` instance to use the correct event scope.
+            let branch = if branch.id() == local_branch.id() {
+                local_branch
+            } else {
+                branch
+            };
+
+            match branch
+                .open_root(DirectoryLocking::Disabled, DirectoryFallback::Disabled)
+                .await
+            {
+                Ok(dir) => versions.push(dir),
+                Err(Error::Store(store::Error::BlockNotFound)) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        let old_ages = mem::take(&mut *ages.0.lock().unwrap());
+        let mut new_ages = HashMap::default();
+        let now = Instant::now();
+
+        let mut queue = vec![(
+            JointDirectory::new(Some(local_branch.clone()), versions),
+            Utf8PathBuf::new(),
+        )];
+
+        while let Some((mut dir, path)) = queue.pop() {
+            let mut purge = Vec::new();
+
+            if let Some(local_version) = dir.local_version() {
+                for entry in local_version.entries() {
+                    let EntryRef::Tombstone(tombstone) = entry else {
+                        continue;
+                    };
+
+                    let name = tombstone.name();
+                    let vv = tombstone.version_vector();
+                    let entry_path = path.join(name);
+
+                    let first_seen = old_ages
+                        .get(&entry_path)
+                        .filter(|(old_vv, _)| old_vv == vv)
+                        .map(|(_, first_seen)| *first_seen)
+                        .unwrap_or(now);
+
+                    let mut needed = false;
+                    let mut acknowledged = 0;
+
+                    for remote_version in dir.versions() {
+                        if remote_version.branch().id() == local_branch.id() {
+                            continue;
+                        }
+
+                        match remote_version.lookup(name) {
+                            Ok(EntryRef::Tombstone(remote)) if remote.version_vector() >= vv => {
+                                acknowledged += 1;
+                            }
+                            Ok(EntryRef::File(_) | EntryRef::Directory(_)) => {
+                                needed = true;
+                            }
+                            Ok(EntryRef::Tombstone(_)) | Err(_) => (),
+                        }
+                    }
+
+                    let acknowledged = remote_count > 0 && acknowledged == remote_count;
+
+                    if !needed && (acknowledged || now.duration_since(first_seen) >= ttl) {
+                        purge.push((name.to_owned(), vv.clone()));
+                    } else {
+                        new_ages.insert(entry_path, (vv.clone(), first_seen));
+                    }
+                }
+            }
+
+            for entry in dir.entries() {
+                let JointEntryRef::Directory(entry) = entry else {
+                    continue;
+                };
+
+                match entry
+                    .open_with(MissingVersionStrategy::Skip, DirectoryFallback::Disabled)
+                    .await
+                {
+                    Ok(subdir) => queue.push((subdir, path.join(entry.name()))),
+                    Err(error) => {
+                        tracing::trace!(entry = entry.name(), ?error, "Failed to open directory");
+                    }
+                }
+            }
+
+            if !purge.is_empty() {
+                // unwrap is ok because `purge` is non-empty only if the local version exists.
+                let local_version = dir.local_version_mut().unwrap();
+
+                // Remember the tombstones before purging them so they are not merged back from
+                // the remote branches that still have them, even if we crash in between.
+                let purged = local_branch.purged_tombstones();
+
+                for (name, vv) in &purge {
+                    purged.insert(*local_version.blob_id(), name.clone(), vv);
+                }
+
+                let mut tx = shared.vault.store().db().begin_write().await?;
+                purged_tombstones::set(&mut tx, &purged.to_vec()).await?;
+                tx.commit().await?;
+
+                let count = local_version.purge_tombstones(&purge).await?;
+
+                tracing::trace!(%path, count, "tombstones purged");
+            }
+        }
+
+        *ages.0.lock().unwrap() = new_ages;
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::crypto::sign::PublicKey;
+
+        #[test]
+        fn file_versions() {
+            let a = PublicKey::random();
+            let b = PublicKey::random();
+
+            let v1 = VersionVector::first(a);
+            let v2 = v1.clone().incremented(a);
+            let v3 = v2.clone().incremented(b);
+
+            let mut versions = FileVersions::new(2);
+
+            // The latest snapshot.
+            assert!(versions.insert_dir(Utf8Path::new("dir"), &v1));
+            assert!(versions.insert_file("dir/a.txt".into(), &v2));
+            assert!(versions.insert_file("dir/b.txt".into(), &v1));
+
+            // An older snapshot where only `a.txt` differs.
+            assert!(versions.insert_dir(Utf8Path::new("dir"), &v2));
+            assert!(versions.insert_file("dir/a.txt".into(), &v1));
+            assert!(versions.insert_file("dir/b.txt".into(), &v1));
+
+            // The same version of a directory doesn't need to be walked again.
+            assert!(!versions.insert_dir(Utf8Path::new("dir"), &v1));
+
+            // A third version of `a.txt` is over the limit, while a different file isn't affected.
+            assert!(!versions.insert_file("dir/a.txt".into(), &v3));
+            assert!(versions.insert_file("dir/c.txt".into(), &v3));
+        }
+    }
 }
 
 /// Remove unreachable blocks
@@ -435,7 +1014,9 @@ mod trash {
     use std::{
         collections::{BTreeSet, VecDeque},
         iter,
+        time::Instant,
     };
+    use tokio::task;
 
     pub(super) async fn run(
         shared: &Shared,
@@ -627,28 +1208,34 @@ mod trash {
         // We need to delete the blocks and also mark them as missing (so they can be requested in
         // case they become needed again) in their corresponding leaf nodes and then update the
         // summaries of the corresponding ancestor nodes. This is a complex and potentially
-        // expensive operation which is why we do it a few blocks at a time.
-        const BATCH_SIZE: usize = 32;
+        // expensive operation which is why we do it a few blocks at a time, yielding in between
+        // so other transactions can interleave.
+        let batch_size = gc_batch_size::get(shared.vault.store().acquire_read().await?.db())
+            .await?
+            .max(1);
+
+        let monitor = &shared.vault.monitor;
+        let start = Instant::now();
 
         let mut unreachable_block_ids = unreachable_block_ids.into_iter();
-        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut batch = Vec::with_capacity(batch_size);
         let mut total_count = 0;
 
+        *monitor.trash_progress.get() = 0;
+
         let local_branch_and_write_keys = local_branch
             .as_ref()
             .and_then(|branch| branch.keys().write().map(|keys| (branch, keys)));
 
         loop {
             batch.clear();
-            batch.extend(unreachable_block_ids.by_ref().take(BATCH_SIZE));
+            batch.extend(unreachable_block_ids.by_ref().take(batch_size));
 
             if batch.is_empty() {
                 break;
             }
 
-            let mut tx = shared.vault.store().begin_write().await?;
-
-            total_count += batch.len();
+            let mut tx = shared.vault.store().begin_write_low_priority().await?;
 
             if let Some((local_branch, write_keys)) = &local_branch_and_write_keys {
                 let mut changeset = Changeset::new();
@@ -672,10 +1259,21 @@ mod trash {
                 // care about cancellation.
                 tx.commit().await?;
             }
+
+            total_count += batch.len();
+
+            monitor.trash_blocks_removed.increment(batch.len() as u64);
+            *monitor.trash_progress.get() = total_count as u64;
+
+            task::yield_now().await;
         }
 
         if total_count > 0 {
-            tracing::debug!("unreachable blocks removed: {}", total_count);
+            let elapsed = start.elapsed();
+
+            monitor.trash_removal_time.record(elapsed);
+
+            tracing::debug!(count = total_count, ?elapsed, "unreachable blocks removed");
         }
 
         Ok(())
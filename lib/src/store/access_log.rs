@@ -0,0 +1,119 @@
+//! Opt-in log of the recent block accesses, for diagnosing performance problems. When disabled
+//! (the default), the only overhead is checking an atomic flag on each access.
+
+use crate::protocol::BlockId;
+use deadlock::BlockingMutex;
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
+
+/// Number of accesses retained in the log. Older ones are evicted.
+pub(super) const CAPACITY: usize = 4096;
+
+/// Kind of access to a block.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BlockAccessKind {
+    /// The block was read from the store.
+    Read,
+    /// The block was supposed to be read but it's not in the store (not downloaded yet or
+    /// expired).
+    ReadMissing,
+    /// The block was written locally.
+    Write,
+    /// The block was received from a peer.
+    Receive,
+}
+
+/// Record of a single access to a block.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockAccess {
+    pub block_id: BlockId,
+    pub kind: BlockAccessKind,
+    pub timestamp: SystemTime,
+}
+
+#[derive(Default)]
+pub(super) struct AccessLog {
+    enabled: AtomicBool,
+    entries: BlockingMutex<VecDeque<BlockAccess>>,
+}
+
+impl AccessLog {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+
+        if !enabled {
+            self.entries.lock().unwrap().clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn record(&self, block_id: &BlockId, kind: BlockAccessKind) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= CAPACITY {
+            entries.pop_front();
+        }
+
+        entries.push_back(BlockAccess {
+            block_id: *block_id,
+            kind,
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    /// Returns up to `n` most recent accesses, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<BlockAccess> {
+        let entries = self.entries.lock().unwrap();
+        let skip = entries.len().saturating_sub(n);
+        entries.iter().skip(skip).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn record() {
+        let mut rng = rand::thread_rng();
+        let log = AccessLog::default();
+        let id: BlockId = rng.gen();
+
+        // Disabled by default.
+        log.record(&id, BlockAccessKind::Read);
+        assert!(log.recent(10).is_empty());
+
+        log.set_enabled(true);
+
+        let ids: Vec<BlockId> = (0..CAPACITY + 2).map(|_| rng.gen()).collect();
+
+        for id in &ids {
+            log.record(id, BlockAccessKind::Write);
+        }
+
+        // Only the most recent ones are retained, oldest first.
+        let recent = log.recent(3);
+        assert_eq!(
+            recent
+                .iter()
+                .map(|access| access.block_id)
+                .collect::<Vec<_>>(),
+            ids[ids.len() - 3..]
+        );
+        assert_eq!(log.recent(usize::MAX).len(), CAPACITY);
+
+        log.set_enabled(false);
+        assert!(log.recent(10).is_empty());
+    }
+}
@@ -29,6 +29,9 @@ pub(super) async fn receive(
 
 /// Reads a block from the store into a buffer.
 ///
+/// Compressed blocks are shorter than [`BLOCK_SIZE`]. When such block is read, `content` is
+/// replaced with a buffer of the stored length.
+///
 /// # Panics
 ///
 /// Panics if `buffer` length is less than [`BLOCK_SIZE`].
@@ -55,7 +58,7 @@ pub(super) async fn read(
     let nonce = BlockNonce::try_from(nonce).map_err(|_| Error::MalformedData)?;
 
     let src_content: &[u8] = row.get(1);
-    if src_content.len() != BLOCK_SIZE {
+    if !is_valid_len(src_content.len()) {
         tracing::error!(
             expected = BLOCK_SIZE,
             actual = src_content.len(),
@@ -64,7 +67,11 @@ pub(super) async fn read(
         return Err(Error::MalformedData);
     }
 
-    content.copy_from_slice(src_content);
+    if src_content.len() == BLOCK_SIZE {
+        content.copy_from_slice(src_content);
+    } else {
+        *content = BlockContent::from_slice(src_content);
+    }
 
     Ok(nonce)
 }
@@ -73,16 +80,16 @@ pub(super) async fn read(
 ///
 /// If a block with the same id already exists, this is a no-op.
 ///
-/// # Panics
-///
-/// Panics if buffer length is not equal to [`BLOCK_SIZE`].
-///
+/// Returns `MalformedData` error if the block is empty or longer than [`BLOCK_SIZE`].
 pub(super) async fn write(tx: &mut db::WriteTransaction, block: &Block) -> Result<(), Error> {
-    assert_eq!(
-        block.content.len(),
-        BLOCK_SIZE,
-        "incorrect buffer length for block write"
-    );
+    if !is_valid_len(block.content.len()) {
+        tracing::error!(
+            expected = BLOCK_SIZE,
+            actual = block.content.len(),
+            "Wrong block length"
+        );
+        return Err(Error::MalformedData);
+    }
 
     sqlx::query(
         "INSERT INTO blocks (id, nonce, content)
@@ -98,6 +105,12 @@ pub(super) async fn write(tx: &mut db::WriteTransaction, block: &Block) -> Resul
     Ok(())
 }
 
+// Uncompressed blocks are exactly `BLOCK_SIZE` long, compressed ones are shorter (but never
+// empty).
+fn is_valid_len(len: usize) -> bool {
+    len > 0 && len <= BLOCK_SIZE
+}
+
 pub(super) async fn remove(tx: &mut db::WriteTransaction, id: &BlockId) -> Result<(), Error> {
     sqlx::query("DELETE FROM blocks WHERE id = ?")
         .bind(id)
@@ -117,6 +130,37 @@ pub(super) async fn count(conn: &mut db::Connection) -> Result<u64, Error> {
     ))
 }
 
+/// Loads at most `limit` blocks whose ids are greater than `lower_bound`, in ascending order, and
+/// checks whether their content still matches their id. Returns the ids together with the result
+/// (`true` if the block is intact).
+pub(super) async fn verify(
+    conn: &mut db::Connection,
+    lower_bound: Option<&BlockId>,
+    limit: u32,
+) -> Result<Vec<(BlockId, bool)>, Error> {
+    sqlx::query(
+        "SELECT id, nonce, content FROM blocks WHERE id > COALESCE(?, x'') ORDER BY id LIMIT ?",
+    )
+    .bind(lower_bound)
+    .bind(limit)
+    .fetch(conn)
+    .map_ok(|row| {
+        let id: BlockId = row.get(0);
+        let nonce: &[u8] = row.get(1);
+        let content: &[u8] = row.get(2);
+
+        let intact = is_valid_len(content.len())
+            && BlockNonce::try_from(nonce)
+                .map(|nonce| BlockId::new(&BlockContent::from_slice(content), &nonce) == id)
+                .unwrap_or(false);
+
+        (id, intact)
+    })
+    .err_into()
+    .try_collect()
+    .await
+}
+
 /// Checks whether the block exists in the store.
 pub(super) async fn exists(conn: &mut db::Connection, id: &BlockId) -> Result<bool, Error> {
     Ok(sqlx::query("SELECT 0 FROM blocks WHERE id = ?")
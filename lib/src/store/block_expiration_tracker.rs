@@ -10,7 +10,8 @@ use crate::{
     crypto::sign::PublicKey,
     db,
     future::try_collect_into,
-    protocol::{BlockId, SingleBlockPresence},
+    protocol::{BlockId, SingleBlockPresence, BLOCK_RECORD_SIZE, BLOCK_SIZE},
+    storage_size::StorageSize,
     sync::{broadcast_hash_set, uninitialized_watch},
 };
 use deadlock::BlockingMutex;
@@ -19,15 +20,74 @@ use scoped_task::{self, ScopedJoinHandle};
 use sqlx::Row;
 use std::{
     collections::{btree_map, BTreeMap},
+    future,
     sync::Arc,
-    time::{Duration, SystemTime},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::watch,
+    time::{sleep_until, Instant},
 };
-use tokio::{select, sync::watch, time::sleep};
 use tracing::{Instrument, Span};
 
+// Size of the block db record minus the content (the id and the nonce).
+const RECORD_OVERHEAD: u64 = BLOCK_RECORD_SIZE - BLOCK_SIZE as u64;
+
+/// Determines when blocks get expired (removed from the store to free space).
+///
+/// Expired blocks are re-fetched from peers when they are needed again (at which point they are
+/// marked as missing).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ExpirationPolicy {
+    /// Blocks expire when they haven't been accessed for the given duration.
+    TimeBased(Duration),
+    /// When the total size of the blocks exceeds the given limit, the least recently accessed
+    /// blocks are expired until it's under the limit again.
+    SizeBased(StorageSize),
+    /// Combination of `TimeBased` and `SizeBased`: blocks expire when either condition is met.
+    Both(Duration, StorageSize),
+}
+
+impl ExpirationPolicy {
+    pub(crate) fn new(
+        expiration_time: Option<Duration>,
+        size_limit: Option<StorageSize>,
+    ) -> Option<Self> {
+        match (expiration_time, size_limit) {
+            (Some(expiration_time), Some(size_limit)) => {
+                Some(Self::Both(expiration_time, size_limit))
+            }
+            (Some(expiration_time), None) => Some(Self::TimeBased(expiration_time)),
+            (None, Some(size_limit)) => Some(Self::SizeBased(size_limit)),
+            (None, None) => None,
+        }
+    }
+
+    /// Duration after which unused blocks expire, if any.
+    pub fn expiration_time(&self) -> Option<Duration> {
+        match self {
+            Self::TimeBased(expiration_time) | Self::Both(expiration_time, _) => {
+                Some(*expiration_time)
+            }
+            Self::SizeBased(_) => None,
+        }
+    }
+
+    /// Total size of the blocks above which the least recently used ones expire, if any.
+    pub fn size_limit(&self) -> Option<StorageSize> {
+        match self {
+            Self::SizeBased(size_limit) | Self::Both(_, size_limit) => Some(*size_limit),
+            Self::TimeBased(_) => None,
+        }
+    }
+}
+
 /// This structure keeps track (in memory) of which blocks are currently in the database. To each
 /// one block it assigns a time when it should expire to free space. Once a block is expired, it is
-/// removed from the DB and its state is changed from "Present" to "Expired" in the index.
+/// removed from the DB and its state is changed from "Present" to "Expired" in the index. If a size
+/// limit is set (see `ExpirationPolicy`), the least recently accessed blocks are expired as soon as
+/// the tracked blocks exceed it, regardless of their age.
 ///
 /// One tricky thing in implementing this structure properly is to ensure the following invariant
 /// holds:
@@ -55,42 +115,44 @@ use tracing::{Instrument, Span};
 pub(crate) struct BlockExpirationTracker {
     shared: Arc<BlockingMutex<Shared>>,
     watch_tx: uninitialized_watch::Sender<()>,
-    expiration_time_tx: watch::Sender<Duration>,
+    policy_tx: watch::Sender<ExpirationPolicy>,
     _task: ScopedJoinHandle<()>,
 }
 
 impl BlockExpirationTracker {
     pub(super) async fn enable_expiration(
         pool: db::Pool,
-        expiration_time: Duration,
+        policy: ExpirationPolicy,
         block_download_tracker: BlockDownloadTracker,
         client_reload_index_tx: broadcast_hash_set::Sender<PublicKey>,
         cache: Arc<Cache>,
     ) -> Result<Self, Error> {
-        let mut shared = Shared {
-            blocks_by_id: Default::default(),
-            blocks_by_expiration: Default::default(),
-            to_missing_if_expired: Default::default(),
-        };
+        let mut shared = Shared::new();
 
         let mut tx = pool.begin_read().await?;
 
-        let mut ids =
-            sqlx::query("SELECT block_id FROM snapshot_leaf_nodes WHERE block_presence = ?")
-                .bind(SingleBlockPresence::Present)
-                .fetch(&mut tx)
-                .map_ok(|row| row.get(0));
+        // Blocks referenced as present but not actually stored count as zero size.
+        let mut blocks = sqlx::query(
+            "SELECT n.block_id, COALESCE(LENGTH(b.content), 0)
+             FROM snapshot_leaf_nodes AS n
+             LEFT JOIN blocks AS b ON b.id = n.block_id
+             WHERE n.block_presence = ?",
+        )
+        .bind(SingleBlockPresence::Present)
+        .fetch(&mut tx)
+        .map_ok(|row| (row.get(0), db::decode_u64(row.get(1))));
 
-        let now = SystemTime::now();
+        let now = Instant::now();
 
-        while let Some(id) = ids.next().await {
-            shared.insert_block(&id?, now);
+        while let Some(block) = blocks.next().await {
+            let (id, len) = block?;
+            shared.insert_block(&id, now, record_size(len));
         }
 
         let (watch_tx, watch_rx) = uninitialized_watch::channel();
         let shared = Arc::new(BlockingMutex::new(shared));
 
-        let (expiration_time_tx, expiration_time_rx) = watch::channel(expiration_time);
+        let (policy_tx, policy_rx) = watch::channel(policy);
 
         let _task = scoped_task::spawn({
             let shared = shared.clone();
@@ -101,7 +163,7 @@ impl BlockExpirationTracker {
                     shared,
                     pool,
                     watch_rx,
-                    expiration_time_rx,
+                    policy_rx,
                     block_download_tracker,
                     client_reload_index_tx,
                     cache,
@@ -117,28 +179,34 @@ impl BlockExpirationTracker {
         Ok(Self {
             shared,
             watch_tx,
-            expiration_time_tx,
+            policy_tx,
             _task,
         })
     }
 
-    pub fn handle_block_update(&self, block_id: &BlockId, is_missing: bool) {
-        // Not inlining these lines to call `SystemTime::now()` only once the `lock` is acquired.
+    /// Records an access to the given block. `stored_len` is the length of the block as stored in
+    /// the database (which is less than `BLOCK_SIZE` if it's compressed), or `None` if the block
+    /// is missing.
+    pub fn handle_block_update(&self, block_id: &BlockId, stored_len: Option<usize>) {
+        // Not inlining these lines to call `Instant::now()` only once the `lock` is acquired.
         let mut lock = self.shared.lock().unwrap();
-        lock.insert_block(block_id, SystemTime::now());
-        if is_missing {
+        if let Some(stored_len) = stored_len {
+            lock.insert_block(block_id, Instant::now(), record_size(stored_len as u64));
+        } else {
+            // Not tracking missing blocks so they don't count towards the size limit. If such block
+            // gets received later, it's tracked at that point.
             lock.to_missing_if_expired.insert(*block_id);
         }
         drop(lock);
         self.watch_tx.send(()).unwrap_or(());
     }
 
-    pub fn set_expiration_time(&self, expiration_time: Duration) {
-        self.expiration_time_tx.send(expiration_time).unwrap_or(());
+    pub fn set_policy(&self, policy: ExpirationPolicy) {
+        self.policy_tx.send(policy).unwrap_or(());
     }
 
-    pub fn block_expiration(&self) -> Duration {
-        *self.expiration_time_tx.borrow()
+    pub fn policy(&self) -> ExpirationPolicy {
+        *self.policy_tx.borrow()
     }
 
     pub fn begin_untrack_blocks(&self) -> UntrackTransaction {
@@ -184,8 +252,9 @@ impl UntrackTransaction {
     }
 }
 
-// For semantics
-type TimeUpdated = SystemTime;
+// For semantics. Using tokio's `Instant` (instead of `SystemTime`) so the expiration can be tested
+// with paused time.
+type TimeUpdated = Instant;
 
 struct Shared {
     // Invariant #1: There exists `(block, time_updated)` in `blocks_by_id` *iff*
@@ -193,25 +262,47 @@ struct Shared {
     //
     // Invariant #2: `blocks_by_expiration[x]` is never empty for any `x`.
     //
-    blocks_by_id: HashMap<BlockId, TimeUpdated>,
+    // Invariant #3: `size` is the sum of the sizes in `blocks_by_id`.
+    //
+    blocks_by_id: HashMap<BlockId, TrackedBlock>,
     blocks_by_expiration: BTreeMap<TimeUpdated, HashSet<BlockId>>,
+    size: u64,
 
     to_missing_if_expired: HashSet<BlockId>,
 }
 
+struct TrackedBlock {
+    time_updated: TimeUpdated,
+    // Size of the block record as stored in the database (see `record_size`).
+    size: u64,
+}
+
 impl Shared {
+    fn new() -> Self {
+        Self {
+            blocks_by_id: Default::default(),
+            blocks_by_expiration: Default::default(),
+            size: 0,
+            to_missing_if_expired: Default::default(),
+        }
+    }
+
     /// Add the `block` into `Self`. If it's already there, remove it and add it back with the new
     /// time stamp.
-    fn insert_block(&mut self, block: &BlockId, ts: TimeUpdated) {
+    fn insert_block(&mut self, block: &BlockId, ts: TimeUpdated, size: u64) {
         // Asserts and unwraps are OK due to the `Shared` invariants defined above.
         match self.blocks_by_id.entry(*block) {
             hash_map::Entry::Occupied(mut entry) => {
-                let old_ts = *entry.get();
+                let entry_mut = entry.get_mut();
+                self.size = self.size - entry_mut.size + size;
+                entry_mut.size = size;
+
+                let old_ts = entry_mut.time_updated;
                 if old_ts == ts {
                     return;
                 }
 
-                entry.insert(ts);
+                entry_mut.time_updated = ts;
 
                 let mut entry = match self.blocks_by_expiration.entry(old_ts) {
                     btree_map::Entry::Occupied(entry) => entry,
@@ -237,17 +328,23 @@ impl Shared {
                     .or_default()
                     .insert(*block));
 
-                entry.insert(ts);
+                entry.insert(TrackedBlock {
+                    time_updated: ts,
+                    size,
+                });
+                self.size += size;
             }
         }
     }
 
     fn remove_block(&mut self, block: &BlockId) {
         // Asserts and unwraps are OK due to the `Shared` invariants defined above.
-        let Some(time_updated) = self.blocks_by_id.remove(block) else {
+        let Some(TrackedBlock { time_updated, size }) = self.blocks_by_id.remove(block) else {
             return;
         };
 
+        self.size -= size;
+
         let mut entry = match self.blocks_by_expiration.entry(time_updated) {
             btree_map::Entry::Occupied(entry) => entry,
             btree_map::Entry::Vacant(_) => unreachable!(),
@@ -260,16 +357,25 @@ impl Shared {
         }
     }
 
+    /// Total size of the tracked blocks.
+    fn size(&self) -> StorageSize {
+        StorageSize::from_bytes(self.size)
+    }
+
     #[cfg(test)]
     fn assert_invariants(&self) {
         // #1 =>
-        for (block, ts) in self.blocks_by_id.iter() {
-            assert!(self.blocks_by_expiration.get(ts).unwrap().contains(block));
+        for (block, tracked) in self.blocks_by_id.iter() {
+            assert!(self
+                .blocks_by_expiration
+                .get(&tracked.time_updated)
+                .unwrap()
+                .contains(block));
         }
         // #1 <=
         for (ts, blocks) in self.blocks_by_expiration.iter() {
             for block in blocks.iter() {
-                assert_eq!(self.blocks_by_id.get(block).unwrap(), ts);
+                assert_eq!(self.blocks_by_id.get(block).unwrap().time_updated, *ts);
             }
         }
         // Degenerate case
@@ -281,6 +387,24 @@ impl Shared {
         for blocks in self.blocks_by_expiration.values() {
             assert!(!blocks.is_empty());
         }
+        // #3
+        assert_eq!(
+            self.blocks_by_id
+                .values()
+                .map(|tracked| tracked.size)
+                .sum::<u64>(),
+            self.size
+        );
+    }
+}
+
+// Size of the db record of a block whose stored content has the given length. Blocks not actually
+// stored (zero length) count as zero.
+fn record_size(content_len: u64) -> u64 {
+    if content_len > 0 {
+        content_len + RECORD_OVERHEAD
+    } else {
+        0
     }
 }
 
@@ -288,17 +412,17 @@ async fn run_task(
     shared: Arc<BlockingMutex<Shared>>,
     pool: db::Pool,
     mut watch_rx: uninitialized_watch::Receiver<()>,
-    mut expiration_time_rx: watch::Receiver<Duration>,
+    mut policy_rx: watch::Receiver<ExpirationPolicy>,
     block_download_tracker: BlockDownloadTracker,
     client_reload_index_tx: broadcast_hash_set::Sender<PublicKey>,
     cache: Arc<Cache>,
 ) -> Result<(), Error> {
     loop {
-        let expiration_time = *expiration_time_rx.borrow();
+        let policy = *policy_rx.borrow();
 
-        let (ts, block_id) = {
+        let (ts, block_id, over_limit) = {
             enum Enum {
-                OldestEntry(Option<(TimeUpdated, BlockId)>),
+                OldestEntry(Option<(TimeUpdated, BlockId)>, bool),
                 ToMissing(HashSet<BlockId>),
             }
 
@@ -308,18 +432,26 @@ async fn run_task(
                 if !lock.to_missing_if_expired.is_empty() {
                     Enum::ToMissing(std::mem::take(&mut lock.to_missing_if_expired))
                 } else {
+                    let over_limit = policy
+                        .size_limit()
+                        .map(|size_limit| lock.size() > size_limit)
+                        .unwrap_or(false);
+
                     Enum::OldestEntry(
                         lock.blocks_by_expiration
                             .first_entry()
                             // Unwrap OK due to the invariant #2.
                             .map(|e| (*e.key(), *e.get().iter().next().unwrap())),
+                        over_limit,
                     )
                 }
             };
 
             match action {
-                Enum::OldestEntry(Some((time_updated, block_id))) => (time_updated, block_id),
-                Enum::OldestEntry(None) => {
+                Enum::OldestEntry(Some((time_updated, block_id)), over_limit) => {
+                    (time_updated, block_id, over_limit)
+                }
+                Enum::OldestEntry(None, _) => {
                     if watch_rx.changed().await.is_err() {
                         return Ok(());
                     }
@@ -339,19 +471,27 @@ async fn run_task(
             }
         };
 
-        let expires_at = ts + expiration_time;
-        let now = SystemTime::now();
+        // When over the size limit, the least recently accessed block is expired right away.
+        // Otherwise it's expired once it gets old enough or never if there is no expiration time.
+        let expires_at = policy
+            .expiration_time()
+            .map(|expiration_time| ts + expiration_time);
+
+        if !over_limit && expires_at.map(|at| at > Instant::now()).unwrap_or(true) {
+            let sleep = async {
+                match expires_at {
+                    Some(at) => sleep_until(at).await,
+                    None => future::pending().await,
+                }
+            };
 
-        if expires_at > now {
-            if let Ok(duration) = expires_at.duration_since(now) {
-                select! {
-                    _ = sleep(duration) => (),
-                    _ = expiration_time_rx.changed() => {
-                        continue;
-                    }
-                    _ = watch_rx.changed() => {
-                        continue;
-                    }
+            select! {
+                _ = sleep => (),
+                _ = policy_rx.changed() => {
+                    continue;
+                }
+                _ = watch_rx.changed() => {
+                    continue;
                 }
             }
 
@@ -372,7 +512,10 @@ async fn run_task(
         let mut tx = pool.begin_write().await?;
 
         if !leaf_node::set_expired_if_present(&mut tx, &block_id).await? {
-            return Ok(());
+            // The block is tracked but not present (e.g., it's been requested but not yet
+            // downloaded). Stop tracking it so it doesn't count towards the size limit.
+            shared.lock().unwrap().remove_block(&block_id);
+            continue;
         }
 
         block::remove(&mut tx, &block_id).await?;
@@ -433,47 +576,48 @@ mod test {
     use super::super::*;
     use super::*;
     use crate::crypto::sign::Keypair;
+    use assert_matches::assert_matches;
     use futures_util::future;
     use rand::distributions::Standard;
     use rand::seq::SliceRandom;
     use rand::Rng;
     use tempfile::TempDir;
-    use tokio::task;
+    use tokio::{task, time::sleep};
 
     #[test]
     fn shared_state() {
-        let mut shared = Shared {
-            blocks_by_id: Default::default(),
-            blocks_by_expiration: Default::default(),
-            to_missing_if_expired: Default::default(),
-        };
+        let mut shared = Shared::new();
 
         // add once
 
-        let ts = SystemTime::now();
+        let ts = Instant::now();
         let block: BlockId = rand::random();
 
-        shared.insert_block(&block, ts);
+        shared.insert_block(&block, ts, 100);
 
-        assert_eq!(*shared.blocks_by_id.get(&block).unwrap(), ts,);
+        assert_eq!(shared.blocks_by_id.get(&block).unwrap().time_updated, ts);
+        assert_eq!(shared.size(), StorageSize::from_bytes(100));
         shared.assert_invariants();
 
         shared.remove_block(&block);
 
         assert!(shared.blocks_by_id.is_empty());
+        assert_eq!(shared.size(), StorageSize::from_bytes(0));
         shared.assert_invariants();
 
         // add twice
 
-        shared.insert_block(&block, ts);
-        shared.insert_block(&block, ts);
+        shared.insert_block(&block, ts, 100);
+        shared.insert_block(&block, ts, 100);
 
-        assert_eq!(*shared.blocks_by_id.get(&block).unwrap(), ts);
+        assert_eq!(shared.blocks_by_id.get(&block).unwrap().time_updated, ts);
+        assert_eq!(shared.size(), StorageSize::from_bytes(100));
         shared.assert_invariants();
 
         shared.remove_block(&block);
 
         assert!(shared.blocks_by_id.is_empty());
+        assert_eq!(shared.size(), StorageSize::from_bytes(0));
         shared.assert_invariants();
     }
 
@@ -503,7 +647,7 @@ mod test {
 
         let tracker = BlockExpirationTracker::enable_expiration(
             store.db().clone(),
-            Duration::from_secs(1),
+            ExpirationPolicy::TimeBased(Duration::from_secs(1)),
             BlockDownloadTracker::new(),
             broadcast_hash_set::channel().0,
             Arc::new(Cache::new()),
@@ -514,7 +658,7 @@ mod test {
         sleep(Duration::from_millis(700)).await;
 
         let block_id = add_block(rand::random(), &write_keys, &branch_id, &store).await;
-        tracker.handle_block_update(&block_id, false);
+        tracker.handle_block_update(&block_id, Some(BLOCK_SIZE));
 
         assert_eq!(count_blocks(store.db()).await, 2);
 
@@ -527,6 +671,122 @@ mod test {
         assert_eq!(count_blocks(store.db()).await, 0);
     }
 
+    #[tokio::test]
+    async fn evict_least_recently_used_blocks() {
+        crate::test_utils::init_log();
+
+        let (_base_dir, store) = setup().await;
+        let write_keys = Keypair::random();
+        let branch_id = PublicKey::random();
+
+        store
+            .set_block_expiration(
+                Some(ExpirationPolicy::SizeBased(StorageSize::from_blocks(2))),
+                BlockDownloadTracker::new(),
+            )
+            .await
+            .unwrap();
+
+        let block_id_0 = add_block(rand::random(), &write_keys, &branch_id, &store).await;
+        sleep(Duration::from_millis(10)).await;
+        let block_id_1 = add_block(rand::random(), &write_keys, &branch_id, &store).await;
+        sleep(Duration::from_millis(10)).await;
+
+        // Access the first block so the second one becomes the least recently used.
+        store
+            .acquire_read()
+            .await
+            .unwrap()
+            .read_block(&block_id_0, &mut BlockContent::new())
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(10)).await;
+
+        let block_id_2 = add_block(rand::random(), &write_keys, &branch_id, &store).await;
+
+        while count_blocks(store.db()).await > 2 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut conn = store.db().acquire().await.unwrap();
+        assert!(block::exists(&mut conn, &block_id_0).await.unwrap());
+        assert!(!block::exists(&mut conn, &block_id_1).await.unwrap());
+        assert!(block::exists(&mut conn, &block_id_2).await.unwrap());
+        drop(conn);
+
+        assert_eq!(
+            load_block_presence(store.db(), &block_id_1).await,
+            SingleBlockPresence::Expired
+        );
+
+        // Accessing the evicted block marks it as missing so it gets fetched again.
+        assert_matches!(
+            store
+                .acquire_read()
+                .await
+                .unwrap()
+                .read_block(&block_id_1, &mut BlockContent::new())
+                .await,
+            Err(Error::BlockNotFound)
+        );
+
+        while load_block_presence(store.db(), &block_id_1).await != SingleBlockPresence::Missing {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn evict_by_stored_size() {
+        crate::test_utils::init_log();
+
+        let (_base_dir, store) = setup().await;
+        let write_keys = Keypair::random();
+        let branch_id = PublicKey::random();
+
+        store
+            .set_block_expiration(
+                Some(ExpirationPolicy::SizeBased(StorageSize::from_blocks(2))),
+                BlockDownloadTracker::new(),
+            )
+            .await
+            .unwrap();
+
+        // Blocks shorter than `BLOCK_SIZE` (e.g., compressed ones) count only with their stored
+        // size.
+        let mut small_ids = Vec::new();
+
+        for _ in 0..3 {
+            let content: Vec<u8> = (&mut rand::thread_rng())
+                .sample_iter(Standard)
+                .take(BLOCK_SIZE / 4)
+                .collect();
+            let block = Block::new(BlockContent::from_slice(&content), rand::random());
+
+            small_ids.push(add_block(block, &write_keys, &branch_id, &store).await);
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let large_id_0 = add_block(rand::random(), &write_keys, &branch_id, &store).await;
+        sleep(Duration::from_millis(10)).await;
+
+        // 1.75 blocks in total, under the limit.
+        assert_eq!(count_blocks(store.db()).await, 4);
+
+        let large_id_1 = add_block(rand::random(), &write_keys, &branch_id, &store).await;
+
+        // 2.75 blocks in total. Evicting the three small blocks brings it back to the limit.
+        while count_blocks(store.db()).await > 2 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut conn = store.db().acquire().await.unwrap();
+        for id in &small_ids {
+            assert!(!block::exists(&mut conn, id).await.unwrap());
+        }
+        assert!(block::exists(&mut conn, &large_id_0).await.unwrap());
+        assert!(block::exists(&mut conn, &large_id_1).await.unwrap());
+    }
+
     /// This test checks the condition that "if there is a block in the main database, then it must
     /// be in the expiration tracker" in the presence of concurrent block insertions and removals.
     #[tokio::test]
@@ -540,7 +800,9 @@ mod test {
             .set_block_expiration(
                 // Setting expiration time to something big, we don't care about blocks actually
                 // expiring in this test.
-                Some(Duration::from_secs(60 * 60 /* one hour */)),
+                Some(ExpirationPolicy::TimeBased(Duration::from_secs(
+                    60 * 60, /* one hour */
+                ))),
                 BlockDownloadTracker::new(),
             )
             .await
@@ -646,6 +908,15 @@ mod test {
         block_id
     }
 
+    async fn load_block_presence(pool: &db::Pool, block_id: &BlockId) -> SingleBlockPresence {
+        sqlx::query("SELECT block_presence FROM snapshot_leaf_nodes WHERE block_id = ?")
+            .bind(block_id)
+            .fetch_one(&mut *pool.acquire().await.unwrap())
+            .await
+            .unwrap()
+            .get(0)
+    }
+
     async fn count_blocks(pool: &db::Pool) -> u64 {
         block::count(&mut pool.acquire().await.unwrap())
             .await
@@ -1,4 +1,4 @@
-use super::{block, error::Error, patch::Patch, WriteTransaction};
+use super::{block, error::Error, patch::Patch, quota, BlockAccessKind, WriteTransaction};
 use crate::{
     crypto::{
         sign::{Keypair, PublicKey},
@@ -24,12 +24,17 @@ impl Changeset {
 
     /// Applies this changeset to the transaction.
     /// Returns `true` if any change was performed, or `false` if the changeset is a no-op.
+    /// Fails with `Error::QuotaExceeded` if writing the blocks would exceed the repository quota.
     pub async fn apply(
         self,
         tx: &mut WriteTransaction,
         branch_id: &PublicKey,
         write_keys: &Keypair,
     ) -> Result<bool, Error> {
+        if !self.blocks.is_empty() {
+            quota::check_local(tx.db(), self.blocks.len()).await?;
+        }
+
         let mut patch = Patch::new(tx, *branch_id).await?;
         let mut changed = false;
 
@@ -63,9 +68,11 @@ impl Changeset {
             block::write(tx.db(), &block).await?;
 
             if let Some(tracker) = &tx.block_expiration_tracker {
-                tracker.handle_block_update(&block.id, false);
+                tracker.handle_block_update(&block.id, Some(block.content.len()));
             }
 
+            tx.record_access_on_commit(&block.id, BlockAccessKind::Write);
+
             changed = true;
         }
 
@@ -1,3 +1,4 @@
+use crate::storage_size::StorageSize;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -18,4 +19,26 @@ pub enum Error {
     BlockNotFound,
     #[error("block is not referenced from the index")]
     BlockNotReferenced,
+    #[error("storage quota exceeded (limit: {limit}, actual: {actual})")]
+    QuotaExceeded {
+        limit: StorageSize,
+        actual: StorageSize,
+    },
+}
+
+impl Error {
+    /// Whether this is a transient database error (e.g., the database is busy or locked by another
+    /// connection) and the failed operation can be retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Db(sqlx::Error::Database(error)) => matches!(
+                error.code().as_deref(),
+                // SQLITE_BUSY, SQLITE_LOCKED, SQLITE_BUSY_RECOVERY, SQLITE_LOCKED_SHAREDCACHE,
+                // SQLITE_BUSY_SNAPSHOT
+                Some("5" | "6" | "261" | "262" | "517")
+            ),
+            Self::Db(sqlx::Error::PoolTimedOut) => true,
+            _ => false,
+        }
+    }
 }
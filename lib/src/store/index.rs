@@ -15,6 +15,7 @@ use crate::{
     protocol::NodeState,
     storage_size::StorageSize,
 };
+use futures_util::TryStreamExt;
 use sqlx::Row;
 
 /// Status of receiving nodes from remote replica.
@@ -24,6 +25,9 @@ pub(super) struct ReceiveStatus {
     pub old_approved: bool,
     /// List of branches whose snapshots have been approved.
     pub new_approved: Vec<PublicKey>,
+    /// If any snapshot was rejected because it would exceed the quota, this is the size the
+    /// repository would have if it was approved.
+    pub quota_exceeded: Option<StorageSize>,
 }
 
 /// Does a parent node (root or inner) with the given hash exist?
@@ -78,6 +82,31 @@ pub(super) async fn update_summaries(
     Ok(states)
 }
 
+/// Recompute the summaries of all the nodes in the index. Useful when some of the nodes or blocks
+/// might have been lost without their ancestors being updated (e.g., after recovering a damaged
+/// database).
+pub(super) async fn recompute_all_summaries(
+    write_tx: &mut db::WriteTransaction,
+    cache_tx: &mut CacheTransaction,
+) -> Result<(), Error> {
+    let nodes = sqlx::query(
+        "SELECT DISTINCT parent FROM snapshot_leaf_nodes
+         UNION
+         SELECT hash FROM snapshot_inner_nodes
+         UNION
+         SELECT hash FROM snapshot_root_nodes",
+    )
+    .fetch(&mut *write_tx)
+    .map_ok(|row| row.get(0))
+    .err_into()
+    .try_collect()
+    .await?;
+
+    update_summaries(write_tx, cache_tx, nodes).await?;
+
+    Ok(())
+}
+
 pub(super) async fn finalize(
     write_tx: &mut db::WriteTransaction,
     cache_tx: &mut CacheTransaction,
@@ -94,6 +123,7 @@ pub(super) async fn finalize(
 
     let mut old_approved = false;
     let mut new_approved = Vec::new();
+    let mut quota_exceeded = None;
 
     for (hash, state) in states {
         match state {
@@ -110,6 +140,7 @@ pub(super) async fn finalize(
                 Ok(()) => true,
                 Err(QuotaError::Exceeded(size)) => {
                     tracing::warn!(?hash, quota = %quota, size = %size, "snapshot rejected - quota exceeded");
+                    quota_exceeded = quota_exceeded.max(Some(size));
                     false
                 }
                 Err(QuotaError::Outdated) => {
@@ -139,6 +170,7 @@ pub(super) async fn finalize(
     Ok(ReceiveStatus {
         old_approved,
         new_approved,
+        quota_exceeded,
     })
 }
 
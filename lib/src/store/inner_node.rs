@@ -3,6 +3,7 @@ use crate::{
     crypto::{sign::PublicKey, Hash},
     db,
     protocol::{InnerNode, InnerNodes, LeafNodes, Summary, EMPTY_INNER_HASH, EMPTY_LEAF_HASH},
+    storage_size::StorageSize,
 };
 use futures_util::{future, TryStreamExt};
 use sqlx::Row;
@@ -12,6 +13,8 @@ use std::convert::TryInto;
 pub(crate) struct ReceiveStatus {
     /// List of branches whose snapshots have been approved.
     pub new_approved: Vec<PublicKey>,
+    /// Size the repository would have if the snapshots rejected due to quota were approved, if any.
+    pub quota_exceeded: Option<StorageSize>,
     /// Which of the received nodes should we request the children of.
     pub request_children: Vec<InnerNode>,
 }
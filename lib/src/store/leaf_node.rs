@@ -2,7 +2,8 @@ use super::error::Error;
 use crate::{
     crypto::{sign::PublicKey, Hash},
     db,
-    protocol::{BlockId, LeafNode, LeafNodes, SingleBlockPresence},
+    protocol::{BlockId, LeafNode, LeafNodes, RootNode, SingleBlockPresence},
+    storage_size::StorageSize,
 };
 use futures_util::{Stream, TryStreamExt};
 use sqlx::Row;
@@ -16,6 +17,8 @@ pub(crate) struct ReceiveStatus {
     pub old_approved: bool,
     /// List of branches whose snapshots have been approved.
     pub new_approved: Vec<PublicKey>,
+    /// Size the repository would have if the snapshots rejected due to quota were approved, if any.
+    pub quota_exceeded: Option<StorageSize>,
     /// Which of the received nodes should we request the blocks of.
     pub request_blocks: Vec<LeafNode>,
 }
@@ -66,6 +69,35 @@ pub(super) fn load_locators<'a>(
         .err_into()
 }
 
+/// Loads the block ids of all the leaf nodes of the given snapshot together with the stored length
+/// of the block content (zero if the block is not stored).
+pub(super) fn load_blocks_in_snapshot<'a>(
+    conn: &'a mut db::Connection,
+    root_node: &RootNode,
+) -> impl Stream<Item = Result<(BlockId, u64), Error>> + 'a {
+    sqlx::query(
+        "WITH RECURSIVE
+             inner_nodes(hash) AS (
+                 SELECT i.hash
+                     FROM snapshot_inner_nodes AS i
+                     INNER JOIN snapshot_root_nodes AS r ON r.hash = i.parent
+                     WHERE r.snapshot_id = ?
+                 UNION ALL
+                 SELECT c.hash
+                     FROM snapshot_inner_nodes AS c
+                     INNER JOIN inner_nodes AS p ON p.hash = c.parent
+             )
+         SELECT n.block_id, COALESCE(LENGTH(b.content), 0)
+             FROM snapshot_leaf_nodes AS n
+             LEFT JOIN blocks AS b ON b.id = n.block_id
+             WHERE n.parent IN inner_nodes",
+    )
+    .bind(root_node.snapshot_id)
+    .fetch(conn)
+    .map_ok(|row| (row.get(0), db::decode_u64(row.get(1))))
+    .err_into()
+}
+
 /// Saves the node to the db unless it already exists.
 async fn save(tx: &mut db::WriteTransaction, node: &LeafNode, parent: &Hash) -> Result<(), Error> {
     sqlx::query(
@@ -161,6 +193,21 @@ pub(super) async fn is_missing(
     )
 }
 
+/// Returns the ids of the blocks that are referenced as present but don't actually exist in the
+/// store.
+pub(super) async fn load_lost_block_ids(conn: &mut db::Connection) -> Result<Vec<BlockId>, Error> {
+    sqlx::query(
+        "SELECT DISTINCT block_id FROM snapshot_leaf_nodes
+         WHERE block_presence = ? AND block_id NOT IN (SELECT id FROM blocks)",
+    )
+    .bind(SingleBlockPresence::Present)
+    .fetch(conn)
+    .map_ok(|row| row.get(0))
+    .err_into()
+    .try_collect()
+    .await
+}
+
 /// Marks all leaf nodes that point to the specified block as missing.
 pub(super) async fn set_missing(
     tx: &mut db::WriteTransaction,
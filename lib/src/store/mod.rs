@@ -1,3 +1,4 @@
+mod access_log;
 mod block;
 mod block_expiration_tracker;
 mod block_ids;
@@ -16,6 +17,8 @@ mod root_node;
 #[cfg(test)]
 mod tests;
 
+pub use access_log::{BlockAccess, BlockAccessKind};
+pub use block_expiration_tracker::ExpirationPolicy;
 pub use error::Error;
 pub use migrations::DATA_VERSION;
 
@@ -27,11 +30,13 @@ pub(crate) use {
 };
 
 use self::{
+    access_log::AccessLog,
     block_expiration_tracker::BlockExpirationTracker,
     cache::{Cache, CacheTransaction},
 };
 use crate::{
     block_tracker::BlockTracker as BlockDownloadTracker,
+    collections::HashSet,
     crypto::{
         sign::{Keypair, PublicKey},
         CacheHash, Hash, Hashable,
@@ -40,21 +45,27 @@ use crate::{
     debug::DebugPrinter,
     progress::Progress,
     protocol::{
-        get_bucket, Block, BlockContent, BlockId, BlockNonce, InnerNodes, LeafNodes,
-        MultiBlockPresence, NodeState, Proof, RootNode, RootNodeFilter, Summary, INNER_LAYER_COUNT,
+        get_bucket, Block, BlockContent, BlockId, BlockNonce, InnerNodes, LeafNode, LeafNodes,
+        MultiBlockPresence, NodeState, Proof, RootNode, RootNodeFilter, SingleBlockPresence,
+        Summary, INNER_LAYER_COUNT,
     },
     storage_size::StorageSize,
     sync::broadcast_hash_set,
 };
+use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
 use futures_util::{Stream, TryStreamExt};
 use std::{
     borrow::Cow,
+    future::Future,
     ops::{Deref, DerefMut},
     sync::Arc,
     time::Duration,
 };
 // TODO: Consider creating an async `RwLock` in the `deadlock` module and use it here.
-use tokio::sync::RwLock;
+use tokio::{sync::RwLock, time};
+
+/// Default max number of retries for `Store::with_write_retry`.
+pub(crate) const WRITE_RETRY_LIMIT: u32 = 5;
 
 /// Data store
 #[derive(Clone)]
@@ -63,6 +74,7 @@ pub(crate) struct Store {
     cache: Arc<Cache>,
     pub client_reload_index_tx: broadcast_hash_set::Sender<PublicKey>,
     block_expiration_tracker: Arc<RwLock<Option<Arc<BlockExpirationTracker>>>>,
+    access_log: Arc<AccessLog>,
 }
 
 impl Store {
@@ -74,6 +86,7 @@ impl Store {
             cache: Arc::new(Cache::new()),
             client_reload_index_tx,
             block_expiration_tracker: Arc::new(RwLock::new(None)),
+            access_log: Arc::new(AccessLog::default()),
         }
     }
 
@@ -93,27 +106,27 @@ impl Store {
 
     pub async fn set_block_expiration(
         &self,
-        expiration_time: Option<Duration>,
+        policy: Option<ExpirationPolicy>,
         block_download_tracker: BlockDownloadTracker,
     ) -> Result<(), Error> {
         let mut tracker_lock = self.block_expiration_tracker.write().await;
 
         if let Some(tracker) = &*tracker_lock {
-            if let Some(expiration_time) = expiration_time {
-                tracker.set_expiration_time(expiration_time);
+            if let Some(policy) = policy {
+                tracker.set_policy(policy);
             }
             return Ok(());
         }
 
-        let expiration_time = match expiration_time {
-            Some(expiration_time) => expiration_time,
+        let policy = match policy {
+            Some(policy) => policy,
             // Tracker is `None` so we're good.
             None => return Ok(()),
         };
 
         let tracker = BlockExpirationTracker::enable_expiration(
             self.db.clone(),
-            expiration_time,
+            policy,
             block_download_tracker,
             self.client_reload_index_tx.clone(),
             self.cache.clone(),
@@ -125,12 +138,12 @@ impl Store {
         Ok(())
     }
 
-    pub async fn block_expiration(&self) -> Option<Duration> {
+    pub async fn block_expiration(&self) -> Option<ExpirationPolicy> {
         self.block_expiration_tracker
             .read()
             .await
             .as_ref()
-            .map(|tracker| tracker.block_expiration())
+            .map(|tracker| tracker.policy())
     }
 
     #[cfg(test)]
@@ -138,12 +151,30 @@ impl Store {
         self.block_expiration_tracker.read().await.as_ref().cloned()
     }
 
+    /// Enables or disables the log of the recent block accesses (see
+    /// [`Self::recent_block_accesses`]). Disabling it also clears it. Disabled by default.
+    pub fn set_block_access_log_enabled(&self, enabled: bool) {
+        self.access_log.set_enabled(enabled);
+    }
+
+    pub fn is_block_access_log_enabled(&self) -> bool {
+        self.access_log.is_enabled()
+    }
+
+    /// Returns up to `n` most recent block accesses (reads, local writes and blocks received from
+    /// peers), oldest first. Always empty unless enabled with
+    /// [`Self::set_block_access_log_enabled`].
+    pub fn recent_block_accesses(&self, n: usize) -> Vec<BlockAccess> {
+        self.access_log.recent(n)
+    }
+
     /// Acquires a `Reader`
     pub async fn acquire_read(&self) -> Result<Reader, Error> {
         Ok(Reader {
             inner: Handle::Connection(self.db.acquire().await?),
             cache: self.cache.begin(),
             block_expiration_tracker: self.block_expiration_tracker.read().await.clone(),
+            access_log: self.access_log.clone(),
         })
     }
 
@@ -154,22 +185,85 @@ impl Store {
                 inner: Handle::ReadTransaction(self.db.begin_read().await?),
                 cache: self.cache.begin(),
                 block_expiration_tracker: self.block_expiration_tracker.read().await.clone(),
+                access_log: self.access_log.clone(),
             },
         })
     }
 
-    /// Begins a `WriteTransaction`
+    /// Begins a high-priority `WriteTransaction`. This is the default and should be used for
+    /// anything the user might be waiting for. See also [`Self::begin_write_low_priority`] and
+    /// [`db::Pool::begin_write`].
     pub async fn begin_write(&self) -> Result<WriteTransaction, Error> {
-        Ok(WriteTransaction {
+        let tx = self.db.begin_write().await?;
+        Ok(self.wrap_write(tx).await)
+    }
+
+    /// Begins a low-priority `WriteTransaction`. See [`db::Pool::begin_write_low_priority`] for
+    /// details.
+    pub async fn begin_write_low_priority(&self) -> Result<WriteTransaction, Error> {
+        let tx = self.db.begin_write_low_priority().await?;
+        Ok(self.wrap_write(tx).await)
+    }
+
+    async fn wrap_write(&self, tx: db::WriteTransaction) -> WriteTransaction {
+        WriteTransaction {
             inner: ReadTransaction {
                 inner: Reader {
-                    inner: Handle::WriteTransaction(self.db.begin_write().await?),
+                    inner: Handle::WriteTransaction(tx),
                     cache: self.cache.begin(),
                     block_expiration_tracker: self.block_expiration_tracker.read().await.clone(),
+                    access_log: self.access_log.clone(),
                 },
             },
             untrack_blocks: None,
-        })
+            committed_accesses: Vec::new(),
+        }
+    }
+
+    /// Begins a `WriteTransaction` and passes it to `f` which is expected to perform some work in
+    /// it and commit it. If that fails with a transient error (see [`Error::is_transient`]), the
+    /// whole thing is retried (with a new transaction and exponential backoff) up to `max_retries`
+    /// times.
+    ///
+    /// Note `f` can be called multiple times and so it should be safe to do so. In particular, it
+    /// should not have side effects outside of the transaction (or those side effects must be
+    /// idempotent), because those are not rolled back when the transaction fails.
+    pub async fn with_write_retry<F, Fut, R>(&self, max_retries: u32, mut f: F) -> Result<R, Error>
+    where
+        F: FnMut(WriteTransaction) -> Fut,
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        let mut backoff = ExponentialBackoffBuilder::new()
+            .with_initial_interval(Duration::from_millis(10))
+            .with_max_interval(Duration::from_secs(1))
+            .with_max_elapsed_time(None)
+            .build();
+        let mut retries = 0;
+
+        loop {
+            let result = match self.begin_write().await {
+                Ok(tx) => f(tx).await,
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Err(error) if error.is_transient() && retries < max_retries => {
+                    retries += 1;
+
+                    // We set max elapsed time to None above.
+                    let delay = backoff.next_backoff().unwrap_or_default();
+                    tracing::debug!(
+                        ?error,
+                        retries,
+                        ?delay,
+                        "write transaction failed, retrying"
+                    );
+
+                    time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
     }
 
     pub async fn count_blocks(&self) -> Result<u64, Error> {
@@ -195,16 +289,26 @@ impl Store {
     /// This preserves older snapshots that can be used as fallback for the latest snapshot and only
     /// removes those that can't. This also preserves all older snapshots that have the same
     /// version vector as the latest one (that is, when the latest snapshot is a draft).
-    pub async fn remove_outdated_snapshots(&self, root_node: &RootNode) -> Result<(), Error> {
+    ///
+    /// If `max_fallback_snapshots` is `Some`, at most that many fallback snapshots are preserved
+    /// (the most recent ones) and any older ones are removed even if they could serve as fallback.
+    pub async fn remove_outdated_snapshots(
+        &self,
+        root_node: &RootNode,
+        max_fallback_snapshots: Option<usize>,
+    ) -> Result<(), Error> {
         // First remove all incomplete snapshots as they can never serve as fallback.
-        let mut tx = self.begin_write().await?;
-        root_node::remove_older_incomplete(tx.db(), root_node).await?;
-        tx.commit().await?;
+        self.with_write_retry(WRITE_RETRY_LIMIT, |mut tx| async move {
+            root_node::remove_older_incomplete(tx.db(), root_node).await?;
+            tx.commit().await
+        })
+        .await?;
 
         let mut reader = self.acquire_read().await?;
 
         // Then remove those snapshots that can't serve as fallback for the current one.
         let mut new = Cow::Borrowed(root_node);
+        let mut fallback_count = 0;
 
         while let Some(old) = reader.load_prev_root_node(&new).await? {
             if old.proof.version_vector == new.proof.version_vector {
@@ -220,7 +324,9 @@ impl Store {
                 continue;
             }
 
-            if root_node::check_fallback(reader.db(), &old, &new).await? {
+            let limit_reached = max_fallback_snapshots.is_some_and(|max| fallback_count >= max);
+
+            if !limit_reached && root_node::check_fallback(reader.db(), &old, &new).await? {
                 // `old` can serve as fallback for `self` and so we can't prune it yet. Try the
                 // previous snapshot.
                 tracing::trace!(
@@ -230,14 +336,22 @@ impl Store {
                     "outdated snapshot not removed - possible fallback"
                 );
 
+                fallback_count += 1;
                 new = Cow::Owned(old);
                 continue;
             }
 
-            // `old` can't serve as fallback for `self` and so we can safely remove it
-            let mut tx = self.begin_write().await?;
-            root_node::remove(tx.db(), &old).await?;
-            tx.commit().await?;
+            // `old` can't serve as fallback for `self` (or we already keep enough fallbacks) and
+            // so we can safely remove it
+            self.with_write_retry(WRITE_RETRY_LIMIT, |mut tx| {
+                let old = &old;
+
+                async move {
+                    root_node::remove(tx.db(), old).await?;
+                    tx.commit().await
+                }
+            })
+            .await?;
 
             tracing::trace!(
                 branch_id = ?old.proof.writer_id,
@@ -250,12 +364,107 @@ impl Store {
         Ok(())
     }
 
+    /// Remove the given snapshot. Must not be the latest snapshot of its branch.
+    pub async fn remove_snapshot(&self, root_node: &RootNode) -> Result<(), Error> {
+        self.with_write_retry(WRITE_RETRY_LIMIT, |mut tx| async move {
+            root_node::remove(tx.db(), root_node).await?;
+            tx.commit().await
+        })
+        .await?;
+
+        tracing::trace!(
+            branch_id = ?root_node.proof.writer_id,
+            hash = ?root_node.proof.hash,
+            vv = ?root_node.proof.version_vector,
+            "snapshot removed"
+        );
+
+        Ok(())
+    }
+
     /// Returns all block ids referenced from complete snapshots. The result is paginated (with
     /// `page_size` entries per page) to avoid loading too many items into memory.
     pub fn block_ids(&self, page_size: u32) -> BlockIdsPage {
         BlockIdsPage::new(self.db.clone(), page_size)
     }
 
+    /// Checks the content of every stored block against its id and the index against the stored
+    /// blocks. Blocks whose content doesn't match their id (corrupt) and blocks referenced as
+    /// present but not actually stored (lost) are removed and marked as missing so they can be
+    /// downloaded again, and the indices of all branches are then reloaded from the peers.
+    pub async fn repair_blocks(&self) -> Result<BlockRepair, Error> {
+        const BATCH_SIZE: u32 = 256;
+
+        let mut repair = BlockRepair::default();
+        let mut lower_bound = None;
+
+        loop {
+            let blocks = {
+                let mut reader = self.acquire_read().await?;
+                block::verify(reader.db(), lower_bound.as_ref(), BATCH_SIZE).await?
+            };
+
+            let Some((last, _)) = blocks.last().copied() else {
+                break;
+            };
+
+            repair.verified += blocks.len() as u64;
+
+            let corrupt: Vec<_> = blocks
+                .into_iter()
+                .filter(|(_, intact)| !intact)
+                .map(|(id, _)| id)
+                .collect();
+
+            if !corrupt.is_empty() {
+                let mut tx = self.begin_write_low_priority().await?;
+
+                for id in &corrupt {
+                    tracing::warn!(?id, "Corrupt block");
+                    tx.remove_block(id).await?;
+                }
+
+                tx.commit().await?;
+                repair.corrupt.extend(corrupt);
+            }
+
+            lower_bound = Some(last);
+        }
+
+        let mut tx = self.begin_write().await?;
+
+        repair.lost = leaf_node::load_lost_block_ids(tx.db()).await?;
+
+        for id in &repair.lost {
+            tracing::warn!(?id, "Lost block");
+            tx.remove_block(id).await?;
+        }
+
+        let writer_ids: Vec<_> = if repair.corrupt.is_empty() && repair.lost.is_empty() {
+            Vec::new()
+        } else {
+            tx.load_writer_ids().try_collect().await?
+        };
+
+        tx.commit().await?;
+
+        for writer_id in writer_ids {
+            self.client_reload_index_tx.insert(&writer_id);
+        }
+
+        Ok(repair)
+    }
+
+    /// Recomputes the summaries of all the index nodes, to reflect any nodes or blocks that were
+    /// lost without going through the regular removal (e.g., when recovering a damaged database).
+    /// Use together with `repair_blocks` which marks the lost blocks as missing.
+    pub async fn recompute_summaries(&self) -> Result<(), Error> {
+        let mut tx = self.begin_write().await?;
+        let (db, cache) = tx.db_and_cache();
+        index::recompute_all_summaries(db, cache).await?;
+        tx.commit().await
+    }
+
     pub async fn debug_print_root_node(&self, printer: DebugPrinter) {
         match self.acquire_read().await {
             Ok(mut reader) => root_node::debug_print(reader.db(), printer).await,
@@ -276,11 +485,61 @@ impl Store {
     }
 }
 
+/// Copies the index and the `retained` blocks from the `src` database into the `main` database of
+/// the export connection (see `db::export`). The leaf nodes of the blocks that weren't copied are
+/// marked as missing. The summaries of the copied nodes are not updated, call
+/// `Store::recompute_summaries` on the export afterwards.
+pub(crate) async fn export_index(
+    conn: &mut db::Connection,
+    retained: &HashSet<BlockId>,
+) -> Result<(), Error> {
+    for table in [
+        "snapshot_root_nodes",
+        "snapshot_inner_nodes",
+        "snapshot_leaf_nodes",
+    ] {
+        sqlx::query(&format!(
+            "INSERT INTO main.{table} SELECT * FROM src.{table}"
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for id in retained {
+        sqlx::query("INSERT OR IGNORE INTO main.blocks SELECT * FROM src.blocks WHERE id = ?")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    sqlx::query(
+        "UPDATE main.snapshot_leaf_nodes SET block_presence = ?
+         WHERE block_id NOT IN (SELECT id FROM main.blocks)",
+    )
+    .bind(SingleBlockPresence::Missing)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Result of `Store::repair_blocks`.
+#[derive(Default)]
+pub(crate) struct BlockRepair {
+    /// Number of the stored blocks that have been verified.
+    pub verified: u64,
+    /// Blocks whose content didn't match their id.
+    pub corrupt: Vec<BlockId>,
+    /// Blocks that were referenced as present but weren't actually stored.
+    pub lost: Vec<BlockId>,
+}
+
 /// Read-only operations. This is an up-to-date view of the data.
 pub(crate) struct Reader {
     inner: Handle,
     cache: CacheTransaction,
     block_expiration_tracker: Option<Arc<BlockExpirationTracker>>,
+    access_log: Arc<AccessLog>,
 }
 
 impl Reader {
@@ -295,10 +554,18 @@ impl Reader {
         content: &mut BlockContent,
     ) -> Result<BlockNonce, Error> {
         let result = block::read(self.db(), id, content).await;
+        let is_missing = matches!(result, Err(Error::BlockNotFound));
 
         if let Some(expiration_tracker) = &self.block_expiration_tracker {
-            let is_missing = matches!(result, Err(Error::BlockNotFound));
-            expiration_tracker.handle_block_update(id, is_missing);
+            // `content` has the stored length now (see `block::read`).
+            expiration_tracker.handle_block_update(id, (!is_missing).then_some(content.len()));
+        }
+
+        // Other errors tell nothing about the block so they are not logged.
+        match result {
+            Ok(_) => self.access_log.record(id, BlockAccessKind::Read),
+            Err(Error::BlockNotFound) => self.access_log.record(id, BlockAccessKind::ReadMissing),
+            Err(_) => (),
         }
 
         result
@@ -328,6 +595,16 @@ impl Reader {
         leaf_node::count_block_ids(self.db()).await
     }
 
+    /// Returns the ids of the blocks referenced from the given snapshot together with their stored
+    /// length (less than `BLOCK_SIZE` if compressed, zero if not stored). A block id appears as
+    /// many times as it is referenced.
+    pub fn load_blocks_in_snapshot<'a>(
+        &'a mut self,
+        root_node: &RootNode,
+    ) -> impl Stream<Item = Result<(BlockId, u64), Error>> + 'a {
+        leaf_node::load_blocks_in_snapshot(self.db(), root_node)
+    }
+
     #[cfg(test)]
     pub async fn count_leaf_nodes_in_branch(
         &mut self,
@@ -459,6 +736,18 @@ impl ReadTransaction {
         root_node: &RootNode,
         encoded_locator: &Hash,
     ) -> Result<BlockId, Error> {
+        self.find_leaf_node_at(root_node, encoded_locator)
+            .await
+            .map(|node| node.block_id)
+    }
+
+    /// Finds the leaf node (containing the block id and the block presence) corresponding to the
+    /// given locator at the given snapshot.
+    pub async fn find_leaf_node_at(
+        &mut self,
+        root_node: &RootNode,
+        encoded_locator: &Hash,
+    ) -> Result<LeafNode, Error> {
         // TODO: On cache miss load only the one node we actually need per layer.
 
         let mut parent_hash = root_node.proof.hash;
@@ -475,7 +764,7 @@ impl ReadTransaction {
         self.load_leaf_nodes_with_cache(&parent_hash)
             .await?
             .get(encoded_locator)
-            .map(|node| node.block_id)
+            .copied()
             .ok_or(Error::LocatorNotFound)
     }
 
@@ -516,6 +805,8 @@ impl DerefMut for ReadTransaction {
 pub(crate) struct WriteTransaction {
     inner: ReadTransaction,
     untrack_blocks: Option<block_expiration_tracker::UntrackTransaction>,
+    // Block accesses to record in the access log once the transaction is committed.
+    committed_accesses: Vec<(BlockId, BlockAccessKind)>,
 }
 
 impl WriteTransaction {
@@ -540,6 +831,7 @@ impl WriteTransaction {
                         },
                 },
             untrack_blocks,
+            ..
         } = self;
 
         if let Some(tracker) = block_expiration_tracker {
@@ -626,6 +918,7 @@ impl WriteTransaction {
 
         Ok(InnerNodeReceiveStatus {
             new_approved: status.new_approved,
+            quota_exceeded: status.quota_exceeded,
             request_children,
         })
     }
@@ -654,6 +947,7 @@ impl WriteTransaction {
         Ok(LeafNodeReceiveStatus {
             old_approved: status.old_approved,
             new_approved: status.new_approved,
+            quota_exceeded: status.quota_exceeded,
             request_blocks,
         })
     }
@@ -666,7 +960,11 @@ impl WriteTransaction {
         let result = block::receive(db, cache, block).await;
 
         if let Some(tracker) = &self.block_expiration_tracker {
-            tracker.handle_block_update(&block.id, false);
+            tracker.handle_block_update(&block.id, Some(block.content.len()));
+        }
+
+        if result.is_ok() {
+            self.record_access_on_commit(&block.id, BlockAccessKind::Receive);
         }
 
         result
@@ -690,6 +988,8 @@ impl WriteTransaction {
     }
 
     pub async fn commit(self) -> Result<(), Error> {
+        let access_log = self.inner.inner.access_log;
+        let accesses = self.committed_accesses;
         let inner = self.inner.inner.inner.into_write();
         let cache = self.inner.inner.cache;
 
@@ -721,6 +1021,10 @@ impl WriteTransaction {
             }
         };
 
+        for (id, kind) in &accesses {
+            access_log.record(id, *kind);
+        }
+
         Ok(())
     }
 
@@ -733,10 +1037,12 @@ impl WriteTransaction {
         F: FnOnce() -> R + Send + 'static,
         R: Send + 'static,
     {
+        let access_log = self.inner.inner.access_log;
+        let accesses = self.committed_accesses;
         let inner = self.inner.inner.inner.into_write();
         let cache = self.inner.inner.cache;
 
-        Ok(match (cache.is_dirty(), self.untrack_blocks) {
+        let output = match (cache.is_dirty(), self.untrack_blocks) {
             (true, Some(untrack)) => {
                 inner
                     .commit_and_then(move || {
@@ -763,11 +1069,24 @@ impl WriteTransaction {
                     .await?
             }
             (false, None) => inner.commit_and_then(f).await?,
-        })
+        };
+
+        for (id, kind) in &accesses {
+            access_log.record(id, *kind);
+        }
+
+        Ok(output)
 
         //Ok(inner.commit_and_then(then).await?)
     }
 
+    // Records the block access in the access log, but only after this transaction is committed.
+    fn record_access_on_commit(&mut self, id: &BlockId, kind: BlockAccessKind) {
+        if self.inner.inner.access_log.is_enabled() {
+            self.committed_accesses.push((*id, kind));
+        }
+    }
+
     // Access the underlying database transaction.
     fn db(&mut self) -> &mut db::WriteTransaction {
         self.inner.inner.inner.as_write()
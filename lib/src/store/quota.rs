@@ -1,5 +1,7 @@
-use super::{error::Error as StoreError, root_node};
-use crate::{crypto::Hash, db, future::try_collect_into, storage_size::StorageSize, versioned};
+use super::{block, error::Error as StoreError, root_node};
+use crate::{
+    crypto::Hash, db, future::try_collect_into, repository, storage_size::StorageSize, versioned,
+};
 use sqlx::{QueryBuilder, Row};
 use thiserror::Error;
 
@@ -27,6 +29,29 @@ pub(super) async fn check(
     }
 }
 
+/// Check whether the repository would be within its quota (if any) after writing `new_blocks`
+/// blocks into it. Used for local writes. Unlike `check`, this counts all the stored blocks which
+/// is cheap but only approximate: blocks that become unreferenced by the write (and so are going
+/// to be eventually removed) are still counted. It's also what `Repository::size` reports.
+pub(super) async fn check_local(
+    conn: &mut db::Connection,
+    new_blocks: usize,
+) -> Result<(), StoreError> {
+    let Some(quota) = repository::quota::get(conn).await? else {
+        return Ok(());
+    };
+
+    let limit = StorageSize::from_bytes(quota);
+    let count = block::count(conn).await?;
+    let actual = StorageSize::from_blocks(count.saturating_add(new_blocks as u64));
+
+    if actual <= limit {
+        Ok(())
+    } else {
+        Err(StoreError::QuotaExceeded { limit, actual })
+    }
+}
+
 #[derive(Debug, Error)]
 pub(super) enum QuotaError {
     #[error("quota exceeded")]
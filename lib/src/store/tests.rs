@@ -4,6 +4,7 @@ use crate::{
     protocol::{Bump, Locator, SingleBlockPresence, EMPTY_INNER_HASH},
     test_utils,
 };
+use assert_matches::assert_matches;
 use proptest::{arbitrary::any, collection::vec};
 use rand::{
     rngs::StdRng,
@@ -14,6 +15,7 @@ use sqlx::Row;
 use std::collections::BTreeMap;
 use tempfile::TempDir;
 use test_strategy::{proptest, Arbitrary};
+use tokio::{sync::mpsc, task};
 
 #[tokio::test(flavor = "multi_thread")]
 async fn link_and_find_block() {
@@ -272,7 +274,10 @@ async fn fallback() {
         .load_root_node(&branch_0_id, RootNodeFilter::Any)
         .await
         .unwrap();
-    store.remove_outdated_snapshots(&root_node).await.unwrap();
+    store
+        .remove_outdated_snapshots(&root_node, None)
+        .await
+        .unwrap();
 
     let mut tx = store.begin_read().await.unwrap();
 
@@ -422,7 +427,10 @@ async fn prune_case(ops: Vec<PruneTestOp>, rng_seed: u64) {
                     Err(error) => panic!("unexpected error: {:?}", error),
                 };
 
-                store.remove_outdated_snapshots(&root_node).await.unwrap();
+                store
+                    .remove_outdated_snapshots(&root_node, None)
+                    .await
+                    .unwrap();
             }
         }
 
@@ -445,6 +453,163 @@ async fn prune_case(ops: Vec<PruneTestOp>, rng_seed: u64) {
     }
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn with_write_retry() {
+    let (_base_dir, store) = setup().await;
+    let branch_id = PublicKey::random();
+    let write_keys = Keypair::random();
+    let block: Block = rand::random();
+
+    // Transient errors are retried.
+    let mut attempts = 0;
+    store
+        .with_write_retry(3, |mut tx| {
+            attempts += 1;
+            let attempt = attempts;
+
+            let mut changeset = Changeset::new();
+            changeset.link_block(rand::random(), block.id, SingleBlockPresence::Present);
+            changeset.write_block(block.clone());
+
+            let branch_id = &branch_id;
+            let write_keys = &write_keys;
+
+            async move {
+                if attempt < 3 {
+                    return Err(Error::Db(sqlx::Error::PoolTimedOut));
+                }
+
+                changeset.apply(&mut tx, branch_id, write_keys).await?;
+                tx.commit().await
+            }
+        })
+        .await
+        .unwrap();
+    assert_eq!(attempts, 3);
+    assert_eq!(store.count_blocks().await.unwrap(), 1);
+
+    // ...but only up to the limit.
+    let mut attempts = 0;
+    let result: Result<(), _> = store
+        .with_write_retry(2, |_tx| {
+            attempts += 1;
+            async { Err(Error::Db(sqlx::Error::PoolTimedOut)) }
+        })
+        .await;
+    assert_matches!(result, Err(Error::Db(sqlx::Error::PoolTimedOut)));
+    assert_eq!(attempts, 3);
+
+    // Other errors are not retried.
+    let mut attempts = 0;
+    let result: Result<(), _> = store
+        .with_write_retry(2, |_tx| {
+            attempts += 1;
+            async { Err(Error::BranchNotFound) }
+        })
+        .await;
+    assert_matches!(result, Err(Error::BranchNotFound));
+    assert_eq!(attempts, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_priority() {
+    let (_base_dir, store) = setup().await;
+    let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+
+    // Hold the write transaction so the other writers have to wait for it.
+    let tx = store.begin_write().await.unwrap();
+
+    // The low-priority writer starts waiting first...
+    let low = task::spawn({
+        let store = store.clone();
+        let order_tx = order_tx.clone();
+
+        async move {
+            let _tx = store.begin_write_low_priority().await.unwrap();
+            order_tx.send("low").unwrap();
+        }
+    });
+
+    time::sleep(Duration::from_millis(100)).await;
+
+    // ...but the high-priority one goes first.
+    let high = task::spawn({
+        let store = store.clone();
+
+        async move {
+            let _tx = store.begin_write().await.unwrap();
+            order_tx.send("high").unwrap();
+        }
+    });
+
+    // Wait until both are queued behind `tx`.
+    time::timeout(Duration::from_secs(5), async {
+        while store.db().write_queue_depth() < 3 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    drop(tx);
+
+    low.await.unwrap();
+    high.await.unwrap();
+
+    assert_eq!(order_rx.recv().await, Some("high"));
+    assert_eq!(order_rx.recv().await, Some("low"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn block_access_log_records_writes_on_commit() {
+    let (_base_dir, store) = setup().await;
+    store.set_block_access_log_enabled(true);
+
+    let read_key = SecretKey::random();
+    let write_keys = Keypair::random();
+    let branch_id = PublicKey::random();
+
+    let write = |block: Block| {
+        let mut changeset = Changeset::new();
+        changeset.link_block(
+            Locator::head(rand::random()).encode(&read_key),
+            block.id,
+            SingleBlockPresence::Present,
+        );
+        changeset.write_block(block);
+        changeset
+    };
+
+    // Rolled back write is not logged.
+    let mut tx = store.begin_write().await.unwrap();
+    write(rand::random())
+        .apply(&mut tx, &branch_id, &write_keys)
+        .await
+        .unwrap();
+    drop(tx);
+
+    assert!(store.recent_block_accesses(usize::MAX).is_empty());
+
+    // Committed one is.
+    let block: Block = rand::random();
+    let block_id = block.id;
+
+    let mut tx = store.begin_write().await.unwrap();
+    write(block)
+        .apply(&mut tx, &branch_id, &write_keys)
+        .await
+        .unwrap();
+
+    assert!(store.recent_block_accesses(usize::MAX).is_empty());
+
+    tx.commit().await.unwrap();
+
+    let accesses = store.recent_block_accesses(usize::MAX);
+    assert_eq!(accesses.len(), 1);
+    assert_eq!(accesses[0].block_id, block_id);
+    assert_eq!(accesses[0].kind, BlockAccessKind::Write);
+}
+
 async fn setup() -> (TempDir, Store) {
     let (temp_dir, pool) = db::create_temp().await.unwrap();
     let store = Store::new(pool);
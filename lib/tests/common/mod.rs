@@ -300,7 +300,7 @@ pub(crate) mod actor {
         network: &Network,
     ) -> (Repository, Registration) {
         let repo = create_repo(name).await;
-        let reg = network.register(repo.handle()).await;
+        let reg = network.register(repo.handle()).await.unwrap();
 
         (repo, reg)
     }
@@ -482,7 +482,8 @@ pub(crate) async fn wait(rx: &mut broadcast::Receiver<Event>) {
                         payload:
                             Payload::BranchChanged(_)
                             | Payload::BlockReceived { .. }
-                            | Payload::MaintenanceCompleted,
+                            | Payload::MaintenanceCompleted
+                            | Payload::QuotaExceeded { .. },
                         ..
                     })
                     | Err(RecvError::Lagged(_)) => return,
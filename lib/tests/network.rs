@@ -6,10 +6,15 @@
 #[macro_use]
 mod common;
 
-use self::common::{actor, Env, Proto, DEFAULT_REPO, TEST_TIMEOUT};
-use ouisync::network::{Network, PeerState};
-use std::sync::Arc;
-use tokio::{sync::Barrier, time};
+use self::common::{actor, expect_file_content, Env, Proto, DEFAULT_REPO, TEST_TIMEOUT};
+use assert_matches::assert_matches;
+use futures_util::StreamExt;
+use ouisync::{
+    network::{ConnectionDirection, Network, NetworkEvent, PeerSource, PeerState},
+    Error, PeerAddr,
+};
+use std::{net::Ipv4Addr, pin::pin, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::Barrier, time};
 
 // This test requires QUIC which is not yet supported in simulation
 #[test]
@@ -174,6 +179,53 @@ fn dht_toggle() {
     });
 }
 
+#[test]
+fn dht_announce_toggle() {
+    let mut env = Env::new();
+    let proto = Proto::Quic;
+
+    env.actor("eric", async move {
+        let network = actor::create_network(proto).await;
+        let (repo, reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+        assert!(reg.is_dht_announce_enabled());
+
+        reg.set_dht_enabled(true).await;
+        reg.set_dht_announce_enabled(false).await;
+        assert!(!reg.is_dht_announce_enabled());
+        assert!(reg.is_dht_enabled());
+
+        // The setting is persisted and applied on the next registration.
+        drop(reg);
+        let reg = network.register(repo.handle()).await.unwrap();
+        assert!(reg.is_dht_enabled());
+        assert!(!reg.is_dht_announce_enabled());
+
+        reg.set_dht_announce_enabled(true).await;
+        assert!(reg.is_dht_announce_enabled());
+    });
+}
+
+#[test]
+fn dht_find_peers_now() {
+    let mut env = Env::new();
+    let proto = Proto::Quic;
+
+    env.actor("eric", async move {
+        let network = actor::create_network(proto).await;
+        let (_repo, reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+        // DHT disabled
+        assert!(!reg.find_peers_now());
+
+        reg.set_dht_enabled(true).await;
+        assert!(reg.find_peers_now());
+
+        // Rate limited
+        assert!(!reg.find_peers_now());
+    });
+}
+
 #[test]
 fn local_discovery() {
     let mut env = Env::new();
@@ -203,6 +255,88 @@ fn local_discovery() {
     }
 }
 
+#[test]
+fn local_discovery_after_network_change() {
+    let mut env = Env::new();
+    let proto = Proto::Quic;
+    let barrier = Arc::new(Barrier::new(2));
+
+    for (src, dst) in [("alice", "bob"), ("bob", "alice")] {
+        let barrier = barrier.clone();
+
+        env.actor(src, async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+            reg.set_dht_enabled(true).await;
+
+            network.set_local_discovery_enabled(true);
+            network.set_network_change_watch_enabled(true);
+            assert!(network.is_network_change_watch_enabled());
+
+            // Restarts local discovery (and wakes up the DHT lookup) without disabling anything.
+            network.handle_network_change();
+            assert!(network.is_local_discovery_enabled());
+            assert!(reg.is_dht_enabled());
+
+            let dst_port = actor::lookup_addr(dst).await.port();
+            expect_knows_port(&network, dst_port).await;
+
+            network.set_network_change_watch_enabled(false);
+            assert!(!network.is_network_change_watch_enabled());
+
+            barrier.wait().await;
+        });
+    }
+}
+
+#[test]
+fn rebind_after_network_change() {
+    let mut env = Env::new();
+
+    env.actor("alice", async move {
+        let network = actor::create_unbound_network();
+        let mut events = pin!(network.subscribe());
+
+        // Listeners bound to unspecified addresses are kept.
+        network
+            .bind(&[PeerAddr::Quic((Ipv4Addr::UNSPECIFIED, 0).into())])
+            .await;
+        let addr = network.listener_local_addrs()[0];
+        assert_eq!(
+            next_event(&mut events).await,
+            NetworkEvent::ListenerBound(addr)
+        );
+
+        network.handle_network_change();
+        assert!(
+            time::timeout(Duration::from_millis(500), next_event(&mut events))
+                .await
+                .is_err()
+        );
+        assert_eq!(network.listener_local_addrs(), [addr]);
+
+        // Listeners bound to specific ones are rebound.
+        network
+            .bind(&[PeerAddr::Quic((Ipv4Addr::LOCALHOST, 0).into())])
+            .await;
+        let addr = network.listener_local_addrs()[0];
+        assert_eq!(
+            next_event(&mut events).await,
+            NetworkEvent::ListenerBound(addr)
+        );
+
+        network.handle_network_change();
+
+        match next_event(&mut events).await {
+            NetworkEvent::ListenerBound(new_addr) => {
+                assert!(new_addr.is_quic());
+                assert_eq!(new_addr.ip(), Ipv4Addr::LOCALHOST);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+    });
+}
+
 #[test]
 fn add_peer_before_bind() {
     let mut env = Env::new();
@@ -236,6 +370,598 @@ fn add_peer_before_bind() {
     });
 }
 
+#[test]
+fn list_and_disconnect_connections() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+            let (repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+            expect_peer_active(&network, "alice").await;
+
+            // The connection becomes active slightly before the repositories get linked.
+            let connection = time::timeout(*TEST_TIMEOUT, async {
+                loop {
+                    let mut connections = network.connections();
+                    assert_eq!(connections.len(), 1);
+
+                    if !connections[0].repositories.is_empty() {
+                        break connections.remove(0);
+                    }
+
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(connection.addr, peer_addr);
+            assert_eq!(connection.direction, ConnectionDirection::Outgoing);
+            assert_eq!(connection.source, PeerSource::UserProvided);
+            assert_eq!(connection.repositories, [*repo.secrets().id()]);
+            assert_eq!(
+                connection.protocol_version,
+                Some(network.current_protocol_version())
+            );
+
+            // Both peers run on the same machine so their clocks should agree.
+            let clock_skew = connection.clock_skew.unwrap();
+            assert!(clock_skew.magnitude() < Duration::from_secs(1));
+
+            assert!(network.disconnect(&connection.runtime_id).await);
+            assert!(!network.disconnect(&network.this_runtime_id()).await);
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn clock_skew_detection_disabled() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            network.set_clock_skew_detection_enabled(false);
+
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+            expect_peer_active(&network, "alice").await;
+
+            // The peer connects fine but without sharing its time.
+            let connections = network.connections();
+            assert_eq!(connections.len(), 1);
+            assert_eq!(connections[0].clock_skew, None);
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn prefer_newest_connection() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            assert!(!network.is_prefer_newest_connection_enabled());
+            network.set_prefer_newest_connection_enabled(true);
+
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let peer_addr = actor::lookup_addr("alice").await;
+
+            // Simulate bob rapidly reconnecting to alice while the previous connections still look
+            // alive to her. All the networks created by the same actor share the same runtime id.
+            let mut networks = Vec::new();
+
+            for _ in 0..3 {
+                let network = actor::create_network(proto).await;
+                network.add_user_provided_peer(&peer_addr);
+                expect_peer_active(&network, "alice").await;
+
+                // Prevent reconnecting after alice closes the connection.
+                network.remove_user_provided_peer(&peer_addr);
+
+                networks.push(network);
+            }
+
+            // Only the newest connection survives.
+            time::timeout(*TEST_TIMEOUT, async {
+                loop {
+                    let counts: Vec<_> = networks
+                        .iter()
+                        .map(|network| network.connections().len())
+                        .collect();
+
+                    if counts == [0, 0, 1] {
+                        break;
+                    }
+
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn connection_idle_timeout() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            assert_eq!(network.connection_idle_timeout(), None);
+            network.set_connection_idle_timeout(Some(Duration::from_secs(2)));
+
+            let (repo, reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            let peer_addr = actor::lookup_addr("bob").await;
+            network.add_user_provided_peer(&peer_addr);
+
+            wait_for_linked_connection(&network).await;
+
+            // The connection is not idle while the repositories are linked.
+            time::sleep(Duration::from_secs(4)).await;
+            assert_eq!(network.connections().len(), 1);
+
+            // Deregister the repository, the connection becomes idle and gets closed.
+            drop(reg);
+
+            time::timeout(*TEST_TIMEOUT, async {
+                while !network.connections().is_empty() {
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            // The peer is not reconnected while nothing is registered, even though it's still
+            // among the user provided peers.
+            time::sleep(Duration::from_secs(4)).await;
+            assert!(network.connections().is_empty());
+
+            // Registering a repository again reconnects the peer.
+            let _reg = network.register(repo.handle()).await.unwrap();
+            wait_for_linked_connection(&network).await;
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn linked_peers() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+            // Only bob has this one so it's not linked with anyone.
+            let (_other_repo, other_reg) = actor::create_linked_repo("other", &network).await;
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+            expect_peer_active(&network, "alice").await;
+
+            let peers = time::timeout(*TEST_TIMEOUT, async {
+                loop {
+                    let peers = reg.linked_peers();
+
+                    if !peers.is_empty() {
+                        break peers;
+                    }
+
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(peers.len(), 1);
+            assert_eq!(peers[0].addr, peer_addr);
+            assert_eq!(peers[0].runtime_id, network.connections()[0].runtime_id);
+
+            assert!(other_reg.linked_peers().is_empty());
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn status_with_network() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+            let (repo, reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+            reg.set_pex_enabled(true).await;
+
+            // Without the network the network related fields are left at the defaults.
+            let status = repo.status().await.unwrap();
+            assert_eq!(status.linked_peer_count, 0);
+            assert!(!status.pex_enabled);
+
+            network.add_user_provided_peer(&actor::lookup_addr("alice").await);
+            expect_peer_active(&network, "alice").await;
+
+            let status = time::timeout(*TEST_TIMEOUT, async {
+                loop {
+                    let status = repo.status_with_network(&reg).await.unwrap();
+
+                    if status.linked_peer_count > 0 {
+                        break status;
+                    }
+
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(status.linked_peer_count, 1);
+            assert!(status.pex_enabled);
+            assert!(!status.dht_enabled);
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn known_good_peers() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            let (_repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+            let (repo, reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+
+            time::timeout(*TEST_TIMEOUT, async {
+                while reg.known_good_peers().is_empty() {
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(reg.known_good_peers(), [peer_addr]);
+
+            // The addresses are not stored in plain text.
+            assert_eq!(
+                repo.metadata().get::<String>("known_peers").await.unwrap(),
+                None
+            );
+
+            // Restart the network. The known peer is reconnected to without being added again.
+            drop(reg);
+            network.shutdown().await;
+            drop(network);
+
+            let network = actor::create_network(proto).await;
+            let reg = network.register(repo.handle()).await.unwrap();
+            assert_eq!(reg.known_good_peers(), [peer_addr]);
+
+            expect_peer_active(&network, "alice").await;
+            assert_eq!(
+                network.peer_info(peer_addr).map(|info| info.source),
+                Some(PeerSource::KnownPeer)
+            );
+
+            reg.set_known_good_peers_limit(0).await;
+            assert!(reg.known_good_peers().is_empty());
+            assert_eq!(reg.known_good_peers_limit(), 0);
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn registration_limit() {
+    let mut env = Env::new();
+
+    env.actor("alice", async move {
+        let network = actor::create_unbound_network();
+        network.set_max_registrations(Some(1));
+
+        let repo_a = actor::create_repo("a").await;
+        let repo_b = actor::create_repo("b").await;
+
+        let reg_a = network.register(repo_a.handle()).await.unwrap();
+        assert_eq!(network.registered_count(), 1);
+
+        assert_matches!(
+            network.register(repo_b.handle()).await,
+            Err(Error::RegistrationLimitExceeded)
+        );
+        assert_eq!(network.registered_count(), 1);
+
+        // Deregistering frees the slot.
+        drop(reg_a);
+        assert_eq!(network.registered_count(), 0);
+
+        let _reg_b = network.register(repo_b.handle()).await.unwrap();
+        assert_eq!(network.registered_count(), 1);
+    });
+}
+
+#[test]
+fn multiple_registrations() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let network = actor::create_network(proto).await;
+            let (repo, _reg) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+
+            barrier.wait().await;
+
+            // Bob is still linked after dropping one of his registrations.
+            repo.write_file("test.txt", b"hello").await.unwrap();
+
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+            let (repo, reg0) = actor::create_linked_repo(DEFAULT_REPO, &network).await;
+            let reg1 = network.register(repo.handle()).await.unwrap();
+            assert_eq!(network.registered_count(), 2);
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+            expect_peer_active(&network, "alice").await;
+
+            time::timeout(*TEST_TIMEOUT, async {
+                while reg1.linked_peers().is_empty() {
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            // The repository is listed only once.
+            assert_eq!(
+                network.connections()[0].repositories,
+                [*repo.secrets().id()]
+            );
+
+            // Dropping one registration doesn't unlink the repository.
+            drop(reg0);
+            assert_eq!(reg1.linked_peers().len(), 1);
+
+            barrier.wait().await;
+
+            expect_file_content(&repo, "test.txt", b"hello").await;
+
+            // Dropping the last one does.
+            drop(reg1);
+            assert!(network.connections()[0].repositories.is_empty());
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn bind_fallback() {
+    let mut env = Env::new();
+
+    env.actor("alice", async move {
+        let network = actor::create_unbound_network();
+
+        // Occupy a port so the network can't bind to it.
+        let taken = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let taken_addr = PeerAddr::Tcp(taken.local_addr().unwrap());
+        let fallback_addr = PeerAddr::Tcp((Ipv4Addr::LOCALHOST, 0).into());
+
+        network.bind(&[taken_addr, fallback_addr]).await;
+
+        let addrs = network.listener_local_addrs();
+        assert_eq!(addrs.len(), 1);
+        assert_ne!(addrs[0].port(), taken_addr.port());
+    });
+}
+
+#[test]
+fn bind_all() {
+    let mut env = Env::new();
+
+    env.actor("alice", async move {
+        let network = actor::create_unbound_network();
+
+        let taken = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let taken_addr = PeerAddr::Tcp(taken.local_addr().unwrap());
+        let addr = PeerAddr::Tcp((Ipv4Addr::LOCALHOST, 0).into());
+
+        // The taken address is skipped, the rest are all bound.
+        network.bind_all(&[addr, taken_addr, addr]).await;
+
+        let addrs = network.listener_local_addrs();
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0], addrs[1]);
+        assert!(addrs.iter().all(|addr| addr.port() != taken_addr.port()));
+
+        // Regular bind listens only on one.
+        network.bind(&[addr]).await;
+        assert_eq!(network.listener_local_addrs().len(), 1);
+    });
+}
+
+#[test]
+fn network_events() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let _network = actor::create_network(proto).await;
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_unbound_network();
+            let mut events = pin!(network.subscribe());
+
+            actor::bind(&network, proto).await;
+
+            let bind_addr = network.listener_local_addrs()[0];
+            assert_eq!(
+                next_event(&mut events).await,
+                NetworkEvent::ListenerBound(bind_addr)
+            );
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+
+            let runtime_id = match next_event(&mut events).await {
+                NetworkEvent::PeerConnected {
+                    addr,
+                    runtime_id,
+                    source,
+                } => {
+                    assert_eq!(addr, peer_addr);
+                    assert_eq!(source, PeerSource::UserProvided);
+                    runtime_id
+                }
+                event => panic!("unexpected event {event:?}"),
+            };
+
+            assert!(network.disconnect(&runtime_id).await);
+
+            assert_eq!(
+                next_event(&mut events).await,
+                NetworkEvent::PeerDisconnected {
+                    addr: peer_addr,
+                    runtime_id,
+                    source: PeerSource::UserProvided,
+                }
+            );
+
+            barrier.wait().await;
+        }
+    });
+}
+
 async fn expect_peer_known(network: &Network, peer_name: &str) {
     expect_peer_state(network, peer_name, |_| true).await
 }
@@ -297,3 +1023,35 @@ async fn expect_peer_not_known(network: &Network, peer_name: &str) {
         panic!("unexpected known peer {peer_name}: {info:?}");
     }
 }
+
+// Returns the next non-DHT network event.
+async fn next_event<S>(events: &mut S) -> NetworkEvent
+where
+    S: futures_util::Stream<Item = NetworkEvent> + Unpin,
+{
+    time::timeout(*TEST_TIMEOUT, async {
+        loop {
+            match events.next().await.unwrap() {
+                NetworkEvent::DhtBootstrapped(_) | NetworkEvent::DhtBootstrapFailed(_) => continue,
+                event => break event,
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+// Waits until at least one connection is linked with a repository.
+async fn wait_for_linked_connection(network: &Network) {
+    time::timeout(*TEST_TIMEOUT, async {
+        while network
+            .connections()
+            .iter()
+            .all(|connection| connection.repositories.is_empty())
+        {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
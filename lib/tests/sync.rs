@@ -231,6 +231,46 @@ fn sync_dump_case(dump: dump::Directory) {
     });
 }
 
+#[test]
+fn sync_with_message_compression() {
+    let mut env = Env::new();
+    let (tx, rx) = sync_watch::channel();
+
+    let dump = (0..10)
+        .map(|index| (format!("file-{index}.dat"), common::random_bytes(64 * 1024)))
+        .fold(dump::Directory::new(), |dump, (name, content)| {
+            dump.add(name, content)
+        });
+    let dump = Arc::new(dump);
+
+    env.actor("writer", {
+        let dump = dump.clone();
+        async move {
+            let (network, repo, _reg) = actor::setup().await;
+            network.set_message_compression_enabled(true);
+
+            dump::load(&repo, &dump).await;
+
+            tx.run(&repo).await;
+        }
+    });
+
+    env.actor("reader", {
+        async move {
+            let (network, repo, _reg) = actor::setup().await;
+            network.set_message_compression_enabled(true);
+            assert!(network.is_message_compression_enabled());
+
+            network.add_user_provided_peer(&actor::lookup_addr("writer").await);
+
+            rx.run(&repo).await;
+
+            let actual_dump = dump::save(&repo).await;
+            similar_asserts::assert_eq!(actual_dump, *dump);
+        }
+    });
+}
+
 #[test]
 fn relink_repository() {
     let mut env = Env::new();
@@ -279,7 +319,7 @@ fn relink_repository() {
         writer_rx.recv().await;
 
         // Relink the repo
-        let _reg = network.register(repo.handle()).await;
+        let _reg = network.register(repo.handle()).await.unwrap();
 
         // Wait until the file is updated
         common::expect_file_content(&repo, "test.txt", b"second").await;
@@ -320,6 +360,69 @@ fn remove_remote_file() {
     });
 }
 
+#[test]
+fn selective_sync() {
+    let mut env = Env::new();
+    let (tx, mut rx) = mpsc::channel(1);
+
+    let photo_content = common::random_bytes(2 * BLOCK_SIZE);
+    let doc_content = common::random_bytes(2 * BLOCK_SIZE);
+
+    env.actor("writer", {
+        let photo_content = photo_content.clone();
+        let doc_content = doc_content.clone();
+
+        async move {
+            let (_network, repo, _reg) = actor::setup().await;
+
+            repo.create_directory("photos").await.unwrap();
+            repo.create_directory("docs").await.unwrap();
+
+            let mut file = repo.create_file("photos/a.jpg").await.unwrap();
+            file.write_all(&photo_content).await.unwrap();
+            file.flush().await.unwrap();
+
+            let mut file = repo.create_file("docs/b.txt").await.unwrap();
+            file.write_all(&doc_content).await.unwrap();
+            file.flush().await.unwrap();
+
+            rx.recv().await;
+        }
+    });
+
+    env.actor("reader", async move {
+        let (network, repo, reg) = actor::setup().await;
+        reg.set_sync_filter(vec!["/photos".to_owned()])
+            .await
+            .unwrap();
+        assert_eq!(reg.sync_filter(), ["/photos"]);
+
+        let peer_addr = actor::lookup_addr("writer").await;
+        network.add_user_provided_peer(&peer_addr);
+
+        common::expect_file_content(&repo, "photos/a.jpg", &photo_content).await;
+
+        // The excluded file is listed but its content is not downloaded.
+        common::eventually(&repo, || async {
+            match repo.open_directory("docs").await {
+                Ok(dir) => dir.lookup_unique("b.txt").is_ok(),
+                Err(_) => false,
+            }
+        })
+        .await;
+        let progress = repo.sync_progress().await.unwrap();
+        assert!(progress.value < progress.total);
+
+        // Removing the filter pulls in the rest.
+        reg.set_sync_filter(vec![]).await.unwrap();
+        assert!(reg.sync_filter().is_empty());
+
+        common::expect_file_content(&repo, "docs/b.txt", &doc_content).await;
+
+        tx.send(()).await.unwrap();
+    });
+}
+
 #[test]
 fn relay_write() {
     let file_size = LARGE_SIZE;
@@ -346,7 +449,7 @@ fn relay_case(proto: Proto, file_size: usize, relay_access_mode: AccessMode) {
         async move {
             let network = actor::create_network(proto).await;
             let repo = actor::create_repo_with_mode(DEFAULT_REPO, relay_access_mode).await;
-            let _reg = network.register(repo.handle()).await;
+            let _reg = network.register(repo.handle()).await.unwrap();
 
             rx.recv().await.unwrap();
         }
@@ -552,7 +655,7 @@ fn recreate_local_branch() {
         repo.set_access_mode(AccessMode::Read, None).await.unwrap();
 
         // 4. Establish link
-        let reg = network.register(repo.handle()).await;
+        let reg = network.register(repo.handle()).await.unwrap();
 
         // 7. Sync with Bob. Afterwards our local branch will become outdated compared to Bob's
         common::expect_file_content(&repo, "foo.txt", b"hello from Alice\nhello from Bob\n").await;
@@ -615,7 +718,7 @@ fn remote_rename_file() {
         rx.recv().await;
 
         // Rename it and wait until reader is done
-        repo.move_entry("/", "foo.txt", "/", "bar.txt")
+        repo.move_entry("/", "foo.txt", "/", "bar.txt", true)
             .await
             .unwrap();
         rx.recv().await;
@@ -647,7 +750,7 @@ fn remote_rename_empty_directory() {
         rx.recv().await;
 
         // Rename the directory and wait until reader is done
-        repo.move_entry("/", "foo", "/", "bar").await.unwrap();
+        repo.move_entry("/", "foo", "/", "bar", true).await.unwrap();
         rx.recv().await;
     });
 
@@ -681,7 +784,7 @@ fn remote_rename_non_empty_directory() {
         drop(dir);
 
         // Rename the directory and wait until reader is done
-        repo.move_entry("/", "foo", "/", "bar").await.unwrap();
+        repo.move_entry("/", "foo", "/", "bar", true).await.unwrap();
         rx.recv().await;
     });
 
@@ -711,12 +814,12 @@ fn remote_rename_directory_during_conflict() {
         // Create file before linking the repo to ensure we create conflict.
         repo.create_file("dummy.txt").await.unwrap();
 
-        let _reg = network.register(repo.handle()).await;
+        let _reg = network.register(repo.handle()).await.unwrap();
 
         repo.create_directory("foo").await.unwrap();
         rx.recv().await;
 
-        repo.move_entry("/", "foo", "/", "bar").await.unwrap();
+        repo.move_entry("/", "foo", "/", "bar", true).await.unwrap();
         rx.recv().await;
     });
 
@@ -730,7 +833,7 @@ fn remote_rename_directory_during_conflict() {
         // This prevents the remote branch from being pruned.
         repo.create_file("dummy.txt").await.unwrap();
 
-        let _reg = network.register(repo.handle()).await;
+        let _reg = network.register(repo.handle()).await.unwrap();
 
         expect_local_directory_exists(&repo, "foo").await;
         tx.send(()).await.unwrap();
@@ -753,12 +856,12 @@ fn remote_move_file_to_directory_then_rename_that_directory() {
         rx.recv().await;
 
         repo.create_directory("archive").await.unwrap();
-        repo.move_entry("/", "data.txt", "archive", "data.txt")
+        repo.move_entry("/", "data.txt", "archive", "data.txt", true)
             .await
             .unwrap();
         rx.recv().await;
 
-        repo.move_entry("/", "archive", "/", "trash").await.unwrap();
+        repo.move_entry("/", "archive", "/", "trash", true).await.unwrap();
         rx.recv().await;
     });
 
@@ -815,7 +918,7 @@ fn concurrent_update_and_delete_during_conflict() {
             // branch from being pruned.
             repo.create_file("dummy.txt").await.unwrap();
 
-            let reg = network.register(repo.handle()).await;
+            let reg = network.register(repo.handle()).await.unwrap();
 
             // 3. Wait until the file gets merged
             common::expect_file_version_content(&repo, "data.txt", Some(&id_a), &content_v0).await;
@@ -828,7 +931,7 @@ fn concurrent_update_and_delete_during_conflict() {
             repo.remove_entry("data.txt").await.unwrap();
 
             // 6a. Relink
-            let _reg = network.register(repo.handle()).await;
+            let _reg = network.register(repo.handle()).await.unwrap();
 
             // 7. We are able to read the whole file again including the previously gc-ed blocks.
             common::expect_file_version_content(&repo, "data.txt", Some(&id_b), &content_v1).await;
@@ -853,7 +956,7 @@ fn concurrent_update_and_delete_during_conflict() {
             // from being pruned.
             repo.create_file("dummy.txt").await.unwrap();
 
-            let reg = network.register(repo.handle()).await;
+            let reg = network.register(repo.handle()).await.unwrap();
 
             // 2. Create the file and wait until alice sees it
             let mut file = repo.create_file("data.txt").await.unwrap();
@@ -879,7 +982,7 @@ fn concurrent_update_and_delete_during_conflict() {
             .await;
 
             // 6b. Relink
-            let _reg = network.register(repo.handle()).await;
+            let _reg = network.register(repo.handle()).await.unwrap();
 
             alice_rx.recv().await.unwrap();
         }
@@ -925,7 +1028,7 @@ fn content_stays_available_during_sync() {
             // Bob is read-only to disable the merger which could otherwise interfere with this test.
             let network = actor::create_network(Proto::Tcp).await;
             let repo = actor::create_repo_with_mode(DEFAULT_REPO, AccessMode::Read).await;
-            let _reg = network.register(repo.handle()).await;
+            let _reg = network.register(repo.handle()).await.unwrap();
             network.add_user_provided_peer(&actor::lookup_addr("alice").await);
 
             // 2. Sync "b/c.dat"
@@ -1001,7 +1104,7 @@ fn redownload_expired_blocks() {
     env.actor("cache", async move {
         let network = actor::create_network(Proto::Tcp).await;
         let repo = actor::create_repo_with_mode(DEFAULT_REPO, AccessMode::Blind).await;
-        let _reg = network.register(repo.handle()).await;
+        let _reg = network.register(repo.handle()).await.unwrap();
 
         let block_count = origin_has_it_rx.recv().await.unwrap();
 
@@ -1035,7 +1138,7 @@ fn redownload_expired_blocks() {
     env.actor("reader", async move {
         let network = actor::create_network(Proto::Tcp).await;
         let repo = actor::create_repo_with_mode(DEFAULT_REPO, AccessMode::Blind).await;
-        let _reg = network.register(repo.handle()).await;
+        let _reg = network.register(repo.handle()).await.unwrap();
 
         // Use `start` to measure how long it took to sync the data from the expired cache.
         let (block_count, normal_sync_duration) = cache_had_it_rx.recv().await.unwrap();
@@ -1135,7 +1238,7 @@ fn quota_exceed() {
 
             repo.set_quota(Some(quota)).await.unwrap();
 
-            let _reg = network.register(repo.handle()).await;
+            let _reg = network.register(repo.handle()).await.unwrap();
 
             // The first file is within the quota
             common::expect_file_content(&repo, "0.dat", &content0).await;
@@ -1211,7 +1314,7 @@ fn quota_concurrent_writes() {
         .unwrap();
         repo.set_quota(Some(quota)).await.unwrap();
 
-        let _reg = network.register(repo.handle()).await;
+        let _reg = network.register(repo.handle()).await.unwrap();
 
         traffic.wait().await;
 
@@ -12,7 +12,7 @@ use dokan_sys::win32::{
     FILE_CREATE, FILE_DELETE_ON_CLOSE, FILE_DIRECTORY_FILE, FILE_OPEN, FILE_OPEN_IF,
     FILE_OVERWRITE, FILE_OVERWRITE_IF, FILE_SUPERSEDE,
 };
-use ouisync_lib::{path, File, JointDirectory, JointEntryRef, Repository};
+use ouisync_lib::{path, AccessMode, File, JointDirectory, JointEntryRef, Repository, StoreError};
 use std::{
     collections::{hash_map, HashMap},
     fmt,
@@ -32,6 +32,9 @@ use winapi::{shared::ntstatus::*, um::winnt};
 // Use the same value as NTFS.
 pub const MAX_COMPONENT_LENGTH: u32 = 255;
 
+// Number of directory entries to process at a time when listing a directory.
+const FIND_FILES_PAGE_SIZE: usize = 256;
+
 struct VirtualFilesystem {
     rt: tokio::runtime::Handle,
     repo: Arc<Repository>,
@@ -53,6 +56,21 @@ impl VirtualFilesystem {
         }
     }
 
+    // Whether the repository is currently not in write mode. All modifying operations are then
+    // rejected with `STATUS_MEDIA_WRITE_PROTECTED`. Checked on every operation (and not only when
+    // mounting) so that changes of the access mode while mounted are reflected.
+    fn is_read_only(&self) -> bool {
+        self.repo.access_mode() != AccessMode::Write
+    }
+
+    fn ensure_writable(&self) -> Result<(), Error> {
+        if self.is_read_only() {
+            Err(STATUS_MEDIA_WRITE_PROTECTED.into())
+        } else {
+            Ok(())
+        }
+    }
+
     async fn get_or_set_shared(
         &self,
         path: Utf8PathBuf,
@@ -119,6 +137,8 @@ impl VirtualFilesystem {
                     return Err(E::EntryNotFound.into());
                 }
 
+                self.ensure_writable()?;
+
                 let entry = if access_mask.has_delete() {
                     if create_directory {
                         Entry::new_dir(self.repo.clone(), path.clone(), shared).await?
@@ -161,6 +181,12 @@ impl VirtualFilesystem {
         } else {
             match existing_entry {
                 JointEntryRef::File(file_entry) => {
+                    // Make sure the local branch exists when opening for writing so the writes
+                    // themselves don't have to check it.
+                    if access_mask.has_write() && !self.is_read_only() {
+                        self.repo.ensure_local_branch().await?;
+                    }
+
                     let mut file = file_entry.open().await?;
                     if access_mask.has_append() {
                         file.seek(SeekFrom::End(0));
@@ -266,48 +292,67 @@ impl VirtualFilesystem {
     ) -> Result<(), Error> {
         let dir = dir_entry.cached_or_load_dir().await?;
 
-        for entry in dir.entries() {
-            let name = entry.unique_name();
+        // Fill the results one page at a time so that huge directories don't need to have all
+        // their entries (and the opened files to get their sizes) collected at once. Each page
+        // resumes after the name of the last entry of the previous one.
+        let mut after = None;
 
-            if name == "." || name == ".." {
-                continue;
-            }
+        loop {
+            let mut page_len = 0;
 
-            // TODO: Unwrap
-            let file_name = U16CString::from_str(entry.unique_name().as_ref()).unwrap();
+            for entry in dir.entries_after(after, FIND_FILES_PAGE_SIZE) {
+                page_len += 1;
+                after = Some(entry.name());
 
-            let (attributes, file_size) = match &entry {
-                JointEntryRef::File(file) => {
-                    let file_size = match file.open().await {
-                        Ok(file) => file.len(),
-                        Err(_) => 0,
-                    };
-                    (winnt::FILE_ATTRIBUTE_NORMAL, file_size)
-                }
-                JointEntryRef::Directory(_) => {
-                    // TODO: Count block sizes
-                    (winnt::FILE_ATTRIBUTE_DIRECTORY, 0)
-                }
-            };
+                let name = entry.unique_name();
 
-            if let Some(pattern) = pattern {
-                let ignore_case = true;
-                if !dokan::is_name_in_expression(pattern, &file_name, ignore_case) {
+                if name == "." || name == ".." {
                     continue;
                 }
+
+                // TODO: Unwrap
+                let file_name = U16CString::from_str(name.as_ref()).unwrap();
+
+                // Match the pattern before opening the file so that non-matching files are
+                // skipped cheaply.
+                if let Some(pattern) = pattern {
+                    let ignore_case = true;
+                    if !dokan::is_name_in_expression(pattern, &file_name, ignore_case) {
+                        continue;
+                    }
+                }
+
+                let (attributes, file_size) = match &entry {
+                    JointEntryRef::File(file) => {
+                        let file_size = match file.open().await {
+                            Ok(file) => file.len(),
+                            Err(_) => 0,
+                        };
+                        (winnt::FILE_ATTRIBUTE_NORMAL, file_size)
+                    }
+                    JointEntryRef::Directory(_) => {
+                        // TODO: Count block sizes
+                        (winnt::FILE_ATTRIBUTE_DIRECTORY, 0)
+                    }
+                };
+
+                fill_find_data(&FindData {
+                    attributes,
+                    // TODO
+                    creation_time: UNIX_EPOCH,
+                    last_access_time: UNIX_EPOCH,
+                    last_write_time: UNIX_EPOCH,
+                    file_size,
+                    file_name,
+                })
+                .or_else(ignore_name_too_long)?;
             }
 
-            fill_find_data(&FindData {
-                attributes,
-                // TODO
-                creation_time: UNIX_EPOCH,
-                last_access_time: UNIX_EPOCH,
-                last_write_time: UNIX_EPOCH,
-                file_size,
-                file_name,
-            })
-            .or_else(ignore_name_too_long)?;
+            if page_len == 0 {
+                break;
+            }
         }
+
         Ok(())
     }
 
@@ -340,6 +385,10 @@ impl VirtualFilesystem {
 
         let path = to_path(file_name)?;
 
+        if create_disposition.should_truncate() || delete_on_close {
+            self.ensure_writable()?;
+        }
+
         let (entry, is_new, id) = self
             .create_entry(
                 path,
@@ -515,6 +564,7 @@ impl VirtualFilesystem {
         context: &'c EntryHandle,
     ) -> Result<u32, Error> {
         tracing::trace!("enter");
+        self.ensure_writable()?;
 
         let file_entry = context.entry.as_file()?;
 
@@ -527,6 +577,7 @@ impl VirtualFilesystem {
             offset.try_into().map_err(|_| STATUS_INVALID_PARAMETER)?
         };
 
+        // Created when the file was opened already.
         let local_branch = self.repo.local_branch()?;
 
         if offset != file.len() {
@@ -757,6 +808,11 @@ impl VirtualFilesystem {
         context: &'c EntryHandle,
     ) -> Result<(), Error> {
         tracing::trace!("enter");
+
+        if info.delete_on_close() {
+            self.ensure_writable()?;
+        }
+
         let file_entry = context.entry.as_file()?;
         file_entry.shared.write().await.delete_on_close = info.delete_on_close();
         Ok(())
@@ -781,6 +837,11 @@ impl VirtualFilesystem {
         context: &'c EntryHandle,
     ) -> Result<(), Error> {
         tracing::trace!("enter");
+
+        if info.delete_on_close() {
+            self.ensure_writable()?;
+        }
+
         let dir_entry = context.entry.as_directory()?;
         let path = to_path(file_name)?;
         let mut shared = dir_entry.shared.write().await;
@@ -813,11 +874,12 @@ impl VirtualFilesystem {
         &self,
         file_name: &U16CStr,
         new_file_name: &U16CStr,
-        _replace_if_existing: bool,
+        replace_if_existing: bool,
         _info: &OperationInfo<'c, 'h, Super>,
         handle: &'c EntryHandle,
     ) -> Result<(), Error> {
         tracing::trace!("enter");
+        self.ensure_writable()?;
 
         let src_path = to_path(file_name)?;
         let dst_path = to_path(new_file_name)?;
@@ -856,7 +918,7 @@ impl VirtualFilesystem {
         }
 
         self.repo
-            .move_entry(&src_dir, src_name, &dst_dir, dst_name)
+            .move_entry(&src_dir, src_name, &dst_dir, dst_name, replace_if_existing)
             .await?;
 
         Ok(())
@@ -916,6 +978,8 @@ impl VirtualFilesystem {
         context: &'c EntryHandle,
     ) -> Result<(), Error> {
         tracing::trace!("enter");
+        self.ensure_writable()?;
+
         let desired_len: u64 = alloc_size
             .try_into()
             .map_err(|_| STATUS_INVALID_PARAMETER)?;
@@ -924,39 +988,7 @@ impl VirtualFilesystem {
         let mut lock = entry.file.lock().await;
         let file = lock.opened_file(&self.repo).await?;
 
-        let start_len = file.len();
-
-        if start_len == desired_len {
-            return Ok(());
-        }
-
-        let local_branch = self.repo.local_branch()?;
-
-        file.fork(local_branch).await?;
-
-        if start_len > desired_len {
-            file.truncate(desired_len)?;
-        } else {
-            let start_pos = file.seek(SeekFrom::Current(0));
-
-            file.seek(SeekFrom::End(0));
-
-            let mut remaining: usize = (desired_len - start_len)
-                .try_into()
-                .map_err(|_| STATUS_INVALID_PARAMETER)?;
-
-            let zeros = vec![0; ouisync_lib::BLOCK_SIZE];
-
-            while remaining != 0 {
-                let to_write = remaining.min(zeros.len());
-                file.write(&zeros[0..to_write]).await?;
-                remaining -= to_write;
-            }
-
-            file.seek(SeekFrom::Start(start_pos));
-        }
-
-        file.flush().await?;
+        self.repo.resize_file(file, desired_len).await?;
 
         Ok(())
     }
@@ -979,11 +1011,24 @@ impl VirtualFilesystem {
         _info: &OperationInfo<'c, 'h, Super>,
     ) -> Result<DiskSpaceInfo, Error> {
         tracing::trace!("enter");
-        // TODO
+
+        // TODO: Without quota, report the free space of the underlying disk.
+        let (Some(quota), Some(available)) =
+            (self.repo.quota().await?, self.repo.available_space().await?)
+        else {
+            return Ok(DiskSpaceInfo {
+                byte_count: 1024 * 1024 * 1024,
+                free_byte_count: 512 * 1024 * 1024,
+                available_byte_count: 512 * 1024 * 1024,
+            });
+        };
+
+        let available = available.to_bytes();
+
         Ok(DiskSpaceInfo {
-            byte_count: 1024 * 1024 * 1024,
-            free_byte_count: 512 * 1024 * 1024,
-            available_byte_count: 512 * 1024 * 1024,
+            byte_count: quota.to_bytes(),
+            free_byte_count: available,
+            available_byte_count: available,
         })
     }
 
@@ -1002,13 +1047,20 @@ impl VirtualFilesystem {
         _info: &OperationInfo<'c, 'h, Super>,
     ) -> Result<VolumeInfo, Error> {
         tracing::trace!("enter");
+
+        let mut fs_flags = winnt::FILE_CASE_PRESERVED_NAMES
+            | winnt::FILE_CASE_SENSITIVE_SEARCH
+            | winnt::FILE_UNICODE_ON_DISK;
+
+        if self.is_read_only() {
+            fs_flags |= winnt::FILE_READ_ONLY_VOLUME;
+        }
+
         Ok(VolumeInfo {
             name: U16CString::from_str("ouisync").unwrap(),
             serial_number: 0,
             max_component_length: MAX_COMPONENT_LENGTH,
-            fs_flags: winnt::FILE_CASE_PRESERVED_NAMES
-                | winnt::FILE_CASE_SENSITIVE_SEARCH
-                | winnt::FILE_UNICODE_ON_DISK,
+            fs_flags,
             // Custom names don't play well with UAC.
             fs_name: U16CString::from_str("NTFS").unwrap(),
         })
@@ -1102,6 +1154,7 @@ impl fmt::Debug for Error {
                 STATUS_LOCK_NOT_GRANTED => write!(f, "STATUS_LOCK_NOT_GRANTED"),
                 STATUS_INVALID_DEVICE_REQUEST => write!(f, "STATUS_INVALID_DEVICE_REQUEST"),
                 STATUS_FILE_CLOSED => write!(f, "STATUS_FILE_CLOSED"),
+                STATUS_MEDIA_WRITE_PROTECTED => write!(f, "STATUS_MEDIA_WRITE_PROTECTED"),
                 other => write!(f, "{:#x}", other),
             },
             Self::OuiSync(error) => {
@@ -1133,7 +1186,8 @@ impl From<Error> for i32 {
                 use ouisync_lib::Error as E;
 
                 match error {
-                    E::Db(_) | E::Store(_) => STATUS_INTERNAL_DB_ERROR,
+                    E::Db(_) => STATUS_INTERNAL_DB_ERROR,
+                    E::Store(error) => store_error_to_ntstatus(error),
                     E::PermissionDenied => STATUS_ACCESS_DENIED,
                     E::MalformedData => STATUS_DATA_ERROR,
                     E::MalformedDirectory => STATUS_DATA_ERROR,
@@ -1143,19 +1197,44 @@ impl From<Error> for i32 {
                     // These two are as they were used in the memfs dokan example.
                     E::EntryIsFile => STATUS_INVALID_DEVICE_REQUEST,
                     E::EntryIsDirectory => STATUS_INVALID_DEVICE_REQUEST,
-                    E::NonUtf8FileName => STATUS_OBJECT_NAME_INVALID,
+                    E::NonUtf8FileName | E::InvalidName => STATUS_OBJECT_NAME_INVALID,
+                    E::NameTooLong => STATUS_NAME_TOO_LONG,
                     E::InvalidArgument | E::OffsetOutOfRange => STATUS_INVALID_PARAMETER,
                     E::DirectoryNotEmpty => STATUS_DIRECTORY_NOT_EMPTY,
                     E::OperationNotSupported => STATUS_NOT_IMPLEMENTED,
                     E::Writer(_) => STATUS_IO_DEVICE_ERROR,
                     E::StorageVersionMismatch => STATUS_IO_DEVICE_ERROR,
                     E::Locked => STATUS_LOCK_NOT_GRANTED,
+                    E::QuotaExceeded { .. } | E::FileTooLarge => STATUS_DISK_FULL,
+                    E::Corrupted(_) => STATUS_FILE_CORRUPT_ERROR,
+                    E::RegistrationLimitExceeded => STATUS_INSUFFICIENT_RESOURCES,
                 }
             }
         }
     }
 }
 
+fn store_error_to_ntstatus(error: StoreError) -> i32 {
+    match error {
+        // The content hasn't been downloaded yet.
+        StoreError::BlockNotFound => STATUS_FILE_NOT_AVAILABLE,
+        StoreError::QuotaExceeded { .. } => STATUS_DISK_FULL,
+        StoreError::MalformedData => STATUS_DATA_ERROR,
+        error if error.is_transient() => STATUS_DEVICE_BUSY,
+        StoreError::Db(_) => STATUS_INTERNAL_DB_ERROR,
+        // These should be handled inside the library and never reach us. Don't panic if they do
+        // but fail the operation.
+        StoreError::BranchNotFound
+        | StoreError::OutdatedRootNode
+        | StoreError::ConcurrentRootNode
+        | StoreError::LocatorNotFound
+        | StoreError::BlockNotReferenced => {
+            tracing::warn!(?error, "Unexpected store error");
+            STATUS_UNSUCCESSFUL
+        }
+    }
+}
+
 fn to_path(path_cstr: &U16CStr) -> OperationResult<Utf8PathBuf> {
     let path_str: String = match path_cstr.to_string() {
         Ok(path_str) => path_str,
@@ -1351,6 +1430,18 @@ impl CreateDisposition {
             Self::OverwriteIf => true,
         }
     }
+
+    // Does this disposition replace or overwrite the file if it already exists?
+    fn should_truncate(&self) -> bool {
+        match self {
+            Self::Supersede => true,
+            Self::Create => false,
+            Self::Open => false,
+            Self::OpenIf => false,
+            Self::Overwrite => true,
+            Self::OverwriteIf => true,
+        }
+    }
 }
 
 impl TryFrom<u32> for CreateDisposition {
@@ -1381,6 +1472,9 @@ impl AccessMask {
     fn has_append(&self) -> bool {
         self.mask & winnt::FILE_APPEND_DATA > 0
     }
+    fn has_write(&self) -> bool {
+        self.mask & (winnt::FILE_WRITE_DATA | winnt::FILE_APPEND_DATA | winnt::GENERIC_WRITE) > 0
+    }
 }
 
 impl From<winnt::ACCESS_MASK> for AccessMask {
@@ -486,7 +486,8 @@ impl Inner {
     ) -> Result<FileAttr> {
         self.record_path(inode, None);
 
-        let local_branch = self.repository.local_branch()?;
+        // Fail early (before opening the file) if the repository is not writable.
+        self.repository.local_branch()?;
 
         let mut scope = FormatOptionScope::new(", ");
 
@@ -534,9 +535,7 @@ impl Inner {
         };
 
         if let Some(size) = size {
-            file.fork(local_branch).await?;
-            file.truncate(size)?;
-            file.flush().await?;
+            self.repository.resize_file(&mut file, size).await?;
         }
 
         Ok(make_file_attr(
@@ -715,10 +714,14 @@ impl Inner {
 
         let mut file = self.open_file_by_inode(inode).await?;
 
-        if flags.contains(OpenFlags::TRUNC) {
-            let local_branch = self.repository.local_branch()?;
+        // Make sure the local branch exists when opening for writing so the writes themselves
+        // don't have to check it.
+        if flags.intersects(OpenFlags::WRONLY | OpenFlags::RDWR | OpenFlags::TRUNC) {
+            self.repository.ensure_local_branch().await?;
+        }
 
-            file.fork(local_branch).await?;
+        if flags.contains(OpenFlags::TRUNC) {
+            file.fork(self.repository.local_branch()?).await?;
             file.truncate(0)?;
             file.flush().await?;
         }
@@ -802,6 +805,7 @@ impl Inner {
         self.record_path(inode, None);
 
         let offset: u64 = offset.try_into().map_err(|_| Error::OffsetOutOfRange)?;
+        // Created by `open` (or `create`) already.
         let local_branch = self.repository.local_branch()?;
 
         let file = self.entries.get_file_mut(handle)?;
@@ -856,7 +860,7 @@ impl Inner {
         dst_name: &OsStr,
         flags: RenameFlags,
     ) -> Result<()> {
-        if !flags.is_empty() {
+        if !flags.difference(RenameFlags::NOREPLACE).is_empty() {
             tracing::error!("flag(s) not supported");
             return Err(Error::OperationNotSupported);
         }
@@ -885,7 +889,13 @@ impl Inner {
         };
 
         self.repository
-            .move_entry(src_dir, src_name, dst_dir, dst_name)
+            .move_entry(
+                src_dir,
+                src_name,
+                dst_dir,
+                dst_name,
+                !flags.contains(RenameFlags::NOREPLACE),
+            )
             .await
     }
 
@@ -969,17 +979,21 @@ fn to_error_code(error: &Error) -> libc::c_int {
         | Error::MalformedData
         | Error::MalformedDirectory
         | Error::Writer(_)
-        | Error::StorageVersionMismatch => libc::EIO,
+        | Error::StorageVersionMismatch
+        | Error::Corrupted(_) => libc::EIO,
         Error::EntryNotFound | Error::AmbiguousEntry => libc::ENOENT,
         Error::EntryExists => libc::EEXIST,
         Error::EntryIsFile => libc::ENOTDIR,
         Error::EntryIsDirectory => libc::EISDIR,
-        Error::NonUtf8FileName | Error::InvalidArgument => libc::EINVAL,
+        Error::NonUtf8FileName | Error::InvalidArgument | Error::InvalidName => libc::EINVAL,
+        Error::NameTooLong => libc::ENAMETOOLONG,
         Error::OffsetOutOfRange => libc::EINVAL,
         Error::PermissionDenied => libc::EACCES,
         Error::DirectoryNotEmpty => libc::ENOTEMPTY,
         Error::OperationNotSupported => libc::ENOTSUP,
-        Error::Locked => libc::EBUSY,
+        Error::Locked | Error::RegistrationLimitExceeded => libc::EBUSY,
+        Error::QuotaExceeded { .. } => libc::EDQUOT,
+        Error::FileTooLarge => libc::EFBIG,
     }
 }
 